@@ -0,0 +1,118 @@
+//! Fails if parsing or delivering events allocates more than it did when these budgets were
+//! set.  Run with `cargo bench --bench allocations --features bench,alloc_count`.
+use flodgatt::alloc_count::{self, Allocations, Counting};
+use flodgatt::config;
+use flodgatt::request::{Subscription, TagRegistry, Timeline};
+use flodgatt::response::Manager;
+use futures::future::{self, Future};
+use futures::{Async, Stream};
+use std::fs;
+use std::process;
+
+#[global_allocator]
+static ALLOC: Counting = Counting;
+
+/// The most allocations parsing one event from Redis input may make (most are the event's
+/// `String` fields)
+const PARSE_BUDGET: f64 = 400.0;
+/// The most allocations sending an already-parsed event to one more client may make.  Sending
+/// an event only clones an `Arc`, but a channel may allocate the first time it is polled.
+const DELIVERY_BUDGET: f64 = 0.5;
+const SUBSCRIBERS: usize = 100;
+
+fn input_msg(i: usize) -> Vec<u8> {
+    fs::read_to_string(format!("test_data/redis_input_{:03}.resp", i))
+        .expect("test input not found")
+        .as_bytes()
+        .to_vec()
+}
+
+fn manager_with_input() -> Manager {
+    let mut manager = Manager::try_from(&config::Redis::default()).expect("bench");
+    for i in 1..=6 {
+        manager.redis_conn.add(&input_msg(i));
+    }
+    manager
+}
+
+/// The allocations made parsing the six test messages, and the number of events parsed
+fn parse() -> (Allocations, usize) {
+    let mut m = manager_with_input();
+    let (events, allocations) = alloc_count::measure(|| {
+        let mut events = 0;
+        while let Ok(Async::Ready(Some(len))) = m.redis_conn.poll_redis(m.unread_idx.1) {
+            m.unread_idx = (0, m.unread_idx.1 + len);
+            while let Ok(Async::Ready(Some(_))) = m.poll() {
+                events += 1;
+            }
+        }
+        events
+    });
+    (allocations, events)
+}
+
+/// The allocations made sending the six test messages to `subscribers` clients
+fn deliver(subscribers: usize) -> Allocations {
+    future::lazy(|| {
+        let mut manager = manager_with_input();
+        let subscription = Subscription {
+            timeline: Timeline::from_redis_text("public", &TagRegistry::default()).expect("bench"),
+            ..Subscription::default()
+        };
+        let receivers: Vec<_> = (0..subscribers)
+            .map(|_| {
+                let (event_tx, event_rx) = manager.channel();
+                manager.subscribe(&subscription, event_tx).expect("bench");
+                event_rx
+            })
+            .collect();
+        let (sent, allocations) = alloc_count::measure(|| manager.send_msgs());
+        assert_eq!(sent.ok(), Some(Async::Ready(())));
+        drop(receivers);
+        Ok::<_, ()>(allocations)
+    })
+    .wait()
+    .expect("bench")
+}
+
+fn check(name: &str, per_event: f64, budget: f64) -> bool {
+    let within = per_event <= budget;
+    println!(
+        "{:<32} {:>8.2} allocations per event (budget: {}){}",
+        name,
+        per_event,
+        budget,
+        if within { "" } else { "  REGRESSION" }
+    );
+    within
+}
+
+fn main() {
+    let (parsed, events) = parse();
+    assert_eq!(events, 6);
+
+    // Parsing happens once per event, however many clients receive it, so the cost of each
+    // delivery is the difference between sending to many clients and sending to one.
+    let (one, many) = (deliver(1), deliver(SUBSCRIBERS));
+    let extra = Allocations {
+        count: many.count.saturating_sub(one.count),
+        bytes: many.bytes.saturating_sub(one.bytes),
+    };
+    let per_delivery = extra.per(events * (SUBSCRIBERS - 1));
+
+    let within_budgets = [
+        check(
+            "parse Redis input to an Event",
+            parsed.per(events),
+            PARSE_BUDGET,
+        ),
+        check(
+            "deliver an Event to a client",
+            per_delivery,
+            DELIVERY_BUDGET,
+        ),
+    ];
+    if within_budgets.iter().any(|within| !within) {
+        process::exit(1);
+    }
+}
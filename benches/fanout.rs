@@ -0,0 +1,98 @@
+//! Compares how long the `Manager` takes to send one event to every client of a timeline
+//! through each client's own channel (as it sends the events of a list) with sending it
+//! through the timeline's `broadcast` channel (as it sends the events of the public timeline),
+//! for 1,000 and 10,000 clients.  Each iteration reads one message from Redis, parses it,
+//! sends it, and has every client receive it.  Run with
+//! `cargo bench --bench fanout --features bench`.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use flodgatt::config;
+use flodgatt::request::{Subscription, TagRegistry, Timeline};
+use flodgatt::response::Manager;
+use futures::future::{self, Future};
+use futures::{Async, Stream};
+
+const SUBSCRIBERS: [usize; 2] = [1_000, 10_000];
+
+/// The timelines compared, by the Redis channel of each
+const TIMELINES: [(&str, &str); 2] = [
+    ("channel per client", "timeline:list:1"),
+    ("broadcast", "timeline:public"),
+];
+
+/// One message from Redis on `channel`
+fn frame(channel: &str) -> Vec<u8> {
+    let event = r#"{"event":"delete","payload":"102775370117886890"}"#;
+    format!(
+        "*3\r\n$7\r\nmessage\r\n${}\r\n{}\r\n${}\r\n{}\r\n",
+        channel.len(),
+        channel,
+        event.len(),
+        event
+    )
+    .into_bytes()
+}
+
+/// Read the message at the end of `manager`'s input, as though Redis had just sent it
+fn read(manager: &mut Manager, frame: &[u8]) {
+    manager.redis_conn.add(frame);
+    while let Ok(Async::Ready(Some(len))) = manager.redis_conn.poll_redis(manager.unread_idx.1) {
+        manager.unread_idx.1 += len;
+    }
+}
+
+/// Receive every event a client has been sent (as the task that writes to it would), of which
+/// there must be at least one
+fn drain<S: Stream>(rx: &mut S) {
+    let mut received = 0;
+    while let Ok(Async::Ready(Some(_))) = rx.poll() {
+        received += 1;
+    }
+    assert!(received > 0);
+}
+
+fn subscription(channel: &str) -> Subscription {
+    let tl = channel.trim_start_matches("timeline:");
+    Subscription {
+        timeline: Timeline::from_redis_text(tl, &TagRegistry::default()).expect("bench"),
+        ..Subscription::default()
+    }
+}
+
+fn criterion_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("Send one event to every client of a timeline");
+    for &subscribers in &SUBSCRIBERS {
+        group.throughput(Throughput::Elements(subscribers as u64));
+        for &(strategy, channel) in &TIMELINES {
+            let frame = frame(channel);
+
+            // Channels are polled, so the clients must be polled from within a task
+            future::lazy(|| {
+                let mut manager = Manager::try_from(&config::Redis::default()).expect("bench");
+                let mut receivers: Vec<_> = (0..subscribers)
+                    .map(|_| {
+                        let (event_tx, event_rx) = manager.channel();
+                        manager
+                            .subscribe(&subscription(channel), event_tx)
+                            .expect("bench");
+                        event_rx
+                    })
+                    .collect();
+                let id = BenchmarkId::new(strategy, subscribers);
+                group.bench_function(id, |b| {
+                    b.iter(|| {
+                        read(&mut manager, &frame);
+                        assert_eq!(manager.send_msgs().ok(), Some(Async::Ready(())));
+                        receivers.iter_mut().for_each(drain);
+                    })
+                });
+                Ok::<_, ()>(())
+            })
+            .wait()
+            .expect("bench");
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
@@ -1,12 +1,12 @@
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
 use flodgatt::config;
-use flodgatt::request::{Content::*, Reach::*, Stream::*, Timeline};
+use flodgatt::request::{Content::*, Reach::*, Stream::*, TagRegistry, Timeline};
 use flodgatt::response::{Event, Manager, RedisMsg, RedisParseOutput};
 use flodgatt::Id;
 use futures::{Async, Stream};
-use lru::LruCache;
 use std::convert::TryFrom;
 use std::fs;
+use std::time::{Duration, Instant};
 
 fn parse_long_redis_input<'a>(input: &'a str) -> RedisMsg<'a> {
     if let RedisParseOutput::Msg(msg) = RedisParseOutput::try_from(input).unwrap() {
@@ -19,7 +19,7 @@ fn parse_long_redis_input<'a>(input: &'a str) -> RedisMsg<'a> {
 
 fn parse_to_timeline(msg: RedisMsg) -> Timeline {
     let trimmed_tl_txt = &msg.timeline_txt["timeline:".len()..];
-    let tl = Timeline::from_redis_text(trimmed_tl_txt, &mut LruCache::new(1000)).unwrap();
+    let tl = Timeline::from_redis_text(trimmed_tl_txt, &TagRegistry::default()).unwrap();
     assert_eq!(tl, Timeline(User(Id(1)), Federated, All));
     tl
 }
@@ -46,7 +46,63 @@ fn input_msg(i: usize) -> Vec<u8> {
         .to_vec()
 }
 
+/// A `Manager` that has read `frames` short messages from Redis, all of them still unparsed
+fn burst(frames: usize) -> Manager {
+    let event = r#"{"event":"delete","payload":"102775370117886890"}"#;
+    let frame = format!(
+        "*3\r\n$7\r\nmessage\r\n$10\r\ntimeline:1\r\n${}\r\n{}\r\n",
+        event.len(),
+        event
+    );
+    let mut manager = Manager::try_from(&config::Redis::default()).expect("bench");
+    manager.redis_conn.add(frame.repeat(frames).as_bytes());
+    while let Ok(Async::Ready(Some(len))) = manager.redis_conn.poll_redis(manager.unread_idx.1) {
+        manager.unread_idx.1 += len;
+    }
+    manager
+}
+
+/// Parse every message `manager` has read, returning how many there were
+fn parse_burst(manager: &mut Manager) -> usize {
+    let mut parsed = 0;
+    while let Ok(Async::Ready(Some(_))) = manager.poll() {
+        parsed += 1;
+    }
+    parsed
+}
+
+/// The fastest of a few runs of parsing a burst of `frames` messages
+fn time_burst(frames: usize) -> Duration {
+    (0..5)
+        .map(|_| {
+            let mut manager = burst(frames);
+            let start = Instant::now();
+            assert_eq!(parse_burst(&mut manager), frames);
+            start.elapsed()
+        })
+        .min()
+        .expect("runs")
+}
+
+/// Parsing a burst should take time in proportion to its messages; if the rest of a read were
+/// parsed again for each message, sixteen times the messages would take far longer per message
+fn assert_linear_scaling() {
+    let (small, large) = (1_000, 16_000);
+    let per_frame = |frames| time_burst(frames).as_nanos() / frames as u128;
+    let (small_cost, large_cost) = (per_frame(small), per_frame(large));
+    assert!(
+        large_cost <= small_cost * 4,
+        "Parsing took {}ns per message in a burst of {}, but {}ns in a burst of {}",
+        large_cost,
+        large,
+        small_cost,
+        small
+    );
+}
+
 fn criterion_benchmark(c: &mut Criterion) {
+    assert_linear_scaling();
+
     let input = ONE_MESSAGE_FOR_THE_USER_TIMLINE_FROM_REDIS;
     let mut group = c.benchmark_group("Parse redis RESP array");
 
@@ -102,6 +158,24 @@ fn criterion_benchmark(c: &mut Criterion) {
             criterion::BatchSize::SmallInput,
         )
     });
+    group.finish();
+
+    let mut group = c.benchmark_group("Parse a burst of messages from one read");
+    for &frames in &[100, 1_000, 10_000] {
+        group.throughput(Throughput::Elements(frames as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(frames),
+            &frames,
+            |b, &frames| {
+                b.iter_batched(
+                    || burst(frames),
+                    |mut m| assert_eq!(black_box(parse_burst(&mut m)), frames),
+                    criterion::BatchSize::LargeInput,
+                )
+            },
+        );
+    }
+    group.finish();
 }
 
 criterion_group!(benches, criterion_benchmark);
@@ -0,0 +1,75 @@
+//! A global allocator that counts allocations, for catching regressions in the hot path
+//!
+//! Only built with the `alloc_count` feature.  A binary opts in by registering the allocator:
+//!
+//! ```ignore
+//! #[global_allocator]
+//! static ALLOC: flodgatt::alloc_count::Counting = flodgatt::alloc_count::Counting;
+//! ```
+//!
+//! and then wraps the code it wants to measure in `alloc_count::measure`.  The counts are
+//! process-wide, so anything measured should run while no other thread is allocating.
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// The system allocator, counting each allocation (and reallocation) it makes
+pub struct Counting;
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        record(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        record(layout.size());
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        record(new_size);
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+fn record(size: usize) {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    BYTES.fetch_add(size as u64, Ordering::Relaxed);
+}
+
+/// The allocations made while running some code
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Allocations {
+    pub count: u64,
+    pub bytes: u64,
+}
+
+impl Allocations {
+    /// The allocations per item, when `self` were made handling `n` items
+    #[allow(clippy::cast_precision_loss)]
+    pub fn per(self, n: usize) -> f64 {
+        self.count as f64 / n as f64
+    }
+}
+
+/// Run `f`, returning its result and the allocations it made.  Always zero unless `Counting`
+/// is the global allocator.
+pub fn measure<T>(f: impl FnOnce() -> T) -> (T, Allocations) {
+    let (count, bytes) = (
+        ALLOCATIONS.load(Ordering::SeqCst),
+        BYTES.load(Ordering::SeqCst),
+    );
+    let result = f();
+    let allocations = Allocations {
+        count: ALLOCATIONS.load(Ordering::SeqCst) - count,
+        bytes: BYTES.load(Ordering::SeqCst) - bytes,
+    };
+    (result, allocations)
+}
@@ -1,15 +1,29 @@
+pub use self::cli::{Cli, Command};
 pub use self::deployment_cfg::Deployment;
+pub use self::deployment_cfg_types::{
+    CompositeAuthInner, CompositePartInner, ContentSizeInner, DeletePayloadInner,
+    GeneratorTargetInner, GeoActionInner, LogFormatInner, STREAM_TYPES,
+};
+pub use self::node_import::NodeImport;
 pub use self::postgres_cfg::Postgres;
+pub use self::postgres_cfg_types::PgSslInner;
 pub use self::redis_cfg::Redis;
+pub use self::redis_cfg_types::{
+    ClientOverflowInner, EventModeInner, InvalidUtf8Inner, SubscribedKeyStyleInner,
+    EVENT_CATEGORIES,
+};
 
-use self::environmental_variables::EnvVar;
+use self::environmental_variables::{EnvVar, Redact, Setting};
 
 use hashbrown::HashMap;
 use std::env;
 use std::fmt;
+mod cli;
+mod config_file;
 mod deployment_cfg;
 mod deployment_cfg_types;
 mod environmental_variables;
+mod node_import;
 mod postgres_cfg;
 mod postgres_cfg_types;
 mod redis_cfg;
@@ -17,12 +31,18 @@ mod redis_cfg_types;
 
 type Result<T> = std::result::Result<T, Error>;
 
+/// The file that environmental variables are read from (`.env`, or `.env.production` with
+/// `ENV=production`)
+fn env_file() -> Result<&'static str> {
+    match env::var("ENV").ok().as_deref() {
+        Some("production") => Ok(".env.production"),
+        Some("development") | None => Ok(".env"),
+        Some(v) => Err(Error::config("ENV", v, "`production` or `development`")),
+    }
+}
+
 pub fn merge_dotenv() -> Result<()> {
-    let env_file = match env::var("ENV").ok().as_deref() {
-        Some("production") => ".env.production",
-        Some("development") | None => ".env",
-        Some(v) => Err(Error::config("ENV", v, "`production` or `development`"))?,
-    };
+    let env_file = env_file()?;
     let res = dotenv::from_filename(env_file);
 
     if let Ok(log_level) = env::var("RUST_LOG") {
@@ -50,22 +70,74 @@ pub fn merge_dotenv() -> Result<()> {
     Ok(())
 }
 
+/// Set the variables that the command line's flags set, over those of any other source
+pub fn merge_cli(cli: &Cli) {
+    for (var, value) in &cli.vars {
+        env::set_var(var, value);
+    }
+}
+
+/// Set the variables in the `--config` file at `path` that aren't set already, by the
+/// environment or the env file (see `config_file`)
+pub fn merge_config_file(path: &str) -> Result<()> {
+    for (var, value) in config_file::read(path)? {
+        if env::var_os(&var).is_none() {
+            env::set_var(var, value);
+        }
+    }
+    Ok(())
+}
+
+/// The environmental variables with the `--config` file (if there is one) and the env file read
+/// again, for reloading the configuration on `SIGHUP`.  Unlike at startup (see `merge_dotenv`),
+/// the files' values take precedence over the environment's, since the environment is the one
+/// Flodgatt was started with.
+pub fn reread_dotenv(config_file: Option<&str>) -> Result<HashMap<String, String>> {
+    let env_file = env_file()?;
+    let unreadable =
+        |e: dotenv::Error| Error::Config(format!("could not read {}: {}", env_file, e));
+    let mut vars: HashMap<String, String> = env::vars().collect();
+    if let Some(path) = config_file {
+        vars.extend(config_file::read(path)?);
+    }
+    for var in dotenv::from_filename_iter(env_file).map_err(unreadable)? {
+        let (key, value) = var.map_err(unreadable)?;
+        vars.insert(key, value);
+    }
+    Ok(vars)
+}
+
+/// The `LOG_FORMAT`, which is needed before the rest of the config (so that parsing it is
+/// logged in that format)
+pub fn log_format() -> Result<LogFormatInner> {
+    let format = env::var("LOG_FORMAT").ok();
+    Ok(*deployment_cfg_types::LogFormat::default().maybe_update(format.as_ref())?)
+}
+
+/// The `OTLP_ENDPOINT`, which (like the `LOG_FORMAT`) is needed before the rest of the config
+pub fn otlp_endpoint() -> Result<Option<String>> {
+    let endpoint = env::var("OTLP_ENDPOINT").ok();
+    Ok(deployment_cfg_types::OtlpEndpoint::default()
+        .maybe_update(endpoint.as_ref())?
+        .0)
+}
+
 #[allow(clippy::implicit_hasher)]
 pub fn from_env<'a>(
     env_vars: HashMap<String, String>,
 ) -> Result<(Postgres, Redis, Deployment<'a>)> {
-    let env_vars = EnvVar::new(env_vars);
-    log::info!(
+    let env_vars = EnvVar::new(env_vars).read_secret_files()?;
+    tracing::info!(
         "Flodgatt received the following environmental variables:{}",
         &env_vars
     );
 
     let pg_cfg = Postgres::from_env(env_vars.clone())?;
-    log::info!("Configuration for {:#?}", &pg_cfg);
+    tracing::info!("Configuration for {:#?}", &pg_cfg);
     let redis_cfg = Redis::from_env(env_vars.clone())?;
-    log::info!("Configuration for {:#?},", &redis_cfg);
+    tracing::info!("Configuration for {:#?},", &redis_cfg);
     let deployment_cfg = Deployment::from_env(&env_vars)?;
-    log::info!("Configuration for {:#?}", &deployment_cfg);
+    tracing::info!("Configuration for {:#?}", &deployment_cfg);
 
     Ok((pg_cfg, redis_cfg, deployment_cfg))
 }
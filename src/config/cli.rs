@@ -0,0 +1,229 @@
+//! Flodgatt's command line: a flag for each environmental variable (`--redis-url` for
+//! `REDIS_URL`), which takes precedence over the environment, the env file, and the `--config`
+//! file, along with the subcommands and the flags that aren't settings.
+//!
+//! `--help` lists every setting with what it must be set to and its default, taken from the
+//! type that parses it (see `from_env_var!`).
+use super::deployment_cfg_types::Env;
+use super::environmental_variables::{ENV_VARS, SECRET_FILES};
+use super::{Deployment, Postgres, Redis, Setting};
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use std::ffi::OsString;
+
+#[cfg(test)]
+mod test;
+
+/// The variables with no type of their own to describe them
+const UNTYPED: &[(&str, &str, &str)] = &[
+    (
+        "DATABASE_URL",
+        "The Postgres to connect to, which takes precedence over the other Postgres settings",
+        "a URL (e.g., postgresql://mastodon@localhost/mastodon_production?sslmode=require)",
+    ),
+    (
+        "REDIS_URL",
+        "The Redis to connect to, which takes precedence over the other Redis connection settings",
+        "a URL (e.g., redis://:password@localhost:6379/0)",
+    ),
+    (
+        "REDIS_PUBSUB_URL",
+        "The Redis to subscribe through, in place of REDIS_URL",
+        "a URL (e.g., redis://localhost:6379)",
+    ),
+    (
+        "REDIS_CACHE_URL",
+        "The Redis to keep the subscribed keys and the denylist on, if not the one subscribed through",
+        "a URL (e.g., redis://localhost:6380)",
+    ),
+];
+
+/// Variables Flodgatt accepts but no longer uses, which are left out of `--help`
+const UNUSED: &[&str] = &["USER", "SSE_FREQ", "WS_FREQ", "REDIS_FREQ"];
+
+/// What the command line asked for
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Cli {
+    /// The variables set by flags, which take precedence over every other source
+    pub vars: Vec<(String, String)>,
+    /// `--config <file>`
+    pub config_file: Option<String>,
+    /// `--dev`: run without Mastodon, Postgres, or Redis (see `Handler::dev`)
+    pub dev: bool,
+    /// `--print-config`: print the resolved configuration and exit
+    pub print_config: bool,
+    pub command: Option<Command>,
+}
+
+/// A subcommand, which is run in place of the server
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Check a running server (see `conformance`)
+    Conformance {
+        base_url: String,
+        access_token: Option<String>,
+    },
+    /// Print the equivalent of a Node streaming server's config (see `NodeImport`)
+    ImportNodeConfig { env_file: String },
+}
+
+impl Cli {
+    /// The command line Flodgatt was run with; exits with a usage message if it is invalid (or
+    /// with the help asked for)
+    pub fn from_args() -> Self {
+        Self::parse(std::env::args_os()).unwrap_or_else(|e| e.exit())
+    }
+
+    pub(crate) fn parse<I, T>(args: I) -> Result<Self, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let settings = settings();
+        let flags: Vec<(String, String)> = settings
+            .iter()
+            .map(|setting| (flag(setting.var), help(setting)))
+            .collect();
+        let app = settings
+            .iter()
+            .zip(&flags)
+            .fold(app(), |app, (setting, (flag, help))| {
+                app.arg(
+                    Arg::with_name(setting.var)
+                        .long(flag)
+                        .value_name(setting.var)
+                        .takes_value(true)
+                        .help(help)
+                        .hidden(UNUSED.contains(&setting.var)),
+                )
+            });
+        let matches = app.get_matches_from_safe(args)?;
+
+        let mut vars: Vec<(String, String)> = settings
+            .iter()
+            .filter_map(|setting| {
+                let value = matches.value_of(setting.var)?;
+                Some((setting.var.to_string(), value.to_string()))
+            })
+            .collect();
+        let level = match matches.occurrences_of("verbose") {
+            0 => None,
+            1 => Some("info"),
+            2 => Some("debug"),
+            _ => Some("trace"),
+        };
+        if let Some(level) = level {
+            vars.push(("RUST_LOG".to_string(), level.to_string()));
+        }
+        Ok(Self {
+            vars,
+            config_file: matches.value_of("config").map(String::from),
+            dev: matches.is_present("dev"),
+            print_config: matches.is_present("print-config"),
+            command: command(&matches),
+        })
+    }
+}
+
+/// The flags and subcommands that aren't settings
+fn app<'a, 'b>() -> App<'a, 'b> {
+    App::new("flodgatt")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about(env!("CARGO_PKG_DESCRIPTION"))
+        .setting(AppSettings::UnifiedHelpMessage)
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("A TOML file to read settings from, under those of the environment"),
+        )
+        .arg(
+            Arg::with_name("dev")
+                .long("dev")
+                .help("Run without Mastodon, Postgres, or Redis, sending synthetic events"),
+        )
+        .arg(
+            Arg::with_name("verbose")
+                .short("v")
+                .long("verbose")
+                .multiple(true)
+                .conflicts_with("RUST_LOG")
+                .help("Log at the `info` level (`-vv` for `debug`, `-vvv` for `trace`)"),
+        )
+        .arg(
+            Arg::with_name("print-config")
+                .long("print-config")
+                .help("Print the configuration from every source (passwords included) and exit"),
+        )
+        .subcommand(
+            SubCommand::with_name("conformance")
+                .about("Check a running server against the Mastodon streaming API")
+                .arg(Arg::with_name("base-url").required(true))
+                .arg(Arg::with_name("access-token")),
+        )
+        .subcommand(
+            SubCommand::with_name("import-node-config")
+                .about("Print the Flodgatt settings equivalent to a Node streaming server's")
+                .arg(Arg::with_name("env-file").default_value(".env.production")),
+        )
+}
+
+fn command(matches: &ArgMatches) -> Option<Command> {
+    match matches.subcommand() {
+        ("conformance", Some(args)) => Some(Command::Conformance {
+            base_url: args.value_of("base-url")?.to_string(),
+            access_token: args.value_of("access-token").map(String::from),
+        }),
+        ("import-node-config", Some(args)) => Some(Command::ImportNodeConfig {
+            env_file: args.value_of("env-file")?.to_string(),
+        }),
+        _ => None,
+    }
+}
+
+/// Every variable Flodgatt reads, in the order of `ENV_VARS`
+fn settings() -> Vec<Setting> {
+    let mut all = Postgres::settings();
+    all.extend(Redis::settings());
+    all.extend(Deployment::settings());
+    let untyped = UNTYPED.iter().map(|&(var, about, allowed_values)| {
+        Setting::new(var, about, allowed_values, "None".to_string())
+    });
+    all.extend(untyped);
+    let secret_files = SECRET_FILES.iter().map(|&(_, file_var)| {
+        let about = "A file to read the variable without `_FILE` from, in place of setting it";
+        Setting::new(file_var, about, "the path to a file", "None".to_string())
+    });
+    all.extend(secret_files);
+    all.push(Setting {
+        var: "NODE_ENV",
+        ..Env::setting()
+    });
+    all.extend(
+        UNUSED
+            .iter()
+            .map(|&var| Setting::new(var, "Not used", "any string", "None".to_string())),
+    );
+
+    ENV_VARS
+        .iter()
+        .filter_map(|var| all.iter().find(|setting| setting.var == *var).cloned())
+        .collect()
+}
+
+/// The flag that sets `var` (e.g., `redis-url` for `REDIS_URL`)
+fn flag(var: &str) -> String {
+    var.to_lowercase().replace('_', "-")
+}
+
+fn help(setting: &Setting) -> String {
+    let default = match &setting.default {
+        Some(default) => format!(" [default: {}]", default),
+        None => String::new(),
+    };
+    format!(
+        "{} (must be {}){}",
+        setting.about, setting.allowed_values, default
+    )
+}
@@ -0,0 +1,76 @@
+use super::*;
+
+fn parse(args: &[&str]) -> Cli {
+    Cli::parse(std::iter::once("flodgatt").chain(args.iter().cloned())).expect("valid arguments")
+}
+
+#[test]
+fn every_variable_has_a_flag() {
+    let settings = settings();
+    let vars: Vec<&str> = settings.iter().map(|setting| setting.var).collect();
+    assert_eq!(vars, ENV_VARS);
+
+    let cli = parse(&[
+        "--redis-url",
+        "redis://redis:6379",
+        "--port",
+        "4001",
+        "--dev",
+    ]);
+    assert_eq!(
+        cli.vars,
+        vec![
+            ("PORT".to_string(), "4001".to_string()),
+            ("REDIS_URL".to_string(), "redis://redis:6379".to_string()),
+        ]
+    );
+    assert!(cli.dev);
+}
+
+#[test]
+fn help_gives_the_defaults_and_allowed_values_of_the_types() {
+    let settings = settings();
+    let setting = |var| settings.iter().find(|s| s.var == var).expect("a setting");
+
+    assert_eq!(setting("PORT").default.as_deref(), Some("4000"));
+    assert_eq!(setting("SOCKET").default, None);
+    assert_eq!(setting("SOCKET_MODE").default.as_deref(), Some("666"));
+    assert_eq!(
+        help(setting("PORT")),
+        "The port to run Flodgatt on (must be a number between 0 and 65535) [default: 4000]"
+    );
+    assert!(help(setting("CLIENT_OVERFLOW")).contains("(must be one of: ["));
+}
+
+#[test]
+fn verbose_sets_the_log_level() {
+    let level = |args: &[&str]| parse(args).vars;
+    assert_eq!(level(&[]), vec![]);
+    assert_eq!(
+        level(&["-v"]),
+        vec![("RUST_LOG".to_string(), "info".to_string())]
+    );
+    assert_eq!(
+        level(&["-vvv"]),
+        vec![("RUST_LOG".to_string(), "trace".to_string())]
+    );
+    assert!(Cli::parse(vec!["flodgatt", "-v", "--rust-log", "warn"]).is_err());
+}
+
+#[test]
+fn subcommands_are_parsed() {
+    assert_eq!(
+        parse(&["conformance", "http://localhost:4000"]).command,
+        Some(Command::Conformance {
+            base_url: "http://localhost:4000".to_string(),
+            access_token: None,
+        })
+    );
+    assert_eq!(
+        parse(&["import-node-config"]).command,
+        Some(Command::ImportNodeConfig {
+            env_file: ".env.production".to_string()
+        })
+    );
+    assert!(Cli::parse(vec!["flodgatt", "conformance"]).is_err());
+}
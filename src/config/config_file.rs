@@ -0,0 +1,84 @@
+//! `--config <file>`: Flodgatt's settings in a TOML file, for deployments with more of them
+//! than are comfortable to keep in environmental variables
+//!
+//! Each setting is named as its environmental variable is, in lower case, and may be put in a
+//! `[postgres]`, `[redis]`, or `[deployment]` table to group it:
+//!
+//! ```toml
+//! rust_log = "warn"
+//!
+//! [redis]
+//! redis_host = "redis.internal"
+//! redis_max_channels = 5000
+//! instance_peers = ["10.0.0.2:4000", "10.0.0.3:4000"]
+//! ```
+//!
+//! Numbers and booleans are written as the variable would be, and a list is joined with commas.
+//! The environment (and the `.env` file) take precedence over the file.
+use super::environmental_variables::ENV_VARS;
+use super::Error;
+
+use std::fs;
+use toml::value::{Table, Value};
+
+#[cfg(test)]
+mod test;
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// The tables settings may be grouped in
+const SECTIONS: &[&str] = &["postgres", "redis", "deployment"];
+
+/// The variables set by the config file at `path`
+pub(crate) fn read(path: &str) -> Result<Vec<(String, String)>> {
+    let toml = fs::read_to_string(path)
+        .map_err(|e| Error::Config(format!("could not read the config file {}: {}", path, e)))?;
+    parse(&toml).map_err(|e| match e {
+        Error::Config(e) => Error::Config(format!("in the config file {}, {}", path, e)),
+        e => e,
+    })
+}
+
+/// The variables set by a config file's `toml`
+pub(crate) fn parse(toml: &str) -> Result<Vec<(String, String)>> {
+    let table: Table = toml::from_str(toml).map_err(|e| Error::Config(e.to_string()))?;
+    let mut vars = Vec::new();
+    for (key, value) in table {
+        match value {
+            Value::Table(section) if SECTIONS.contains(&key.as_str()) => {
+                for (setting, value) in section {
+                    let name = format!("{}.{}", key, setting);
+                    vars.push(var(&name, &setting, value)?);
+                }
+            }
+            value => vars.push(var(&key, &key, value)?),
+        }
+    }
+    Ok(vars)
+}
+
+/// The variable that the `setting` at `key` sets to `value`
+fn var(key: &str, setting: &str, value: Value) -> Result<(String, String)> {
+    let invalid = |why: &str| Error::Config(format!("`{}` {}", key, why));
+    let var = setting.to_uppercase();
+    if setting != setting.to_lowercase() || !ENV_VARS.contains(&var.as_str()) {
+        return Err(invalid("is not a setting Flodgatt reads"));
+    }
+    let text = |value: Value| match value {
+        Value::String(s) => Ok(s),
+        Value::Integer(i) => Ok(i.to_string()),
+        Value::Float(f) => Ok(f.to_string()),
+        Value::Boolean(b) => Ok(b.to_string()),
+        Value::Datetime(_) | Value::Array(_) | Value::Table(_) => Err(invalid(
+            "must be a string, number, boolean, or list of them",
+        )),
+    };
+    let value = match value {
+        Value::Array(items) => {
+            let items: Result<Vec<String>> = items.into_iter().map(text).collect();
+            items?.join(",")
+        }
+        value => text(value)?,
+    };
+    Ok((var, value))
+}
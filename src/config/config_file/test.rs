@@ -0,0 +1,40 @@
+use super::*;
+
+#[test]
+fn settings_are_read_from_the_top_level_and_from_sections() -> Result<()> {
+    let vars = parse(
+        r#"
+        rust_log = "warn"
+        ws_max_subscriptions = 10
+
+        [redis]
+        redis_host = "redis.internal"
+        redis_psubscribe = true
+        instance_peers = ["10.0.0.2:4000", "10.0.0.3:4000"]
+        "#,
+    )?;
+
+    let mut expected = vec![
+        ("RUST_LOG", "warn"),
+        ("WS_MAX_SUBSCRIPTIONS", "10"),
+        ("REDIS_HOST", "redis.internal"),
+        ("REDIS_PSUBSCRIBE", "true"),
+        ("INSTANCE_PEERS", "10.0.0.2:4000,10.0.0.3:4000"),
+    ];
+    expected.sort();
+    let mut vars: Vec<(&str, &str)> = vars.iter().map(|(k, v)| (&k[..], &v[..])).collect();
+    vars.sort();
+    assert_eq!(vars, expected);
+    Ok(())
+}
+
+#[test]
+fn errors_name_the_offending_key() {
+    let error = |toml| parse(toml).expect_err("invalid").to_string();
+
+    assert!(error("[redis]\nredis_hots = \"redis\"").contains("`redis.redis_hots`"));
+    assert!(error("REDIS_HOST = \"redis\"").contains("`REDIS_HOST`"));
+    assert!(error("[redis]\nredis_host = { name = \"redis\" }").contains("`redis.redis_host`"));
+    assert!(error("[metrics]\nmetrics_port = 9090").contains("`metrics`"));
+    assert!(error("redis_host = ").contains("line 1"));
+}
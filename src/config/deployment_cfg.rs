@@ -1,15 +1,59 @@
 use super::deployment_cfg_types::*;
-use super::{EnvVar, Error};
+use super::{EnvVar, Error, Setting};
 
 #[derive(Debug, Default)]
 pub struct Deployment<'a> {
     pub(crate) env: Env,
     pub(crate) log_level: LogLevel,
+    pub(crate) log_format: LogFormat,
+    pub(crate) otlp_endpoint: OtlpEndpoint,
     pub address: FlodgattAddr,
     pub port: Port,
     pub unix_socket: Socket,
+    pub socket_mode: SocketMode,
+    pub ipc_socket: IpcSocket,
+    pub ipc_lifecycle: IpcLifecycle,
     pub cors: Cors<'a>,
     pub whitelist_mode: WhitelistMode,
+    pub admin_token: AdminToken,
+    pub wait_for_deps: WaitForDeps,
+    pub event_names: EventNames,
+    pub event_ttls: EventTtls,
+    pub delete_payload: DeletePayload,
+    pub hello_event: HelloEvent,
+    pub scrub_emails: ScrubEmails,
+    pub scrub_account_fields: ScrubAccountFields,
+    pub scrub_media_urls: ScrubMediaUrls,
+    pub instance_id: InstanceId,
+    pub instance_peers: InstancePeers,
+    pub identity_headers: IdentityHeaders,
+    pub disabled_streams: DisabledStreams,
+    pub ws_max_subscriptions: WsMaxSubscriptions,
+    pub composite_streams: CompositeStreams,
+    pub composite_stream_auth: CompositeStreamAuth,
+    pub shard_urls: ShardUrls,
+    pub shard_index: ShardIndex,
+    pub geoip_database: GeoipDatabase,
+    pub geo_policy: GeoPolicy,
+    pub geo_throttle: GeoThrottle,
+    pub dev_tokens: DevTokens,
+    pub generator_rate: GeneratorRate,
+    pub generator_target: GeneratorTarget,
+    pub generator_content_size: GeneratorContentSize,
+    pub generator_notifications: GeneratorNotifications,
+    pub generator_seed: GeneratorSeed,
+    pub seed: Seed,
+    pub jitter: Jitter,
+    pub decision_log: DecisionLog,
+    pub receipt_log: ReceiptLog,
+    pub receipt_sample: ReceiptSample,
+    pub canary_interval: CanaryInterval,
+    pub canary_deadline: CanaryDeadline,
+    pub event_history: EventHistory,
+    pub first_byte_timeout: FirstByteTimeout,
+    pub shutdown_timeout: ShutdownTimeout,
+    pub metrics_port: MetricsPort,
+    pub metrics_path: MetricsPath,
 }
 
 impl Deployment<'_> {
@@ -17,13 +61,169 @@ impl Deployment<'_> {
         let mut cfg = Self {
             env: Env::default().maybe_update(env.get("NODE_ENV"))?,
             log_level: LogLevel::default().maybe_update(env.get("RUST_LOG"))?,
+            log_format: LogFormat::default().maybe_update(env.get("LOG_FORMAT"))?,
+            otlp_endpoint: OtlpEndpoint::default().maybe_update(env.get("OTLP_ENDPOINT"))?,
             address: FlodgattAddr::default().maybe_update(env.get("BIND"))?,
             port: Port::default().maybe_update(env.get("PORT"))?,
             unix_socket: Socket::default().maybe_update(env.get("SOCKET"))?,
+            socket_mode: SocketMode::default().maybe_update(env.get("SOCKET_MODE"))?,
+            ipc_socket: IpcSocket::default().maybe_update(env.get("IPC_SOCKET"))?,
+            ipc_lifecycle: IpcLifecycle::default().maybe_update(env.get("IPC_LIFECYCLE"))?,
             whitelist_mode: WhitelistMode::default().maybe_update(env.get("WHITELIST_MODE"))?,
+            admin_token: AdminToken::default().maybe_update(env.get("ADMIN_TOKEN"))?,
+            wait_for_deps: WaitForDeps::default().maybe_update(env.get("WAIT_FOR_DEPS"))?,
+            event_names: EventNames::default().maybe_update(env.get("EVENT_NAMES"))?,
+            event_ttls: EventTtls::default().maybe_update(env.get("EVENT_TTLS"))?,
+            delete_payload: DeletePayload::default().maybe_update(env.get("DELETE_PAYLOAD"))?,
+            hello_event: HelloEvent::default().maybe_update(env.get("HELLO_EVENT"))?,
+            scrub_emails: ScrubEmails::default().maybe_update(env.get("SCRUB_EMAILS"))?,
+            scrub_account_fields: ScrubAccountFields::default()
+                .maybe_update(env.get("SCRUB_ACCOUNT_FIELDS"))?,
+            scrub_media_urls: ScrubMediaUrls::default()
+                .maybe_update(env.get("SCRUB_MEDIA_URLS"))?,
+            instance_id: InstanceId::default().maybe_update(env.get("INSTANCE_ID"))?,
+            instance_peers: InstancePeers::default().maybe_update(env.get("INSTANCE_PEERS"))?,
+            identity_headers: IdentityHeaders::default()
+                .maybe_update(env.get("IDENTITY_HEADERS"))?,
+            disabled_streams: DisabledStreams::default()
+                .maybe_update(env.get("DISABLED_STREAMS"))?,
+            ws_max_subscriptions: WsMaxSubscriptions::default()
+                .maybe_update(env.get("WS_MAX_SUBSCRIPTIONS"))?,
+            composite_streams: CompositeStreams::default()
+                .maybe_update(env.get("COMPOSITE_STREAMS"))?,
+            composite_stream_auth: CompositeStreamAuth::default()
+                .maybe_update(env.get("COMPOSITE_STREAM_AUTH"))?,
+            shard_urls: ShardUrls::default().maybe_update(env.get("SHARD_URLS"))?,
+            shard_index: ShardIndex::default().maybe_update(env.get("SHARD_INDEX"))?,
+            geoip_database: GeoipDatabase::default().maybe_update(env.get("GEOIP_DATABASE"))?,
+            geo_policy: GeoPolicy::default().maybe_update(env.get("GEO_POLICY"))?,
+            geo_throttle: GeoThrottle::default().maybe_update(env.get("GEO_THROTTLE"))?,
+            dev_tokens: DevTokens::default().maybe_update(env.get("DEV_TOKENS"))?,
+            generator_rate: GeneratorRate::default().maybe_update(env.get("GENERATOR_RATE"))?,
+            generator_target: GeneratorTarget::default()
+                .maybe_update(env.get("GENERATOR_TARGET"))?,
+            generator_content_size: GeneratorContentSize::default()
+                .maybe_update(env.get("GENERATOR_CONTENT_SIZE"))?,
+            generator_notifications: GeneratorNotifications::default()
+                .maybe_update(env.get("GENERATOR_NOTIFICATIONS"))?,
+            generator_seed: GeneratorSeed::default().maybe_update(env.get("GENERATOR_SEED"))?,
+            seed: Seed::default().maybe_update(env.get("SEED"))?,
+            jitter: Jitter::default().maybe_update(env.get("JITTER"))?,
+            decision_log: DecisionLog::default().maybe_update(env.get("DECISION_LOG"))?,
+            receipt_log: ReceiptLog::default().maybe_update(env.get("RECEIPT_LOG"))?,
+            receipt_sample: ReceiptSample::default().maybe_update(env.get("RECEIPT_SAMPLE"))?,
+            canary_interval: CanaryInterval::default().maybe_update(env.get("CANARY_INTERVAL"))?,
+            canary_deadline: CanaryDeadline::default().maybe_update(env.get("CANARY_DEADLINE"))?,
+            event_history: EventHistory::default().maybe_update(env.get("EVENT_HISTORY"))?,
+            first_byte_timeout: FirstByteTimeout::default()
+                .maybe_update(env.get("FIRST_BYTE_TIMEOUT"))?,
+            shutdown_timeout: ShutdownTimeout::default()
+                .maybe_update(env.get("SHUTDOWN_TIMEOUT"))?,
+            metrics_port: MetricsPort::default().maybe_update(env.get("METRICS_PORT"))?,
+            metrics_path: MetricsPath::default().maybe_update(env.get("METRICS_PATH"))?,
             cors: Cors::default(),
         };
         cfg.env = cfg.env.maybe_update(env.get("RUST_ENV"))?;
+        if !cfg.shard_urls.is_empty() && *cfg.shard_index >= cfg.shard_urls.len() {
+            let index = cfg.shard_index.to_string();
+            Err(Error::config(
+                "SHARD_INDEX",
+                index.as_str(),
+                "less than the number of SHARD_URLS",
+            ))?
+        }
+        if let Some(name) = cfg
+            .composite_stream_auth
+            .keys()
+            .find(|name| !cfg.composite_streams.contains_key(*name))
+        {
+            Err(Error::config(
+                "COMPOSITE_STREAM_AUTH",
+                name.as_str(),
+                "one of the COMPOSITE_STREAMS",
+            ))?
+        }
+        cfg.warn_if_not_compiled();
         Ok(cfg)
     }
+
+    /// What `--help` says about each of the deployment's variables
+    pub(super) fn settings() -> Vec<Setting> {
+        vec![
+            Env::setting(),
+            FlodgattAddr::setting(),
+            LogLevel::setting(),
+            OtlpEndpoint::setting(),
+            LogFormat::setting(),
+            Socket::setting(),
+            SocketMode::setting(),
+            IpcSocket::setting(),
+            IpcLifecycle::setting(),
+            Port::setting(),
+            WhitelistMode::setting(),
+            AdminToken::setting(),
+            WaitForDeps::setting(),
+            EventNames::setting(),
+            EventTtls::setting(),
+            DeletePayload::setting(),
+            HelloEvent::setting(),
+            ScrubEmails::setting(),
+            ScrubAccountFields::setting(),
+            ScrubMediaUrls::setting(),
+            InstanceId::setting(),
+            IdentityHeaders::setting(),
+            DisabledStreams::setting(),
+            WsMaxSubscriptions::setting(),
+            CompositeStreams::setting(),
+            CompositeStreamAuth::setting(),
+            InstancePeers::setting(),
+            ShardUrls::setting(),
+            ShardIndex::setting(),
+            GeoipDatabase::setting(),
+            GeoPolicy::setting(),
+            GeoThrottle::setting(),
+            DevTokens::setting(),
+            GeneratorRate::setting(),
+            GeneratorTarget::setting(),
+            GeneratorContentSize::setting(),
+            GeneratorNotifications::setting(),
+            GeneratorSeed::setting(),
+            Seed::setting(),
+            Jitter::setting(),
+            DecisionLog::setting(),
+            ReceiptLog::setting(),
+            ReceiptSample::setting(),
+            CanaryInterval::setting(),
+            ShutdownTimeout::setting(),
+            CanaryDeadline::setting(),
+            EventHistory::setting(),
+            FirstByteTimeout::setting(),
+            MetricsPort::setting(),
+            MetricsPath::setting(),
+        ]
+    }
+
+    /// Warn about settings for the optional subsystems that this build was compiled without
+    fn warn_if_not_compiled(&self) {
+        #[cfg(not(feature = "admin"))]
+        {
+            if self.admin_token.is_some() {
+                tracing::warn!("Built without the `admin` feature; no admin API for ADMIN_TOKEN");
+            }
+        }
+        #[cfg(not(feature = "otlp"))]
+        {
+            if self.otlp_endpoint.is_some() {
+                tracing::warn!(
+                    "Built without the `otlp` feature; not exporting to the OTLP_ENDPOINT"
+                );
+            }
+        }
+        #[cfg(not(feature = "geoip"))]
+        {
+            if !self.geoip_database.is_empty() {
+                tracing::warn!("Built without the `geoip` feature; not reading the GEOIP_DATABASE");
+            }
+        }
+    }
 }
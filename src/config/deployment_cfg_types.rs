@@ -1,7 +1,9 @@
 use crate::from_env_var;
+use hashbrown::HashMap;
 use std::fmt;
 use std::net::{IpAddr, Ipv4Addr};
 use std::str::FromStr;
+use std::time::Duration;
 use strum_macros::{EnumString, EnumVariantNames};
 
 from_env_var!(
@@ -28,6 +30,20 @@ from_env_var!(
     let (env_var, allowed_values) = ("RUST_LOG",  &format!("one of: {:?}", LogLevelInner::variants())); 
     let from_str = |s| LogLevelInner::from_str(s).ok();
 );
+from_env_var!(
+    /// An OpenTelemetry collector to export spans to over OTLP (with the `otlp` feature)
+    let name = OtlpEndpoint;
+    let default: Option<String> = None;
+    let (env_var, allowed_values) = ("OTLP_ENDPOINT", "a URL (e.g., http://localhost:4317)");
+    let from_str = |s| Some(Some(s.to_string()));
+);
+from_env_var!(
+    /// Whether to log lines for people to read or JSON objects for a log pipeline
+    let name = LogFormat;
+    let default: LogFormatInner = LogFormatInner::Text;
+    let (env_var, allowed_values) = ("LOG_FORMAT", &format!("one of: {:?}", LogFormatInner::variants()));
+    let from_str = |s| LogFormatInner::from_str(s).ok();
+);
 from_env_var!(
     /// A Unix Socket to use in place of a local address
     let name = Socket;
@@ -35,6 +51,31 @@ from_env_var!(
     let (env_var, allowed_values) = ("SOCKET", "any string");
     let from_str = |s| Some(Some(s.to_string()));
 );
+from_env_var!(
+    /// The permissions of the SOCKET (as with `chmod`), which the user nginx runs as needs to
+    /// read and write
+    let name = SocketMode;
+    let default: FileModeInner = FileModeInner(0o666);
+    let (env_var, allowed_values) = ("SOCKET_MODE", "an octal mode up to 777 (e.g., 660)");
+    let from_str = |s| u32::from_str_radix(s.trim_start_matches("0o"), 8)
+        .ok()
+        .filter(|mode| *mode <= 0o777)
+        .map(FileModeInner);
+);
+from_env_var!(
+    /// A Unix Socket on which to publish every event for local sidecar processes
+    let name = IpcSocket;
+    let default: Option<String> = None;
+    let (env_var, allowed_values) = ("IPC_SOCKET", "any string");
+    let from_str = |s| Some(Some(s.to_string()));
+);
+from_env_var!(
+    /// Whether to also publish the changefeed of clients' connections on the IPC_SOCKET
+    let name = IpcLifecycle;
+    let default: bool = false;
+    let (env_var, allowed_values) = ("IPC_LIFECYCLE", "true or false");
+    let from_str = |s| s.parse().ok();
+);
 from_env_var!(
     /// The port to run Flodgatt on
     let name = Port;
@@ -52,6 +93,398 @@ from_env_var!(
     let (env_var, allowed_values) = ("WHITELIST_MODE", "true or false");
     let from_str = |s| s.parse().ok();
 );
+from_env_var!(
+    /// A bearer token that grants access to the admin API (which is disabled if no token is set)
+    let secret = AdminToken;
+    let default: Option<String> = None;
+    let (env_var, allowed_values) = ("ADMIN_TOKEN", "any string");
+    let from_str = |s| Some(Some(s.to_string()));
+);
+from_env_var!(
+    /// How long to keep retrying Redis and Postgres at startup before giving up
+    let name = WaitForDeps;
+    let default: Option<Duration> = None;
+    let (env_var, allowed_values) = ("WAIT_FOR_DEPS", "a number of seconds (0 to exit immediately)");
+    let from_str = |s| match s.parse() {
+        Ok(0) => Some(None),
+        Ok(secs) => Some(Some(Duration::from_secs(secs))),
+        Err(_) => None,
+    };
+);
+from_env_var!(
+    /// Event names to send in place of Mastodon's (e.g., `update=status.update`)
+    let name = EventNames;
+    let default: HashMap<String, String> = HashMap::new();
+    let (env_var, allowed_values) = ("EVENT_NAMES", "comma-separated pairs (e.g., `update=status.update,delete=status.delete`)");
+    let from_str = |s| s
+        .split(',')
+        .map(|pair| match &pair.splitn(2, '=').map(str::trim).collect::<Vec<_>>()[..] {
+            [from, to] if !from.is_empty() && !to.is_empty() => Some((from.to_string(), to.to_string())),
+            _ => None,
+        })
+        .collect();
+);
+from_env_var!(
+    /// How long (in seconds) each kind of event stays relevant, by Mastodon's event name (e.g.,
+    /// `update=300`).  WebSocket messages and IPC frames for these events carry it as a `ttl`
+    /// for downstream caches; Server Sent Events have no field to carry it in.
+    let name = EventTtls;
+    let default: HashMap<String, u64> = HashMap::new();
+    let (env_var, allowed_values) = ("EVENT_TTLS", "comma-separated pairs of an event name and a number of seconds (e.g., `update=300,announcement=86400`)");
+    let from_str = |s| s
+        .split(',')
+        .map(|pair| match &pair.splitn(2, '=').map(str::trim).collect::<Vec<_>>()[..] {
+            [name, secs] if !name.is_empty() => Some((name.to_string(), secs.parse().ok()?)),
+            _ => None,
+        })
+        .collect();
+);
+from_env_var!(
+    /// Whether `delete` events carry the deleted status's ID, an object containing it, or both
+    /// (in two events)
+    let name = DeletePayload;
+    let default: DeletePayloadInner = DeletePayloadInner::Id;
+    let (env_var, allowed_values) = ("DELETE_PAYLOAD", &format!("one of: {:?}", DeletePayloadInner::variants()));
+    let from_str = |s| DeletePayloadInner::from_str(s).ok();
+);
+from_env_var!(
+    /// Whether to send each client a `connected` event describing its connection first
+    let name = HelloEvent;
+    let default: bool = false;
+    let (env_var, allowed_values) = ("HELLO_EVENT", "true or false");
+    let from_str = |s| s.parse().ok();
+);
+from_env_var!(
+    /// Whether to redact email addresses from statuses sent to anonymous clients of the public
+    /// and hashtag timelines
+    let name = ScrubEmails;
+    let default: bool = false;
+    let (env_var, allowed_values) = ("SCRUB_EMAILS", "true or false");
+    let from_str = |s| s.parse().ok();
+);
+from_env_var!(
+    /// The account attributes to send to anonymous clients of the public and hashtag timelines
+    /// (all of them, unless set)
+    let name = ScrubAccountFields;
+    let default: Option<Vec<String>> = None;
+    let (env_var, allowed_values) = ("SCRUB_ACCOUNT_FIELDS", "a comma-separated list of attributes (e.g., `id,username,acct,url`)");
+    let from_str = |s| Some(Some(s
+        .split(',')
+        .map(|field| field.trim().to_string())
+        .filter(|field| !field.is_empty())
+        .collect()));
+);
+from_env_var!(
+    /// Whether to remove the URLs of media attachments from statuses sent to anonymous clients
+    /// of the public and hashtag timelines
+    let name = ScrubMediaUrls;
+    let default: bool = false;
+    let (env_var, allowed_values) = ("SCRUB_MEDIA_URLS", "true or false");
+    let from_str = |s| s.parse().ok();
+);
+from_env_var!(
+    /// A name for this instance, which is sent to clients so that a load balancer can route
+    /// their later requests back to it
+    let name = InstanceId;
+    let default: Option<String> = None;
+    let (env_var, allowed_values) = ("INSTANCE_ID", "letters, digits, `-`, `_`, and `.`");
+    let from_str = |s| match s.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c)) {
+        true => Some(Some(s.to_string())),
+        false => None,
+    };
+);
+from_env_var!(
+    /// Whether to identify Flodgatt (and the `INSTANCE_ID`) in the `Server` and `X-Served-By`
+    /// response headers
+    let name = IdentityHeaders;
+    let default: bool = true;
+    let (env_var, allowed_values) = ("IDENTITY_HEADERS", "true or false");
+    let from_str = |s| s.parse().ok();
+);
+from_env_var!(
+    /// The stream types that clients are refused (with a 503) until they are enabled again
+    /// through the admin API
+    let name = DisabledStreams;
+    let default: Vec<String> = Vec::new();
+    let (env_var, allowed_values) = ("DISABLED_STREAMS", &format!("a comma-separated list of {:?}", STREAM_TYPES));
+    let from_str = |s| s
+        .split(',')
+        .map(str::trim)
+        .filter(|stream| !stream.is_empty())
+        .map(|stream| Some(stream.to_string()).filter(|_| STREAM_TYPES.contains(&stream)))
+        .collect();
+);
+from_env_var!(
+    /// The most streams a client may subscribe to over one WebSocket connection, including the
+    /// one it connected with (unlimited, as in Mastodon's streaming server, unless set)
+    let name = WsMaxSubscriptions;
+    let default: Option<usize> = None;
+    let (env_var, allowed_values) = ("WS_MAX_SUBSCRIPTIONS", "a positive number");
+    let from_str = |s| match s.parse() {
+        Ok(0) | Err(_) => None,
+        Ok(max) => Some(Some(max)),
+    };
+);
+from_env_var!(
+    /// Streams composed of other streams, which clients subscribe to by name (e.g.,
+    /// `events=hashtag:conf2024+list:42+public:local`) and are sent the events of them all
+    let name = CompositeStreams;
+    let default: HashMap<String, Vec<CompositePartInner>> = HashMap::new();
+    let (env_var, allowed_values) = ("COMPOSITE_STREAMS", "comma-separated pairs of a name and the public, `hashtag:<tag>`, `hashtag:local:<tag>`, and `list:<id>` streams to compose it of, joined by `+`");
+    let from_str = |s| s
+        .split(',')
+        .map(|pair| match &pair.splitn(2, '=').map(str::trim).collect::<Vec<_>>()[..] {
+            [name, parts] if is_composite_name(name) => Some((
+                name.to_string(),
+                parts.split('+').map(str::trim).map(CompositePartInner::parse).collect::<Option<_>>()?,
+            )),
+            _ => None,
+        })
+        .collect();
+);
+from_env_var!(
+    /// Who may subscribe to each of the COMPOSITE_STREAMS (by default, any user with a valid
+    /// token), in place of who may read the streams it is composed of
+    let name = CompositeStreamAuth;
+    let default: HashMap<String, CompositeAuthInner> = HashMap::new();
+    let (env_var, allowed_values) = ("COMPOSITE_STREAM_AUTH", &format!("comma-separated pairs of one of the COMPOSITE_STREAMS and one of {:?} (e.g., `events=public`)", CompositeAuthInner::variants()));
+    let from_str = |s| s
+        .split(',')
+        .map(|pair| match &pair.splitn(2, '=').map(str::trim).collect::<Vec<_>>()[..] {
+            [name, auth] => Some((name.to_string(), CompositeAuthInner::from_str(auth).ok()?)),
+            _ => None,
+        })
+        .collect();
+);
+from_env_var!(
+    /// The base URLs of the other instances behind the same load balancer, by `INSTANCE_ID`
+    let name = InstancePeers;
+    let default: HashMap<String, String> = HashMap::new();
+    let (env_var, allowed_values) = ("INSTANCE_PEERS", "comma-separated pairs (e.g., `flodgatt-2=https://s2.example.com`)");
+    let from_str = |s| s
+        .split(',')
+        .map(|pair| match &pair.splitn(2, '=').map(str::trim).collect::<Vec<_>>()[..] {
+            [id, url] if !id.is_empty() && !url.is_empty() => {
+                Some((id.to_string(), url.trim_end_matches('/').to_string()))
+            }
+            _ => None,
+        })
+        .collect();
+);
+from_env_var!(
+    /// The base URLs of every Flodgatt instance that splits the public and hashtag timelines
+    /// between them (empty unless sharding)
+    let name = ShardUrls;
+    let default: Vec<String> = Vec::new();
+    let (env_var, allowed_values) = ("SHARD_URLS", "a comma-separated list of URLs");
+    let from_str = |s| Some(s
+        .split(',')
+        .map(|url| url.trim().trim_end_matches('/').to_string())
+        .filter(|url| !url.is_empty())
+        .collect());
+);
+from_env_var!(
+    /// Which of the `SHARD_URLS` is this instance
+    let name = ShardIndex;
+    let default: usize = 0;
+    let (env_var, allowed_values) = ("SHARD_INDEX", "the index of this instance in SHARD_URLS");
+    let from_str = |s| s.parse().ok();
+);
+from_env_var!(
+    /// MaxMind databases (e.g., GeoLite2 Country and ASN) that say where connections come from
+    let name = GeoipDatabase;
+    let default: Vec<String> = Vec::new();
+    let (env_var, allowed_values) = ("GEOIP_DATABASE", "a comma-separated list of paths to `.mmdb` files");
+    let from_str = |s| Some(s
+        .split(',')
+        .map(|path| path.trim().to_string())
+        .filter(|path| !path.is_empty())
+        .collect());
+);
+from_env_var!(
+    /// What to do with connections from each country (e.g., `CN`) or autonomous system (e.g.,
+    /// `AS16509`)
+    let name = GeoPolicy;
+    let default: HashMap<String, GeoActionInner> = HashMap::new();
+    let (env_var, allowed_values) = ("GEO_POLICY", &format!("comma-separated pairs of a country or AS and one of {:?} (e.g., `AS16509=reject`)", GeoActionInner::variants()));
+    let from_str = |s| s
+        .split(',')
+        .map(|pair| match &pair.splitn(2, '=').map(str::trim).collect::<Vec<_>>()[..] {
+            [origin, action] if is_geo_origin(origin) => {
+                Some((origin.to_ascii_uppercase(), GeoActionInner::from_str(action).ok()?))
+            }
+            _ => None,
+        })
+        .collect();
+);
+from_env_var!(
+    /// How many new connections per minute to accept from each throttled country or AS
+    let name = GeoThrottle;
+    let default: u32 = 60;
+    let (env_var, allowed_values) = ("GEO_THROTTLE", "a number of connections per minute (greater than 0)");
+    let from_str = |s| s.parse().ok().filter(|&n| n > 0);
+);
+from_env_var!(
+    /// The access tokens accepted in `--dev` mode, and the account ID each logs in as
+    let secret = DevTokens;
+    let default: HashMap<String, i64> = vec![("dev".to_string(), 1)].into_iter().collect();
+    let (env_var, allowed_values) = ("DEV_TOKENS", "comma-separated pairs (e.g., `alice=1,bob=2`)");
+    let from_str = |s| s
+        .split(',')
+        .map(|pair| match &pair.splitn(2, '=').map(str::trim).collect::<Vec<_>>()[..] {
+            [token, id] if !token.is_empty() => Some((token.to_string(), id.parse().ok()?)),
+            _ => None,
+        })
+        .collect();
+);
+from_env_var!(
+    /// How many synthetic events to publish per second (the generator is off unless this is set
+    /// or Flodgatt is running with `--dev`)
+    let name = GeneratorRate;
+    let default: Option<f64> = None;
+    let (env_var, allowed_values) = ("GENERATOR_RATE", "a number of events per second (greater than 0)");
+    let from_str = |s| match s.parse() {
+        Ok(rate) if rate > 0.0 && f64::is_finite(rate) => Some(Some(rate)),
+        _ => None,
+    };
+);
+from_env_var!(
+    /// Whether synthetic events are published to Redis or sent straight to clients
+    let name = GeneratorTarget;
+    let default: GeneratorTargetInner = GeneratorTargetInner::Redis;
+    let (env_var, allowed_values) = ("GENERATOR_TARGET", &format!("one of: {:?}", GeneratorTargetInner::variants()));
+    let from_str = |s| GeneratorTargetInner::from_str(s).ok();
+);
+from_env_var!(
+    /// The distribution of synthetic statuses' content lengths, in bytes
+    let name = GeneratorContentSize;
+    let default: ContentSizeInner = ContentSizeInner::Exponential(280);
+    let (env_var, allowed_values) = ("GENERATOR_CONTENT_SIZE", "a size (`500`), a range (`100-1000`), or a mean (`exp:280`)");
+    let from_str = |s| ContentSizeInner::parse(s);
+);
+from_env_var!(
+    /// The fraction of synthetic events that are notifications rather than statuses
+    let name = GeneratorNotifications;
+    let default: f64 = 0.1;
+    let (env_var, allowed_values) = ("GENERATOR_NOTIFICATIONS", "a number from 0 to 1");
+    let from_str = |s| s.parse().ok().filter(|f| (0.0..=1.0).contains(f));
+);
+from_env_var!(
+    /// A seed for the generator, so that load tests can repeat the same sequence of events
+    let name = GeneratorSeed;
+    let default: Option<u64> = None;
+    let (env_var, allowed_values) = ("GENERATOR_SEED", "a positive integer");
+    let from_str = |s| s.parse().ok().map(Some);
+);
+from_env_var!(
+    /// A seed for everything this server would otherwise pick at random (the generator, unless
+    /// it has its own `GENERATOR_SEED`, the canary's hashtag, and the `JITTER`), so that a load
+    /// test can be repeated
+    let name = Seed;
+    let default: Option<u64> = None;
+    let (env_var, allowed_values) = ("SEED", "a positive integer");
+    let from_str = |s| s.parse().ok().map(Some);
+);
+from_env_var!(
+    /// How much to vary the period of each heartbeat, retry, and other periodic work at random,
+    /// as a fraction of it, so that timers started together don't stay in step
+    let name = Jitter;
+    let default: f64 = 0.1;
+    let (env_var, allowed_values) = ("JITTER", "a number from 0 to 0.5");
+    let from_str = |s| s.parse().ok().filter(|f| (0.0..=0.5).contains(f));
+);
+from_env_var!(
+    /// A file to append the `RedisManager`'s decisions about its subscriptions to, one JSON
+    /// line each, so that two runs of a load test can be compared
+    let name = DecisionLog;
+    let default: Option<String> = None;
+    let (env_var, allowed_values) = ("DECISION_LOG", "a file path");
+    let from_str = |s| Some(Some(s.to_string()));
+);
+from_env_var!(
+    /// A file to append a sample of the deliveries of statuses to, one JSON line each, so that
+    /// a report that a client never got a status can be checked
+    let name = ReceiptLog;
+    let default: Option<String> = None;
+    let (env_var, allowed_values) = ("RECEIPT_LOG", "a file path");
+    let from_str = |s| Some(Some(s.to_string()));
+);
+from_env_var!(
+    /// How many deliveries there are to each one recorded in the `RECEIPT_LOG`
+    let name = ReceiptSample;
+    let default: u64 = 1000;
+    let (env_var, allowed_values) = ("RECEIPT_SAMPLE", "a number of deliveries (greater than 0)");
+    let from_str = |s| match s.parse() {
+        Ok(0) | Err(_) => None,
+        Ok(every) => Some(every),
+    };
+);
+from_env_var!(
+    /// How often to check that a marker published to Redis reaches a client of this server
+    let name = CanaryInterval;
+    let default: Option<Duration> = None;
+    let (env_var, allowed_values) = ("CANARY_INTERVAL", "a number of seconds (0 to disable)");
+    let from_str = |s| match s.parse() {
+        Ok(0) => Some(None),
+        Ok(secs) => Some(Some(Duration::from_secs(secs))),
+        Err(_) => None,
+    };
+);
+from_env_var!(
+    /// How long to wait, on shutdown, for clients' connections to close after they are sent
+    /// their last event
+    let name = ShutdownTimeout;
+    let default: Duration = Duration::from_secs(10);
+    let (env_var, allowed_values) = ("SHUTDOWN_TIMEOUT", "a number of seconds");
+    let from_str = |s| s.parse().ok().map(Duration::from_secs);
+);
+from_env_var!(
+    /// How long the canary's marker may take to arrive before the check fails
+    let name = CanaryDeadline;
+    let default: Duration = Duration::from_secs(10);
+    let (env_var, allowed_values) = ("CANARY_DEADLINE", "a number of seconds (greater than 0)");
+    let from_str = |s| match s.parse() {
+        Ok(0) | Err(_) => None,
+        Ok(secs) => Some(Duration::from_secs(secs)),
+    };
+);
+from_env_var!(
+    /// How many of the latest events of each timeline to keep for `GET
+    /// /api/v1/streaming/history` (which is disabled unless this is set)
+    let name = EventHistory;
+    let default: Option<usize> = None;
+    let (env_var, allowed_values) = ("EVENT_HISTORY", "a number of events (0 to disable)");
+    let from_str = |s| match s.parse() {
+        Ok(0) => Some(None),
+        Ok(len) => Some(Some(len)),
+        Err(_) => None,
+    };
+);
+from_env_var!(
+    /// How long a new connection may go without being sent anything before a heartbeat is sent
+    let name = FirstByteTimeout;
+    let default: Option<Duration> = Some(Duration::from_secs(1));
+    let (env_var, allowed_values) = ("FIRST_BYTE_TIMEOUT", "a number of milliseconds (0 to disable)");
+    let from_str = |s| match s.parse() {
+        Ok(0) => Some(None),
+        Ok(millis) => Some(Some(Duration::from_millis(millis))),
+        Err(_) => None,
+    };
+);
+from_env_var!(
+    /// The port to serve Prometheus metrics on (by default, they are only in the admin API)
+    let name = MetricsPort;
+    let default: Option<u16> = None;
+    let (env_var, allowed_values) = ("METRICS_PORT", "a number between 0 and 65535");
+    let from_str = |s| s.parse().ok().map(Some);
+);
+from_env_var!(
+    /// The path to serve Prometheus metrics at, on the METRICS_PORT
+    let name = MetricsPath;
+    let default: String = "/metrics".to_string();
+    let (env_var, allowed_values) = ("METRICS_PATH", "a path starting with `/`");
+    let from_str = |s| Some(s.to_string()).filter(|s| s.starts_with('/'));
+);
 /// Permissions for Cross Origin Resource Sharing (CORS)
 pub struct Cors<'a> {
     pub allowed_headers: Vec<&'a str>,
@@ -75,6 +508,15 @@ impl fmt::Debug for Cors<'_> {
     }
 }
 
+/// Unix file permissions, which read and print in octal
+#[derive(Clone, Copy, PartialEq)]
+pub struct FileModeInner(pub u32);
+impl fmt::Debug for FileModeInner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:03o}", self.0)
+    }
+}
+
 #[derive(EnumString, EnumVariantNames, Debug, Clone)]
 #[strum(serialize_all = "snake_case")]
 pub enum LogLevelInner {
@@ -85,9 +527,144 @@ pub enum LogLevelInner {
     Error,
 }
 
+#[derive(EnumString, EnumVariantNames, Debug, Clone, Copy)]
+#[strum(serialize_all = "snake_case")]
+pub enum LogFormatInner {
+    Text,
+    Json,
+}
+
 #[derive(EnumString, EnumVariantNames, Debug, Clone)]
 #[strum(serialize_all = "snake_case")]
 pub enum EnvInner {
     Production,
     Development,
 }
+
+#[derive(EnumString, EnumVariantNames, Debug, Clone, Copy, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+pub enum GeneratorTargetInner {
+    /// `PUBLISH` each event, as Mastodon would
+    Redis,
+    /// Skip Redis and send each event to subscribed clients directly
+    Pipeline,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ContentSizeInner {
+    Fixed(usize),
+    /// Evenly distributed between the two sizes (inclusive)
+    Uniform(usize, usize),
+    /// Mostly short, with a long tail (up to ten times the mean), like real statuses
+    Exponential(usize),
+}
+
+impl ContentSizeInner {
+    fn parse(s: &str) -> Option<Self> {
+        let size = |s: &str| s.trim().parse().ok();
+        if s.starts_with("exp:") {
+            size(&s["exp:".len()..]).map(ContentSizeInner::Exponential)
+        } else if let [min, max] = &s.splitn(2, '-').collect::<Vec<_>>()[..] {
+            let (min, max) = (size(min)?, size(max)?);
+            Some(ContentSizeInner::Uniform(min, max)).filter(|_| min <= max)
+        } else {
+            size(s).map(ContentSizeInner::Fixed)
+        }
+    }
+}
+
+/// What `GEO_POLICY` does with a connection, from the most lenient to the strictest
+#[derive(EnumString, EnumVariantNames, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[strum(serialize_all = "snake_case")]
+pub enum GeoActionInner {
+    /// Accept the connection, but log and count it
+    Tag,
+    /// Accept no more than `GEO_THROTTLE` new connections per minute
+    Throttle,
+    /// Turn the connection away
+    Reject,
+}
+
+/// The streams a client can ask for, by the names Mastodon gives them
+pub const STREAM_TYPES: [&str; 10] = [
+    "public",
+    "public:local",
+    "public:media",
+    "public:local:media",
+    "hashtag",
+    "hashtag:local",
+    "user",
+    "user:notification",
+    "list",
+    "direct",
+];
+
+/// One of the streams a stream in `COMPOSITE_STREAMS` is composed of
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompositePartInner {
+    /// One of the public streams, e.g. `public:local`
+    Public(String),
+    /// `hashtag:<tag>`, or `hashtag:local:<tag>`
+    Hashtag { tag: String, local: bool },
+    /// `list:<id>`
+    List(i64),
+}
+
+impl CompositePartInner {
+    fn parse(s: &str) -> Option<Self> {
+        let tag = |tag: &str| Some(tag.to_string()).filter(|tag| !tag.is_empty());
+        Some(match &s.splitn(3, ':').collect::<Vec<_>>()[..] {
+            ["hashtag", "local", name] => CompositePartInner::Hashtag {
+                tag: tag(name)?,
+                local: true,
+            },
+            ["hashtag", name] => CompositePartInner::Hashtag {
+                tag: tag(name)?,
+                local: false,
+            },
+            ["list", id] => CompositePartInner::List(id.parse().ok().filter(|&id| id > 0)?),
+            ["public", ..] if STREAM_TYPES.contains(&s) => CompositePartInner::Public(s.to_string()),
+            _ => return None,
+        })
+    }
+}
+
+/// Who may subscribe to a stream in `COMPOSITE_STREAMS`
+#[derive(EnumString, EnumVariantNames, Debug, Clone, Copy, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+pub enum CompositeAuthInner {
+    /// Anyone, with or without a token (even with `WHITELIST_MODE`)
+    Public,
+    /// Any user with a valid token
+    User,
+    /// Only the `ADMIN_TOKEN`
+    Admin,
+}
+
+/// A name for a composite stream, which can't be one of Mastodon's `STREAM_TYPES`
+fn is_composite_name(s: &str) -> bool {
+    !s.is_empty()
+        && !STREAM_TYPES.contains(&s)
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || "_-:.".contains(c))
+}
+
+/// A two-letter country code (`DE`) or an autonomous system number (`AS16509`)
+fn is_geo_origin(s: &str) -> bool {
+    let is_asn = s.len() > 2
+        && s.is_char_boundary(2)
+        && s[..2].eq_ignore_ascii_case("as")
+        && s[2..].parse::<u32>().is_ok();
+    is_asn || (s.len() == 2 && s.chars().all(|c| c.is_ascii_alphabetic()))
+}
+
+#[derive(EnumString, EnumVariantNames, Debug, Clone, Copy, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+pub enum DeletePayloadInner {
+    /// The ID as a string, e.g. `"123"` (as Mastodon sends it)
+    Id,
+    /// An object holding the ID, e.g. `{"id":"123"}`
+    Object,
+    /// Each `delete` event twice: with the ID, then with the object (for deployments whose
+    /// clients expect either)
+    Both,
+}
@@ -1,5 +1,169 @@
+use super::Error;
+
 use hashbrown::HashMap;
 use std::fmt;
+use std::fs;
+use url::Url;
+
+#[cfg(test)]
+mod test;
+
+/// Every variable Flodgatt reads its configuration from
+pub(crate) const ENV_VARS: &[&str] = &[
+    "NODE_ENV",
+    "RUST_ENV",
+    "RUST_LOG",
+    "LOG_FORMAT",
+    "OTLP_ENDPOINT",
+    "BIND",
+    "PORT",
+    "SOCKET",
+    "SOCKET_MODE",
+    "IPC_SOCKET",
+    "IPC_LIFECYCLE",
+    "ADMIN_TOKEN",
+    "ADMIN_TOKEN_FILE",
+    "WAIT_FOR_DEPS",
+    "EVENT_NAMES",
+    "EVENT_TTLS",
+    "DELETE_PAYLOAD",
+    "HELLO_EVENT",
+    "SCRUB_EMAILS",
+    "SCRUB_ACCOUNT_FIELDS",
+    "SCRUB_MEDIA_URLS",
+    "INSTANCE_ID",
+    "INSTANCE_PEERS",
+    "IDENTITY_HEADERS",
+    "DISABLED_STREAMS",
+    "WHITELIST_MODE",
+    "WS_MAX_SUBSCRIPTIONS",
+    "COMPOSITE_STREAMS",
+    "COMPOSITE_STREAM_AUTH",
+    "SHARD_URLS",
+    "SHARD_INDEX",
+    "GEOIP_DATABASE",
+    "GEO_POLICY",
+    "GEO_THROTTLE",
+    "DEV_TOKENS",
+    "GENERATOR_RATE",
+    "GENERATOR_TARGET",
+    "GENERATOR_CONTENT_SIZE",
+    "GENERATOR_NOTIFICATIONS",
+    "GENERATOR_SEED",
+    "SEED",
+    "JITTER",
+    "DECISION_LOG",
+    "RECEIPT_LOG",
+    "RECEIPT_SAMPLE",
+    "CANARY_INTERVAL",
+    "CANARY_DEADLINE",
+    "EVENT_HISTORY",
+    "FIRST_BYTE_TIMEOUT",
+    "SHUTDOWN_TIMEOUT",
+    "METRICS_PORT",
+    "METRICS_PATH",
+    "SSE_FREQ",
+    "WS_FREQ",
+    "DATABASE_URL",
+    "DATABASE_URL_FILE",
+    "DB_USER",
+    "USER",
+    "DB_PORT",
+    "DB_HOST",
+    "DB_PASS",
+    "DB_PASS_FILE",
+    "DB_NAME",
+    "DB_SSLMODE",
+    "REDIS_URL",
+    "REDIS_URL_FILE",
+    "REDIS_PUBSUB_URL",
+    "REDIS_PUBSUB_URL_FILE",
+    "REDIS_CACHE_URL",
+    "REDIS_CACHE_URL_FILE",
+    "REDIS_HOST",
+    "REDIS_UNIX_SOCKET",
+    "REDIS_BIND_ADDRESS",
+    "REDIS_TLS",
+    "REDIS_TLS_CA_CERT",
+    "REDIS_TLS_CLIENT_CERT",
+    "REDIS_TLS_CLIENT_KEY",
+    "REDIS_SENTINEL_HOSTS",
+    "REDIS_SENTINEL_MASTER_NAME",
+    "REDIS_CLUSTER",
+    "REDIS_SHARDED_PUBSUB",
+    "REDIS_PSUBSCRIBE",
+    "REDIS_PING_INTERVAL",
+    "REDIS_FREQ",
+    "REDIS_DNS_TTL",
+    "REDIS_USER",
+    "REDIS_PORT",
+    "REDIS_PASSWORD",
+    "REDIS_PASSWORD_FILE",
+    "REDIS_DB",
+    "REDIS_NAMESPACE",
+    "REDIS_DISPATCH_BUDGET",
+    "REDIS_MAX_INPUT_BUFFER",
+    "CLIENT_BUFFER",
+    "CLIENT_OVERFLOW",
+    "INVALID_UTF8",
+    "EVENT_MODES",
+    "TAG_CACHE_SIZE",
+    "TAG_CACHE_KEY",
+    "TAG_CACHE_TTL",
+    "HASHTAG_ID_CHANNELS",
+    "DENYLIST_KEY",
+    "HEARTBEAT_KEY",
+    "HEARTBEAT_TTL",
+    "HOT_TIMELINES",
+    "REDIS_RECONCILE_INTERVAL",
+    "REDIS_MAX_CHANNELS",
+    "REDIS_MAX_HASHTAG_CHANNELS",
+    "REDIS_MAX_LIST_CHANNELS",
+    "REDIS_MAX_USER_CHANNELS",
+    "SUBSCRIBED_KEY_STYLE",
+    "SUBSCRIBED_KEY_PREFIX",
+    "SUBSCRIBED_KEY_TTL",
+];
+
+/// The secrets that can be read from a file (e.g., one mounted by Docker or Kubernetes), by
+/// setting the variable with `_FILE` appended to its path, so that they needn't be kept in the
+/// environment
+pub(crate) const SECRET_FILES: &[(&str, &str)] = &[
+    ("ADMIN_TOKEN", "ADMIN_TOKEN_FILE"),
+    ("DATABASE_URL", "DATABASE_URL_FILE"),
+    ("DB_PASS", "DB_PASS_FILE"),
+    ("REDIS_URL", "REDIS_URL_FILE"),
+    ("REDIS_PUBSUB_URL", "REDIS_PUBSUB_URL_FILE"),
+    ("REDIS_CACHE_URL", "REDIS_CACHE_URL_FILE"),
+    ("REDIS_PASSWORD", "REDIS_PASSWORD_FILE"),
+];
+
+/// What `--help` says about a variable, from the type that parses it
+#[derive(Debug, Clone)]
+pub(crate) struct Setting {
+    pub(crate) var: &'static str,
+    pub(crate) about: &'static str,
+    pub(crate) allowed_values: String,
+    /// The default, as its `Debug` representation (`None` if there is nothing to show)
+    pub(crate) default: Option<String>,
+}
+
+impl Setting {
+    pub(crate) fn new(
+        var: &'static str,
+        about: &'static str,
+        allowed_values: &str,
+        default: String,
+    ) -> Self {
+        let unset = ["None", "[]", "{}", "\"\""].contains(&default.as_str());
+        Self {
+            var,
+            about: about.trim(),
+            allowed_values: allowed_values.to_string(),
+            default: if unset { None } else { Some(default) },
+        }
+    }
+}
 
 #[derive(Debug)]
 pub(crate) struct EnvVar(pub HashMap<String, String>);
@@ -20,46 +184,92 @@ impl EnvVar {
         Self(vars)
     }
 
+    /// Set each secret whose `_FILE` variable is set to the contents of that file (without a
+    /// trailing newline).  A secret may be set directly or by a file, but not both.
+    pub(crate) fn read_secret_files(mut self) -> Result<Self, Error> {
+        for &(var, file_var) in SECRET_FILES {
+            let path = match self.0.remove(file_var) {
+                Some(path) if !path.is_empty() => path,
+                _ => continue,
+            };
+            if self.get(var).map_or(false, |value| !value.is_empty()) {
+                return Err(Error::Config(format!(
+                    "{} and {} are both set; set one or the other",
+                    var, file_var
+                )));
+            }
+            let secret = fs::read_to_string(&path).map_err(|e| {
+                Error::Config(format!("could not read {} from {}: {}", file_var, path, e))
+            })?;
+            let secret = secret.trim_end_matches(|c| c == '\n' || c == '\r');
+            self.0.insert(var.to_string(), secret.to_string());
+        }
+        Ok(self)
+    }
+
     pub(crate) fn maybe_add_env_var(&mut self, key: &str, maybe_value: Option<impl ToString>) {
         if let Some(value) = maybe_value {
             self.0.insert(key.to_string(), value.to_string());
         }
     }
+
+    pub(crate) fn remove_env_var(&mut self, key: &str) {
+        self.0.remove(key);
+    }
 }
+/// Shows each variable that is set, except for the values of secrets and the credentials in URLs
 impl fmt::Display for EnvVar {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut result = String::new();
-        for env_var in &[
-            "NODE_ENV",
-            "RUST_LOG",
-            "BIND",
-            "PORT",
-            "SOCKET",
-            "SSE_FREQ",
-            "WS_FREQ",
-            "DATABASE_URL",
-            "DB_USER",
-            "USER",
-            "DB_PORT",
-            "DB_HOST",
-            "DB_PASS",
-            "DB_NAME",
-            "DB_SSLMODE",
-            "REDIS_HOST",
-            "REDIS_USER",
-            "REDIS_PORT",
-            "REDIS_PASSWORD",
-            "REDIS_USER",
-            "REDIS_DB",
-            "REDIS_FREQ",
-        ] {
+        for env_var in ENV_VARS {
             if let Some(value) = self.get(&(*env_var).to_string()) {
+                let value = match *env_var {
+                    var if SECRETS.contains(&var) => REDACTED.to_string(),
+                    var if SECRET_URLS.contains(&var) => redact_url(value),
+                    _ => value.to_string(),
+                };
                 result = format!("{}\n    {}: {}", result, env_var, value)
             }
         }
         write!(f, "{}", result)
     }
 }
+
+/// What a secret is shown as
+pub(crate) const REDACTED: &str = "<redacted>";
+/// The variables whose values are secret
+const SECRETS: &[&str] = &["ADMIN_TOKEN", "DB_PASS", "REDIS_PASSWORD", "DEV_TOKENS"];
+/// The variables whose values are URLs that can carry a password
+const SECRET_URLS: &[&str] = &[
+    "DATABASE_URL",
+    "REDIS_URL",
+    "REDIS_PUBSUB_URL",
+    "REDIS_CACHE_URL",
+];
+
+/// `url` with its password (whether in its userinfo or its `password` parameter) replaced,
+/// or entirely redacted if it can't be parsed
+fn redact_url(url: &str) -> String {
+    let mut url = match Url::parse(url) {
+        Ok(url) => url,
+        Err(_) => return REDACTED.to_string(),
+    };
+    if url.password().is_some() {
+        url.set_password(Some("redacted")).ok();
+    }
+    if url.query_pairs().any(|(k, _)| k == "password") {
+        let pairs: Vec<_> = url.query_pairs().into_owned().collect();
+        let mut query = url.query_pairs_mut();
+        query.clear();
+        for (k, v) in pairs {
+            match k.as_str() {
+                "password" => query.append_pair(&k, "redacted"),
+                _ => query.append_pair(&k, &v),
+            };
+        }
+    }
+    url.to_string()
+}
 #[macro_export]
 #[doc(hidden)]
 macro_rules! maybe_update {
@@ -78,22 +288,68 @@ macro_rules! maybe_update {
             }
         })}
 
+/// Shows where a secret is, but not what it is
+struct Redacted;
+impl fmt::Debug for Redacted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", REDACTED)
+    }
+}
+
+/// How the settings declared with `let secret = ...` are shown by `--print-config` and `--help`
+pub(crate) trait Redact {
+    fn redact(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result;
+}
+impl Redact for Option<String> {
+    fn redact(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.as_ref().map(|_| Redacted), f)
+    }
+}
+impl<V: fmt::Debug> Redact for HashMap<String, V> {
+    fn redact(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.values().map(|v| (Redacted, v)))
+            .finish()
+    }
+}
+
 #[macro_export]
 #[doc(hidden)]
 macro_rules! from_env_var {
-    ($(#[$outer:meta])*
+    ($(#[doc = $doc:literal])*
      let name = $name:ident;
      let default: $type:ty = $inner:expr;
      let (env_var, allowed_values) = ($env_var:tt, $allowed_values:expr);
      let from_str = |$arg:ident| $body:expr;
     ) => {
-        #[derive(Clone)]
-        pub struct $name(pub $type);
+        $crate::from_env_var!(@setting $(#[doc = $doc])* $name; $type = $inner;
+                      ($env_var, $allowed_values); |$arg| $body);
         impl std::fmt::Debug for $name {
             fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
                 write!(f, "{:?}", self.0)
             }
         }
+    };
+    // A setting whose value isn't shown
+    ($(#[doc = $doc:literal])*
+     let secret = $name:ident;
+     let default: $type:ty = $inner:expr;
+     let (env_var, allowed_values) = ($env_var:tt, $allowed_values:expr);
+     let from_str = |$arg:ident| $body:expr;
+    ) => {
+        $crate::from_env_var!(@setting $(#[doc = $doc])* $name; $type = $inner;
+                      ($env_var, $allowed_values); |$arg| $body);
+        impl std::fmt::Debug for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                super::Redact::redact(&self.0, f)
+            }
+        }
+    };
+    (@setting $(#[doc = $doc:literal])* $name:ident; $type:ty = $inner:expr;
+     ($env_var:tt, $allowed_values:expr); |$arg:ident| $body:expr) => {
+        $(#[doc = $doc])*
+        #[derive(Clone)]
+        pub struct $name(pub $type);
         impl std::ops::Deref for $name {
             type Target = $type;
             fn deref(&self) -> &$type {
@@ -120,6 +376,11 @@ macro_rules! from_env_var {
                     None => self,
                 })
             }
+            /// What `--help` says about this variable
+            pub(crate) fn setting() -> super::Setting {
+                let default = format!("{:?}", Self::default());
+                super::Setting::new($env_var, concat!($($doc),*), $allowed_values, default)
+            }
         }
     };
 }
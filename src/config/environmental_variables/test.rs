@@ -0,0 +1,52 @@
+use super::*;
+
+fn env_var(vars: &[(&str, &str)]) -> EnvVar {
+    EnvVar::new(
+        vars.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+    )
+}
+
+#[test]
+fn secrets_are_not_shown() {
+    let shown = env_var(&[
+        ("ADMIN_TOKEN", "admin-secret"),
+        ("DB_PASS", "db-secret"),
+        ("REDIS_PASSWORD", "redis-secret"),
+        ("DEV_TOKENS", "alice=1"),
+        ("PORT", "4000"),
+    ])
+    .to_string();
+
+    for secret in &["admin-secret", "db-secret", "redis-secret", "alice"] {
+        assert!(!shown.contains(secret), "{} is shown in {}", secret, shown);
+    }
+    assert!(shown.contains("ADMIN_TOKEN: <redacted>"));
+    assert!(shown.contains("PORT: 4000"));
+}
+
+#[test]
+fn the_passwords_in_urls_are_not_shown() {
+    let shown = env_var(&[
+        (
+            "DATABASE_URL",
+            "postgres://mastodon:db-secret@db:5432/mastodon",
+        ),
+        ("REDIS_URL", "redis://:redis-secret@redis:6379/0"),
+        (
+            "REDIS_CACHE_URL",
+            "redis://cache:6379?password=cache-secret&db=1",
+        ),
+        ("REDIS_PUBSUB_URL", "not a url, db-secret"),
+    ])
+    .to_string();
+
+    for secret in &["db-secret", "redis-secret", "cache-secret"] {
+        assert!(!shown.contains(secret), "{} is shown in {}", secret, shown);
+    }
+    assert!(shown.contains("DATABASE_URL: postgres://mastodon:redacted@db:5432/mastodon"));
+    assert!(shown.contains("REDIS_URL: redis://:redacted@redis:6379/0"));
+    assert!(shown.contains("REDIS_CACHE_URL: redis://cache:6379?password=redacted&db=1"));
+    assert!(shown.contains("REDIS_PUBSUB_URL: <redacted>"));
+}
@@ -0,0 +1,165 @@
+//! `flodgatt import-node-config [env-file]`: translate the configuration of Mastodon's Node
+//! streaming server into the `.env` file Flodgatt reads
+//!
+//! The variables come from `env-file` (`.env.production` by default) and from the environment,
+//! which takes precedence, as it does for the Node server.  Variables the Node server doesn't
+//! read (Mastodon's other settings share its `.env.production`) are left out; those Flodgatt
+//! can't honor are listed at the end, commented out, with the reason.
+use super::Error;
+
+use hashbrown::HashMap;
+use std::env;
+use std::fmt;
+
+#[cfg(test)]
+mod test;
+
+/// How Flodgatt treats a variable that Mastodon's streaming server reads
+enum Rule {
+    /// Flodgatt reads the variable, with the same meaning
+    Same,
+    /// Flodgatt reads the same setting from another variable, in its own format
+    Renamed(&'static str, fn(&str) -> Option<&'static str>),
+    /// Flodgatt has no equivalent, for the reason given
+    Unsupported(&'static str),
+}
+
+const NODE_VARS: &[(&str, Rule)] = &[
+    ("NODE_ENV", Rule::Same),
+    ("PORT", Rule::Same),
+    ("BIND", Rule::Same),
+    ("SOCKET", Rule::Same),
+    ("DATABASE_URL", Rule::Same),
+    ("DB_HOST", Rule::Same),
+    ("DB_PORT", Rule::Same),
+    ("DB_USER", Rule::Same),
+    ("DB_PASS", Rule::Same),
+    ("DB_NAME", Rule::Same),
+    ("DB_SSLMODE", Rule::Same),
+    ("REDIS_URL", Rule::Same),
+    ("REDIS_HOST", Rule::Same),
+    ("REDIS_PORT", Rule::Same),
+    ("REDIS_DB", Rule::Same),
+    ("REDIS_PASSWORD", Rule::Same),
+    ("REDIS_NAMESPACE", Rule::Same),
+    ("LOG_LEVEL", Rule::Renamed("RUST_LOG", rust_log)),
+    (
+        "DB_POOL",
+        Rule::Unsupported("Flodgatt's Postgres pool always has 10 connections"),
+    ),
+    (
+        "STREAMING_CLUSTER_NUM",
+        Rule::Unsupported("Flodgatt runs as one process, with a thread per CPU core"),
+    ),
+    (
+        "TRUSTED_PROXY_IP",
+        Rule::Unsupported("Flodgatt trusts the last `X-Forwarded-For` address it is sent"),
+    ),
+];
+
+/// The `RUST_LOG` level for one of npmlog's levels
+fn rust_log(level: &str) -> Option<&'static str> {
+    match level {
+        "silly" => Some("trace"),
+        "verbose" => Some("debug"),
+        "info" | "http" => Some("info"),
+        "warn" => Some("warn"),
+        "error" | "silent" => Some("error"),
+        _ => None,
+    }
+}
+
+/// Flodgatt's equivalent of a Node streaming server's configuration
+#[derive(Debug, Default)]
+pub struct NodeImport {
+    /// The variables to set, in the order of `NODE_VARS`
+    vars: Vec<(String, String)>,
+    /// The Node server's variables (and their values) that couldn't be translated, and why
+    unsupported: Vec<(String, String, String)>,
+}
+
+impl NodeImport {
+    /// Read the Node server's configuration from `env_file` and the environment
+    pub fn read(env_file: &str) -> Result<Self, dotenv::Error> {
+        let file_vars = dotenv::from_path_iter(env_file)?.collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::from_vars(file_vars.into_iter().chain(env::vars())))
+    }
+
+    /// Translate `node_vars`; a variable set more than once takes its last value
+    pub fn from_vars(node_vars: impl IntoIterator<Item = (String, String)>) -> Self {
+        let node_vars: HashMap<String, String> = node_vars.into_iter().collect();
+        let mut import = Self::default();
+        for &(name, ref rule) in NODE_VARS {
+            let value = match node_vars.get(name) {
+                Some(value) => value.clone(),
+                None => continue,
+            };
+            match rule {
+                Rule::Same => import.vars.push((name.to_string(), value)),
+                Rule::Renamed(to, translate) => match translate(&value) {
+                    Some(translated) => import.vars.push(((*to).to_string(), translated.into())),
+                    None => import.unsupported.push((
+                        name.to_string(),
+                        value,
+                        format!("Flodgatt has no equivalent for this value of {}", to),
+                    )),
+                },
+                Rule::Unsupported(why) => {
+                    import
+                        .unsupported
+                        .push((name.to_string(), value, (*why).to_string()))
+                }
+            }
+        }
+        import
+    }
+
+    /// The Node server's variables that Flodgatt can't honor, with the reason for each
+    pub fn warnings(&self) -> impl Iterator<Item = String> + '_ {
+        self.unsupported
+            .iter()
+            .map(|(name, value, why)| format!("{}={} is not supported: {}", name, value, why))
+    }
+
+    /// Check the imported configuration as Flodgatt would when starting
+    pub fn validate(&self) -> Result<(), Error> {
+        super::from_env(self.vars.iter().cloned().collect()).map(|_| ())
+    }
+}
+
+impl fmt::Display for NodeImport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "# Imported from Mastodon's streaming server configuration"
+        )?;
+        for (name, value) in &self.vars {
+            writeln!(f, "{}={}", name, quoted(value))?;
+        }
+        if !self.unsupported.is_empty() {
+            writeln!(f, "\n# Not supported by Flodgatt:")?;
+            for (name, value, why) in &self.unsupported {
+                writeln!(f, "# {}={}\n#   ({})", name, quoted(value), why)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `value`, quoted if dotenv would otherwise misread it.  dotenv expands `$` in double quotes,
+/// but not in single quotes.
+fn quoted(value: &str) -> String {
+    match value {
+        v if !v
+            .chars()
+            .any(|c| c.is_whitespace() || "#\"'\\$".contains(c)) =>
+        {
+            v.to_string()
+        }
+        v if !v.contains('\'') => format!("'{}'", v),
+        v => {
+            let escaped = v.replace('\\', "\\\\").replace('"', "\\\"");
+            format!("\"{}\"", escaped.replace('$', "\\$"))
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use super::*;
+
+fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+#[test]
+fn node_config_is_translated_and_unsupported_options_are_flagged() {
+    let import = NodeImport::from_vars(vars(&[
+        ("LOCAL_DOMAIN", "example.com"),
+        ("REDIS_HOST", "redis"),
+        ("DB_PASS", "pa ss$word"),
+        ("LOG_LEVEL", "verbose"),
+        ("STREAMING_CLUSTER_NUM", "4"),
+        ("REDIS_HOST", "redis.internal"),
+    ]));
+
+    assert_eq!(
+        import.to_string(),
+        "# Imported from Mastodon's streaming server configuration\n\
+         DB_PASS='pa ss$word'\n\
+         REDIS_HOST=redis.internal\n\
+         RUST_LOG=debug\n\
+         \n\
+         # Not supported by Flodgatt:\n\
+         # STREAMING_CLUSTER_NUM=4\n\
+         #   (Flodgatt runs as one process, with a thread per CPU core)\n"
+    );
+    assert_eq!(import.warnings().count(), 1);
+    assert!(import.validate().is_ok());
+}
+
+#[test]
+fn unknown_log_levels_are_flagged() {
+    let import = NodeImport::from_vars(vars(&[("LOG_LEVEL", "loud")]));
+    assert_eq!(
+        import.warnings().collect::<Vec<_>>(),
+        vec!["LOG_LEVEL=loud is not supported: Flodgatt has no equivalent for this value of RUST_LOG"]
+    );
+}
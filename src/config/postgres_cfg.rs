@@ -1,11 +1,27 @@
 use super::postgres_cfg_types::*;
-use super::{EnvVar, Error};
+use super::{EnvVar, Error, Setting};
 
 use url::Url;
 use urlencoding;
 
+#[cfg(test)]
+mod test;
+
 type Result<T> = std::result::Result<T, Error>;
 
+/// The parameters of a `DATABASE_URL` that only Rails reads
+const RAILS_PARAMS: &[&str] = &[
+    "pool",
+    "prepared_statements",
+    "encoding",
+    "timeout",
+    "connect_timeout",
+    "checkout_timeout",
+    "reaping_frequency",
+    "statement_limit",
+    "advisory_locks",
+];
+
 /// Configuration values for Postgres
 #[derive(Debug, Clone)]
 pub struct Postgres {
@@ -22,36 +38,71 @@ impl EnvVar {
     fn update_with_postgres_url(mut self, url_str: &str) -> Result<Self> {
         let url = Url::parse(url_str)?;
         let none_if_empty = |s: String| if s.is_empty() { None } else { Some(s) };
-
-        for (k, v) in url.query_pairs().into_owned() {
-            match k.to_string().as_str() {
-                "user" => self.maybe_add_env_var("DB_USER", Some(v.to_string())),
-                "password" => self.maybe_add_env_var("DB_PASS", Some(v.to_string())),
-                "host" => self.maybe_add_env_var("DB_HOST", Some(v.to_string())),
-                "sslmode" => self.maybe_add_env_var("DB_SSLMODE", Some(v.to_string())),
-                _ => Err(Error::config(
-                    "POSTGRES_URL",
-                    &k,
-                    "a URL with parameters `password`, `user`, `host`, and `sslmode` only",
-                ))?,
-            }
+        if !["postgres", "postgresql"].contains(&url.scheme()) {
+            Err(Error::config(
+                "DATABASE_URL",
+                url_str,
+                "a postgres:// or postgresql:// URL",
+            ))?
         }
 
+        // The parts of the URL are percent-encoded (e.g., a socket directory as the host)
         self.maybe_add_env_var("DB_PORT", url.port());
-        self.maybe_add_env_var("DB_PASS", url.password());
+        self.maybe_add_env_var(
+            "DB_PASS",
+            url.password().map(urlencoding::decode).transpose()?,
+        );
         self.maybe_add_env_var(
             "DB_HOST",
             url.host()
                 .map(|host| urlencoding::decode(&host.to_string()))
                 .transpose()?,
         );
-        self.maybe_add_env_var("DB_USER", none_if_empty(url.username().to_string()));
-        self.maybe_add_env_var("DB_NAME", none_if_empty(url.path()[1..].to_string()));
+        self.maybe_add_env_var(
+            "DB_USER",
+            none_if_empty(urlencoding::decode(url.username())?),
+        );
+        self.maybe_add_env_var(
+            "DB_NAME",
+            none_if_empty(urlencoding::decode(url.path().trim_start_matches('/'))?),
+        );
+
+        // The parameters take precedence over the rest of the URL, as they do for libpq
+        for (k, v) in url.query_pairs().into_owned() {
+            match k.as_str() {
+                "user" => self.maybe_add_env_var("DB_USER", Some(v)),
+                "password" => self.maybe_add_env_var("DB_PASS", Some(v)),
+                "host" => self.maybe_add_env_var("DB_HOST", Some(v)),
+                "port" => self.maybe_add_env_var("DB_PORT", Some(v)),
+                "dbname" => self.maybe_add_env_var("DB_NAME", Some(v)),
+                "sslmode" => self.maybe_add_env_var("DB_SSLMODE", Some(v)),
+                // Settings for Rails's connection pool, which a Mastodon `DATABASE_URL` may have
+                k if RAILS_PARAMS.contains(&k) => (),
+                _ => Err(Error::config(
+                    "DATABASE_URL",
+                    &k,
+                    "a URL with parameters `user`, `password`, `host`, `port`, `dbname`, and \
+                     `sslmode` only",
+                ))?,
+            }
+        }
         Ok(self)
     }
 }
 
 impl Postgres {
+    /// What `--help` says about each of Postgres's variables
+    pub(super) fn settings() -> Vec<Setting> {
+        vec![
+            PgUser::setting(),
+            PgHost::setting(),
+            PgPass::setting(),
+            PgDatabase::setting(),
+            PgPort::setting(),
+            PgSslMode::setting(),
+        ]
+    }
+
     /// Configure Postgres and return a connection
     pub(crate) fn from_env(env: EnvVar) -> Result<Self> {
         let env = match env.get("DATABASE_URL").cloned() {
@@ -69,11 +120,4 @@ impl Postgres {
         };
         Ok(cfg)
     }
-
-    //     // use openssl::ssl::{SslConnector, SslMethod};
-    //     // use postgres_openssl::MakeTlsConnector;
-    //     // let mut builder = SslConnector::builder(SslMethod::tls())?;
-    //     // builder.set_ca_file("/etc/ssl/cert.pem")?;
-    //     // let connector = MakeTlsConnector::new(builder.build());
-    //     // TODO: add TLS support, remove `NoTls`
 }
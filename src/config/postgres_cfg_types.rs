@@ -20,7 +20,7 @@ from_env_var!(
 
 from_env_var!(
     /// The password to use with Postgress
-    let name = PgPass;
+    let secret = PgPass;
     let default: Option<String> = None;
     let (env_var, allowed_values) = ("DB_PASS", "any string");
     let from_str = |s| Some(Some(s.to_string()));
@@ -43,14 +43,28 @@ from_env_var!(
 );
 
 from_env_var!(
+    /// Whether to connect to Postgres over TLS, and how far to trust its certificate, as libpq
+    /// does (see `PgSslInner`)
     let name = PgSslMode;
     let default: PgSslInner = PgSslInner::Prefer;
     let (env_var, allowed_values) = ("DB_SSLMODE", &format!("one of: {:?}", PgSslInner::variants()));
     let from_str = |s| PgSslInner::from_str(s).ok();
 );
 
-#[derive(EnumString, EnumVariantNames, Debug, Clone)]
-#[strum(serialize_all = "snake_case")]
+#[derive(EnumString, EnumVariantNames, Debug, Clone, Copy, PartialEq)]
+#[strum(serialize_all = "kebab_case")]
 pub enum PgSslInner {
+    /// Never use TLS
+    Disable,
+    /// Use TLS if the server supports it (as with `prefer`, since Flodgatt can't retry a
+    /// refused connection with TLS)
+    Allow,
+    /// Use TLS if the server supports it, without checking its certificate
     Prefer,
+    /// Always use TLS, without checking the server's certificate
+    Require,
+    /// Always use TLS, with a certificate signed by a trusted CA
+    VerifyCa,
+    /// Always use TLS, with a certificate signed by a trusted CA for the host connected to
+    VerifyFull,
 }
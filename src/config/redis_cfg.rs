@@ -1,8 +1,11 @@
 use super::redis_cfg_types::*;
-use super::{EnvVar, Error};
+use super::{EnvVar, Error, Setting};
 
 use url::Url;
 
+#[cfg(test)]
+mod test;
+
 type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug, Default)]
@@ -11,31 +14,83 @@ pub struct Redis {
     pub(crate) password: RedisPass,
     pub(crate) port: RedisPort,
     pub(crate) host: RedisHost,
+    pub(crate) unix_socket: RedisUnixSocket,
+    pub(crate) bind_address: RedisBindAddress,
+    pub(crate) tls: RedisTls,
+    pub(crate) tls_ca_cert: RedisTlsCaCert,
+    pub(crate) tls_client_cert: RedisTlsClientCert,
+    pub(crate) tls_client_key: RedisTlsClientKey,
+    pub(crate) sentinel_hosts: RedisSentinelHosts,
+    pub(crate) sentinel_master_name: RedisSentinelMasterName,
+    pub(crate) cluster: RedisCluster,
+    pub(crate) sharded_pubsub: RedisShardedPubsub,
+    pub(crate) psubscribe: RedisPsubscribe,
+    pub(crate) ping_interval: RedisPingInterval,
+    pub(crate) dns_ttl: RedisDnsTtl,
     pub(crate) db: RedisDb,
     pub(crate) namespace: RedisNamespace,
-    // **NOTE**:  Polling Redis is much more time consuming than polling the `Receiver` (~1ms
-    // compared to ~50μs).  Thus, changing this setting with REDIS_POLL_INTERVAL may be a good
-    // place to start for performance improvements at the cost of delaying all updates.
-    pub polling_interval: RedisInterval,
+    pub(crate) dispatch_budget: RedisDispatchBudget,
+    pub(crate) max_input_buffer: RedisMaxInputBuffer,
+    pub(crate) client_buffer: ClientBuffer,
+    pub(crate) client_overflow: ClientOverflow,
+    pub(crate) invalid_utf8: InvalidUtf8,
+    pub(crate) event_modes: EventModes,
+    pub tag_cache_size: TagCacheSize,
+    pub(crate) tag_cache_key: TagCacheKey,
+    pub(crate) tag_cache_ttl: TagCacheTtl,
+    pub(crate) hashtag_id_channels: HashtagIdChannels,
+    pub(crate) denylist_key: DenylistKey,
+    pub(crate) heartbeat_key: HeartbeatKey,
+    pub(crate) heartbeat_ttl: HeartbeatTtl,
+    pub(crate) hot_timelines: HotTimelines,
+    pub reconcile_interval: RedisReconcileInterval,
+    pub(crate) max_channels: RedisMaxChannels,
+    pub(crate) max_hashtag_channels: RedisMaxHashtagChannels,
+    pub(crate) max_list_channels: RedisMaxListChannels,
+    pub(crate) max_user_channels: RedisMaxUserChannels,
+    pub(crate) subscribed_key_style: SubscribedKeyStyle,
+    pub(crate) subscribed_key_prefix: SubscribedKeyPrefix,
+    pub(crate) subscribed_key_ttl: SubscribedKeyTtl,
+    /// The server that the subscribed keys (and the denylist) are kept on, if REDIS_CACHE_URL
+    /// names one other than the server Flodgatt subscribes to
+    pub(crate) cache: Option<Box<Redis>>,
 }
 
 impl EnvVar {
-    fn update_with_redis_url(mut self, url_str: &str) -> Result<Self> {
+    fn update_with_redis_url(mut self, var: &str, url_str: &str) -> Result<Self> {
         let url = Url::parse(url_str)?;
         let none_if_empty = |s: String| if s.is_empty() { None } else { Some(s) };
 
+        if url.scheme() == "rediss" {
+            self.maybe_add_env_var("REDIS_TLS", Some(true));
+        }
+
+        self.maybe_add_env_var(
+            "REDIS_HOST",
+            url.host_str().and_then(|h| none_if_empty(h.into())),
+        );
         self.maybe_add_env_var("REDIS_PORT", url.port());
-        self.maybe_add_env_var("REDIS_PASSWORD", url.password());
-        self.maybe_add_env_var("REDIS_USERNAME", none_if_empty(url.username().to_string()));
-        self.maybe_add_env_var("REDIS_DB", none_if_empty(url.path()[1..].to_string()));
+        // The URL's user and password are percent-encoded (e.g., `p%40ss` for `p@ss`)
+        self.maybe_add_env_var(
+            "REDIS_PASSWORD",
+            url.password().map(urlencoding::decode).transpose()?,
+        );
+        self.maybe_add_env_var(
+            "REDIS_USER",
+            none_if_empty(urlencoding::decode(url.username())?),
+        );
+        self.maybe_add_env_var(
+            "REDIS_DB",
+            none_if_empty(url.path().trim_start_matches('/').to_string()),
+        );
         for (k, v) in url.query_pairs().into_owned() {
             match k.to_string().as_str() {
                 "password" => self.maybe_add_env_var("REDIS_PASSWORD", Some(v.to_string())),
                 "db" => self.maybe_add_env_var("REDIS_DB", Some(v.to_string())),
                 _ => Err(Error::config(
-                    "REDIS_URL",
+                    var,
                     &k,
-                    "a URL with parameters `password`, `db`,  only",
+                    "a URL with parameters `password` and `db` only",
                 ))?,
             }
         }
@@ -44,14 +99,87 @@ impl EnvVar {
 }
 
 impl Redis {
-    const USER_SET_WARNING: &'static str =
-        "Redis user specified, but Redis did not ask for a username.  Ignoring it.";
-    const DB_SET_WARNING: &'static str = r"Redis database specified, but PubSub connections do not use databases.
-For similar functionality, you may wish to set a REDIS_NAMESPACE";
+    const FREQ_SET_WARNING: &'static str =
+        "REDIS_FREQ is set, but Redis is no longer polled on a timer.  Ignoring it.";
+
+    /// The variables that say how to reach (and log in to) the server Flodgatt subscribes to,
+    /// none of which carry over to the REDIS_CACHE_URL server
+    const PUBSUB_ONLY_VARS: [&'static str; 15] = [
+        "REDIS_URL",
+        "REDIS_PUBSUB_URL",
+        "REDIS_CACHE_URL",
+        "REDIS_HOST",
+        "REDIS_PORT",
+        "REDIS_UNIX_SOCKET",
+        "REDIS_USER",
+        "REDIS_PASSWORD",
+        "REDIS_DB",
+        "REDIS_TLS",
+        "REDIS_SENTINEL_HOSTS",
+        "REDIS_SENTINEL_MASTER_NAME",
+        "REDIS_CLUSTER",
+        "REDIS_SHARDED_PUBSUB",
+        "REDIS_PSUBSCRIBE",
+    ];
+
+    /// What `--help` says about each of Redis's variables
+    pub(super) fn settings() -> Vec<Setting> {
+        vec![
+            RedisHost::setting(),
+            RedisPort::setting(),
+            RedisUnixSocket::setting(),
+            RedisBindAddress::setting(),
+            RedisTls::setting(),
+            RedisTlsCaCert::setting(),
+            RedisTlsClientCert::setting(),
+            RedisTlsClientKey::setting(),
+            RedisSentinelHosts::setting(),
+            RedisSentinelMasterName::setting(),
+            RedisCluster::setting(),
+            RedisPsubscribe::setting(),
+            RedisShardedPubsub::setting(),
+            RedisDispatchBudget::setting(),
+            RedisMaxInputBuffer::setting(),
+            ClientBuffer::setting(),
+            TagCacheSize::setting(),
+            HotTimelines::setting(),
+            ClientOverflow::setting(),
+            InvalidUtf8::setting(),
+            EventModes::setting(),
+            TagCacheKey::setting(),
+            TagCacheTtl::setting(),
+            HashtagIdChannels::setting(),
+            DenylistKey::setting(),
+            HeartbeatKey::setting(),
+            HeartbeatTtl::setting(),
+            RedisMaxChannels::setting(),
+            RedisMaxHashtagChannels::setting(),
+            RedisMaxListChannels::setting(),
+            RedisMaxUserChannels::setting(),
+            RedisReconcileInterval::setting(),
+            RedisPingInterval::setting(),
+            RedisDnsTtl::setting(),
+            RedisPass::setting(),
+            RedisNamespace::setting(),
+            RedisUser::setting(),
+            RedisDb::setting(),
+            SubscribedKeyStyle::setting(),
+            SubscribedKeyPrefix::setting(),
+            SubscribedKeyTtl::setting(),
+        ]
+    }
 
     pub(crate) fn from_env(env: EnvVar) -> Result<Self> {
-        let env = match env.get("REDIS_URL").cloned() {
-            Some(url_str) => env.update_with_redis_url(&url_str)?,
+        let cache = match env.get("REDIS_CACHE_URL").cloned() {
+            Some(url_str) => Some(Box::new(Self::cache_from_env(env.clone(), &url_str)?)),
+            None => None,
+        };
+        let (var, url) = match env.get("REDIS_PUBSUB_URL") {
+            Some(url_str) => ("REDIS_PUBSUB_URL", Some(url_str.clone())),
+            None => ("REDIS_URL", env.get("REDIS_URL").cloned()),
+        };
+        let env = match url {
+            Some(url_str) => env.update_with_redis_url(var, &url_str)?,
             None => env,
         };
 
@@ -60,17 +188,141 @@ For similar functionality, you may wish to set a REDIS_NAMESPACE";
             password: RedisPass::default().maybe_update(env.get("REDIS_PASSWORD"))?,
             port: RedisPort::default().maybe_update(env.get("REDIS_PORT"))?,
             host: RedisHost::default().maybe_update(env.get("REDIS_HOST"))?,
+            unix_socket: RedisUnixSocket::default().maybe_update(env.get("REDIS_UNIX_SOCKET"))?,
+            bind_address: RedisBindAddress::default()
+                .maybe_update(env.get("REDIS_BIND_ADDRESS"))?,
+            tls: RedisTls::default().maybe_update(env.get("REDIS_TLS"))?,
+            tls_ca_cert: RedisTlsCaCert::default().maybe_update(env.get("REDIS_TLS_CA_CERT"))?,
+            tls_client_cert: RedisTlsClientCert::default()
+                .maybe_update(env.get("REDIS_TLS_CLIENT_CERT"))?,
+            tls_client_key: RedisTlsClientKey::default()
+                .maybe_update(env.get("REDIS_TLS_CLIENT_KEY"))?,
+            sentinel_hosts: RedisSentinelHosts::default()
+                .maybe_update(env.get("REDIS_SENTINEL_HOSTS"))?,
+            sentinel_master_name: RedisSentinelMasterName::default()
+                .maybe_update(env.get("REDIS_SENTINEL_MASTER_NAME"))?,
+            cluster: RedisCluster::default().maybe_update(env.get("REDIS_CLUSTER"))?,
+            sharded_pubsub: RedisShardedPubsub::default()
+                .maybe_update(env.get("REDIS_SHARDED_PUBSUB"))?,
+            psubscribe: RedisPsubscribe::default().maybe_update(env.get("REDIS_PSUBSCRIBE"))?,
+            ping_interval: RedisPingInterval::default()
+                .maybe_update(env.get("REDIS_PING_INTERVAL"))?,
+            dns_ttl: RedisDnsTtl::default().maybe_update(env.get("REDIS_DNS_TTL"))?,
             db: RedisDb::default().maybe_update(env.get("REDIS_DB"))?,
             namespace: RedisNamespace::default().maybe_update(env.get("REDIS_NAMESPACE"))?,
-            polling_interval: RedisInterval::default().maybe_update(env.get("REDIS_FREQ"))?,
+            dispatch_budget: RedisDispatchBudget::default()
+                .maybe_update(env.get("REDIS_DISPATCH_BUDGET"))?,
+            max_input_buffer: RedisMaxInputBuffer::default()
+                .maybe_update(env.get("REDIS_MAX_INPUT_BUFFER"))?,
+            client_buffer: ClientBuffer::default().maybe_update(env.get("CLIENT_BUFFER"))?,
+            client_overflow: ClientOverflow::default().maybe_update(env.get("CLIENT_OVERFLOW"))?,
+            invalid_utf8: InvalidUtf8::default().maybe_update(env.get("INVALID_UTF8"))?,
+            event_modes: EventModes::default().maybe_update(env.get("EVENT_MODES"))?,
+            tag_cache_size: TagCacheSize::default().maybe_update(env.get("TAG_CACHE_SIZE"))?,
+            tag_cache_key: TagCacheKey::default().maybe_update(env.get("TAG_CACHE_KEY"))?,
+            tag_cache_ttl: TagCacheTtl::default().maybe_update(env.get("TAG_CACHE_TTL"))?,
+            hashtag_id_channels: HashtagIdChannels::default()
+                .maybe_update(env.get("HASHTAG_ID_CHANNELS"))?,
+            denylist_key: DenylistKey::default().maybe_update(env.get("DENYLIST_KEY"))?,
+            heartbeat_key: HeartbeatKey::default().maybe_update(env.get("HEARTBEAT_KEY"))?,
+            heartbeat_ttl: HeartbeatTtl::default().maybe_update(env.get("HEARTBEAT_TTL"))?,
+            hot_timelines: HotTimelines::default().maybe_update(env.get("HOT_TIMELINES"))?,
+            reconcile_interval: RedisReconcileInterval::default()
+                .maybe_update(env.get("REDIS_RECONCILE_INTERVAL"))?,
+            max_channels: RedisMaxChannels::default()
+                .maybe_update(env.get("REDIS_MAX_CHANNELS"))?,
+            max_hashtag_channels: RedisMaxHashtagChannels::default()
+                .maybe_update(env.get("REDIS_MAX_HASHTAG_CHANNELS"))?,
+            max_list_channels: RedisMaxListChannels::default()
+                .maybe_update(env.get("REDIS_MAX_LIST_CHANNELS"))?,
+            max_user_channels: RedisMaxUserChannels::default()
+                .maybe_update(env.get("REDIS_MAX_USER_CHANNELS"))?,
+            subscribed_key_style: SubscribedKeyStyle::default()
+                .maybe_update(env.get("SUBSCRIBED_KEY_STYLE"))?,
+            subscribed_key_prefix: SubscribedKeyPrefix::default()
+                .maybe_update(env.get("SUBSCRIBED_KEY_PREFIX"))?,
+            subscribed_key_ttl: SubscribedKeyTtl::default()
+                .maybe_update(env.get("SUBSCRIBED_KEY_TTL"))?,
+            cache,
         };
 
-        if cfg.db.is_some() {
-            log::warn!("{}", Self::DB_SET_WARNING);
+        if cfg!(not(feature = "tls")) && *cfg.tls {
+            Err(Error::config(
+                "REDIS_TLS",
+                "true",
+                "unset in a build without the `tls` feature",
+            ))?;
+        }
+        if cfg.tls_client_cert.is_some() != cfg.tls_client_key.is_some() {
+            Err(Error::config(
+                "REDIS_TLS_CLIENT_KEY",
+                cfg.tls_client_key.as_deref().unwrap_or(""),
+                "set along with REDIS_TLS_CLIENT_CERT (and only then)",
+            ))?;
+        }
+        let ipv4 = cfg.bind_address.iter().filter(|ip| ip.is_ipv4()).count();
+        if ipv4 > 1 || cfg.bind_address.len() - ipv4 > 1 {
+            Err(Error::config(
+                "REDIS_BIND_ADDRESS",
+                env.get("REDIS_BIND_ADDRESS").map_or("", String::as_str),
+                "at most one IPv4 and one IPv6 address",
+            ))?;
         }
-        if cfg.user.is_some() {
-            log::warn!("{}", Self::USER_SET_WARNING);
+        if cfg.sentinel_hosts.is_empty() != cfg.sentinel_master_name.is_none() {
+            Err(Error::config(
+                "REDIS_SENTINEL_MASTER_NAME",
+                cfg.sentinel_master_name.as_deref().unwrap_or(""),
+                "set along with REDIS_SENTINEL_HOSTS (and only then)",
+            ))?;
+        }
+        if *cfg.cluster && (cfg.unix_socket.is_some() || cfg.sentinel_master_name.is_some()) {
+            Err(Error::config(
+                "REDIS_CLUSTER",
+                "true",
+                "unset when connecting through REDIS_UNIX_SOCKET or REDIS_SENTINEL_HOSTS",
+            ))?;
+        }
+        if *cfg.sharded_pubsub && !*cfg.cluster {
+            Err(Error::config(
+                "REDIS_SHARDED_PUBSUB",
+                "true",
+                "set along with REDIS_CLUSTER (and only then)",
+            ))?;
+        }
+        if *cfg.psubscribe && *cfg.sharded_pubsub {
+            Err(Error::config(
+                "REDIS_PSUBSCRIBE",
+                "true",
+                "unset with REDIS_SHARDED_PUBSUB, which has no pattern subscriptions",
+            ))?;
+        }
+        if cfg.user.is_some() && cfg.password.is_none() {
+            Err(Error::config(
+                "REDIS_USER",
+                cfg.user.as_deref().unwrap_or(""),
+                "set along with REDIS_PASSWORD",
+            ))?;
+        }
+        match (*cfg.cluster, *cfg.db) {
+            (true, Some(db)) if db != 0 => Err(Error::config(
+                "REDIS_DB",
+                &db.to_string(),
+                "0 (or unset) with REDIS_CLUSTER, which has only the one database",
+            ))?,
+            _ => (),
+        }
+        if env.get("REDIS_FREQ").is_some() {
+            tracing::warn!("{}", Self::FREQ_SET_WARNING);
         }
         Ok(cfg)
     }
+
+    /// The configuration of the REDIS_CACHE_URL server, which shares every setting but those
+    /// of the connection itself (and the TLS certificates) with the pubsub server
+    fn cache_from_env(mut env: EnvVar, url_str: &str) -> Result<Self> {
+        for var in Self::PUBSUB_ONLY_VARS.iter() {
+            env.remove_env_var(var);
+        }
+        Self::from_env(env.update_with_redis_url("REDIS_CACHE_URL", url_str)?)
+    }
 }
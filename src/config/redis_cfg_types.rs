@@ -1,5 +1,9 @@
 use crate::from_env_var; //macro
+use hashbrown::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
 use std::time::Duration;
+use strum_macros::{EnumString, EnumVariantNames};
 //use std::{fmt, net::IpAddr, os::unix::net::UnixListener, str::FromStr, time::Duration};
 //use strum_macros::{EnumString, EnumVariantNames};
 
@@ -18,15 +22,302 @@ from_env_var!(
     let from_str = |s| s.parse().ok();
 );
 from_env_var!(
-    /// How frequently to poll Redis
-    let name = RedisInterval;
-    let default: Duration = Duration::from_millis(100);
-    let (env_var, allowed_values) = ("REDIS_FREQ", "a number of milliseconds");
-    let from_str = |s| s.parse().map(Duration::from_millis).ok();
+    /// A Unix domain socket to connect to Redis over, in place of REDIS_HOST and REDIS_PORT
+    let name = RedisUnixSocket;
+    let default: Option<String> = None;
+    let (env_var, allowed_values) = ("REDIS_UNIX_SOCKET", "the path to a Unix domain socket");
+    let from_str = |s| Some(Some(s.to_string()));
+);
+from_env_var!(
+    /// The local addresses to connect to Redis from (at most one of each IP family), rather
+    /// than whichever address the route to Redis would use
+    let name = RedisBindAddress;
+    let default: Vec<IpAddr> = Vec::new();
+    let (env_var, allowed_values) = ("REDIS_BIND_ADDRESS", "a comma-separated list of IP addresses");
+    let from_str = |s| s
+        .split(',')
+        .map(str::trim)
+        .filter(|ip| !ip.is_empty())
+        .map(|ip| ip.parse().ok())
+        .collect();
+);
+from_env_var!(
+    /// Whether to connect to Redis over TLS (which managed Redis services often require)
+    let name = RedisTls;
+    let default: bool = false;
+    let (env_var, allowed_values) = ("REDIS_TLS", "true or false");
+    let from_str = |s| s.parse().ok();
+);
+from_env_var!(
+    /// A PEM file of the certificates to trust for REDIS_TLS (by default, the Mozilla root
+    /// certificates are trusted)
+    let name = RedisTlsCaCert;
+    let default: Option<String> = None;
+    let (env_var, allowed_values) = ("REDIS_TLS_CA_CERT", "the path to a PEM file");
+    let from_str = |s| Some(Some(s.to_string()));
+);
+from_env_var!(
+    /// A PEM file of the certificate chain to authenticate to Redis with, for REDIS_TLS
+    let name = RedisTlsClientCert;
+    let default: Option<String> = None;
+    let (env_var, allowed_values) = ("REDIS_TLS_CLIENT_CERT", "the path to a PEM file");
+    let from_str = |s| Some(Some(s.to_string()));
+);
+from_env_var!(
+    /// A PEM file of the private key for REDIS_TLS_CLIENT_CERT
+    let name = RedisTlsClientKey;
+    let default: Option<String> = None;
+    let (env_var, allowed_values) = ("REDIS_TLS_CLIENT_KEY", "the path to a PEM file");
+    let from_str = |s| Some(Some(s.to_string()));
+);
+from_env_var!(
+    /// The Redis Sentinels to ask for the address of the master, in place of REDIS_HOST and
+    /// REDIS_PORT
+    let name = RedisSentinelHosts;
+    let default: Vec<String> = Vec::new();
+    let (env_var, allowed_values) = ("REDIS_SENTINEL_HOSTS", "a comma-separated list of host:port pairs");
+    let from_str = |s| Some(s
+        .split(',')
+        .map(str::trim)
+        .filter(|host| !host.is_empty())
+        .map(|host| match host.rfind(':') {
+            Some(i) if !host.ends_with(']') && host[i + 1..].parse::<u16>().is_ok() => host.to_string(),
+            _ => format!("{}:26379", host), // Sentinel's default port
+        })
+        .collect());
+);
+from_env_var!(
+    /// The name the REDIS_SENTINEL_HOSTS monitor the master under
+    let name = RedisSentinelMasterName;
+    let default: Option<String> = None;
+    let (env_var, allowed_values) = ("REDIS_SENTINEL_MASTER_NAME", "any string");
+    let from_str = |s| Some(Some(s.to_string()));
+);
+from_env_var!(
+    /// Whether REDIS_HOST is a node of a Redis Cluster, in which case each channel is
+    /// subscribed to on the master that serves its hash slot
+    let name = RedisCluster;
+    let default: bool = false;
+    let (env_var, allowed_values) = ("REDIS_CLUSTER", "true or false");
+    let from_str = |s| s.parse().ok();
+);
+from_env_var!(
+    /// Whether to subscribe to every timeline's channel with one `PSUBSCRIBE timeline:*` (and
+    /// skip the messages of timelines without clients) rather than to each channel in use
+    let name = RedisPsubscribe;
+    let default: bool = false;
+    let (env_var, allowed_values) = ("REDIS_PSUBSCRIBE", "true or false");
+    let from_str = |s| s.parse().ok();
+);
+from_env_var!(
+    /// Whether to use sharded pub/sub (`SSUBSCRIBE`, Redis 7 and later) in a REDIS_CLUSTER,
+    /// which Mastodon must publish to with `SPUBLISH`
+    let name = RedisShardedPubsub;
+    let default: bool = false;
+    let (env_var, allowed_values) = ("REDIS_SHARDED_PUBSUB", "true or false");
+    let from_str = |s| s.parse().ok();
+);
+from_env_var!(
+    /// How many messages from Redis to send to clients per poll before yielding to other tasks
+    let name = RedisDispatchBudget;
+    let default: usize = 1000;
+    let (env_var, allowed_values) = ("REDIS_DISPATCH_BUDGET", "a positive number of messages");
+    let from_str = |s| s.parse().ok().filter(|&n| n > 0);
+);
+from_env_var!(
+    /// The most bytes the Redis input buffer may grow to, to hold a message larger than it
+    let name = RedisMaxInputBuffer;
+    let default: Option<usize> = Some(64 * 1024 * 1024);
+    let (env_var, allowed_values) = ("REDIS_MAX_INPUT_BUFFER", "a number of KiB, at least 64 (0 for no limit)");
+    let from_str = |s| match s.parse() {
+        Ok(0) => Some(None),
+        Ok(kib) if kib >= 64 => Some(Some(kib * 1024)),
+        Ok(_) | Err(_) => None,
+    };
+);
+from_env_var!(
+    /// How many events may wait to be sent to each client before its channel is full
+    let name = ClientBuffer;
+    let default: usize = 10;
+    let (env_var, allowed_values) = ("CLIENT_BUFFER", "a positive number of events");
+    let from_str = |s| s.parse().ok().filter(|&n| n > 0);
+);
+from_env_var!(
+    /// How many hashtags to cache, in each direction (name to id and id to name), for naming
+    /// and reading the Redis channels of hashtag timelines
+    let name = TagCacheSize;
+    let default: usize = 1000;
+    let (env_var, allowed_values) = ("TAG_CACHE_SIZE", "a positive number of hashtags");
+    let from_str = |s| s.parse().ok().filter(|&n| n > 0);
+);
+from_env_var!(
+    /// How many of the timelines with the most deliveries (in the latest minute) to rank for
+    /// the admin API and metrics
+    let name = HotTimelines;
+    let default: usize = 10;
+    let (env_var, allowed_values) = ("HOT_TIMELINES", "a number of timelines (0 for none)");
+    let from_str = |s| s.parse().ok();
+);
+from_env_var!(
+    /// What to do when a client's channel is full (see `ClientOverflowInner`); the clients of
+    /// public and hashtag timelines instead skip the events of those timelines they fall
+    /// `CLIENT_BUFFER` behind on
+    let name = ClientOverflow;
+    let default: ClientOverflowInner = ClientOverflowInner::Wait;
+    let (env_var, allowed_values) = ("CLIENT_OVERFLOW", &format!("one of: {:?}", ClientOverflowInner::variants()));
+    let from_str = |s| ClientOverflowInner::from_str(s).ok();
+);
+from_env_var!(
+    /// What to do with a message whose bytes aren't valid UTF-8 (see `InvalidUtf8Inner`)
+    let name = InvalidUtf8;
+    let default: InvalidUtf8Inner = InvalidUtf8Inner::Reject;
+    let (env_var, allowed_values) = ("INVALID_UTF8", &format!("one of: {:?}", InvalidUtf8Inner::variants()));
+    let from_str = |s| InvalidUtf8Inner::from_str(s).ok();
+);
+from_env_var!(
+    /// How the events of each category of timeline are parsed (see `EventModeInner`); a
+    /// category that isn't named is `strict`
+    let name = EventModes;
+    let default: HashMap<String, EventModeInner> = HashMap::new();
+    let (env_var, allowed_values) = ("EVENT_MODES", &format!("comma-separated pairs of one of {:?} and one of {:?} (e.g., `public=pass_through`)", EVENT_CATEGORIES, EventModeInner::variants()));
+    let from_str = |s| s
+        .split(',')
+        .map(|pair| match &pair.splitn(2, '=').map(str::trim).collect::<Vec<_>>()[..] {
+            [category, mode] if EVENT_CATEGORIES.contains(category) => {
+                Some((category.to_string(), EventModeInner::from_str(mode).ok()?))
+            }
+            _ => None,
+        })
+        .collect();
+);
+from_env_var!(
+    /// The Redis hash to keep the hashtag cache in, so that it survives restarts without a
+    /// lookup in Postgres for each hashtag (by default, it is only kept in memory)
+    let name = TagCacheKey;
+    let default: Option<String> = None;
+    let (env_var, allowed_values) = ("TAG_CACHE_KEY", "any string");
+    let from_str = |s| Some(Some(s.to_string()));
+);
+from_env_var!(
+    /// How long a hashtag kept in the `TAG_CACHE_KEY` hash is trusted after it was last looked
+    /// up in Postgres
+    let name = TagCacheTtl;
+    let default: Duration = Duration::from_secs(24 * 60 * 60);
+    let (env_var, allowed_values) = ("TAG_CACHE_TTL", "a number of seconds (greater than 0)");
+    let from_str = |s| match s.parse() {
+        Ok(0) | Err(_) => None,
+        Ok(secs) => Some(Duration::from_secs(secs)),
+    };
+);
+from_env_var!(
+    /// Whether hashtag timelines are published to channels named by the hashtag's id (e.g.,
+    /// `timeline:hashtag:42`), as a patched Mastodon can, rather than by its name
+    let name = HashtagIdChannels;
+    let default: bool = false;
+    let (env_var, allowed_values) = ("HASHTAG_ID_CHANNELS", "true or false");
+    let from_str = |s| s.parse().ok();
+);
+from_env_var!(
+    /// The Redis set to keep the denylist in, so that it survives restarts and is shared by
+    /// every instance (by default, it is only kept in memory)
+    let name = DenylistKey;
+    let default: Option<String> = None;
+    let (env_var, allowed_values) = ("DENYLIST_KEY", "any string");
+    let from_str = |s| Some(Some(s.to_string()));
+);
+from_env_var!(
+    /// The Redis hash to keep each instance's heartbeat in, so that Mastodon or a monitor can
+    /// check that the streaming servers are alive (by default, no heartbeat is kept)
+    let name = HeartbeatKey;
+    let default: Option<String> = None;
+    let (env_var, allowed_values) = ("HEARTBEAT_KEY", "any string");
+    let from_str = |s| Some(Some(s.to_string()));
+);
+from_env_var!(
+    /// How long a heartbeat in the `HEARTBEAT_KEY` hash is fresh; each instance beats three
+    /// times per TTL
+    let name = HeartbeatTtl;
+    let default: Duration = Duration::from_secs(30);
+    let (env_var, allowed_values) = ("HEARTBEAT_TTL", "a number of seconds (greater than 2)");
+    let from_str = |s| match s.parse() {
+        Ok(secs) if secs > 2 => Some(Duration::from_secs(secs)),
+        _ => None,
+    };
+);
+from_env_var!(
+    /// The most Redis channels to subscribe to at once, of every kind
+    let name = RedisMaxChannels;
+    let default: Option<usize> = None;
+    let (env_var, allowed_values) = ("REDIS_MAX_CHANNELS", "a positive number (unlimited unless set)");
+    let from_str = |s| match s.parse() {
+        Ok(0) | Err(_) => None,
+        Ok(max) => Some(Some(max)),
+    };
+);
+from_env_var!(
+    /// The most hashtag timelines (local or federated) to subscribe to at once
+    let name = RedisMaxHashtagChannels;
+    let default: Option<usize> = None;
+    let (env_var, allowed_values) = ("REDIS_MAX_HASHTAG_CHANNELS", "a positive number (unlimited unless set)");
+    let from_str = |s| match s.parse() {
+        Ok(0) | Err(_) => None,
+        Ok(max) => Some(Some(max)),
+    };
+);
+from_env_var!(
+    /// The most list timelines to subscribe to at once
+    let name = RedisMaxListChannels;
+    let default: Option<usize> = None;
+    let (env_var, allowed_values) = ("REDIS_MAX_LIST_CHANNELS", "a positive number (unlimited unless set)");
+    let from_str = |s| match s.parse() {
+        Ok(0) | Err(_) => None,
+        Ok(max) => Some(Some(max)),
+    };
+);
+from_env_var!(
+    /// The most user timelines (home, notifications, and direct messages) to subscribe to at
+    /// once
+    let name = RedisMaxUserChannels;
+    let default: Option<usize> = None;
+    let (env_var, allowed_values) = ("REDIS_MAX_USER_CHANNELS", "a positive number (unlimited unless set)");
+    let from_str = |s| match s.parse() {
+        Ok(0) | Err(_) => None,
+        Ok(max) => Some(Some(max)),
+    };
+);
+from_env_var!(
+    /// How frequently to compare our subscriptions against the channels Redis reports
+    let name = RedisReconcileInterval;
+    let default: Option<Duration> = Some(Duration::from_secs(60));
+    let (env_var, allowed_values) = ("REDIS_RECONCILE_INTERVAL", "a number of seconds (0 to disable)");
+    let from_str = |s| match s.parse() {
+        Ok(0) => Some(None),
+        Ok(secs) => Some(Some(Duration::from_secs(secs))),
+        Err(_) => None,
+    };
+);
+from_env_var!(
+    /// How long a primary connection may go without input before it is sent a `PING`
+    let name = RedisPingInterval;
+    let default: Option<Duration> = None;
+    let (env_var, allowed_values) = ("REDIS_PING_INTERVAL", "a number of seconds (0 to disable)");
+    let from_str = |s| match s.parse() {
+        Ok(0) => Some(None),
+        Ok(secs) => Some(Some(Duration::from_secs(secs))),
+        Err(_) => None,
+    };
+);
+from_env_var!(
+    /// How long to cache the addresses REDIS_HOST resolves to (in place of the TTLs of its
+    /// records, which the system's resolver doesn't report)
+    let name = RedisDnsTtl;
+    let default: Duration = Duration::from_secs(30);
+    let (env_var, allowed_values) = ("REDIS_DNS_TTL", "a number of seconds");
+    let from_str = |s| s.parse().map(Duration::from_secs).ok();
 );
 from_env_var!(
     /// The password to use for Redis
-    let name = RedisPass;
+    let secret = RedisPass;
     let default: Option<String> = None;
     let (env_var, allowed_values) = ("REDIS_PASSWORD", "any string");
     let from_str = |s| Some(Some(s.to_string()));
@@ -39,16 +330,87 @@ from_env_var!(
     let from_str = |s| Some(Some(s.to_string()));
 );
 from_env_var!(
-    /// A user for Redis (not supported)
+    /// The ACL user to authenticate to Redis as, with REDIS_PASSWORD (Redis 6 and later)
     let name = RedisUser;
     let default: Option<String> = None;
     let (env_var, allowed_values) = ("REDIS_USER", "any string");
     let from_str = |s| Some(Some(s.to_string()));
 );
 from_env_var!(
-    /// The database to use with Redis (no current effect for PubSub connections)
+    /// The database to set the `subscribed:` keys in (PubSub channels are shared by every
+    /// database, so it has no effect on them)
     let name = RedisDb;
-    let default: Option<String> = None;
-    let (env_var, allowed_values) = ("REDIS_DB", "any string");
-    let from_str = |s| Some(Some(s.to_string()));
+    let default: Option<u32> = None;
+    let (env_var, allowed_values) = ("REDIS_DB", "a database number");
+    let from_str = |s| s.parse().ok().map(Some);
 );
+from_env_var!(
+    /// How to tell Mastodon which channels have subscribers (see `SubscribedKeyStyleInner`)
+    let name = SubscribedKeyStyle;
+    let default: SubscribedKeyStyleInner = SubscribedKeyStyleInner::Legacy;
+    let (env_var, allowed_values) = ("SUBSCRIBED_KEY_STYLE", &format!("one of: {:?}", SubscribedKeyStyleInner::variants()));
+    let from_str = |s| SubscribedKeyStyleInner::from_str(s).ok();
+);
+from_env_var!(
+    /// The prefix of the keys that tell Mastodon which channels have subscribers
+    let name = SubscribedKeyPrefix;
+    let default: String = "subscribed:".to_string();
+    let (env_var, allowed_values) = ("SUBSCRIBED_KEY_PREFIX", "any string");
+    let from_str = |s| Some(s.to_string());
+);
+from_env_var!(
+    /// How long `expiring` subscribed keys last unless they are refreshed
+    let name = SubscribedKeyTtl;
+    let default: Duration = Duration::from_secs(3 * 6 * 60);
+    let (env_var, allowed_values) = ("SUBSCRIBED_KEY_TTL", "a positive number of seconds");
+    let from_str = |s| s.parse().ok().filter(|&secs| secs > 0).map(Duration::from_secs);
+);
+
+#[derive(EnumString, EnumVariantNames, Debug, Clone, Copy, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+pub enum SubscribedKeyStyleInner {
+    /// `subscribed:<channel>` set to `1` while any instance is subscribed and `0` afterwards,
+    /// without expiry (as early versions of Mastodon's streaming server did)
+    Legacy,
+    /// `SET <namespace:>subscribed:<channel> 1 EX <ttl>`, refreshed while anyone is subscribed
+    /// and left to expire afterwards (as current versions of Mastodon do)
+    Expiring,
+}
+
+#[derive(EnumString, EnumVariantNames, Debug, Clone, Copy, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+pub enum ClientOverflowInner {
+    /// Stop reading from Redis until the client catches up, so that no event is lost but one
+    /// slow client delays every other
+    Wait,
+    /// Skip the events that don't fit in the client's channel
+    Drop,
+    /// Close the client's connection
+    Disconnect,
+}
+
+#[derive(EnumString, EnumVariantNames, Debug, Clone, Copy, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+pub enum EventModeInner {
+    /// Check each event against Mastodon's API, so that the filters see every field they can
+    /// use (such as a status's mentions); an event that doesn't conform is passed through
+    Strict,
+    /// Read only the fields that routing needs, which takes less CPU but leaves the filters
+    /// without the mentions of statuses
+    PassThrough,
+}
+
+/// The categories of timeline that `EVENT_MODES` sets a mode for
+pub const EVENT_CATEGORIES: [&str; 5] = ["public", "hashtag", "user", "list", "direct"];
+
+#[derive(EnumString, EnumVariantNames, Debug, Clone, Copy, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+pub enum InvalidUtf8Inner {
+    /// Drop the message (logging it and counting it)
+    Reject,
+    /// Replace each invalid sequence with U+FFFD, in every transport
+    Replace,
+    /// Send WebSocket clients the message's bytes as published, in a binary frame; other
+    /// transports (and the filters) get the message as `Replace` would
+    Raw,
+}
@@ -0,0 +1,658 @@
+//! `flodgatt conformance <base-url> [access-token]`: connect to a running streaming server (or
+//! the proxy in front of it) as a client would, and check each kind of stream
+//!
+//! The checks cover the response codes for good and bad requests, the framing of Server Sent
+//! Events and WebSocket messages, heartbeats, and that a WebSocket stays open when the client
+//! sends it messages (as clients that multiplex several streams over one socket do).  Without
+//! an access token, the checks that need one are skipped.  Only plain HTTP is supported, so a
+//! proxy that terminates TLS has to be checked through a listener without it.
+use serde_json::Value;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+use url::{form_urlencoded, Url};
+
+#[cfg(test)]
+mod test;
+
+/// How long to wait for a connection or a response
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// How long to wait for a heartbeat (both SSE keep-alives and WebSocket pings are sent every
+/// 30 seconds)
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(40);
+/// The sample key from RFC 6455, and the `Sec-WebSocket-Accept` a server must answer it with
+const WS_KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+const WS_ACCEPT: &str = "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=";
+/// The origin of a Mastodon web UI served from another host than the streaming server (when
+/// `streaming_api_base_url` names its own)
+const WEB_ORIGIN: &str = "https://mastodon.example";
+
+type Check = fn(&Target) -> Outcome;
+
+const CHECKS: &[(&str, Check)] = &[
+    ("health endpoint", health),
+    ("health: cross-origin GET", health_cross_origin),
+    ("health: CORS preflight", health_preflight),
+    ("sse: public", |t| t.sse("public", false)),
+    ("sse: public:local", |t| t.sse("public/local", false)),
+    ("sse: hashtag", |t| t.sse("hashtag?tag=mastodon", false)),
+    ("sse: user (token in header)", |t| t.sse("user", true)),
+    ("sse: user:notification", |t| {
+        t.sse("user/notification", true)
+    }),
+    ("sse: direct", |t| t.sse("direct", true)),
+    ("sse: user without a token is 401", |t| {
+        t.sse_status("user", None, 401)
+    }),
+    ("sse: user with a bad token is 401", |t| {
+        t.sse_status("user", Some("not-a-token"), 401)
+    }),
+    ("sse: hashtag without a tag is 400", |t| {
+        t.sse_status("hashtag", None, 400)
+    }),
+    ("sse: unknown stream is 404", |t| {
+        t.sse_status("nonexistent", None, 404)
+    }),
+    ("ws: public", |t| t.ws("public", false)),
+    ("ws: public:local", |t| t.ws("public:local", false)),
+    ("ws: hashtag", |t| t.ws("hashtag&tag=mastodon", false)),
+    ("ws: user (token in query)", |t| t.ws("user", true)),
+    ("ws: direct", |t| t.ws("direct", true)),
+    ("ws: user without a token is 401", |t| {
+        t.ws_status("user", None, 401)
+    }),
+    ("ws: user with a bad token is 401", |t| {
+        t.ws_status("user", Some("not-a-token"), 401)
+    }),
+    ("ws: unknown stream is 400", |t| {
+        t.ws_status("nonexistent", None, 400)
+    }),
+    ("ws: client messages don't close the stream", multiplexing),
+    ("ws: trailing slash (as Mastodon's web UI connects)", |t| {
+        Outcome::from(
+            t.ws_handshake("/?stream=public")
+                .and_then(|(status, _, _)| expect_status(status, 101)),
+        )
+    }),
+];
+
+/// The server being checked
+#[derive(Clone, Debug)]
+pub struct Target {
+    /// `host:port`, for connecting
+    addr: String,
+    /// The `Host` header
+    host: String,
+    /// The path the streaming API is under (usually empty)
+    prefix: String,
+    token: Option<String>,
+}
+
+/// The result of one check
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Pass,
+    Fail(String),
+    Skip(String),
+}
+
+/// Every check's outcome, in order
+#[derive(Debug)]
+pub struct Report(Vec<(&'static str, Outcome)>);
+
+/// One Server Sent Event, or a WebSocket message
+#[derive(Debug, PartialEq)]
+enum Received {
+    Heartbeat,
+    Event,
+}
+
+impl Target {
+    pub fn new(base_url: &str, token: Option<String>) -> Result<Self, String> {
+        let url = Url::parse(base_url).map_err(|e| format!("{}: {}", base_url, e))?;
+        match url.scheme() {
+            "http" | "ws" => (),
+            "https" | "wss" => Err("TLS is not supported; use an address that serves plain HTTP")?,
+            other => Err(format!("unsupported scheme `{}`", other))?,
+        }
+        let host = url
+            .host_str()
+            .ok_or_else(|| format!("{} has no host", base_url))?;
+        Ok(Self {
+            addr: format!("{}:{}", host, url.port().unwrap_or(80)),
+            host: match url.port() {
+                Some(port) => format!("{}:{}", host, port),
+                None => host.to_string(),
+            },
+            prefix: url.path().trim_end_matches('/').to_string(),
+            token,
+        })
+    }
+
+    /// Send a `GET` request for `path_and_query` (under `/api/v1/streaming`) and read the
+    /// response's status and headers, leaving the body to be read.
+    fn get(
+        &self,
+        path_and_query: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<(u16, Vec<(String, String)>, BufReader<TcpStream>), String> {
+        self.request("GET", path_and_query, headers)
+    }
+
+    fn request(
+        &self,
+        method: &str,
+        path_and_query: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<(u16, Vec<(String, String)>, BufReader<TcpStream>), String> {
+        let conn = TcpStream::connect(&self.addr).map_err(|e| format!("connect: {}", e))?;
+        conn.set_read_timeout(Some(TIMEOUT))
+            .map_err(|e| e.to_string())?;
+        let mut request = format!(
+            "{} {}/api/v1/streaming{} HTTP/1.1\r\nHost: {}\r\nUser-Agent: flodgatt-conformance\r\n",
+            method, self.prefix, path_and_query, self.host
+        );
+        for (name, value) in headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        request.push_str("\r\n");
+        (&conn)
+            .write_all(request.as_bytes())
+            .map_err(|e| format!("write: {}", e))?;
+
+        let mut reader = BufReader::new(conn);
+        let mut line = String::new();
+        reader
+            .read_line(&mut line)
+            .map_err(|e| format!("read: {}", e))?;
+        let status = line
+            .split(' ')
+            .nth(1)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| format!("not an HTTP status line: {:?}", line))?;
+        let mut headers = Vec::new();
+        loop {
+            line.clear();
+            reader
+                .read_line(&mut line)
+                .map_err(|e| format!("read: {}", e))?;
+            match line.trim_end().splitn(2, ':').collect::<Vec<_>>()[..] {
+                [""] => break,
+                [name, value] => headers.push((name.to_lowercase(), value.trim().to_string())),
+                _ => Err(format!("malformed header: {:?}", line))?,
+            }
+        }
+        Ok((status, headers, reader))
+    }
+
+    fn sse(&self, stream: &str, authenticated: bool) -> Outcome {
+        let token = match (authenticated, &self.token) {
+            (true, None) => return Outcome::Skip("needs an access token".to_string()),
+            (true, Some(token)) => Some(format!("Bearer {}", token)),
+            (false, _) => None,
+        };
+        let mut headers = vec![("Accept", "text/event-stream")];
+        headers.extend(token.as_deref().map(|token| ("Authorization", token)));
+        Outcome::from(self.get(&format!("/{}", stream), &headers).and_then(
+            |(status, headers, body)| {
+                expect_status(status, 200)?;
+                match header(&headers, "content-type") {
+                    Some(kind) if kind.starts_with("text/event-stream") => (),
+                    kind => Err(format!("Content-Type is {:?}, not text/event-stream", kind))?,
+                }
+                let chunked = header(&headers, "transfer-encoding") == Some("chunked");
+                body.get_ref()
+                    .set_read_timeout(Some(HEARTBEAT_TIMEOUT))
+                    .map_err(|e| e.to_string())?;
+                let mut events = BufReader::new(Body::new(body, chunked));
+                until_heartbeat(|| read_sse(&mut events))
+            },
+        ))
+    }
+
+    fn sse_status(&self, stream: &str, token: Option<&str>, expected: u16) -> Outcome {
+        let query = token.map_or_else(String::new, |token| format!("?access_token={}", token));
+        Outcome::from(
+            self.get(
+                &format!("/{}{}", stream, query),
+                &[("Accept", "text/event-stream")],
+            )
+            .and_then(|(status, _, _)| expect_status(status, expected)),
+        )
+    }
+
+    /// Open a WebSocket for the `stream` query (which may carry other parameters).
+    fn ws_connect(
+        &self,
+        stream: &str,
+        token: Option<&str>,
+    ) -> Result<(u16, Vec<(String, String)>, BufReader<TcpStream>), String> {
+        let mut query = format!("?stream={}", stream);
+        if let Some(token) = token {
+            let token: String = form_urlencoded::byte_serialize(token.as_bytes()).collect();
+            query.push_str(&format!("&access_token={}", token));
+        }
+        self.ws_handshake(&query)
+    }
+
+    /// Send a WebSocket handshake for `path_and_query`.
+    fn ws_handshake(
+        &self,
+        path_and_query: &str,
+    ) -> Result<(u16, Vec<(String, String)>, BufReader<TcpStream>), String> {
+        self.get(
+            path_and_query,
+            &[
+                ("Upgrade", "websocket"),
+                ("Connection", "Upgrade"),
+                ("Sec-WebSocket-Key", WS_KEY),
+                ("Sec-WebSocket-Version", "13"),
+            ],
+        )
+    }
+
+    /// Open a WebSocket and check that the server completed the handshake correctly.
+    fn ws_open(&self, stream: &str, authenticated: bool) -> Result<BufReader<TcpStream>, Outcome> {
+        let token = match (authenticated, &self.token) {
+            (true, None) => return Err(Outcome::Skip("needs an access token".to_string())),
+            (true, Some(token)) => Some(token.as_str()),
+            (false, _) => None,
+        };
+        let (status, headers, conn) = self.ws_connect(stream, token).map_err(Outcome::Fail)?;
+        expect_status(status, 101).map_err(Outcome::Fail)?;
+        if !header(&headers, "upgrade").map_or(false, |up| up.eq_ignore_ascii_case("websocket")) {
+            return Err(Outcome::Fail(
+                "the 101 response has no `Upgrade: websocket`".into(),
+            ));
+        }
+        if header(&headers, "sec-websocket-accept") != Some(WS_ACCEPT) {
+            let accept = header(&headers, "sec-websocket-accept");
+            return Err(Outcome::Fail(format!(
+                "wrong Sec-WebSocket-Accept: {:?}",
+                accept
+            )));
+        }
+        conn.get_ref()
+            .set_read_timeout(Some(HEARTBEAT_TIMEOUT))
+            .map_err(|e| Outcome::Fail(e.to_string()))?;
+        Ok(conn)
+    }
+
+    fn ws(&self, stream: &str, authenticated: bool) -> Outcome {
+        match self.ws_open(stream, authenticated) {
+            Ok(mut conn) => Outcome::from(until_heartbeat(|| read_ws(&mut conn))),
+            Err(outcome) => outcome,
+        }
+    }
+
+    fn ws_status(&self, stream: &str, token: Option<&str>, expected: u16) -> Outcome {
+        Outcome::from(
+            self.ws_connect(stream, token)
+                .and_then(|(status, _, _)| expect_status(status, expected)),
+        )
+    }
+}
+
+/// Check `target` and report the outcome of each check.  The checks run concurrently, since
+/// most of them wait for a heartbeat.
+pub fn run(target: &Target) -> Report {
+    let checks: Vec<_> = CHECKS
+        .iter()
+        .map(|&(name, check)| {
+            let target = target.clone();
+            (name, thread::spawn(move || check(&target)))
+        })
+        .collect();
+    Report(
+        checks
+            .into_iter()
+            .map(|(name, check)| {
+                let outcome = check.join();
+                (
+                    name,
+                    outcome.unwrap_or_else(|_| Outcome::Fail("panicked".to_string())),
+                )
+            })
+            .collect(),
+    )
+}
+
+fn health(target: &Target) -> Outcome {
+    Outcome::from(
+        target
+            .get("/health", &[])
+            .and_then(|(status, _, mut body)| {
+                expect_status(status, 200)?;
+                let mut text = [0; 2];
+                body.read_exact(&mut text)
+                    .map_err(|e| format!("read: {}", e))?;
+                match &text {
+                    b"OK" => Ok(()),
+                    other => Err(format!(
+                        "body is {:?}, not OK",
+                        String::from_utf8_lossy(other)
+                    )),
+                }
+            }),
+    )
+}
+
+/// Fetch the health endpoint from the web UI's origin, as a browser would.
+fn health_cross_origin(target: &Target) -> Outcome {
+    Outcome::from(target.get("/health", &[("Origin", WEB_ORIGIN)]).and_then(
+        |(status, headers, _)| {
+            expect_status(status, 200)?;
+            expect_cors(&headers)
+        },
+    ))
+}
+
+/// Ask whether the web UI's origin may fetch the health endpoint, as a browser does before a
+/// request it can't send without asking.
+fn health_preflight(target: &Target) -> Outcome {
+    let headers = [
+        ("Origin", WEB_ORIGIN),
+        ("Access-Control-Request-Method", "GET"),
+        ("Access-Control-Request-Headers", "authorization"),
+    ];
+    Outcome::from(target.request("OPTIONS", "/health", &headers).and_then(
+        |(status, headers, _)| {
+            match status {
+                200 | 204 => (),
+                status => Err(format!("status {}, expected 200 or 204", status))?,
+            }
+            expect_cors(&headers)
+        },
+    ))
+}
+
+/// Send a subscribe message (as a multiplexing client would) on a public WebSocket, and check
+/// that the stream carries on (after acknowledging it).
+fn multiplexing(target: &Target) -> Outcome {
+    let mut conn = match target.ws_open("public", false) {
+        Ok(conn) => conn,
+        Err(outcome) => return outcome,
+    };
+    let msg = br#"{"type":"subscribe","stream":"public:local"}"#;
+    if let Err(e) = write_frame(conn.get_mut(), 0x1, msg) {
+        return Outcome::Fail(format!("write: {}", e));
+    }
+    Outcome::from(until_heartbeat(|| read_ws(&mut conn)))
+}
+
+/// Read from a stream until a heartbeat arrives.  A busy stream may send events instead of
+/// heartbeats, so valid events until the `HEARTBEAT_TIMEOUT` also pass.
+fn until_heartbeat(mut read: impl FnMut() -> Result<Received, String>) -> Result<(), String> {
+    let deadline = Instant::now() + HEARTBEAT_TIMEOUT;
+    while Instant::now() < deadline {
+        if read()? == Received::Heartbeat {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+/// Read one Server Sent Event (or keep-alive comment), checking its framing.
+fn read_sse(events: &mut impl BufRead) -> Result<Received, String> {
+    let (mut name, mut data, mut line) = (None, None, String::new());
+    loop {
+        line.clear();
+        let n = read_with_deadline(|| events.read_line(&mut line))?;
+        if n == 0 {
+            return Err("the server closed the stream".to_string());
+        }
+        let line = line.trim_end_matches(|c| c == '\r' || c == '\n');
+        match line.splitn(2, ':').collect::<Vec<_>>()[..] {
+            [""] if name.is_none() && data.is_none() => continue,
+            [""] => break,
+            ["", ")"] => continue, // sent when the stream opens, not as a heartbeat
+            ["", _comment] => return Ok(Received::Heartbeat),
+            ["event", value] => name = Some(value.trim_start().to_string()),
+            ["data", value] => data = Some(value.trim_start().to_string()),
+            ["id", _] | ["retry", _] => (),
+            _ => Err(format!("malformed SSE line: {:?}", line))?,
+        }
+    }
+    let name = name.ok_or("an SSE event has data but no `event` field")?;
+    validate_event(&name, data.as_deref().unwrap_or_default())?;
+    Ok(Received::Event)
+}
+
+/// Read one WebSocket message, checking its framing and answering pings.
+fn read_ws(conn: &mut BufReader<TcpStream>) -> Result<Received, String> {
+    let mut msg = Vec::new();
+    loop {
+        let (fin, opcode, payload) = read_with_deadline(|| read_frame(conn))?;
+        match opcode {
+            0x0 | 0x1 => msg.extend(payload),
+            0x2 => Err("the server sent a binary message")?,
+            0x8 => Err(format!("the server closed the WebSocket: {:?}", payload))?,
+            0x9 => {
+                write_frame(conn.get_mut(), 0xA, &payload).map_err(|e| format!("write: {}", e))?;
+                return Ok(Received::Heartbeat);
+            }
+            0xA => continue,
+            other => Err(format!("unknown WebSocket opcode {:#x}", other))?,
+        }
+        if fin {
+            break;
+        }
+    }
+    let msg = String::from_utf8(msg).map_err(|_| "a text message isn't UTF-8")?;
+    if msg == "{}" {
+        return Ok(Received::Heartbeat); // Mastodon's keep-alive
+    }
+    let msg: Value = serde_json::from_str(&msg).map_err(|e| format!("{}: {}", e, msg))?;
+    if msg["error"].is_string() {
+        Err(format!("the server refused a message: {}", msg))?
+    }
+    if let Some("subscribed") | Some("unsubscribed") = msg["type"].as_str() {
+        return Ok(Received::Event); // acknowledges a message we sent
+    }
+    match (&msg["event"], &msg["payload"]) {
+        (Value::String(event), Value::String(payload)) => validate_event(event, payload)?,
+        (Value::String(event), Value::Null) => validate_event(event, "")?,
+        _ => Err(format!(
+            "a message has no `event` name or a non-string `payload`: {}",
+            msg
+        ))?,
+    }
+    Ok(Received::Event)
+}
+
+/// Check that an event's payload has the shape its name calls for.
+fn validate_event(name: &str, payload: &str) -> Result<(), String> {
+    match name {
+        "" => Err("an event has an empty name".to_string()),
+        "update" | "status.update" | "notification" | "conversation" | "announcement" => {
+            match serde_json::from_str(payload) {
+                Ok(Value::Object(object)) if object.contains_key("id") => Ok(()),
+                _ => Err(format!(
+                    "`{}` payload isn't an object with an `id`: {}",
+                    name, payload
+                )),
+            }
+        }
+        "delete" | "announcement.delete" if payload.is_empty() => {
+            Err(format!("`{}` has no ID", name))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Describe a read that fails, including one that times out waiting for input.
+fn read_with_deadline<T>(read: impl FnOnce() -> io::Result<T>) -> Result<T, String> {
+    read().map_err(|e| match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+            format!("no heartbeat within {:?}", HEARTBEAT_TIMEOUT)
+        }
+        _ => format!("read: {}", e),
+    })
+}
+
+/// Read a frame sent by the server: whether it ends a message, its opcode, and its payload
+fn read_frame(conn: &mut impl Read) -> io::Result<(bool, u8, Vec<u8>)> {
+    let mut head = [0_u8; 2];
+    conn.read_exact(&mut head)?;
+    let (fin, opcode) = (head[0] & 0x80 != 0, head[0] & 0x0F);
+    if head[1] & 0x80 != 0 {
+        let msg = "the server sent a masked frame";
+        return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+    }
+    let len = match head[1] & 0x7F {
+        126 => {
+            let mut len = [0_u8; 2];
+            conn.read_exact(&mut len)?;
+            u64::from(u16::from_be_bytes(len))
+        }
+        127 => {
+            let mut len = [0_u8; 8];
+            conn.read_exact(&mut len)?;
+            u64::from_be_bytes(len)
+        }
+        len => u64::from(len),
+    };
+    let mut payload = Vec::new();
+    let n = conn.take(len).read_to_end(&mut payload)?;
+    if u64::try_from(n) != Ok(len) {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok((fin, opcode, payload))
+}
+
+/// Write a frame as a client must, masked.
+fn write_frame(conn: &mut impl Write, opcode: u8, payload: &[u8]) -> io::Result<()> {
+    let mask = [0x37, 0xFA, 0x21, 0x3D];
+    let mut frame = vec![0x80 | opcode];
+    match (u8::try_from(payload.len()), u16::try_from(payload.len())) {
+        (Ok(len), _) if len < 126 => frame.push(0x80 | len),
+        (_, Ok(len)) => {
+            frame.push(0x80 | 126);
+            frame.extend_from_slice(&len.to_be_bytes());
+        }
+        (_, Err(_)) => {
+            frame.push(0x80 | 127);
+            let len = u64::try_from(payload.len()).unwrap_or(u64::MAX);
+            frame.extend_from_slice(&len.to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(&mask);
+    frame.extend(
+        payload
+            .iter()
+            .zip(mask.iter().cycle())
+            .map(|(byte, m)| byte ^ m),
+    );
+    conn.write_all(&frame)
+}
+
+fn expect_status(status: u16, expected: u16) -> Result<(), String> {
+    match status {
+        status if status == expected => Ok(()),
+        status => Err(format!("status {}, expected {}", status, expected)),
+    }
+}
+
+/// Check that a response lets a page on `WEB_ORIGIN` read it.
+fn expect_cors(headers: &[(String, String)]) -> Result<(), String> {
+    match header(headers, "access-control-allow-origin") {
+        Some("*") | Some(WEB_ORIGIN) => Ok(()),
+        other => Err(format!(
+            "Access-Control-Allow-Origin is {:?}, not {} or *",
+            other, WEB_ORIGIN
+        )),
+    }
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(n, _)| n == name)
+        .map(|(_, value)| value.as_str())
+}
+
+/// A response body, decoding the chunked transfer encoding that streaming responses use
+struct Body<R> {
+    inner: R,
+    chunked: bool,
+    /// The bytes left in the current chunk
+    left: usize,
+}
+
+impl<R: BufRead> Body<R> {
+    fn new(inner: R, chunked: bool) -> Self {
+        Self {
+            inner,
+            chunked,
+            left: 0,
+        }
+    }
+}
+
+impl<R: BufRead> Read for Body<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.chunked {
+            return self.inner.read(buf);
+        }
+        if self.left == 0 {
+            let mut size = String::new();
+            while size.trim().is_empty() {
+                size.clear();
+                if self.inner.read_line(&mut size)? == 0 {
+                    return Ok(0);
+                }
+            }
+            let size = size.trim().split(';').next().unwrap_or_default();
+            self.left = usize::from_str_radix(size, 16).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("bad chunk size {:?}", size),
+                )
+            })?;
+            if self.left == 0 {
+                return Ok(0);
+            }
+        }
+        let len = buf.len().min(self.left);
+        let n = self.inner.read(&mut buf[..len])?;
+        self.left -= n;
+        Ok(n)
+    }
+}
+
+impl From<Result<(), String>> for Outcome {
+    fn from(result: Result<(), String>) -> Self {
+        result.map_or_else(Outcome::Fail, |()| Outcome::Pass)
+    }
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.0
+            .iter()
+            .all(|(_, outcome)| !matches!(outcome, Outcome::Fail(_)))
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let count = |kind: fn(&Outcome) -> bool| self.0.iter().filter(|(_, o)| kind(o)).count();
+        for (name, outcome) in &self.0 {
+            match outcome {
+                Outcome::Pass => writeln!(f, "PASS  {}", name)?,
+                Outcome::Fail(why) => writeln!(f, "FAIL  {}: {}", name, why)?,
+                Outcome::Skip(why) => writeln!(f, "SKIP  {}: {}", name, why)?,
+            }
+        }
+        writeln!(
+            f,
+            "\n{} passed, {} failed, {} skipped",
+            count(|o| *o == Outcome::Pass),
+            count(|o| matches!(o, Outcome::Fail(_))),
+            count(|o| matches!(o, Outcome::Skip(_))),
+        )
+    }
+}
@@ -0,0 +1,81 @@
+use super::*;
+
+#[test]
+fn target_parses_base_url() -> Result<(), String> {
+    let target = Target::new("http://localhost:4000/", None)?;
+    assert_eq!(target.addr, "localhost:4000");
+    assert_eq!(target.host, "localhost:4000");
+    assert_eq!(target.prefix, "");
+
+    let target = Target::new("ws://example.com/streaming", None)?;
+    assert_eq!(target.addr, "example.com:80");
+    assert_eq!(target.host, "example.com");
+    assert_eq!(target.prefix, "/streaming");
+
+    assert!(Target::new("https://example.com", None).is_err());
+    assert!(Target::new("not a url", None).is_err());
+    Ok(())
+}
+
+#[test]
+fn sse_framing_is_checked() {
+    let stream = "\
+        :thump\n\n\
+        event: update\ndata: {\"id\":\"1\"}\n\n\
+        event: delete\ndata: 1\n\n\
+        event: update\ndata: not json\n\n\
+        data: 1\n\n\
+        bogus line\n\n";
+    let mut events = stream.as_bytes();
+    assert_eq!(read_sse(&mut events), Ok(Received::Heartbeat));
+    assert_eq!(read_sse(&mut events), Ok(Received::Event));
+    assert_eq!(read_sse(&mut events), Ok(Received::Event));
+    assert!(read_sse(&mut events).is_err());
+    assert!(read_sse(&mut events).is_err());
+    assert!(read_sse(&mut events).is_err());
+    assert!(read_sse(&mut events).is_err()); // the stream ended
+}
+
+#[test]
+fn chunked_body_is_decoded() -> io::Result<()> {
+    let chunked = "7\r\n:thump\n\r\n1\r\n\n\r\n0\r\n\r\n";
+    let mut body = String::new();
+    Body::new(chunked.as_bytes(), true).read_to_string(&mut body)?;
+    assert_eq!(body, ":thump\n\n");
+    Ok(())
+}
+
+#[test]
+fn ws_frames_are_masked_by_the_client_only() -> io::Result<()> {
+    let server_frame = [0x81, 0x02, b'{', b'}'];
+    assert_eq!(
+        read_frame(&mut &server_frame[..])?,
+        (true, 0x1, b"{}".to_vec())
+    );
+
+    let mut client_frame = Vec::new();
+    write_frame(&mut client_frame, 0x1, &[b'x'; 200])?;
+    assert_eq!(client_frame[..4], [0x81, 0x80 | 126, 0, 200]);
+    assert_eq!(client_frame.len(), 4 + 4 + 200);
+    assert!(read_frame(&mut &client_frame[..]).is_err());
+    Ok(())
+}
+
+#[test]
+fn event_payloads_are_validated() {
+    assert!(validate_event("update", r#"{"id":"1"}"#).is_ok());
+    assert!(validate_event("update", r#"{"content":"no id"}"#).is_err());
+    assert!(validate_event("delete", "103").is_ok());
+    assert!(validate_event("delete", "").is_err());
+    assert!(validate_event("filters_changed", "").is_ok());
+    assert!(validate_event("", "").is_err());
+}
+
+#[test]
+fn cors_headers_are_checked() {
+    let allow = |origin: &str| vec![("access-control-allow-origin".into(), origin.into())];
+    assert_eq!(expect_cors(&allow("*")), Ok(()));
+    assert_eq!(expect_cors(&allow(WEB_ORIGIN)), Ok(()));
+    assert!(expect_cors(&allow("https://elsewhere.example")).is_err());
+    assert!(expect_cors(&[]).is_err());
+}
@@ -6,7 +6,7 @@ use std::fmt;
 
 pub enum Error {
     Response(response::Error),
-    Logger(log::SetLoggerError),
+    Logger(String),
     Postgres(request::Error),
     Unrecoverable,
     StdIo(std::io::Error),
@@ -68,8 +68,16 @@ impl From<std::io::Error> for Error {
 }
 
 #[doc(hidden)]
-impl From<log::SetLoggerError> for Error {
-    fn from(e: log::SetLoggerError) -> Self {
-        Self::Logger(e)
+impl From<tracing_subscriber::util::TryInitError> for Error {
+    fn from(e: tracing_subscriber::util::TryInitError) -> Self {
+        Self::Logger(e.to_string())
+    }
+}
+
+#[cfg(feature = "otlp")]
+#[doc(hidden)]
+impl From<opentelemetry::trace::TraceError> for Error {
+    fn from(e: opentelemetry::trace::TraceError) -> Self {
+        Self::Logger(format!("could not export to the OTLP_ENDPOINT: {}", e))
     }
 }
@@ -0,0 +1,80 @@
+//! Jitter for Flodgatt's periodic work (with `JITTER`), so that timers that would otherwise
+//! fire together don't: the heartbeats sent to every client, the retries after Redis fails,
+//! the heartbeat kept in Redis, reconciliation, the canary, and setting the subscribed keys
+//! again.  Without it, instances started together (and the clients they ping) spike in step
+//! every 30 seconds.
+//!
+//! Each period is lengthened or shortened at random by up to the `JITTER` fraction of it with
+//! `vary`, or (for a deadline that mustn't be missed, such as setting expiring keys again)
+//! only shortened, with `shorten`.  Until `configure` is called, periods are left as they are.
+use futures::{stream, Future, Stream};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::time::{Duration, Instant};
+use tokio::timer::{self, Delay};
+
+#[cfg(test)]
+mod test;
+
+/// The `JITTER` fraction, as the bits of an `f64` (`0.0` until configured)
+static FRACTION: AtomicU64 = AtomicU64::new(0);
+/// The state of a SplitMix64 generator, which is advanced without a lock
+static STATE: AtomicU64 = AtomicU64::new(0);
+
+/// Vary periods by up to `fraction` of them, at random (or, with a `seed`, as every other run
+/// with the same seed does)
+pub fn configure(fraction: f64, seed: Option<u64>) {
+    let seed = seed.unwrap_or_else(|| RandomState::new().build_hasher().finish());
+    STATE.store(seed, Relaxed);
+    FRACTION.store(fraction.max(0.0).min(1.0).to_bits(), Relaxed);
+}
+
+/// `period`, lengthened or shortened by up to the `JITTER` fraction of it
+pub fn vary(period: Duration) -> Duration {
+    varied(period, fraction(), next_u64())
+}
+
+/// `period`, shortened by up to the `JITTER` fraction of it
+pub fn shorten(period: Duration) -> Duration {
+    period - offset(period, fraction(), next_u64())
+}
+
+/// A stream that yields at `first` and then after every `period` (each one varied), like
+/// `tokio::timer::Interval`
+pub fn interval(
+    first: Instant,
+    period: Duration,
+) -> impl Stream<Item = Instant, Error = timer::Error> {
+    stream::unfold(first, move |at| {
+        Some(Delay::new(at).map(move |()| (at, at + vary(period))))
+    })
+}
+
+fn fraction() -> f64 {
+    f64::from_bits(FRACTION.load(Relaxed))
+}
+
+/// `period`, varied by `random`: its lowest bit chooses whether to lengthen or shorten it, and
+/// the rest by how much
+fn varied(period: Duration, fraction: f64, random: u64) -> Duration {
+    match random & 1 {
+        0 => period + offset(period, fraction, random),
+        _ => period - offset(period, fraction, random),
+    }
+}
+
+/// Up to `fraction` of `period`, in proportion to the highest 53 bits of `random`
+fn offset(period: Duration, fraction: f64, random: u64) -> Duration {
+    let unit = (random >> 11) as f64 / (1_u64 << 53) as f64;
+    period.mul_f64(fraction * unit)
+}
+
+fn next_u64() -> u64 {
+    let mut z = STATE
+        .fetch_add(0x9e37_79b9_7f4a_7c15, Relaxed)
+        .wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
@@ -0,0 +1,29 @@
+use super::*;
+
+const PERIOD: Duration = Duration::from_secs(30);
+
+#[test]
+fn periods_vary_by_up_to_the_fraction_both_ways() {
+    let (min, max) = (PERIOD.mul_f64(0.9), PERIOD.mul_f64(1.1));
+    let periods: Vec<_> = (0..1000).map(|_| varied(PERIOD, 0.1, next_u64())).collect();
+
+    assert!(periods.iter().all(|period| (min..=max).contains(period)));
+    assert!(periods.iter().any(|period| *period > PERIOD.mul_f64(1.05)));
+    assert!(periods.iter().any(|period| *period < PERIOD.mul_f64(0.95)));
+    assert_eq!(varied(PERIOD, 0.0, next_u64()), PERIOD);
+}
+
+#[test]
+fn deadlines_only_come_sooner() {
+    for random in &[0, u64::MAX, next_u64()] {
+        let period = PERIOD - offset(PERIOD, 0.2, *random);
+        assert!(period <= PERIOD && period >= PERIOD.mul_f64(0.8));
+    }
+    assert_eq!(offset(PERIOD, 0.2, 0), Duration::from_secs(0));
+}
+
+#[test]
+fn periods_are_left_alone_until_configured() {
+    assert_eq!(vary(PERIOD), PERIOD);
+    assert_eq!(shorten(PERIOD), PERIOD);
+}
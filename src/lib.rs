@@ -17,8 +17,8 @@
 //! the `User` data on to the `ClientAgent`.  The `ClientAgent` is responsible for
 //! communicating the user's request to the `Receiver`, polling the `Receiver` for any
 //! updates, and then for wording those updates on to the client.  The `Receiver`, in tern, is
-//! responsible for managing the Redis subscriptions, periodically polling Redis, and sorting
-//! the replies from Redis into queues for when it is polled by the `ClientAgent`.
+//! responsible for managing the Redis subscriptions, reading from Redis as input arrives, and
+//! sorting the replies from Redis into queues for when it is polled by the `ClientAgent`.
 //!
 //! # Concurrency
 //! The `Receiver` is created when the server is first initialized, and there is only one
@@ -31,8 +31,8 @@
 //!
 //! # Configuration By default, the server uses config values from the `config.rs` module;
 //! these values can be overwritten with environmental variables or in the `.env` file.  The
-//! most important settings for performance control the frequency with which the `ClientAgent`
-//! polls the `Receiver` and the frequency with which the `Receiver` polls Redis.
+//! most important setting for performance controls the frequency with which the `ClientAgent`
+//! polls the `Receiver`.
 //!
 
 #![warn(clippy::pedantic)]
@@ -41,10 +41,17 @@
 
 pub use err::Error;
 
+#[cfg(feature = "alloc_count")]
+pub mod alloc_count;
 pub mod config;
+pub mod conformance;
 mod err;
+pub mod jitter;
+pub mod logger;
+pub mod metrics;
 pub mod request;
 pub mod response;
+pub mod unix_socket;
 
 /// A user ID.
 ///
@@ -0,0 +1,114 @@
+//! Flodgatt's diagnostics, which are `tracing` spans and events (along with whatever the crates
+//! Flodgatt uses log): written as lines to read in a terminal or, with `LOG_FORMAT=json`, as
+//! one JSON object per line for a log pipeline such as Loki or ELK.  `RUST_LOG` filters them by
+//! level and module (e.g., `warn,flodgatt::response=info`).  Built with the `otlp` feature,
+//! Flodgatt also exports its spans to the OpenTelemetry collector at `OTLP_ENDPOINT`, so that
+//! they can be traced alongside Mastodon's.
+//!
+//! Each client connection has a `client` span (with a `client_id`, its protocol, and the
+//! timeline it first asked for), each message from Redis a `redis_msg` span (with its timeline
+//! and how many clients it was `delivered` to), and the work on each Redis connection a `redis`
+//! span (with its `addr`).  Lines carry the fields of the spans they were logged in.  The
+//! spans are at the `info` level, so they are only recorded when `RUST_LOG` includes it.
+//!
+//! `RUST_LOG` is re-read on `SIGHUP` (see `LogFilter`), so that a running instance can be made
+//! more verbose without dropping its clients.
+use crate::config::LogFormatInner;
+use crate::request::Timeline;
+use crate::Error;
+
+use std::io;
+use tracing::{Span, Subscriber};
+use tracing_subscriber::fmt::{self, MakeWriter};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+#[cfg(test)]
+mod test;
+
+/// Start recording spans and events at the `RUST_LOG` level, in `format`, and exporting them to
+/// the `otlp_endpoint` (if there is one)
+pub fn init(format: LogFormatInner, otlp_endpoint: Option<&str>) -> Result<LogFilter, Error> {
+    let (filter, handle) = reload::Layer::new(EnvFilter::from_default_env());
+    let subscriber = subscriber(format, io::stderr).with(filter);
+    #[cfg(feature = "otlp")]
+    let subscriber = subscriber.with(otlp::layer(otlp_endpoint)?);
+    #[cfg(not(feature = "otlp"))]
+    let _ = otlp_endpoint; // warned about by `Deployment`
+    subscriber.try_init()?;
+    Ok(LogFilter(Box::new(move |filter| handle.reload(filter))))
+}
+
+/// Changes which spans and events are recorded, once `init` has started recording them
+pub struct LogFilter(Box<dyn Fn(EnvFilter) -> Result<(), reload::Error> + Send + Sync>);
+
+impl LogFilter {
+    /// Record what the `RUST_LOG`-style `directives` (e.g., `warn,flodgatt::response=info`)
+    /// enable from now on
+    pub fn set(&self, directives: &str) -> Result<(), Error> {
+        let filter = EnvFilter::try_new(directives)
+            .map_err(|e| Error::Logger(format!("could not parse RUST_LOG: {}", e)))?;
+        (self.0)(filter).map_err(|e| Error::Logger(e.to_string()))
+    }
+}
+
+/// Export the spans that haven't been yet, before exiting
+pub fn flush() {
+    #[cfg(feature = "otlp")]
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// A span for a client's connection, so that everything logged about the client can be found
+/// (by its `client_id`, which is also its id on the changefeed, see `OpenConnection`)
+pub fn client_span(client_id: u64, protocol: &'static str, timeline: &Timeline) -> Span {
+    tracing::info_span!("client", client_id, protocol, timeline = ?timeline)
+}
+
+/// The spans and events, written to `writer` in `format`
+fn subscriber<W>(format: LogFormatInner, writer: W) -> impl Subscriber + for<'a> LookupSpan<'a>
+where
+    W: MakeWriter + Send + Sync + 'static,
+{
+    let (text, json) = match format {
+        LogFormatInner::Text => (Some(fmt::layer().with_writer(writer)), None),
+        LogFormatInner::Json => {
+            let json = fmt::layer().json().flatten_event(true);
+            (None, Some(json.with_writer(writer)))
+        }
+    };
+    Registry::default().with(text).with(json)
+}
+
+#[cfg(feature = "otlp")]
+mod otlp {
+    use crate::Error;
+
+    use opentelemetry::sdk::{trace, Resource};
+    use opentelemetry::{runtime, KeyValue};
+    use tracing::Subscriber;
+    use tracing_opentelemetry::OpenTelemetryLayer;
+    use tracing_subscriber::registry::LookupSpan;
+
+    /// Exports spans to the collector at `endpoint`, in batches sent from threads of their own
+    /// (since the simple exporter would block the thread that closed each span)
+    pub(super) fn layer<S>(
+        endpoint: Option<&str>,
+    ) -> Result<Option<OpenTelemetryLayer<S, trace::Tracer>>, Error>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let endpoint = match endpoint {
+            Some(endpoint) => endpoint,
+            None => return Ok(None),
+        };
+        let service = Resource::new(vec![KeyValue::new("service.name", "flodgatt")]);
+        let tracer = opentelemetry_otlp::new_pipeline()
+            .with_endpoint(endpoint)
+            .with_trace_config(trace::config().with_resource(service))
+            .with_grpcio()
+            .install_batch(runtime::AsyncStd)?;
+        Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+    }
+}
@@ -0,0 +1,45 @@
+use super::*;
+use crate::request::TagRegistry;
+
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+
+/// Where a test's log lines are written
+#[derive(Clone, Default)]
+struct Lines(Arc<Mutex<Vec<u8>>>);
+
+impl io::Write for Lines {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().expect("unpoisoned").extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn json_lines_carry_the_fields_of_their_spans() -> Result<(), Box<dyn std::error::Error>> {
+    let lines = Lines::default();
+    let writer = lines.clone();
+    let subscriber = subscriber(LogFormatInner::Json, move || writer.clone());
+    let timeline = Timeline::from_redis_text("public", &TagRegistry::default())?;
+
+    tracing::subscriber::with_default(subscriber, || {
+        let client = client_span(7, "sse", &timeline);
+        let _client = client.enter();
+        tracing::warn!("Refused {:?}", timeline);
+    });
+    let output = lines.0.lock().expect("unpoisoned").clone();
+    let line: Value = serde_json::from_slice(&output)?;
+
+    assert_eq!(line["level"], "WARN");
+    assert_eq!(line["target"], "flodgatt::logger::test");
+    assert_eq!(line["message"], format!("Refused {:?}", timeline));
+    assert_eq!(line["span"]["name"], "client");
+    assert_eq!(line["span"]["protocol"], "sse");
+    assert_eq!(line["span"]["timeline"], format!("{:?}", timeline));
+    assert_eq!(line["span"]["client_id"], 7);
+    assert!(line["timestamp"].is_string());
+    Ok(())
+}
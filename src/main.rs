@@ -1,69 +1,274 @@
-use flodgatt::config;
-use flodgatt::request::{Handler, Subscription};
-use flodgatt::response::{RedisManager, SseStream, WsStream};
+#[cfg(feature = "admin")]
+use flodgatt::config::EventModeInner;
+use flodgatt::config::{self, Cli, Command};
+use flodgatt::conformance::{self, Target};
+use flodgatt::jitter;
+use flodgatt::logger::{self, LogFilter};
+#[cfg(feature = "admin")]
+use flodgatt::request::{
+    CacheScopes, DenylistEntry, DenylistSummary, Disconnect, Disconnected, ReceiptQuery,
+};
+use flodgatt::request::{
+    Denylist, Drain, GeoPolicy, Handler, HealthChecks, HistoryRequest, Ingest, Readiness,
+    Subscription, TagRegistry, POLICY_VIOLATION,
+};
+use flodgatt::response::{
+    Canary, CanaryHealth, EventFormat, FirstByte, IpcSink, Multiplex, OpenConnection, RedisManager,
+    SseStream, WsStream,
+};
+use flodgatt::unix_socket;
 use flodgatt::Error;
+#[cfg(feature = "generator")]
+use flodgatt::{config::GeneratorTargetInner, response::Generator};
 
-use futures::future::lazy;
+use futures::future::{self, lazy, Future as _};
 use futures::stream::Stream as _;
-use std::fs;
+use futures::Async;
+use serde_json::json;
+use std::env;
+use std::fmt;
+use std::mem;
 use std::net::SocketAddr;
-use std::os::unix::fs::PermissionsExt;
-use std::time::Instant;
+use std::process;
+use std::ptr;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
 use tokio::net::UnixListener;
-use tokio::sync::mpsc;
-use tokio::timer::Interval;
+use tokio::timer::Delay;
+use warp::http::StatusCode;
 use warp::ws::Ws2;
 use warp::Filter;
+#[cfg(feature = "admin")]
+use warp::Reply;
 
 fn main() -> Result<(), Error> {
+    let cli = Cli::from_args();
+    match &cli.command {
+        // `conformance <base-url> [access-token]` checks a running server instead of being one
+        Some(Command::Conformance {
+            base_url,
+            access_token,
+        }) => match Target::new(base_url, access_token.clone()) {
+            Ok(target) => {
+                let report = conformance::run(&target);
+                print!("{}", report);
+                process::exit(if report.passed() { 0 } else { 1 });
+            }
+            Err(e) => {
+                eprintln!("Invalid base URL: {}", e);
+                process::exit(2);
+            }
+        },
+        // `import-node-config [env-file]` prints the equivalent of a Node streaming server's config
+        Some(Command::ImportNodeConfig { env_file }) => match config::NodeImport::read(env_file) {
+            Ok(import) => {
+                print!("{}", import);
+                for warning in import.warnings() {
+                    eprintln!(" WARN: {}", warning);
+                }
+                if let Err(e) = import.validate() {
+                    eprintln!(
+                        " ERROR: Flodgatt would not start with this configuration: {}",
+                        e
+                    );
+                    process::exit(1);
+                }
+                process::exit(0);
+            }
+            Err(e) => {
+                eprintln!("Could not read {}: {}", env_file, e);
+                process::exit(2);
+            }
+        },
+        None => (),
+    }
+
+    // Before any other thread starts (see `on_signals`)
+    let running = on_signals();
+
+    // Flags take precedence over the environment, which takes precedence over the files
+    config::merge_cli(&cli);
+    let dev_mode = cli.dev;
+    if dev_mode && env::var_os("RUST_LOG").is_none() {
+        env::set_var("RUST_LOG", "debug"); // set before `.env` can set a quieter level
+    }
     config::merge_dotenv()?;
-    pretty_env_logger::try_init_timed()?;
+    if let Some(path) = &cli.config_file {
+        config::merge_config_file(path)?;
+    }
+    let log_filter = logger::init(config::log_format()?, config::otlp_endpoint()?.as_deref())?;
     let (postgres_cfg, redis_cfg, cfg) = config::from_env(dotenv::vars().collect())?;
-    let poll_freq = *redis_cfg.polling_interval;
+    if cli.print_config {
+        println!("{:#?}\n{:#?}\n{:#?}", postgres_cfg, redis_cfg, cfg);
+        process::exit(0);
+    }
+    jitter::configure(*cfg.jitter, *cfg.seed);
+    let reconcile_freq = *redis_cfg.reconcile_interval;
 
-    let request = Handler::new(&postgres_cfg, *cfg.whitelist_mode)?;
-    let shared_manager = RedisManager::try_from(&redis_cfg)?.into_arc();
+    let (request, mut manager) = if dev_mode {
+        tracing::warn!("Development mode: accepting DEV_TOKENS and sending synthetic events");
+        (Handler::dev(&cfg), RedisManager::detached(&redis_cfg))
+    } else {
+        let wait = *cfg.wait_for_deps;
+        let request = with_retries("Postgres", wait, || Handler::new(&postgres_cfg, &cfg))?;
+        let manager = with_retries("Redis", wait, || RedisManager::try_from(&redis_cfg))?;
+        (request, manager)
+    };
+    let request = request
+        .with_geo_policy(GeoPolicy::from_cfg(&cfg)?)
+        .with_tags(TagRegistry::new(*redis_cfg.tag_cache_size));
+    manager.use_tags(request.tags().clone());
+    match manager.load_tag_cache() {
+        Ok(0) => (),
+        Ok(loaded) => tracing::info!("Loaded {} hashtags from Redis", loaded),
+        Err(e) => tracing::error!("Could not load hashtags from Redis: {}", e),
+    }
+    #[cfg(feature = "generator")]
+    let generator = if dev_mode {
+        // With no Redis connection, synthetic events go straight to clients
+        let generator = Generator::from_cfg(&cfg, &redis_cfg);
+        Some(generator.with_target(GeneratorTargetInner::Pipeline))
+    } else {
+        cfg.generator_rate
+            .map(|_| Generator::from_cfg(&cfg, &redis_cfg))
+    };
+    #[cfg(not(feature = "generator"))]
+    {
+        if dev_mode || cfg.generator_rate.is_some() {
+            tracing::warn!(
+                "Built without the `generator` feature (build with `--features generator`); \
+                 not sending synthetic events"
+            );
+        }
+    }
+    let mut canary = Canary::from_cfg(&cfg, &redis_cfg, request.tags().clone());
+    if dev_mode {
+        // With no Redis connection, markers go straight to the `RedisManager`
+        canary = canary.map(Canary::without_redis);
+    }
+    let canary_health = canary
+        .as_ref()
+        .map_or_else(CanaryHealth::default, Canary::health);
+    let event_format = EventFormat::from_cfg(&cfg);
+    if let Some(socket) = &*cfg.ipc_socket {
+        let running = request.subsystems().switch("ipc");
+        let sink = IpcSink::bind(socket)?
+            .with_ttls(&event_format)
+            .with_lifecycle(*cfg.ipc_lifecycle);
+        manager.publish_to(sink.with_switch(running));
+    }
+    if let Some(len) = *cfg.event_history {
+        manager.retain_history(len);
+    }
+    if let Some(path) = &*cfg.decision_log {
+        manager.log_decisions_to(path)?;
+    }
+    if let Some(id) = &*cfg.instance_id {
+        manager.set_instance_id(id);
+    }
+    if let Some(path) = &*cfg.receipt_log {
+        manager.log_receipts_to(path, *cfg.receipt_sample)?;
+    }
+    load_denylist(&mut manager, request.denylist());
+    let heartbeat_interval = manager.heartbeat_interval();
+    let shared_manager = manager.into_arc();
+
+    *running.lock().unwrap_or_else(PoisonError::into_inner) = Some(Running {
+        manager: shared_manager.clone(),
+        drain: request.drain().clone(),
+        log_filter,
+        config_file: cli.config_file.clone(),
+        flags: cli.vars.clone(),
+        timeout: *cfg.shutdown_timeout,
+        socket: cfg.unix_socket.0.clone(),
+    });
+
+    let first_byte_timeout = *cfg.first_byte_timeout;
 
     // Server Sent Events
-    let sse_manager = shared_manager.clone();
+    let (sse_manager, sse_format) = (shared_manager.clone(), event_format.clone());
     let sse = request
         .sse_subscription()
-        .and(warp::sse())
-        .map(move |subscription: Subscription, sse: warp::sse::Sse| {
-            log::info!("Incoming SSE request for {:?}", subscription.timeline);
+        .map(move |mut subscription: Subscription| {
+            let connection = OpenConnection::new(&sse_manager, "sse");
+            subscription.connection_id = Some(connection.id());
+            let span = logger::client_span(connection.id(), "sse", &subscription.timeline);
+            let _client = span.enter();
+            tracing::info!("Incoming SSE request for {:?}", subscription.timeline);
             let mut manager = sse_manager.lock().unwrap_or_else(RedisManager::recover);
-            let (event_tx, event_rx) = mpsc::channel(10);
-            manager.subscribe(&subscription, event_tx);
-            let sse_stream = SseStream::new(subscription);
-            sse_stream.send_events(sse, event_rx)
+            let (event_tx, event_rx) = manager.channel();
+            if let Err(e) = manager.subscribe(&subscription, event_tx) {
+                tracing::warn!("Closing an SSE stream: {}", e);
+            }
+            let sse_stream = SseStream::new(subscription, &sse_format);
+            let first_byte = FirstByte::new(first_byte_timeout);
+            sse_stream.send_events(event_rx, first_byte, span.clone(), connection)
         })
-        .with(warp::reply::with::header("Connection", "keep-alive"));
+        .with(warp::reply::with::header("Connection", "keep-alive"))
+        .with(warp::reply::with::headers(request.response_headers()));
 
     // WebSocket
-    let ws_manager = shared_manager.clone();
+    let (ws_manager, ws_format) = (shared_manager.clone(), event_format.clone());
+    let multiplexer = request.multiplexer();
     let ws = request
         .ws_subscription()
         .and(warp::ws::ws2())
-        .map(move |subscription: Subscription, ws: Ws2| {
-            log::info!("Incoming websocket request for {:?}", subscription.timeline);
+        .map(move |mut subscription: Subscription, ws: Ws2| {
+            let connection = OpenConnection::new(&ws_manager, "ws");
+            subscription.connection_id = Some(connection.id());
+            let span = logger::client_span(connection.id(), "ws", &subscription.timeline);
+            let _client = span.enter();
+            tracing::info!("Incoming websocket request for {:?}", subscription.timeline);
             let mut manager = ws_manager.lock().unwrap_or_else(RedisManager::recover);
-            let (event_tx, event_rx) = mpsc::channel(10);
-            manager.subscribe(&subscription, event_tx);
+            let (event_tx, event_rx) = manager.channel();
+            if let Err(e) = manager.subscribe(&subscription, event_tx) {
+                tracing::warn!("Closing a WebSocket: {}", e);
+            }
             let token = subscription.access_token.clone().unwrap_or_default(); // token sent for security
-            let ws_stream = WsStream::new(subscription);
+            let ws_stream = WsStream::new(subscription, &ws_format);
+            let multiplex = Multiplex {
+                manager: ws_manager.clone(),
+                multiplexer: multiplexer.clone(),
+            };
 
             (
-                ws.on_upgrade(move |ws| ws_stream.send_to(ws, event_rx)),
+                ws.on_upgrade(move |ws| {
+                    let first_byte = FirstByte::new(first_byte_timeout);
+                    ws_stream.send_to(ws, event_rx, multiplex, first_byte, span, connection)
+                }),
                 token,
             )
         })
-        .map(|(reply, token)| warp::reply::with_header(reply, "sec-websocket-protocol", token));
+        .map(|(reply, token)| warp::reply::with_header(reply, "sec-websocket-protocol", token))
+        .with(warp::reply::with::headers(request.response_headers()));
+
+    // Load balancers stop sending new clients while the instance drains
+    let ready = request.readyz().map(|readiness: Readiness| {
+        warp::reply::with_status(readiness.to_string(), readiness.status())
+    });
+
+    // Fails while Postgres doesn't answer, Redis is disconnected, or the canary's markers
+    // aren't arriving
+    let health_manager = shared_manager.clone();
+    let health = request.health().map(move |checks: HealthChecks| {
+        let redis = health_manager
+            .lock()
+            .unwrap_or_else(RedisManager::recover)
+            .pubsub_health();
+        let checks = checks
+            .check("redis", redis)
+            .check("canary", canary_health.check());
+        let (status, body) = checks.reply();
+        warp::reply::with_status(body, status)
+    });
 
     #[cfg(feature = "stub_status")]
     #[rustfmt::skip]
     let status = {
         let (r1, r2, r3) = (shared_manager.clone(), shared_manager.clone(), shared_manager.clone());
-        request.health().map(|| "OK")
+        health
+            .or(ready)
             .or(request.status()
                 .map(move || r1.lock().unwrap_or_else(RedisManager::recover).count()))
             .or(request.status_backpresure()
@@ -72,7 +277,146 @@ fn main() -> Result<(), Error> {
                 .map(move || r3.lock().unwrap_or_else(RedisManager::recover).list()))
     };
     #[cfg(not(feature = "stub_status"))]
-    let status = request.health().map(|| "OK");
+    let status = health.or(ready);
+
+    // Admin API
+    #[cfg(feature = "admin")]
+    #[rustfmt::skip]
+    let admin = {
+        let (r1, r2, r3) = (shared_manager.clone(), shared_manager.clone(), shared_manager.clone());
+        let cache_request = request.clone();
+        let (switches, switch) = (request.stream_switches().clone(), request.stream_switches().clone());
+        let (subsystems, start_stop) = (request.subsystems().clone(), request.subsystems().clone());
+        let (denylist, deny, r4) = (request.denylist().clone(), request.denylist().clone(), shared_manager.clone());
+        let r5 = shared_manager.clone();
+        let (drain, drain_status) = (request.drain().clone(), request.drain().clone());
+        let (r6, r7, r8) = (shared_manager.clone(), shared_manager.clone(), shared_manager.clone());
+        let (r9, r10) = (shared_manager.clone(), shared_manager.clone());
+        let receipt_log = cfg.receipt_log.clone();
+        request.admin_subscriptions()
+            .map(move || warp::reply::json(&r1.lock().unwrap_or_else(RedisManager::recover).snapshot()))
+            .or(request.admin_resync().map(move || {
+                let mut manager = r2.lock().unwrap_or_else(RedisManager::recover);
+                manager.resync().unwrap_or_else(|e| tracing::error!("Could not resync Redis: {}", e));
+                warp::reply::json(&manager.snapshot())
+            }))
+            .unify()
+            .or(request.admin_cache_clear().map(move |scopes: CacheScopes| {
+                let mut cleared = cache_request.clear_caches(scopes);
+                if scopes.tags {
+                    let mut manager = r3.lock().unwrap_or_else(RedisManager::recover);
+                    cleared.tag_names = Some(manager.clear_tag_cache());
+                }
+                warp::reply::json(&cleared)
+            }))
+            .unify()
+            .or(request.admin_streams().map(move || warp::reply::json(&switches.disabled())))
+            .unify()
+            .or(request.admin_streams_switch().map(move |streams: Vec<String>, enabled: bool| {
+                warp::reply::json(&switch.set(&streams, enabled))
+            }))
+            .unify()
+            .or(request.admin_event_modes().map(move || {
+                warp::reply::json(&r9.lock().unwrap_or_else(RedisManager::recover).pass_through())
+            }))
+            .unify()
+            .or(request.admin_event_modes_switch().map(move |categories: Vec<String>, mode: EventModeInner| {
+                let mut manager = r10.lock().unwrap_or_else(RedisManager::recover);
+                warp::reply::json(&manager.set_event_mode(&categories, mode))
+            }))
+            .unify()
+            .or(request.admin_subsystems().map(move || warp::reply::json(&subsystems.stopped())))
+            .unify()
+            .or(request.admin_subsystems_switch().map(move |names: Vec<String>, running: bool| {
+                warp::reply::json(&start_stop.set(&names, running))
+            }))
+            .unify()
+            .or(request.admin_denylist().map(move || warp::reply::json(&denylist.summary())))
+            .unify()
+            .or(request.admin_denylist_change().map(move |entries: Vec<DenylistEntry>, banned: bool| {
+                let mut manager = r4.lock().unwrap_or_else(RedisManager::recover);
+                let members: Vec<_> = entries.iter().map(DenylistEntry::to_member).collect();
+                manager.update_denylist(&members, banned)
+                    .unwrap_or_else(|e| tracing::error!("Could not store the denylist in Redis: {}", e));
+                deny.set(&entries, banned);
+                let disconnected = match banned {
+                    true => Some(manager.disconnect(POLICY_VIOLATION, |_, token, ip| deny.is_banned(token, ip))),
+                    false => None,
+                };
+                warp::reply::json(&DenylistSummary { disconnected, ..deny.summary() })
+            }))
+            .unify()
+            .or(request.admin_disconnect().map(move |clients: Disconnect| {
+                let mut manager = r5.lock().unwrap_or_else(RedisManager::recover);
+                let disconnected = manager.disconnect(clients.code, |user_id, token, ip| clients.matches(user_id, token, ip));
+                warp::reply::json(&Disconnected { disconnected })
+            }))
+            .unify()
+            .or(request.admin_drain_status().map(move || warp::reply::json(&drain_status.status())))
+            .unify()
+            .or(request.admin_drain().map(move |draining: bool| warp::reply::json(&drain.set(draining))))
+            .unify()
+            .or(request.admin_hot().map(move || {
+                warp::reply::json(&r6.lock().unwrap_or_else(RedisManager::recover).hot_timelines())
+            }))
+            .unify()
+            .or(request.admin_info().map(move || {
+                let pool = r8.lock().unwrap_or_else(RedisManager::recover).pool_health()
+                    .unwrap_or_else(|e| { tracing::error!("Could not read the heartbeats: {}", e); None });
+                warp::reply::json(&json!({ "version": env!("CARGO_PKG_VERSION"), "pool": pool }))
+            }))
+            .unify()
+            .map(Reply::into_response)
+            .or(request.admin_metrics().map(move || {
+                r7.lock().unwrap_or_else(RedisManager::recover).metrics_report().into_response()
+            }))
+            .unify()
+            .or(request.admin_receipts().map(move |q: ReceiptQuery| match &*receipt_log {
+                Some(path) => match RedisManager::find_receipts(path, &q) {
+                    Ok(receipts) => warp::reply::json(&receipts).into_response(),
+                    Err(e) => {
+                        tracing::error!("Could not read the RECEIPT_LOG: {}", e);
+                        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                    }
+                },
+                None => warp::reply::with_status("RECEIPT_LOG is not set", StatusCode::NOT_FOUND).into_response(),
+            }))
+            .unify()
+    };
+    #[cfg(not(feature = "admin"))]
+    let admin = warp::any().and_then(|| Err::<warp::reply::Response, _>(warp::reject::not_found()));
+
+    // For Prometheus to scrape, on a port of its own
+    let metrics_manager = shared_manager.clone();
+    let metrics = request
+        .metrics()
+        .map(move || {
+            let mut manager = metrics_manager.lock().unwrap_or_else(RedisManager::recover);
+            manager.metrics_report()
+        })
+        .boxed();
+    let metrics_addr = cfg
+        .metrics_port
+        .map(|port| SocketAddr::new(*cfg.address, port));
+
+    // Publishing without Redis
+    let ingest_manager = shared_manager.clone();
+    let ingest = request.ingest().map(move |ingest: Ingest| {
+        let mut manager = ingest_manager.lock().unwrap_or_else(RedisManager::recover);
+        let (reply, code) = match manager.inject(&ingest.channel, &ingest.event_txt()) {
+            Ok(clients) => (json!({ "clients": clients }), StatusCode::ACCEPTED),
+            Err(e) => (json!({ "error": e.to_string() }), StatusCode::BAD_REQUEST),
+        };
+        warp::reply::with_status(warp::reply::json(&reply), code)
+    });
+
+    // The latest events sent on each stream
+    let history_manager = shared_manager.clone();
+    let history = request.history().map(move |req: HistoryRequest| {
+        let manager = history_manager.lock().unwrap_or_else(RedisManager::recover);
+        let events = manager.history(req.timeline, req.limit, &event_format);
+        warp::reply::json(&events.unwrap_or_default())
+    });
 
     let cors = warp::cors()
         .allow_any_origin()
@@ -80,29 +424,94 @@ fn main() -> Result<(), Error> {
         .allow_headers(cfg.cors.allowed_headers);
 
     let streaming_server = move || {
+        // Send what Redis publishes as soon as it arrives: the task is woken by input from
+        // Redis, by clients connecting, and (with `wake_at`) for heartbeats and retries, so
+        // that it never polls and an idle server doesn't wake up at all.
         let manager = shared_manager.clone();
-        let stream = Interval::new(Instant::now(), poll_freq)
-            .map_err(|e| log::error!("{}", e))
-            .for_each(move |_| {
-                match manager
-                    .lock()
-                    .unwrap_or_else(RedisManager::recover)
-                    .send_msgs()
-                {
-                    Err(e) => Ok(log::error!("{}", e)),
-                    Ok(_) => Ok(()),
+        let mut wake_at = Delay::new(Instant::now());
+        let stream = future::poll_fn(move || loop {
+            let mut manager = manager.lock().unwrap_or_else(RedisManager::recover);
+            if let Err(e) = manager.send_msgs() {
+                tracing::error!("{}", e);
+            }
+            manager.park();
+            match manager.next_due() {
+                Some(due) => wake_at.reset(due),
+                None => return Ok(Async::NotReady),
+            }
+            if let Async::NotReady = wake_at.poll().map_err(|e| tracing::error!("{}", e))? {
+                return Ok(Async::NotReady);
+            }
+        });
+
+        warp::spawn(lazy(move || stream));
+
+        #[cfg(feature = "generator")]
+        {
+            if let Some(generator) = generator {
+                let running = request.subsystems().switch("generator");
+                warp::spawn(generator.into_task(shared_manager.clone(), running));
+            }
+        }
+
+        if let Some(canary) = canary {
+            let running = request.subsystems().switch("canary");
+            canary.spawn(shared_manager.clone(), running);
+        }
+
+        // With no Redis connection, there are no subscriptions to reconcile (and no denylist
+        // shared with other instances to reload)
+        if let Some(reconcile_freq) = reconcile_freq.filter(|_| !dev_mode) {
+            let (manager, denylist) = (shared_manager.clone(), request.denylist().clone());
+            let running = request.subsystems().switch("reconciliation");
+            // On a thread of its own, since asking Redis blocks
+            thread::spawn(move || loop {
+                thread::sleep(jitter::vary(reconcile_freq));
+                if running.is_on() {
+                    reconcile(&manager, &denylist);
                 }
             });
+        }
 
-        warp::spawn(lazy(move || stream));
-        warp::serve(ws.or(sse).with(cors).or(status).recover(Handler::err))
+        // Beat only with a real Redis to beat in
+        if let Some(interval) = heartbeat_interval.filter(|_| !dev_mode) {
+            let manager = shared_manager.clone();
+            let running = request.subsystems().switch("heartbeat");
+            let heartbeat = jitter::interval(Instant::now(), interval)
+                .map_err(|e| tracing::error!("{}", e))
+                .filter(move |_| running.is_on())
+                .for_each(move |_| {
+                    let mut manager = manager.lock().unwrap_or_else(RedisManager::recover);
+                    if let Err(e) = manager.beat() {
+                        tracing::error!("Could not set the heartbeat: {}", e);
+                    }
+                    Ok(())
+                });
+            warp::spawn(lazy(move || heartbeat));
+        }
+
+        if let Some(addr) = metrics_addr {
+            tracing::info!("Serving metrics on {}", addr);
+            warp::spawn(warp::serve(metrics).bind(addr));
+        }
+
+        warp::serve(
+            // Mastodon's web UI may check the health endpoint from its own origin, too
+            ws.or(sse)
+                .or(status)
+                .with(cors)
+                .or(admin)
+                .or(ingest)
+                .or(history)
+                .recover(Handler::err),
+        )
     };
 
     if let Some(socket) = &*cfg.unix_socket {
-        log::info!("Using Unix socket {}", socket);
-        fs::remove_file(socket).unwrap_or_default();
-        let incoming = UnixListener::bind(socket)?.incoming();
-        fs::set_permissions(socket, PermissionsExt::from_mode(0o666))?;
+        tracing::info!("Using Unix socket {}", socket);
+        let mode = *cfg.socket_mode;
+        let listener = unix_socket::bind(socket, mode.0, |path| UnixListener::bind(path))?;
+        let incoming = listener.incoming();
         tokio::run(lazy(|| streaming_server().serve_incoming(incoming)));
     } else {
         let server_addr = SocketAddr::new(*cfg.address, *cfg.port);
@@ -110,3 +519,195 @@ fn main() -> Result<(), Error> {
     }
     Err(Error::Unrecoverable) // only reached if poll_broadcast encounters an unrecoverable error
 }
+
+/// Replace the `denylist` with the one kept in Redis (if it is kept there), disconnecting any
+/// clients that other instances have banned since it was last loaded.
+fn load_denylist(manager: &mut RedisManager, denylist: &Denylist) {
+    match manager.denylist_members() {
+        Ok(members) => apply_denylist(manager, members, denylist),
+        Err(e) => tracing::error!("Could not load the denylist from Redis: {}", e),
+    }
+}
+
+/// Ban the `members` of the `DENYLIST_KEY` set (if it is kept), disconnecting the clients they
+/// ban
+fn apply_denylist(manager: &mut RedisManager, members: Option<Vec<String>>, denylist: &Denylist) {
+    if let Some(members) = members {
+        denylist.load(&members);
+        manager.disconnect(POLICY_VIOLATION, |_, token, ip| {
+            denylist.is_banned(token, ip)
+        });
+    }
+}
+
+/// Repair any drift between Redis's subscriptions and the timelines with clients, and reload
+/// the denylist.  Redis is asked without holding the manager's lock, so that a slow reply
+/// doesn't hold up every client; the lock is only taken to prepare and then apply the replies.
+fn reconcile(manager: &Mutex<RedisManager>, denylist: &Denylist) {
+    let lock = || manager.lock().unwrap_or_else(RedisManager::recover);
+    let (channels, members) = {
+        let mut manager = lock();
+        (manager.reconciliation_query(), manager.denylist_query())
+    };
+    let channels = channels.map(|query| query.run()).transpose();
+    let members = members.map(|query| query.run()).transpose();
+
+    let mut manager = lock();
+    match channels {
+        Ok(Some(channels)) => {
+            if let Err(e) = manager.reconcile(channels) {
+                tracing::error!("Could not reconcile subscriptions: {}", e);
+            }
+        }
+        Ok(None) => (),
+        Err(e) => tracing::error!("Could not reconcile subscriptions: {}", e),
+    }
+    match members {
+        Ok(members) => apply_denylist(&mut manager, members, denylist),
+        Err(e) => tracing::error!("Could not load the denylist from Redis: {}", e),
+    }
+}
+
+/// What to reconfigure or shut down gracefully, once the server is set up
+struct Running {
+    manager: Arc<Mutex<RedisManager>>,
+    drain: Drain,
+    log_filter: LogFilter,
+    /// The `--config` file, which is read again on `SIGHUP`
+    config_file: Option<String>,
+    /// The variables set by flags, which still take precedence over the files once reread
+    flags: Vec<(String, String)>,
+    /// How long to wait for clients' connections to close
+    timeout: Duration,
+    /// The `SOCKET`, which is removed once the clients' connections have closed
+    socket: Option<String>,
+}
+
+/// Reload the configuration on `SIGHUP`, and exit on `SIGTERM` or `SIGINT`, with what is put
+/// in the returned `Running` (once anything is).
+///
+/// On `SIGHUP`, the env file is read again and the settings that can change without dropping
+/// clients are applied (see `reload`).  On `SIGTERM` or `SIGINT`, new clients are refused,
+/// every client is sent a last event and closed, and every Redis channel is unsubscribed from
+/// (handing off the subscribed keys, so that a rolling restart doesn't clear keys other
+/// instances need).  Flodgatt exits once the clients' connections have closed, or after the
+/// `timeout`.
+///
+/// The signals are blocked and then received by a thread of their own with `sigwait`, so this
+/// must be called before any other thread starts: threads inherit the blocked signals, and
+/// one that didn't would be killed by them.
+fn on_signals() -> Arc<Mutex<Option<Running>>> {
+    // SAFETY: `signals` is initialized by `sigemptyset` before it is used
+    let signals = unsafe {
+        let mut signals = mem::zeroed();
+        libc::sigemptyset(&mut signals);
+        libc::sigaddset(&mut signals, libc::SIGTERM);
+        libc::sigaddset(&mut signals, libc::SIGINT);
+        libc::sigaddset(&mut signals, libc::SIGHUP);
+        libc::pthread_sigmask(libc::SIG_BLOCK, &signals, ptr::null_mut());
+        signals
+    };
+    let running = Arc::new(Mutex::new(None));
+    let set_up = running.clone();
+    thread::spawn(move || loop {
+        let mut signal = 0;
+        // SAFETY: `signals` is a valid signal set
+        unsafe { libc::sigwait(&signals, &mut signal) };
+        let running = set_up.lock().unwrap_or_else(PoisonError::into_inner);
+        if signal == libc::SIGHUP {
+            match &*running {
+                Some(running) => reload(running),
+                None => tracing::warn!("Received SIGHUP before starting; not reloading"),
+            }
+            continue;
+        }
+        tracing::warn!("Received signal {}; shutting down", signal);
+        if let Some(running) = &*running {
+            running.drain.shut_down();
+            let mut manager = running.manager.lock().unwrap_or_else(RedisManager::recover);
+            match manager.shut_down() {
+                Ok(clients) => tracing::info!("Closing the connections of {} clients", clients),
+                Err(e) => tracing::error!("Could not unsubscribe from Redis: {}", e),
+            }
+            drop(manager);
+            let deadline = Instant::now() + running.timeout;
+            while OpenConnection::count() > 0 && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(50));
+            }
+            if OpenConnection::count() > 0 {
+                tracing::warn!("Exiting with {} connections open", OpenConnection::count());
+            }
+            if let Some(socket) = &running.socket {
+                std::fs::remove_file(socket).unwrap_or_default();
+            }
+        }
+        logger::flush();
+        process::exit(0);
+    });
+    running
+}
+
+/// Apply the configuration in the env file, the `--config` file, and the environment that can
+/// change while clients are connected: `RUST_LOG`, and the client and channel limits that
+/// `RedisManager::reconfigure` applies.  If any of it is invalid, none of it is applied.  The
+/// rest (such as the ports, the CORS headers, and the Redis connection) takes a restart.
+fn reload(running: &Running) {
+    tracing::info!("Received SIGHUP; reloading the configuration");
+    let mut vars = match config::reread_dotenv(running.config_file.as_deref()) {
+        Ok(vars) => vars,
+        Err(e) => {
+            tracing::error!("Could not reload the configuration: {}", e);
+            return;
+        }
+    };
+    vars.extend(running.flags.iter().cloned());
+    let log_level = vars.get("RUST_LOG").cloned().unwrap_or_default();
+    let redis_cfg = match config::from_env(vars) {
+        Ok((_, redis_cfg, _)) => redis_cfg,
+        Err(e) => {
+            tracing::error!("Could not reload the configuration: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = running.log_filter.set(&log_level) {
+        tracing::error!("Could not reload the configuration: {}", e);
+        return;
+    }
+    running
+        .manager
+        .lock()
+        .unwrap_or_else(RedisManager::recover)
+        .reconfigure(&redis_cfg);
+    tracing::info!("Reloaded RUST_LOG, the client and channel limits, and the event modes");
+}
+
+/// Retry `connect` with exponential backoff until it succeeds or `wait` has elapsed, so that
+/// Flodgatt can start before the services it depends on.
+fn with_retries<T, E: fmt::Display>(
+    service: &str,
+    wait: Option<Duration>,
+    mut connect: impl FnMut() -> Result<T, E>,
+) -> Result<T, E> {
+    const MAX_BACKOFF: Duration = Duration::from_secs(30);
+    let deadline = wait.map(|wait| Instant::now() + wait);
+    let mut backoff = Duration::from_millis(500);
+    loop {
+        let result = connect();
+        let remaining =
+            deadline.and_then(|deadline| deadline.checked_duration_since(Instant::now()));
+        match (result, remaining) {
+            (Err(e), Some(remaining)) => {
+                let delay = backoff.min(remaining);
+                tracing::warn!(
+                    "Could not connect to {}; retrying in {:?}: {}",
+                    service,
+                    delay,
+                    e
+                );
+                thread::sleep(delay);
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+            (result, _) => break result,
+        }
+    }
+}
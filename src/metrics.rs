@@ -0,0 +1,364 @@
+//! Counters that describe what Flodgatt has been doing since it started
+//!
+//! Each counter (and histogram) is a process-wide static, so any module can record an event
+//! without needing a handle threaded through to it.
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+#[cfg(test)]
+mod test;
+
+/// The upper bounds of each histogram's buckets, in seconds
+const BOUNDS: [f64; 12] = [
+    0.000_5, 0.001, 0.002_5, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+];
+
+/// A monotonically increasing count of some event
+pub struct Counter {
+    pub name: &'static str,
+    pub help: &'static str,
+    count: AtomicU64,
+}
+
+impl Counter {
+    const fn new(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            count: AtomicU64::new(0),
+        }
+    }
+
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.count.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+pub static SUBSCRIPTION_DRIFT: Counter = Counter::new(
+    "flodgatt_subscription_drift_total",
+    "Redis channels found out of sync with the subscription registry",
+);
+pub static RECONCILIATIONS: Counter = Counter::new(
+    "flodgatt_reconciliations_total",
+    "Times the subscription registry has been compared against Redis",
+);
+pub static SUBSCRIBED_KEY_FAILURES: Counter = Counter::new(
+    "flodgatt_subscribed_key_failures_total",
+    "Failed attempts to set the keys that tell Mastodon which channels have subscribers",
+);
+
+pub static DISPATCH_YIELDS: Counter = Counter::new(
+    "flodgatt_dispatch_yields_total",
+    "Polls that stopped sending messages from Redis because they used up their budget",
+);
+
+pub static GEO_TAGGED: Counter = Counter::new(
+    "flodgatt_geo_tagged_total",
+    "Connections accepted from a country or AS that the GEO_POLICY tags",
+);
+pub static GEO_THROTTLED: Counter = Counter::new(
+    "flodgatt_geo_throttled_total",
+    "Connections turned away because their country or AS exceeded the GEO_THROTTLE",
+);
+pub static GEO_REJECTED: Counter = Counter::new(
+    "flodgatt_geo_rejected_total",
+    "Connections turned away because the GEO_POLICY rejects their country or AS",
+);
+
+pub static REDIS_WRITE_STALLS: Counter = Counter::new(
+    "flodgatt_redis_write_stalls_total",
+    "Times a write to the Redis pubsub connection found its buffer full and was queued",
+);
+pub static REDIS_WRITE_OVERFLOWS: Counter = Counter::new(
+    "flodgatt_redis_write_overflows_total",
+    "Times the commands queued for the Redis pubsub connection outgrew their bound and it was reconnected",
+);
+pub static SUBSCRIBED_KEY_RETRIES_DROPPED: Counter = Counter::new(
+    "flodgatt_subscribed_key_retries_dropped_total",
+    "Failed writes of subscribed keys given up on because too many were waiting to be retried",
+);
+pub static REDIS_REDIRECTS: Counter = Counter::new(
+    "flodgatt_redis_redirects_total",
+    "Writes to Redis refused by a replica (READONLY) or another node (MOVED)",
+);
+pub static REDIS_INPUT_OVERFLOWS: Counter = Counter::new(
+    "flodgatt_redis_input_overflows_total",
+    "Messages from Redis larger than REDIS_MAX_INPUT_BUFFER, dropped along with the connection",
+);
+pub static REDIS_MESSAGES: Counter = Counter::new(
+    "flodgatt_redis_messages_total",
+    "Events read from Redis and sent on to the clients of their timeline",
+);
+pub static REDIS_PARSE_ERRORS: Counter = Counter::new(
+    "flodgatt_redis_parse_errors_total",
+    "Messages from Redis that could not be parsed (as a reply, a timeline, or an event)",
+);
+pub static REDIS_RESYNCS: Counter = Counter::new(
+    "flodgatt_redis_resyncs_total",
+    "Replies from Redis that could not be parsed, skipped up to where the next reply starts",
+);
+pub static REDIS_RESYNC_BYTES_SKIPPED: Counter = Counter::new(
+    "flodgatt_redis_resync_bytes_skipped_total",
+    "Bytes of input from Redis skipped because they could not be parsed",
+);
+pub static REDIS_PING_TIMEOUTS: Counter = Counter::new(
+    "flodgatt_redis_ping_timeouts_total",
+    "Idle Redis pubsub connections given up on because Redis didn't answer a PING in time",
+);
+pub static REDIS_RECONNECTS: Counter = Counter::new(
+    "flodgatt_redis_reconnects_total",
+    "Failed connections to Redis replaced with new ones (after which every channel is subscribed to again)",
+);
+pub static REDIS_CHANNEL_LIMIT_HITS: Counter = Counter::new(
+    "flodgatt_redis_channel_limit_hits_total",
+    "New Redis channels that would have exceeded REDIS_MAX_CHANNELS (or the limit for their kind)",
+);
+pub static REDIS_CHANNELS_EVICTED: Counter = Counter::new(
+    "flodgatt_redis_channels_evicted_total",
+    "Idle channels with only anonymous clients dropped to make room under a channel limit",
+);
+
+pub static INVALID_UTF8_MESSAGES: Counter = Counter::new(
+    "flodgatt_invalid_utf8_messages_total",
+    "Messages from Redis whose bytes were not valid UTF-8 (whatever INVALID_UTF8 did with them)",
+);
+
+pub static CLIENT_EVENTS_SENT: Counter = Counter::new(
+    "flodgatt_client_events_sent_total",
+    "Events from Redis queued to be sent to a client",
+);
+pub static CLIENT_EVENTS_DROPPED: Counter = Counter::new(
+    "flodgatt_client_events_dropped_total",
+    "Events not sent to a client because its channel was full",
+);
+pub static SLOW_CLIENT_DISCONNECTS: Counter = Counter::new(
+    "flodgatt_slow_client_disconnects_total",
+    "Clients disconnected because their channel was full (with CLIENT_OVERFLOW=disconnect)",
+);
+
+pub static CLIENT_RECONNECTS: Counter = Counter::new(
+    "flodgatt_client_reconnects_total",
+    "Connections whose client_uuid was seen on an earlier connection",
+);
+pub static CLIENT_RAPID_RECONNECTS: Counter = Counter::new(
+    "flodgatt_client_rapid_reconnects_total",
+    "Connections whose client_uuid was seen on another connection within the last 10 seconds",
+);
+pub static CLIENT_FIRST_BYTES_LATE: Counter = Counter::new(
+    "flodgatt_client_first_bytes_late_total",
+    "Connections sent their first byte more than FIRST_BYTE_TIMEOUT after they were accepted",
+);
+
+pub static CANARY_FAILURES: Counter = Counter::new(
+    "flodgatt_canary_failures_total",
+    "Canary markers that did not reach the canary's own client within CANARY_DEADLINE",
+);
+
+pub static TAG_CACHE_HITS: Counter = Counter::new(
+    "flodgatt_tag_cache_hits_total",
+    "Hashtag ids (or names) found in the cache when reading (or naming) a Redis channel",
+);
+pub static TAG_CACHE_MISSES: Counter = Counter::new(
+    "flodgatt_tag_cache_misses_total",
+    "Hashtag ids (or names) missing from the cache, which are looked up in Postgres instead",
+);
+pub static TAG_CACHE_EVICTIONS: Counter = Counter::new(
+    "flodgatt_tag_cache_evictions_total",
+    "Hashtags dropped from a full cache (see TAG_CACHE_SIZE) to make room for another",
+);
+
+/// Every counter Flodgatt keeps
+pub static ALL: [&Counter; 31] = [
+    &SUBSCRIPTION_DRIFT,
+    &RECONCILIATIONS,
+    &SUBSCRIBED_KEY_FAILURES,
+    &DISPATCH_YIELDS,
+    &GEO_TAGGED,
+    &GEO_THROTTLED,
+    &GEO_REJECTED,
+    &REDIS_WRITE_STALLS,
+    &REDIS_WRITE_OVERFLOWS,
+    &SUBSCRIBED_KEY_RETRIES_DROPPED,
+    &REDIS_REDIRECTS,
+    &REDIS_INPUT_OVERFLOWS,
+    &REDIS_MESSAGES,
+    &REDIS_PARSE_ERRORS,
+    &REDIS_RESYNCS,
+    &REDIS_RESYNC_BYTES_SKIPPED,
+    &REDIS_PING_TIMEOUTS,
+    &REDIS_RECONNECTS,
+    &REDIS_CHANNEL_LIMIT_HITS,
+    &REDIS_CHANNELS_EVICTED,
+    &INVALID_UTF8_MESSAGES,
+    &CLIENT_EVENTS_SENT,
+    &CLIENT_EVENTS_DROPPED,
+    &SLOW_CLIENT_DISCONNECTS,
+    &CLIENT_RECONNECTS,
+    &CLIENT_RAPID_RECONNECTS,
+    &CLIENT_FIRST_BYTES_LATE,
+    &CANARY_FAILURES,
+    &TAG_CACHE_HITS,
+    &TAG_CACHE_MISSES,
+    &TAG_CACHE_EVICTIONS,
+];
+
+/// How long some operation takes, counted in buckets (as a Prometheus histogram is)
+pub struct Histogram {
+    pub name: &'static str,
+    pub help: &'static str,
+    /// How many observations were no longer than each of the `BOUNDS`
+    buckets: [AtomicU64; BOUNDS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    const fn new(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            buckets: [
+                AtomicU64::new(0),
+                AtomicU64::new(0),
+                AtomicU64::new(0),
+                AtomicU64::new(0),
+                AtomicU64::new(0),
+                AtomicU64::new(0),
+                AtomicU64::new(0),
+                AtomicU64::new(0),
+                AtomicU64::new(0),
+                AtomicU64::new(0),
+                AtomicU64::new(0),
+                AtomicU64::new(0),
+            ],
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        for (bound, bucket) in BOUNDS.iter().zip(&self.buckets) {
+            if secs <= *bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        let micros = u64::try_from(duration.as_micros()).unwrap_or(u64::MAX);
+        self.sum_micros.fetch_add(micros, Ordering::Relaxed);
+    }
+
+    /// The `_bucket`, `_sum`, and `_count` lines for this histogram
+    #[allow(clippy::cast_precision_loss)]
+    fn report(&self) -> String {
+        let count = self.count.load(Ordering::Relaxed);
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let mut report = header(self.name, self.help, "histogram");
+        report += &BOUNDS
+            .iter()
+            .zip(&self.buckets)
+            .map(|(bound, bucket)| {
+                let n = bucket.load(Ordering::Relaxed);
+                format!("{}_bucket{{le=\"{}\"}} {}\n", self.name, bound, n)
+            })
+            .collect::<String>();
+        report.push_str(&format!("{}_bucket{{le=\"+Inf\"}} {}\n", self.name, count));
+        report.push_str(&format!(
+            "{}_sum {}\n{}_count {}\n",
+            self.name, sum, self.name, count
+        ));
+        report
+    }
+}
+
+pub static REDIS_PRIMARY_WRITE: Histogram = Histogram::new(
+    "flodgatt_redis_primary_write_seconds",
+    "Time from queueing a SUBSCRIBE or UNSUBSCRIBE command until it is written to the Redis pubsub connection",
+);
+pub static REDIS_SECONDARY_ROUND_TRIP: Histogram = Histogram::new(
+    "flodgatt_redis_secondary_round_trip_seconds",
+    "Time from writing a command to Redis's secondary connection to reading Redis's reply",
+);
+pub static REDIS_SUBSCRIBE_CONFIRMATION: Histogram = Histogram::new(
+    "flodgatt_redis_subscribe_confirmation_seconds",
+    "Time from sending SUBSCRIBE to Redis to receiving its confirmation",
+);
+pub static CANARY_LATENCY: Histogram = Histogram::new(
+    "flodgatt_canary_latency_seconds",
+    "Time from publishing a canary marker to reading it back from the server's own SSE stream",
+);
+pub static CLIENT_FIRST_BYTE: Histogram = Histogram::new(
+    "flodgatt_client_first_byte_seconds",
+    "Time from accepting a connection (upgrading it, for a WebSocket) to sending its first byte",
+);
+
+pub static PG_SELECT_USER: Histogram = Histogram::new(
+    "flodgatt_postgres_select_user_seconds",
+    "Time Postgres takes to look up the user an access token belongs to",
+);
+pub static PG_SELECT_HASHTAG_ID: Histogram = Histogram::new(
+    "flodgatt_postgres_select_hashtag_id_seconds",
+    "Time Postgres takes to look up a hashtag's id",
+);
+pub static PG_SELECT_HASHTAG_NAME: Histogram = Histogram::new(
+    "flodgatt_postgres_select_hashtag_name_seconds",
+    "Time Postgres takes to look up the name of a hashtag with a given id",
+);
+pub static PG_SELECT_BLOCKED_USERS: Histogram = Histogram::new(
+    "flodgatt_postgres_select_blocked_users_seconds",
+    "Time Postgres takes to look up the accounts a user has blocked or muted",
+);
+pub static PG_SELECT_BLOCKING_USERS: Histogram = Histogram::new(
+    "flodgatt_postgres_select_blocking_users_seconds",
+    "Time Postgres takes to look up the accounts that have blocked a user",
+);
+pub static PG_SELECT_BLOCKED_DOMAINS: Histogram = Histogram::new(
+    "flodgatt_postgres_select_blocked_domains_seconds",
+    "Time Postgres takes to look up the domains a user has blocked",
+);
+pub static PG_SELECT_LIST_OWNER: Histogram = Histogram::new(
+    "flodgatt_postgres_select_list_owner_seconds",
+    "Time Postgres takes to look up who owns a list",
+);
+
+/// Every histogram Flodgatt keeps
+pub static ALL_HISTOGRAMS: [&Histogram; 12] = [
+    &REDIS_PRIMARY_WRITE,
+    &REDIS_SECONDARY_ROUND_TRIP,
+    &REDIS_SUBSCRIBE_CONFIRMATION,
+    &CANARY_LATENCY,
+    &CLIENT_FIRST_BYTE,
+    &PG_SELECT_USER,
+    &PG_SELECT_HASHTAG_ID,
+    &PG_SELECT_HASHTAG_NAME,
+    &PG_SELECT_BLOCKED_USERS,
+    &PG_SELECT_BLOCKING_USERS,
+    &PG_SELECT_BLOCKED_DOMAINS,
+    &PG_SELECT_LIST_OWNER,
+];
+
+/// The current value of every counter (and every histogram bucket), one `name value` pair per
+/// line, in Prometheus's text format
+pub fn report() -> String {
+    let counters = ALL.iter().map(|counter| {
+        let header = header(counter.name, counter.help, "counter");
+        format!("{}{} {}\n", header, counter.name, counter.get())
+    });
+    counters
+        .chain(ALL_HISTOGRAMS.iter().map(|histogram| histogram.report()))
+        .collect()
+}
+
+/// The `# HELP` and `# TYPE` lines that introduce a metric
+pub fn header(name: &str, help: &str, kind: &str) -> String {
+    format!("# HELP {} {}\n# TYPE {} {}\n", name, help, name, kind)
+}
@@ -0,0 +1,30 @@
+use super::*;
+
+#[test]
+fn histograms_count_observations_in_every_bucket_they_fit() {
+    let histogram = Histogram::new("test_seconds", "A histogram for testing");
+    for &millis in &[0, 3, 3, 40, 5000] {
+        histogram.observe(Duration::from_millis(millis));
+    }
+
+    let report = histogram.report();
+    for line in &[
+        "# HELP test_seconds A histogram for testing",
+        "# TYPE test_seconds histogram",
+        r#"test_seconds_bucket{le="0.0005"} 1"#,
+        r#"test_seconds_bucket{le="0.0025"} 1"#,
+        r#"test_seconds_bucket{le="0.005"} 3"#,
+        r#"test_seconds_bucket{le="0.05"} 4"#,
+        r#"test_seconds_bucket{le="2.5"} 4"#,
+        r#"test_seconds_bucket{le="+Inf"} 5"#,
+        "test_seconds_sum 5.046",
+        "test_seconds_count 5",
+    ] {
+        assert!(
+            report.lines().any(|l| l == *line),
+            "{} in:\n{}",
+            line,
+            report
+        );
+    }
+}
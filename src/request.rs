@@ -1,13 +1,51 @@
 //! Parse the client request and return a Subscription
+mod affinity;
+mod cache;
+mod composite;
+mod denylist;
+mod dev_users;
+mod disconnect;
+mod drain;
+mod event_types;
+mod geo;
+mod health;
+mod history;
+mod ingest;
+mod multiplex;
 mod postgres;
 mod query;
+mod receipts;
+mod reconnects;
+mod shard;
+mod tags;
 mod timeline;
 
 mod err;
 mod subscription;
+mod subsystems;
+mod switches;
 
+pub use affinity::Affinity;
+pub use cache::{CacheCleared, CacheScopes};
+pub use composite::{Composite, Composites};
+pub use denylist::{Denylist, DenylistSummary, Entry as DenylistEntry};
+pub use disconnect::{
+    Closing, Disconnect, Disconnected, GOING_AWAY, POLICY_VIOLATION, TRY_AGAIN_LATER,
+};
+pub use drain::{Drain, DrainStatus, Readiness};
 pub use err::{Error, Timeline as TimelineErr};
+pub use event_types::EventTypes;
+pub use geo::GeoPolicy;
+pub use health::HealthChecks;
+pub use history::HistoryRequest;
+pub use ingest::Ingest;
+pub use multiplex::{Frame, FrameKind, Multiplexer, Refusal};
+pub use receipts::ReceiptQuery;
+pub use shard::Shards;
 pub use subscription::{Blocks, Subscription};
+pub use subsystems::{StoppedSubsystems, Subsystems, Switch, SUBSYSTEMS};
+pub use switches::{DisabledStreams, StreamSwitches};
+pub use tags::TagRegistry;
 pub use timeline::Timeline;
 
 #[cfg(feature = "bench")]
@@ -16,22 +54,59 @@ pub use timeline::{Content, Reach, Stream};
 #[cfg(not(feature = "bench"))]
 use timeline::{Content, Reach, Stream};
 
+use self::dev_users::DevUsers;
 pub use self::postgres::PgPool;
 use self::query::Query;
-use crate::config::Postgres;
+use self::reconnects::Reconnects;
+use crate::config::{Deployment, EventModeInner, Postgres};
+use std::net::{IpAddr, SocketAddr};
 use warp::filters::BoxedFilter;
+use warp::http::header::{HeaderMap, HeaderValue, SERVER};
 use warp::http::StatusCode;
 use warp::path;
 use warp::reply;
-use warp::{Filter, Rejection};
+use warp::{Filter, Rejection, Reply};
 
+#[cfg(test)]
+mod composite_test;
+#[cfg(test)]
+mod denylist_test;
+#[cfg(test)]
+mod disconnect_test;
+#[cfg(test)]
+mod drain_test;
+#[cfg(test)]
+mod event_types_test;
+#[cfg(test)]
+mod geo_test;
+#[cfg(test)]
+mod health_test;
+#[cfg(test)]
+mod multiplex_test;
+#[cfg(test)]
+mod query_test;
+#[cfg(test)]
+mod reconnects_test;
+#[cfg(test)]
+mod shard_test;
 #[cfg(test)]
 mod sse_test;
 #[cfg(test)]
+mod subsystems_test;
+#[cfg(test)]
+mod switches_test;
+#[cfg(test)]
+mod tags_test;
+#[cfg(test)]
+mod timeline_test;
+#[cfg(test)]
 mod ws_test;
 
 type Result<T> = std::result::Result<T, err::Error>;
 
+/// The largest request body `POST /api/v1/streaming/ingest` accepts
+const MAX_INGEST_BYTES: u64 = 1024 * 1024;
+
 /// Helper macro to match on the first of any of the provided filters
 macro_rules! any_of {
     ($filter:expr, $($other_filter:expr),*) => {
@@ -46,14 +121,18 @@ macro_rules! parse_sse_query {
             .and(query::Media::to_filter())
             .and(query::Hashtag::to_filter())
             .and(query::List::to_filter())
-            .map(|auth: query::Auth, media: query::Media, hashtag: query::Hashtag, list: query::List| {
-                Query {
+            .and(query::ClientUuid::to_filter())
+            .and(query::event_types())
+            .and_then(|auth: query::Auth, media: query::Media, hashtag: query::Hashtag, list: query::List, client: query::ClientUuid, event_types: EventTypes| {
+                Ok::<_, Rejection>(Query {
                     access_token: auth.access_token,
                     stream: $endpoint.to_string(),
                     media: media.is_truthy(),
-                    hashtag: hashtag.tag,
-                    list: list.list,
-                }
+                    hashtag: hashtag.name().map_err(warp::reject::custom)?,
+                    list: list.id().map_err(warp::reject::custom)?,
+                    client_uuid: client.client_uuid,
+                    event_types,
+                })
             },
         )
         .boxed()
@@ -63,17 +142,95 @@ macro_rules! parse_sse_query {
 #[derive(Clone)]
 pub struct Handler {
     pg_conn: PgPool,
+    admin_token: Option<String>,
+    shards: Shards,
+    affinity: Affinity,
+    geo: GeoPolicy,
+    reconnects: Reconnects,
+    identity_headers: bool,
+    ws_max_subscriptions: Option<usize>,
+    switches: StreamSwitches,
+    composites: Composites,
+    subsystems: Subsystems,
+    denylist: Denylist,
+    drain: Drain,
+    tags: TagRegistry,
+    /// Whether `EVENT_HISTORY` is set, which enables `GET /api/v1/streaming/history`
+    history: bool,
+    metrics_path: String,
 }
 
 impl Handler {
-    pub fn new(postgres_cfg: &Postgres, whitelist_mode: bool) -> Result<Self> {
+    pub fn new(postgres_cfg: &Postgres, cfg: &Deployment) -> Result<Self> {
+        let pg_conn = PgPool::new(postgres_cfg, *cfg.whitelist_mode)?;
+        let switches = StreamSwitches::from_cfg(cfg);
         Ok(Self {
-            pg_conn: PgPool::new(postgres_cfg, whitelist_mode)?,
+            tags: TagRegistry::default().with_postgres(pg_conn.clone()),
+            pg_conn,
+            admin_token: cfg.admin_token.clone().0,
+            shards: Shards::from_cfg(cfg),
+            affinity: Affinity::from_cfg(cfg),
+            geo: GeoPolicy::default(),
+            reconnects: Reconnects::new(),
+            identity_headers: *cfg.identity_headers,
+            ws_max_subscriptions: *cfg.ws_max_subscriptions,
+            composites: Composites::from_cfg(cfg, switches.clone()),
+            switches,
+            subsystems: Subsystems::default(),
+            denylist: Denylist::default(),
+            drain: Drain::default(),
+            history: cfg.event_history.is_some(),
+            metrics_path: cfg.metrics_path.clone().0,
         })
     }
 
+    /// A `Handler` for `--dev` mode, which accepts the `DEV_TOKENS` instead of querying Postgres
+    pub fn dev(cfg: &Deployment) -> Self {
+        let pg_conn = PgPool::dev(DevUsers::from_cfg(cfg), *cfg.whitelist_mode);
+        let switches = StreamSwitches::from_cfg(cfg);
+        Self {
+            tags: TagRegistry::default().with_postgres(pg_conn.clone()),
+            pg_conn,
+            admin_token: cfg.admin_token.clone().0,
+            shards: Shards::from_cfg(cfg),
+            affinity: Affinity::from_cfg(cfg),
+            geo: GeoPolicy::default(),
+            reconnects: Reconnects::new(),
+            identity_headers: *cfg.identity_headers,
+            ws_max_subscriptions: *cfg.ws_max_subscriptions,
+            composites: Composites::from_cfg(cfg, switches.clone()),
+            switches,
+            subsystems: Subsystems::default(),
+            denylist: Denylist::default(),
+            drain: Drain::default(),
+            history: cfg.event_history.is_some(),
+            metrics_path: cfg.metrics_path.clone().0,
+        }
+    }
+
+    /// Check new connections against `geo` before authenticating them
+    pub fn with_geo_policy(mut self, geo: GeoPolicy) -> Self {
+        self.geo = geo;
+        self
+    }
+
+    /// Resolve hashtags through `tags` (which the `RedisManager` should share), looking up
+    /// those that aren't cached in Postgres
+    pub fn with_tags(mut self, tags: TagRegistry) -> Self {
+        self.tags = tags.with_postgres(self.pg_conn.clone());
+        self
+    }
+
+    /// The hashtags this handler has resolved
+    pub fn tags(&self) -> &TagRegistry {
+        &self.tags
+    }
+
     pub fn sse_subscription(&self) -> BoxedFilter<(Subscription,)> {
-        let pg_conn = self.pg_conn.clone();
+        let (pg_conn, shards) = (self.pg_conn.clone(), self.shards.clone());
+        let (reconnects, switches) = (self.reconnects.clone(), self.switches.clone());
+        let (denylist, tags) = (self.denylist.clone(), self.tags.clone());
+        let drain = self.drain.clone();
         any_of!(
             parse_sse_query!( path => "api" / "v1" / "streaming" / "user" / "notification"
                               endpoint => "user:notification" ),
@@ -92,25 +249,111 @@ impl Handler {
             parse_sse_query!( path => "api" / "v1" / "streaming" / "list"
                               endpoint => "list")
         )
+        .and(query::unambiguous())
+        .and(self.affinity_route())
+        .and(self.geo_policy())
         // because SSE requests place their `access_token` in the header instead of in a query
         // parameter, we need to update our Query if the header has a token
         .and(query::OptionalAccessToken::from_sse_header())
         .and_then(Query::update_access_token)
-        .and_then(move |q| Subscription::query_postgres(q, pg_conn.clone()))
+        .and_then(move |q| switches.admit(q))
+        .and_then(move |q| drain.admit(q))
+        .and(client_ip())
+        .and_then(move |q: Query, ip: Option<IpAddr>| denylist.admit(q, ip))
+        .and_then(move |q| Subscription::query_postgres(q, pg_conn.clone(), &tags))
+        .and(client_ip())
+        .map(|s: Subscription, ip: Option<IpAddr>| Subscription { ip, ..s })
+        .and(path::full())
+        .and(raw_query())
+        .and_then(
+            move |s: Subscription, path: path::FullPath, query: String| {
+                shards.route(s, &path, &query)
+            },
+        )
+        .map(move |s: Subscription| reconnects.observe(s))
         .boxed()
     }
 
     pub fn ws_subscription(&self) -> BoxedFilter<(Subscription,)> {
-        let pg_conn = self.pg_conn.clone();
+        let (pg_conn, shards) = (self.pg_conn.clone(), self.shards.clone());
+        let (reconnects, switches) = (self.reconnects.clone(), self.switches.clone());
+        let (denylist, tags) = (self.denylist.clone(), self.tags.clone());
+        let (drain, composites) = (self.drain.clone(), self.composites.clone());
         parse_ws_query()
+            .and(self.affinity_route())
+            .and(self.geo_policy())
             .and(query::OptionalAccessToken::from_ws_header())
             .and_then(Query::update_access_token)
-            .and_then(move |q| Subscription::query_postgres(q, pg_conn.clone()))
+            .and_then(move |q| switches.admit(q))
+            .and_then(move |q| drain.admit(q))
+            .and(client_ip())
+            .and_then(move |q: Query, ip: Option<IpAddr>| denylist.admit(q, ip))
+            .and_then(move |q| composites.subscription(q, pg_conn.clone(), &tags))
+            .and(client_ip())
+            .map(|s: Subscription, ip: Option<IpAddr>| Subscription { ip, ..s })
+            .and(path::full())
+            .and(raw_query())
+            .and_then(
+                move |s: Subscription, path: path::FullPath, query: String| {
+                    shards.route(s, &path, &query)
+                },
+            )
+            .map(move |s: Subscription| reconnects.observe(s))
             .boxed()
     }
 
-    pub fn health(&self) -> BoxedFilter<()> {
-        warp::path!("api" / "v1" / "streaming" / "health").boxed()
+    /// Authorizes the streams that WebSocket clients add after connecting
+    pub fn multiplexer(&self) -> Multiplexer {
+        let (pg_conn, tags) = (self.pg_conn.clone(), self.tags.clone());
+        let (switches, composites) = (self.switches.clone(), self.composites.clone());
+        Multiplexer::new(pg_conn, tags, switches, composites, self.ws_max_subscriptions)
+    }
+
+    /// Headers for streaming responses: routing hints for load balancers that keep clients on
+    /// one instance and (unless `IDENTITY_HEADERS` is off) `Server` and `X-Served-By`, so that
+    /// operators can tell which process served a connection.
+    pub fn response_headers(&self) -> HeaderMap {
+        let mut headers = self.affinity.headers();
+        if self.identity_headers {
+            let server = concat!("flodgatt/", env!("CARGO_PKG_VERSION"));
+            headers.insert(SERVER, HeaderValue::from_static(server));
+            if let Some(Ok(id)) = self.affinity.instance_id().map(HeaderValue::from_str) {
+                headers.insert("x-served-by", id);
+            }
+        }
+        headers
+    }
+
+    /// `GET /api/v1/streaming/health`, which yields the outcome of a query sent to Postgres
+    /// (to which the caller adds the checks of the dependencies the `Handler` doesn't hold)
+    pub fn health(&self) -> BoxedFilter<(HealthChecks,)> {
+        let pg_conn = self.pg_conn.clone();
+        warp::path!("api" / "v1" / "streaming" / "health")
+            .map(move || HealthChecks::default().check("postgres", pg_conn.check()))
+            .boxed()
+    }
+
+    /// `GET /api/v1/streaming/readyz`, which yields whether the instance is draining
+    pub fn readyz(&self) -> BoxedFilter<(Readiness,)> {
+        let drain = self.drain.clone();
+        warp::path!("api" / "v1" / "streaming" / "readyz")
+            .and(path::end())
+            .map(move || drain.readiness())
+            .boxed()
+    }
+
+    /// `GET` the `METRICS_PATH`, for Prometheus (on the `METRICS_PORT`)
+    pub fn metrics(&self) -> BoxedFilter<()> {
+        let metrics_path = self.metrics_path.clone();
+        let running = self.subsystems.switch("metrics");
+        path::full()
+            .and_then(move |path: path::FullPath| match path.as_str() {
+                path if path == metrics_path && running.is_on() => Ok(()),
+                _ => Err(warp::reject::not_found()),
+            })
+            .untuple_one()
+            .and(warp::get2())
+            .boxed()
     }
 
     pub fn status(&self) -> BoxedFilter<()> {
@@ -127,8 +370,439 @@ impl Handler {
         warp::path!("api" / "v1" / "streaming" / "status" / "backpresure").boxed()
     }
 
+    /// `GET /api/v1/streaming/admin/subscriptions`
+    pub fn admin_subscriptions(&self) -> BoxedFilter<()> {
+        self.admin()
+            .and(warp::path("subscriptions"))
+            .and(path::end())
+            .and(warp::get2())
+            .boxed()
+    }
+
+    /// `POST /api/v1/streaming/admin/subscriptions/resync`
+    pub fn admin_resync(&self) -> BoxedFilter<()> {
+        self.admin()
+            .and(path!("subscriptions" / "resync"))
+            .and(path::end())
+            .and(warp::post2())
+            .boxed()
+    }
+
+    /// `GET /api/v1/streaming/admin/hot`, the timelines whose events reached the most clients
+    /// in the latest minute
+    pub fn admin_hot(&self) -> BoxedFilter<()> {
+        self.admin()
+            .and(warp::path("hot"))
+            .and(path::end())
+            .and(warp::get2())
+            .boxed()
+    }
+
+    /// `GET /api/v1/streaming/admin/info`, this server's version and the heartbeats of every
+    /// instance (see `HEARTBEAT_KEY`)
+    pub fn admin_info(&self) -> BoxedFilter<()> {
+        self.admin()
+            .and(warp::path("info"))
+            .and(path::end())
+            .and(warp::get2())
+            .boxed()
+    }
+
+    /// `GET /api/v1/streaming/admin/metrics`
+    pub fn admin_metrics(&self) -> BoxedFilter<()> {
+        self.admin()
+            .and(warp::path("metrics"))
+            .and(path::end())
+            .and(warp::get2())
+            .boxed()
+    }
+
+    /// `POST /api/v1/streaming/admin/cache/clear`, with an optional comma-separated `scope`
+    /// of `tokens`, `tags`, `lists`, and `filters`
+    pub fn admin_cache_clear(&self) -> BoxedFilter<(CacheScopes,)> {
+        self.admin()
+            .and(path!("cache" / "clear"))
+            .and(path::end())
+            .and(warp::post2())
+            .and(query::Scope::to_filter())
+            .and_then(|q: query::Scope| {
+                CacheScopes::parse(q.scope.as_deref()).map_err(warp::reject::custom)
+            })
+            .boxed()
+    }
+
+    /// Clear the Postgres side of the caches in `scopes` (see `CacheCleared`)
+    pub fn clear_caches(&self, scopes: CacheScopes) -> CacheCleared {
+        tracing::info!("Clearing cached {:?}", scopes);
+        self.pg_conn.clear_statements(scopes)
+    }
+
+    /// `GET /api/v1/streaming/admin/streams`
+    pub fn admin_streams(&self) -> BoxedFilter<()> {
+        self.admin()
+            .and(warp::path("streams"))
+            .and(path::end())
+            .and(warp::get2())
+            .boxed()
+    }
+
+    /// `POST /api/v1/streaming/admin/streams/enable` or `.../disable`, with a comma-separated
+    /// `stream` of stream types (e.g., `public,public:media`).  Yields the streams, and whether
+    /// to enable them.
+    pub fn admin_streams_switch(&self) -> BoxedFilter<(Vec<String>, bool)> {
+        let enable = path!("streams" / "enable").map(|| true);
+        let disable = path!("streams" / "disable").map(|| false);
+        self.admin()
+            .and(enable.or(disable).unify())
+            .and(path::end())
+            .and(warp::post2())
+            .and(warp::query())
+            .and_then(|enabled: bool, q: query::Stream| {
+                let streams = switches::parse(&q.stream).map_err(warp::reject::custom)?;
+                Ok::<_, Rejection>((streams, enabled))
+            })
+            .untuple_one()
+            .boxed()
+    }
+
+    /// The stream types that are disabled, which the admin API changes
+    pub fn stream_switches(&self) -> &StreamSwitches {
+        &self.switches
+    }
+
+    /// `GET /api/v1/streaming/admin/event_modes`
+    pub fn admin_event_modes(&self) -> BoxedFilter<()> {
+        self.admin()
+            .and(warp::path("event_modes"))
+            .and(path::end())
+            .and(warp::get2())
+            .boxed()
+    }
+
+    /// `POST /api/v1/streaming/admin/event_modes/strict` or `.../pass_through`, with a
+    /// comma-separated `category` of timelines (e.g., `public,hashtag`).  Yields the
+    /// categories, and the mode to parse their events in.
+    pub fn admin_event_modes_switch(&self) -> BoxedFilter<(Vec<String>, EventModeInner)> {
+        let strict = path!("event_modes" / "strict").map(|| EventModeInner::Strict);
+        let pass_through =
+            path!("event_modes" / "pass_through").map(|| EventModeInner::PassThrough);
+        self.admin()
+            .and(strict.or(pass_through).unify())
+            .and(path::end())
+            .and(warp::post2())
+            .and(query::Category::to_filter())
+            .and_then(|mode: EventModeInner, q: query::Category| {
+                let categories =
+                    switches::parse_categories(&q.category).map_err(warp::reject::custom)?;
+                Ok::<_, Rejection>((categories, mode))
+            })
+            .untuple_one()
+            .boxed()
+    }
+
+    /// `GET /api/v1/streaming/admin/subsystems`
+    pub fn admin_subsystems(&self) -> BoxedFilter<()> {
+        self.admin()
+            .and(warp::path("subsystems"))
+            .and(path::end())
+            .and(warp::get2())
+            .boxed()
+    }
+
+    /// `POST /api/v1/streaming/admin/subsystems/start` or `.../stop`, with a comma-separated
+    /// `subsystem` list (e.g., `canary,reconciliation`).  Yields the subsystems, and whether to
+    /// run them.
+    pub fn admin_subsystems_switch(&self) -> BoxedFilter<(Vec<String>, bool)> {
+        let start = path!("subsystems" / "start").map(|| true);
+        let stop = path!("subsystems" / "stop").map(|| false);
+        self.admin()
+            .and(start.or(stop).unify())
+            .and(path::end())
+            .and(warp::post2())
+            .and(query::Subsystem::to_filter())
+            .and_then(|running: bool, q: query::Subsystem| {
+                let names = subsystems::parse(&q.subsystem).map_err(warp::reject::custom)?;
+                Ok::<_, Rejection>((names, running))
+            })
+            .untuple_one()
+            .boxed()
+    }
+
+    /// Whether each optional subsystem is running, which the admin API changes
+    pub fn subsystems(&self) -> &Subsystems {
+        &self.subsystems
+    }
+
+    /// `GET /api/v1/streaming/admin/denylist`
+    pub fn admin_denylist(&self) -> BoxedFilter<()> {
+        self.admin()
+            .and(warp::path("denylist"))
+            .and(path::end())
+            .and(warp::get2())
+            .boxed()
+    }
+
+    /// `POST /api/v1/streaming/admin/denylist/add` or `.../remove`, with a `token`, an `ip`
+    /// (an address or a range, e.g. `192.0.2.0/24`), or both.  Yields the entries, and
+    /// whether to ban them.
+    pub fn admin_denylist_change(&self) -> BoxedFilter<(Vec<DenylistEntry>, bool)> {
+        let add = path!("denylist" / "add").map(|| true);
+        let remove = path!("denylist" / "remove").map(|| false);
+        self.admin()
+            .and(add.or(remove).unify())
+            .and(path::end())
+            .and(warp::post2())
+            .and(warp::query())
+            .and_then(|banned: bool, q: query::Deny| {
+                let entries = DenylistEntry::parse(q.token, q.ip).map_err(warp::reject::custom)?;
+                Ok::<_, Rejection>((entries, banned))
+            })
+            .untuple_one()
+            .boxed()
+    }
+
+    /// `POST /api/v1/streaming/admin/disconnect`, with a `user_id`, a `token`, or an `ip` (an
+    /// address or a range), and optionally the WebSocket close `code` to send (1008, policy
+    /// violation, by default)
+    pub fn admin_disconnect(&self) -> BoxedFilter<(Disconnect,)> {
+        self.admin()
+            .and(warp::path("disconnect"))
+            .and(path::end())
+            .and(warp::post2())
+            .and(warp::query())
+            .and_then(|q: query::Disconnect| Disconnect::parse(q).map_err(warp::reject::custom))
+            .boxed()
+    }
+
+    /// `GET /api/v1/streaming/admin/receipts`, with an optional `status_id` and `connection`
+    /// to list the sampled deliveries of (see `RECEIPT_LOG`)
+    pub fn admin_receipts(&self) -> BoxedFilter<(ReceiptQuery,)> {
+        self.admin()
+            .and(warp::path("receipts"))
+            .and(path::end())
+            .and(warp::get2())
+            .and(warp::query())
+            .and_then(|q: query::Receipts| ReceiptQuery::parse(q).map_err(warp::reject::custom))
+            .boxed()
+    }
+
+    /// `GET /api/v1/streaming/admin/drain`
+    pub fn admin_drain_status(&self) -> BoxedFilter<()> {
+        self.admin()
+            .and(warp::path("drain"))
+            .and(path::end())
+            .and(warp::get2())
+            .boxed()
+    }
+
+    /// `POST /api/v1/streaming/admin/drain` or `.../undrain`.  Yields whether to drain.
+    pub fn admin_drain(&self) -> BoxedFilter<(bool,)> {
+        let drain = warp::path("drain").map(|| true);
+        let undrain = warp::path("undrain").map(|| false);
+        self.admin()
+            .and(drain.or(undrain).unify())
+            .and(path::end())
+            .and(warp::post2())
+            .boxed()
+    }
+
+    /// Whether the instance is draining, which the admin API changes
+    pub fn drain(&self) -> &Drain {
+        &self.drain
+    }
+
+    /// The banned tokens and IP ranges, which the admin API changes
+    pub fn denylist(&self) -> &Denylist {
+        &self.denylist
+    }
+
+    /// `POST /api/v1/streaming/ingest`, with a JSON `Ingest` body
+    pub fn ingest(&self) -> BoxedFilter<(Ingest,)> {
+        path!("api" / "v1" / "streaming" / "ingest")
+            .and(path::end())
+            .and(warp::post2())
+            .and(self.admin_auth())
+            .and(warp::body::content_length_limit(MAX_INGEST_BYTES))
+            .and(warp::body::json())
+            .boxed()
+    }
+
+    /// `GET /api/v1/streaming/history`, with the `stream` (and its `tag` or `list`) to list the
+    /// latest events of and an optional `limit`.  Disabled unless `EVENT_HISTORY` is set.
+    pub fn history(&self) -> BoxedFilter<(HistoryRequest,)> {
+        let (pg_conn, tags) = (self.pg_conn.clone(), self.tags.clone());
+        let (admin_token, enabled) = (self.admin_token.clone(), self.history);
+        path!("api" / "v1" / "streaming" / "history")
+            .and(path::end())
+            .and(warp::get2())
+            .and_then(move || match enabled {
+                true => Ok(()),
+                false => Err(warp::reject::not_found()),
+            })
+            .untuple_one()
+            .and(query::unambiguous())
+            .and(warp::query())
+            .and(query::Auth::to_filter())
+            .and(query::Media::to_filter())
+            .and(query::Hashtag::to_filter())
+            .and(query::List::to_filter())
+            .and(query::Limit::to_filter())
+            .and_then(
+                |s: query::Stream,
+                 a: query::Auth,
+                 m: query::Media,
+                 h: query::Hashtag,
+                 l: query::List,
+                 n: query::Limit| {
+                    let q = Query {
+                        access_token: a.access_token,
+                        stream: s.stream,
+                        media: m.is_truthy(),
+                        hashtag: h.name().map_err(warp::reject::custom)?,
+                        list: l.id().map_err(warp::reject::custom)?,
+                        client_uuid: None,
+                        event_types: EventTypes::default(),
+                    };
+                    Ok::<_, Rejection>((q, n.len().map_err(warp::reject::custom)?))
+                },
+            )
+            .untuple_one()
+            .and(query::OptionalAccessToken::from_sse_header())
+            .and_then(
+                move |q: Query, limit: Option<usize>, token: Option<String>| {
+                    let q = q.update_access_token(token)?;
+                    let pool = pg_conn.clone();
+                    let timeline = history::authorize(q, admin_token.as_deref(), pool, &tags)?;
+                    Ok::<_, Rejection>(HistoryRequest { timeline, limit })
+                },
+            )
+            .boxed()
+    }
+
+    /// Redirects requests that ask for another instance (with the affinity cookie or an
+    /// `instance` query parameter) to that instance.
+    fn affinity_route(&self) -> BoxedFilter<()> {
+        let affinity = self.affinity.clone();
+        query::Instance::to_filter()
+            .and(warp::cookie::optional(affinity::COOKIE))
+            .and(path::full())
+            .and(raw_query())
+            .and_then(
+                move |q: query::Instance,
+                      cookie: Option<String>,
+                      path: path::FullPath,
+                      query: String| {
+                    affinity.route(q.instance.or(cookie), &path, &query)
+                },
+            )
+            .untuple_one()
+            .boxed()
+    }
+
+    /// Applies the `GEO_POLICY` to the client's address (see `client_ip`).
+    fn geo_policy(&self) -> BoxedFilter<()> {
+        let geo = self.geo.clone();
+        client_ip()
+            .and_then(move |ip: Option<IpAddr>| geo.check(ip).map_err(warp::reject::custom))
+            .untuple_one()
+            .boxed()
+    }
+
+    /// Matches requests to the admin API that carry the `ADMIN_TOKEN` as a bearer token.
+    fn admin(&self) -> BoxedFilter<()> {
+        path!("api" / "v1" / "streaming" / "admin")
+            .and(self.admin_auth())
+            .boxed()
+    }
+
+    /// Requires the `ADMIN_TOKEN` as a bearer token.
+    ///
+    /// If no `ADMIN_TOKEN` is configured, the endpoints that need it are disabled and requests
+    /// are treated as if the endpoint does not exist.
+    fn admin_auth(&self) -> BoxedFilter<()> {
+        let admin_token = self.admin_token.clone();
+        query::OptionalAccessToken::from_sse_header()
+            .and_then(move |token: Option<String>| match (&admin_token, token) {
+                (None, _) => Err(warp::reject::not_found()),
+                (Some(_), Some(token)) if is_admin_token(admin_token.as_deref(), &token) => Ok(()),
+                (Some(_), _) => Err(warp::reject::custom(PgPool::BAD_TOKEN)),
+            })
+            .untuple_one()
+            .boxed()
+    }
+
     pub fn err(r: Rejection) -> std::result::Result<impl warp::Reply, warp::Rejection> {
         use StatusCode as Code;
+        if let Some(redirect) = r.find_cause::<err::Redirect>() {
+            tracing::info!("Redirecting to {}", redirect.location);
+            let reply =
+                reply::with_status(reply::json(&redirect.to_string()), Code::TEMPORARY_REDIRECT);
+            let reply = reply::with_header(reply, "location", redirect.location.as_str());
+            return Ok(
+                reply::with_header(reply, redirect.header, redirect.target.as_str())
+                    .into_response(),
+            );
+        }
+        if let Some(ambiguous) = r.find_cause::<err::Ambiguous>() {
+            tracing::info!("Request rejected: {}", ambiguous);
+            let reply = reply::with_status(reply::json(&ambiguous.to_string()), Code::BAD_REQUEST);
+            return Ok(reply.into_response());
+        }
+        if let Some(unknown) = r.find_cause::<err::UnknownScope>() {
+            let reply = reply::with_status(reply::json(&unknown.to_string()), Code::BAD_REQUEST);
+            return Ok(reply.into_response());
+        }
+        if let Some(unknown) = r.find_cause::<err::UnknownStream>() {
+            let reply = reply::with_status(reply::json(&unknown.to_string()), Code::BAD_REQUEST);
+            return Ok(reply.into_response());
+        }
+        if let Some(unknown) = r.find_cause::<err::UnknownCategory>() {
+            let reply = reply::with_status(reply::json(&unknown.to_string()), Code::BAD_REQUEST);
+            return Ok(reply.into_response());
+        }
+        if let Some(unknown) = r.find_cause::<err::UnknownSubsystem>() {
+            let reply = reply::with_status(reply::json(&unknown.to_string()), Code::BAD_REQUEST);
+            return Ok(reply.into_response());
+        }
+        if let Some(invalid) = r.find_cause::<err::InvalidEntry>() {
+            let reply = reply::with_status(reply::json(&invalid.to_string()), Code::BAD_REQUEST);
+            return Ok(reply.into_response());
+        }
+        if let Some(invalid) = r.find_cause::<err::InvalidNumber>() {
+            tracing::info!("Request rejected: {}", invalid);
+            let reply = reply::with_status(reply::json(&invalid.to_string()), Code::BAD_REQUEST);
+            return Ok(reply.into_response());
+        }
+        if let Some(too_long) = r.find_cause::<err::TooLong>() {
+            tracing::info!("Request rejected: {}", too_long);
+            let reply = reply::with_status(reply::json(&too_long.to_string()), Code::BAD_REQUEST);
+            return Ok(reply.into_response());
+        }
+        if let Some(invalid) = r.find_cause::<err::InvalidDisconnect>() {
+            let reply = reply::with_status(reply::json(&invalid.to_string()), Code::BAD_REQUEST);
+            return Ok(reply.into_response());
+        }
+        if let Some(banned) = r.find_cause::<err::Banned>() {
+            let code = Code::FORBIDDEN;
+            return Ok(reply::with_status(reply::json(&banned.to_string()), code).into_response());
+        }
+        if let Some(shutting_down) = r.find_cause::<err::ShuttingDown>() {
+            let code = Code::SERVICE_UNAVAILABLE;
+            let reply = reply::with_status(reply::json(&shutting_down.to_string()), code);
+            return Ok(reply.into_response());
+        }
+        if let Some(disabled) = r.find_cause::<err::Disabled>() {
+            tracing::info!("Request rejected: {}", disabled);
+            let code = Code::SERVICE_UNAVAILABLE;
+            return Ok(reply::with_status(reply::json(&disabled.to_string()), code).into_response());
+        }
+        if let Some(refused) = r.find_cause::<err::Refused>() {
+            let code = match refused {
+                err::Refused::Rejected(_) => Code::FORBIDDEN,
+                err::Refused::Throttled(_) => Code::TOO_MANY_REQUESTS,
+            };
+            return Ok(reply::with_status(reply::json(&refused.to_string()), code).into_response());
+        }
         let (msg, code) = match &r.cause().map(|cause| cause.to_string()).as_deref() {
             Some(PgPool::BAD_TOKEN) => (PgPool::BAD_TOKEN, Code::UNAUTHORIZED),
             Some(PgPool::PG_NULL) => (PgPool::PG_NULL, Code::BAD_REQUEST),
@@ -140,11 +814,11 @@ impl Handler {
         };
 
         if code == Code::INTERNAL_SERVER_ERROR {
-            log::error!("Internal error: {:?}", &r);
+            tracing::error!("Internal error: {:?}", &r);
         } else {
-            log::info!("Request rejected: {} - {:?}", code, &r);
+            tracing::info!("Request rejected: {} - {:?}", code, &r);
         };
-        Ok(reply::with_status(reply::json(&msg), code))
+        Ok(reply::with_status(reply::json(&msg), code).into_response())
     }
 }
 
@@ -152,17 +826,61 @@ fn parse_ws_query() -> BoxedFilter<(Query,)> {
     use query::*;
     path!("api" / "v1" / "streaming")
         .and(path::end())
+        .and(query::unambiguous())
         .and(warp::query())
         .and(Auth::to_filter())
         .and(Media::to_filter())
         .and(Hashtag::to_filter())
         .and(List::to_filter())
-        .map(|s: Stream, a: Auth, m: Media, h: Hashtag, l: List| Query {
-            access_token: a.access_token,
-            stream: s.stream,
-            media: m.is_truthy(),
-            hashtag: h.tag,
-            list: l.list,
+        .and(ClientUuid::to_filter())
+        .and(event_types())
+        .and_then(
+            |s: Stream, a: Auth, m: Media, h: Hashtag, l: List, c: ClientUuid, e: EventTypes| {
+                Ok::<_, Rejection>(Query {
+                    access_token: a.access_token,
+                    stream: s.stream,
+                    media: m.is_truthy(),
+                    hashtag: h.name().map_err(warp::reject::custom)?,
+                    list: l.id().map_err(warp::reject::custom)?,
+                    client_uuid: c.client_uuid,
+                    event_types: e,
+                })
+            },
+        )
+        .boxed()
+}
+
+/// The client's address: the last `X-Forwarded-For` address (which the nearest proxy added),
+/// if there is one, or else the peer's address (which a Unix socket doesn't have)
+fn client_ip() -> BoxedFilter<(Option<IpAddr>,)> {
+    let forwarded = warp::header::header::<String>("x-forwarded-for").map(Some);
+    let not_forwarded = warp::any().map(|| None);
+    warp::addr::remote()
+        .and(forwarded.or(not_forwarded).unify())
+        .map(|peer: Option<SocketAddr>, forwarded: Option<String>| {
+            let forwarded = forwarded
+                .and_then(|addrs| addrs.rsplit(',').next()?.trim().parse::<IpAddr>().ok());
+            forwarded.or_else(|| peer.map(|peer| peer.ip()))
         })
         .boxed()
 }
+
+/// The request's query string, or an empty string if it has none
+fn raw_query() -> BoxedFilter<(String,)> {
+    warp::query::raw()
+        .or(warp::any().map(String::new))
+        .unify()
+        .boxed()
+}
+
+/// Whether `token` is the `ADMIN_TOKEN`, compared in constant time so that how long a
+/// comparison takes doesn't tell a client how much of its guess was right
+fn is_admin_token(admin_token: Option<&str>, token: &str) -> bool {
+    match admin_token {
+        Some(admin_token) if admin_token.len() == token.len() => {
+            let bytes = admin_token.bytes().zip(token.bytes());
+            bytes.fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,75 @@
+//! Keep clients on the same instance when several Flodgatt instances share a load balancer
+//!
+//! Responses name the instance that served them (in the `X-Flodgatt-Instance` header and the
+//! `flodgatt_instance` cookie).  A client that asks for a particular instance again, with that
+//! cookie or an `instance` query parameter, is redirected to it if it is one of the
+//! `INSTANCE_PEERS`; requests for an unknown instance are served by whichever instance gets
+//! them.
+use super::err::Redirect;
+use crate::config::Deployment;
+
+use hashbrown::HashMap;
+use std::sync::Arc;
+use warp::http::header::{HeaderMap, HeaderValue, SET_COOKIE};
+use warp::path::FullPath;
+use warp::Rejection;
+
+pub(super) const COOKIE: &str = "flodgatt_instance";
+
+#[derive(Debug, Clone, Default)]
+pub struct Affinity {
+    instance_id: Option<String>,
+    peers: Arc<HashMap<String, String>>,
+}
+
+impl Affinity {
+    pub fn from_cfg(cfg: &Deployment) -> Self {
+        Self {
+            instance_id: cfg.instance_id.clone().0,
+            peers: Arc::new(cfg.instance_peers.0.clone()),
+        }
+    }
+
+    /// Redirect a request for another instance to that instance, keeping its path and query.
+    pub(super) fn route(
+        &self,
+        requested: Option<String>,
+        path: &FullPath,
+        query: &str,
+    ) -> Result<(), Rejection> {
+        let peer = requested
+            .filter(|id| Some(id) != self.instance_id.as_ref())
+            .and_then(|id| self.peers.get(&id).map(|url| (id, url)));
+        match peer {
+            None => Ok(()),
+            Some((id, url)) => {
+                let separator = if query.is_empty() { "" } else { "?" };
+                Err(warp::reject::custom(Redirect {
+                    location: [url, path.as_str(), separator, query].concat(),
+                    header: "x-flodgatt-instance",
+                    target: id,
+                }))
+            }
+        }
+    }
+
+    pub(super) fn instance_id(&self) -> Option<&str> {
+        self.instance_id.as_deref()
+    }
+
+    /// The headers that tell the client (and the load balancer) which instance served it
+    pub(super) fn headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(id) = &self.instance_id {
+            let cookie = format!("{}={}; Path=/api/v1/streaming; HttpOnly", COOKIE, id);
+            // `INSTANCE_ID` only allows characters that are valid in headers
+            if let (Ok(id), Ok(cookie)) =
+                (HeaderValue::from_str(id), HeaderValue::from_str(&cookie))
+            {
+                headers.insert("x-flodgatt-instance", id);
+                headers.insert(SET_COOKIE, cookie);
+            }
+        }
+        headers
+    }
+}
@@ -0,0 +1,61 @@
+//! Which cached data `POST /api/v1/streaming/admin/cache/clear` drops
+use super::err::UnknownScope;
+use serde::Serialize;
+
+/// The kinds of cached data an operator can clear, chosen with a comma-separated `scope`
+/// query parameter (all of them, if it is omitted)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CacheScopes {
+    pub tokens: bool,
+    pub tags: bool,
+    pub lists: bool,
+    pub filters: bool,
+}
+
+/// What clearing the caches dropped, with `None` for the scopes that weren't cleared.
+///
+/// Flodgatt reads tokens, lists, and filters from Postgres each time a client connects, so
+/// the only cached state for those scopes is the statements prepared on each pooled
+/// connection; each scope counts the connections that will prepare its queries again.
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct CacheCleared {
+    pub tokens: Option<usize>,
+    pub tags: Option<usize>,
+    pub lists: Option<usize>,
+    pub filters: Option<usize>,
+    /// Hashtag names dropped from the cache that names Redis channels (names that clients are
+    /// still subscribed to are kept)
+    pub tag_names: Option<usize>,
+}
+
+impl CacheScopes {
+    pub const ALL: Self = Self {
+        tokens: true,
+        tags: true,
+        lists: true,
+        filters: true,
+    };
+
+    pub(super) fn parse(scope: Option<&str>) -> Result<Self, UnknownScope> {
+        let scope = match scope {
+            None => return Ok(Self::ALL),
+            Some(scope) => scope,
+        };
+        let mut scopes = Self {
+            tokens: false,
+            tags: false,
+            lists: false,
+            filters: false,
+        };
+        for name in scope.split(',').map(str::trim) {
+            match name {
+                "tokens" => scopes.tokens = true,
+                "tags" => scopes.tags = true,
+                "lists" => scopes.lists = true,
+                "filters" => scopes.filters = true,
+                other => return Err(UnknownScope(other.to_string())),
+            }
+        }
+        Ok(scopes)
+    }
+}
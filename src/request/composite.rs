@@ -0,0 +1,162 @@
+//! Streams that an operator composes of other streams (with `COMPOSITE_STREAMS`), e.g. an
+//! `events` stream of a conference's hashtag, a list of its speakers, and the local timeline,
+//! for a dashboard that would otherwise open a stream of each and merge them itself.
+//!
+//! A client subscribes to one by name (with `stream=events`, or a `subscribe` message), and is
+//! admitted by its `COMPOSITE_STREAM_AUTH` policy in place of the policies of the streams it is
+//! composed of: its list can be read by anyone the policy admits, not only the list's owner.
+//! The `Manager` sends the client the events of each of its timelines as it would any other
+//! client's, except that a status on more than one of them is sent once; events that name
+//! their stream name the composite stream.
+use super::err::UnknownStream;
+use super::postgres::PgPool;
+use super::query::{self, Query};
+use super::subscription::is_uuid;
+use super::switches::StreamSwitches;
+use super::tags::TagRegistry;
+use super::timeline::UserData;
+use super::{is_admin_token, Blocks, Content, Reach, Stream, Subscription, Timeline};
+use crate::config::{CompositeAuthInner, CompositePartInner, Deployment};
+
+use hashbrown::HashMap;
+use std::sync::Arc;
+use warp::reject::{self, Rejection};
+
+/// The `COMPOSITE_STREAMS`, by name
+#[derive(Debug, Clone, Default)]
+pub struct Composites {
+    streams: Arc<HashMap<String, Definition>>,
+    admin_token: Option<String>,
+    switches: StreamSwitches,
+}
+
+#[derive(Debug, Clone)]
+struct Definition {
+    parts: Vec<CompositePartInner>,
+    auth: CompositeAuthInner,
+}
+
+/// The composite stream a `Subscription` is for
+#[derive(Debug, Clone, PartialEq)]
+pub struct Composite {
+    pub name: String,
+    /// Every timeline it is composed of, with the hashtag that each hashtag timeline names
+    pub timelines: Vec<(Timeline, Option<String>)>,
+}
+
+impl Composites {
+    /// The streams in `cfg`, which are refused while any stream type they are composed of is
+    /// disabled in `switches`
+    pub fn from_cfg(cfg: &Deployment, switches: StreamSwitches) -> Self {
+        let streams = cfg
+            .composite_streams
+            .iter()
+            .map(|(name, parts)| {
+                let auth = cfg.composite_stream_auth.get(name).copied();
+                let definition = Definition {
+                    parts: parts.clone(),
+                    auth: auth.unwrap_or(CompositeAuthInner::User),
+                };
+                (name.clone(), definition)
+            })
+            .collect();
+        Self {
+            streams: Arc::new(streams),
+            admin_token: cfg.admin_token.clone().0,
+            switches,
+        }
+    }
+
+    /// Whether `stream` names a composite stream
+    pub(super) fn contains(&self, stream: &str) -> bool {
+        self.streams.contains_key(stream)
+    }
+
+    /// The `Subscription` to the stream `q` names: a composite stream, if it names one, or else
+    /// one of Mastodon's
+    pub(super) fn subscription(
+        &self,
+        q: Query,
+        pool: PgPool,
+        tags: &TagRegistry,
+    ) -> Result<Subscription, Rejection> {
+        match self.streams.get(&q.stream) {
+            Some(definition) => self.compose(q, definition, pool, tags),
+            None => Subscription::query_postgres(q, pool, tags),
+        }
+    }
+
+    fn compose(
+        &self,
+        q: Query,
+        definition: &Definition,
+        pool: PgPool,
+        tags: &TagRegistry,
+    ) -> Result<Subscription, Rejection> {
+        use CompositeAuthInner::*;
+        let user = match (definition.auth, q.access_token.as_deref()) {
+            (Admin, Some(token)) if is_admin_token(self.admin_token.as_deref(), token) => None,
+            (Admin, _) | (User, None) => Err(reject::custom(PgPool::BAD_TOKEN))?,
+            (Public, None) => None,
+            // so that what the user has blocked is filtered out
+            (_, Some(_)) => Some(pool.clone().select_user(&q.access_token)?),
+        };
+
+        let timelines = definition
+            .parts
+            .iter()
+            .map(|part| self.timeline(part, tags))
+            .collect::<Result<Vec<_>, _>>()?;
+        let (timeline, hashtag_name) = timelines
+            .first()
+            .cloned()
+            .ok_or_else(|| reject::custom(UnknownStream(q.stream.clone())))?;
+        let user_id = user.as_ref().map(|user| user.id);
+        let UserData { allowed_langs, .. } = user.unwrap_or_else(UserData::public);
+        Ok(Subscription {
+            timeline,
+            allowed_langs,
+            blocks: match user_id {
+                Some(id) => Blocks::select(id, pool)?,
+                None => Blocks::default(),
+            },
+            hashtag_name,
+            access_token: q.access_token,
+            user_id,
+            client_uuid: q.client_uuid.filter(|uuid| is_uuid(uuid)),
+            ip: None,
+            event_types: q.event_types,
+            connection_id: None,
+            composite: Some(Composite {
+                name: q.stream,
+                timelines,
+            }),
+        })
+    }
+
+    /// The timeline of `part`, if its stream type is enabled
+    fn timeline(
+        &self,
+        part: &CompositePartInner,
+        tags: &TagRegistry,
+    ) -> Result<(Timeline, Option<String>), Rejection> {
+        use {Content::*, Reach::*, Stream::*};
+        let (timeline, tag) = match part {
+            CompositePartInner::Public(stream) => {
+                let (timeline, _scope) = Timeline::parse(stream, false)
+                    .map_err(|_| reject::custom(UnknownStream(stream.clone())))?;
+                (timeline, None)
+            }
+            CompositePartInner::Hashtag { tag, local } => {
+                let tag = query::check_tag(tag.clone()).map_err(reject::custom)?;
+                let reach = if *local { Local } else { Federated };
+                (Timeline(Hashtag(tags.id(&tag)?), reach, All), Some(tag))
+            }
+            CompositePartInner::List(id) => (Timeline(List(*id), Federated, All), None),
+        };
+        if let Some(stream) = timeline.stream_type() {
+            self.switches.check_stream(stream).map_err(reject::custom)?;
+        }
+        Ok((timeline, tag))
+    }
+}
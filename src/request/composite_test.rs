@@ -0,0 +1,167 @@
+use super::composite::Composites;
+use super::dev_users::DevUsers;
+use super::err::Disabled;
+use super::postgres::PgPool;
+use super::query::Query;
+use super::{StreamSwitches, TagRegistry};
+use crate::config::{self, Deployment};
+use crate::Id;
+
+use hashbrown::HashMap;
+
+fn deployment(vars: &[(&str, &str)]) -> Result<Deployment<'static>, config::Error> {
+    let vars: HashMap<String, String> = vars
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    config::from_env(vars).map(|(_postgres, _redis, deployment)| deployment)
+}
+
+fn query(stream: &str, access_token: Option<&str>) -> Query {
+    Query {
+        access_token: access_token.map(str::to_string),
+        stream: stream.to_string(),
+        media: false,
+        hashtag: String::new(),
+        list: 0,
+        client_uuid: None,
+        event_types: Default::default(),
+    }
+}
+
+fn composites(cfg: &Deployment, switches: &StreamSwitches) -> (Composites, PgPool, TagRegistry) {
+    let pool = PgPool::dev(DevUsers::from_cfg(cfg), false);
+    let tags = TagRegistry::new(10).with_postgres(pool.clone());
+    (Composites::from_cfg(cfg, switches.clone()), pool, tags)
+}
+
+#[test]
+fn composite_streams_subscribe_to_each_of_their_timelines() -> Result<(), config::Error> {
+    let cfg = deployment(&[
+        (
+            "COMPOSITE_STREAMS",
+            "events=hashtag:conf2024+list:42+public:local",
+        ),
+        ("COMPOSITE_STREAM_AUTH", "events=public"),
+    ])?;
+    let (composites, pool, tags) = composites(&cfg, &StreamSwitches::default());
+
+    let subscription = composites
+        .subscription(query("events", None), pool.clone(), &tags)
+        .expect("a public composite stream");
+    let conf2024 = tags
+        .id("conf2024")
+        .expect("every hashtag exists in dev mode");
+    let timelines: Vec<_> = subscription
+        .timelines()
+        .into_iter()
+        .map(|(timeline, tag)| (timeline.to_redis_raw_timeline(tag.as_ref()).ok(), tag))
+        .collect();
+    assert_eq!(
+        timelines,
+        vec![
+            (
+                Some("timeline:hashtag:conf2024".to_string()),
+                Some("conf2024".to_string())
+            ),
+            (Some("timeline:list:42".to_string()), None),
+            (Some("timeline:public:local".to_string()), None),
+        ]
+    );
+    assert_eq!(subscription.timeline.tag(), Some(conf2024));
+    assert_eq!(subscription.stream_name(), vec!["events"]);
+    assert_eq!(subscription.user_id, None);
+
+    // A user's token is still checked, so that what they block is filtered out
+    let subscription = composites
+        .subscription(query("events", Some("dev")), pool.clone(), &tags)
+        .expect("a public composite stream");
+    assert_eq!(subscription.user_id, Some(Id(1)));
+    assert!(composites
+        .subscription(query("events", Some("unknown")), pool, &tags)
+        .is_err());
+    Ok(())
+}
+
+#[test]
+fn composite_streams_admit_clients_by_their_own_policy() -> Result<(), config::Error> {
+    let cfg = deployment(&[
+        ("COMPOSITE_STREAMS", "users=list:42,admins=public"),
+        ("COMPOSITE_STREAM_AUTH", "admins=admin"),
+        ("ADMIN_TOKEN", "secret"),
+    ])?;
+    let (composites, pool, tags) = composites(&cfg, &StreamSwitches::default());
+    let subscribe =
+        |stream, token| composites.subscription(query(stream, token), pool.clone(), &tags);
+
+    // Any user may read the list, which only its owner could otherwise stream
+    assert!(subscribe("users", None).is_err());
+    assert_eq!(
+        subscribe("users", Some("dev")).ok().map(|s| s.user_id),
+        Some(Some(Id(1)))
+    );
+    assert!(subscribe("admins", Some("dev")).is_err());
+    assert_eq!(
+        subscribe("admins", Some("secret")).ok().map(|s| s.user_id),
+        Some(None)
+    );
+
+    // Other streams are authorized as Mastodon's streaming server would
+    let public = subscribe("public", None).expect("the public timeline");
+    assert_eq!(public.composite, None);
+    assert_eq!(public.timelines(), vec![(public.timeline, None)]);
+    Ok(())
+}
+
+#[test]
+fn composite_streams_are_refused_while_a_stream_type_they_include_is_disabled(
+) -> Result<(), config::Error> {
+    let cfg = deployment(&[
+        ("COMPOSITE_STREAMS", "events=public:local+hashtag:conf2024"),
+        ("COMPOSITE_STREAM_AUTH", "events=public"),
+    ])?;
+    let switches = StreamSwitches::default();
+    let (composites, pool, tags) = composites(&cfg, &switches);
+    switches.set(&["public:local".to_string()], false);
+
+    let refused = composites
+        .subscription(query("events", None), pool, &tags)
+        .err()
+        .expect("a disabled stream type");
+    assert_eq!(
+        refused.find_cause::<Disabled>(),
+        Some(&Disabled("public:local".to_string()))
+    );
+    Ok(())
+}
+
+#[test]
+fn composite_streams_must_be_composed_of_public_hashtag_and_list_streams() {
+    for invalid in &[
+        "events=user",
+        "events=direct+public",
+        "events=list:0",
+        "events=hashtag:",
+        "public=hashtag:conf2024",
+        "=public",
+    ] {
+        assert!(
+            deployment(&[("COMPOSITE_STREAMS", invalid)]).is_err(),
+            "{}",
+            invalid
+        );
+    }
+    assert!(deployment(&[("COMPOSITE_STREAM_AUTH", "events=public")]).is_err());
+    assert!(deployment(&[
+        ("COMPOSITE_STREAMS", "events=public"),
+        ("COMPOSITE_STREAM_AUTH", "events=everyone"),
+    ])
+    .is_err());
+
+    let cfg = deployment(&[(
+        "COMPOSITE_STREAMS",
+        "events=hashtag:local:conf2024+public:media",
+    )])
+    .expect("valid composite streams");
+    assert_eq!(cfg.composite_streams["events"].len(), 2);
+}
@@ -0,0 +1,215 @@
+//! The access tokens and IP ranges that are banned from streaming, e.g. a scraper's token or
+//! the address range of an abusive host.  Changed at runtime with `POST
+//! /api/v1/streaming/admin/denylist/{add,remove}`; adding an entry also disconnects the
+//! clients it matches.
+//!
+//! With a `DENYLIST_KEY`, the entries are kept in that Redis set (as `token:<token>` and
+//! `ip:<range>`), so that they survive restarts and reach every instance that shares Redis.
+use super::err::{Banned, InvalidEntry};
+use super::query::Query;
+
+use serde::Serialize;
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use warp::reject::Rejection;
+
+/// Shared by every clone, so that a change made through the admin API applies to all routes
+#[derive(Debug, Clone, Default)]
+pub struct Denylist(Arc<RwLock<Entries>>);
+
+#[derive(Debug, Default)]
+struct Entries {
+    tokens: HashSet<String>,
+    ranges: BTreeSet<IpRange>,
+}
+
+/// A banned access token or IP range
+#[derive(Debug, Clone, PartialEq)]
+pub enum Entry {
+    Token(String),
+    Ip(IpRange),
+}
+
+/// An IP address and the number of its leading bits that an address must share to be in range
+/// (e.g., `192.0.2.0/24`; a single address is a range of one)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct IpRange {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+/// The admin API's reply: how many tokens are banned (the tokens themselves are secret) and
+/// which IP ranges, along with how many clients were just disconnected
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct DenylistSummary {
+    pub tokens: usize,
+    pub ips: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disconnected: Option<usize>,
+}
+
+impl Denylist {
+    /// Pass `q` on unless its token, or the client's address, is banned
+    pub(super) fn admit(&self, q: Query, ip: Option<IpAddr>) -> Result<Query, Rejection> {
+        match self.is_banned(q.access_token.as_deref(), ip) {
+            true => {
+                tracing::info!("Refused a connection from a banned token or address");
+                Err(warp::reject::custom(Banned))
+            }
+            false => Ok(q),
+        }
+    }
+
+    /// Whether a client with `token`, connecting from `ip`, matches any entry
+    pub fn is_banned(&self, token: Option<&str>, ip: Option<IpAddr>) -> bool {
+        let entries = self.read();
+        token.map_or(false, |token| entries.tokens.contains(token))
+            || ip.map_or(false, |ip| entries.ranges.iter().any(|r| r.contains(ip)))
+    }
+
+    /// Add or remove each of `entries`
+    pub fn set(&self, entries: &[Entry], banned: bool) {
+        let mut list = self.0.write().unwrap_or_else(|e| e.into_inner());
+        for entry in entries {
+            match (entry, banned) {
+                (Entry::Token(token), true) => list.tokens.insert(token.clone()),
+                (Entry::Token(token), false) => list.tokens.remove(token),
+                (Entry::Ip(range), true) => list.ranges.insert(*range),
+                (Entry::Ip(range), false) => list.ranges.remove(range),
+            };
+        }
+        tracing::warn!(
+            "Denylist: {} tokens and {} IP ranges",
+            list.tokens.len(),
+            list.ranges.len()
+        );
+    }
+
+    /// Replace every entry with the members of the `DENYLIST_KEY` set (see `Entry::to_member`),
+    /// which other instances may have changed
+    pub fn load(&self, members: &[String]) {
+        let mut list = Entries::default();
+        let entries = members.iter().filter_map(|member| {
+            let entry = Entry::from_member(member);
+            if entry.is_none() {
+                tracing::warn!("Ignoring an invalid denylist entry: {}", member);
+            }
+            entry
+        });
+        for entry in entries {
+            match entry {
+                Entry::Token(token) => list.tokens.insert(token),
+                Entry::Ip(range) => list.ranges.insert(range),
+            };
+        }
+        *self.0.write().unwrap_or_else(|e| e.into_inner()) = list;
+    }
+
+    pub fn summary(&self) -> DenylistSummary {
+        let entries = self.read();
+        DenylistSummary {
+            tokens: entries.tokens.len(),
+            ips: entries.ranges.iter().map(IpRange::to_string).collect(),
+            disconnected: None,
+        }
+    }
+
+    fn read(&self) -> RwLockReadGuard<Entries> {
+        self.0.read().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Entry {
+    /// The entries named by an admin request's `token` and `ip` parameters (at least one)
+    pub(super) fn parse(
+        token: Option<String>,
+        ip: Option<String>,
+    ) -> Result<Vec<Self>, InvalidEntry> {
+        let mut entries: Vec<_> = token
+            .filter(|token| !token.is_empty())
+            .map(Entry::Token)
+            .into_iter()
+            .collect();
+        if let Some(ip) = ip {
+            entries.push(Entry::Ip(ip.parse()?));
+        }
+        match entries.is_empty() {
+            true => Err(InvalidEntry::Missing),
+            false => Ok(entries),
+        }
+    }
+
+    /// The entry as a member of the `DENYLIST_KEY` set
+    pub fn to_member(&self) -> String {
+        match self {
+            Entry::Token(token) => format!("token:{}", token),
+            Entry::Ip(range) => format!("ip:{}", range),
+        }
+    }
+
+    /// The entry a member of the `DENYLIST_KEY` set stands for, if it is valid
+    pub(super) fn from_member(member: &str) -> Option<Self> {
+        match member.find(':').map(|i| member.split_at(i)) {
+            Some(("token", token)) => Some(Entry::Token(token[1..].to_string())),
+            Some(("ip", range)) => range[1..].parse().ok().map(Entry::Ip),
+            _ => None,
+        }
+    }
+}
+
+impl IpRange {
+    fn bits(&self) -> u8 {
+        match self.addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        }
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let (range, ip) = match (self.addr, ip) {
+            (IpAddr::V4(range), IpAddr::V4(ip)) => {
+                (u128::from(u32::from(range)), u128::from(u32::from(ip)))
+            }
+            (IpAddr::V6(range), IpAddr::V6(ip)) => (u128::from(range), u128::from(ip)),
+            _ => return false,
+        };
+        let host_bits = u32::from(self.bits() - self.prefix);
+        (range ^ ip).checked_shr(host_bits).unwrap_or(0) == 0
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = InvalidEntry;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidEntry::Ip(s.to_string());
+        let (addr, prefix) = match s.find('/') {
+            Some(i) => (&s[..i], Some(&s[i + 1..])),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.trim().parse().map_err(|_| invalid())?;
+        let mut range = Self { addr, prefix: 0 };
+        range.prefix = match prefix {
+            Some(prefix) => prefix
+                .trim()
+                .parse()
+                .ok()
+                .filter(|&prefix| prefix <= range.bits())
+                .ok_or_else(invalid)?,
+            None => range.bits(),
+        };
+        Ok(range)
+    }
+}
+
+impl fmt::Display for IpRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.prefix == self.bits() {
+            true => write!(f, "{}", self.addr),
+            false => write!(f, "{}/{}", self.addr, self.prefix),
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use super::denylist::{Denylist, Entry, IpRange};
+use super::err::InvalidEntry;
+use std::net::IpAddr;
+
+fn ip(addr: &str) -> Option<IpAddr> {
+    Some(addr.parse().expect("valid address"))
+}
+
+#[test]
+fn banned_tokens_and_ranges_are_matched_until_removed() {
+    let denylist = Denylist::default();
+    let entries = Entry::parse(
+        Some("scraper".to_string()),
+        Some("192.0.2.0/24".to_string()),
+    )
+    .expect("valid entries");
+    denylist.set(&entries, true);
+
+    assert!(denylist.is_banned(Some("scraper"), None));
+    assert!(denylist.is_banned(Some("other"), ip("192.0.2.200")));
+    assert!(!denylist.is_banned(Some("other"), ip("192.0.3.1")));
+    assert!(!denylist.is_banned(None, ip("2001:db8::1")));
+    assert_eq!(denylist.summary().tokens, 1);
+    assert_eq!(denylist.summary().ips, vec!["192.0.2.0/24"]);
+
+    denylist.set(&entries[..1], false);
+    assert!(!denylist.is_banned(Some("scraper"), None));
+    assert!(denylist.is_banned(None, ip("192.0.2.1")));
+}
+
+#[test]
+fn ip_ranges_are_parsed_and_checked() {
+    let range = |s: &str| s.parse::<IpRange>();
+    let contains = |r: &str, addr: &str| {
+        range(r)
+            .expect("valid range")
+            .contains(addr.parse().expect("valid address"))
+    };
+
+    assert!(contains("2001:db8::/32", "2001:db8:ffff::1"));
+    assert!(!contains("2001:db8::/32", "2001:db9::1"));
+    assert!(contains("0.0.0.0/0", "203.0.113.7"));
+    assert!(contains("203.0.113.7", "203.0.113.7"));
+    assert!(!contains("203.0.113.7", "203.0.113.8"));
+    assert_eq!(
+        range("203.0.113.7").map(|r| r.to_string()),
+        Ok("203.0.113.7".to_string())
+    );
+
+    assert_eq!(
+        range("10.0.0.0/33"),
+        Err(InvalidEntry::Ip("10.0.0.0/33".to_string()))
+    );
+    assert_eq!(
+        range("example.com"),
+        Err(InvalidEntry::Ip("example.com".to_string()))
+    );
+    assert_eq!(Entry::parse(None, None), Err(InvalidEntry::Missing));
+}
+
+#[test]
+fn denylist_loads_from_redis_set_members() {
+    let denylist = Denylist::default();
+    let stored = Entry::Ip("198.51.100.0/24".parse().expect("valid range"));
+    denylist.set(&[Entry::Token("stale".to_string())], true);
+
+    denylist.load(&[
+        Entry::Token("abc:123".to_string()).to_member(),
+        stored.to_member(),
+        "nonsense".to_string(),
+    ]);
+    assert!(denylist.is_banned(Some("abc:123"), None));
+    assert!(denylist.is_banned(None, ip("198.51.100.9")));
+    assert!(!denylist.is_banned(Some("stale"), None));
+}
@@ -0,0 +1,62 @@
+//! Stand-ins for the Postgres queries, so that `--dev` mode can run without a database
+use super::postgres::PgPool;
+use super::timeline::{Scope, UserData};
+use crate::config::Deployment;
+use crate::Id;
+
+use hashbrown::{HashMap, HashSet};
+use std::sync::Mutex;
+use warp::reject;
+
+pub(crate) struct DevUsers {
+    tokens: HashMap<String, Id>,
+    tags: Mutex<HashMap<String, i64>>,
+}
+
+impl DevUsers {
+    pub(crate) fn from_cfg(cfg: &Deployment) -> Self {
+        Self {
+            tokens: cfg
+                .dev_tokens
+                .iter()
+                .map(|(token, id)| (token.clone(), Id(*id)))
+                .collect(),
+            tags: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Any of the `DEV_TOKENS` logs in as its account, with every read scope
+    pub(super) fn select_user(
+        &self,
+        token: &Option<String>,
+        whitelist_mode: bool,
+    ) -> Result<UserData, warp::Rejection> {
+        match token.as_ref().map(|token| self.tokens.get(token)) {
+            Some(Some(id)) => Ok(UserData {
+                id: *id,
+                allowed_langs: HashSet::new(),
+                scopes: vec![Scope::Statuses, Scope::Notifications, Scope::Lists]
+                    .into_iter()
+                    .collect(),
+            }),
+            Some(None) => Err(reject::custom(PgPool::BAD_TOKEN)),
+            None if whitelist_mode => Err(reject::custom(PgPool::BAD_TOKEN)),
+            None => Ok(UserData::public()),
+        }
+    }
+
+    /// Every hashtag exists; each gets the next ID the first time it is used
+    pub(super) fn hashtag_id(&self, name: &str) -> i64 {
+        let mut tags = self.tags.lock().unwrap_or_else(|e| e.into_inner());
+        let next_id = tags.len() as i64 + 1;
+        *tags.entry(name.to_string()).or_insert(next_id)
+    }
+
+    /// The hashtag that was given `id`, if any has been
+    pub(super) fn hashtag_name(&self, id: i64) -> Option<String> {
+        let tags = self.tags.lock().unwrap_or_else(|e| e.into_inner());
+        tags.iter()
+            .find(|(_, tag_id)| **tag_id == id)
+            .map(|(name, _)| name.clone())
+    }
+}
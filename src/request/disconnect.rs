@@ -0,0 +1,103 @@
+//! Ending the connections of particular clients, e.g. those of a user being harassed by a
+//! compromised token, without restarting.  Requested with `POST
+//! /api/v1/streaming/admin/disconnect`; unlike the `Denylist`, nothing stops the clients from
+//! reconnecting.
+use super::denylist::IpRange;
+use super::err::InvalidDisconnect;
+use super::query::{self, parse_id, parse_number};
+use crate::Id;
+
+use serde::Serialize;
+use std::net::IpAddr;
+
+/// The WebSocket close code sent to every client when the instance shuts down
+pub const GOING_AWAY: u16 = 1001;
+/// The WebSocket close code sent when no other is asked for (and to clients that are banned)
+pub const POLICY_VIOLATION: u16 = 1008;
+/// The WebSocket close code sent to clients whose stream was dropped to stay under a limit
+pub const TRY_AGAIN_LATER: u16 = 1013;
+
+/// Why the server ended a client's stream (given its close code), which the client is told in a
+/// last `error` event: a stable `code` for client apps to act on, and an `error` to show
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Closing {
+    pub error: &'static str,
+    pub code: &'static str,
+}
+
+impl Closing {
+    pub fn from_close_code(code: u16) -> Self {
+        let (code, error) = match code {
+            GOING_AWAY => (
+                "server_shutting_down",
+                "The server is shutting down; reconnect",
+            ),
+            TRY_AGAIN_LATER => ("at_capacity", "The server is at capacity; try again later"),
+            _ => ("disconnected", "Disconnected by the server's administrator"),
+        };
+        Self { error, code }
+    }
+}
+
+/// The clients to disconnect: those that match any of the `user_id`, `token`, and `ip` given
+#[derive(Debug, Clone, PartialEq)]
+pub struct Disconnect {
+    pub user_id: Option<Id>,
+    pub token: Option<String>,
+    pub ip: Option<IpRange>,
+    /// Sent in the close frame of a WebSocket (see `Closing` for what both protocols are told)
+    pub code: u16,
+}
+
+/// The admin API's reply: how many clients were disconnected
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Disconnected {
+    pub disconnected: usize,
+}
+
+impl Disconnect {
+    pub(super) fn parse(q: query::Disconnect) -> Result<Self, InvalidDisconnect> {
+        let code = match q.code {
+            Some(code) => parse_number("code", &code)?,
+            None => POLICY_VIOLATION,
+        };
+        if !is_sendable(code) {
+            Err(InvalidDisconnect::Code(code))?;
+        }
+        let ip = match q.ip {
+            Some(ip) => Some(ip.parse().map_err(|_| InvalidDisconnect::Ip(ip))?),
+            None => None,
+        };
+        let user_id = match q.user_id {
+            Some(user_id) => Some(Id(parse_id("user_id", &user_id)?)),
+            None => None,
+        };
+        let token = q.token.filter(|t| !t.is_empty());
+        match (user_id, &token, ip) {
+            (None, None, None) => Err(InvalidDisconnect::Missing),
+            _ => Ok(Self {
+                user_id,
+                token,
+                ip,
+                code,
+            }),
+        }
+    }
+
+    /// Whether to disconnect a client of `user_id`, with `token`, connected from `ip`
+    pub fn matches(&self, user_id: Option<Id>, token: Option<&str>, ip: Option<IpAddr>) -> bool {
+        (self.user_id.is_some() && self.user_id == user_id)
+            || (self.token.is_some() && self.token.as_deref() == token)
+            || match (self.ip, ip) {
+                (Some(range), Some(ip)) => range.contains(ip),
+                _ => false,
+            }
+    }
+}
+
+/// Whether a server may close a WebSocket with `code`: one of the standard codes that apply
+/// (normal closure, going away, policy violation, and internal error), or a registered or
+/// private one
+fn is_sendable(code: u16) -> bool {
+    matches!(code, 1000 | 1001 | 1008 | 1011 | 3000..=4999)
+}
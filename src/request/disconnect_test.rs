@@ -0,0 +1,71 @@
+use super::disconnect::{Disconnect, POLICY_VIOLATION};
+use super::err::{InvalidDisconnect, InvalidNumber};
+use super::query;
+use crate::Id;
+
+fn ip(addr: &str) -> Option<std::net::IpAddr> {
+    Some(addr.parse().expect("valid address"))
+}
+
+#[test]
+fn clients_matching_any_criterion_are_disconnected() {
+    let clients = Disconnect::parse(query::Disconnect {
+        user_id: Some("42".to_string()),
+        ip: Some("192.0.2.0/24".to_string()),
+        ..query::Disconnect::default()
+    })
+    .expect("valid request");
+    assert_eq!(clients.code, POLICY_VIOLATION);
+
+    assert!(clients.matches(Some(Id(42)), None, None));
+    assert!(clients.matches(None, Some("token"), ip("192.0.2.7")));
+    assert!(!clients.matches(Some(Id(7)), None, ip("198.51.100.1")));
+    assert!(!clients.matches(None, None, None));
+}
+
+#[test]
+fn invalid_requests_are_refused() {
+    let parse = |q| Disconnect::parse(q).map(|d| d.code);
+    assert_eq!(
+        parse(query::Disconnect::default()),
+        Err(InvalidDisconnect::Missing)
+    );
+    assert_eq!(
+        parse(query::Disconnect {
+            token: Some(String::new()),
+            ..query::Disconnect::default()
+        }),
+        Err(InvalidDisconnect::Missing)
+    );
+    assert_eq!(
+        parse(query::Disconnect {
+            ip: Some("192.0.2.0/33".to_string()),
+            ..query::Disconnect::default()
+        }),
+        Err(InvalidDisconnect::Ip("192.0.2.0/33".to_string()))
+    );
+    let with_code = |code: &str| query::Disconnect {
+        token: Some("compromised".to_string()),
+        code: Some(code.to_string()),
+        ..query::Disconnect::default()
+    };
+    assert_eq!(parse(with_code("1005")), Err(InvalidDisconnect::Code(1005)));
+    assert_eq!(parse(with_code("4001")), Ok(4001));
+    assert_eq!(
+        parse(with_code("70000")),
+        Err(InvalidDisconnect::Number(InvalidNumber {
+            param: "code",
+            value: "70000".to_string()
+        }))
+    );
+    assert_eq!(
+        parse(query::Disconnect {
+            user_id: Some("-42".to_string()),
+            ..query::Disconnect::default()
+        }),
+        Err(InvalidDisconnect::Number(InvalidNumber {
+            param: "user_id",
+            value: "-42".to_string()
+        }))
+    );
+}
@@ -0,0 +1,135 @@
+//! Taking an instance out of its load balancer's rotation, e.g. for maintenance.  `POST
+//! /api/v1/streaming/admin/drain` makes `GET /api/v1/streaming/readyz` fail with a 503, so a
+//! load balancer that checks it stops sending the instance new clients (connected clients keep
+//! their streams), and `POST /api/v1/streaming/admin/undrain` makes it succeed again.
+//!
+//! Only the admin API changes the state; nothing in the configuration sets it, so reloading or
+//! reapplying the configuration leaves an instance that is draining still draining.
+//!
+//! Shutting down (on `SIGTERM`) drains the instance for good: new clients are refused with a
+//! 503 as well, while the connected ones are sent a last event and closed.
+use super::err::ShuttingDown;
+use super::query::Query;
+
+use serde::Serialize;
+use std::fmt;
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::time::Instant;
+use warp::http::StatusCode;
+use warp::reject::Rejection;
+
+/// Shared by every clone, so that a change made through the admin API applies to `/readyz`
+#[derive(Debug, Clone, Default)]
+pub struct Drain(Arc<RwLock<State>>);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Serving,
+    /// Since the instant it started draining
+    Draining(Instant),
+    /// Since the instant it started shutting down, which can't be undone
+    ShuttingDown(Instant),
+}
+
+/// Whether a load balancer should send the instance new clients
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum Readiness {
+    Ready,
+    NotReady,
+}
+
+/// The admin API's reply: whether the instance is ready, and for how long it has been draining
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct DrainStatus {
+    pub readiness: Readiness,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub draining_secs: Option<u64>,
+}
+
+impl Drain {
+    /// Start draining, or stop.  Draining an instance that is already draining (or undraining
+    /// one that isn't) changes nothing, so a retried admin request is harmless.
+    pub fn set(&self, draining: bool) -> DrainStatus {
+        let mut state = self.0.write().unwrap_or_else(|e| e.into_inner());
+        *state = match (*state, draining) {
+            (State::Serving, true) => {
+                tracing::warn!("Draining: /readyz reports NotReady until undrained");
+                State::Draining(Instant::now())
+            }
+            (State::Draining(since), false) => {
+                tracing::warn!(
+                    "Undrained after {:?}: /readyz reports Ready",
+                    since.elapsed()
+                );
+                State::Serving
+            }
+            (unchanged, _) => unchanged,
+        };
+        state.status()
+    }
+
+    /// Start shutting down: stop admitting clients and report `NotReady` from then on
+    pub fn shut_down(&self) {
+        let mut state = self.0.write().unwrap_or_else(|e| e.into_inner());
+        if !matches!(*state, State::ShuttingDown(_)) {
+            *state = State::ShuttingDown(Instant::now());
+        }
+    }
+
+    /// Admit a client's query, unless the instance is shutting down
+    pub(super) fn admit(&self, q: Query) -> Result<Query, Rejection> {
+        match *self.read() {
+            State::ShuttingDown(_) => Err(warp::reject::custom(ShuttingDown)),
+            _ => Ok(q),
+        }
+    }
+
+    pub fn readiness(&self) -> Readiness {
+        self.read().status().readiness
+    }
+
+    pub fn status(&self) -> DrainStatus {
+        self.read().status()
+    }
+
+    fn read(&self) -> RwLockReadGuard<State> {
+        self.0.read().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State::Serving
+    }
+}
+
+impl State {
+    fn status(&self) -> DrainStatus {
+        match self {
+            State::Serving => DrainStatus {
+                readiness: Readiness::Ready,
+                draining_secs: None,
+            },
+            State::Draining(since) | State::ShuttingDown(since) => DrainStatus {
+                readiness: Readiness::NotReady,
+                draining_secs: Some(since.elapsed().as_secs()),
+            },
+        }
+    }
+}
+
+impl Readiness {
+    /// The status `/readyz` replies with
+    pub fn status(self) -> StatusCode {
+        match self {
+            Readiness::Ready => StatusCode::OK,
+            Readiness::NotReady => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+impl fmt::Display for Readiness {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
@@ -0,0 +1,56 @@
+use super::drain::{Drain, Readiness};
+use super::query::Query;
+use warp::http::StatusCode;
+
+#[test]
+fn draining_makes_the_instance_not_ready_until_undrained() {
+    let drain = Drain::default();
+    assert_eq!(drain.readiness(), Readiness::Ready);
+    assert_eq!(drain.status().draining_secs, None);
+
+    let status = drain.clone().set(true);
+    assert_eq!(status.readiness, Readiness::NotReady);
+    assert_eq!(status.draining_secs, Some(0));
+    assert_eq!(drain.readiness().status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    // Draining again is harmless
+    assert_eq!(drain.set(true).readiness, Readiness::NotReady);
+
+    let status = drain.set(false);
+    assert_eq!(status.readiness, Readiness::Ready);
+    assert_eq!(status.draining_secs, None);
+    assert_eq!(drain.readiness().status(), StatusCode::OK);
+    assert_eq!(drain.set(false).readiness, Readiness::Ready);
+}
+
+#[test]
+fn readiness_is_reported_in_plain_text_and_json() {
+    assert_eq!(Readiness::NotReady.to_string(), "NotReady");
+    let status = Drain::default().set(true);
+    assert_eq!(
+        serde_json::to_string(&status).expect("serializable"),
+        r#"{"readiness":"NotReady","draining_secs":0}"#
+    );
+}
+
+#[test]
+fn shutting_down_refuses_new_clients_for_good() {
+    let query = || Query {
+        access_token: None,
+        stream: "public".to_string(),
+        media: false,
+        hashtag: String::new(),
+        list: 0,
+        client_uuid: None,
+        event_types: Default::default(),
+    };
+    let drain = Drain::default();
+    assert!(drain.admit(query()).is_ok());
+
+    drain.clone().shut_down();
+    assert_eq!(drain.readiness(), Readiness::NotReady);
+    assert!(drain.admit(query()).is_err());
+    // Undraining can't undo it
+    assert_eq!(drain.set(false).readiness, Readiness::NotReady);
+    assert!(drain.admit(query()).is_err());
+}
@@ -4,6 +4,10 @@ use std::fmt;
 pub enum Error {
     PgPool(r2d2::Error),
     Pg(postgres::Error),
+    /// OpenSSL couldn't be set up for `DB_SSLMODE`
+    Tls(openssl::error::ErrorStack),
+    /// The database lacks these columns, which authentication needs
+    Schema(Vec<String>),
 }
 
 impl std::error::Error for Error {}
@@ -14,6 +18,11 @@ impl fmt::Display for Error {
         let msg = match self {
             PgPool(e) => format!("{}", e),
             Pg(e) => format!("{}", e),
+            Tls(e) => format!("could not set up TLS for Postgres: {}", e),
+            Schema(missing) => format!(
+                "the database is missing {}, which Flodgatt needs to authenticate clients",
+                missing.join(", ")
+            ),
         };
         write!(f, "{}", msg)
     }
@@ -29,12 +38,19 @@ impl From<postgres::Error> for Error {
         Self::Pg(e)
     }
 }
+impl From<openssl::error::ErrorStack> for Error {
+    fn from(e: openssl::error::ErrorStack) -> Self {
+        Self::Tls(e)
+    }
+}
 
 #[derive(Debug)]
 pub enum Timeline {
     MissingHashtag,
     InvalidInput,
     BadTag,
+    /// The Redis channel would be longer than `MAX_CHANNEL_LEN`
+    TooLong,
 }
 
 impl std::error::Error for Timeline {}
@@ -51,8 +67,273 @@ impl fmt::Display for Timeline {
         let msg = match self {
             InvalidInput => "The timeline text from Redis could not be parsed into a supported timeline.  TODO: add incoming timeline text",
             MissingHashtag => "Attempted to send a hashtag timeline without supplying a tag name",
-            BadTag => "No hashtag exists with the specified hashtag ID"
+            BadTag => "No hashtag exists with the specified hashtag ID",
+            TooLong => "The timeline's Redis channel would be too long to subscribe to",
         };
         write!(f, "{}", msg)
     }
 }
+
+/// A request that another Flodgatt instance should serve
+#[derive(Debug)]
+pub struct Redirect {
+    pub(super) location: String,
+    /// The header that names the other instance in the redirect
+    pub(super) header: &'static str,
+    pub(super) target: String,
+}
+
+impl std::error::Error for Redirect {}
+
+impl fmt::Display for Redirect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "This stream is served by {}", self.target)
+    }
+}
+
+/// A connection turned away by the `GEO_POLICY`, because of the country or AS it comes from
+#[derive(Debug, PartialEq)]
+pub enum Refused {
+    Rejected(String),
+    Throttled(String),
+}
+
+impl std::error::Error for Refused {}
+
+/// A query string that could mean more than one stream
+#[derive(Debug, PartialEq)]
+pub enum Ambiguous {
+    /// The parameter was sent more than once
+    Duplicate(&'static str),
+    /// Both a hashtag and a list were named
+    TagAndList,
+}
+
+impl std::error::Error for Ambiguous {}
+
+impl fmt::Display for Ambiguous {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            Ambiguous::Duplicate(param) => {
+                write!(
+                    f,
+                    "Error: The `{}` parameter was sent more than once",
+                    param
+                )
+            }
+            Ambiguous::TagAndList => write!(f, "Error: A stream can't have both a tag and a list"),
+        }
+    }
+}
+
+impl fmt::Display for Refused {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            Refused::Rejected(origin) => write!(f, "Connections from {} are not accepted", origin),
+            Refused::Throttled(origin) => {
+                write!(f, "Too many connections from {}; try again later", origin)
+            }
+        }
+    }
+}
+
+/// A `scope` for `POST /api/v1/streaming/admin/cache/clear` that names no cache
+#[derive(Debug, PartialEq)]
+pub struct UnknownScope(pub(super) String);
+
+impl std::error::Error for UnknownScope {}
+
+impl fmt::Display for UnknownScope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(
+            f,
+            "Error: `{}` is not a cache scope (expected tokens, tags, lists, or filters)",
+            self.0
+        )
+    }
+}
+
+/// A request for a stream type that is disabled (see `StreamSwitches`)
+#[derive(Debug, PartialEq)]
+pub struct Disabled(pub(super) String);
+
+impl std::error::Error for Disabled {}
+
+impl fmt::Display for Disabled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(
+            f,
+            "Error: The {} stream is disabled; try again later",
+            self.0
+        )
+    }
+}
+
+/// A `stream` for `POST /api/v1/streaming/admin/streams/{enable,disable}` that names no stream
+#[derive(Debug, PartialEq)]
+pub struct UnknownStream(pub(super) String);
+
+impl std::error::Error for UnknownStream {}
+
+impl fmt::Display for UnknownStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "Error: `{}` is not a stream type", self.0)
+    }
+}
+
+/// A `category` for `POST /api/v1/streaming/admin/event_modes/{strict,pass_through}` that
+/// names none
+#[derive(Debug, PartialEq)]
+pub struct UnknownCategory(pub(super) String);
+
+impl std::error::Error for UnknownCategory {}
+
+impl fmt::Display for UnknownCategory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "Error: `{}` is not a category of timeline", self.0)
+    }
+}
+
+/// A `subsystem` for `POST /api/v1/streaming/admin/subsystems/{start,stop}` that names none
+#[derive(Debug, PartialEq)]
+pub struct UnknownSubsystem(pub(super) String);
+
+impl std::error::Error for UnknownSubsystem {}
+
+impl fmt::Display for UnknownSubsystem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "Error: `{}` is not a subsystem", self.0)
+    }
+}
+
+/// A connection made while the instance is shutting down (see `Drain::shut_down`)
+#[derive(Debug, PartialEq)]
+pub struct ShuttingDown;
+
+impl std::error::Error for ShuttingDown {}
+
+impl fmt::Display for ShuttingDown {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "Error: The server is shutting down; try again shortly")
+    }
+}
+
+/// A connection from a token or address on the `Denylist`
+#[derive(Debug, PartialEq)]
+pub struct Banned;
+
+impl std::error::Error for Banned {}
+
+impl fmt::Display for Banned {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "Error: Access denied")
+    }
+}
+
+/// A request to `POST /api/v1/streaming/admin/denylist/{add,remove}` that names no valid entry
+#[derive(Debug, PartialEq)]
+pub enum InvalidEntry {
+    /// Neither a `token` nor an `ip` was sent
+    Missing,
+    /// The `ip` is not an IP address or range
+    Ip(String),
+}
+
+impl std::error::Error for InvalidEntry {}
+
+impl fmt::Display for InvalidEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            InvalidEntry::Missing => write!(f, "Error: Name a `token` or an `ip` to ban"),
+            InvalidEntry::Ip(ip) => write!(
+                f,
+                "Error: `{}` is not an IP address or range (e.g., 192.0.2.0/24)",
+                ip
+            ),
+        }
+    }
+}
+
+/// A numeric parameter that isn't a plain decimal number in range (e.g., `list=007`, `list=%201`,
+/// or `list=99999999999999999999`)
+#[derive(Debug, PartialEq)]
+pub struct InvalidNumber {
+    pub(super) param: &'static str,
+    pub(super) value: String,
+}
+
+impl std::error::Error for InvalidNumber {}
+
+impl fmt::Display for InvalidNumber {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(
+            f,
+            "Error: `{}` is not a valid `{}` (expected a number, without a sign or leading zeros)",
+            self.value, self.param
+        )
+    }
+}
+
+/// A name too long to be part of a Redis channel (e.g., a `tag` longer than
+/// `MAX_HASHTAG_LEN`)
+#[derive(Debug, PartialEq)]
+pub struct TooLong {
+    pub(super) param: &'static str,
+    pub(super) max: usize,
+}
+
+impl std::error::Error for TooLong {}
+
+impl fmt::Display for TooLong {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(
+            f,
+            "Error: The `{}` may be at most {} bytes long",
+            self.param, self.max
+        )
+    }
+}
+
+/// A request to `POST /api/v1/streaming/admin/disconnect` that names no valid client
+#[derive(Debug, PartialEq)]
+pub enum InvalidDisconnect {
+    /// None of a `user_id`, a `token`, or an `ip` was sent
+    Missing,
+    /// The `ip` is not an IP address or range
+    Ip(String),
+    /// The `code` can't be sent in a WebSocket close frame
+    Code(u16),
+    /// The `user_id` or `code` isn't a number
+    Number(InvalidNumber),
+}
+
+impl std::error::Error for InvalidDisconnect {}
+
+impl fmt::Display for InvalidDisconnect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            InvalidDisconnect::Missing => write!(
+                f,
+                "Error: Name a `user_id`, a `token`, or an `ip` to disconnect"
+            ),
+            InvalidDisconnect::Ip(ip) => write!(
+                f,
+                "Error: `{}` is not an IP address or range (e.g., 192.0.2.0/24)",
+                ip
+            ),
+            InvalidDisconnect::Code(code) => write!(
+                f,
+                "Error: {} is not a close code a server may send (use 1000, 1001, 1008, 1011, \
+                 or 3000-4999)",
+                code
+            ),
+            InvalidDisconnect::Number(invalid) => invalid.fmt(f),
+        }
+    }
+}
+
+impl From<InvalidNumber> for InvalidDisconnect {
+    fn from(invalid: InvalidNumber) -> Self {
+        InvalidDisconnect::Number(invalid)
+    }
+}
@@ -0,0 +1,44 @@
+//! The kinds of event a client asked for, so that (e.g.) a notification widget isn't sent every
+//! `update` only to discard it.  As with the `types` and `exclude_types` of Mastodon's
+//! notifications API, a client sends `types` to receive only those events, `exclude_types` to
+//! receive all but those, or both.  Each may be comma-separated (`types=update,delete`) or
+//! repeated (`types[]=update&types[]=delete`), and names events as Mastodon does (regardless of
+//! `EVENT_NAMES`).  Heartbeats are always sent.
+use hashbrown::HashSet;
+use url::form_urlencoded;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EventTypes {
+    /// Every kind of event is sent unless the client sent `types`
+    only: Option<HashSet<String>>,
+    excluded: HashSet<String>,
+}
+
+impl EventTypes {
+    /// The types named in a raw query string (other parameters are ignored)
+    pub(super) fn parse(raw_query: &str) -> Self {
+        let (mut only, mut excluded) = (HashSet::new(), HashSet::new());
+        for (key, value) in form_urlencoded::parse(raw_query.as_bytes()) {
+            let types = match key.trim_end_matches("[]") {
+                "types" => &mut only,
+                "exclude_types" => &mut excluded,
+                _ => continue,
+            };
+            let names = value
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty());
+            types.extend(names.map(String::from));
+        }
+        Self {
+            only: Some(only).filter(|only| !only.is_empty()),
+            excluded,
+        }
+    }
+
+    /// Whether to send the client events named `event`
+    pub fn allows(&self, event: &str) -> bool {
+        self.only.as_ref().map_or(true, |only| only.contains(event))
+            && !self.excluded.contains(event)
+    }
+}
@@ -0,0 +1,35 @@
+use super::event_types::EventTypes;
+
+#[test]
+fn every_type_is_allowed_by_default() {
+    for types in &[
+        EventTypes::default(),
+        EventTypes::parse("stream=user&types="),
+    ] {
+        assert!(types.allows("update"));
+        assert!(types.allows("notification"));
+    }
+}
+
+#[test]
+fn only_the_listed_types_are_allowed() {
+    let types = EventTypes::parse("stream=user&types=update,%20delete");
+    assert!(types.allows("update") && types.allows("delete"));
+    assert!(!types.allows("notification"));
+
+    // as sent by clients of Mastodon's notifications API
+    let types = EventTypes::parse("types[]=update&types[]=delete");
+    assert!(types.allows("update") && types.allows("delete"));
+    assert!(!types.allows("status.update"));
+}
+
+#[test]
+fn excluded_types_are_refused_even_if_listed() {
+    let types = EventTypes::parse("exclude_types=update");
+    assert!(!types.allows("update"));
+    assert!(types.allows("notification"));
+
+    let types = EventTypes::parse("types=update,delete&exclude_types[]=delete");
+    assert!(types.allows("update"));
+    assert!(!types.allows("delete"));
+}
@@ -0,0 +1,165 @@
+//! Tag, throttle, or turn away connections according to where they come from
+//!
+//! The `GEO_POLICY` maps countries (e.g., `CN`) and autonomous systems (e.g., `AS16509`) to an
+//! action, and the `GEOIP_DATABASE` files (MaxMind's Country and ASN databases) say which
+//! country and AS each new connection comes from.  This is mostly for pushing back on
+//! scrapers in datacenters that read the public timelines.  When both a connection's country
+//! and its AS have an action, the stricter one applies.  Builds without the `geoip` feature
+//! can't read the databases, so no connection has a country or AS there.
+use super::err::Refused;
+use crate::config::{self, Deployment, GeoActionInner as Action};
+use crate::metrics;
+
+use hashbrown::HashMap;
+#[cfg(feature = "geoip")]
+use maxminddb::{MaxMindDBError, Reader};
+#[cfg(feature = "geoip")]
+use serde::Deserialize;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Instant;
+
+/// The `GEO_POLICY`, or no policy at all (which accepts every connection)
+#[derive(Clone, Default)]
+pub struct GeoPolicy(Option<Arc<Inner>>);
+
+struct Inner {
+    #[cfg(feature = "geoip")]
+    databases: Vec<Reader<Vec<u8>>>,
+    actions: HashMap<String, Action>,
+    per_minute: f64,
+    /// Connections left (and when they were last counted) for each throttled country or AS
+    allowances: Mutex<HashMap<String, (f64, Instant)>>,
+}
+
+/// The parts of a Country or ASN database record that the policy uses
+#[cfg(feature = "geoip")]
+#[derive(Deserialize)]
+struct Record {
+    country: Option<Country>,
+    autonomous_system_number: Option<u32>,
+}
+
+#[cfg(feature = "geoip")]
+#[derive(Deserialize)]
+struct Country {
+    iso_code: Option<String>,
+}
+
+impl GeoPolicy {
+    pub fn from_cfg(cfg: &Deployment) -> Result<Self, config::Error> {
+        if cfg.geo_policy.is_empty() {
+            return Ok(Self::default());
+        }
+        if cfg.geoip_database.is_empty() {
+            tracing::warn!("GEO_POLICY is set, but it has no effect without a GEOIP_DATABASE");
+        }
+        #[cfg(feature = "geoip")]
+        let databases = cfg
+            .geoip_database
+            .iter()
+            .map(|path| {
+                Reader::open_readfile(path).map_err(|e| {
+                    config::Error::Config(format!("could not open GEOIP_DATABASE {}: {}", path, e))
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self(Some(Arc::new(Inner {
+            #[cfg(feature = "geoip")]
+            databases,
+            actions: cfg.geo_policy.0.clone(),
+            per_minute: f64::from(*cfg.geo_throttle),
+            allowances: Mutex::new(HashMap::new()),
+        }))))
+    }
+
+    /// Apply the policy to a new connection from `ip` (or from an unknown address, e.g. over a
+    /// Unix socket, which is always accepted).
+    pub(super) fn check(&self, ip: Option<IpAddr>) -> Result<(), Refused> {
+        match (&self.0, ip) {
+            (Some(inner), Some(ip)) => self.decide(&inner.origins(ip)).map_err(|refused| {
+                tracing::info!("Refused a connection from {}: {}", ip, refused);
+                refused
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Apply the policy to a connection from the `origins` (a country code and an AS, at most)
+    pub(super) fn decide(&self, origins: &[String]) -> Result<(), Refused> {
+        let inner = match &self.0 {
+            Some(inner) => inner,
+            None => return Ok(()),
+        };
+        let strictest = origins
+            .iter()
+            .filter_map(|origin| Some((*inner.actions.get(origin)?, origin)))
+            .max_by_key(|(action, _)| *action);
+
+        match strictest {
+            None => Ok(()),
+            Some((Action::Tag, origin)) => {
+                tracing::info!("Accepted a connection from {}", origin);
+                metrics::GEO_TAGGED.inc();
+                Ok(())
+            }
+            Some((Action::Throttle, origin)) if inner.allow(origin) => Ok(()),
+            Some((Action::Throttle, origin)) => {
+                metrics::GEO_THROTTLED.inc();
+                Err(Refused::Throttled(origin.clone()))
+            }
+            Some((Action::Reject, origin)) => {
+                metrics::GEO_REJECTED.inc();
+                Err(Refused::Rejected(origin.clone()))
+            }
+        }
+    }
+}
+
+impl Inner {
+    /// The country code and AS (e.g., `AS16509`) of `ip`, as far as the databases know them
+    #[cfg(feature = "geoip")]
+    fn origins(&self, ip: IpAddr) -> Vec<String> {
+        let mut origins = Vec::new();
+        for database in &self.databases {
+            match database.lookup::<Record>(ip) {
+                Ok(record) => {
+                    let country = record.country.and_then(|country| country.iso_code);
+                    origins.extend(country);
+                    let asn = record.autonomous_system_number;
+                    origins.extend(asn.map(|asn| format!("AS{}", asn)));
+                }
+                Err(MaxMindDBError::AddressNotFoundError(_)) => (),
+                Err(e) => tracing::error!("Could not look up {} in a GEOIP_DATABASE: {}", ip, e),
+            }
+        }
+        origins
+    }
+
+    #[cfg(not(feature = "geoip"))]
+    fn origins(&self, _ip: IpAddr) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Whether `origin` has a connection left this minute (which it then uses up)
+    fn allow(&self, origin: &str) -> bool {
+        let now = Instant::now();
+        let mut allowances = self
+            .allowances
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let (left, counted_at) = allowances
+            .entry(origin.to_string())
+            .or_insert((self.per_minute, now));
+        let refilled = now.duration_since(*counted_at).as_secs_f64() * self.per_minute / 60.0;
+        *left = (*left + refilled).min(self.per_minute);
+        *counted_at = now;
+        if *left >= 1.0 {
+            *left -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
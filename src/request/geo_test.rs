@@ -0,0 +1,58 @@
+use super::err::Refused;
+use super::GeoPolicy;
+use crate::config::{
+    Deployment,
+    GeoActionInner::{self, *},
+};
+
+fn policy(rules: &[(&str, GeoActionInner)], per_minute: u32) -> GeoPolicy {
+    let mut cfg = Deployment::default();
+    cfg.geo_policy.0 = rules.iter().map(|(k, v)| (k.to_string(), *v)).collect();
+    cfg.geo_throttle.0 = per_minute;
+    GeoPolicy::from_cfg(&cfg).expect("no databases to open")
+}
+
+fn origins(origins: &[&str]) -> Vec<String> {
+    origins.iter().map(|origin| origin.to_string()).collect()
+}
+
+#[test]
+fn no_policy_accepts_everything() {
+    let policy = policy(&[], 1);
+    assert_eq!(policy.decide(&origins(&["CN", "AS16509"])), Ok(()));
+    assert_eq!(policy.check(Some([203, 0, 113, 1].into())), Ok(()));
+}
+
+#[test]
+fn unlisted_and_tagged_origins_are_accepted() {
+    let policy = policy(&[("AS16509", Reject), ("DE", Tag)], 1);
+    assert_eq!(policy.decide(&origins(&["US", "AS7922"])), Ok(()));
+    assert_eq!(policy.decide(&origins(&["DE", "AS3320"])), Ok(()));
+    assert_eq!(policy.decide(&[]), Ok(()));
+}
+
+#[test]
+fn the_strictest_action_applies() {
+    let policy = policy(&[("AS16509", Reject), ("US", Tag), ("SG", Throttle)], 5);
+    assert_eq!(
+        policy.decide(&origins(&["US", "AS16509"])),
+        Err(Refused::Rejected("AS16509".to_string()))
+    );
+    assert_eq!(
+        policy.decide(&origins(&["SG", "AS16509"])),
+        Err(Refused::Rejected("AS16509".to_string()))
+    );
+}
+
+#[test]
+fn throttled_origins_get_a_few_connections_each() {
+    let policy = policy(&[("AS14061", Throttle), ("AS24940", Throttle)], 3);
+    for _ in 0..3 {
+        assert_eq!(policy.decide(&origins(&["NL", "AS14061"])), Ok(()));
+    }
+    assert_eq!(
+        policy.decide(&origins(&["NL", "AS14061"])),
+        Err(Refused::Throttled("AS14061".to_string()))
+    );
+    assert_eq!(policy.decide(&origins(&["DE", "AS24940"])), Ok(()));
+}
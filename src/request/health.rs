@@ -0,0 +1,33 @@
+//! `GET /api/v1/streaming/health`, which fails (with a 503, for liveness and readiness probes)
+//! while any dependency the instance can't stream without fails its check: Postgres, the Redis
+//! connection that reads what Mastodon publishes, and the canary (if it's enabled).
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use warp::http::StatusCode;
+
+/// The dependencies checked so far that failed, each with why
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HealthChecks(BTreeMap<&'static str, String>);
+
+impl HealthChecks {
+    /// Add the outcome of the `dependency`'s check
+    pub fn check<E: Display>(mut self, dependency: &'static str, outcome: Result<(), E>) -> Self {
+        if let Err(e) = outcome {
+            self.0.insert(dependency, e.to_string());
+        }
+        self
+    }
+
+    /// The status and body to reply with: `OK`, or the failing dependencies as JSON, e.g.
+    /// `{"failing":{"postgres":"timed out waiting for connection"}}`
+    pub fn reply(&self) -> (StatusCode, String) {
+        match self.0.is_empty() {
+            true => (StatusCode::OK, "OK".to_string()),
+            false => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                json!({ "failing": self.0 }).to_string(),
+            ),
+        }
+    }
+}
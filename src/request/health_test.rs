@@ -0,0 +1,37 @@
+use super::health::HealthChecks;
+use warp::http::StatusCode;
+
+#[test]
+fn the_instance_is_healthy_while_every_check_passes() {
+    let checks = HealthChecks::default()
+        .check("postgres", Ok::<(), String>(()))
+        .check("redis", Ok::<(), String>(()));
+    assert_eq!(checks.reply(), (StatusCode::OK, "OK".to_string()));
+}
+
+#[test]
+fn a_failing_check_is_named_in_the_reply() {
+    let checks = HealthChecks::default()
+        .check("postgres", Ok::<(), String>(()))
+        .check("redis", Err("the pubsub connection to redis:6379 is down"));
+    assert_eq!(
+        checks.reply(),
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            r#"{"failing":{"redis":"the pubsub connection to redis:6379 is down"}}"#.to_string()
+        )
+    );
+
+    let checks = checks.check("postgres", Err("timed out waiting for connection"));
+    let (status, body) = checks.reply();
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    let body: serde_json::Value = serde_json::from_str(&body).expect("JSON");
+    assert_eq!(
+        body["failing"]["postgres"],
+        "timed out waiting for connection"
+    );
+    assert_eq!(
+        body["failing"].as_object().map(|failing| failing.len()),
+        Some(2)
+    );
+}
@@ -0,0 +1,48 @@
+//! `GET /api/v1/streaming/history`, which lists the latest events sent on a stream (see
+//! `EVENT_HISTORY`) to the admin, or to a user who could subscribe to the stream
+use super::err::UnknownStream;
+use super::postgres::PgPool;
+use super::query::Query;
+use super::tags::TagRegistry;
+use super::{is_admin_token, Stream, Subscription, Timeline};
+
+use warp::reject::{self, Rejection};
+
+/// The stream whose history is asked for, and how many of its latest events to list (by
+/// default, all of those kept)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HistoryRequest {
+    pub timeline: Timeline,
+    pub limit: Option<usize>,
+}
+
+/// The `Timeline` that `q` names, if its token may read it.  A user's token may read the
+/// streams the user could subscribe to, and the `ADMIN_TOKEN` any stream that isn't a user's
+/// own (the `user` and `direct` streams, which it has no user for).  Requests without a token
+/// are refused.
+pub(super) fn authorize(
+    q: Query,
+    admin_token: Option<&str>,
+    pool: PgPool,
+    tags: &TagRegistry,
+) -> Result<Timeline, Rejection> {
+    use Stream::*;
+    match q.access_token.as_deref() {
+        None => Err(reject::custom(PgPool::BAD_TOKEN)),
+        Some(token) if is_admin_token(admin_token, token) => {
+            let (timeline, _scope) = Timeline::parse(&q.stream, q.media)
+                .map_err(|_| reject::custom(UnknownStream(q.stream.clone())))?;
+            Ok(match timeline {
+                Timeline(Hashtag(_), reach, content) => {
+                    Timeline(Hashtag(tags.id(&q.hashtag)?), reach, content)
+                }
+                Timeline(List(_), reach, content) => Timeline(List(q.list), reach, content),
+                Timeline(User(_), ..) | Timeline(Direct(_), ..) => {
+                    Err(reject::custom(PgPool::BAD_TOKEN))?
+                }
+                public => public,
+            })
+        }
+        Some(_) => Ok(Subscription::query_postgres(q, pool, tags)?.timeline),
+    }
+}
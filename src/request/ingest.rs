@@ -0,0 +1,22 @@
+//! The body of a request to publish an event without going through Redis
+use serde_derive::Deserialize;
+use serde_json::Value;
+
+/// An event to deliver to the clients subscribed to `channel`
+#[derive(Deserialize, Debug, Clone)]
+pub struct Ingest {
+    /// The Redis channel the event would have been published to (e.g., `timeline:public`)
+    pub channel: String,
+    /// The message Mastodon would have published, either as JSON or as a string of JSON
+    pub payload: Value,
+}
+
+impl Ingest {
+    /// The payload as the text of a Redis message
+    pub fn event_txt(&self) -> String {
+        match &self.payload {
+            Value::String(txt) => txt.clone(),
+            other => other.to_string(),
+        }
+    }
+}
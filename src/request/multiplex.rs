@@ -0,0 +1,201 @@
+//! The messages a WebSocket client sends to add streams to (or remove them from) its connection
+use super::composite::Composites;
+use super::err::Disabled;
+use super::postgres::PgPool;
+use super::query::{self, Query};
+use super::subscription::Subscription;
+use super::switches::StreamSwitches;
+use super::tags::TagRegistry;
+use super::timeline::Timeline;
+
+use serde_derive::Deserialize;
+use std::fmt;
+
+/// A `subscribe` or `unsubscribe` message, in the format Mastodon's streaming server accepts
+/// (e.g., `{"type":"subscribe","stream":"hashtag","tag":"rust"}`)
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct Frame {
+    #[serde(rename = "type")]
+    pub kind: FrameKind,
+    pub stream: String,
+    #[serde(default)]
+    pub tag: String,
+    #[serde(default)]
+    pub list: String,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FrameKind {
+    Subscribe,
+    Unsubscribe,
+}
+
+impl Frame {
+    /// The name that Mastodon gives this stream in the `stream` field of its messages (e.g.,
+    /// `["hashtag", "rust"]`)
+    pub fn stream_name(&self) -> Vec<String> {
+        match self.stream.as_str() {
+            "hashtag" | "hashtag:local" => vec![self.stream.clone(), self.tag.clone()],
+            "list" => vec![self.stream.clone(), self.list.clone()],
+            _ => vec![self.stream.clone()],
+        }
+    }
+
+    /// The query that asks for the stream this frame names, as the connection (`opened_with`)
+    /// would have sent it.  The stream is parsed just as it is from a query string, so a
+    /// message can't add a stream that a new connection couldn't open.
+    pub(super) fn to_query(&self, opened_with: &Subscription) -> Result<Query, Refusal> {
+        Timeline::parse(&self.stream, false).map_err(|_| Refusal::Invalid)?;
+        Ok(Query {
+            access_token: opened_with.access_token.clone(),
+            stream: self.stream.clone(),
+            media: false,
+            hashtag: query::check_tag(self.tag.clone()).map_err(|_| Refusal::Invalid)?,
+            list: match self.list.as_str() {
+                "" => 0,
+                list => query::parse_id("list", list).map_err(|_| Refusal::Invalid)?,
+            },
+            client_uuid: opened_with.client_uuid.clone(),
+            event_types: opened_with.event_types.clone(),
+        })
+    }
+
+    /// The query that asks for the composite stream this frame names (see `Composites`), which
+    /// has no tag or list of its own
+    fn to_composite_query(&self, opened_with: &Subscription) -> Query {
+        Query {
+            access_token: opened_with.access_token.clone(),
+            stream: self.stream.clone(),
+            media: false,
+            hashtag: String::new(),
+            list: 0,
+            client_uuid: opened_with.client_uuid.clone(),
+            event_types: opened_with.event_types.clone(),
+        }
+    }
+}
+
+/// Authorizes the streams that clients add to their WebSocket connections
+#[derive(Clone)]
+pub struct Multiplexer {
+    pg_conn: PgPool,
+    tags: TagRegistry,
+    switches: StreamSwitches,
+    composites: Composites,
+    /// The most streams one connection may carry, including the one it opened with
+    pub max_streams: Option<usize>,
+}
+
+impl Multiplexer {
+    pub(super) fn new(
+        pg_conn: PgPool,
+        tags: TagRegistry,
+        switches: StreamSwitches,
+        composites: Composites,
+        max_streams: Option<usize>,
+    ) -> Self {
+        Self {
+            pg_conn,
+            tags,
+            switches,
+            composites,
+            max_streams,
+        }
+    }
+
+    /// The `Subscription` to the stream that `frame` asks for, authorized with the token that
+    /// the connection (`opened_with`) was opened with.
+    pub fn subscribe(
+        &self,
+        frame: &Frame,
+        opened_with: &Subscription,
+    ) -> Result<Subscription, Refusal> {
+        let q = match self.composites.contains(&frame.stream) {
+            true => frame.to_composite_query(opened_with),
+            false => frame.to_query(opened_with)?,
+        };
+        self.switches.check(&q).map_err(|_| Refusal::Disabled)?;
+        let subscription = self
+            .composites
+            .subscription(q, self.pg_conn.clone(), &self.tags)
+            .map_err(|rejection| {
+                match rejection.cause().map(|cause| cause.to_string()).as_deref() {
+                    _ if rejection.find_cause::<Disabled>().is_some() => Refusal::Disabled,
+                    Some(PgPool::BAD_TOKEN) => Refusal::Unauthorized,
+                    Some(PgPool::MISSING_HASHTAG) => Refusal::NoSuchStream,
+                    _ => Refusal::ServerError,
+                }
+            })?;
+        Ok(Subscription {
+            ip: opened_with.ip,
+            connection_id: opened_with.connection_id,
+            ..subscription
+        })
+    }
+}
+
+/// Why a `subscribe` or `unsubscribe` message was refused, which the client is told (with a
+/// stable `code`) in an error frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Refusal {
+    /// The message names no stream that exists
+    Invalid,
+    /// The connection's token doesn't grant access to the stream
+    Unauthorized,
+    /// The hashtag or list doesn't exist
+    NoSuchStream,
+    /// An `unsubscribe` for a stream the connection isn't subscribed to
+    NotSubscribed,
+    /// The connection already has `WS_MAX_SUBSCRIPTIONS` streams
+    TooManyStreams,
+    /// An operator has disabled the stream type (see `StreamSwitches`)
+    Disabled,
+    /// The server is subscribed to as many Redis channels as it allows (`REDIS_MAX_CHANNELS`,
+    /// or the limit for the stream's kind)
+    AtChannelLimit,
+    ServerError,
+}
+
+impl Refusal {
+    pub fn code(self) -> &'static str {
+        match self {
+            Refusal::Invalid => "invalid_stream",
+            Refusal::Unauthorized => "unauthorized",
+            Refusal::NoSuchStream => "no_such_stream",
+            Refusal::NotSubscribed => "not_subscribed",
+            Refusal::TooManyStreams => "too_many_streams",
+            Refusal::Disabled => "stream_disabled",
+            Refusal::AtChannelLimit => "at_capacity",
+            Refusal::ServerError => "server_error",
+        }
+    }
+
+    /// The HTTP status that Mastodon's streaming server would send for the same problem
+    pub fn status(self) -> u16 {
+        match self {
+            Refusal::Invalid => 400,
+            Refusal::Unauthorized => 401,
+            Refusal::NoSuchStream | Refusal::NotSubscribed => 404,
+            Refusal::TooManyStreams => 429,
+            Refusal::Disabled | Refusal::AtChannelLimit => 503,
+            Refusal::ServerError => 500,
+        }
+    }
+}
+
+impl fmt::Display for Refusal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let msg = match self {
+            Refusal::Invalid => "Error: Unknown stream type",
+            Refusal::Unauthorized => PgPool::BAD_TOKEN,
+            Refusal::NoSuchStream => "Error: No such stream",
+            Refusal::NotSubscribed => "Error: Not subscribed to this stream",
+            Refusal::TooManyStreams => "Error: Too many streams on this connection",
+            Refusal::Disabled => "Error: This stream is disabled; try again later",
+            Refusal::AtChannelLimit => "Error: The server is at capacity; try again later",
+            Refusal::ServerError => PgPool::SERVER_ERR,
+        };
+        write!(f, "{}", msg)
+    }
+}
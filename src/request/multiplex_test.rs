@@ -0,0 +1,76 @@
+use super::multiplex::{Frame, FrameKind, Refusal};
+use super::{Subscription, TagRegistry, Timeline};
+
+#[test]
+fn frames_parse_in_mastodons_format() {
+    let frame: Frame =
+        serde_json::from_str(r#"{"type":"subscribe","stream":"hashtag","tag":"rust"}"#)
+            .expect("valid frame");
+    assert_eq!(frame.kind, FrameKind::Subscribe);
+    assert_eq!(frame.stream_name(), vec!["hashtag", "rust"]);
+
+    let frame: Frame =
+        serde_json::from_str(r#"{"type":"unsubscribe","stream":"list","list":"12"}"#)
+            .expect("valid frame");
+    assert_eq!(frame.kind, FrameKind::Unsubscribe);
+    assert_eq!(frame.stream_name(), vec!["list", "12"]);
+
+    assert!(serde_json::from_str::<Frame>(r#"{"type":"ping"}"#).is_err());
+}
+
+#[test]
+fn frames_and_subscriptions_name_streams_alike() {
+    let tags = TagRegistry::new(1);
+    tags.insert("rust".to_string(), 1);
+    for (frame, timeline, hashtag_name) in vec![
+        (
+            r#"{"type":"subscribe","stream":"public:local"}"#,
+            "public:local",
+            None,
+        ),
+        (
+            r#"{"type":"subscribe","stream":"public:media"}"#,
+            "public:media",
+            None,
+        ),
+        (
+            r#"{"type":"subscribe","stream":"hashtag:local","tag":"rust"}"#,
+            "hashtag:rust:local",
+            Some("rust"),
+        ),
+        (
+            r#"{"type":"subscribe","stream":"list","list":"12"}"#,
+            "list:12",
+            None,
+        ),
+    ] {
+        let frame: Frame = serde_json::from_str(frame).expect("valid frame");
+        let subscription = Subscription {
+            timeline: Timeline::from_redis_text(timeline, &tags).expect("valid timeline"),
+            hashtag_name: hashtag_name.map(str::to_string),
+            ..Subscription::default()
+        };
+        assert_eq!(frame.stream_name(), subscription.stream_name());
+    }
+}
+
+#[test]
+fn frames_name_only_the_streams_a_query_could() {
+    let opened_with = Subscription::default();
+    let frame = |json: &str| serde_json::from_str::<Frame>(json).expect("valid frame");
+
+    let q = frame(r#"{"type":"subscribe","stream":"list","list":"12"}"#)
+        .to_query(&opened_with)
+        .expect("known stream");
+    assert_eq!((q.stream.as_str(), q.list), ("list", 12));
+
+    for json in &[
+        r#"{"type":"subscribe","stream":"public:remote"}"#,
+        r#"{"type":"subscribe","stream":"list","list":"012"}"#,
+    ] {
+        assert_eq!(
+            frame(json).to_query(&opened_with).err(),
+            Some(Refusal::Invalid)
+        );
+    }
+}
@@ -1,25 +1,77 @@
 //! Postgres queries
+use super::dev_users::DevUsers;
 use super::err;
 use super::timeline::{Scope, UserData};
-use crate::config;
+use crate::config::{self, PgSslInner};
 use crate::Id;
 
-use ::postgres::{self, SimpleQueryMessage};
+use self::schema::Schema;
+use self::statements::{Generations, Manager, Query};
+use super::cache::{CacheCleared, CacheScopes};
+use ::postgres;
+use ::postgres::config::SslMode;
 use hashbrown::HashSet;
-use r2d2_postgres::PostgresConnectionManager;
+use openssl::error::ErrorStack;
+use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+use postgres_openssl::MakeTlsConnector;
 use std::convert::TryFrom;
+use std::sync::Arc;
+use std::time::Duration;
 #[allow(deprecated)] // one fn is deprecated, not whole module
 use warp::reject;
 
+mod schema;
+mod statements;
+
 #[derive(Clone)]
 pub struct PgPool {
-    conn: r2d2::Pool<PostgresConnectionManager<postgres::NoTls>>,
+    conn: Backend,
     whitelist_mode: bool,
+    schema: Schema,
+    generations: Arc<Generations>,
+}
+
+#[derive(Clone)]
+enum Backend {
+    Postgres(r2d2::Pool<Manager>),
+    /// `--dev` mode, which has no database
+    Dev(Arc<DevUsers>),
 }
 
 type Result<T> = std::result::Result<T, err::Error>;
+
+/// How long the health check waits for a pooled connection (rather than the pool's 30 seconds,
+/// which outlasts a probe's timeout)
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(2);
 type Rejectable<T> = std::result::Result<T, warp::Rejection>;
 
+/// Whether to use TLS, as `DB_SSLMODE` says.  `allow` is treated as `prefer`, since a refused
+/// connection isn't retried.
+fn ssl_mode(mode: PgSslInner) -> SslMode {
+    match mode {
+        PgSslInner::Disable => SslMode::Disable,
+        PgSslInner::Allow | PgSslInner::Prefer => SslMode::Prefer,
+        PgSslInner::Require | PgSslInner::VerifyCa | PgSslInner::VerifyFull => SslMode::Require,
+    }
+}
+
+/// How far to trust the server's certificate, as libpq does: `require` (and `prefer`) encrypt
+/// without checking it, `verify-ca` checks that a trusted CA signed it, and `verify-full` also
+/// checks that it names the host connected to
+fn tls(mode: PgSslInner) -> std::result::Result<MakeTlsConnector, ErrorStack> {
+    let mut builder = SslConnector::builder(SslMethod::tls())?;
+    let verify_hostname = mode == PgSslInner::VerifyFull;
+    if !(verify_hostname || mode == PgSslInner::VerifyCa) {
+        builder.set_verify(SslVerifyMode::NONE);
+    }
+    let mut connector = MakeTlsConnector::new(builder.build());
+    connector.set_callback(move |config, _| {
+        config.set_verify_hostname(verify_hostname);
+        Ok(())
+    });
+    Ok(connector)
+}
+
 impl PgPool {
     pub(crate) const BAD_TOKEN: &'static str = "Error: Missing access token";
     pub(crate) const SERVER_ERR: &'static str = "Error: Internal server error";
@@ -31,62 +83,107 @@ impl PgPool {
         cfg.user(&pg_cfg.user)
             .host(&*pg_cfg.host.to_string())
             .port(*pg_cfg.port)
-            .dbname(&pg_cfg.database);
+            .dbname(&pg_cfg.database)
+            .ssl_mode(ssl_mode(*pg_cfg.ssl_mode));
         if let Some(password) = &*pg_cfg.password {
             cfg.password(password);
         };
+        let tls = tls(*pg_cfg.ssl_mode)?;
 
-        cfg.connect(postgres::NoTls)?; // Test connection, letting us immediately exit with an error
-                                       // when Postgres isn't running instead of timing out below
-        let manager = PostgresConnectionManager::new(cfg, postgres::NoTls);
+        // Test connection, letting us immediately exit with an error when Postgres isn't running
+        // (or isn't Mastodon's database) instead of timing out below
+        let schema = Schema::check(&mut cfg.connect(tls.clone())?)?;
+        let generations = Arc::new(Generations::default());
+        let manager = Manager::new(cfg, tls, schema, generations.clone());
 
         Ok(Self {
-            conn: r2d2::Pool::builder().max_size(10).build(manager)?,
+            conn: Backend::Postgres(r2d2::Pool::builder().max_size(10).build(manager)?),
             whitelist_mode,
+            schema,
+            generations,
         })
     }
 
+    /// A pool that answers from the `DEV_TOKENS` instead of a database
+    pub(crate) fn dev(users: DevUsers, whitelist_mode: bool) -> Self {
+        Self {
+            conn: Backend::Dev(Arc::new(users)),
+            whitelist_mode,
+            schema: Schema::FULL,
+            generations: Arc::default(),
+        }
+    }
+
+    /// Make every pooled connection prepare the queries in `scopes` again before it next runs
+    /// them, so that they pick up changes made to the database by hand.  Returns how many
+    /// pooled connections (if any) the cleared queries were prepared on.
+    pub(crate) fn clear_statements(&self, scopes: CacheScopes) -> CacheCleared {
+        use Query::*;
+        let scoped = [
+            (scopes.tokens, &[User][..]),
+            (scopes.tags, &[HashtagId, HashtagName][..]),
+            (scopes.lists, &[ListOwner][..]),
+            (scopes.filters, &[BlockedUsers, BlockingUsers, BlockedDomains][..]),
+        ];
+        for query in scoped.iter().filter(|(cleared, _)| *cleared).flat_map(|s| s.1) {
+            self.generations.clear(*query);
+        }
+
+        let connections = match &self.conn {
+            Backend::Postgres(pool) => pool.state().connections as usize,
+            Backend::Dev(_) => 0,
+        };
+        let count = |cleared: bool| Some(connections).filter(|_| cleared);
+        CacheCleared {
+            tokens: count(scopes.tokens),
+            tags: count(scopes.tags),
+            lists: count(scopes.lists),
+            filters: count(scopes.filters),
+            tag_names: None,
+        }
+    }
+
+    /// Check that Postgres answers a query on a pooled connection (which, in `--dev` mode, there
+    /// is no database to fail)
+    pub(crate) fn check(&self) -> Result<()> {
+        match &self.conn {
+            Backend::Postgres(pool) => Ok(pool.get_timeout(HEALTH_TIMEOUT)?.ping()?),
+            Backend::Dev(_) => Ok(()),
+        }
+    }
+
     fn is_safe(txt: &str) -> bool {
         txt.chars()
             .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
     }
 
     pub(crate) fn select_user(self, token: &Option<String>) -> Rejectable<UserData> {
-        let mut conn = self.conn.get().map_err(reject::custom)?;
+        let mut conn = match &self.conn {
+            Backend::Postgres(pool) => pool.get().map_err(reject::custom)?,
+            Backend::Dev(users) => return users.select_user(token, self.whitelist_mode),
+        };
 
         if let Some(token) = token {
             if !Self::is_safe(token) {
                 Err(reject::custom(Self::BAD_TOKEN))?;
             };
 
-            let rows = conn
-                .simple_query(&format!("
-SELECT oauth_access_tokens.resource_owner_id, users.account_id, users.chosen_languages, oauth_access_tokens.scopes
-  FROM oauth_access_tokens
-INNER JOIN users ON oauth_access_tokens.resource_owner_id = users.id
-  WHERE oauth_access_tokens.token='{}' AND oauth_access_tokens.revoked_at IS NULL
-LIMIT 1", &token.to_owned())
-                ).map_err(reject::custom)?;
-
-            let row = match rows.get(0) {
-                Some(postgres::SimpleQueryMessage::Row(row)) => row,
-                _ => Err(reject::custom(Self::PG_NULL))?, // Wildcard required by #[non_exhaustive]
-            };
+            let rows = conn.query(Query::User, &[token]).map_err(reject::custom)?;
+            let row = rows.get(0).ok_or_else(|| reject::custom(Self::PG_NULL))?;
 
-            let id = Id(get_col_or_reject(row, 1)?.parse().map_err(reject::custom)?);
+            let id = Id(row.try_get(1).map_err(reject::custom)?);
 
-            let allowed_langs: HashSet<_> = row
-                .try_get(2)
-                .map_err(reject::custom)? // looks like `Some("{en,eo,es}")`
-                .map_or_else(HashSet::new, |str| {
-                    str.trim_start_matches('{')
-                        .trim_end_matches('}')
-                        .split(',')
-                        .map(String::from)
-                        .collect()
-                });
+            let allowed_langs: HashSet<String> = row
+                .try_get::<_, Option<Vec<String>>>(2)
+                .map_err(reject::custom)?
+                .unwrap_or_default()
+                .into_iter()
+                .collect();
 
-            let mut scopes: HashSet<Scope> = get_col_or_reject(row, 3)?
+            let mut scopes: HashSet<Scope> = row
+                .try_get::<_, Option<String>>(3)
+                .map_err(reject::custom)?
+                .ok_or_else(|| reject::custom(Self::PG_NULL))?
                 .split(' ')
                 .filter_map(|scope| Scope::try_from(scope).ok())
                 .collect();
@@ -110,22 +207,43 @@ LIMIT 1", &token.to_owned())
     }
 
     pub(crate) fn select_hashtag_id(self, tag_name: &str) -> Rejectable<i64> {
-        if !Self::is_safe(tag_name) {
+        if !Self::is_safe(tag_name) || !self.schema.tags {
             Err(reject::custom(Self::MISSING_HASHTAG))?;
         };
 
-        let mut conn = self.conn.get().map_err(reject::custom)?;
+        let mut conn = match &self.conn {
+            Backend::Postgres(pool) => pool.get().map_err(reject::custom)?,
+            Backend::Dev(users) => return Ok(users.hashtag_id(tag_name)),
+        };
         let rows = conn
-            .simple_query(&format!(
-                "SELECT id FROM tags WHERE name='{}' LIMIT 1",
-                &tag_name
-            ))
+            .query(Query::HashtagId, &[&tag_name])
             .map_err(reject::custom)?;
-        match rows.get(0).ok_or_else(|| reject::custom(Self::PG_NULL))? {
-            SimpleQueryMessage::Row(row) => get_col_or_reject(row, 0),
-            _ => Err(reject::custom(Self::MISSING_HASHTAG))?,
+        match rows.get(0) {
+            Some(row) => row.try_get(0).map_err(reject::custom),
+            None => Err(reject::custom(Self::MISSING_HASHTAG)),
+        }
+    }
+
+    pub(crate) fn select_hashtag_name(self, tag_id: i64) -> Rejectable<String> {
+        if !self.schema.tags {
+            Err(reject::custom(Self::MISSING_HASHTAG))?;
+        };
+
+        let mut conn = match &self.conn {
+            Backend::Postgres(pool) => pool.get().map_err(reject::custom)?,
+            Backend::Dev(users) => {
+                return users
+                    .hashtag_name(tag_id)
+                    .ok_or_else(|| reject::custom(Self::MISSING_HASHTAG))
+            }
+        };
+        let rows = conn
+            .query(Query::HashtagName, &[&tag_id])
+            .map_err(reject::custom)?;
+        match rows.get(0) {
+            Some(row) => row.try_get(0).map_err(reject::custom),
+            None => Err(reject::custom(Self::MISSING_HASHTAG)),
         }
-        .map(|s| s.parse().map_err(reject::custom))?
     }
 
     /// Query Postgres for everyone the user has blocked or muted
@@ -133,21 +251,17 @@ LIMIT 1", &token.to_owned())
     /// **NOTE**: because we check this when the user connects, it will not include any blocks
     /// the user adds until they refresh/reconnect.
     pub(crate) fn select_blocked_users(self, user_id: Id) -> Rejectable<HashSet<Id>> {
-        let mut conn = self.conn.get().map_err(reject::custom)?;
-        conn.simple_query(&format!(
-            "SELECT target_account_id FROM blocks WHERE account_id = {0}
-                 UNION SELECT target_account_id FROM mutes WHERE account_id = {0}",
-            &*user_id
-        ))
-        .map_err(reject::custom)?
-        .iter()
-        .try_fold(HashSet::new(), |mut set, row| match row {
-            SimpleQueryMessage::Row(row) => {
-                set.insert(get_col_or_reject(row, 0)?.parse().map_err(reject::custom)?);
-                Ok(set)
-            }
-            _ => Ok(set),
-        })
+        let mut conn = match &self.conn {
+            Backend::Postgres(_) if !self.schema.blocks => return Ok(HashSet::new()),
+            Backend::Postgres(pool) => pool.get().map_err(reject::custom)?,
+            Backend::Dev(_) => return Ok(HashSet::new()),
+        };
+        conn.query(Query::BlockedUsers, &[&user_id.0])
+            .map_err(reject::custom)?
+            .iter()
+            .map(|row| row.try_get(0).map(Id))
+            .collect::<std::result::Result<_, _>>()
+            .map_err(reject::custom)
     }
 
     /// Query Postgres for everyone who has blocked the user
@@ -155,20 +269,17 @@ LIMIT 1", &token.to_owned())
     /// **NOTE**: because we check this when the user connects, it will not include any blocks
     /// the user adds until they refresh/reconnect.
     pub(crate) fn select_blocking_users(self, user_id: Id) -> Rejectable<HashSet<Id>> {
-        let mut conn = self.conn.get().map_err(reject::custom)?;
-        conn.simple_query(&format!(
-            "SELECT account_id FROM blocks WHERE target_account_id = {}",
-            &*user_id
-        ))
-        .map_err(reject::custom)?
-        .iter()
-        .try_fold(HashSet::new(), |mut set, row| match row {
-            SimpleQueryMessage::Row(row) => {
-                set.insert(get_col_or_reject(row, 0)?.parse().map_err(reject::custom)?);
-                Ok(set)
-            }
-            _ => Ok(set),
-        })
+        let mut conn = match &self.conn {
+            Backend::Postgres(_) if !self.schema.blocks => return Ok(HashSet::new()),
+            Backend::Postgres(pool) => pool.get().map_err(reject::custom)?,
+            Backend::Dev(_) => return Ok(HashSet::new()),
+        };
+        conn.query(Query::BlockingUsers, &[&user_id.0])
+            .map_err(reject::custom)?
+            .iter()
+            .map(|row| row.try_get(0).map(Id))
+            .collect::<std::result::Result<_, _>>()
+            .map_err(reject::custom)
     }
 
     /// Query Postgres for all current domain blocks
@@ -176,45 +287,33 @@ LIMIT 1", &token.to_owned())
     /// **NOTE**: because we check this when the user connects, it will not include any blocks
     /// the user adds until they refresh/reconnect.
     pub(crate) fn select_blocked_domains(self, user_id: Id) -> Rejectable<HashSet<String>> {
-        let mut conn = self.conn.get().map_err(reject::custom)?;
-        conn.simple_query(&format!(
-            "SELECT domain FROM account_domain_blocks WHERE account_id = {}",
-            &*user_id,
-        ))
-        .map_err(reject::custom)?
-        .iter()
-        .try_fold(HashSet::new(), |mut set, row| match row {
-            SimpleQueryMessage::Row(row) => {
-                set.insert(get_col_or_reject(row, 0)?.to_string());
-                Ok(set)
-            }
-            _ => Ok(set),
-        })
+        let mut conn = match &self.conn {
+            Backend::Postgres(_) if !self.schema.domain_blocks => return Ok(HashSet::new()),
+            Backend::Postgres(pool) => pool.get().map_err(reject::custom)?,
+            Backend::Dev(_) => return Ok(HashSet::new()),
+        };
+        conn.query(Query::BlockedDomains, &[&user_id.0])
+            .map_err(reject::custom)?
+            .iter()
+            .map(|row| row.try_get(0))
+            .collect::<std::result::Result<_, _>>()
+            .map_err(reject::custom)
     }
 
     /// Test whether a user owns a list
     pub(crate) fn user_owns_list(self, user_id: Id, list_id: i64) -> Rejectable<bool> {
-        // For the Postgres query, `id` = list number; `account_id` = user.id
-        let mut conn = self.conn.get().map_err(reject::custom)?;
+        let mut conn = match &self.conn {
+            Backend::Postgres(_) if !self.schema.lists => return Ok(false),
+            Backend::Postgres(pool) => pool.get().map_err(reject::custom)?,
+            Backend::Dev(_) => return Ok(true), // every list belongs to whoever asks for it
+        };
         let rows = conn
-            .simple_query(&format!(
-                "SELECT id, account_id FROM lists WHERE id={} LIMIT 1",
-                &list_id,
-            ))
+            .query(Query::ListOwner, &[&list_id])
             .map_err(reject::custom)?;
 
-        match rows.get(0).ok_or_else(|| reject::custom(Self::PG_NULL))? {
-            SimpleQueryMessage::Row(row) => {
-                Ok(Id(get_col_or_reject(row, 1)?.parse().map_err(reject::custom)?) == user_id)
-            }
-            _ => Err(reject::custom(Self::MISSING_HASHTAG))?,
+        match rows.get(0) {
+            Some(row) => Ok(Id(row.try_get(0).map_err(reject::custom)?) == user_id),
+            None => Err(reject::custom(Self::MISSING_HASHTAG))?,
         }
     }
 }
-
-fn get_col_or_reject(row: &postgres::row::SimpleQueryRow, col: usize) -> Rejectable<&str> {
-    Ok(row
-        .try_get(col)
-        .map_err(reject::custom)?
-        .ok_or_else(|| reject::custom(PgPool::PG_NULL))?)
-}
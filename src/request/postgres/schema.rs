@@ -0,0 +1,124 @@
+//! Which of the tables and columns that Flodgatt queries the database actually has
+//!
+//! Forks of Mastodon sometimes change its schema.  Rather than failing on an SQL error each
+//! time a client connects, Flodgatt checks the schema once at startup: it refuses to start
+//! without the columns it needs to authenticate clients, and it disables (with a warning) the
+//! features that need any other missing table or column.
+use super::super::err;
+
+use ::postgres::{self, SimpleQueryMessage};
+use hashbrown::HashSet;
+
+#[cfg(test)]
+mod test;
+
+/// The columns that authentication needs
+const REQUIRED: [(&str, &str); 6] = [
+    ("oauth_access_tokens", "token"),
+    ("oauth_access_tokens", "resource_owner_id"),
+    ("oauth_access_tokens", "scopes"),
+    ("oauth_access_tokens", "revoked_at"),
+    ("users", "id"),
+    ("users", "account_id"),
+];
+
+/// The optional parts of the schema that the database has
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) struct Schema {
+    /// `users.chosen_languages`, for filtering public timelines by language
+    pub(super) chosen_languages: bool,
+    /// `tags`, for hashtag timelines
+    pub(super) tags: bool,
+    /// `blocks` and `mutes`, for filtering out statuses from blocked and muted accounts
+    pub(super) blocks: bool,
+    /// `account_domain_blocks`, for filtering out statuses from blocked domains
+    pub(super) domain_blocks: bool,
+    /// `lists`, for list timelines
+    pub(super) lists: bool,
+}
+
+impl Schema {
+    /// Every part of the schema (as in `--dev` mode, which has no database to check)
+    pub(super) const FULL: Self = Self {
+        chosen_languages: true,
+        tags: true,
+        blocks: true,
+        domain_blocks: true,
+        lists: true,
+    };
+
+    /// Read the tables and columns in the database's current schema.
+    pub(super) fn check(conn: &mut postgres::Client) -> Result<Self, err::Error> {
+        let columns = conn
+            .simple_query(
+                "SELECT table_name, column_name FROM information_schema.columns
+                   WHERE table_schema = current_schema()",
+            )?
+            .iter()
+            .filter_map(|msg| match msg {
+                SimpleQueryMessage::Row(row) => {
+                    Some((row.get(0)?.to_string(), row.get(1)?.to_string()))
+                }
+                _ => None,
+            })
+            .collect();
+        Self::from_columns(&columns)
+    }
+
+    /// The `Schema` of a database with the (`table`, `column`) pairs in `columns`, or an error
+    /// naming the `REQUIRED` columns it lacks
+    pub(super) fn from_columns(columns: &HashSet<(String, String)>) -> Result<Self, err::Error> {
+        let has = |table: &str, cols: &[&str]| {
+            cols.iter()
+                .all(|col| columns.contains(&(table.to_string(), col.to_string())))
+        };
+        let missing: Vec<_> = REQUIRED
+            .iter()
+            .filter(|(table, col)| !has(table, &[col]))
+            .map(|(table, col)| format!("{}.{}", table, col))
+            .collect();
+        if !missing.is_empty() {
+            return Err(err::Error::Schema(missing));
+        }
+
+        let schema = Self {
+            chosen_languages: has("users", &["chosen_languages"]),
+            tags: has("tags", &["id", "name"]),
+            blocks: has("blocks", &["account_id", "target_account_id"])
+                && has("mutes", &["account_id", "target_account_id"]),
+            domain_blocks: has("account_domain_blocks", &["account_id", "domain"]),
+            lists: has("lists", &["id", "account_id"]),
+        };
+        schema.warn();
+        Ok(schema)
+    }
+
+    fn warn(self) {
+        let disabled = [
+            (
+                self.chosen_languages,
+                "users.chosen_languages",
+                "language filtering",
+            ),
+            (self.tags, "tags", "hashtag timelines"),
+            (
+                self.blocks,
+                "blocks or mutes",
+                "filtering blocked and muted accounts",
+            ),
+            (
+                self.domain_blocks,
+                "account_domain_blocks",
+                "filtering blocked domains",
+            ),
+            (self.lists, "lists", "list timelines"),
+        ];
+        for (_, missing, feature) in disabled.iter().filter(|(present, ..)| !present) {
+            tracing::warn!(
+                "The database is missing (or has changed) {}, so {} is disabled",
+                missing,
+                feature
+            );
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use super::*;
+
+fn columns(tables: &[(&str, &[&str])]) -> HashSet<(String, String)> {
+    tables
+        .iter()
+        .flat_map(|(table, cols)| {
+            cols.iter()
+                .map(move |col| (table.to_string(), col.to_string()))
+        })
+        .collect()
+}
+
+const AUTH: [(&str, &[&str]); 2] = [
+    (
+        "oauth_access_tokens",
+        &["token", "resource_owner_id", "scopes", "revoked_at"],
+    ),
+    ("users", &["id", "account_id"]),
+];
+
+#[test]
+fn mastodon_schema_has_every_feature() {
+    let mut tables = AUTH.to_vec();
+    tables.extend_from_slice(&[
+        ("users", &["chosen_languages"][..]),
+        ("tags", &["id", "name"][..]),
+        ("blocks", &["account_id", "target_account_id"][..]),
+        ("mutes", &["account_id", "target_account_id"][..]),
+        ("account_domain_blocks", &["account_id", "domain"][..]),
+        ("lists", &["id", "account_id"][..]),
+    ]);
+    assert_eq!(
+        Schema::from_columns(&columns(&tables)).ok(),
+        Some(Schema::FULL)
+    );
+}
+
+#[test]
+fn missing_optional_tables_disable_features() {
+    let mut tables = AUTH.to_vec();
+    tables.extend_from_slice(&[
+        ("tags", &["id", "name"][..]),
+        ("blocks", &["account_id", "target_account_id"][..]),
+    ]);
+    let schema = Schema::from_columns(&columns(&tables)).ok();
+    let expected = Schema {
+        chosen_languages: false,
+        tags: true,
+        blocks: false, // no `mutes`
+        domain_blocks: false,
+        lists: false,
+    };
+    assert_eq!(schema, Some(expected));
+}
+
+#[test]
+fn missing_auth_columns_are_named() {
+    let tables = [
+        ("oauth_access_tokens", &["token", "resource_owner_id"][..]),
+        ("users", &["id", "account_id"][..]),
+    ];
+    match Schema::from_columns(&columns(&tables)) {
+        Err(err::Error::Schema(missing)) => assert_eq!(
+            missing,
+            vec![
+                "oauth_access_tokens.scopes",
+                "oauth_access_tokens.revoked_at"
+            ]
+        ),
+        other => panic!("expected missing columns, got {:?}", other.map(|_| ())),
+    }
+}
@@ -0,0 +1,205 @@
+//! The SQL Flodgatt sends to Postgres, prepared once on each pooled connection
+//!
+//! Every query is defined here, so this is the one place to adapt when Mastodon's schema
+//! changes (and the `Schema` says which queries a database supports).
+use super::schema::Schema;
+use crate::metrics::{self, Histogram};
+
+use ::postgres::{self, types::ToSql, Client, Row, Statement};
+use postgres_openssl::MakeTlsConnector;
+use r2d2_postgres::PostgresConnectionManager;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// How many queries there are
+const COUNT: usize = 7;
+
+/// Each query Flodgatt makes
+#[derive(Clone, Copy, Debug)]
+pub(super) enum Query {
+    /// The user an access token belongs to, with their languages and scopes
+    User,
+    HashtagId,
+    HashtagName,
+    /// The accounts a user has blocked or muted
+    BlockedUsers,
+    /// The accounts that have blocked a user
+    BlockingUsers,
+    BlockedDomains,
+    ListOwner,
+}
+
+impl Query {
+    const ALL: [Self; COUNT] = [
+        Query::User,
+        Query::HashtagId,
+        Query::HashtagName,
+        Query::BlockedUsers,
+        Query::BlockingUsers,
+        Query::BlockedDomains,
+        Query::ListOwner,
+    ];
+
+    /// The SQL for this query, or `None` if the `schema` lacks what it reads
+    fn sql(self, schema: Schema) -> Option<String> {
+        use Query::*;
+        Some(match self {
+            User => format!(
+                "SELECT oauth_access_tokens.resource_owner_id, users.account_id, {}, \
+                        oauth_access_tokens.scopes
+                   FROM oauth_access_tokens
+                   INNER JOIN users ON oauth_access_tokens.resource_owner_id = users.id
+                   WHERE oauth_access_tokens.token = $1::text
+                     AND oauth_access_tokens.revoked_at IS NULL
+                   LIMIT 1",
+                match schema.chosen_languages {
+                    true => "users.chosen_languages::text[]",
+                    false => "NULL::text[]",
+                }
+            ),
+            HashtagId if schema.tags => "SELECT id FROM tags WHERE name = $1::text LIMIT 1".into(),
+            HashtagName if schema.tags => "SELECT name FROM tags WHERE id = $1 LIMIT 1".into(),
+            BlockedUsers if schema.blocks => "SELECT target_account_id FROM blocks
+                                                WHERE account_id = $1
+                                              UNION SELECT target_account_id FROM mutes
+                                                WHERE account_id = $1"
+                .into(),
+            BlockingUsers if schema.blocks => {
+                "SELECT account_id FROM blocks WHERE target_account_id = $1".into()
+            }
+            BlockedDomains if schema.domain_blocks => {
+                "SELECT domain::text FROM account_domain_blocks WHERE account_id = $1".into()
+            }
+            ListOwner if schema.lists => {
+                "SELECT account_id FROM lists WHERE id = $1 LIMIT 1".into()
+            }
+            HashtagId | HashtagName | BlockedUsers | BlockingUsers | BlockedDomains | ListOwner => {
+                return None
+            }
+        })
+    }
+
+    fn latency(self) -> &'static Histogram {
+        use Query::*;
+        match self {
+            User => &metrics::PG_SELECT_USER,
+            HashtagId => &metrics::PG_SELECT_HASHTAG_ID,
+            HashtagName => &metrics::PG_SELECT_HASHTAG_NAME,
+            BlockedUsers => &metrics::PG_SELECT_BLOCKED_USERS,
+            BlockingUsers => &metrics::PG_SELECT_BLOCKING_USERS,
+            BlockedDomains => &metrics::PG_SELECT_BLOCKED_DOMAINS,
+            ListOwner => &metrics::PG_SELECT_LIST_OWNER,
+        }
+    }
+}
+
+/// Counts how many times each query has been cleared, so that pooled connections prepare it
+/// again (e.g., after the tables it reads were altered) without being closed
+#[derive(Default)]
+pub(super) struct Generations([AtomicUsize; COUNT]);
+
+impl Generations {
+    pub(super) fn clear(&self, query: Query) {
+        self.0[query as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn get(&self, query: Query) -> usize {
+        self.0[query as usize].load(Ordering::Relaxed)
+    }
+}
+
+/// A pooled connection, with every query its database supports already prepared
+pub(super) struct Connection {
+    client: Client,
+    schema: Schema,
+    statements: [Option<Statement>; COUNT],
+    /// The `Generations` each statement was prepared in
+    prepared: [usize; COUNT],
+    generations: Arc<Generations>,
+}
+
+impl Connection {
+    /// Run `query`.  A query the database doesn't support returns no rows.
+    pub(super) fn query(
+        &mut self,
+        query: Query,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<Vec<Row>, postgres::Error> {
+        let generation = self.generations.get(query);
+        if self.prepared[query as usize] != generation {
+            self.statements[query as usize] = match query.sql(self.schema) {
+                Some(sql) => Some(self.client.prepare(&sql)?),
+                None => None,
+            };
+            self.prepared[query as usize] = generation;
+        }
+        let statement = match &self.statements[query as usize] {
+            Some(statement) => statement,
+            None => return Ok(Vec::new()),
+        };
+        let started = Instant::now();
+        let rows = self.client.query(statement, params);
+        query.latency().observe(started.elapsed());
+        rows
+    }
+
+    /// Run the lightest query there is, to check that the database answers
+    pub(super) fn ping(&mut self) -> Result<(), postgres::Error> {
+        self.client.simple_query("SELECT 1").map(|_| ())
+    }
+}
+
+/// Opens `Connection`s for the pool
+pub(super) struct Manager {
+    inner: PostgresConnectionManager<MakeTlsConnector>,
+    schema: Schema,
+    generations: Arc<Generations>,
+}
+
+impl Manager {
+    pub(super) fn new(
+        cfg: postgres::Config,
+        tls: MakeTlsConnector,
+        schema: Schema,
+        generations: Arc<Generations>,
+    ) -> Self {
+        Self {
+            inner: PostgresConnectionManager::new(cfg, tls),
+            schema,
+            generations,
+        }
+    }
+}
+
+impl r2d2::ManageConnection for Manager {
+    type Connection = Connection;
+    type Error = postgres::Error;
+
+    fn connect(&self) -> Result<Connection, postgres::Error> {
+        let mut client = self.inner.connect()?;
+        let mut statements: [Option<Statement>; COUNT] = Default::default();
+        let mut prepared = [0; COUNT];
+        for &query in &Query::ALL {
+            prepared[query as usize] = self.generations.get(query);
+            if let Some(sql) = query.sql(self.schema) {
+                statements[query as usize] = Some(client.prepare(&sql)?);
+            }
+        }
+        Ok(Connection {
+            client,
+            schema: self.schema,
+            statements,
+            prepared,
+            generations: self.generations.clone(),
+        })
+    }
+
+    fn is_valid(&self, conn: &mut Connection) -> Result<(), postgres::Error> {
+        self.inner.is_valid(&mut conn.client)
+    }
+
+    fn has_broken(&self, conn: &mut Connection) -> bool {
+        self.inner.has_broken(&mut conn.client)
+    }
+}
@@ -21,7 +21,7 @@ type Rejectable<T> = std::result::Result<T, warp::Rejection>;
 impl PgPool {
     pub(crate) fn new(pg_cfg: &config::Postgres, whitelist_mode: bool) -> Result<Self> {
         let mut cfg = postgres::Config::new();
-        log::info!(
+        tracing::info!(
             "Connecting to postgres.\nuser: {:?}, password: {:?}",
             &pg_cfg.user,
             &pg_cfg.password
@@ -46,9 +46,9 @@ impl PgPool {
     }
 
     pub(crate) fn select_user(self, token: &Option<String>) -> Rejectable<UserData> {
-        log::info!("Running `select_user`");
+        tracing::info!("Running `select_user`");
         let mut conn = self.conn.get().map_err(warp::reject::custom)?;
-        log::info!("    got conn");
+        tracing::info!("    got conn");
         if let Some(token) = token {
             let query_rows = conn
                 .query("
@@ -59,10 +59,10 @@ INNER JOIN users ON oauth_access_tokens.resource_owner_id = users.id
 LIMIT 1",
                        &[&token.to_owned()],
                 ).map_err(warp::reject::custom)?;
-            log::info!("    got rows");
+            tracing::info!("    got rows");
             if let Some(result_columns) = query_rows.get(0) {
                 let id = Id(result_columns.get(1));
-                log::info!("    got id: {:?}", id);
+                tracing::info!("    got id: {:?}", id);
                 let allowed_langs = result_columns
                     .try_get::<_, Vec<_>>(2)
                     .unwrap_or_default()
@@ -1,8 +1,25 @@
 //! Validate query prarams with type checking
+use super::err::{Ambiguous, InvalidNumber, TooLong};
+use super::event_types::EventTypes;
+use super::timeline::MAX_HASHTAG_LEN;
 use serde_derive::Deserialize;
+use std::str::FromStr;
+use url::form_urlencoded;
 use warp::filters::BoxedFilter;
 use warp::Filter as WarpFilter;
 
+/// The parameters that choose (or authorize) the stream, which may each be sent only once
+const PARAMETERS: [&str; 8] = [
+    "stream",
+    "access_token",
+    "tag",
+    "list",
+    "only_media",
+    "instance",
+    "client_uuid",
+    "limit",
+];
+
 #[derive(Debug)]
 pub(crate) struct Query {
     pub(crate) access_token: Option<String>,
@@ -10,9 +27,13 @@ pub(crate) struct Query {
     pub(crate) media: bool,
     pub(crate) hashtag: String,
     pub(crate) list: i64,
+    pub(crate) client_uuid: Option<String>,
+    pub(crate) event_types: EventTypes,
 }
 
 impl Query {
+    /// Use the token from a header (the `Authorization` header for SSE, or
+    /// `Sec-WebSocket-Protocol` for WebSockets) over an `access_token` parameter.
     pub(crate) fn update_access_token(
         self,
         token: Option<String>,
@@ -56,15 +77,147 @@ impl Media {
     }
 }
 make_query_type!(Hashtag => tag: String);
-make_query_type!(List => list: i64);
+impl Hashtag {
+    /// The name of the hashtag, or "" if none was sent
+    pub(crate) fn name(self) -> Result<String, TooLong> {
+        check_tag(self.tag)
+    }
+}
+make_query_type!(List => list: Option<String>);
+impl List {
+    /// The id of the list, or 0 if none was sent
+    pub(crate) fn id(&self) -> Result<i64, InvalidNumber> {
+        self.list
+            .as_deref()
+            .map_or(Ok(0), |list| parse_id("list", list))
+    }
+}
+make_query_type!(Limit => limit: Option<String>);
+impl Limit {
+    /// How many events to list, if the client sent a limit
+    pub(crate) fn len(&self) -> Result<Option<usize>, InvalidNumber> {
+        self.limit
+            .as_deref()
+            .map(|limit| parse_number("limit", limit))
+            .transpose()
+    }
+}
 make_query_type!(Auth => access_token: Option<String>);
+make_query_type!(Instance => instance: Option<String>);
+make_query_type!(ClientUuid => client_uuid: Option<String>);
+make_query_type!(Scope => scope: Option<String>);
 make_query_type!(Stream => stream: String);
+make_query_type!(Subsystem => subsystem: String);
+make_query_type!(Category => category: String);
 impl ToString for Stream {
     fn to_string(&self) -> String {
         format!("{:?}", self)
     }
 }
 
+/// The `token` and/or `ip` (address or range) to add to or remove from the denylist
+#[derive(Deserialize, Debug, Default)]
+pub(crate) struct Deny {
+    pub(crate) token: Option<String>,
+    pub(crate) ip: Option<String>,
+}
+
+/// The `user_id`, `token`, and/or `ip` (address or range) of the clients to disconnect, and the
+/// WebSocket close `code` to send them (the numbers are checked with `parse_number`)
+#[derive(Deserialize, Debug, Default)]
+pub(crate) struct Disconnect {
+    pub(crate) user_id: Option<String>,
+    pub(crate) token: Option<String>,
+    pub(crate) ip: Option<String>,
+    pub(crate) code: Option<String>,
+}
+
+/// The `status_id` and/or `connection` whose delivery receipts to list (the numbers are checked
+/// with `parse_number`)
+#[derive(Deserialize, Debug, Default)]
+pub(crate) struct Receipts {
+    pub(crate) status_id: Option<String>,
+    pub(crate) connection: Option<String>,
+}
+
+/// Parse the `value` of the numeric parameter `param` strictly: only decimal digits, with no
+/// sign, whitespace, or leading zeros, and within the range of `T`.  (`str::parse` allows a
+/// `+`, and a lenient parse lets `007` and `7` name the same stream.)
+pub(super) fn parse_number<T: FromStr>(
+    param: &'static str,
+    value: &str,
+) -> Result<T, InvalidNumber> {
+    let invalid = || InvalidNumber {
+        param,
+        value: value.to_string(),
+    };
+    let digits = !value.is_empty() && value.bytes().all(|byte| byte.is_ascii_digit());
+    match digits && (value == "0" || !value.starts_with('0')) {
+        true => value.parse().map_err(|_| invalid()), // out of range
+        false => Err(invalid()),
+    }
+}
+
+/// Parse the `value` of `param` as an id, which Mastodon's are: a number greater than 0
+pub(super) fn parse_id(param: &'static str, value: &str) -> Result<i64, InvalidNumber> {
+    match parse_number(param, value)? {
+        0 => Err(InvalidNumber {
+            param,
+            value: value.to_string(),
+        }),
+        id => Ok(id),
+    }
+}
+
+/// Reject a hashtag `tag` longer than `MAX_HASHTAG_LEN`, whose channel would be too long
+pub(super) fn check_tag(tag: String) -> Result<String, TooLong> {
+    match tag.len() {
+        len if len > MAX_HASHTAG_LEN => Err(TooLong {
+            param: "tag",
+            max: MAX_HASHTAG_LEN,
+        }),
+        _ => Ok(tag),
+    }
+}
+
+/// Reject a query that sends one of the `PARAMETERS` more than once, or that names both a
+/// hashtag and a list, instead of letting whichever value happens to be parsed win.  Other
+/// parameters (e.g., a client's cache buster) are ignored.
+pub(super) fn check_unambiguous(raw: &str) -> Result<(), Ambiguous> {
+    let mut sent = Vec::new();
+    for (key, _) in form_urlencoded::parse(raw.as_bytes()) {
+        if let Some(&param) = PARAMETERS.iter().find(|&&param| param == key) {
+            if sent.contains(&param) {
+                return Err(Ambiguous::Duplicate(param));
+            }
+            sent.push(param);
+        }
+    }
+    if sent.contains(&"tag") && sent.contains(&"list") {
+        return Err(Ambiguous::TagAndList);
+    }
+    Ok(())
+}
+
+/// A filter that rejects ambiguous queries (see `check_unambiguous`)
+pub(super) fn unambiguous() -> BoxedFilter<()> {
+    warp::query::raw()
+        .or(warp::any().map(String::new))
+        .unify()
+        .and_then(|raw: String| check_unambiguous(&raw).map_err(warp::reject::custom))
+        .untuple_one()
+        .boxed()
+}
+
+/// The `types` and `exclude_types` parameters, which may be repeated (see `EventTypes`)
+pub(super) fn event_types() -> BoxedFilter<(EventTypes,)> {
+    warp::query::raw()
+        .or(warp::any().map(String::new))
+        .unify()
+        .map(|raw: String| EventTypes::parse(&raw))
+        .boxed()
+}
+
 pub(super) struct OptionalAccessToken;
 
 impl OptionalAccessToken {
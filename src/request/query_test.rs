@@ -0,0 +1,97 @@
+use super::cache::CacheScopes;
+use super::err::{Ambiguous, InvalidNumber, TooLong, UnknownScope};
+use super::query::{check_unambiguous, parse_id, parse_number, Hashtag, List};
+use super::timeline::MAX_HASHTAG_LEN;
+
+#[test]
+fn distinct_parameters_are_accepted() {
+    assert_eq!(check_unambiguous(""), Ok(()));
+    assert_eq!(
+        check_unambiguous("stream=hashtag&tag=rust&only_media=1"),
+        Ok(())
+    );
+    assert_eq!(
+        check_unambiguous("stream=list&list=1&access_token=abc"),
+        Ok(())
+    );
+    // parameters that don't choose the stream may repeat
+    assert_eq!(check_unambiguous("stream=public&_=1&_=2"), Ok(()));
+}
+
+#[test]
+fn repeated_parameters_are_rejected() {
+    let duplicate = Err(Ambiguous::Duplicate("stream"));
+    assert_eq!(check_unambiguous("stream=public&stream=user"), duplicate);
+    assert_eq!(check_unambiguous("stream=public&stream=public"), duplicate);
+    let duplicate = Err(Ambiguous::Duplicate("tag"));
+    assert_eq!(check_unambiguous("tag=rust&tag=go"), duplicate);
+    let duplicate = Err(Ambiguous::Duplicate("access_token"));
+    assert_eq!(
+        check_unambiguous("access_token=a&%61ccess_token=b"),
+        duplicate
+    );
+}
+
+#[test]
+fn tag_and_list_together_are_rejected() {
+    let query = "stream=list&list=1&tag=rust";
+    assert_eq!(check_unambiguous(query), Err(Ambiguous::TagAndList));
+}
+
+#[test]
+fn cache_scopes_default_to_all() {
+    assert_eq!(CacheScopes::parse(None), Ok(CacheScopes::ALL));
+    let scopes = CacheScopes::parse(Some("tags,filters")).expect("valid scopes");
+    assert!(scopes.tags && scopes.filters && !scopes.tokens && !scopes.lists);
+    assert_eq!(
+        CacheScopes::parse(Some("tags,users")),
+        Err(UnknownScope("users".to_string()))
+    );
+}
+
+#[test]
+fn ids_are_parsed_strictly() {
+    assert_eq!(parse_id("list", "12"), Ok(12));
+    assert_eq!(
+        parse_id("list", "9223372036854775807"),
+        Ok(i64::max_value())
+    );
+    let too_large = "9223372036854775808";
+    for value in &[
+        "", "0", "012", "+12", "-12", " 12", "12 ", "1_2", "0x12", "١٢", too_large,
+    ] {
+        let invalid = InvalidNumber {
+            param: "list",
+            value: value.to_string(),
+        };
+        assert_eq!(parse_id("list", value), Err(invalid));
+    }
+    assert_eq!(parse_number::<u16>("code", "0"), Ok(0));
+    assert!(parse_number::<u16>("code", "65536").is_err());
+}
+
+#[test]
+fn a_missing_list_is_list_zero() {
+    assert_eq!(List::default().id(), Ok(0));
+    let list = List {
+        list: Some("007".to_string()),
+    };
+    assert!(list.id().is_err());
+}
+
+#[test]
+fn tags_longer_than_a_channel_allows_are_rejected() {
+    let longest = "é".repeat(MAX_HASHTAG_LEN / 2);
+    let tag = Hashtag {
+        tag: longest.clone(),
+    };
+    assert_eq!(tag.name(), Ok(longest.clone()));
+    let tag = Hashtag {
+        tag: format!("{}a", longest),
+    };
+    let too_long = TooLong {
+        param: "tag",
+        max: MAX_HASHTAG_LEN,
+    };
+    assert_eq!(tag.name(), Err(too_long));
+}
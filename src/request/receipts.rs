@@ -0,0 +1,35 @@
+//! Searching the sampled delivery receipts (see `RECEIPT_LOG`), e.g. when a user reports that
+//! a status never arrived.  Requested with `GET /api/v1/streaming/admin/receipts`.
+use super::err::InvalidNumber;
+use super::query::{self, parse_id, parse_number};
+use crate::Id;
+
+/// The receipts to list: those of the `status_id` and/or `connection` given (or all of them)
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ReceiptQuery {
+    pub status_id: Option<Id>,
+    pub connection: Option<u64>,
+}
+
+impl ReceiptQuery {
+    pub(super) fn parse(q: query::Receipts) -> Result<Self, InvalidNumber> {
+        let status_id = match q.status_id {
+            Some(status_id) => Some(Id(parse_id("status_id", &status_id)?)),
+            None => None,
+        };
+        let connection = match q.connection {
+            Some(connection) => Some(parse_number("connection", &connection)?),
+            None => None,
+        };
+        Ok(Self {
+            status_id,
+            connection,
+        })
+    }
+
+    /// Whether the receipt of `status_id` for `connection` is asked for
+    pub fn matches(&self, status_id: Id, connection: Option<u64>) -> bool {
+        self.status_id.map_or(true, |id| id == status_id)
+            && self.connection.map_or(true, |c| Some(c) == connection)
+    }
+}
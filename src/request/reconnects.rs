@@ -0,0 +1,60 @@
+//! Trace each client's reconnects by the `client_uuid` it sends with every connection
+use super::Subscription;
+use crate::metrics;
+
+use lru::LruCache;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// How many clients to remember
+const CAPACITY: usize = 10_000;
+/// A reconnect sooner than this after the previous connection counts as rapid
+pub(super) const RAPID: Duration = Duration::from_secs(10);
+
+/// When each recently seen client last connected, and how many times it has connected
+#[derive(Clone)]
+pub(super) struct Reconnects(Arc<Mutex<LruCache<String, (Instant, u64)>>>);
+
+impl Reconnects {
+    pub(super) fn new() -> Self {
+        Self(Arc::new(Mutex::new(LruCache::new(CAPACITY))))
+    }
+
+    /// Log a new connection for `subscription`, counting it as a reconnect if its client has
+    /// connected before.
+    pub(super) fn observe(&self, subscription: Subscription) -> Subscription {
+        if let Some(uuid) = &subscription.client_uuid {
+            let (connections, since_last) = self.record(uuid, Instant::now());
+            match since_last {
+                Some(since_last) => tracing::info!(
+                    "Client {} connected to {:?} (connection {}, {:?} after the last)",
+                    uuid,
+                    subscription.timeline,
+                    connections,
+                    since_last
+                ),
+                None => tracing::info!("Client {} connected to {:?}", uuid, subscription.timeline),
+            }
+        }
+        subscription
+    }
+
+    /// Count a connection from `uuid` at `now`.  Returns how many connections the client has
+    /// made and how long it has been since the previous one (if it made one).
+    pub(super) fn record(&self, uuid: &str, now: Instant) -> (u64, Option<Duration>) {
+        let mut clients = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let (connections, since_last) = match clients.get(&uuid.to_string()) {
+            Some((last, connections)) => (connections + 1, Some(now.duration_since(*last))),
+            None => (1, None),
+        };
+        clients.put(uuid.to_string(), (now, connections));
+
+        if let Some(since_last) = since_last {
+            metrics::CLIENT_RECONNECTS.inc();
+            if since_last < RAPID {
+                metrics::CLIENT_RAPID_RECONNECTS.inc();
+            }
+        }
+        (connections, since_last)
+    }
+}
@@ -0,0 +1,31 @@
+use super::reconnects::{Reconnects, RAPID};
+use super::subscription::is_uuid;
+use std::time::{Duration, Instant};
+
+#[test]
+fn reconnects_are_counted_per_client() {
+    let reconnects = Reconnects::new();
+    let start = Instant::now();
+    let later = start + Duration::from_secs(3);
+    let (a, b) = ("client-a", "client-b");
+
+    assert_eq!(reconnects.record(a, start), (1, None));
+    assert_eq!(reconnects.record(b, start), (1, None));
+    assert_eq!(
+        reconnects.record(a, later),
+        (2, Some(Duration::from_secs(3)))
+    );
+    assert_eq!(reconnects.record(a, later + RAPID), (3, Some(RAPID)));
+}
+
+#[test]
+fn only_uuids_are_accepted() {
+    assert!(is_uuid("0f8fad5b-d9cb-469f-a165-70867728950e"));
+    assert!(is_uuid("0F8FAD5B-D9CB-469F-A165-70867728950E"));
+    assert!(!is_uuid("0f8fad5bd9cb469fa16570867728950e"));
+    assert!(!is_uuid("0f8fad5b-d9cb-469f-a165-70867728950g"));
+    assert!(!is_uuid(
+        "0f8fad5b-d9cb-469f-a165-70867728950e\nforged log line"
+    ));
+    assert!(!is_uuid(""));
+}
@@ -0,0 +1,81 @@
+//! Split the public and hashtag timelines between several Flodgatt instances
+//!
+//! When `SHARD_URLS` is set, each timeline is owned by exactly one instance, chosen by
+//! rendezvous hashing so that adding or removing an instance only moves the timelines that
+//! instance owned.  Requests for a timeline owned by another instance are redirected to it,
+//! so each instance only subscribes to (and parses) its own slice of Redis's public and
+//! hashtag channels.  User, list, and direct timelines (and composite streams) are always
+//! served locally.
+use super::err::Redirect;
+use super::{Stream, Subscription, Timeline};
+use crate::config::Deployment;
+
+use std::sync::Arc;
+use warp::path::FullPath;
+use warp::Rejection;
+
+#[derive(Debug, Clone, Default)]
+pub struct Shards {
+    urls: Arc<Vec<String>>,
+    index: usize,
+}
+
+impl Shards {
+    pub fn from_cfg(cfg: &Deployment) -> Self {
+        Self {
+            urls: Arc::new(cfg.shard_urls.0.clone()),
+            index: *cfg.shard_index,
+        }
+    }
+
+    /// The base URL of the instance that should serve `timeline`, if that isn't this one.
+    pub fn owner(&self, timeline: Timeline) -> Option<&str> {
+        let key = match timeline {
+            _ if self.urls.is_empty() => None,
+            Timeline(Stream::Hashtag(id), _, _) => Some(format!("hashtag:{}", id)),
+            Timeline(Stream::Public, _, _) => timeline.to_redis_raw_timeline(None).ok(),
+            Timeline(..) => None,
+        }?;
+        let (owner, _score) = self
+            .urls
+            .iter()
+            .enumerate()
+            .map(|(i, url)| (i, fnv1a(&[url.as_bytes(), b"\n", key.as_bytes()].concat())))
+            .max_by_key(|&(_, score)| score)?;
+        if owner == self.index {
+            None
+        } else {
+            Some(&self.urls[owner])
+        }
+    }
+
+    /// Pass `subscription` on if this instance owns its timeline; otherwise, reject it with a
+    /// redirect to the same path and query on the instance that does.
+    pub(super) fn route(
+        &self,
+        subscription: Subscription,
+        path: &FullPath,
+        query: &str,
+    ) -> Result<Subscription, Rejection> {
+        match self.owner(subscription.timeline) {
+            // A composite stream's timelines may be owned by different instances
+            _ if subscription.composite.is_some() => Ok(subscription),
+            None => Ok(subscription),
+            Some(shard) => {
+                let separator = if query.is_empty() { "" } else { "?" };
+                Err(warp::reject::custom(Redirect {
+                    location: [shard, path.as_str(), separator, query].concat(),
+                    header: "x-flodgatt-shard",
+                    target: shard.to_string(),
+                }))
+            }
+        }
+    }
+}
+
+/// The 64-bit FNV-1a hash, which (unlike `std`'s hasher) is the same in every build
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
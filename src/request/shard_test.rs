@@ -0,0 +1,66 @@
+use super::{Content::*, Reach::*, Shards, Stream::*, Timeline};
+use crate::config::Deployment;
+
+fn shards(urls: &[&str], index: usize) -> Shards {
+    let mut cfg = Deployment::default();
+    cfg.shard_urls.0 = urls.iter().map(|url| url.to_string()).collect();
+    cfg.shard_index.0 = index;
+    Shards::from_cfg(&cfg)
+}
+
+fn hashtags() -> impl Iterator<Item = Timeline> {
+    (0..1000).map(|id| Timeline(Hashtag(id), Federated, All))
+}
+
+#[test]
+fn unsharded_instances_serve_everything() {
+    let shards = shards(&[], 0);
+    assert!(hashtags().all(|tl| shards.owner(tl).is_none()));
+    assert_eq!(shards.owner(Timeline(Public, Federated, All)), None);
+}
+
+#[test]
+fn user_timelines_stay_local() {
+    let shards = shards(&["https://a", "https://b", "https://c"], 0);
+    for tl in (0..100).flat_map(|id| {
+        vec![
+            Timeline(User(crate::Id(id)), Federated, All),
+            Timeline(User(crate::Id(id)), Federated, Notification),
+            Timeline(List(id), Federated, All),
+            Timeline(Direct(id), Federated, All),
+        ]
+    }) {
+        assert_eq!(shards.owner(tl), None);
+    }
+}
+
+#[test]
+fn every_timeline_has_exactly_one_owner() {
+    let urls = ["https://a", "https://b", "https://c"];
+    let instances: Vec<_> = (0..urls.len()).map(|i| shards(&urls, i)).collect();
+    for tl in hashtags().chain(vec![
+        Timeline(Public, Federated, All),
+        Timeline(Public, Local, Media),
+    ]) {
+        let owners = instances.iter().filter(|s| s.owner(tl).is_none()).count();
+        assert_eq!(owners, 1, "{:?}", tl);
+    }
+    // and the hashtags are spread across the instances
+    for instance in &instances {
+        let owned = hashtags()
+            .filter(|&tl| instance.owner(tl).is_none())
+            .count();
+        assert!(owned > 200, "{}", owned);
+    }
+}
+
+#[test]
+fn removing_an_instance_only_moves_its_timelines() {
+    let before = shards(&["https://a", "https://b", "https://c"], 0);
+    let after = shards(&["https://a", "https://b"], 0);
+    for tl in hashtags() {
+        if before.owner(tl) != Some("https://c") {
+            assert_eq!(before.owner(tl), after.owner(tl), "{:?}", tl);
+        }
+    }
+}
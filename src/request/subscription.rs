@@ -5,12 +5,16 @@
 // use mock_postgres as postgres;
 // #[cfg(not(test))]
 
+use super::composite::Composite;
+use super::event_types::EventTypes;
 use super::postgres::PgPool;
 use super::query::Query;
+use super::tags::TagRegistry;
 use super::{Content, Reach, Stream, Timeline};
 use crate::Id;
 
 use hashbrown::HashSet;
+use std::net::IpAddr;
 
 use warp::reject::Rejection;
 
@@ -22,6 +26,23 @@ pub struct Subscription {
     pub blocks: Blocks,
     pub hashtag_name: Option<String>,
     pub access_token: Option<String>,
+    /// The user whose token opened the stream (`None` without a token), so that an admin can
+    /// disconnect them (see `Disconnect`)
+    pub user_id: Option<Id>,
+    /// The UUID a client sends (as `client_uuid`) with each of its connections, so that its
+    /// reconnects can be traced in the logs
+    pub client_uuid: Option<String>,
+    /// The address the client connected from, so that it can be disconnected if the address
+    /// is banned (see `Denylist`)
+    pub ip: Option<IpAddr>,
+    /// The kinds of event the client asked for (see `EventTypes`)
+    pub event_types: EventTypes,
+    /// The id of the client's connection (see `OpenConnection`), once it is open, so that
+    /// deliveries to it can be found (see `RECEIPT_LOG`)
+    pub connection_id: Option<u64>,
+    /// The composite stream this is a subscription to (see `Composites`), if it is one, whose
+    /// first timeline is the `timeline`
+    pub composite: Option<Composite>,
 }
 
 /// Blocked and muted users and domains
@@ -40,12 +61,22 @@ impl Default for Subscription {
             blocks: Blocks::default(),
             hashtag_name: None,
             access_token: None,
+            user_id: None,
+            client_uuid: None,
+            ip: None,
+            event_types: EventTypes::default(),
+            connection_id: None,
+            composite: None,
         }
     }
 }
 
 impl Subscription {
-    pub(super) fn query_postgres(q: Query, pool: PgPool) -> Result<Self, Rejection> {
+    pub(super) fn query_postgres(
+        q: Query,
+        pool: PgPool,
+        tags: &TagRegistry,
+    ) -> Result<Self, Rejection> {
         let user = pool.clone().select_user(&q.access_token)?;
         let timeline = {
             let tl = Timeline::from_query_and_user(&q, &user)?;
@@ -53,7 +84,7 @@ impl Subscription {
             use Stream::*;
             match tl {
                 Timeline(Hashtag(_), reach, stream) => {
-                    let tag = pool.select_hashtag_id(&q.hashtag)?;
+                    let tag = tags.id(&q.hashtag)?;
                     Timeline(Hashtag(tag), reach, stream)
                 }
                 Timeline(List(list_id), _, _) if !pool.user_owns_list(user.id, list_id)? => {
@@ -71,13 +102,67 @@ impl Subscription {
         Ok(Subscription {
             timeline,
             allowed_langs: user.allowed_langs,
-            blocks: Blocks {
-                blocking_users: pool.clone().select_blocking_users(user.id)?,
-                blocked_users: pool.clone().select_blocked_users(user.id)?,
-                blocked_domains: pool.select_blocked_domains(user.id)?,
-            },
+            blocks: Blocks::select(user.id, pool)?,
             hashtag_name,
+            user_id: Some(user.id).filter(|_| q.access_token.is_some()),
             access_token: q.access_token,
+            client_uuid: q.client_uuid.filter(|uuid| is_uuid(uuid)),
+            ip: None,
+            event_types: q.event_types,
+            connection_id: None,
+            composite: None,
         })
     }
 }
+
+impl Blocks {
+    /// The users and domains that the user `id` has blocked or muted, and the users that have
+    /// blocked them
+    pub(super) fn select(id: Id, pool: PgPool) -> Result<Self, Rejection> {
+        Ok(Self {
+            blocking_users: pool.clone().select_blocking_users(id)?,
+            blocked_users: pool.clone().select_blocked_users(id)?,
+            blocked_domains: pool.select_blocked_domains(id)?,
+        })
+    }
+}
+
+impl Subscription {
+    /// The name Mastodon gives the stream this `Subscription` is for (e.g., `["hashtag",
+    /// "rust"]`), which matches `Frame::stream_name`
+    pub fn stream_name(&self) -> Vec<String> {
+        if let Some(composite) = &self.composite {
+            return vec![composite.name.clone()];
+        }
+        let name = match self.timeline.stream_type() {
+            Some(name) => name.to_string(),
+            None => return Vec::new(),
+        };
+        match self.timeline {
+            Timeline(Stream::Hashtag(_), _, _) => {
+                vec![name, self.hashtag_name.clone().unwrap_or_default()]
+            }
+            Timeline(Stream::List(id), _, _) => vec![name, id.to_string()],
+            _ => vec![name],
+        }
+    }
+
+    /// Every timeline this subscription sends the events of, with the hashtag that each
+    /// hashtag timeline names: its `timeline`, or each of its composite stream's
+    pub fn timelines(&self) -> Vec<(Timeline, Option<String>)> {
+        match &self.composite {
+            Some(composite) => composite.timelines.clone(),
+            None => vec![(self.timeline, self.hashtag_name.clone())],
+        }
+    }
+}
+
+/// Whether `text` is a UUID in its usual hyphenated form (which keeps anything else a client
+/// sends out of the logs)
+pub(super) fn is_uuid(text: &str) -> bool {
+    text.len() == 36
+        && text.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
@@ -0,0 +1,100 @@
+//! The optional subsystems an operator can stop (and start again) at runtime, to isolate one
+//! that misbehaves during an incident without restarting Flodgatt.  Changed with
+//! `POST /api/v1/streaming/admin/subsystems/{start,stop}`.
+//!
+//! A stopped subsystem's task keeps running but does nothing: the `canary` skips its checks
+//! (and stops failing the health endpoint), the `generator` sends no events, `ipc` disconnects
+//! its sidecars and accepts no more, the `heartbeat` stops beating (so that the instance looks
+//! down), the `metrics` port answers 404, and `reconciliation` skips its rounds.
+use super::err::UnknownSubsystem;
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Every subsystem that can be stopped
+pub const SUBSYSTEMS: [&str; 6] = [
+    "canary",
+    "generator",
+    "heartbeat",
+    "ipc",
+    "metrics",
+    "reconciliation",
+];
+
+/// Shared by every clone, so that a change made through the admin API reaches every task
+#[derive(Debug, Clone)]
+pub struct Subsystems(Arc<BTreeMap<&'static str, Switch>>);
+
+/// Whether one subsystem is running, as the task that runs it checks
+#[derive(Debug, Clone)]
+pub struct Switch(Arc<AtomicBool>);
+
+/// The admin API's reply: every subsystem that is currently stopped
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct StoppedSubsystems {
+    pub stopped: Vec<String>,
+}
+
+impl Default for Subsystems {
+    fn default() -> Self {
+        let switches = SUBSYSTEMS.iter().map(|&name| (name, Switch::default()));
+        Self(Arc::new(switches.collect()))
+    }
+}
+
+impl Subsystems {
+    /// The switch of `name`, which must be one of the `SUBSYSTEMS`
+    pub fn switch(&self, name: &str) -> Switch {
+        match self.0.get(name) {
+            Some(switch) => switch.clone(),
+            None => panic!("`{}` is not a subsystem", name),
+        }
+    }
+
+    /// Start or stop each of `names`
+    pub fn set(&self, names: &[String], running: bool) -> StoppedSubsystems {
+        for name in names {
+            if let Some(switch) = self.0.get(name.as_str()) {
+                switch.0.store(running, Ordering::Relaxed);
+            }
+        }
+        let stopped = self.stopped();
+        tracing::warn!("Stopped subsystems: {:?}", stopped.stopped);
+        stopped
+    }
+
+    pub fn stopped(&self) -> StoppedSubsystems {
+        StoppedSubsystems {
+            stopped: (self.0.iter())
+                .filter(|(_, switch)| !switch.is_on())
+                .map(|(name, _)| (*name).to_string())
+                .collect(),
+        }
+    }
+}
+
+impl Default for Switch {
+    fn default() -> Self {
+        Self(Arc::new(AtomicBool::new(true)))
+    }
+}
+
+impl Switch {
+    pub fn is_on(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// The subsystems in the comma-separated `names`, all of which must exist
+pub(super) fn parse(names: &str) -> Result<Vec<String>, UnknownSubsystem> {
+    names
+        .split(',')
+        .map(str::trim)
+        .map(|name| match SUBSYSTEMS.contains(&name) {
+            true => Ok(name.to_string()),
+            false => Err(UnknownSubsystem(name.to_string())),
+        })
+        .collect()
+}
@@ -0,0 +1,25 @@
+use super::err::UnknownSubsystem;
+use super::subsystems::{self, Subsystems};
+
+#[test]
+fn stopped_subsystems_are_off_until_started() {
+    let subsystems = Subsystems::default();
+    let (canary, metrics) = (subsystems.switch("canary"), subsystems.switch("metrics"));
+    let stopping = subsystems::parse("canary, reconciliation").expect("valid subsystems");
+    assert_eq!(subsystems.set(&stopping, false).stopped, stopping);
+    assert!(!canary.is_on());
+    assert!(metrics.is_on());
+
+    // Every clone shares the switches
+    subsystems.clone().set(&["canary".to_string()], true);
+    assert!(canary.is_on());
+    assert_eq!(subsystems.stopped().stopped, vec!["reconciliation"]);
+}
+
+#[test]
+fn unknown_subsystems_are_rejected() {
+    assert_eq!(
+        subsystems::parse("canary,webhooks"),
+        Err(UnknownSubsystem("webhooks".to_string()))
+    );
+}
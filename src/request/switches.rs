@@ -0,0 +1,106 @@
+//! The stream types an operator has turned off instance-wide, e.g. the federated timeline
+//! during an attack.  Set at startup with `DISABLED_STREAMS` and changed at runtime with
+//! `POST /api/v1/streaming/admin/streams/{enable,disable}`.
+use super::err::{Disabled, UnknownCategory, UnknownStream};
+use super::query::Query;
+use super::timeline::Timeline;
+use crate::config::{Deployment, EVENT_CATEGORIES, STREAM_TYPES};
+
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use warp::reject::Rejection;
+
+/// Shared by every clone, so that a change made through the admin API applies to all routes
+#[derive(Debug, Clone, Default)]
+pub struct StreamSwitches(Arc<RwLock<BTreeSet<String>>>);
+
+/// The admin API's reply: every stream type that is currently disabled
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct DisabledStreams {
+    pub disabled: Vec<String>,
+}
+
+impl StreamSwitches {
+    pub fn from_cfg(cfg: &Deployment) -> Self {
+        let disabled = cfg.disabled_streams.iter().cloned().collect();
+        Self(Arc::new(RwLock::new(disabled)))
+    }
+
+    /// Pass `q` on unless it asks for a disabled stream type
+    pub(super) fn admit(&self, q: Query) -> Result<Query, Rejection> {
+        match self.check(&q) {
+            Ok(()) => Ok(q),
+            Err(disabled) => Err(warp::reject::custom(disabled)),
+        }
+    }
+
+    pub(super) fn check(&self, q: &Query) -> Result<(), Disabled> {
+        // `only_media=true` asks for `public:media` (or `public:local:media`)
+        let stream = Timeline::parse(&q.stream, q.media)
+            .ok()
+            .and_then(|(timeline, _scope)| timeline.stream_type())
+            .unwrap_or(q.stream.as_str());
+        self.check_stream(stream)
+    }
+
+    /// Whether the stream type `stream` (e.g., one that a composite stream is composed of) is
+    /// enabled
+    pub(super) fn check_stream(&self, stream: &str) -> Result<(), Disabled> {
+        match self.read().contains(stream) {
+            true => Err(Disabled(stream.to_string())),
+            false => Ok(()),
+        }
+    }
+
+    /// Enable or disable each of `streams` (which new clients are then served or refused;
+    /// clients already connected keep their streams)
+    pub fn set(&self, streams: &[String], enabled: bool) -> DisabledStreams {
+        let mut disabled = self.0.write().unwrap_or_else(|e| e.into_inner());
+        for stream in streams {
+            match enabled {
+                true => disabled.remove(stream),
+                false => disabled.insert(stream.clone()),
+            };
+        }
+        tracing::warn!("Disabled streams: {:?}", disabled);
+        DisabledStreams {
+            disabled: disabled.iter().cloned().collect(),
+        }
+    }
+
+    pub fn disabled(&self) -> DisabledStreams {
+        DisabledStreams {
+            disabled: self.read().iter().cloned().collect(),
+        }
+    }
+
+    fn read(&self) -> RwLockReadGuard<BTreeSet<String>> {
+        self.0.read().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// The stream types in the comma-separated `streams`, all of which must exist
+pub(super) fn parse(streams: &str) -> Result<Vec<String>, UnknownStream> {
+    streams
+        .split(',')
+        .map(str::trim)
+        .map(|stream| match STREAM_TYPES.contains(&stream) {
+            true => Ok(stream.to_string()),
+            false => Err(UnknownStream(stream.to_string())),
+        })
+        .collect()
+}
+
+/// The categories of timeline in the comma-separated `categories` (for `EVENT_MODES`), all of
+/// which must exist
+pub(super) fn parse_categories(categories: &str) -> Result<Vec<String>, UnknownCategory> {
+    categories
+        .split(',')
+        .map(str::trim)
+        .map(|category| match EVENT_CATEGORIES.contains(&category) {
+            true => Ok(category.to_string()),
+            false => Err(UnknownCategory(category.to_string())),
+        })
+        .collect()
+}
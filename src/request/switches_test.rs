@@ -0,0 +1,58 @@
+use super::err::{Disabled, UnknownCategory, UnknownStream};
+use super::query::Query;
+use super::switches::{self, StreamSwitches};
+
+fn query(stream: &str, media: bool) -> Query {
+    Query {
+        access_token: None,
+        stream: stream.to_string(),
+        media,
+        hashtag: String::new(),
+        list: 0,
+        client_uuid: None,
+        event_types: Default::default(),
+    }
+}
+
+#[test]
+fn disabled_streams_are_refused_until_enabled() {
+    let switches = StreamSwitches::default();
+    let public = switches::parse("public, public:media").expect("valid streams");
+    switches.set(&public, false);
+
+    assert_eq!(
+        switches.check(&query("public", false)),
+        Err(Disabled("public".to_string()))
+    );
+    // `only_media=true` asks for the `public:media` stream
+    assert_eq!(
+        switches.check(&query("public", true)),
+        Err(Disabled("public:media".to_string()))
+    );
+    assert_eq!(switches.check(&query("public:local", true)), Ok(()));
+    assert_eq!(switches.clone().disabled().disabled, public);
+
+    switches.set(&["public".to_string()], true);
+    assert_eq!(switches.check(&query("public", false)), Ok(()));
+    assert_eq!(switches.disabled().disabled, vec!["public:media"]);
+}
+
+#[test]
+fn unknown_stream_types_are_rejected() {
+    assert_eq!(
+        switches::parse("public,federated"),
+        Err(UnknownStream("federated".to_string()))
+    );
+}
+
+#[test]
+fn event_mode_categories_must_exist() {
+    assert_eq!(
+        switches::parse_categories("public, hashtag"),
+        Ok(vec!["public".to_string(), "hashtag".to_string()])
+    );
+    assert_eq!(
+        switches::parse_categories("public:local"),
+        Err(UnknownCategory("public:local".to_string()))
+    );
+}
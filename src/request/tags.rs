@@ -0,0 +1,221 @@
+//! The hashtags Flodgatt knows the ids of.  Mastodon names hashtag timelines by name (in
+//! Redis channels and client requests) but Flodgatt keys them by id, so both the request
+//! parser and the `RedisManager` translate between the two through one `TagRegistry`.
+//!
+//! Lookups are answered from two LRU caches (name to id, and id to name) of `TAG_CACHE_SIZE`
+//! hashtags each; a miss falls back to Postgres, when the registry has a pool.  Each client
+//! that subscribes to a hashtag caches it again, so the hashtags with subscribers stay fresh.
+//! With `TAG_CACHE_KEY`, the hashtags found in Postgres are also kept until the `RedisManager`
+//! saves them to Redis (see `save_lookups`).
+//!
+//! With `HASHTAG_ID_CHANNELS`, Redis channels name hashtags by id, so only client requests are
+//! translated (see `channel_tag` and `channel_id`).
+use super::postgres::PgPool;
+use crate::metrics;
+
+use hashbrown::HashSet;
+use lru::LruCache;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex, MutexGuard};
+use warp::reject::{self, Rejection};
+
+type Rejectable<T> = std::result::Result<T, Rejection>;
+
+/// Shared by every clone, so that a hashtag cached while parsing a request is known to the
+/// `RedisManager` (and to each of its Redis connections)
+#[derive(Clone)]
+pub struct TagRegistry(Arc<Mutex<Tags>>);
+
+struct Tags {
+    ids: LruCache<String, i64>,
+    names: LruCache<i64, String>,
+    pg_conn: Option<PgPool>,
+    /// The hashtags found in Postgres since they were last taken, if they are being saved
+    unsaved: Option<Vec<(String, i64)>>,
+    /// Whether Redis channels name hashtags by id (`HASHTAG_ID_CHANNELS`)
+    id_channels: bool,
+}
+
+impl TagRegistry {
+    pub const DEFAULT_SIZE: usize = 1000;
+
+    /// A registry that caches `capacity` hashtags (in each direction), without Postgres
+    pub fn new(capacity: usize) -> Self {
+        Self(Arc::new(Mutex::new(Tags {
+            ids: LruCache::new(capacity),
+            names: LruCache::new(capacity),
+            pg_conn: None,
+            unsaved: None,
+            id_channels: false,
+        })))
+    }
+
+    /// Look up the hashtags that aren't cached in `pg_conn`
+    pub(super) fn with_postgres(self, pg_conn: PgPool) -> Self {
+        self.lock().pg_conn = Some(pg_conn);
+        self
+    }
+
+    /// The id of the hashtag called `name`
+    pub fn id(&self, name: &str) -> Rejectable<i64> {
+        let pg_conn = {
+            let mut tags = self.lock();
+            if let Some(id) = counted(tags.ids.get(&name.to_string()).copied()) {
+                return Ok(id);
+            }
+            tags.pg_conn.clone()
+        };
+        match pg_conn {
+            Some(pg_conn) => {
+                let id = pg_conn.select_hashtag_id(name)?;
+                self.looked_up(name.to_string(), id);
+                Ok(id)
+            }
+            None => Err(reject::custom(PgPool::MISSING_HASHTAG)),
+        }
+    }
+
+    /// The name of the hashtag with `id`
+    pub fn name(&self, id: i64) -> Rejectable<String> {
+        let pg_conn = {
+            let mut tags = self.lock();
+            if let Some(name) = counted(tags.names.get(&id).cloned()) {
+                return Ok(name);
+            }
+            tags.pg_conn.clone()
+        };
+        match pg_conn {
+            Some(pg_conn) => {
+                let name = pg_conn.select_hashtag_name(id)?;
+                self.looked_up(name.clone(), id);
+                Ok(name)
+            }
+            None => Err(reject::custom(PgPool::MISSING_HASHTAG)),
+        }
+    }
+
+    /// Name hashtags by id in Redis channels from now on
+    pub fn use_id_channels(&self) {
+        self.lock().id_channels = true;
+    }
+
+    /// Whether Redis channels name hashtags by id
+    pub fn id_channels(&self) -> bool {
+        self.lock().id_channels
+    }
+
+    /// What Redis channels call the hashtag with `id`: its id, or else its name
+    pub fn channel_tag(&self, id: i64) -> Rejectable<String> {
+        match self.id_channels() {
+            true => Ok(id.to_string()),
+            false => self.name(id),
+        }
+    }
+
+    /// The id of the hashtag a Redis channel calls `tag`
+    pub fn channel_id(&self, tag: &str) -> Rejectable<i64> {
+        match self.id_channels() {
+            true => tag
+                .parse()
+                .map_err(|_| reject::custom(PgPool::MISSING_HASHTAG)),
+            false => self.id(tag),
+        }
+    }
+
+    /// Cache the hashtag `name` with `id` (in both directions), counting the hashtags evicted
+    /// to make room
+    pub fn insert(&self, name: String, id: i64) {
+        let mut tags = self.lock();
+        put(&mut tags.ids, name.clone(), id);
+        put(&mut tags.names, id, name);
+    }
+
+    /// Keep the hashtags found in Postgres from now on, until they are taken with `take_unsaved`
+    pub fn save_lookups(&self) {
+        self.lock().unsaved.get_or_insert_with(Vec::new);
+    }
+
+    /// The hashtags found in Postgres since the last call (none, unless `save_lookups`)
+    pub fn take_unsaved(&self) -> Vec<(String, i64)> {
+        self.lock()
+            .unsaved
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Cache a hashtag found in Postgres, keeping it to be saved if lookups are being saved
+    fn looked_up(&self, name: String, id: i64) {
+        if let Some(unsaved) = &mut self.lock().unsaved {
+            unsaved.push((name.clone(), id));
+        }
+        self.insert(name, id);
+    }
+
+    /// Drop every cached hashtag except those in `keep`, so that the rest are read again the
+    /// next time they are needed.  Returns how many hashtag names were dropped.
+    pub fn retain(&self, keep: &HashSet<i64>) -> usize {
+        let mut tags = self.lock();
+        let stale_names: Vec<String> = tags
+            .ids
+            .iter()
+            .filter(|(_, id)| !keep.contains(*id))
+            .map(|(name, _)| name.clone())
+            .collect();
+        let stale_ids: Vec<i64> = tags
+            .names
+            .iter()
+            .filter(|(id, _)| !keep.contains(*id))
+            .map(|(id, _)| *id)
+            .collect();
+        for name in &stale_names {
+            tags.ids.pop(name);
+        }
+        for id in &stale_ids {
+            tags.names.pop(id);
+        }
+        stale_names.len()
+    }
+
+    /// Whether the hashtag called `name` is cached, in both directions
+    pub fn contains(&self, name: &str) -> bool {
+        let tags = self.lock();
+        match tags.ids.peek(&name.to_string()) {
+            Some(id) => tags.names.contains(id),
+            None => false,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<Tags> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for TagRegistry {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_SIZE)
+    }
+}
+
+impl std::fmt::Debug for TagRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let tags = self.lock();
+        write!(f, "TagRegistry({} hashtags)", tags.ids.len())
+    }
+}
+
+/// Count a cache lookup as a hit or a miss
+fn counted<T>(cached: Option<T>) -> Option<T> {
+    match cached {
+        Some(_) => metrics::TAG_CACHE_HITS.inc(),
+        None => metrics::TAG_CACHE_MISSES.inc(),
+    }
+    cached
+}
+
+fn put<K: Hash + Eq, V>(cache: &mut LruCache<K, V>, k: K, v: V) {
+    if cache.len() == cache.cap() && !cache.contains(&k) {
+        metrics::TAG_CACHE_EVICTIONS.inc();
+    }
+    cache.put(k, v);
+}
@@ -0,0 +1,67 @@
+use super::dev_users::DevUsers;
+use super::postgres::PgPool;
+use super::tags::TagRegistry;
+use crate::config::Deployment;
+
+use hashbrown::HashSet;
+
+#[test]
+fn hashtags_are_cached_in_both_directions() {
+    let tags = TagRegistry::new(2);
+    tags.insert("rust".to_string(), 1);
+    tags.insert("go".to_string(), 2);
+    assert_eq!(tags.id("rust").ok(), Some(1));
+    assert_eq!(tags.name(2).ok(), Some("go".to_string()));
+
+    // Without Postgres, a hashtag that isn't cached can't be found
+    tags.insert("zig".to_string(), 3);
+    assert!(tags.id("go").is_err());
+    assert!(tags.name(2).is_err());
+
+    let keep: HashSet<i64> = vec![3].into_iter().collect();
+    assert_eq!(tags.retain(&keep), 1);
+    assert!(tags.contains("zig") && !tags.contains("rust"));
+}
+
+#[test]
+fn hashtags_missing_from_the_cache_are_looked_up_in_postgres() {
+    let pg_conn = PgPool::dev(DevUsers::from_cfg(&Deployment::default()), false);
+    let tags = TagRegistry::new(1).with_postgres(pg_conn);
+    let rust = tags.id("rust").expect("every hashtag exists in dev mode");
+    let go = tags.id("go").expect("every hashtag exists in dev mode");
+
+    assert!(!tags.contains("rust"));
+    assert_eq!(tags.name(rust).ok(), Some("rust".to_string()));
+    assert_eq!(tags.id("go").ok(), Some(go));
+}
+
+#[test]
+fn hashtags_found_in_postgres_are_kept_to_be_saved() {
+    let pg_conn = PgPool::dev(DevUsers::from_cfg(&Deployment::default()), false);
+    let tags = TagRegistry::new(10).with_postgres(pg_conn);
+    let rust = tags.id("rust").expect("every hashtag exists in dev mode");
+    assert!(tags.take_unsaved().is_empty());
+
+    tags.save_lookups();
+    tags.insert("go".to_string(), 2);
+    let zig = tags.id("zig").expect("every hashtag exists in dev mode");
+    assert_eq!(tags.id("rust").ok(), Some(rust)); // cached
+    assert_eq!(tags.take_unsaved(), vec![("zig".to_string(), zig)]);
+    assert!(tags.take_unsaved().is_empty());
+}
+
+#[test]
+fn redis_channels_can_name_hashtags_by_id() {
+    let tags = TagRegistry::new(1);
+    tags.insert("rust".to_string(), 1);
+    assert_eq!(tags.channel_tag(1).ok(), Some("rust".to_string()));
+    assert!(tags.channel_id("42").is_err());
+
+    // Nothing needs to be cached (or looked up) to translate channels by id
+    tags.clone().use_id_channels();
+    assert!(tags.id_channels());
+    assert_eq!(tags.channel_tag(42).ok(), Some("42".to_string()));
+    assert_eq!(tags.channel_id("42").ok(), Some(42));
+    assert!(tags.channel_id("rust").is_err());
+    assert_eq!(tags.id("rust").ok(), Some(1));
+}
@@ -1,9 +1,10 @@
 pub use self::inner::{Content, Reach, Scope, Stream};
 use super::err::Timeline as Error;
 use super::query::Query;
+use super::tags::TagRegistry;
+use crate::Id;
 pub(crate) use inner::UserData;
 
-use lru::LruCache;
 use warp::reject::Rejection;
 
 mod err;
@@ -11,6 +12,14 @@ mod inner;
 
 type Result<T> = std::result::Result<T, Error>;
 
+/// The longest hashtag name (in bytes) that a client may stream.  Mastodon doesn't limit the
+/// length of hashtags, but a pathological one would make every `SUBSCRIBE` that names it huge.
+pub const MAX_HASHTAG_LEN: usize = 256;
+
+/// The longest channel (without its namespace) that a timeline may be streamed from: a local
+/// hashtag timeline with the longest hashtag.  (Every other channel names at most an `i64`.)
+pub const MAX_CHANNEL_LEN: usize = "timeline:hashtag:".len() + MAX_HASHTAG_LEN + ":local".len();
+
 #[derive(Clone, Debug, Copy, Eq, Hash, PartialEq)]
 pub struct Timeline(pub Stream, pub Reach, pub Content);
 
@@ -35,10 +44,12 @@ impl Timeline {
         }
     }
 
+    /// The channel Mastodon publishes this timeline to, without a namespace.  A hashtag
+    /// timeline's channel names its `hashtag` as Redis does (see `TagRegistry::channel_tag`).
     pub(crate) fn to_redis_raw_timeline(&self, hashtag: Option<&String>) -> Result<String> {
         use {Content::*, Error::*, Reach::*, Stream::*};
 
-        Ok(match self {
+        let channel = match self {
             Timeline(Public, Federated, All) => "timeline:public".to_string(),
             Timeline(Public, Local, All) => "timeline:public:local".to_string(),
             Timeline(Public, Federated, Media) => "timeline:public:media".to_string(),
@@ -59,12 +70,16 @@ impl Timeline {
             Timeline(List(id), Federated, All) => ["timeline:list:", &id.to_string()].concat(),
             Timeline(Direct(id), Federated, All) => ["timeline:direct:", &id.to_string()].concat(),
             Timeline(_one, _two, _three) => Err(Error::InvalidInput)?,
-        })
+        };
+        match channel.len() {
+            len if len > MAX_CHANNEL_LEN => Err(TooLong),
+            _ => Ok(channel),
+        }
     }
 
-    pub fn from_redis_text(timeline: &str, cache: &mut LruCache<String, i64>) -> Result<Self> {
+    pub fn from_redis_text(timeline: &str, tags: &TagRegistry) -> Result<Self> {
         use {Content::*, Error::*, Reach::*, Stream::*};
-        let mut tag_id = |t: &str| cache.get(&t.to_string()).map_or(Err(BadTag), |id| Ok(*id));
+        let tag_id = |t: &str| tags.channel_id(t).map_err(|_| BadTag);
 
         Ok(match &timeline.split(':').collect::<Vec<&str>>()[..] {
             ["public"] => Timeline(Public, Federated, All),
@@ -81,46 +96,85 @@ impl Timeline {
         })
     }
 
+    /// Parse the stream a client names (e.g., `public:local`), with or without `only_media`,
+    /// and the scope that a token needs to read it.  Query strings and WebSocket `subscribe`
+    /// messages are both parsed here, so that they accept the same streams.  The ids in the
+    /// `Timeline` are placeholders until the stream is authorized (see `from_query_and_user`).
+    pub(crate) fn parse(stream: &str, media: bool) -> Result<(Self, Option<Scope>)> {
+        use {Content::*, Reach::*, Scope::*, Stream::*};
+
+        Ok(match (stream, media) {
+            ("public", false) => (Timeline(Public, Federated, All), None),
+            ("public", true) | ("public:media", _) => (Timeline(Public, Federated, Media), None),
+            ("public:local", false) => (Timeline(Public, Local, All), None),
+            ("public:local", true) | ("public:local:media", _) => {
+                (Timeline(Public, Local, Media), None)
+            }
+            ("hashtag", _) => (Timeline(Hashtag(0), Federated, All), None),
+            ("hashtag:local", _) => (Timeline(Hashtag(0), Local, All), None),
+            ("user", _) => (Timeline(User(Id(0)), Federated, All), Some(Statuses)),
+            ("user:notification", _) => (
+                Timeline(User(Id(0)), Federated, Notification),
+                Some(Statuses),
+            ),
+            ("list", _) => (Timeline(List(0), Federated, All), Some(Lists)),
+            ("direct", _) => (Timeline(Direct(0), Federated, All), Some(Statuses)),
+            (_other, _) => Err(Error::InvalidInput)?,
+        })
+    }
+
+    /// The name Mastodon gives this kind of stream (one of the `STREAM_TYPES`), which `parse`
+    /// accepts
+    pub(crate) fn stream_type(&self) -> Option<&'static str> {
+        use {Content::*, Reach::*, Stream::*};
+        Some(match self {
+            Timeline(Public, Federated, Media) => "public:media",
+            Timeline(Public, Local, Media) => "public:local:media",
+            Timeline(Public, Local, _) => "public:local",
+            Timeline(Public, _, _) => "public",
+            Timeline(Hashtag(_), Local, _) => "hashtag:local",
+            Timeline(Hashtag(_), _, _) => "hashtag",
+            Timeline(User(_), _, Notification) => "user:notification",
+            Timeline(User(_), _, _) => "user",
+            Timeline(List(_), _, _) => "list",
+            Timeline(Direct(_), _, _) => "direct",
+            Timeline(Unset, _, _) => return None,
+        })
+    }
+
+    /// The category of timeline this is (one of the `EVENT_CATEGORIES`), which `EVENT_MODES`
+    /// sets how to parse the events of
+    pub(crate) fn category(&self) -> Option<&'static str> {
+        Some(match self.0 {
+            Stream::Public => "public",
+            Stream::Hashtag(_) => "hashtag",
+            Stream::User(_) => "user",
+            Stream::List(_) => "list",
+            Stream::Direct(_) => "direct",
+            Stream::Unset => return None,
+        })
+    }
+
     pub(crate) fn from_query_and_user(
         q: &Query,
         user: &UserData,
     ) -> std::result::Result<Self, Rejection> {
-        use {warp::reject::custom, Content::*, Reach::*, Scope::*, Stream::*};
-
-        Ok(match q.stream.as_ref() {
-            "public" => match q.media {
-                true => Timeline(Public, Federated, Media),
-                false => Timeline(Public, Federated, All),
-            },
-            "public:local" => match q.media {
-                true => Timeline(Public, Local, Media),
-                false => Timeline(Public, Local, All),
-            },
-            "public:media" => Timeline(Public, Federated, Media),
-            "public:local:media" => Timeline(Public, Local, Media),
-
-            "hashtag" => Timeline(Hashtag(0), Federated, All),
-            "hashtag:local" => Timeline(Hashtag(0), Local, All),
-            "user" => match user.scopes.contains(&Statuses) {
-                true => Timeline(User(user.id), Federated, All),
-                false => Err(custom("Error: Missing access token"))?,
-            },
-            "user:notification" => match user.scopes.contains(&Statuses) {
-                true => Timeline(User(user.id), Federated, Notification),
-                false => Err(custom("Error: Missing access token"))?,
-            },
-            "list" => match user.scopes.contains(&Lists) {
-                true => Timeline(List(q.list), Federated, All),
-                false => Err(warp::reject::custom("Error: Missing access token"))?,
-            },
-            "direct" => match user.scopes.contains(&Statuses) {
-                true => Timeline(Direct(*user.id), Federated, All),
-                false => Err(custom("Error: Missing access token"))?,
-            },
-            other => {
-                log::warn!("Request for nonexistent endpoint: `{}`", other);
-                Err(custom("Error: Nonexistent endpoint"))?
+        use {warp::reject::custom, Stream::*};
+
+        let (timeline, scope) = Self::parse(&q.stream, q.media).map_err(|_| {
+            tracing::warn!("Request for nonexistent endpoint: `{}`", q.stream);
+            custom("Error: Nonexistent endpoint")
+        })?;
+        if let Some(scope) = scope {
+            if !user.scopes.contains(&scope) {
+                Err(custom("Error: Missing access token"))?
             }
+        }
+        Ok(match timeline {
+            Timeline(User(_), reach, content) => Timeline(User(user.id), reach, content),
+            Timeline(List(_), reach, content) => Timeline(List(q.list), reach, content),
+            Timeline(Direct(_), reach, content) => Timeline(Direct(*user.id), reach, content),
+            other => other,
         })
     }
 }
@@ -46,7 +46,7 @@ impl TryFrom<&str> for Scope {
             "read:lists" => Ok(Scope::Lists),
             "write" | "follow" => Err(Error::InvalidInput), // ignore write scopes
             unexpected => {
-                log::warn!("Ignoring unknown scope `{}`", unexpected);
+                tracing::warn!("Ignoring unknown scope `{}`", unexpected);
                 Err(Error::InvalidInput)
             }
         }
@@ -0,0 +1,79 @@
+use super::query::Query;
+use super::timeline::{Content, Reach, Scope, Stream, UserData, MAX_CHANNEL_LEN, MAX_HASHTAG_LEN};
+use super::Timeline;
+use crate::config::STREAM_TYPES;
+use crate::Id;
+
+#[test]
+fn every_stream_type_parses_to_a_timeline_with_that_name() {
+    for &stream in STREAM_TYPES.iter() {
+        let (timeline, _scope) = Timeline::parse(stream, false).expect("known stream");
+        assert_eq!(timeline.stream_type(), Some(stream));
+    }
+    assert!(Timeline::parse("public:remote", false).is_err());
+    assert!(Timeline::parse("", false).is_err());
+    assert_eq!(Timeline::empty().stream_type(), None);
+}
+
+#[test]
+fn only_media_asks_for_the_media_streams() {
+    use {Content::*, Reach::*, Stream::*};
+    for (stream, expected) in vec![
+        ("public", Timeline(Public, Federated, Media)),
+        ("public:local", Timeline(Public, Local, Media)),
+        ("public:media", Timeline(Public, Federated, Media)),
+        ("hashtag", Timeline(Hashtag(0), Federated, All)),
+    ] {
+        assert_eq!(
+            Timeline::parse(stream, true).expect("known stream").0,
+            expected
+        );
+    }
+}
+
+#[test]
+fn private_streams_need_a_scope_and_are_for_the_tokens_user() {
+    let user = UserData {
+        scopes: vec![Scope::Statuses].into_iter().collect(),
+        id: Id(7),
+        ..UserData::public()
+    };
+    let query = |stream: &str| Query {
+        access_token: Some("token".to_string()),
+        stream: stream.to_string(),
+        media: false,
+        hashtag: String::new(),
+        list: 12,
+        client_uuid: None,
+        event_types: Default::default(),
+    };
+    assert_eq!(Timeline::parse("public", false).expect("known").1, None);
+    assert_eq!(
+        Timeline::parse("list", false).expect("known").1,
+        Some(Scope::Lists)
+    );
+
+    let timeline = Timeline::from_query_and_user(&query("user:notification"), &user);
+    assert_eq!(
+        timeline.expect("authorized"),
+        Timeline(Stream::User(Id(7)), Reach::Federated, Content::Notification)
+    );
+    let timeline = Timeline::from_query_and_user(&query("direct"), &user);
+    assert_eq!(timeline.expect("authorized").0, Stream::Direct(7));
+    assert!(Timeline::from_query_and_user(&query("list"), &user).is_err());
+    assert!(Timeline::from_query_and_user(&query("federated"), &user).is_err());
+}
+
+#[test]
+fn no_channel_is_longer_than_the_longest_hashtag_allows() {
+    use {Content::*, Reach::*, Stream::*};
+    let longest = "a".repeat(MAX_HASHTAG_LEN);
+    let local = Timeline(Hashtag(1), Local, All);
+    let channel = local.to_redis_raw_timeline(Some(&longest));
+    assert_eq!(channel.expect("short enough").len(), MAX_CHANNEL_LEN);
+    assert!(local
+        .to_redis_raw_timeline(Some(&format!("{}a", longest)))
+        .is_err());
+    let user = Timeline(User(Id(i64::max_value())), Federated, Notification);
+    assert!(user.to_redis_raw_timeline(None).is_ok());
+}
@@ -1,18 +1,36 @@
 //! Stream the updates appropriate for a given `User`/`timeline` pair from Redis.
 
-pub use event::Event;
-pub use redis::Manager as RedisManager;
-pub use stream::{Sse as SseStream, Ws as WsStream};
+pub use canary::{Canary, Health as CanaryHealth};
+pub use event::{Event, EventFormat};
+#[cfg(any(test, feature = "generator"))]
+pub use generator::Generator;
+pub use hooks::{Hook, Hooks};
+pub use ipc::{IpcSink, LIFECYCLE_CHANNEL};
+pub use redis::{Manager as RedisManager, OpenConnection, PoolHealth, Receipt};
+pub use stream::{FirstByte, Multiplex, Sse as SseStream, Ws as WsStream};
 
 pub(self) use event::err::Event as EventErr;
 pub(self) use event::Payload;
 
+pub mod broadcast;
+mod canary;
 pub(crate) mod event;
+#[cfg(any(test, feature = "generator"))]
+mod generator;
+mod hooks;
+mod ipc;
 mod redis;
 mod stream;
 
 pub use redis::Error;
 
+#[cfg(test)]
+mod broadcast_test;
+#[cfg(test)]
+mod canary_test;
+#[cfg(test)]
+mod compat_test;
+
 #[cfg(feature = "bench")]
 pub use event::EventKind;
 #[cfg(feature = "bench")]
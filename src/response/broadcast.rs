@@ -0,0 +1,314 @@
+//! A broadcast channel for the events of one timeline, in place of the `Manager`'s channel per
+//! client: an event is written once to a ring that every receiver reads, and only the
+//! receivers that are waiting (whose tasks are kept in a slab) are woken.  Sending an event to
+//! 10,000 clients of a viral post's timeline then costs one write and the wake-ups, rather
+//! than a send per client.
+//!
+//! A receiver that falls more than the ring's capacity behind skips the events it missed and
+//! gets a `Lagged` error saying how many, much as `CLIENT_OVERFLOW=drop` drops them.
+//!
+//! The `Manager` sends the events of public and hashtag timelines this way, handing each of
+//! their clients a `Receiver` through its `ClientTx`; `benches/fanout.rs` compares that with
+//! the channel per client that the other timelines' events are sent through.
+use super::Event;
+use crate::metrics;
+
+use futures::task::{self, Task};
+use futures::{Async, Poll, Stream};
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, Weak};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::{RecvError, SendError, TrySendError};
+
+/// The sending half, of which there is one per timeline
+#[derive(Debug)]
+pub struct Sender {
+    shared: Arc<Shared>,
+}
+
+/// A client's half, which streams every event sent after it subscribed
+#[derive(Debug)]
+pub struct Receiver {
+    shared: Arc<Shared>,
+    /// The sequence number of the next event to read
+    next: u64,
+    /// This receiver's place in the slab of waiting tasks
+    slot: usize,
+}
+
+/// A client's channel, which the `Manager` sends the events of most timelines through (and
+/// the pings and disconnections of every timeline), and hands the `Receiver`s of the
+/// broadcasts the client subscribes to
+#[derive(Debug, Clone)]
+pub struct ClientTx {
+    tx: mpsc::Sender<Arc<Event>>,
+    handoff: Arc<Mutex<Handoff>>,
+}
+
+/// The client's half of a `ClientTx`, which streams what was sent to it along with the events
+/// of the broadcasts it was handed
+#[derive(Debug)]
+pub struct ClientRx {
+    rx: mpsc::Receiver<Arc<Event>>,
+    handoff: Arc<Mutex<Handoff>>,
+    /// Each broadcast, which is read while the `Manager` holds the `Arc` it was handed with
+    broadcasts: Vec<(Weak<()>, Receiver)>,
+}
+
+/// The broadcasts handed to a client that it has yet to read
+#[derive(Debug, Default)]
+struct Handoff {
+    broadcasts: Vec<(Weak<()>, Receiver)>,
+    /// The client's task, which is woken when it is handed a broadcast
+    task: Option<Task>,
+}
+
+/// The events a receiver skipped because it fell more than the capacity behind
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Lagged(pub u64);
+
+impl std::error::Error for Lagged {}
+
+impl fmt::Display for Lagged {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "skipped {} events that didn't fit in the channel",
+            self.0
+        )
+    }
+}
+
+#[derive(Debug)]
+struct Shared {
+    ring: RwLock<Ring>,
+    waiting: Mutex<Slab>,
+}
+
+#[derive(Debug)]
+struct Ring {
+    events: Vec<Option<Arc<Event>>>,
+    /// The sequence number of the next event to be sent
+    head: u64,
+    closed: bool,
+}
+
+/// The tasks of the receivers, each of which is `Some` while its receiver waits for an event
+#[derive(Debug, Default)]
+struct Slab {
+    tasks: Vec<Option<Task>>,
+    /// The slots of receivers that have been dropped, to reuse
+    free: Vec<usize>,
+    receivers: usize,
+}
+
+/// A channel whose receivers can each fall up to `capacity` events behind
+pub fn channel(capacity: usize) -> Sender {
+    let ring = Ring {
+        events: vec![None; capacity.max(1)],
+        head: 0,
+        closed: false,
+    };
+    Sender {
+        shared: Arc::new(Shared {
+            ring: RwLock::new(ring),
+            waiting: Mutex::new(Slab::default()),
+        }),
+    }
+}
+
+/// A client's channel, which holds up to `buffer` events (besides those of its broadcasts)
+pub fn client_channel(buffer: usize) -> (ClientTx, ClientRx) {
+    let (tx, rx) = mpsc::channel(buffer);
+    let handoff = Arc::new(Mutex::new(Handoff::default()));
+    let client_tx = ClientTx {
+        tx,
+        handoff: handoff.clone(),
+    };
+    let client_rx = ClientRx {
+        rx,
+        handoff,
+        broadcasts: Vec::new(),
+    };
+    (client_tx, client_rx)
+}
+
+impl Sender {
+    /// A receiver for the events sent from now on
+    pub fn subscribe(&self) -> Receiver {
+        let next = self.shared.read_ring().head;
+        let mut waiting = self.shared.lock_waiting();
+        waiting.receivers += 1;
+        let slot = match waiting.free.pop() {
+            Some(slot) => slot,
+            None => {
+                waiting.tasks.push(None);
+                waiting.tasks.len() - 1
+            }
+        };
+        Receiver {
+            shared: self.shared.clone(),
+            next,
+            slot,
+        }
+    }
+
+    /// Send `event` to every receiver, waking those that are waiting.  Returns how many
+    /// receivers there are.
+    pub fn send(&self, event: Arc<Event>) -> usize {
+        {
+            let mut ring = self
+                .shared
+                .ring
+                .write()
+                .unwrap_or_else(PoisonError::into_inner);
+            let idx = (ring.head % ring.events.len() as u64) as usize;
+            ring.events[idx] = Some(event);
+            ring.head += 1;
+        }
+        self.shared.wake_all()
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.shared.lock_waiting().receivers
+    }
+}
+
+impl Drop for Sender {
+    /// End every receiver's stream once it has read the events already sent
+    fn drop(&mut self) {
+        self.shared
+            .ring
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .closed = true;
+        self.shared.wake_all();
+    }
+}
+
+impl Stream for Receiver {
+    type Item = Arc<Event>;
+    type Error = Lagged;
+
+    fn poll(&mut self) -> Poll<Option<Arc<Event>>, Lagged> {
+        if let Some(polled) = self.try_next() {
+            return polled;
+        }
+        // Wait, then look again in case an event was sent before the task was stored
+        self.shared.lock_waiting().tasks[self.slot] = Some(task::current());
+        match self.try_next() {
+            Some(polled) => polled,
+            None => Ok(Async::NotReady),
+        }
+    }
+}
+
+impl Receiver {
+    fn try_next(&mut self) -> Option<Poll<Option<Arc<Event>>, Lagged>> {
+        let ring = self.shared.read_ring();
+        let capacity = ring.events.len() as u64;
+        if ring.head.saturating_sub(self.next) > capacity {
+            let oldest = ring.head - capacity;
+            let skipped = oldest - self.next;
+            self.next = oldest;
+            return Some(Err(Lagged(skipped)));
+        }
+        if self.next < ring.head {
+            let event = ring.events[(self.next % capacity) as usize].clone();
+            self.next += 1;
+            return Some(Ok(Async::Ready(event)));
+        }
+        match ring.closed {
+            true => Some(Ok(Async::Ready(None))),
+            false => None,
+        }
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        let mut waiting = self.shared.lock_waiting();
+        waiting.tasks[self.slot] = None;
+        waiting.free.push(self.slot);
+        waiting.receivers -= 1;
+    }
+}
+
+impl ClientTx {
+    pub fn try_send(&mut self, event: Arc<Event>) -> Result<(), TrySendError<Arc<Event>>> {
+        self.tx.try_send(event)
+    }
+
+    pub fn poll_ready(&mut self) -> Poll<(), SendError> {
+        self.tx.poll_ready()
+    }
+
+    /// Hand the client a receiver of `sender`'s events, which it reads until the returned
+    /// `Arc` is dropped
+    pub fn join(&self, sender: &Sender) -> Arc<()> {
+        let subscribed = Arc::new(());
+        let mut handoff = self.handoff.lock().unwrap_or_else(PoisonError::into_inner);
+        let receiver = sender.subscribe();
+        handoff
+            .broadcasts
+            .push((Arc::downgrade(&subscribed), receiver));
+        if let Some(task) = handoff.task.take() {
+            task.notify();
+        }
+        subscribed
+    }
+}
+
+impl Stream for ClientRx {
+    type Item = Arc<Event>;
+    type Error = RecvError;
+
+    /// The next event sent to the client, or else from one of its broadcasts.  The stream ends
+    /// when the `Manager` drops the client.
+    fn poll(&mut self) -> Poll<Option<Arc<Event>>, RecvError> {
+        loop {
+            if let Async::Ready(event) = self.rx.poll()? {
+                return Ok(Async::Ready(event));
+            }
+
+            self.broadcasts
+                .retain(|(subscribed, _)| subscribed.upgrade().is_some());
+            let mut i = 0;
+            while i < self.broadcasts.len() {
+                match self.broadcasts[i].1.poll() {
+                    Ok(Async::Ready(Some(event))) => return Ok(Async::Ready(Some(event))),
+                    Ok(Async::Ready(None)) => drop(self.broadcasts.swap_remove(i)),
+                    Ok(Async::NotReady) => i += 1,
+                    Err(Lagged(skipped)) => metrics::CLIENT_EVENTS_DROPPED.add(skipped),
+                }
+            }
+
+            // Wait, unless the client was handed broadcasts to read in the meantime
+            let mut handoff = self.handoff.lock().unwrap_or_else(PoisonError::into_inner);
+            if handoff.broadcasts.is_empty() {
+                handoff.task = Some(task::current());
+                return Ok(Async::NotReady);
+            }
+            self.broadcasts.append(&mut handoff.broadcasts);
+        }
+    }
+}
+
+impl Shared {
+    /// Wake every waiting receiver, returning how many receivers there are
+    fn wake_all(&self) -> usize {
+        let mut waiting = self.lock_waiting();
+        for task in waiting.tasks.iter_mut().filter_map(Option::take) {
+            task.notify();
+        }
+        waiting.receivers
+    }
+
+    fn read_ring(&self) -> RwLockReadGuard<Ring> {
+        self.ring.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn lock_waiting(&self) -> MutexGuard<Slab> {
+        self.waiting.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
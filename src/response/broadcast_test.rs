@@ -0,0 +1,76 @@
+use super::broadcast::{self, Lagged};
+use super::Event;
+
+use futures::future::{self, Future};
+use futures::{Async, Stream};
+use std::sync::Arc;
+
+type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+#[test]
+fn every_receiver_gets_each_event_sent_after_it_subscribed() -> TestResult {
+    future::lazy(|| -> TestResult {
+        let sender = broadcast::channel(4);
+        let mut early = sender.subscribe();
+        assert_eq!(sender.send(Arc::new(Event::Ping)), 1);
+        let mut late = sender.subscribe();
+        assert_eq!(sender.send(Arc::new(Event::Disconnect(1001))), 2);
+
+        assert_eq!(early.poll()?, Async::Ready(Some(Arc::new(Event::Ping))));
+        let disconnect = Async::Ready(Some(Arc::new(Event::Disconnect(1001))));
+        assert_eq!(early.poll()?, disconnect);
+        assert_eq!(late.poll()?, disconnect);
+        assert_eq!(early.poll()?, Async::NotReady);
+
+        drop(late);
+        assert_eq!(sender.receiver_count(), 1);
+        drop(sender);
+        assert_eq!(early.poll()?, Async::Ready(None));
+        Ok(())
+    })
+    .wait()
+}
+
+#[test]
+fn a_receiver_that_falls_behind_skips_what_it_missed() -> TestResult {
+    future::lazy(|| -> TestResult {
+        let sender = broadcast::channel(2);
+        let mut slow = sender.subscribe();
+        for code in 1000..1005 {
+            sender.send(Arc::new(Event::Disconnect(code)));
+        }
+
+        assert_eq!(slow.poll(), Err(Lagged(3)));
+        let next = Async::Ready(Some(Arc::new(Event::Disconnect(1003))));
+        assert_eq!(slow.poll()?, next);
+        Ok(())
+    })
+    .wait()
+}
+
+#[test]
+fn a_client_reads_its_broadcasts_while_it_is_subscribed_to_them() -> TestResult {
+    future::lazy(|| -> TestResult {
+        let (mut client_tx, mut client_rx) = broadcast::client_channel(4);
+        let sender = broadcast::channel(4);
+        let subscribed = client_tx.join(&sender);
+        assert_eq!(sender.send(Arc::new(Event::Disconnect(1001))), 1);
+        client_tx.try_send(Arc::new(Event::Ping))?;
+
+        // What was sent to the client comes first
+        assert_eq!(client_rx.poll()?, Async::Ready(Some(Arc::new(Event::Ping))));
+        let disconnect = Async::Ready(Some(Arc::new(Event::Disconnect(1001))));
+        assert_eq!(client_rx.poll()?, disconnect);
+        assert_eq!(client_rx.poll()?, Async::NotReady);
+
+        drop(subscribed);
+        sender.send(Arc::new(Event::Disconnect(1002)));
+        assert_eq!(client_rx.poll()?, Async::NotReady);
+        assert_eq!(sender.receiver_count(), 0);
+
+        drop(client_tx);
+        assert_eq!(client_rx.poll()?, Async::Ready(None));
+        Ok(())
+    })
+    .wait()
+}
@@ -0,0 +1,280 @@
+//! A canary that checks, end to end, that what Mastodon publishes reaches clients (with
+//! `CANARY_INTERVAL`)
+//!
+//! Every `CANARY_INTERVAL`, the canary publishes a marker (a `delete` event) to a hashtag that
+//! only it follows, and reads the marker back over an SSE stream it holds open to this very
+//! server.  A marker that hasn't arrived within `CANARY_DEADLINE` means that something between
+//! Redis and the clients has failed without any other check noticing (e.g., a subscription was
+//! lost, or the `RedisManager` stalled), so the failure is logged and counted, and
+//! `/api/v1/streaming/health` fails with a 503 until a marker arrives again.
+use super::RedisManager;
+use crate::config::{Deployment, Redis};
+use crate::jitter;
+use crate::metrics;
+use crate::request::{Switch, TagRegistry};
+
+use serde_json::json;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// The id the canary's hashtag is cached under, which no hashtag Mastodon creates can have
+const TAG_ID: i64 = -1;
+/// How long to let a new stream subscribe to its Redis channel before publishing to it
+const SETTLE: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+pub struct Canary {
+    /// Where this server listens
+    addr: Addr,
+    /// The hashtag only this instance's canary follows
+    tag: String,
+    tags: TagRegistry,
+    namespace: Option<String>,
+    interval: Duration,
+    deadline: Duration,
+    /// Whether to publish markers to Redis (or, without Redis, hand them to the `RedisManager`)
+    via_redis: bool,
+    health: Health,
+    stream: Option<BufReader<Conn>>,
+    count: u64,
+}
+
+/// Whether the latest check passed (or why it failed), shared with the health endpoint
+#[derive(Debug, Clone, Default)]
+pub struct Health(Arc<RwLock<Option<String>>>);
+
+#[derive(Debug)]
+enum Addr {
+    Tcp(SocketAddr),
+    Unix(String),
+}
+
+#[derive(Debug)]
+enum Conn {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Canary {
+    /// The canary the config asks for, if it asks for one
+    pub fn from_cfg(cfg: &Deployment, redis_cfg: &Redis, tags: TagRegistry) -> Option<Self> {
+        let addr = match &*cfg.unix_socket {
+            Some(path) => Addr::Unix(path.clone()),
+            // A server bound to every address can be reached on the loopback one
+            None => Addr::Tcp(match *cfg.address {
+                IpAddr::V4(ip) if ip.is_unspecified() => (Ipv4Addr::LOCALHOST, *cfg.port).into(),
+                IpAddr::V6(ip) if ip.is_unspecified() => (Ipv6Addr::LOCALHOST, *cfg.port).into(),
+                ip => SocketAddr::new(ip, *cfg.port),
+            }),
+        };
+        let nonce = cfg.seed.unwrap_or_else(|| {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH);
+            now.map_or(0, |now| now.as_secs() ^ u64::from(now.subsec_nanos()))
+        });
+        Some(Self {
+            addr,
+            tag: format!("flodgatt_canary_{:x}", nonce),
+            tags,
+            namespace: redis_cfg.namespace.clone().0,
+            interval: (*cfg.canary_interval)?,
+            deadline: *cfg.canary_deadline,
+            via_redis: true,
+            health: Health::default(),
+            stream: None,
+            count: 0,
+        })
+    }
+
+    /// Hand markers straight to the `RedisManager` (in `--dev` mode, which has no Redis)
+    pub fn without_redis(mut self) -> Self {
+        self.via_redis = false;
+        self
+    }
+
+    pub fn health(&self) -> Health {
+        self.health.clone()
+    }
+
+    /// Check every `CANARY_INTERVAL` (varied by the `JITTER`), on a thread of its own (since
+    /// the canary's client blocks), for as long as the server runs.
+    pub fn spawn(mut self, manager: Arc<Mutex<RedisManager>>, running: Switch) {
+        thread::spawn(move || loop {
+            thread::sleep(jitter::vary(self.interval));
+            if !running.is_on() {
+                // A stopped canary can't vouch for the server, but shouldn't fail it either
+                self.health.set(None);
+                self.stream = None;
+                continue;
+            }
+            match self.check(&manager) {
+                Ok(latency) => {
+                    metrics::CANARY_LATENCY.observe(latency);
+                    if self.health.set(None).is_some() {
+                        tracing::warn!(
+                            "The canary's marker arrived in {:?}; healthy again",
+                            latency
+                        );
+                    }
+                }
+                Err(e) => {
+                    tracing::error!("Canary check failed: {}", e);
+                    metrics::CANARY_FAILURES.inc();
+                    self.stream = None; // connect afresh next time
+                    self.health.set(Some(e));
+                }
+            }
+        });
+    }
+
+    /// Publish a marker and wait for it to arrive, returning how long it took.
+    fn check(&mut self, manager: &Mutex<RedisManager>) -> Result<Duration, String> {
+        // Cached again each time, in case other hashtags have pushed it out of the cache
+        self.tags.insert(self.tag.clone(), TAG_ID);
+        if self.stream.is_none() {
+            let stream = self.connect();
+            self.stream = Some(stream.map_err(|e| format!("connect to {}: {}", self.addr, e))?);
+            thread::sleep(SETTLE);
+        }
+        self.count += 1;
+        let marker = format!("{}-{}", self.tag, self.count);
+        let tag = match self.tags.id_channels() {
+            true => TAG_ID.to_string(),
+            false => self.tag.clone(),
+        };
+        let channel = match &self.namespace {
+            Some(ns) => format!("{}:timeline:hashtag:{}", ns, tag),
+            None => format!("timeline:hashtag:{}", tag),
+        };
+        let event_txt = json!({ "event": "delete", "payload": marker }).to_string();
+
+        let started = Instant::now();
+        {
+            let mut manager = manager.lock().unwrap_or_else(RedisManager::recover);
+            match self.via_redis {
+                true => manager.publish(&[(channel, event_txt)]),
+                false => manager.inject(&channel, &event_txt).map(|_| ()),
+            }
+            .map_err(|e| format!("publish: {}", e))?;
+        }
+        let stream = self.stream.as_mut().expect("Guaranteed: connected above");
+        await_marker(stream, &marker, self.deadline)?;
+        Ok(started.elapsed())
+    }
+
+    /// Open an SSE stream of the canary's hashtag, as a client would.
+    fn connect(&self) -> io::Result<BufReader<Conn>> {
+        let mut conn = match &self.addr {
+            Addr::Tcp(addr) => Conn::Tcp(TcpStream::connect_timeout(addr, self.deadline)?),
+            Addr::Unix(path) => Conn::Unix(UnixStream::connect(path)?),
+        };
+        conn.set_read_timeout(Some(self.deadline))?;
+        let request = format!(
+            "GET /api/v1/streaming/hashtag?tag={} HTTP/1.1\r\n\
+             Host: localhost\r\nAccept: text/event-stream\r\n\r\n",
+            self.tag
+        );
+        conn.write_all(request.as_bytes())?;
+
+        let mut stream = BufReader::new(conn);
+        let mut line = String::new();
+        stream.read_line(&mut line)?;
+        if line.split(' ').nth(1) != Some("200") {
+            let msg = format!("the server replied {:?}", line.trim_end());
+            return Err(io::Error::new(io::ErrorKind::Other, msg));
+        }
+        while !matches!(line.as_str(), "\r\n" | "\n" | "") {
+            line.clear();
+            stream.read_line(&mut line)?; // the headers, up to the blank line that ends them
+        }
+        Ok(stream)
+    }
+}
+
+/// Read from an SSE stream until a line carries `marker` (however the event is framed), or
+/// fail once `deadline` has passed.
+pub(super) fn await_marker(
+    events: &mut impl BufRead,
+    marker: &str,
+    deadline: Duration,
+) -> Result<(), String> {
+    let give_up_at = Instant::now() + deadline;
+    let mut line = String::new();
+    while Instant::now() < give_up_at {
+        line.clear();
+        match events.read_line(&mut line) {
+            Ok(0) => return Err("the server closed the stream".to_string()),
+            Ok(_) if line.contains(marker) => return Ok(()),
+            Ok(_) => continue, // heartbeats, and the events of earlier (late) markers
+            Err(e) => match e.kind() {
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => break,
+                _ => return Err(format!("read: {}", e)),
+            },
+        }
+    }
+    Err(format!("the marker did not arrive within {:?}", deadline))
+}
+
+impl Health {
+    /// Record the outcome of a check, returning the previous failure (if the one before
+    /// failed).
+    fn set(&self, failure: Option<String>) -> Option<String> {
+        let mut latest = self.0.write().unwrap_or_else(|e| e.into_inner());
+        std::mem::replace(&mut *latest, failure)
+    }
+
+    /// Whether the latest check passed, which `/api/v1/streaming/health` fails without
+    pub fn check(&self) -> Result<(), String> {
+        match &*self.0.read().unwrap_or_else(|e| e.into_inner()) {
+            None => Ok(()),
+            Some(failure) => Err(failure.clone()),
+        }
+    }
+}
+
+impl Conn {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Conn::Tcp(conn) => conn.set_read_timeout(timeout),
+            Conn::Unix(conn) => conn.set_read_timeout(timeout),
+        }
+    }
+}
+
+impl Read for Conn {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Conn::Tcp(conn) => conn.read(buf),
+            Conn::Unix(conn) => conn.read(buf),
+        }
+    }
+}
+
+impl Write for Conn {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Conn::Tcp(conn) => conn.write(buf),
+            Conn::Unix(conn) => conn.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Conn::Tcp(conn) => conn.flush(),
+            Conn::Unix(conn) => conn.flush(),
+        }
+    }
+}
+
+impl fmt::Display for Addr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Addr::Tcp(addr) => write!(f, "{}", addr),
+            Addr::Unix(path) => write!(f, "{}", path),
+        }
+    }
+}
@@ -0,0 +1,31 @@
+use super::canary::{await_marker, Health};
+
+use std::io::Cursor;
+use std::time::Duration;
+
+const DEADLINE: Duration = Duration::from_secs(1);
+
+#[test]
+fn the_marker_is_found_however_the_stream_frames_it() {
+    // As the server sends it: chunked, with a heartbeat and a late marker from an earlier check
+    let stream = "7\r\n:thump\n\r\n\
+                  2a\r\nevent: delete\ndata: flodgatt_canary_1f-1\n\n\r\n\
+                  33\r\nevent: delete\ndata: {\"id\":\"flodgatt_canary_1f-2\"}\n\n\r\n";
+    let mut events = Cursor::new(stream);
+    assert_eq!(
+        await_marker(&mut events, "flodgatt_canary_1f-2", DEADLINE),
+        Ok(())
+    );
+}
+
+#[test]
+fn a_stream_that_closes_first_fails_the_check() {
+    let mut events = Cursor::new("7\r\n:thump\n\r\n0\r\n\r\n");
+    let failure = await_marker(&mut events, "flodgatt_canary_1f-3", DEADLINE).unwrap_err();
+    assert!(failure.contains("closed"), "{}", failure);
+}
+
+#[test]
+fn the_instance_is_healthy_until_a_check_fails() {
+    assert_eq!(Health::default().check(), Ok(()));
+}
@@ -0,0 +1,324 @@
+//! Golden tests comparing Flodgatt's output to that of Mastodon's Node streaming server
+//!
+//! Each numbered case in `test_data/compat/` holds the raw input both servers received from
+//! Redis (`NNN.redis.resp`) and the output the Node server sent in response, both as WebSocket
+//! frames (`NNN.node_ws.txt`, one frame per line) and as a Server Sent Event stream
+//! (`NNN.node_sse.txt`).  `errors.txt` lists the status codes the Node server uses when it
+//! rejects a request.
+//!
+//! Flodgatt's own output for each case is recorded in `target/compat/` so that a failing case
+//! can be inspected with `diff`.  Everything but the payloads must match byte for byte: event
+//! names and order, the WebSocket envelope, and the Server Sent Event framing (see
+//! `stream::framing`).  Payloads are compared as JSON values, with `null` fields treated as
+//! absent (Mastodon omits some optional fields that Flodgatt serializes as `null`).
+//! `framing.node_sse.txt` is a whole Server Sent Event stream, comments included.
+use super::stream::framing;
+use super::{Event, EventFormat, RedisManager, SseStream};
+use crate::config;
+use crate::request::{Handler, Subscription, GOING_AWAY};
+
+use futures::{stream, Async, Future, Stream};
+use serde_json::Value;
+use std::convert::TryFrom;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use warp::Reply;
+
+type TestResult<T = ()> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+const SSE_DATA: &str = "data: ";
+
+fn compat_file(file_name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("test_data/compat")
+        .join(file_name)
+}
+
+fn record(file_name: &str, output: &str) -> TestResult {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/compat");
+    fs::create_dir_all(&dir)?;
+    Ok(fs::write(dir.join(file_name), output)?)
+}
+
+fn flodgatt_events(redis_input: &[u8]) -> TestResult<Vec<Arc<Event>>> {
+    let mut manager = RedisManager::try_from(&config::Redis::default())?;
+    manager.redis_conn.add(redis_input);
+
+    let mut events = Vec::new();
+    while let Ok(Async::Ready(Some(len))) = manager.redis_conn.poll_redis(manager.unread_idx.1) {
+        manager.unread_idx.1 += len;
+        while let Ok(Async::Ready(msg)) = manager.poll() {
+            if let Some((_tl, event)) = msg {
+                events.push(event);
+            }
+        }
+    }
+    Ok(events)
+}
+
+/// Drop `null` fields so that a missing field and a `null` one compare as equal
+fn normalized(value: Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .filter(|(_, v)| !v.is_null())
+                .map(|(k, v)| (k, normalized(v)))
+                .collect(),
+        ),
+        Value::Array(vec) => Value::Array(vec.into_iter().map(normalized).collect()),
+        other => other,
+    }
+}
+
+fn payload_value(payload: &str) -> Value {
+    serde_json::from_str(payload).map_or_else(|_| Value::String(payload.to_string()), normalized)
+}
+
+/// Split a WebSocket frame into its envelope (everything before the payload) and its payload
+fn split_ws_frame(frame: &str) -> TestResult<(&str, Value)> {
+    let parsed: Value = serde_json::from_str(frame)?;
+    Ok(match frame.find(r#","payload":"#) {
+        Some(i) => (
+            &frame[..i],
+            parsed["payload"]
+                .as_str()
+                .map_or_else(|| normalized(parsed["payload"].clone()), payload_value),
+        ),
+        None => (frame, Value::Null),
+    })
+}
+
+#[test]
+fn output_matches_node_streaming_server() -> TestResult {
+    let mut case = 1;
+    while let Ok(redis_input) = fs::read(compat_file(&format!("{:03}.redis.resp", case))) {
+        let events =
+            flodgatt_events(&redis_input).map_err(|e| format!("case {:03}: {}", case, e))?;
+
+        let ws_frames: Vec<_> = events
+            .iter()
+            .map(|event| event.to_json_string(&EventFormat::default()))
+            .collect();
+        record(
+            &format!("{:03}.flodgatt_ws.txt", case),
+            &format!("{}\n", ws_frames.join("\n")),
+        )?;
+        let node_ws = fs::read_to_string(compat_file(&format!("{:03}.node_ws.txt", case)))?;
+        let node_ws: Vec<_> = node_ws.lines().collect();
+        assert_eq!(
+            ws_frames.len(),
+            node_ws.len(),
+            "case {:03}: frame count",
+            case
+        );
+        for (i, (ours, theirs)) in ws_frames.iter().zip(node_ws).enumerate() {
+            let (our_envelope, our_payload) = split_ws_frame(ours)?;
+            let (node_envelope, node_payload) = split_ws_frame(theirs)?;
+            assert_eq!(our_envelope, node_envelope, "case {:03}, frame {}", case, i);
+            assert_eq!(our_payload, node_payload, "case {:03}, frame {}", case, i);
+        }
+
+        let sse_frames: String = events
+            .iter()
+            .map(|event| framing::sse(event, &EventFormat::default()))
+            .collect();
+        record(&format!("{:03}.flodgatt_sse.txt", case), &sse_frames)?;
+        let node_sse = fs::read_to_string(compat_file(&format!("{:03}.node_sse.txt", case)))?;
+        assert_eq!(
+            sse_frames.split('\n').count(),
+            node_sse.split('\n').count(),
+            "case {:03}: line count",
+            case
+        );
+        for (i, (ours, theirs)) in sse_frames.split('\n').zip(node_sse.split('\n')).enumerate() {
+            match (ours.starts_with(SSE_DATA), theirs.starts_with(SSE_DATA)) {
+                (true, true) => assert_eq!(
+                    payload_value(&ours[SSE_DATA.len()..]),
+                    payload_value(&theirs[SSE_DATA.len()..]),
+                    "case {:03}, line {}",
+                    case,
+                    i
+                ),
+                (_, _) => assert_eq!(ours, theirs, "case {:03}, line {}", case, i),
+            }
+        }
+        case += 1;
+    }
+    assert!(case > 1);
+    Ok(())
+}
+
+#[test]
+fn sse_stream_is_framed_like_node() -> TestResult {
+    let events = vec![
+        Arc::new(Event::try_from(
+            r#"{"event":"delete","payload":"1038647"}"#,
+        )?),
+        Arc::new(Event::Ping),
+    ];
+    let sse = SseStream::new(Subscription::default(), &EventFormat::default());
+    let frames = sse
+        .frames(stream::iter_ok::<_, ()>(events))
+        .collect()
+        .wait()
+        .map_err(|()| "the event stream failed")?;
+
+    let node_sse = fs::read_to_string(compat_file("framing.node_sse.txt"))?;
+    assert_eq!(frames.concat(), node_sse);
+    Ok(())
+}
+
+#[test]
+fn hello_event_opens_the_sse_stream() -> TestResult {
+    let mut cfg = config::Deployment::default();
+    cfg.hello_event.0 = true;
+    let sse = SseStream::new(Subscription::default(), &EventFormat::from_cfg(&cfg));
+    let frames = sse
+        .frames(stream::iter_ok::<_, ()>(vec![Arc::new(Event::Ping)]))
+        .collect()
+        .wait()
+        .map_err(|()| "the event stream failed")?;
+
+    assert_eq!(frames.len(), 3);
+    assert_eq!(frames[0], framing::SSE_OPENED);
+    let data = frames[1]
+        .strip_prefix("event: connected\ndata: ")
+        .ok_or("the second frame is not the `connected` event")?;
+    let hello: Value = serde_json::from_str(data.trim_end())?;
+    assert_eq!(hello["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(hello["heartbeat_interval"], 30);
+    assert_eq!(hello["resume"], false);
+    assert_eq!(frames[2], framing::SSE_HEARTBEAT);
+    Ok(())
+}
+
+#[test]
+fn sse_stream_ends_with_an_error_event_saying_why() -> TestResult {
+    let events = vec![Arc::new(Event::Disconnect(GOING_AWAY))];
+    let sse = SseStream::new(Subscription::default(), &EventFormat::default());
+    let frames = sse
+        .frames(stream::iter_ok::<_, ()>(events))
+        .collect()
+        .wait()
+        .map_err(|()| "the event stream failed")?;
+
+    let data = frames[1]
+        .strip_prefix("event: error\ndata: ")
+        .ok_or("the last frame is not an `error` event")?;
+    let error: Value = serde_json::from_str(data.trim_end())?;
+    assert_eq!(error["code"], "server_shutting_down");
+    assert_eq!(error["error"], "The server is shutting down; reconnect");
+    Ok(())
+}
+
+#[test]
+fn multiline_sse_data_and_ids_are_framed_per_line() {
+    assert_eq!(
+        framing::sse_event("notification", "{\n}", Some("7")),
+        "event: notification\ndata: {\ndata: }\nid: 7\n\n"
+    );
+}
+
+#[test]
+fn multiplexed_ws_frames_name_their_stream_first() -> TestResult {
+    let event = Event::try_from(r#"{"event":"delete","payload":"1038647"}"#)?;
+    let stream = ["hashtag".to_string(), "rust".to_string()];
+    assert_eq!(
+        event.to_json_string_on(&EventFormat::default(), Some(&stream)),
+        r#"{"stream":["hashtag","rust"],"event":"delete","payload":"1038647"}"#
+    );
+    Ok(())
+}
+
+#[test]
+fn rejections_use_node_status_codes() -> TestResult {
+    let expected = fs::read_to_string(compat_file("errors.txt"))?;
+    for line in expected
+        .lines()
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+    {
+        let mut fields = line.splitn(2, '\t');
+        let status: u16 = fields.next().unwrap_or_default().parse()?;
+        let cause = fields.next().unwrap_or_default().to_string();
+
+        match Handler::err(warp::reject::custom(cause.clone())) {
+            Ok(reply) => assert_eq!(reply.into_response().status().as_u16(), status, "{}", cause),
+            Err(rejection) => panic!("`{}` was not handled: {:?}", cause, rejection),
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn configured_format_overrides_node_output() -> TestResult {
+    let mut cfg = config::Deployment::default();
+    cfg.event_names
+        .0
+        .insert("delete".into(), "status.delete".into());
+    cfg.delete_payload.0 = config::DeletePayloadInner::Object;
+    let format = EventFormat::from_cfg(&cfg);
+
+    let event = Event::try_from(r#"{"event":"delete","payload":"1038647"}"#)?;
+    assert_eq!(
+        event.to_json_string(&format),
+        r#"{"event":"status.delete","payload":"{\"id\":\"1038647\"}"}"#
+    );
+    assert_eq!(
+        event.to_sse_fields(&format),
+        Some(("status.delete".into(), r#"{"id":"1038647"}"#.into()))
+    );
+    Ok(())
+}
+
+#[test]
+fn both_delete_payloads_are_sent_in_order() -> TestResult {
+    let mut cfg = config::Deployment::default();
+    cfg.delete_payload.0 = config::DeletePayloadInner::Both;
+    let format = EventFormat::from_cfg(&cfg);
+
+    let event = Event::try_from(r#"{"event":"delete","payload":"1038647"}"#)?;
+    assert_eq!(
+        event.to_json_string(&format),
+        r#"{"event":"delete","payload":"1038647"}"#
+    );
+    let duplicate = event.duplicate_format(&format).expect("a duplicate delete");
+    assert_eq!(
+        event.to_json_string(&duplicate),
+        r#"{"event":"delete","payload":"{\"id\":\"1038647\"}"}"#
+    );
+    assert_eq!(
+        framing::sse(&event, &format),
+        "event: delete\ndata: 1038647\n\nevent: delete\ndata: {\"id\":\"1038647\"}\n\n"
+    );
+
+    let event = Event::try_from(r#"{"event":"filters_changed"}"#)?;
+    assert!(event.duplicate_format(&format).is_none());
+    Ok(())
+}
+
+#[test]
+fn configured_ttls_are_stamped_on_events() -> TestResult {
+    let mut cfg = config::Deployment::default();
+    cfg.event_ttls.0.insert("delete".into(), 300);
+    cfg.event_names
+        .0
+        .insert("delete".into(), "status.delete".into());
+    let format = EventFormat::from_cfg(&cfg);
+
+    let event = Event::try_from(r#"{"event":"delete","payload":"1038647"}"#)?;
+    assert_eq!(
+        event.to_json_string(&format),
+        r#"{"event":"status.delete","payload":"1038647","ttl":300}"#
+    );
+    assert_eq!(
+        event.to_json_string(&format.ttls_only()),
+        r#"{"event":"delete","payload":"1038647","ttl":300}"#
+    );
+    let event = Event::try_from(r#"{"event":"filters_changed"}"#)?;
+    assert_eq!(
+        event.to_json_string(&format),
+        r#"{"event":"filters_changed"}"#
+    );
+    Ok(())
+}
@@ -4,22 +4,34 @@ mod checked_event;
 pub mod checked_event;
 mod dynamic_event;
 pub mod err;
+mod format;
+mod scrub;
 
 pub use self::checked_event::CheckedEvent;
 pub use self::dynamic_event::{DynEvent, EventKind};
+pub use self::format::EventFormat;
+use crate::request::EventTypes;
 use crate::Id;
 
 use hashbrown::HashSet;
 use serde::Serialize;
 use std::convert::TryFrom;
 use std::string::String;
-use warp::sse::ServerSentEvent;
+use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Event {
     TypeSafe(CheckedEvent),
     Dynamic(DynEvent),
     Ping,
+    /// Sent to a client just before its channel is dropped by an admin, with the close code
+    /// for its WebSocket
+    Disconnect(u16),
+    /// A message with bytes that aren't valid UTF-8 (with `INVALID_UTF8=raw`): the event as
+    /// parsed with those bytes replaced (for filtering, and for transports that only carry
+    /// text) and the message's bytes as published (which WebSocket clients receive)
+    Raw(Box<Event>, Arc<[u8]>),
 }
 
 pub(crate) trait Payload {
@@ -31,32 +43,93 @@ pub(crate) trait Payload {
 }
 
 impl Event {
-    pub(crate) fn to_json_string(&self) -> String {
-        if let Event::Ping = self {
+    pub(crate) fn to_json_string(&self, format: &EventFormat) -> String {
+        self.to_json_string_on(format, None)
+    }
+
+    /// The JSON for this `Event`, naming the `stream` it was sent on (as Mastodon does when a
+    /// client has subscribed to more than one stream over a WebSocket)
+    pub(crate) fn to_json_string_on(
+        &self,
+        format: &EventFormat,
+        stream: Option<&[String]>,
+    ) -> String {
+        if let Event::Raw(event, _) = self {
+            event.to_json_string_on(format, stream)
+        } else if matches!(self, Event::Ping | Event::Disconnect(_)) {
             "{}".to_string()
         } else {
-            let event = &self.event_name();
-            let sendable_event = match self.payload() {
-                Some(payload) => SendableEvent::WithPayload { event, payload },
-                None => SendableEvent::NoPayload { event },
+            let name = self.event_name();
+            let ttl = format.ttl(&name);
+            let event = &format.name(name);
+            let sendable_event = match self.payload(format) {
+                Some(payload) => SendableEvent::WithPayload {
+                    stream,
+                    event,
+                    payload,
+                    ttl,
+                },
+                None => SendableEvent::NoPayload { stream, event, ttl },
             };
             serde_json::to_string(&sendable_event).expect("Guaranteed: SendableEvent is Serialize")
         }
     }
 
-    pub(crate) fn to_warp_reply(&self) -> Option<(impl ServerSentEvent, impl ServerSentEvent)> {
-        if let Event::Ping = self {
+    /// The `event` and `data` fields of the Server Sent Event for this `Event`
+    pub(crate) fn to_sse_fields(&self, format: &EventFormat) -> Option<(String, String)> {
+        if let Event::Raw(event, _) = self {
+            event.to_sse_fields(format)
+        } else if matches!(self, Event::Ping | Event::Disconnect(_)) {
             None
         } else {
             Some((
-                warp::sse::event(self.event_name()),
-                warp::sse::data(self.payload().unwrap_or_else(String::new)),
+                format.name(self.event_name()),
+                self.payload(format).unwrap_or_else(String::new),
             ))
         }
     }
 
+    /// The `connected` event that a client is sent first (with `HELLO_EVENT`), so that it can
+    /// confirm what it subscribed to: the `stream`, this server's version, the seconds between
+    /// heartbeats, and whether a stream can be resumed after reconnecting (it can't)
+    pub(crate) fn hello(stream: Vec<String>, heartbeat: Duration) -> Self {
+        Event::Dynamic(DynEvent {
+            kind: EventKind::NonUpdate,
+            event: "connected".to_string(),
+            payload: serde_json::json!({
+                "stream": stream,
+                "version": env!("CARGO_PKG_VERSION"),
+                "heartbeat_interval": heartbeat.as_secs(),
+                "resume": false,
+            }),
+            queued_at: None,
+        })
+    }
+
+    /// The format to send this `Event` in a second time, if it is a `delete` that
+    /// `DELETE_PAYLOAD=both` sends in both payload formats
+    pub(crate) fn duplicate_format(&self, format: &EventFormat) -> Option<EventFormat> {
+        match self {
+            Event::Raw(event, _) => event.duplicate_format(format),
+            Event::TypeSafe(CheckedEvent::Delete { .. }) => format.delete_duplicate(),
+            _ => None,
+        }
+    }
+
+    /// Whether a client that asked for `types` of event wants this one (heartbeats and
+    /// disconnects are always wanted)
+    pub(crate) fn is_wanted(&self, types: &EventTypes) -> bool {
+        match self {
+            Event::Ping | Event::Disconnect(_) => true,
+            Event::Raw(event, _) => event.is_wanted(types),
+            _ => types.allows(&self.event_name()),
+        }
+    }
+
     pub(crate) fn update_payload(&self) -> Option<&checked_event::Status> {
-        if let Self::TypeSafe(CheckedEvent::Update { payload, .. }) = self {
+        if let Self::Raw(event, _) = self {
+            event.update_payload()
+        } else if let Self::TypeSafe(CheckedEvent::Update { payload, .. }) = self {
             Some(&payload)
         } else {
             None
@@ -64,7 +137,9 @@ impl Event {
     }
 
     pub(crate) fn dyn_update_payload(&self) -> Option<&dynamic_event::DynStatus> {
-        if let Self::Dynamic(DynEvent {
+        if let Self::Raw(event, _) = self {
+            event.dyn_update_payload()
+        } else if let Self::Dynamic(DynEvent {
             kind: EventKind::Update(s),
             ..
         }) = self
@@ -75,6 +150,27 @@ impl Event {
         }
     }
 
+    /// The id of the status an `update` carries
+    pub(crate) fn status_id(&self) -> Option<Id> {
+        match self {
+            Self::Raw(event, _) => event.status_id(),
+            Self::TypeSafe(CheckedEvent::Update { payload, .. }) => Some(payload.id),
+            Self::Dynamic(DynEvent {
+                kind: EventKind::Update(_),
+                payload,
+                ..
+            }) => Id::try_from(&payload["id"]).ok(),
+            _ => None,
+        }
+    }
+
+    /// Parse `event_txt` without type checking it, reading only the fields that routing and
+    /// filtering need (as `EventModeInner::PassThrough` does)
+    pub(crate) fn dynamic(event_txt: &str) -> Result<Event, err::Event> {
+        let dyn_event: DynEvent = serde_json::from_str(event_txt)?;
+        Ok(Event::Dynamic(dyn_event.set_update()?))
+    }
+
     fn event_name(&self) -> String {
         String::from(match self {
             Self::TypeSafe(checked) => match checked {
@@ -92,26 +188,27 @@ impl Event {
                 ..
             }) => "update",
             Self::Dynamic(DynEvent { event, .. }) => event,
-            Self::Ping => unreachable!(), // private method only called above
+            Self::Ping | Self::Disconnect(_) | Self::Raw(..) => unreachable!(), // private method only called above
         })
     }
 
     #[rustfmt::skip]
-    fn payload(&self) -> Option<String> {
+    fn payload(&self, format: &EventFormat) -> Option<String> {
         use CheckedEvent::*;
         match self {
             Self::TypeSafe(checked) => match checked {
-                Update               { payload, .. } => Some(escaped(payload)),
+                Update               { payload, .. } => Some(format.status(payload)),
                 Notification         { payload, .. } => Some(escaped(payload)),
                 Conversation         { payload, .. } => Some(escaped(payload)),
                 Announcement         { payload, .. } => Some(escaped(payload)),
                 AnnouncementReaction { payload, .. } => Some(escaped(payload)),
-                AnnouncementDelete   { payload, .. } |
-                Delete               { payload, .. } => Some(payload.clone()),
+                AnnouncementDelete   { payload, .. } => Some(payload.clone()),
+                Delete               { payload, .. } => Some(format.delete_payload(payload)),
                 FiltersChanged                       => None,
             },
+            Self::Dynamic(DynEvent { kind: EventKind::Update(_), payload, .. }) => Some(format.status(payload)),
             Self::Dynamic(DynEvent { payload, .. }) => Some(payload.to_string()),
-            Self::Ping => unreachable!(), // private method only called above
+            Self::Ping | Self::Disconnect(_) | Self::Raw(..) => unreachable!(), // private method only called above
         }
     }
 }
@@ -130,14 +227,13 @@ impl TryFrom<&str> for Event {
         match serde_json::from_str(event_txt) {
             Ok(checked_event) => Ok(Event::TypeSafe(checked_event)),
             Err(e) => {
-                log::error!(
+                tracing::error!(
                     "Error safely parsing Redis input.  Mastodon and Flodgatt do not \
                              strictly conform to the same version of Mastodon's API.\n{}\n\
                              Forwarding Redis payload without type checking it.",
                     e
                 );
-                let dyn_event: DynEvent = serde_json::from_str(&event_txt)?;
-                Ok(Event::Dynamic(dyn_event.set_update()?))
+                Event::dynamic(event_txt)
             }
         }
     }
@@ -146,8 +242,21 @@ impl TryFrom<&str> for Event {
 #[derive(Serialize, Debug, Clone)]
 #[serde(untagged)]
 enum SendableEvent<'a> {
-    WithPayload { event: &'a str, payload: String },
-    NoPayload { event: &'a str },
+    WithPayload {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        stream: Option<&'a [String]>,
+        event: &'a str,
+        payload: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        ttl: Option<u64>,
+    },
+    NoPayload {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        stream: Option<&'a [String]>,
+        event: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        ttl: Option<u64>,
+    },
 }
 
 fn escaped<T: Serialize + std::fmt::Debug>(content: T) -> String {
@@ -0,0 +1,102 @@
+//! How each deployment names and formats the events it sends to clients
+use super::scrub::Scrub;
+use crate::config::{self, DeletePayloadInner};
+use crate::request::Subscription;
+
+use hashbrown::HashMap;
+use serde::Serialize;
+use std::sync::Arc;
+
+/// Changes to the events Mastodon's streaming server sends, for deployments whose clients
+/// expect different event names or payloads.  The default leaves events unchanged.
+#[derive(Debug, Clone, Default)]
+pub struct EventFormat {
+    names: Arc<HashMap<String, String>>,
+    /// Seconds until each kind of event expires, for the `ttl` field (see `EVENT_TTLS`)
+    ttls: Arc<HashMap<String, u64>>,
+    delete_payload: DeletePayloadInner,
+    /// Whether clients are sent a `connected` event first (see `Event::hello`)
+    hello: bool,
+    scrub: Option<Arc<Scrub>>,
+    /// Whether statuses sent with this format are scrubbed (see `for_subscription`)
+    scrubbing: bool,
+}
+
+impl EventFormat {
+    pub fn from_cfg(cfg: &config::Deployment) -> Self {
+        Self {
+            names: Arc::new(cfg.event_names.0.clone()),
+            ttls: Arc::new(cfg.event_ttls.0.clone()),
+            delete_payload: *cfg.delete_payload,
+            hello: *cfg.hello_event,
+            scrub: Scrub::from_cfg(cfg).map(Arc::new),
+            scrubbing: false,
+        }
+    }
+
+    /// The format for one client's events, which scrubs statuses (if `SCRUB_*` is set) when an
+    /// anonymous client subscribes to a public or hashtag timeline
+    pub fn for_subscription(&self, subscription: &Subscription) -> Self {
+        let timelines = subscription.timelines();
+        Self {
+            scrubbing: self.scrub.is_some()
+                && subscription.access_token.is_none()
+                && timelines
+                    .iter()
+                    .any(|(timeline, _tag)| timeline.is_public() || timeline.tag().is_some()),
+            ..self.clone()
+        }
+    }
+
+    /// Mastodon's format, but with the `ttl`s that this format stamps on events
+    pub fn ttls_only(&self) -> Self {
+        Self {
+            ttls: self.ttls.clone(),
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn sends_hello(&self) -> bool {
+        self.hello
+    }
+
+    /// The `ttl` for an event with Mastodon's name `name`
+    pub(super) fn ttl(&self, name: &str) -> Option<u64> {
+        self.ttls.get(name).copied()
+    }
+
+    pub(super) fn name(&self, name: String) -> String {
+        self.names.get(&name).cloned().unwrap_or(name)
+    }
+
+    pub(super) fn delete_payload(&self, id: &str) -> String {
+        match self.delete_payload {
+            DeletePayloadInner::Object => serde_json::json!({ "id": id }).to_string(),
+            DeletePayloadInner::Id | DeletePayloadInner::Both => id.to_string(),
+        }
+    }
+
+    /// With `DELETE_PAYLOAD=both`, the format of the copy of each `delete` event that follows
+    /// it (with the ID in an object, after the one with the ID alone)
+    pub(super) fn delete_duplicate(&self) -> Option<Self> {
+        match self.delete_payload {
+            DeletePayloadInner::Both => Some(Self {
+                delete_payload: DeletePayloadInner::Object,
+                ..self.clone()
+            }),
+            DeletePayloadInner::Id | DeletePayloadInner::Object => None,
+        }
+    }
+
+    /// The payload of an `update` event
+    pub(super) fn status<T: Serialize>(&self, status: &T) -> String {
+        match (&self.scrub, self.scrubbing) {
+            (Some(scrub), true) => {
+                let mut status = serde_json::to_value(status).expect("Guaranteed: T is Serialize");
+                scrub.status(&mut status);
+                status.to_string()
+            }
+            _ => serde_json::to_string(status).expect("Guaranteed: T is Serialize"),
+        }
+    }
+}
@@ -0,0 +1,139 @@
+//! Scrubbing the statuses sent to anonymous clients of the public and hashtag timelines, for
+//! instances that expose a public firehose but want to limit its value to scrapers
+use crate::config;
+
+use hashbrown::HashSet;
+use serde_json::Value;
+use std::mem;
+
+#[cfg(test)]
+mod test;
+
+/// What email addresses are replaced with
+const REDACTED: &str = "[redacted]";
+/// The attributes of a media attachment that link to the media itself
+const MEDIA_URLS: [&str; 5] = [
+    "url",
+    "preview_url",
+    "remote_url",
+    "preview_remote_url",
+    "text_url",
+];
+
+/// An operator's policy for what to remove from statuses
+#[derive(Debug, Clone)]
+pub(crate) struct Scrub {
+    emails: bool,
+    account_fields: Option<HashSet<String>>,
+    media_urls: bool,
+}
+
+impl Scrub {
+    /// The policy set by `SCRUB_*`, or `None` if those leave statuses unchanged
+    pub(crate) fn from_cfg(cfg: &config::Deployment) -> Option<Self> {
+        Some(Self {
+            emails: *cfg.scrub_emails,
+            account_fields: cfg
+                .scrub_account_fields
+                .as_ref()
+                .map(|fields| fields.iter().cloned().collect()),
+            media_urls: *cfg.scrub_media_urls,
+        })
+        .filter(|scrub| scrub.emails || scrub.account_fields.is_some() || scrub.media_urls)
+    }
+
+    /// Apply the policy to a status (and to the status it boosts, if any)
+    pub(crate) fn status(&self, status: &mut Value) {
+        if let Some(reblog) = status.get_mut("reblog") {
+            self.status(reblog);
+        }
+        if self.emails {
+            redact(status.get_mut("content"));
+            redact(status.get_mut("spoiler_text"));
+        }
+
+        if let Some(account) = status.get_mut("account") {
+            self.account(account);
+        }
+        if let Some(Value::Array(attachments)) = status.get_mut("media_attachments") {
+            for attachment in attachments {
+                self.media_attachment(attachment);
+            }
+        }
+    }
+
+    fn account(&self, account: &mut Value) {
+        if let (Some(fields), Value::Object(attrs)) = (&self.account_fields, &mut *account) {
+            *attrs = mem::take(attrs)
+                .into_iter()
+                .filter(|(attr, _)| fields.contains(attr))
+                .collect();
+        }
+        if self.emails {
+            redact(account.get_mut("display_name"));
+            redact(account.get_mut("note"));
+            if let Some(Value::Array(fields)) = account.get_mut("fields") {
+                for field in fields {
+                    redact(field.get_mut("name"));
+                    redact(field.get_mut("value"));
+                }
+            }
+        }
+    }
+
+    fn media_attachment(&self, attachment: &mut Value) {
+        if self.emails {
+            redact(attachment.get_mut("description"));
+        }
+        if let (true, Value::Object(attrs)) = (self.media_urls, attachment) {
+            for &url in &MEDIA_URLS {
+                if let Some(value) = attrs.get_mut(url) {
+                    *value = Value::Null;
+                }
+            }
+        }
+    }
+}
+
+/// Redact the email addresses in `value`, if it is a string
+fn redact(value: Option<&mut Value>) {
+    if let Some(Value::String(text)) = value {
+        if text.contains('@') {
+            *text = redact_emails(text);
+        }
+    }
+}
+
+/// Replace anything shaped like an email address (`local@domain.tld`) with `REDACTED`.
+///
+/// Fediverse handles (`@user@domain.tld`) are left alone, since they are public anyway.
+fn redact_emails(text: &str) -> String {
+    let is_local = |b: &u8| b.is_ascii_alphanumeric() || b"._%+-".contains(b);
+    let is_domain = |b: &u8| b.is_ascii_alphanumeric() || b".-".contains(b);
+    let bytes = text.as_bytes();
+
+    // Every index below is next to an ASCII byte, so slicing `text` with it is safe
+    let (mut redacted, mut copied, mut next) = (String::with_capacity(text.len()), 0, 0);
+    while let Some(at) = text[next..].find('@').map(|i| next + i) {
+        let local_len = bytes[copied..at]
+            .iter()
+            .rev()
+            .take_while(|b| is_local(b))
+            .count();
+        let start = at - local_len;
+        let domain_len = bytes[at + 1..].iter().take_while(|b| is_domain(b)).count();
+        let domain = text[at + 1..at + 1 + domain_len].trim_end_matches('.');
+        let is_handle = start > 0 && bytes[start - 1] == b'@';
+
+        if start < at && !is_handle && domain.contains('.') && !domain.starts_with('.') {
+            redacted.push_str(&text[copied..start]);
+            redacted.push_str(REDACTED);
+            copied = at + 1 + domain.len();
+            next = copied;
+        } else {
+            next = at + 1;
+        }
+    }
+    redacted.push_str(&text[copied..]);
+    redacted
+}
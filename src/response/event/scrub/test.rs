@@ -0,0 +1,116 @@
+use super::super::{Event, EventFormat};
+use super::*;
+use crate::request::{Content, Reach, Stream, Subscription, Timeline};
+use crate::response::Generator;
+use crate::Id;
+
+use serde_json::json;
+use std::convert::TryFrom;
+
+fn scrub(emails: bool, account_fields: Option<&str>, media_urls: bool) -> Option<Scrub> {
+    let mut cfg = config::Deployment::default();
+    cfg.scrub_emails.0 = emails;
+    cfg.scrub_account_fields.0 =
+        account_fields.map(|fields| fields.split(',').map(String::from).collect());
+    cfg.scrub_media_urls.0 = media_urls;
+    Scrub::from_cfg(&cfg)
+}
+
+#[test]
+fn email_addresses_are_redacted() {
+    for (text, expected) in &[
+        ("mail me@example.com!", "mail [redacted]!"),
+        (
+            "<a href=\"mailto:a.b+c@mail.example.org\">",
+            "<a href=\"mailto:[redacted]\">",
+        ),
+        ("x@a.io and y@b.io.", "[redacted] and [redacted]."),
+        ("ask @admin@example.com", "ask @admin@example.com"),
+        ("@someone is not an address", "@someone is not an address"),
+        ("user@localhost", "user@localhost"),
+        ("naïve@example.com", "naï[redacted]"),
+    ] {
+        assert_eq!(&redact_emails(text), expected);
+    }
+}
+
+#[test]
+fn no_policy_means_no_scrubbing() {
+    assert!(scrub(false, None, false).is_none());
+    assert!(scrub(true, None, false).is_some());
+}
+
+#[test]
+fn statuses_and_their_boosts_are_scrubbed() {
+    let scrub = scrub(true, Some("id,username,note"), true).expect("a policy");
+    let status = |reblog| {
+        json!({
+            "id": "1",
+            "content": "<p>write to dev@example.com</p>",
+            "spoiler_text": "",
+            "account": {
+                "id": "1",
+                "username": "dev",
+                "email": "dev@example.com",
+                "note": "dev@example.com",
+                "avatar": "https://example.com/avatar.png"
+            },
+            "media_attachments": [{
+                "id": "2",
+                "url": "https://example.com/media.png",
+                "preview_url": "https://example.com/preview.png",
+                "description": "a picture"
+            }],
+            "reblog": reblog
+        })
+    };
+    let scrubbed = |reblog| {
+        json!({
+            "id": "1",
+            "content": "<p>write to [redacted]</p>",
+            "spoiler_text": "",
+            "account": { "id": "1", "username": "dev", "note": "[redacted]" },
+            "media_attachments": [{
+                "id": "2",
+                "url": null,
+                "preview_url": null,
+                "description": "a picture"
+            }],
+            "reblog": reblog
+        })
+    };
+
+    let mut boost = status(status(Value::Null));
+    scrub.status(&mut boost);
+    assert_eq!(boost, scrubbed(scrubbed(Value::Null)));
+}
+
+#[test]
+fn only_anonymous_public_and_hashtag_clients_are_scrubbed() {
+    let mut cfg = config::Deployment::default();
+    cfg.scrub_account_fields.0 = Some(vec!["id".to_string()]);
+    cfg.generator_notifications.0 = 0.0;
+    let format = EventFormat::from_cfg(&cfg);
+    let (_, event_txt) = Generator::from_cfg(&cfg, &config::Redis::default()).next_event();
+    let event = Event::try_from(event_txt.as_str()).expect("a valid event");
+
+    let account_attrs = |stream, access_token: Option<&str>| {
+        let subscription = Subscription {
+            timeline: Timeline(stream, Reach::Federated, Content::All),
+            access_token: access_token.map(String::from),
+            ..Subscription::default()
+        };
+        let sent = event.to_json_string(&format.for_subscription(&subscription));
+        let sent: Value = serde_json::from_str(&sent).expect("valid JSON");
+        let status: Value =
+            serde_json::from_str(sent["payload"].as_str().expect("a payload")).expect("valid JSON");
+        status["account"]
+            .as_object()
+            .map_or(0, |account| account.len())
+    };
+
+    assert_eq!(account_attrs(Stream::Public, None), 1);
+    assert_eq!(account_attrs(Stream::Hashtag(1), None), 1);
+    assert!(account_attrs(Stream::Public, Some("token")) > 1);
+    assert!(account_attrs(Stream::User(Id(1)), None) > 1);
+}
@@ -0,0 +1,237 @@
+//! Synthetic events for demos, `--dev` mode, and load and soak tests
+//!
+//! A `Generator` writes statuses and notifications from the `DEV_TOKENS` accounts, with
+//! content lengths drawn from `GENERATOR_CONTENT_SIZE`.  Run as a task, it sends
+//! `GENERATOR_RATE` events per second, either by publishing them to Redis (which exercises the
+//! whole pipeline, as Mastodon would) or by handing them straight to the `RedisManager`.
+mod payload;
+
+#[cfg(test)]
+pub(crate) use payload::pubsub_message;
+
+use super::RedisManager;
+use crate::config::GeneratorTargetInner as Target;
+use crate::config::{ContentSizeInner as ContentSize, Deployment, Redis};
+use crate::request::Switch;
+
+use futures::{Future, Stream};
+use std::convert::TryFrom;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::timer::Interval;
+
+#[cfg(test)]
+mod test;
+
+/// The rate `--dev` mode uses unless `GENERATOR_RATE` is set
+const DEV_RATE: f64 = 0.5;
+/// The shortest time between batches, so that high rates don't wake the task for every event
+const MIN_PERIOD: Duration = Duration::from_millis(10);
+/// The most events sent in one batch; if the task falls further behind, the rest are skipped
+const MAX_BATCH: u64 = 10_000;
+/// Filler for statuses' content
+const WORDS: [&str; 8] = [
+    "lorem",
+    "ipsum",
+    "dolor",
+    "sit",
+    "amet",
+    "toot",
+    "boost",
+    "fediverse",
+];
+
+#[derive(Debug)]
+pub struct Generator {
+    accounts: Vec<i64>,
+    namespace: Option<String>,
+    target: Target,
+    rate: f64,
+    content_size: ContentSize,
+    notifications: f64,
+    rng: Rng,
+    count: u64,
+}
+
+impl Generator {
+    pub fn from_cfg(cfg: &Deployment, redis_cfg: &Redis) -> Self {
+        let mut accounts: Vec<i64> = cfg.dev_tokens.values().copied().collect();
+        accounts.sort();
+        accounts.dedup();
+        let seed = cfg.generator_seed.or(*cfg.seed).unwrap_or_else(|| {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH);
+            now.map_or(1, |now| now.as_secs() ^ u64::from(now.subsec_nanos()))
+        });
+        Self {
+            accounts,
+            namespace: redis_cfg.namespace.clone().0,
+            target: *cfg.generator_target,
+            rate: cfg.generator_rate.unwrap_or(DEV_RATE),
+            content_size: *cfg.generator_content_size,
+            notifications: *cfg.generator_notifications,
+            rng: Rng::new(seed),
+            count: 0,
+        }
+    }
+
+    /// Send events to `target` instead of the `GENERATOR_TARGET`
+    pub fn with_target(mut self, target: Target) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// The text of the next event, and the Redis channels Mastodon would publish it to.
+    ///
+    /// Statuses are written by each account in turn and go to the local public timelines,
+    /// `#dev`, and every account's home timeline; notifications (of a mention) go to a random
+    /// account.
+    pub fn next_event(&mut self) -> (Vec<String>, String) {
+        let i = usize::try_from(self.count).unwrap_or_default() % self.accounts.len().max(1);
+        let author_id = self.accounts.get(i).copied().unwrap_or(1);
+        self.count += 1;
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let millis = now.as_secs() * 1000 + u64::from(now.subsec_millis());
+        // Like Mastodon's IDs: a timestamp in the high bits and a sequence in the low ones
+        let id = ((millis << 16) | (self.count % (1 << 16))).to_string();
+        let created_at = payload::timestamp(millis);
+        let author = payload::account(author_id, self.accounts.len(), self.count, &created_at);
+        let text = self.text();
+
+        let (timelines, event) = if self.rng.unit() < self.notifications {
+            let i = self.rng.below(self.accounts.len().max(1));
+            let recipient = self.accounts.get(i).copied().unwrap_or(1);
+            let status = payload::status(&id, &created_at, &author, &text, Some(recipient));
+            let notification = payload::notification(&id, &created_at, &author, status);
+            (vec![recipient.to_string()], notification)
+        } else {
+            let mut timelines = vec![
+                "public".to_string(),
+                "public:local".to_string(),
+                "hashtag:dev".to_string(),
+                "hashtag:dev:local".to_string(),
+            ];
+            timelines.extend(self.accounts.iter().map(i64::to_string));
+            let status = payload::status(&id, &created_at, &author, &text, None);
+            (timelines, payload::update(status))
+        };
+
+        let channels = timelines
+            .iter()
+            .map(|tl| match &self.namespace {
+                Some(ns) => format!("{}:timeline:{}", ns, tl),
+                None => format!("timeline:{}", tl),
+            })
+            .collect();
+        (channels, event.to_string())
+    }
+
+    /// A task that sends `GENERATOR_RATE` events per second (in batches, at high rates) for as
+    /// long as the server runs.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn into_task(
+        mut self,
+        manager: Arc<Mutex<RedisManager>>,
+        running: Switch,
+    ) -> impl Future<Item = (), Error = ()> {
+        let period = Duration::from_secs_f64((1.0 / self.rate).min(3600.0)).max(MIN_PERIOD);
+        let (start, mut scheduled) = (Instant::now(), 0);
+        Interval::new_interval(period)
+            .map_err(|e| tracing::error!("{}", e))
+            .for_each(move |_| {
+                let due = (start.elapsed().as_secs_f64() * self.rate) as u64;
+                let batch = due.saturating_sub(scheduled);
+                scheduled = due;
+                if !running.is_on() {
+                    return Ok(()); // the events due while stopped are never sent
+                }
+                if batch > MAX_BATCH {
+                    tracing::warn!(
+                        "Generator fell behind; skipping {} events",
+                        batch - MAX_BATCH
+                    );
+                }
+
+                let mut manager = manager.lock().unwrap_or_else(RedisManager::recover);
+                self.send(&mut manager, batch.min(MAX_BATCH));
+                Ok(())
+            })
+    }
+
+    fn send(&mut self, manager: &mut RedisManager, events: u64) {
+        let mut msgs = Vec::new();
+        for _ in 0..events {
+            let (channels, event_txt) = self.next_event();
+            msgs.extend(channels.into_iter().map(|c| (c, event_txt.clone())));
+        }
+
+        match self.target {
+            Target::Redis => manager
+                .publish(&msgs)
+                .unwrap_or_else(|e| tracing::error!("Could not publish synthetic events: {}", e)),
+            Target::Pipeline => {
+                for (channel, event_txt) in &msgs {
+                    match manager.inject(channel, event_txt) {
+                        Ok(n) => tracing::debug!("Sent a synthetic event to {} on {}", n, channel),
+                        // e.g., `#dev` before anyone has subscribed to it (and given it an ID)
+                        Err(e) => tracing::debug!("No synthetic event on {}: {}", channel, e),
+                    }
+                }
+            }
+        }
+    }
+
+    /// The text of a status, padded with filler to a length drawn from `GENERATOR_CONTENT_SIZE`
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    fn text(&mut self) -> String {
+        let size = match self.content_size {
+            ContentSize::Fixed(size) => size,
+            ContentSize::Uniform(min, max) => min + self.rng.below(max - min + 1),
+            ContentSize::Exponential(mean) => {
+                let size = -(1.0 - self.rng.unit()).ln() * mean as f64;
+                (size as usize).min(mean * 10)
+            }
+        };
+        let mut text = format!("Synthetic status #{}", self.count);
+        while text.len() < size {
+            text.push(' ');
+            text.push_str(WORDS[self.rng.below(WORDS.len())]);
+        }
+        text
+    }
+}
+
+/// A small, fast PRNG (xorshift64*), which is plenty for picking synthetic content
+#[derive(Debug)]
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.max(1)) // xorshift never leaves 0
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// A number in `0..n` (where `n > 0`)
+    #[allow(clippy::cast_possible_truncation)]
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// A number in `[0, 1)`
+    #[allow(clippy::cast_precision_loss)]
+    fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1_u64 << 53) as f64
+    }
+}
@@ -0,0 +1,146 @@
+//! Mastodon-shaped JSON for synthetic events, modeled on the payloads in `test_data`
+use serde_json::{json, Value};
+
+/// An `update` event
+pub(super) fn update(status: Value) -> Value {
+    json!({ "event": "update", "payload": status })
+}
+
+/// A `notification` event telling the mentioned account about `status`
+pub(super) fn notification(id: &str, created_at: &str, author: &Value, status: Value) -> Value {
+    json!({
+        "event": "notification",
+        "payload": {
+            "id": id,
+            "type": "mention",
+            "created_at": created_at,
+            "account": author,
+            "status": status
+        }
+    })
+}
+
+/// A public status with `text` as its content, tagged `#dev` and optionally mentioning an
+/// account
+pub(super) fn status(
+    id: &str,
+    created_at: &str,
+    author: &Value,
+    text: &str,
+    mention: Option<i64>,
+) -> Value {
+    let url = format!("{}/{}", author["url"].as_str().unwrap_or_default(), id);
+    let mention_html = mention.map_or_else(String::new, |id| {
+        format!(
+            "<span class=\"h-card\"><a href=\"http://localhost/@dev{0}\" class=\"u-url \
+             mention\">@<span>dev{0}</span></a></span> ",
+            id
+        )
+    });
+    let content = format!(
+        "<p>{}{} <a href=\"http://localhost/tags/dev\" class=\"mention hashtag\" \
+         rel=\"tag\">#<span>dev</span></a></p>",
+        mention_html, text
+    );
+    let mentions: Vec<Value> = mention
+        .into_iter()
+        .map(|id| {
+            json!({
+                "id": id.to_string(),
+                "username": format!("dev{}", id),
+                "acct": format!("dev{}", id),
+                "url": format!("http://localhost/@dev{}", id)
+            })
+        })
+        .collect();
+    json!({
+        "id": id,
+        "created_at": created_at,
+        "in_reply_to_id": null,
+        "in_reply_to_account_id": null,
+        "sensitive": false,
+        "spoiler_text": "",
+        "visibility": "public",
+        "language": "en",
+        "uri": url,
+        "url": url,
+        "replies_count": 0,
+        "reblogs_count": 0,
+        "favourites_count": 0,
+        "content": content,
+        "reblog": null,
+        "account": author,
+        "media_attachments": [],
+        "mentions": mentions,
+        "tags": [{ "name": "dev", "url": "http://localhost/tags/dev" }],
+        "emojis": [],
+        "card": null,
+        "poll": null
+    })
+}
+
+/// One of the `DEV_TOKENS` accounts, which all follow each other
+pub(super) fn account(id: i64, accounts: usize, statuses: u64, last_status_at: &str) -> Value {
+    let username = format!("dev{}", id);
+    json!({
+        "id": id.to_string(),
+        "username": username,
+        "acct": username,
+        "display_name": format!("Dev user {}", id),
+        "locked": false,
+        "bot": true,
+        "discoverable": false,
+        "group": false,
+        "created_at": "2020-01-01T00:00:00.000Z",
+        "note": "<p>A synthetic account</p>",
+        "url": format!("http://localhost/@{}", username),
+        "avatar": "http://localhost/avatars/original/missing.png",
+        "avatar_static": "http://localhost/avatars/original/missing.png",
+        "header": "http://localhost/headers/original/missing.png",
+        "header_static": "http://localhost/headers/original/missing.png",
+        "followers_count": accounts,
+        "following_count": accounts,
+        "statuses_count": statuses,
+        "last_status_at": &last_status_at[..10],
+        "emojis": [],
+        "fields": []
+    })
+}
+
+/// Redis's reply to a subscriber when `event_txt` is published to `channel`
+#[cfg(test)]
+pub(crate) fn pubsub_message(channel: &str, event_txt: &str) -> Vec<u8> {
+    format!(
+        "*3\r\n$7\r\nmessage\r\n${}\r\n{}\r\n${}\r\n{}\r\n",
+        channel.len(),
+        channel,
+        event_txt.len(),
+        event_txt
+    )
+    .into_bytes()
+}
+
+/// Format milliseconds since the Unix epoch the way Mastodon does (e.g.,
+/// `2020-04-27T20:58:02.000Z`), using Howard Hinnant's `civil_from_days` algorithm.
+pub(super) fn timestamp(millis: u64) -> String {
+    let (days, ms) = (millis / 86_400_000, millis % 86_400_000);
+    let z = days + 719_468; // days since 0000-03-01
+    let (era, day_of_era) = (z / 146_097, z % 146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153; // months since March
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
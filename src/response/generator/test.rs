@@ -0,0 +1,108 @@
+use super::super::Event;
+use super::*;
+use crate::config;
+
+use std::convert::TryFrom;
+
+fn generator(tokens: &[(&str, i64)], namespace: Option<&str>, notifications: f64) -> Generator {
+    let mut cfg = config::Deployment::default();
+    cfg.dev_tokens.0 = tokens.iter().map(|(t, id)| (t.to_string(), *id)).collect();
+    cfg.generator_notifications.0 = notifications;
+    cfg.generator_seed.0 = Some(226);
+    let mut redis_cfg = config::Redis::default();
+    redis_cfg.namespace.0 = namespace.map(String::from);
+    Generator::from_cfg(&cfg, &redis_cfg)
+}
+
+#[test]
+fn generated_events_parse_as_type_safe_events() {
+    for &notifications in &[0.0, 1.0] {
+        let mut generator = generator(&[("alice", 1), ("bob", 2)], None, notifications);
+        for _ in 0..3 {
+            let (_, event_txt) = generator.next_event();
+            match Event::try_from(event_txt.as_str()) {
+                Ok(Event::TypeSafe(_)) => (),
+                other => panic!("{:?} was not a valid event: {:?}", event_txt, other),
+            }
+        }
+    }
+}
+
+#[test]
+fn statuses_take_turns_between_accounts() {
+    let tokens = [("alice", 2), ("bob", 1), ("carol", 2)];
+    let mut generator = generator(&tokens, Some("ns"), 0.0);
+    let (channels, first) = generator.next_event();
+    let (_, second) = generator.next_event();
+    let (_, third) = generator.next_event();
+
+    assert_eq!(
+        channels,
+        vec![
+            "ns:timeline:public",
+            "ns:timeline:public:local",
+            "ns:timeline:hashtag:dev",
+            "ns:timeline:hashtag:dev:local",
+            "ns:timeline:1",
+            "ns:timeline:2",
+        ]
+    );
+    assert!(first.contains(r#""username":"dev1""#));
+    assert!(second.contains(r#""username":"dev2""#));
+    assert!(third.contains(r#""username":"dev1""#));
+}
+
+#[test]
+fn notifications_go_to_the_mentioned_account() {
+    let mut generator = generator(&[("alice", 1), ("bob", 2)], None, 1.0);
+    for _ in 0..5 {
+        let (channels, event_txt) = generator.next_event();
+        assert_eq!(channels.len(), 1);
+        let recipient = &channels[0]["timeline:".len()..];
+        let event: serde_json::Value = serde_json::from_str(&event_txt).expect("valid JSON");
+        assert_eq!(event["event"], "notification");
+        let mention = &event["payload"]["status"]["mentions"][0];
+        assert_eq!(mention["acct"], format!("dev{}", recipient));
+    }
+}
+
+#[test]
+fn content_sizes_follow_the_distribution() {
+    use ContentSize::*;
+    let mut generator = generator(&[("dev", 1)], None, 0.0);
+    let mut sizes = |distribution| {
+        generator.content_size = distribution;
+        (0..200).map(|_| generator.text().len()).collect::<Vec<_>>()
+    };
+
+    // Filler is added a word at a time, so texts can run a word (≤ 10 bytes) long
+    assert!(sizes(Fixed(500))
+        .iter()
+        .all(|len| (500..=510).contains(len)));
+    let uniform = sizes(Uniform(100, 1000));
+    assert!(uniform.iter().all(|len| (100..=1010).contains(len)));
+    assert!(uniform.iter().any(|&len| len < 400) && uniform.iter().any(|&len| len > 700));
+    let exponential = sizes(Exponential(280));
+    assert!(exponential.iter().all(|&len| len <= 2810));
+    let mean = exponential.iter().sum::<usize>() / exponential.len();
+    assert!((150..450).contains(&mean), "mean of {}", mean);
+}
+
+#[test]
+fn seeded_generators_repeat_their_content() {
+    let (mut first, mut second) = (
+        generator(&[("dev", 1)], None, 0.5),
+        generator(&[("dev", 1)], None, 0.5),
+    );
+    for _ in 0..20 {
+        assert_eq!(first.text(), second.text());
+    }
+}
+
+#[test]
+fn timestamps_match_mastodon_format() {
+    use payload::timestamp;
+    assert_eq!(timestamp(0), "1970-01-01T00:00:00.000Z");
+    assert_eq!(timestamp(1_588_021_082_000), "2020-04-27T20:58:02.000Z");
+    assert_eq!(timestamp(951_825_600_123), "2000-02-29T12:00:00.123Z");
+}
@@ -0,0 +1,96 @@
+//! Callbacks that let programs embedding Flodgatt observe what it is doing
+//!
+//! Implement `Hook` (every method has a no-op default) or register closures with the
+//! `Hooks::on_*` methods, then pass the `Hooks` to `RedisManager::set_hooks`.  Hooks run
+//! synchronously while the `RedisManager` is locked, so they should return quickly.
+use super::redis::Error;
+use super::Event;
+use crate::request::{Subscription, Timeline};
+
+use std::fmt;
+use std::sync::Arc;
+
+pub trait Hook: Send + Sync {
+    /// A client has subscribed to `subscription.timeline` (or to each of a composite stream's
+    /// `timelines`).
+    fn on_client_connect(&self, _subscription: &Subscription) {}
+    /// `event` has been sent to the `clients` subscribed to `timeline`.
+    fn on_event_delivered(&self, _timeline: Timeline, _event: &Event, _clients: usize) {}
+    /// Input from Redis could not be parsed (or did not contain a valid `Event`).
+    fn on_parse_error(&self, _err: &Error) {}
+}
+
+/// The hooks registered with a `RedisManager`, which are called in the order they were added
+#[derive(Clone, Default)]
+pub struct Hooks(Vec<Arc<dyn Hook>>);
+
+impl Hooks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, hook: impl Hook + 'static) -> Self {
+        self.0.push(Arc::new(hook));
+        self
+    }
+
+    pub fn on_client_connect(self, f: impl Fn(&Subscription) + Send + Sync + 'static) -> Self {
+        self.with(ClientConnect(f))
+    }
+
+    pub fn on_event_delivered(
+        self,
+        f: impl Fn(Timeline, &Event, usize) + Send + Sync + 'static,
+    ) -> Self {
+        self.with(EventDelivered(f))
+    }
+
+    pub fn on_parse_error(self, f: impl Fn(&Error) + Send + Sync + 'static) -> Self {
+        self.with(ParseError(f))
+    }
+
+    pub(crate) fn client_connected(&self, subscription: &Subscription) {
+        for hook in &self.0 {
+            hook.on_client_connect(subscription);
+        }
+    }
+
+    pub(crate) fn event_delivered(&self, timeline: Timeline, event: &Event, clients: usize) {
+        for hook in &self.0 {
+            hook.on_event_delivered(timeline, event, clients);
+        }
+    }
+
+    pub(crate) fn parse_failed(&self, err: &Error) {
+        for hook in &self.0 {
+            hook.on_parse_error(err);
+        }
+    }
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Hooks({} registered)", self.0.len())
+    }
+}
+
+struct ClientConnect<F>(F);
+impl<F: Fn(&Subscription) + Send + Sync> Hook for ClientConnect<F> {
+    fn on_client_connect(&self, subscription: &Subscription) {
+        (self.0)(subscription)
+    }
+}
+
+struct EventDelivered<F>(F);
+impl<F: Fn(Timeline, &Event, usize) + Send + Sync> Hook for EventDelivered<F> {
+    fn on_event_delivered(&self, timeline: Timeline, event: &Event, clients: usize) {
+        (self.0)(timeline, event, clients)
+    }
+}
+
+struct ParseError<F>(F);
+impl<F: Fn(&Error) + Send + Sync> Hook for ParseError<F> {
+    fn on_parse_error(&self, err: &Error) {
+        (self.0)(err)
+    }
+}
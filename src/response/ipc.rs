@@ -0,0 +1,163 @@
+//! An optional Unix socket that streams every event Flodgatt receives to local sidecars.
+//!
+//! Each event is sent as a single frame made of two length-prefixed fields: the Redis channel
+//! the event arrived on and the event itself (as the JSON a WebSocket client of Mastodon would
+//! receive, plus the `ttl` from `EVENT_TTLS`, if any).
+//! Each length is a big-endian `u32`.  A reader that falls more than `MAX_BACKLOG` bytes
+//! behind is disconnected rather than being allowed to slow down delivery to clients.
+//!
+//! With `IPC_LIFECYCLE`, the sink also carries the changefeed of clients' connections (see
+//! `Lifecycle`), as frames on the `LIFECYCLE_CHANNEL`.
+use super::{Event, EventFormat};
+use crate::request::Switch;
+
+use std::convert::TryFrom;
+use std::io::{self, Write};
+use std::mem;
+use std::os::unix::net::{UnixListener, UnixStream};
+
+const MAX_BACKLOG: usize = 1024 * 1024;
+
+/// The channel of the changefeed's frames, which no channel Mastodon publishes to is named
+pub const LIFECYCLE_CHANNEL: &str = "flodgatt:lifecycle";
+
+#[derive(Debug)]
+pub struct IpcSink {
+    listener: UnixListener,
+    readers: Vec<Reader>,
+    format: EventFormat,
+    running: Switch,
+    lifecycle: bool,
+}
+
+#[derive(Debug)]
+struct Reader {
+    stream: UnixStream,
+    backlog: Vec<u8>,
+}
+
+impl IpcSink {
+    pub fn bind(path: &str) -> io::Result<Self> {
+        crate::unix_socket::remove_stale(path)?;
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        tracing::info!("Publishing events to sidecars on {}", path);
+        Ok(Self {
+            listener,
+            readers: Vec::new(),
+            format: EventFormat::default(),
+            running: Switch::default(),
+            lifecycle: false,
+        })
+    }
+
+    /// Stamp events with the `ttl`s that `format` gives them
+    pub fn with_ttls(self, format: &EventFormat) -> Self {
+        Self {
+            format: format.ttls_only(),
+            ..self
+        }
+    }
+
+    /// Publish only while `running` is on; while it's off, sidecars are disconnected
+    pub fn with_switch(self, running: Switch) -> Self {
+        Self { running, ..self }
+    }
+
+    /// Also publish the changefeed of clients' connections
+    pub fn with_lifecycle(self, lifecycle: bool) -> Self {
+        Self { lifecycle, ..self }
+    }
+
+    /// Whether the changefeed is published (and sidecars can currently receive it)
+    pub(crate) fn publishes_lifecycle(&self) -> bool {
+        self.lifecycle && self.running.is_on()
+    }
+
+    pub(crate) fn publish(&mut self, channel: &str, event: &Event) {
+        if self.has_readers() {
+            self.send(channel, &event.to_json_string(&self.format));
+        }
+    }
+
+    /// Publish a `change` to clients' connections, as the JSON of a `Lifecycle`
+    pub(crate) fn publish_lifecycle(&mut self, change: &str) {
+        if self.lifecycle && self.has_readers() {
+            self.send(LIFECYCLE_CHANNEL, change);
+        }
+    }
+
+    fn has_readers(&mut self) -> bool {
+        if !self.running.is_on() {
+            self.readers.clear();
+            return false;
+        }
+        self.accept_readers();
+        !self.readers.is_empty()
+    }
+
+    fn send(&mut self, channel: &str, event: &str) {
+        let frame = Self::frame(channel, event);
+        self.readers = mem::take(&mut self.readers)
+            .into_iter()
+            .filter_map(|mut reader| match reader.send(&frame) {
+                Ok(()) => Some(reader),
+                Err(e) => {
+                    tracing::warn!("Disconnecting IPC reader: {}", e);
+                    None
+                }
+            })
+            .collect();
+    }
+
+    fn accept_readers(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => match stream.set_nonblocking(true) {
+                    Ok(()) => self.readers.push(Reader {
+                        stream,
+                        backlog: Vec::new(),
+                    }),
+                    Err(e) => tracing::warn!("Could not set up IPC reader: {}", e),
+                },
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    tracing::error!("Could not accept IPC reader: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+
+    fn frame(channel: &str, event: &str) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(8 + channel.len() + event.len());
+        for field in &[channel, event] {
+            let len = u32::try_from(field.len()).expect("Redis messages are < 512 MiB");
+            frame.extend_from_slice(&len.to_be_bytes());
+            frame.extend_from_slice(field.as_bytes());
+        }
+        frame
+    }
+}
+
+impl Reader {
+    fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+        self.backlog.extend_from_slice(frame);
+        while !self.backlog.is_empty() {
+            match self.stream.write(&self.backlog) {
+                Ok(0) => Err(io::Error::from(io::ErrorKind::WriteZero))?,
+                Ok(n) => {
+                    self.backlog.drain(..n);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => Err(e)?,
+            }
+        }
+        if self.backlog.len() > MAX_BACKLOG {
+            let msg = "reader fell too far behind";
+            Err(io::Error::new(io::ErrorKind::Other, msg))?
+        }
+        Ok(())
+    }
+}
@@ -1,68 +1,57 @@
+mod cluster;
 mod connection;
 mod manager;
 mod msg;
+mod subscribed_keys;
 
-pub(self) use super::{Event, EventErr};
-pub(self) use connection::RedisConn;
+pub(self) use super::{Event, EventErr, Hooks, IpcSink};
+pub(self) use connection::{ListQuery, RedisConn};
 pub use manager::Error;
-pub use manager::Manager;
+pub(crate) use manager::{EventRx, PING_INTERVAL};
+pub use manager::{Manager, OpenConnection, PoolHealth, Receipt};
 
 #[cfg(feature = "bench")]
 pub use msg::{RedisMsg, RedisParseOutput};
 
 use connection::RedisConnErr;
 use msg::RedisParseErr;
+use subscribed_keys::SubscribedKeys;
 
+#[cfg(test)]
+mod cluster_test;
+#[cfg(test)]
+mod subscribed_keys_test;
+
+#[derive(Debug, Clone, Copy)]
 pub(crate) enum RedisCmd {
     Subscribe,
     Unsubscribe,
 }
 
 impl RedisCmd {
-    fn into_sendable(self, timelines: &[String]) -> (Vec<u8>, Vec<u8>) {
-        match self {
-            RedisCmd::Subscribe => {
-                let primary = {
-                    let mut cmd = format!("*{}\r\n$9\r\nsubscribe\r\n", 1 + timelines.len());
-                    for tl in timelines {
-                        cmd.push_str(&format!("${}\r\n{}\r\n", tl.len(), tl));
-                    }
-                    cmd
-                };
-                let secondary = {
-                    let mut cmd = format!("*{}\r\n$4\r\nMSET\r\n", 1 + timelines.len());
-                    for tl in timelines {
-                        cmd.push_str(&format!(
-                            "${}\r\nsubscribed:{}\r\n$1\r\n$1\r\n",
-                            "subscribed:".len() + tl.len(),
-                            tl
-                        ));
-                    }
-                    cmd
-                };
-                (primary.as_bytes().to_vec(), secondary.as_bytes().to_vec())
-            }
-            RedisCmd::Unsubscribe => {
-                let primary = {
-                    let mut cmd = format!("*{}\r\n$11\r\nunsubscribe\r\n", 1 + timelines.len());
-                    for tl in timelines {
-                        cmd.push_str(&format!("${}\r\n{}\r\n", tl.len(), tl));
-                    }
-                    cmd
-                };
-                let secondary = {
-                    let mut cmd = format!("*{}\r\n$4\r\nMSET\r\n", 1 + timelines.len());
-                    for tl in timelines {
-                        cmd.push_str(&format!(
-                            "${}\r\nsubscribed:{}\r\n$1\r\n$0\r\n",
-                            "subscribed:".len() + tl.len(),
-                            tl
-                        ));
-                    }
-                    cmd
-                };
-                (primary.as_bytes().to_vec(), secondary.as_bytes().to_vec())
-            }
+    /// The command, as Redis reads it.  With `sharded`, it's the sharded pub/sub equivalent
+    /// (`SSUBSCRIBE` or `SUNSUBSCRIBE`) that Redis Cluster serves from the node that owns each
+    /// channel's slot.
+    fn into_sendable(self, timelines: &[String], sharded: bool) -> Vec<u8> {
+        let name = match (self, sharded) {
+            (RedisCmd::Subscribe, false) => "subscribe",
+            (RedisCmd::Unsubscribe, false) => "unsubscribe",
+            (RedisCmd::Subscribe, true) => "ssubscribe",
+            (RedisCmd::Unsubscribe, true) => "sunsubscribe",
+        };
+        let mut cmd = format!(
+            "*{}\r\n${}\r\n{}\r\n",
+            1 + timelines.len(),
+            name.len(),
+            name
+        );
+        for tl in timelines {
+            cmd.push_str(&format!("${}\r\n{}\r\n", tl.len(), tl));
         }
+        cmd.into_bytes()
+    }
+
+    fn is_subscribe(&self) -> bool {
+        matches!(self, RedisCmd::Subscribe)
     }
 }
@@ -0,0 +1,68 @@
+//! Which node of a Redis Cluster serves each channel (with `REDIS_CLUSTER`).
+//!
+//! A cluster splits its keyspace into 16384 hash slots, each served by one master.  Flodgatt
+//! subscribes to each channel on the master that serves the channel's slot, so that its
+//! subscriptions are spread across the cluster; with `REDIS_SHARDED_PUBSUB`, that master is
+//! also the only node that receives what is published to the channel.
+pub(super) const SLOTS: u16 = 16384;
+
+/// The masters of a cluster, and the ranges of slots each serves (as `CLUSTER SLOTS` reports)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(super) struct SlotMap {
+    /// The first and last slot of each range, and the index of its master in `nodes`
+    ranges: Vec<(u16, u16, usize)>,
+    /// The `host:port` of each master, in the order they were first named
+    nodes: Vec<String>,
+}
+
+impl SlotMap {
+    pub(super) fn new(ranges: Vec<(u16, u16, String)>) -> Self {
+        let mut map = Self::default();
+        for (first, last, node) in ranges {
+            let i = match map.nodes.iter().position(|known| *known == node) {
+                Some(i) => i,
+                None => {
+                    map.nodes.push(node);
+                    map.nodes.len() - 1
+                }
+            };
+            map.ranges.push((first, last, i));
+        }
+        map
+    }
+
+    pub(super) fn nodes(&self) -> &[String] {
+        &self.nodes
+    }
+
+    /// The index (in `nodes`) of the master that serves `channel`, if any master serves its
+    /// slot
+    pub(super) fn node(&self, channel: &str) -> Option<usize> {
+        let slot = hash_slot(channel);
+        self.ranges
+            .iter()
+            .find(|(first, last, _)| (*first..=*last).contains(&slot))
+            .map(|(_, _, i)| *i)
+    }
+}
+
+/// The slot that Redis Cluster assigns `key` to: the CRC16 of the key (or of its hash tag, the
+/// part between the first `{` and the next `}`, if that isn't empty), modulo 16384
+pub(super) fn hash_slot(key: &str) -> u16 {
+    let key = key.as_bytes();
+    let tag = key.iter().position(|&b| b == b'{').and_then(|open| {
+        let len = key[open + 1..].iter().position(|&b| b == b'}')?;
+        Some(&key[open + 1..open + 1 + len]).filter(|tag| !tag.is_empty())
+    });
+    crc16(tag.unwrap_or(key)) % SLOTS
+}
+
+/// CRC16-CCITT (XMODEM), as Redis Cluster uses
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ (u16::from(byte) << 8), |crc, _| match crc & 0x8000 {
+            0 => crc << 1,
+            _ => (crc << 1) ^ 0x1021,
+        })
+    })
+}
@@ -0,0 +1,35 @@
+use super::cluster::{hash_slot, SlotMap};
+
+#[test]
+fn keys_hash_to_the_slots_redis_assigns_them() {
+    // As `CLUSTER KEYSLOT` reports
+    assert_eq!(hash_slot("foo"), 12182);
+    assert_eq!(hash_slot("bar"), 5061);
+    assert_eq!(hash_slot("123456789"), 12739);
+    assert_eq!(hash_slot(""), 0);
+}
+
+#[test]
+fn hash_tags_put_keys_in_the_same_slot() {
+    assert_eq!(hash_slot("{user1000}.following"), hash_slot("user1000"));
+    assert_eq!(hash_slot("timeline:{public}:local"), hash_slot("public"));
+    // An empty tag (or one that isn't closed) doesn't count
+    assert_ne!(hash_slot("foo{}{bar}"), hash_slot("bar"));
+    assert_ne!(hash_slot("{foo"), hash_slot("foo"));
+}
+
+#[test]
+fn channels_are_routed_to_the_master_that_serves_their_slot() {
+    let slots = SlotMap::new(vec![
+        (0, 5460, "10.0.0.1:6379".to_string()),
+        (5461, 10922, "10.0.0.2:6379".to_string()),
+        (10923, 16000, "10.0.0.3:6379".to_string()),
+        (16001, 16383, "10.0.0.1:6379".to_string()),
+    ]);
+    assert_eq!(slots.nodes().len(), 3);
+    assert_eq!(slots.node("bar"), Some(0)); // slot 5061
+    assert_eq!(slots.node("foo"), Some(2)); // slot 12182
+
+    let partial = SlotMap::new(vec![(0, 100, "10.0.0.1:6379".to_string())]);
+    assert_eq!(partial.node("foo"), None);
+}
@@ -1,133 +1,1432 @@
+#[cfg(any(test, not(feature = "bench")))]
+mod dns;
 mod err;
+#[cfg(any(test, not(feature = "bench")))]
+mod failed_sets;
+#[cfg(not(any(test, feature = "bench")))]
+mod link;
+#[cfg(not(any(test, feature = "bench")))]
+mod resolver;
+#[cfg(not(any(test, feature = "bench")))]
+mod socket;
 pub(super) use connection::*;
 pub use err::RedisConnErr;
 #[cfg(any(test, feature = "bench"))]
 pub(self) use mock_connection as connection;
 
+use crate::request::Timeline;
+
+use std::time::Instant;
+
+/// The usual size of the input buffer, which it shrinks back to after holding a larger message
+const INPUT_SIZE: usize = 4096 * 4;
+
+impl RedisConn {
+    /// Make room for `block` bytes of input at `start`, doubling the input buffer as often as
+    /// that takes, unless it would grow past `REDIS_MAX_INPUT_BUFFER`.
+    fn reserve_input(&mut self, start: usize, block: usize) -> Result<(), RedisConnErr> {
+        let mut len = self.input.len().max(INPUT_SIZE);
+        while len < start + block {
+            len *= 2;
+        }
+        if len == self.input.len() {
+            return Ok(());
+        }
+        if let Some(max) = self.input_max.filter(|&max| len > max) {
+            return Err(RedisConnErr::InputOverflow(max));
+        }
+        self.input.resize(len, 0);
+        tracing::info!("Resizing input buffer to {} KiB.", len / 1024);
+        Ok(())
+    }
+
+    /// Shrink the input buffer back to its usual size once everything in it has been read, so
+    /// that the memory a large message needed isn't held on to.
+    pub(in super::super) fn reclaim_input(&mut self) {
+        if self.input.len() > INPUT_SIZE {
+            self.input.truncate(INPUT_SIZE);
+            self.input.shrink_to_fit();
+        }
+    }
+
+    /// The name of the Redis channel for a `Timeline`, including the namespace (if any).
+    pub(in super::super) fn channel_name(
+        &self,
+        timeline: &Timeline,
+    ) -> std::result::Result<String, RedisConnErr> {
+        let hashtag = timeline.tag().and_then(|id| self.tags.channel_tag(id).ok());
+        let raw_timeline = timeline.to_redis_raw_timeline(hashtag.as_ref())?;
+        Ok(match &self.namespace {
+            Some(ns) => format!("{}:{}", ns, raw_timeline),
+            None => raw_timeline,
+        })
+    }
+
+    /// Whether the subscribed keys are about to expire and need to be set again.
+    pub(in super::super) fn keys_refresh_due(&self) -> bool {
+        self.subscribed_keys
+            .refresh_interval()
+            .map_or(false, |interval| self.keys_refreshed.elapsed() >= interval)
+    }
+
+    /// When the subscribed keys are next due to be set again, if they expire
+    fn keys_refresh_at(&self) -> Option<Instant> {
+        let interval = self.subscribed_keys.refresh_interval()?;
+        Some(self.keys_refreshed + interval)
+    }
+}
+
 #[cfg(not(any(test, feature = "bench")))]
 mod connection {
+    use super::super::cluster::{self, SlotMap};
+    use super::super::msg::{self, RedisParseErr};
+    use super::super::subscribed_keys::bulk_string;
     use super::super::Error as ManagerErr;
-    use super::super::RedisCmd;
+    use super::super::{RedisCmd, SubscribedKeys};
     use super::err::RedisConnErr;
+    use super::failed_sets::{FailedSet, FailedSets};
+    use super::link::Link;
+    use super::resolver::CachingResolver;
+    use super::socket::{self, AsyncSocket, Socket, Tls, Transport};
+    use super::INPUT_SIZE;
     use crate::config::Redis;
-    use crate::request::Timeline;
+    use crate::jitter;
+    use crate::metrics;
+    use crate::request::{TagRegistry, Timeline};
 
     use futures::{Async, Poll};
-    use lru::LruCache;
     use std::io::{self, Read, Write};
-    use std::net::TcpStream;
-    use std::time::Duration;
+    use std::net::{SocketAddr, ToSocketAddrs};
+    use std::os::unix::net::UnixStream;
+    use std::time::{Duration, Instant};
+    use tokio::reactor::Handle;
 
     type Result<T> = std::result::Result<T, RedisConnErr>;
 
+    /// How long to wait for each of Redis's replies while connecting (the primary connections
+    /// are only read without blocking once they are registered with the reactor)
+    const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+    /// How long to wait for Redis to confirm that it has set the `subscribed:` keys
+    const SET_TIMEOUT: Duration = Duration::from_millis(250);
+    /// The most bytes of commands to queue for a primary connection that isn't accepting them
+    /// before giving up on it (every channel is subscribed to again once it reconnects)
+    const MAX_OUTGOING: usize = 1024 * 1024;
+    /// How often to look up a cluster's slots while one of its masters is down (in case a
+    /// replica has taken over) or after a lookup failed
+    const SLOTS_RETRY: Duration = Duration::from_secs(1);
+    const CLUSTER_SLOTS: &[u8] = b"*2\r\n$7\r\nCLUSTER\r\n$5\r\nSLOTS\r\n";
+    /// The most input to read from Redis at once
+    const BLOCK: usize = 4096 * 2;
+    /// How long Redis has to answer a `PING` (or send anything else) before the primary
+    /// connection it was sent on is given up on
+    const PONG_TIMEOUT: Duration = Duration::from_secs(10);
+    /// A connection that reads what Redis publishes: the only one, or (in a cluster) the one to
+    /// one of the masters
+    #[derive(Debug)]
+    struct Primary {
+        /// The master's `host:port` in a cluster (and otherwise, the same as `RedisConn::addr`)
+        addr: String,
+        // Registered with the reactor so that a parked `Manager` wakes when Redis sends input.
+        link: Link<AsyncSocket>,
+        /// Commands waiting for the connection to accept them, so that writing them never
+        /// blocks reading
+        outgoing: Vec<u8>,
+        /// When the oldest of the `outgoing` commands was queued
+        queued_at: Option<Instant>,
+        /// Input from a master of a cluster that doesn't yet end with a complete reply
+        partial: Vec<u8>,
+        /// When the connection last had input (or came up)
+        last_input: Instant,
+        /// When it was sent a `PING` that Redis hasn't yet answered
+        ping_sent: Option<Instant>,
+    }
+
+    /// What to authenticate to Redis with: `REDIS_PASSWORD`, and with `REDIS_USER`, the ACL
+    /// user it is the password of (Redis 6 and later)
+    #[derive(Debug, Clone)]
+    struct Auth {
+        user: Option<String>,
+        password: String,
+    }
+
+    /// A command whose reply lists strings (`PUBSUB CHANNELS` or `SMEMBERS`), with what it
+    /// takes to send it on a connection of its own.  It is taken from the `RedisConn` and then
+    /// run without it, so that the `Manager` isn't locked while Redis takes (up to) the read
+    /// timeout to reply.
+    #[derive(Debug)]
+    pub struct ListQuery {
+        transport: Transport,
+        resolver: CachingResolver,
+        addr: String,
+        auth: Option<Auth>,
+        /// The masters of a cluster to send `cmd` to, or `None` for `addr`
+        nodes: Vec<Option<String>>,
+        cmd: String,
+        /// Whether there is no Redis to ask (in development mode), which lists nothing
+        detached: bool,
+    }
+
+    impl ListQuery {
+        /// Send the command to each node, and list what they reply (without duplicates)
+        pub fn run(mut self) -> Result<Vec<String>> {
+            if self.detached {
+                return Ok(Vec::new());
+            }
+            let (cmd, auth) = (self.cmd.as_bytes(), self.auth.as_ref());
+            let mut listed = Vec::new();
+            for node in &self.nodes {
+                listed.extend(RedisConn::query(
+                    &mut self.resolver,
+                    &self.transport,
+                    &self.addr,
+                    auth,
+                    node.as_deref(),
+                    cmd,
+                    channel_list,
+                )?);
+            }
+            listed.sort();
+            listed.dedup();
+            Ok(listed)
+        }
+    }
+
+    /// The connections to Redis: the primary connection reads what Redis publishes (and takes
+    /// the `SUBSCRIBE` and `UNSUBSCRIBE` commands, which Redis only accepts on the connection
+    /// that reads the messages), and the secondary connection sets keys and publishes.  Each
+    /// reconnects on its own schedule when it fails.
+    ///
+    /// With `REDIS_CLUSTER`, there is a primary connection to each master of the cluster, and
+    /// each channel is subscribed to on the master that serves its hash slot.  Their input is
+    /// merged into `input` a complete reply at a time.  The secondary connection still starts
+    /// at `REDIS_HOST` and follows the `-MOVED` redirects to the master that holds each key.
     #[derive(Debug)]
     pub struct RedisConn {
-        primary: TcpStream,
-        secondary: TcpStream,
+        /// The primary connection, or one to each master of a cluster
+        primaries: Vec<Primary>,
+        secondary: Link<Socket>,
+        /// Which master serves each slot, in a cluster
+        cluster: Option<SlotMap>,
+        /// Whether to subscribe with `SSUBSCRIBE` and `SUNSUBSCRIBE` (`REDIS_SHARDED_PUBSUB`)
+        sharded: bool,
+        /// The pattern that matches every timeline's channel, with `REDIS_PSUBSCRIBE`; it is
+        /// subscribed to in place of each channel
+        pattern: Option<String>,
+        /// How long a primary connection may go without input before it is sent a `PING`
+        /// (`REDIS_PING_INTERVAL`)
+        ping_interval: Option<Duration>,
+        /// When to look up the cluster's slots again, if that's due (after a master failed or
+        /// redirected a command)
+        slots_due: Option<Instant>,
+        /// The master to read from first, so that a busy one can't starve the rest
+        next_primary: usize,
+        /// `REDIS_HOST:REDIS_PORT`, the `REDIS_UNIX_SOCKET`, or the `REDIS_SENTINEL_MASTER_NAME`
+        addr: String,
+        transport: Transport,
+        /// The writable master that a replica redirected the secondary connection to, which
+        /// it connects to in place of `addr` (see `follow_redirect`)
+        master: Option<String>,
+        auth: Option<Auth>,
+        /// The database the secondary connection sets keys in (`REDIS_DB`)
+        db: Option<u32>,
+        resolver: CachingResolver,
+        /// The connection to the `REDIS_CACHE_URL` server, if the keys are set there rather
+        /// than on the server subscribed to (which is still the one published to)
+        cache: Option<Box<RedisConn>>,
+        failed_sets: FailedSets,
+        pub(in super::super) subscribed_keys: SubscribedKeys,
+        pub(in super::super) keys_refreshed: Instant,
         pub(in super::super) namespace: Option<String>,
-        // TODO: eventually, it might make sense to have Mastodon publish to timelines with
-        //       the tag number instead of the tag name.  This would save us from dealing
-        //       with a cache here and would be consistent with how lists/users are handled.
-        pub(in super::super) tag_name_cache: LruCache<i64, String>,
+        /// Names the channels of hashtag timelines (shared with the `Manager`)
+        pub(in super::super) tags: TagRegistry,
         pub(in super::super) input: Vec<u8>,
+        /// The most bytes `input` may grow to (`REDIS_MAX_INPUT_BUFFER`)
+        pub(in super::super) input_max: Option<usize>,
+    }
+
+    impl Primary {
+        fn new(addr: String, link: Link<AsyncSocket>) -> Self {
+            Self {
+                addr,
+                link,
+                outgoing: Vec::new(),
+                queued_at: None,
+                partial: Vec::new(),
+                last_input: Instant::now(),
+                ping_sent: None,
+            }
+        }
+
+        /// Note that the connection is alive: it just had input, or came up.
+        fn alive(&mut self) {
+            self.last_input = Instant::now();
+            self.ping_sent = None;
+        }
+
+        /// When the connection is next due a `PING` (or, if it was sent one, to be given up
+        /// on), while it is up
+        fn ping_due(&self, interval: Duration) -> Option<Instant> {
+            match (&self.link, self.ping_sent) {
+                (Link::Up(_), Some(sent)) => Some(sent + PONG_TIMEOUT),
+                (Link::Up(_), None) => Some(self.last_input + interval),
+                (Link::Down { .. }, _) | (Link::Detached, _) => None,
+            }
+        }
+    }
+
+    impl Auth {
+        fn from_cfg(redis_cfg: &Redis) -> Option<Self> {
+            Some(Self {
+                user: redis_cfg.user.clone().0,
+                password: redis_cfg.password.clone().0?,
+            })
+        }
+    }
+
+    /// The pattern that matches the channel of every timeline in the `namespace`
+    fn timeline_pattern(namespace: &Option<String>) -> String {
+        match namespace {
+            Some(ns) => format!("{}:timeline:*", ns),
+            None => "timeline:*".to_string(),
+        }
+    }
+
+    /// Parse a reply that lists strings (e.g., to `PUBSUB CHANNELS`).
+    fn channel_list(reply: &str) -> std::result::Result<Vec<String>, RedisParseErr> {
+        let list = msg::parse_channel_list(reply)?;
+        Ok(list.into_iter().map(String::from).collect())
     }
 
     impl RedisConn {
         pub(in super::super) fn new(redis_cfg: &Redis) -> Result<Self> {
+            let transport = Transport::from_cfg(redis_cfg)?;
+            let addr = match (&transport.unix_socket, &*redis_cfg.sentinel_master_name) {
+                (Some(path), _) => path.clone(),
+                (None, Some(name)) => format!("{} (from Sentinel)", name),
+                (None, None) => [&*redis_cfg.host, ":", &*redis_cfg.port.to_string()].concat(),
+            };
+            let mut resolver = CachingResolver::from_cfg(redis_cfg);
+            let (auth, db) = (Auth::from_cfg(redis_cfg), *redis_cfg.db);
+
+            let primaries = match *redis_cfg.cluster {
+                true => Vec::new(), // connected to once the slots are known, below
+                false => {
+                    let conn =
+                        Self::new_connection(&mut resolver, &transport, &addr, auth.as_ref())?;
+                    // The reactor that runs the server isn't started yet; the default handle
+                    // binds to it the first time the connection is polled.
+                    let conn = conn
+                        .into_async(&Handle::default())
+                        .map_err(|e| RedisConnErr::with_addr(&addr, e))?;
+                    vec![Primary::new(addr.clone(), Link::Up(conn))]
+                }
+            };
+            let secondary =
+                Self::new_secondary(&mut resolver, &transport, &addr, None, auth.as_ref(), db)?;
+            let mut conn = Self {
+                primaries,
+                secondary: Link::Up(secondary),
+                cluster: None,
+                sharded: *redis_cfg.sharded_pubsub,
+                pattern: Some(timeline_pattern(&*redis_cfg.namespace))
+                    .filter(|_| *redis_cfg.psubscribe),
+                ping_interval: *redis_cfg.ping_interval,
+                slots_due: None,
+                next_primary: 0,
+                master: None,
+                auth,
+                db,
+                resolver,
+                cache: match &redis_cfg.cache {
+                    Some(cache_cfg) => Some(Box::new(Self::new_cache(cache_cfg)?)),
+                    None => None,
+                },
+                failed_sets: FailedSets::default(),
+                subscribed_keys: SubscribedKeys::from_cfg(redis_cfg),
+                keys_refreshed: Instant::now(),
+                addr,
+                transport,
+                tags: TagRegistry::new(*redis_cfg.tag_cache_size),
+                namespace: redis_cfg.namespace.clone().0,
+                input: vec![0; INPUT_SIZE],
+                input_max: *redis_cfg.max_input_buffer,
+            };
+            if *redis_cfg.cluster {
+                conn.refresh_slots()?;
+            }
+            conn.psubscribe();
+            Ok(conn)
+        }
+
+        /// A connection that never connects to Redis: it has no input, and commands sent to it
+        /// are discarded.
+        pub(in super::super) fn detached(redis_cfg: &Redis) -> Self {
             let addr = [&*redis_cfg.host, ":", &*redis_cfg.port.to_string()].concat();
+            Self {
+                primaries: vec![Primary::new(addr.clone(), Link::Detached)],
+                secondary: Link::Detached,
+                cluster: None,
+                sharded: false,
+                pattern: None,
+                ping_interval: None,
+                slots_due: None,
+                next_primary: 0,
+                addr,
+                transport: Transport::default(),
+                master: None,
+                auth: Auth::from_cfg(redis_cfg),
+                db: *redis_cfg.db,
+                resolver: CachingResolver::from_cfg(redis_cfg),
+                cache: None,
+                failed_sets: FailedSets::default(),
+                subscribed_keys: SubscribedKeys::from_cfg(redis_cfg),
+                keys_refreshed: Instant::now(),
+                tags: TagRegistry::new(*redis_cfg.tag_cache_size),
+                namespace: redis_cfg.namespace.clone().0,
+                input: vec![0; INPUT_SIZE],
+                input_max: *redis_cfg.max_input_buffer,
+            }
+        }
 
-            let conn = Self::new_connection(&addr, redis_cfg.password.as_ref())?;
-            conn.set_nonblocking(true)
-                .map_err(|e| RedisConnErr::with_addr(&addr, e))?;
+        /// A connection that only sets keys, on the `REDIS_CACHE_URL` server, with its own
+        /// login, database, and backoff between reconnections.
+        fn new_cache(redis_cfg: &Redis) -> Result<Self> {
+            let transport = Transport::from_cfg(redis_cfg)?;
+            let addr = [&*redis_cfg.host, ":", &*redis_cfg.port.to_string()].concat();
+            let mut resolver = CachingResolver::from_cfg(redis_cfg);
+            let (auth, db) = (Auth::from_cfg(redis_cfg), *redis_cfg.db);
+            let secondary =
+                Self::new_secondary(&mut resolver, &transport, &addr, None, auth.as_ref(), db)?;
             Ok(Self {
-                primary: conn,
-                secondary: Self::new_connection(&addr, redis_cfg.password.as_ref())?,
-                tag_name_cache: LruCache::new(1000),
-                namespace: redis_cfg.namespace.clone().0,
-                input: vec![0; 4096 * 4],
+                primaries: Vec::new(),
+                secondary: Link::Up(secondary),
+                addr,
+                transport,
+                resolver,
+                input: Vec::new(),
+                ..Self::detached(redis_cfg)
             })
         }
 
+        /// The connection that keys are set on: the `cache` one, if there is one.
+        fn keys_conn(&mut self) -> &mut Self {
+            match self.cache.is_some() {
+                true => self.cache.as_deref_mut().expect("checked above"),
+                false => self,
+            }
+        }
+
+        /// Read any input Redis has sent.  When there is none, the current task is woken once
+        /// there is.
         pub(in super::super) fn poll_redis(&mut self, i: usize) -> Poll<Option<usize>, ManagerErr> {
-            const BLOCK: usize = 4096 * 2;
-            if self.input.len() < i + BLOCK {
-                self.input.resize(self.input.len() * 2, 0);
-                log::info!("Resizing input buffer to {} KiB.", self.input.len() / 1024);
-                // log::info!("Current buffer: {}", String::from_utf8_lossy(&self.input));
+            if let Err(e) = self.reserve_input(i, BLOCK) {
+                // The rest of the message would arrive on the connection as though it were new
+                // input, so that is replaced (and every channel subscribed to again)
+                metrics::REDIS_INPUT_OVERFLOWS.inc();
+                for idx in 0..self.primaries.len() {
+                    self.fail_primary(idx);
+                }
+                return Err(e.into());
             }
 
             use Async::*;
-            match self.primary.read(&mut self.input[i..i + BLOCK]) {
-                Ok(n) if n == 0 => Ok(Ready(None)),
-                Ok(n) => Ok(Ready(Some(n))),
+            for idx in 0..self.primaries.len() {
+                self.flush_primary(idx);
+            }
+            if self.cluster.is_some() {
+                return Ok(self.poll_masters(i));
+            }
+            let redis = tracing::info_span!("redis", addr = %self.primaries[0].addr);
+            let _redis = redis.enter();
+            let primary = match self.primaries[0].link.get() {
+                Some(primary) => primary,
+                None => return Ok(NotReady),
+            };
+            match primary.read(&mut self.input[i..i + BLOCK]) {
+                Ok(n) if n == 0 => {
+                    tracing::error!("Redis closed the connection");
+                    self.fail_primary(0);
+                    Ok(Ready(None))
+                }
+                Ok(n) => {
+                    self.primaries[0].alive();
+                    Ok(Ready(Some(n)))
+                }
                 Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock) => Ok(NotReady),
                 Err(e) => {
-                    Ready(log::error!("{}", e));
+                    tracing::error!("{}", e);
+                    self.fail_primary(0);
                     Ok(Ready(None))
                 }
             }
         }
 
-        pub(crate) fn send_cmd(&mut self, cmd: RedisCmd, timelines: &[Timeline]) -> Result<()> {
-            let namespace = self.namespace.take();
-            let timelines: Result<Vec<String>> = timelines
-                .iter()
-                .map(|tl| {
-                    let hashtag = tl.tag().and_then(|id| self.tag_name_cache.get(&id));
-                    match &namespace {
-                        Some(ns) => Ok(format!("{}:{}", ns, tl.to_redis_raw_timeline(hashtag)?)),
-                        None => Ok(tl.to_redis_raw_timeline(hashtag)?),
+        /// Read from the next master of the cluster that has sent input, and copy the replies
+        /// that input completes to `input` at `i`.
+        fn poll_masters(&mut self, i: usize) -> Async<Option<usize>> {
+            let mut block = [0_u8; BLOCK];
+            let count = self.primaries.len();
+            for idx in (0..count).map(|offset| (self.next_primary + offset) % count) {
+                let redis = tracing::info_span!("redis", addr = %self.primaries[idx].addr);
+                let _redis = redis.enter();
+                let primary = match self.primaries[idx].link.get() {
+                    Some(primary) => primary,
+                    None => continue,
+                };
+                match primary.read(&mut block) {
+                    Ok(0) => {
+                        tracing::error!(
+                            "Redis at {} closed the connection",
+                            self.primaries[idx].addr
+                        );
+                        self.fail_primary(idx);
+                        return Async::Ready(None);
+                    }
+                    Ok(n) => {
+                        self.primaries[idx].alive();
+                        self.next_primary = idx + 1;
+                        self.primaries[idx].partial.extend_from_slice(&block[..n]);
+                        let replies = self.take_replies(idx);
+                        if self.input.len() < i + replies.len() {
+                            self.input.resize(i + replies.len(), 0);
+                        }
+                        self.input[i..i + replies.len()].copy_from_slice(&replies);
+                        return Async::Ready(Some(replies.len()));
+                    }
+                    Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock) => continue,
+                    Err(e) => {
+                        tracing::error!("{}", e);
+                        self.fail_primary(idx);
+                        return Async::Ready(None);
+                    }
+                }
+            }
+            Async::NotReady
+        }
+
+        /// Take the complete replies from the start of a master's `partial` input.  Error
+        /// replies are dropped, since the parser stops at them; a redirect means that the
+        /// cluster's slots have moved, so they are looked up again.
+        fn take_replies(&mut self, idx: usize) -> Vec<u8> {
+            let primary = &mut self.primaries[idx];
+            let (mut replies, mut end) = (Vec::new(), 0);
+            while let Some(len) = msg::reply_len(&primary.partial[end..]) {
+                let reply = &primary.partial[end..end + len];
+                match reply[0] {
+                    b'-' => {
+                        let reply = String::from_utf8_lossy(reply);
+                        let (addr, error) = (&primary.addr, reply.trim_end());
+                        tracing::error!("Redis at {} refused a command: {}", addr, error);
+                        if msg::parse_moved(&reply).is_some() || reply.starts_with("-ASK ") {
+                            self.slots_due = Some(Instant::now());
+                        }
                     }
+                    _ => replies.extend_from_slice(reply),
+                }
+                end += len;
+            }
+            primary.partial.drain(..end);
+            replies
+        }
+
+        /// Replace the primary connections that have failed and whose backoff has passed (and,
+        /// in a cluster, look up the slots again if that's due).
+        ///
+        /// Returns whether any was replaced, in which case the new connection has no
+        /// subscriptions and any input left over from the old one is meaningless.
+        pub(in super::super) fn reconnect_primary(&mut self) -> bool {
+            if self.slots_due.map_or(false, |due| due <= Instant::now()) {
+                match self.refresh_slots() {
+                    Ok(replaced) => {
+                        self.slots_due = None;
+                        if replaced {
+                            self.psubscribe();
+                            return true;
+                        }
+                    }
+                    Err(e) => {
+                        tracing::error!("Could not look up the Redis Cluster's slots.\n{}", e);
+                        self.slots_due = Some(Instant::now() + SLOTS_RETRY);
+                    }
+                }
+            }
+
+            let (resolver, addr, auth) = (&mut self.resolver, &self.addr, self.auth.as_ref());
+            let (transport, cluster) = (&self.transport, self.cluster.is_some());
+            let mut reconnected = false;
+            for primary in &mut self.primaries {
+                let node = &primary.addr;
+                let redis = tracing::info_span!("redis", addr = %node);
+                let _redis = redis.enter();
+                let up = primary.link.reconnect("primary", || {
+                    let conn = match cluster {
+                        true => Self::connect_node(transport, node, auth)?,
+                        false => {
+                            // The connection may have failed because Redis moved (e.g.,
+                            // Sentinel demoted the master it led to), so look up where it is
+                            // now.
+                            resolver.invalidate();
+                            Self::new_connection(resolver, transport, addr, auth)?
+                        }
+                    };
+                    conn.into_async(&Handle::default())
+                        .map_err(|e| RedisConnErr::with_addr(node, e))
+                });
+                if up {
+                    primary.alive();
+                }
+                reconnected |= up;
+            }
+            // While a master is down, a replica may take over its slots
+            if cluster
+                && self
+                    .primaries
+                    .iter_mut()
+                    .any(|primary| primary.link.get().is_none())
+            {
+                self.slots_due
+                    .get_or_insert_with(|| Instant::now() + SLOTS_RETRY);
+            }
+            if reconnected {
+                self.psubscribe();
+            }
+            reconnected
+        }
+
+        /// With `REDIS_PSUBSCRIBE`, subscribe to every timeline's channel at once: on the
+        /// first master, in a cluster, since what is published to any master reaches them all
+        /// (without `REDIS_SHARDED_PUBSUB`, which has no patterns).
+        fn psubscribe(&mut self) {
+            if let Some(pattern) = &self.pattern {
+                let cmd = ["*2\r\n$10\r\nPSUBSCRIBE\r\n", &bulk_string(pattern)].concat();
+                self.queue_primary(0, cmd.as_bytes());
+            }
+        }
+
+        /// Look up which master of the cluster serves each slot (`CLUSTER SLOTS`), asking
+        /// `REDIS_HOST` or else any master already known, and connect to the masters afresh if
+        /// that has changed.
+        ///
+        /// Returns whether the primary connections were replaced, in which case every channel
+        /// needs to be subscribed to again.
+        fn refresh_slots(&mut self) -> Result<bool> {
+            let known: Vec<String> = self.primaries.iter().map(|p| p.addr.clone()).collect();
+            let ranges = self
+                .read_reply(None, CLUSTER_SLOTS, msg::parse_cluster_slots)
+                .or_else(|e| {
+                    known
+                        .iter()
+                        .find_map(|node| {
+                            self.read_reply(
+                                Some(node.as_str()),
+                                CLUSTER_SLOTS,
+                                msg::parse_cluster_slots,
+                            )
+                            .ok()
+                        })
+                        .ok_or(e)
+                })?;
+            let slots = SlotMap::new(ranges);
+            if slots.nodes().is_empty() {
+                let reply = "CLUSTER SLOTS named no masters".to_string();
+                Err(RedisConnErr::InvalidRedisReply(reply))?;
+            }
+            if self.cluster.as_ref() == Some(&slots) {
+                return Ok(false);
+            }
+
+            tracing::warn!(
+                "Subscribing on the Redis Cluster's masters: {}",
+                slots.nodes().join(", ")
+            );
+            let (transport, auth) = (&self.transport, self.auth.as_ref());
+            self.primaries = slots
+                .nodes()
+                .iter()
+                .map(|node| {
+                    let conn = Self::connect_node(transport, node, auth).and_then(|conn| {
+                        conn.into_async(&Handle::default())
+                            .map_err(|e| RedisConnErr::with_addr(node, e))
+                    });
+                    let link = match conn {
+                        Ok(conn) => Link::Up(conn),
+                        Err(e) => {
+                            tracing::error!("Could not connect to Redis; retrying.\n{}", e);
+                            Link::down()
+                        }
+                    };
+                    Primary::new(node.clone(), link)
                 })
                 .collect();
+            self.cluster = Some(slots);
+            self.next_primary = 0;
+            Ok(true)
+        }
+
+        /// With `REDIS_PING_INTERVAL`, `PING` each primary connection that has been without
+        /// input that long, and give up on one that Redis hasn't answered within `PONG_TIMEOUT`
+        /// so that it is reconnected.  (A load balancer may drop an idle connection without
+        /// closing it, after which nothing would ever arrive on it.)
+        pub(in super::super) fn ping_idle_primaries(&mut self) {
+            let interval = match self.ping_interval {
+                Some(interval) => interval,
+                None => return,
+            };
+            for idx in 0..self.primaries.len() {
+                let primary = &mut self.primaries[idx];
+                if primary.link.get().is_none() {
+                    continue;
+                }
+                match primary.ping_sent {
+                    Some(sent) if sent.elapsed() >= PONG_TIMEOUT => {
+                        tracing::error!(
+                            "Redis at {} didn't answer a PING within {:?}; reconnecting",
+                            primary.addr,
+                            PONG_TIMEOUT
+                        );
+                        metrics::REDIS_PING_TIMEOUTS.inc();
+                        self.fail_primary(idx);
+                    }
+                    None if primary.last_input.elapsed() >= interval => {
+                        primary.ping_sent = Some(Instant::now());
+                        self.queue_primary(idx, b"*1\r\n$4\r\nPING\r\n");
+                    }
+                    Some(_) | None => (),
+                }
+            }
+        }
 
-            let (primary_cmd, secondary_cmd) = cmd.into_sendable(&timelines?[..]);
-            self.primary.write_all(&primary_cmd)?;
+        pub(in super::super) fn has_queued_cmds(&self) -> bool {
+            self.primaries
+                .iter()
+                .any(|primary| !primary.outgoing.is_empty())
+        }
+
+        /// Write as much of a primary connection's `outgoing` commands as it accepts without
+        /// blocking; the rest are written on a later poll.
+        fn flush_primary(&mut self, idx: usize) {
+            let primary = &mut self.primaries[idx];
+            let redis = tracing::info_span!("redis", addr = %primary.addr);
+            let _redis = redis.enter();
+            let conn = match primary.link.get() {
+                Some(conn) => conn,
+                None => return,
+            };
+            let mut written = 0;
+            let result = loop {
+                if written == primary.outgoing.len() {
+                    break Ok(());
+                }
+                match conn.write(&primary.outgoing[written..]) {
+                    Ok(0) => break Err(io::Error::from(io::ErrorKind::WriteZero)),
+                    Ok(n) => written += n,
+                    Err(e) => break Err(e),
+                }
+            };
+            primary.outgoing.drain(..written);
+
+            match result {
+                Ok(()) => {
+                    if let Some(queued_at) = primary.queued_at.take() {
+                        metrics::REDIS_PRIMARY_WRITE.observe(queued_at.elapsed());
+                    }
+                }
+                // Redis isn't reading fast enough to empty the socket's buffer
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    metrics::REDIS_WRITE_STALLS.inc()
+                }
+                Err(e) => {
+                    tracing::error!("Could not write to Redis: {}", e);
+                    self.fail_primary(idx);
+                }
+            }
+        }
+
+        /// Drop a primary connection (and the commands waiting for it, since every channel is
+        /// subscribed to again after reconnecting).
+        fn fail_primary(&mut self, idx: usize) {
+            let primary = &mut self.primaries[idx];
+            primary.link.fail();
+            primary.outgoing.clear();
+            primary.queued_at = None;
+            primary.partial.clear();
+            if self.cluster.is_some() {
+                self.slots_due = Some(Instant::now()); // in case the master failed over
+            }
+        }
+
+        pub(crate) fn send_cmd(&mut self, cmd: RedisCmd, timelines: &[Timeline]) -> Result<()> {
+            let timelines: Result<Vec<String>> =
+                timelines.iter().map(|tl| self.channel_name(tl)).collect();
+            let timelines = timelines?;
+
+            let subscribed = cmd.is_subscribe();
+            if self.secondary.is_detached() {
+                return Ok(());
+            }
+            // The pattern already covers the channels, but Mastodon still reads their keys
+            if self.pattern.is_none() {
+                for (idx, channels) in self.route(&timelines[..]) {
+                    let sendable = cmd.into_sendable(&channels[..], self.sharded);
+                    self.queue_primary(idx, &sendable);
+                }
+            }
+            self.mark_subscribed(subscribed, &timelines[..]);
+            Ok(())
+        }
 
+        /// Group `channels` by the primary connection that takes their commands: in a cluster,
+        /// the one to the master that serves their slot.  A sharded command can only name the
+        /// channels of one slot, so those are grouped by slot as well.
+        fn route(&self, channels: &[String]) -> Vec<(usize, Vec<String>)> {
+            let mut groups: Vec<(usize, Option<u16>, Vec<String>)> = Vec::new();
+            for channel in channels {
+                let idx = self.cluster.as_ref().and_then(|slots| slots.node(channel));
+                let slot = Some(cluster::hash_slot(channel)).filter(|_| self.sharded);
+                let key = (idx.unwrap_or(0), slot);
+                match groups
+                    .iter_mut()
+                    .find(|(idx, slot, _)| (*idx, *slot) == key)
+                {
+                    Some((_, _, group)) => group.push(channel.clone()),
+                    None => groups.push((key.0, key.1, vec![channel.clone()])),
+                }
+            }
+            groups
+                .into_iter()
+                .map(|(idx, _, group)| (idx, group))
+                .collect()
+        }
+
+        /// Queue `cmd` for a primary connection and write as much of it as the connection
+        /// accepts.  While the connection is down, there's no need to queue commands: every
+        /// channel is subscribed to again once it reconnects.
+        fn queue_primary(&mut self, idx: usize, cmd: &[u8]) {
+            let primary = &mut self.primaries[idx];
+            if primary.link.get().is_none() {
+                return;
+            }
+            if primary.outgoing.is_empty() {
+                primary.queued_at = Some(Instant::now());
+            }
+            primary.outgoing.extend_from_slice(cmd);
+            self.flush_primary(idx);
+            if self.primaries[idx].outgoing.len() > MAX_OUTGOING {
+                tracing::error!("Redis isn't accepting commands; reconnecting");
+                metrics::REDIS_WRITE_OVERFLOWS.inc();
+                self.fail_primary(idx);
+            }
+        }
+
+        /// Set the subscribed keys for every timeline that still has subscribers so that they
+        /// don't expire.  The next refresh is due up to the `JITTER` early, so that instances
+        /// started together don't all set their keys at once.
+        pub(in super::super) fn refresh_subscribed_keys(
+            &mut self,
+            timelines: &[Timeline],
+        ) -> Result<()> {
+            let early = self
+                .subscribed_keys
+                .refresh_interval()
+                .map(|interval| interval - jitter::shorten(interval));
+            self.keys_refreshed = early
+                .and_then(|early| Instant::now().checked_sub(early))
+                .unwrap_or_else(Instant::now);
+            let timelines: Result<Vec<String>> =
+                timelines.iter().map(|tl| self.channel_name(tl)).collect();
+            self.mark_subscribed(true, &timelines?[..]);
+            Ok(())
+        }
+
+        /// Give up this instance's hold on the subscribed keys for `timelines` (e.g., when
+        /// shutting down), clearing only the keys that no other instance holds.  Expiring keys
+        /// are left to expire.
+        pub(in super::super) fn release_subscribed_keys(
+            &mut self,
+            timelines: &[Timeline],
+        ) -> Result<()> {
+            let channels: Vec<String> = timelines
+                .iter()
+                .map(|tl| self.channel_name(tl))
+                .collect::<Result<_>>()?;
+            self.failed_sets.supersede(&channels);
+            match self.subscribed_keys.cmd(false, &channels) {
+                Some((cmd, replies)) => self.set_keys(&cmd, replies),
+                None => Ok(()),
+            }
+        }
+
+        fn mark_subscribed(&mut self, subscribed: bool, channels: &[String]) {
             // We also need to set a key to tell the Puma server that we've subscribed or
             // unsubscribed to the channel because it stops publishing updates when it thinks
             // no one is subscribed.
             // (Documented in [PR #3278](https://github.com/tootsuite/mastodon/pull/3278))
             // Question: why can't the Puma server just use NUMSUB for this?
-            self.secondary.write_all(&secondary_cmd)?;
-            Ok(())
+            // A failed write for these channels would undo this one if it were retried
+            self.failed_sets.supersede(channels);
+            if let Some((cmd, replies)) = self.subscribed_keys.cmd(subscribed, channels) {
+                if let Err(e) = self.set_keys(&cmd, replies) {
+                    self.set_failed(subscribed, channels.to_vec(), 0, &e);
+                }
+            }
         }
 
-        fn new_connection(addr: &str, pass: Option<&String>) -> Result<TcpStream> {
-            let mut conn = TcpStream::connect(&addr)?;
-            if let Some(password) = pass {
-                Self::auth_connection(&mut conn, &addr, password)?;
+        /// Retry the writes to the secondary connection that failed and are due to be retried.
+        ///
+        /// If these writes fail, Mastodon believes no one is subscribed to the channel and
+        /// stops publishing to it, so we keep retrying (with backoff) until they succeed.
+        pub(in super::super) fn retry_failed_sets(&mut self) {
+            for failed in self.failed_sets.take_due(Instant::now()) {
+                let (subscribed, channels, attempts) =
+                    (failed.subscribed, failed.channels, failed.attempts);
+                // Built again, so that expiring keys are given a whole TTL
+                let (cmd, replies) = match self.subscribed_keys.cmd(subscribed, &channels) {
+                    Some(cmd) => cmd,
+                    None => continue,
+                };
+                match self.set_keys(&cmd, replies) {
+                    Ok(()) => tracing::warn!("Set subscribed keys after {} failures", attempts),
+                    Err(e) => self.set_failed(subscribed, channels, attempts, &e),
+                }
             }
+        }
 
-            Self::validate_connection(&mut conn, &addr)?;
-            conn.set_read_timeout(Some(Duration::from_millis(10)))
+        pub(in super::super) fn has_failed_sets(&self) -> bool {
+            !self.failed_sets.is_empty()
+        }
+
+        /// The addresses of the primary connections that are down, or that Redis hasn't
+        /// answered a `PING` on within `PONG_TIMEOUT` (and that are about to be given up on)
+        pub(in super::super) fn primaries_down(&self) -> Vec<&str> {
+            let down = |primary: &&Primary| match (&primary.link, primary.ping_sent) {
+                (Link::Down { .. }, _) => true,
+                (Link::Up(_), Some(sent)) => sent.elapsed() >= PONG_TIMEOUT,
+                (Link::Up(_), None) | (Link::Detached, _) => false,
+            };
+            let primaries = self.primaries.iter().filter(down);
+            primaries.map(|primary| primary.addr.as_str()).collect()
+        }
+
+        /// When there is next something to do that Redis won't wake us for: reconnecting a
+        /// failed primary connection, pinging an idle one, looking up a cluster's slots,
+        /// retrying a failed write, or setting the subscribed keys again.  (Input and room to
+        /// write commands wake the task that last found the primary connections without them.)
+        pub(in super::super) fn next_due(&self) -> Option<Instant> {
+            let reconnects = self.primaries.iter().filter_map(|p| p.link.retry_at());
+            let pings = self.primaries.iter().filter_map(|p| {
+                let interval = self.ping_interval?;
+                p.ping_due(interval)
+            });
+            let retries = self.failed_sets.next_retry();
+            reconnects
+                .chain(pings)
+                .chain(retries)
+                .chain(self.slots_due)
+                .chain(self.keys_refresh_at())
+                .min()
+        }
+
+        fn set_failed(
+            &mut self,
+            subscribed: bool,
+            channels: Vec<String>,
+            prev_attempts: u32,
+            e: &RedisConnErr,
+        ) {
+            metrics::SUBSCRIBED_KEY_FAILURES.inc();
+            let attempts = prev_attempts + 1;
+            let backoff = jitter::vary(Duration::from_millis(500 * 2_u64.pow(attempts.min(7))));
+            tracing::error!(
+                "Could not set subscribed keys (attempt {}); Mastodon will not publish to \
+                 these channels until they are set.  Retrying in {:?}.\n{}",
+                attempts,
+                backoff,
+                e
+            );
+            self.failed_sets.push(FailedSet {
+                subscribed,
+                channels,
+                attempts,
+                retry_at: Instant::now() + backoff,
+            });
+        }
+
+        /// Write `cmd` to the secondary connection and check that Redis accepted it (with one
+        /// `+OK` for each of the `replies` it expects).
+        fn set_keys(&mut self, cmd: &[u8], replies: usize) -> Result<()> {
+            self.keys_conn().send_secondary(cmd, replies, |reply| {
+                reply == &b"+OK\r\n".repeat(replies)[..]
+            })
+        }
+
+        /// `PUBLISH` each event to its channel, the way Mastodon does.
+        pub(in super::super) fn publish(&mut self, msgs: &[(String, String)]) -> Result<()> {
+            let publish = match self.sharded {
+                true => "*3\r\n$8\r\nSPUBLISH\r\n",
+                false => "*3\r\n$7\r\nPUBLISH\r\n",
+            };
+            let cmd: String = msgs
+                .iter()
+                .map(|(channel, event_txt)| {
+                    [publish, &bulk_string(channel), &bulk_string(event_txt)].concat()
+                })
+                .collect();
+            // Redis replies to each with the number of subscribers, e.g. `:2\r\n`
+            self.send_secondary(cmd.as_bytes(), msgs.len(), |reply| {
+                reply
+                    .split(|&byte| byte == b'\n')
+                    .all(|line| line.is_empty() || line[0] == b':')
+            })
+        }
+
+        /// Write `cmd` to the secondary connection and check the `replies` lines Redis sends
+        /// back with `is_valid`.  On failure, the connection is replaced (once its backoff has
+        /// passed) so that a late reply can't be mistaken for the reply to the next command.
+        fn send_secondary(
+            &mut self,
+            cmd: &[u8],
+            replies: usize,
+            is_valid: impl Fn(&[u8]) -> bool,
+        ) -> Result<()> {
+            if self.secondary.is_detached() {
+                return Ok(());
+            }
+            let (resolver, addr, auth) = (&mut self.resolver, &self.addr, self.auth.as_ref());
+            let (transport, master, db) = (&self.transport, self.master.as_deref(), self.db);
+            self.secondary.reconnect("secondary", || {
+                resolver.invalidate();
+                Self::new_secondary(resolver, transport, addr, master, auth, db)
+            });
+            let secondary = match self.secondary.get() {
+                Some(secondary) => secondary,
+                None => return Err(RedisConnErr::Disconnected(self.secondary.retry_in())),
+            };
+            let started = Instant::now();
+            let reply = Self::send_and_read_reply(secondary, cmd, replies);
+            metrics::REDIS_SECONDARY_ROUND_TRIP.observe(started.elapsed());
+            let result = reply
+                .map_err(|e| RedisConnErr::with_addr(&self.addr, e))
+                .and_then(|reply| {
+                    if is_valid(&reply) {
+                        Ok(())
+                    } else {
+                        let reply = String::from_utf8_lossy(&reply).to_string();
+                        Err(RedisConnErr::InvalidRedisReply(reply))
+                    }
+                });
+
+            if let Err(RedisConnErr::InvalidRedisReply(reply)) = &result {
+                if reply.starts_with("-READONLY") || reply.starts_with("-MOVED") {
+                    let reply = reply.clone();
+                    return Err(self.follow_redirect(&reply));
+                }
+            }
+            if result.is_err() {
+                self.secondary.fail();
+            }
+            result
+        }
+
+        /// Point the secondary connection at the writable master after a write was refused
+        /// because it reached a replica (`-READONLY`, e.g. after a failover) or a node that
+        /// doesn't hold the key (`-MOVED`).  The connection is replaced on the next write.
+        ///
+        /// A replica names its master in its reply to `ROLE`.  If the master can't be found,
+        /// `REDIS_HOST` is looked up again, in case it has moved to the new master.
+        fn follow_redirect(&mut self, reply: &str) -> RedisConnErr {
+            let from = self.master.clone().unwrap_or_else(|| self.addr.clone());
+            let master = match msg::parse_moved(reply) {
+                Some(addr) => Some(addr.to_string()),
+                None => self.secondary.get().and_then(|secondary| {
+                    let role = Self::send_and_read_reply(secondary, b"*1\r\n$4\r\nROLE\r\n", 5);
+                    let role = String::from_utf8_lossy(&role.ok()?).to_string();
+                    msg::parse_role(&role).ok()?
+                }),
+            };
+            self.secondary.fail();
+            metrics::REDIS_REDIRECTS.inc();
+            match master {
+                Some(master) => {
+                    tracing::warn!("Redis at {} redirected writes to {}", from, master);
+                    self.master = Some(master.clone());
+                    RedisConnErr::Redirected { from, to: master }
+                }
+                None => {
+                    tracing::warn!(
+                        "Redis at {} refused writes; looking up REDIS_HOST again",
+                        from
+                    );
+                    self.master = None;
+                    self.resolver.invalidate();
+                    RedisConnErr::InvalidRedisReply(reply.to_string())
+                }
+            }
+        }
+
+        /// A query for the channels Redis has subscribers for (from any client, not just
+        /// Flodgatt)
+        pub(in super::super) fn pubsub_channels(&self) -> ListQuery {
+            let pattern = timeline_pattern(&self.namespace);
+            let pubsub = match self.sharded {
+                true => "*3\r\n$6\r\nPUBSUB\r\n$13\r\nSHARDCHANNELS\r\n",
+                false => "*3\r\n$6\r\nPUBSUB\r\n$8\r\nCHANNELS\r\n",
+            };
+            // Each master of a cluster only lists the channels subscribed to on it
+            let nodes = match &self.cluster {
+                Some(slots) => slots.nodes().iter().cloned().map(Some).collect(),
+                None => vec![None],
+            };
+            self.list_query(nodes, [pubsub, &bulk_string(&pattern)].concat())
+        }
+
+        /// A query for the members of the set at `key`
+        pub(in super::super) fn set_members(&mut self, key: &str) -> ListQuery {
+            let cmd = ["*2\r\n$8\r\nSMEMBERS\r\n", &bulk_string(key)].concat();
+            let conn = self.keys_conn();
+            let node = conn.cluster.as_ref().and_then(|slots| {
+                let idx = slots.node(key)?;
+                Some(slots.nodes()[idx].clone())
+            });
+            conn.list_query(vec![node], cmd)
+        }
+
+        fn list_query(&self, nodes: Vec<Option<String>>, cmd: String) -> ListQuery {
+            ListQuery {
+                transport: self.transport.clone(),
+                resolver: self.resolver.clone(),
+                addr: self.addr.clone(),
+                auth: self.auth.clone(),
+                nodes,
+                cmd,
+                detached: self.secondary.is_detached(),
+            }
+        }
+
+        /// Add each of `members` to (or remove it from) the set at `key`
+        pub(in super::super) fn update_set(
+            &mut self,
+            key: &str,
+            members: &[String],
+            add: bool,
+        ) -> Result<()> {
+            let cmd = match add {
+                true => "*3\r\n$4\r\nSADD\r\n",
+                false => "*3\r\n$4\r\nSREM\r\n",
+            };
+            let cmd: String = members
+                .iter()
+                .map(|member| [cmd, &bulk_string(key), &bulk_string(member)].concat())
+                .collect();
+            // Redis replies to each with the number of members added or removed, e.g. `:1\r\n`
+            self.keys_conn()
+                .send_secondary(cmd.as_bytes(), members.len(), |reply| {
+                    reply
+                        .split(|&byte| byte == b'\n')
+                        .all(|line| line.is_empty() || line[0] == b':')
+                })
+        }
+
+        /// The fields of the hash at `key` and their values
+        pub(in super::super) fn hash_fields(&mut self, key: &str) -> Result<Vec<(String, String)>> {
+            let cmd = ["*2\r\n$7\r\nHGETALL\r\n", &bulk_string(key)].concat();
+            let conn = self.keys_conn();
+            let node = conn.cluster.as_ref().and_then(|slots| {
+                let idx = slots.node(key)?;
+                Some(slots.nodes()[idx].clone())
+            });
+            conn.read_reply(node.as_deref(), cmd.as_bytes(), |reply| {
+                let fields = msg::parse_hash(reply)?.into_iter();
+                Ok(fields
+                    .map(|(f, v)| (f.to_string(), v.to_string()))
+                    .collect())
+            })
+        }
+
+        /// Set `fields` of the hash at `key` and delete the `removed` ones; the hash expires
+        /// `ttl` after it was last updated.
+        pub(in super::super) fn update_hash(
+            &mut self,
+            key: &str,
+            fields: &[(String, String)],
+            removed: &[String],
+            ttl: Duration,
+        ) -> Result<()> {
+            let (mut cmd, mut replies) = (String::new(), 1);
+            if !fields.is_empty() {
+                cmd.push_str(&format!("*{}\r\n$4\r\nHSET\r\n", 2 + 2 * fields.len()));
+                cmd.push_str(&bulk_string(key));
+                for (field, value) in fields {
+                    cmd.push_str(&bulk_string(field));
+                    cmd.push_str(&bulk_string(value));
+                }
+                replies += 1;
+            }
+            if !removed.is_empty() {
+                cmd.push_str(&format!("*{}\r\n$4\r\nHDEL\r\n", 2 + removed.len()));
+                cmd.push_str(&bulk_string(key));
+                for field in removed {
+                    cmd.push_str(&bulk_string(field));
+                }
+                replies += 1;
+            }
+            let ttl = ttl.as_secs().to_string();
+            let expire = [&bulk_string(key), &bulk_string(&ttl)].concat();
+            cmd.push_str(&format!("*3\r\n$6\r\nEXPIRE\r\n{}", expire));
+            // Redis replies to each with an integer: the fields set or deleted, and whether the
+            // expiry was set
+            self.keys_conn()
+                .send_secondary(cmd.as_bytes(), replies, |reply| {
+                    reply
+                        .split(|&byte| byte == b'\n')
+                        .all(|line| line.is_empty() || line[0] == b':')
+                })
+        }
+
+        /// Send `cmd` (to the master of a cluster at `node`, if given) and `parse` the reply.
+        fn read_reply<T: Default>(
+            &mut self,
+            node: Option<&str>,
+            cmd: &[u8],
+            parse: impl Fn(&str) -> std::result::Result<T, RedisParseErr>,
+        ) -> Result<T> {
+            if self.secondary.is_detached() {
+                return Ok(T::default());
+            }
+            let (addr, auth) = (self.addr.as_str(), self.auth.as_ref());
+            Self::query(
+                &mut self.resolver,
+                &self.transport,
+                addr,
+                auth,
+                node,
+                cmd,
+                parse,
+            )
+        }
+
+        /// Send `cmd` to Redis at `addr` (or to the master of a cluster at `node`) and `parse`
+        /// the reply.
+        ///
+        /// This uses a short-lived connection because the primary connection is in pubsub
+        /// mode, where only the pubsub commands are allowed.
+        fn query<T>(
+            resolver: &mut CachingResolver,
+            transport: &Transport,
+            addr: &str,
+            auth: Option<&Auth>,
+            node: Option<&str>,
+            cmd: &[u8],
+            parse: impl Fn(&str) -> std::result::Result<T, RedisParseErr>,
+        ) -> Result<T> {
+            let addr = node.unwrap_or(addr);
+            let mut conn = match node {
+                Some(node) => Self::connect_node(transport, node, auth)?,
+                None => Self::new_connection(resolver, transport, addr, auth)?,
+            };
+            conn.set_read_timeout(Some(Duration::from_secs(5)))
+                .map_err(|e| RedisConnErr::with_addr(addr, e))?;
+            conn.write_all(cmd)
+                .map_err(|e| RedisConnErr::with_addr(addr, e))?;
+
+            let (mut reply, mut buffer) = (Vec::new(), vec![0_u8; 4096]);
+            loop {
+                let n = conn
+                    .read(&mut buffer)
+                    .map_err(|e| RedisConnErr::with_addr(addr, e))?;
+                reply.extend_from_slice(&buffer[..n]);
+                let reply_txt = String::from_utf8_lossy(&reply);
+                match parse(&reply_txt) {
+                    Ok(parsed) => break Ok(parsed),
+                    Err(RedisParseErr::Incomplete) if n > 0 => continue,
+                    Err(_) => break Err(RedisConnErr::InvalidRedisReply(reply_txt.to_string())),
+                }
+            }
+        }
+
+        /// Write `cmd` and read until Redis has sent `replies` lines (or closed the connection).
+        fn send_and_read_reply(
+            conn: &mut Socket,
+            cmd: &[u8],
+            replies: usize,
+        ) -> io::Result<Vec<u8>> {
+            conn.write_all(cmd)?;
+            let (mut reply, mut buffer) = (Vec::new(), [0_u8; 100]);
+            while reply.iter().filter(|&&byte| byte == b'\n').count() < replies {
+                let n = conn.read(&mut buffer)?;
+                if n == 0 {
+                    break;
+                }
+                reply.extend_from_slice(&buffer[..n]);
+            }
+            Ok(reply)
+        }
+
+        /// Open the secondary connection: to the `master` a replica redirected us to, if any,
+        /// and otherwise to `addr`, in the database `db` (if `REDIS_DB` is set).
+        fn new_secondary(
+            resolver: &mut CachingResolver,
+            transport: &Transport,
+            addr: &str,
+            master: Option<&str>,
+            auth: Option<&Auth>,
+            db: Option<u32>,
+        ) -> Result<Socket> {
+            let mut conn = match master {
+                Some(master) => Self::connect_node(transport, master, auth)?,
+                None => Self::new_connection(resolver, transport, addr, auth)?,
+            };
+            conn.set_read_timeout(Some(SET_TIMEOUT))
+                .map_err(|e| RedisConnErr::with_addr(addr, e))?;
+            if let Some(db) = db {
+                Self::select_db(&mut conn, addr, db)?;
+            }
+            Ok(conn)
+        }
+
+        /// Set the `subscribed:` keys in the database Mastodon reads them from.  (Only keys
+        /// belong to a database; what is published reaches subscribers in every database.)
+        fn select_db(conn: &mut Socket, addr: &str, db: u32) -> Result<()> {
+            let db_txt = db.to_string();
+            let cmd = format!("*2\r\n$6\r\nSELECT\r\n${}\r\n{}\r\n", db_txt.len(), db_txt);
+            let reply = Self::send_and_read_reply(conn, cmd.as_bytes(), 1)
+                .map_err(|e| RedisConnErr::with_addr(addr, e))?;
+            match reply.starts_with(b"+OK\r\n") {
+                true => Ok(()),
+                false => Err(RedisConnErr::SelectFailed {
+                    db,
+                    reply: String::from_utf8_lossy(&reply).trim_end().to_string(),
+                }),
+            }
+        }
+
+        /// Connect over TCP to the Redis server at `node` (`host:port`), such as a master that
+        /// a replica redirected us to or one of a cluster's masters.
+        fn connect_node(transport: &Transport, node: &str, auth: Option<&Auth>) -> Result<Socket> {
+            let addrs: Vec<SocketAddr> = node
+                .to_socket_addrs()
+                .map_err(|e| RedisConnErr::with_addr(node, e))?
+                .collect();
+            let tls = transport.tls.as_ref().map(|tls| tls.for_addr(node));
+            Self::open_connection(&addrs[..], transport, tls.as_ref(), node, auth)
+        }
+
+        /// Connect to Redis over the `transport`'s Unix domain socket, if it has one, and
+        /// otherwise over TCP to the addresses `addr` resolves to.
+        fn new_connection(
+            resolver: &mut CachingResolver,
+            transport: &Transport,
+            addr: &str,
+            auth: Option<&Auth>,
+        ) -> Result<Socket> {
+            let tls = transport.tls.as_ref();
+            if let Some(path) = &transport.unix_socket {
+                let conn =
+                    UnixStream::connect(path).map_err(|e| RedisConnErr::with_addr(addr, e))?;
+                return Self::handshake(Socket::Unix(conn), tls, addr, auth);
+            }
+            let addrs = resolver
+                .addrs()
+                .map_err(|e| RedisConnErr::with_addr(addr, e))?;
+            Self::open_connection(&addrs[..], transport, tls, addr, auth).map_err(|e| {
+                resolver.invalidate(); // the host may have moved; look it up again next time
+                e
+            })
+        }
+
+        /// Connect over TCP to the first of `addrs` that accepts, from the `transport`'s local
+        /// address for its IP family (if it has one)
+        fn open_connection(
+            addrs: &[SocketAddr],
+            transport: &Transport,
+            tls: Option<&Tls>,
+            addr: &str,
+            auth: Option<&Auth>,
+        ) -> Result<Socket> {
+            let conn = socket::connect_tcp(addrs, &transport.bind, None)
+                .map_err(|e| RedisConnErr::with_addr(addr, e))?;
+            Self::handshake(Socket::Tcp(conn), tls, addr, auth)
+        }
+
+        /// Start a TLS session (if there are `tls` settings), authenticate (if there is a
+        /// password, as `REDIS_USER` if that is set), check that the connection leads to Redis,
+        /// name it, and switch it to RESP3 if Redis can, whichever socket it runs over.
+        fn handshake(
+            conn: Socket,
+            tls: Option<&Tls>,
+            addr: &str,
+            auth: Option<&Auth>,
+        ) -> Result<Socket> {
+            conn.set_read_timeout(Some(HANDSHAKE_TIMEOUT))
                 .map_err(|e| RedisConnErr::with_addr(&addr, e))?;
+            let mut conn = match tls {
+                Some(tls) => tls.wrap(conn)?,
+                None => conn,
+            };
+            if let Some(auth) = auth {
+                Self::auth_connection(&mut conn, &addr, auth)?;
+            }
+
+            Self::validate_connection(&mut conn, &addr)?;
             Self::set_connection_name(&mut conn, &addr)?;
+            Self::negotiate_protocol(&mut conn, &addr)?;
             Ok(conn)
         }
 
-        fn auth_connection(conn: &mut TcpStream, addr: &str, pass: &str) -> Result<()> {
-            conn.write_all(
-                &[
-                    b"*2\r\n$4\r\nauth\r\n$",
-                    pass.len().to_string().as_bytes(),
-                    b"\r\n",
-                    pass.as_bytes(),
-                    b"\r\n",
-                ]
-                .concat(),
-            )
-            .map_err(|e| RedisConnErr::with_addr(&addr, e))?;
-            let mut buffer = vec![0_u8; 5];
-            conn.read_exact(&mut buffer)
+        /// Ask for RESP3 with `HELLO 3`, so that pubsub messages arrive as push frames.  Redis
+        /// before 6 rejects the command, and the connection stays on RESP2, which the parser
+        /// reads just as well.
+        fn negotiate_protocol(conn: &mut Socket, addr: &str) -> Result<()> {
+            conn.write_all(b"*2\r\n$5\r\nHELLO\r\n$1\r\n3\r\n")
+                .map_err(|e| RedisConnErr::with_addr(&addr, e))?;
+            let (mut reply, mut buffer) = (Vec::new(), vec![0_u8; 512]);
+            loop {
+                let n = conn
+                    .read(&mut buffer)
+                    .map_err(|e| RedisConnErr::with_addr(&addr, e))?;
+                reply.extend_from_slice(&buffer[..n]);
+                let reply_txt = String::from_utf8_lossy(&reply);
+                match msg::parse_hello(&reply_txt) {
+                    Ok(Some(3)) => break Ok(()),
+                    Ok(_) => {
+                        tracing::info!("Redis at {} doesn't speak RESP3; using RESP2", addr);
+                        break Ok(());
+                    }
+                    Err(RedisParseErr::Incomplete) if n > 0 => continue,
+                    Err(_) => break Err(RedisConnErr::InvalidRedisReply(reply_txt.to_string())),
+                }
+            }
+        }
+
+        /// Send `AUTH [user] password`.  A Redis older than 6 has no ACL users, and so rejects
+        /// `AUTH` with a user as having too many arguments.
+        fn auth_connection(conn: &mut Socket, addr: &str, auth: &Auth) -> Result<()> {
+            let args: Vec<&str> = auth
+                .user
+                .iter()
+                .chain(Some(&auth.password))
+                .map(String::as_str)
+                .collect();
+            let mut cmd = format!("*{}\r\n$4\r\nauth\r\n", args.len() + 1);
+            for arg in args {
+                cmd.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+            }
+            conn.write_all(cmd.as_bytes())
                 .map_err(|e| RedisConnErr::with_addr(&addr, e))?;
-            if String::from_utf8_lossy(&buffer) != "+OK\r\n" {
-                Err(RedisConnErr::IncorrectPassword(pass.to_string()))?
+            let mut buffer = vec![0_u8; 100];
+            let n = conn
+                .read(&mut buffer)
+                .map_err(|e| RedisConnErr::with_addr(&addr, e))?;
+            let reply = String::from_utf8_lossy(&buffer[..n]);
+            match (&*reply, &auth.user) {
+                (r, _) if r.starts_with("+OK\r\n") => Ok(()),
+                (r, Some(user)) if r.starts_with("-ERR wrong number of arguments") => {
+                    Err(RedisConnErr::AclUnsupported(user.clone()))
+                }
+                _ => Err(RedisConnErr::IncorrectPassword(auth.password.clone())),
             }
-            Ok(())
         }
 
-        fn validate_connection(conn: &mut TcpStream, addr: &str) -> Result<()> {
+        fn validate_connection(conn: &mut Socket, addr: &str) -> Result<()> {
             conn.write_all(b"PING\r\n")
                 .map_err(|e| RedisConnErr::with_addr(&addr, e))?;
             let mut buffer = vec![0_u8; 100];
@@ -142,7 +1441,7 @@ mod connection {
             }
         }
 
-        fn set_connection_name(conn: &mut TcpStream, addr: &str) -> Result<()> {
+        fn set_connection_name(conn: &mut Socket, addr: &str) -> Result<()> {
             conn.write_all(b"*3\r\n$6\r\nCLIENT\r\n$7\r\nSETNAME\r\n$8\r\nflodgatt\r\n")
                 .map_err(|e| RedisConnErr::with_addr(&addr, e))?;
             let mut buffer = vec![0_u8; 100];
@@ -159,40 +1458,118 @@ mod connection {
 #[cfg(any(test, feature = "bench"))]
 mod mock_connection {
     use super::super::Error as ManagerErr;
-    use super::super::RedisCmd;
+    use super::super::{RedisCmd, SubscribedKeys};
     use super::err::RedisConnErr;
+    use super::INPUT_SIZE;
     use crate::config::Redis;
-    use crate::request::Timeline;
+    use crate::request::{TagRegistry, Timeline};
 
     use futures::{Async, Poll};
-    use lru::LruCache;
-    use std::collections::VecDeque;
+    use std::collections::{BTreeMap, BTreeSet, VecDeque};
+    use std::time::{Duration, Instant};
 
     type Result<T> = std::result::Result<T, RedisConnErr>;
 
+    /// The list a test set up, which the query would have read from Redis
+    #[derive(Debug)]
+    pub struct ListQuery(Vec<String>);
+
+    impl ListQuery {
+        pub fn run(self) -> Result<Vec<String>> {
+            Ok(self.0)
+        }
+    }
+
     #[derive(Debug)]
     pub struct RedisConn {
+        pub(in super::super) subscribed_keys: SubscribedKeys,
+        pub(in super::super) keys_refreshed: Instant,
         pub(in super::super) namespace: Option<String>,
-        pub(in super::super) tag_name_cache: LruCache<i64, String>,
+        pub(in super::super) tags: TagRegistry,
         pub(in super::super) input: Vec<u8>,
+        pub(in super::super) input_max: Option<usize>,
         pub(in super::super) test_input: VecDeque<u8>,
+        pub(in super::super) test_pubsub_channels: Vec<String>,
+        pub(in super::super) test_key_cmds: Vec<Vec<u8>>,
+        pub(in super::super) test_published: Vec<(String, String)>,
+        pub(in super::super) test_reconnected: bool,
+        pub(in super::super) test_down: Vec<String>,
+        pub(in super::super) test_set: BTreeSet<String>,
+        pub(in super::super) test_hash: BTreeMap<String, String>,
     }
 
     impl RedisConn {
         pub(in super::super) fn new(redis_cfg: &Redis) -> Result<Self> {
-            Ok(Self {
-                tag_name_cache: LruCache::new(1000),
+            Ok(Self::detached(redis_cfg))
+        }
+
+        pub(in super::super) fn detached(redis_cfg: &Redis) -> Self {
+            Self {
+                subscribed_keys: SubscribedKeys::from_cfg(redis_cfg),
+                keys_refreshed: Instant::now(),
+                tags: TagRegistry::new(*redis_cfg.tag_cache_size),
                 namespace: redis_cfg.namespace.clone().0,
-                input: vec![0; 4096 * 4],
+                input: vec![0; INPUT_SIZE],
+                input_max: *redis_cfg.max_input_buffer,
                 test_input: VecDeque::new(),
-            })
+                test_pubsub_channels: Vec::new(),
+                test_key_cmds: Vec::new(),
+                test_published: Vec::new(),
+                test_reconnected: false,
+                test_down: Vec::new(),
+                test_set: BTreeSet::new(),
+                test_hash: BTreeMap::new(),
+            }
+        }
+
+        pub(in super::super) fn pubsub_channels(&self) -> ListQuery {
+            ListQuery(self.test_pubsub_channels.clone())
+        }
+
+        pub(in super::super) fn set_members(&self, _key: &str) -> ListQuery {
+            ListQuery(self.test_set.iter().cloned().collect())
+        }
+
+        pub(in super::super) fn update_set(
+            &mut self,
+            _key: &str,
+            members: &[String],
+            add: bool,
+        ) -> Result<()> {
+            for member in members {
+                match add {
+                    true => self.test_set.insert(member.clone()),
+                    false => self.test_set.remove(member),
+                };
+            }
+            Ok(())
+        }
+
+        pub(in super::super) fn hash_fields(&self, _key: &str) -> Result<Vec<(String, String)>> {
+            Ok(self.test_hash.clone().into_iter().collect())
+        }
+
+        pub(in super::super) fn update_hash(
+            &mut self,
+            _key: &str,
+            fields: &[(String, String)],
+            removed: &[String],
+            _ttl: Duration,
+        ) -> Result<()> {
+            self.test_hash.extend(fields.iter().cloned());
+            for field in removed {
+                self.test_hash.remove(field);
+            }
+            Ok(())
         }
 
         pub fn poll_redis(&mut self, start: usize) -> Poll<Option<usize>, ManagerErr> {
             const BLOCK: usize = 4096 * 2;
-            if self.input.len() < start + BLOCK {
-                self.input.resize(self.input.len() * 2, 0);
-                log::info!("Resizing input buffer to {} KiB.", self.input.len() / 1024);
+            if let Err(e) = self.reserve_input(start, BLOCK) {
+                // Like the real connection, drop the rest of the input with the connection
+                self.test_input.clear();
+                self.test_reconnected = true;
+                return Err(e.into());
             }
 
             for i in 0..BLOCK {
@@ -207,20 +1584,76 @@ mod mock_connection {
             Ok(Async::Ready(Some(BLOCK)))
         }
 
+        pub(in super::super) fn publish(&mut self, msgs: &[(String, String)]) -> Result<()> {
+            self.test_published.extend_from_slice(msgs);
+            Ok(())
+        }
+
+        pub(in super::super) fn retry_failed_sets(&mut self) {}
+
+        pub(in super::super) fn has_failed_sets(&self) -> bool {
+            false
+        }
+
+        pub(in super::super) fn next_due(&self) -> Option<Instant> {
+            self.keys_refresh_at()
+        }
+
+        pub(in super::super) fn reconnect_primary(&mut self) -> bool {
+            std::mem::take(&mut self.test_reconnected)
+        }
+
+        pub(in super::super) fn has_queued_cmds(&self) -> bool {
+            false
+        }
+
+        pub(in super::super) fn primaries_down(&self) -> Vec<&str> {
+            self.test_down.iter().map(String::as_str).collect()
+        }
+
+        pub(in super::super) fn ping_idle_primaries(&mut self) {}
+
         pub fn add(&mut self, input: &[u8]) {
             for byte in input {
                 self.test_input.push_back(*byte)
             }
         }
         pub(crate) fn send_cmd(&mut self, cmd: RedisCmd, timelines: &[Timeline]) -> Result<()> {
-            // stub - does nothing; silences some unused-code warnings
-            let timelines: Result<Vec<String>> = timelines
-                .iter()
-                .map(|tl| Ok(tl.to_redis_raw_timeline(None).expect("test")))
-                .collect();
+            // stub - only records the subscribed keys; silences some unused-code warnings
+            let timelines: Result<Vec<String>> =
+                timelines.iter().map(|tl| self.channel_name(tl)).collect();
+            let timelines = timelines?;
 
-            let _ = cmd.into_sendable(&timelines?);
+            let subscribed = cmd.is_subscribe();
+            let _ = cmd.into_sendable(&timelines, false);
+            self.test_key_cmds.extend(
+                self.subscribed_keys
+                    .cmd(subscribed, &timelines)
+                    .map(|(cmd, _)| cmd),
+            );
+            Ok(())
+        }
+
+        pub(in super::super) fn refresh_subscribed_keys(
+            &mut self,
+            timelines: &[Timeline],
+        ) -> Result<()> {
+            self.keys_refreshed = Instant::now();
+            self.send_cmd(RedisCmd::Subscribe, timelines)
+        }
 
+        pub(in super::super) fn release_subscribed_keys(
+            &mut self,
+            timelines: &[Timeline],
+        ) -> Result<()> {
+            let channels: Result<Vec<String>> =
+                timelines.iter().map(|tl| self.channel_name(tl)).collect();
+            let channels = channels?;
+            self.test_key_cmds.extend(
+                self.subscribed_keys
+                    .cmd(false, &channels)
+                    .map(|(cmd, _)| cmd),
+            );
             Ok(())
         }
     }
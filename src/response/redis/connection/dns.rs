@@ -0,0 +1,68 @@
+//! A cache of the addresses a host resolves to.
+//!
+//! The system's resolver (`to_socket_addrs`) doesn't report the records' TTLs, so the cache
+//! keeps addresses for the fixed `REDIS_DNS_TTL` instead: set it no longer than the TTL of the
+//! Redis host's records, or a failover done through DNS is picked up only once both have
+//! expired (or a connection to the old address fails, which discards the cached addresses).
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+#[cfg(test)]
+mod test;
+
+/// A way to look up the addresses for a host
+pub trait Resolve {
+    fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>>;
+}
+
+/// Caches the addresses a `Resolve` returns for a single host
+#[derive(Debug, Clone)]
+pub struct DnsCache<R> {
+    inner: R,
+    host: String,
+    port: u16,
+    ttl: Duration,
+    /// The most recently resolved addresses, and when they expire
+    cached: Option<(Instant, Vec<SocketAddr>)>,
+}
+
+impl<R: Resolve> DnsCache<R> {
+    pub fn new(inner: R, host: &str, port: u16, ttl: Duration) -> Self {
+        Self {
+            inner,
+            host: host.to_string(),
+            port,
+            ttl,
+            cached: None,
+        }
+    }
+
+    /// The addresses for the host, resolving them again if the cached ones have expired
+    pub fn addrs(&mut self) -> io::Result<Vec<SocketAddr>> {
+        match &self.cached {
+            Some((expires_at, addrs)) if Instant::now() < *expires_at => Ok(addrs.clone()),
+            previous => {
+                let addrs = self.inner.resolve(&self.host, self.port)?;
+                if addrs.is_empty() {
+                    let msg = format!("{} did not resolve to any addresses", self.host);
+                    Err(io::Error::new(io::ErrorKind::NotFound, msg))?
+                }
+                if let Some((_, previous_addrs)) = previous {
+                    if *previous_addrs != addrs {
+                        tracing::warn!("{} now resolves to {:?}", self.host, addrs);
+                    }
+                }
+                self.cached = Some((Instant::now() + self.ttl, addrs.clone()));
+                Ok(addrs)
+            }
+        }
+    }
+
+    /// Discard the cached addresses, so that the next lookup resolves the host again
+    pub fn invalidate(&mut self) {
+        if let Some((expires_at, _)) = &mut self.cached {
+            *expires_at = Instant::now();
+        }
+    }
+}
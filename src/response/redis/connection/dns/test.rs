@@ -0,0 +1,72 @@
+use super::*;
+use std::cell::{Cell, RefCell};
+
+/// Answers with whatever addresses a test gives it, counting the lookups
+#[derive(Default)]
+struct FakeDns {
+    addrs: RefCell<Vec<SocketAddr>>,
+    lookups: Cell<usize>,
+}
+
+impl FakeDns {
+    fn answer(&self, addr: &str) {
+        *self.addrs.borrow_mut() = vec![addr.parse().expect("a socket address")];
+    }
+}
+
+impl Resolve for &FakeDns {
+    fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        assert_eq!((host, port), ("redis.example", 6379));
+        self.lookups.set(self.lookups.get() + 1);
+        Ok(self.addrs.borrow().clone())
+    }
+}
+
+fn addrs(addr: &str) -> Vec<SocketAddr> {
+    vec![addr.parse().expect("a socket address")]
+}
+
+#[test]
+fn addresses_are_cached_until_they_expire() -> io::Result<()> {
+    let dns = FakeDns::default();
+    dns.answer("10.0.0.1:6379");
+    let mut cache = DnsCache::new(&dns, "redis.example", 6379, Duration::from_secs(60));
+    assert_eq!(cache.addrs()?, addrs("10.0.0.1:6379"));
+
+    // A failover that changes the record isn't seen while the cached addresses are fresh
+    dns.answer("10.0.0.2:6379");
+    assert_eq!(cache.addrs()?, addrs("10.0.0.1:6379"));
+    assert_eq!(dns.lookups.get(), 1);
+
+    // but is once they expire, which they do at once with a TTL of 0
+    let mut uncached = DnsCache::new(&dns, "redis.example", 6379, Duration::from_secs(0));
+    assert_eq!(uncached.addrs()?, addrs("10.0.0.2:6379"));
+    dns.answer("10.0.0.3:6379");
+    assert_eq!(uncached.addrs()?, addrs("10.0.0.3:6379"));
+    assert_eq!(dns.lookups.get(), 3);
+    Ok(())
+}
+
+#[test]
+fn invalidated_addresses_are_resolved_again() -> io::Result<()> {
+    let dns = FakeDns::default();
+    let mut cache = DnsCache::new(&dns, "redis.example", 6379, Duration::from_secs(60));
+    cache.invalidate(); // with nothing cached yet, there is nothing to discard
+
+    dns.answer("10.0.0.1:6379");
+    assert_eq!(cache.addrs()?, addrs("10.0.0.1:6379"));
+    dns.answer("10.0.0.2:6379");
+    cache.invalidate();
+    assert_eq!(cache.addrs()?, addrs("10.0.0.2:6379"));
+    assert_eq!(cache.addrs()?, addrs("10.0.0.2:6379"));
+    assert_eq!(dns.lookups.get(), 2);
+    Ok(())
+}
+
+#[test]
+fn a_host_without_addresses_is_an_error() {
+    let dns = FakeDns::default();
+    let mut cache = DnsCache::new(&dns, "redis.example", 6379, Duration::from_secs(60));
+    let err = cache.addrs().map_err(|e| e.kind());
+    assert_eq!(err, Err(io::ErrorKind::NotFound));
+}
@@ -1,15 +1,38 @@
 use crate::request;
 use std::fmt;
+use std::time::Duration;
 
 #[derive(Debug)]
 pub enum RedisConnErr {
-    ConnectionErr { addr: String, inner: std::io::Error },
+    ConnectionErr {
+        addr: String,
+        inner: std::io::Error,
+    },
     InvalidRedisReply(String),
     UnknownRedisErr(std::io::Error),
     IncorrectPassword(String),
     MissingPassword,
+    /// REDIS_USER is set, but Redis predates ACL users (added in Redis 6)
+    AclUnsupported(String),
     NotRedis(String),
     TimelineErr(request::TimelineErr),
+    /// The connection failed and will be replaced after this long
+    Disconnected(Duration),
+    /// Redis refused a write and named the node that takes it
+    Redirected {
+        from: String,
+        to: String,
+    },
+    /// Redis refused to `SELECT` the REDIS_DB database
+    SelectFailed {
+        db: u32,
+        reply: String,
+    },
+    /// REDIS_TLS is set, but its certificates can't be used (or the server's name can't be
+    /// checked against them)
+    Tls(String),
+    /// A message from Redis would take more than REDIS_MAX_INPUT_BUFFER bytes to buffer
+    InputOverflow(usize),
 }
 
 impl RedisConnErr {
@@ -40,19 +63,43 @@ impl fmt::Display for RedisConnErr {
             }
             IncorrectPassword(attempted_password) => format!(
                 "Incorrect Redis password.  You supplied `{}`.\n \
-                 Please supply correct password with REDIS_PASSWORD environmental variable.",
+                 Please supply correct password with REDIS_PASSWORD environmental variable \
+                 (and the user it belongs to, if any, with REDIS_USER).",
                 attempted_password
             ),
             MissingPassword => "Invalid authentication for Redis.  Redis is configured to require \
                                 a password, but you did not provide one. \n\
                                 Set a password using the REDIS_PASSWORD environmental variable."
                 .to_string(),
+            AclUnsupported(user) => format!(
+                "Redis rejected the user `{}`: Redis servers older than version 6 do not have \
+                 users.\n Please upgrade Redis, or unset the REDIS_USER environmental variable.",
+                user
+            ),
             NotRedis(addr) => format!(
                 "The server at {} is not a Redis server.  Please update the REDIS_HOST and/or \
                  REDIS_PORT environmental variables and try again.",
                 addr
             ),
             TimelineErr(inner) => format!("{}", inner),
+            Disconnected(retry_in) => {
+                format!("Not connected to Redis; reconnecting in {:?}", retry_in)
+            }
+            Redirected { from, to } => format!(
+                "Redis at {} does not accept writes; reconnecting to the master at {}",
+                from, to
+            ),
+            SelectFailed { db, reply } => format!(
+                "Redis could not select database {}: `{}`.\n \
+                 Please set the REDIS_DB environmental variable to a database Redis has.",
+                db, reply
+            ),
+            Tls(msg) => format!("Could not connect to Redis over TLS: {}", msg),
+            InputOverflow(max) => format!(
+                "A message from Redis is larger than the input buffer may grow ({} KiB); \
+                 dropping it and reconnecting.\n Raise REDIS_MAX_INPUT_BUFFER to accept it.",
+                max / 1024
+            ),
         };
         write!(f, "{}", msg)
     }
@@ -0,0 +1,71 @@
+//! The writes of subscribed keys that failed, which are retried (with backoff) until they
+//! succeed, since Mastodon stops publishing to a channel when it believes no one is subscribed.
+//!
+//! A newer write for a channel supersedes any write for it that is waiting to be retried,
+//! whether the newer one succeeded or is waiting in turn.  Otherwise a retry could undo it:
+//! setting the key of a channel that has since been unsubscribed from would keep Mastodon
+//! publishing to a channel that no one reads, and clearing the key of one that has since been
+//! subscribed to again would stop the events its clients are waiting for.
+use crate::metrics;
+
+use std::collections::VecDeque;
+use std::time::Instant;
+
+#[cfg(test)]
+mod test;
+
+/// The most failed writes to keep for retrying; beyond that, the oldest are dropped
+/// (`expiring` keys are set again when they are next refreshed)
+const MAX_FAILED_SETS: usize = 1000;
+
+/// A write that marked `channels` as subscribed (or unsubscribed), which failed
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailedSet {
+    pub subscribed: bool,
+    pub channels: Vec<String>,
+    pub attempts: u32,
+    pub retry_at: Instant,
+}
+
+/// The failed writes, oldest first, with at most one for each channel
+#[derive(Debug, Default)]
+pub struct FailedSets(VecDeque<FailedSet>);
+
+impl FailedSets {
+    /// Stop retrying the writes for `channels`, which a newer write supersedes
+    pub fn supersede(&mut self, channels: &[String]) {
+        for failed in self.0.iter_mut() {
+            failed
+                .channels
+                .retain(|channel| !channels.contains(channel));
+        }
+        self.0.retain(|failed| !failed.channels.is_empty());
+    }
+
+    /// Retry `failed` once it is due, in place of any write for its channels
+    pub fn push(&mut self, failed: FailedSet) {
+        self.supersede(&failed.channels);
+        if self.0.len() >= MAX_FAILED_SETS {
+            tracing::error!("Too many failed writes of subscribed keys; dropping the oldest");
+            metrics::SUBSCRIBED_KEY_RETRIES_DROPPED.inc();
+            self.0.pop_front();
+        }
+        self.0.push_back(failed);
+    }
+
+    /// Take the writes that are due to be retried at `now`
+    pub fn take_due(&mut self, now: Instant) -> Vec<FailedSet> {
+        let (due, waiting) = self.0.drain(..).partition(|failed| failed.retry_at <= now);
+        self.0 = waiting;
+        due
+    }
+
+    /// When the next write is due to be retried
+    pub fn next_retry(&self) -> Option<Instant> {
+        self.0.iter().map(|failed| failed.retry_at).min()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
@@ -0,0 +1,73 @@
+use super::*;
+use std::time::Duration;
+
+fn failed(subscribed: bool, channels: &[&str]) -> FailedSet {
+    FailedSet {
+        subscribed,
+        channels: self::channels(channels),
+        attempts: 1,
+        retry_at: Instant::now(),
+    }
+}
+
+fn channels(channels: &[&str]) -> Vec<String> {
+    channels.iter().map(|channel| channel.to_string()).collect()
+}
+
+/// The writes due now, as whether each subscribes and to which channels
+fn due(failed_sets: &mut FailedSets) -> Vec<(bool, Vec<String>)> {
+    let due = failed_sets.take_due(Instant::now()).into_iter();
+    due.map(|failed| (failed.subscribed, failed.channels))
+        .collect()
+}
+
+#[test]
+fn a_failed_subscribe_is_not_retried_after_a_newer_unsubscribe() {
+    let mut failed_sets = FailedSets::default();
+    failed_sets.push(failed(true, &["timeline:public", "timeline:hashtag:rust"]));
+
+    // The last client of `#rust` left, and its key was cleared
+    failed_sets.supersede(&channels(&["timeline:hashtag:rust"]));
+
+    assert_eq!(
+        due(&mut failed_sets),
+        vec![(true, channels(&["timeline:public"]))]
+    );
+    assert!(failed_sets.is_empty());
+}
+
+#[test]
+fn a_failed_unsubscribe_is_not_retried_after_a_newer_subscribe() {
+    let mut failed_sets = FailedSets::default();
+    failed_sets.push(failed(false, &["timeline:public"]));
+
+    // A client subscribed again, and that write failed too: only it is retried
+    failed_sets.push(failed(true, &["timeline:public"]));
+    assert_eq!(
+        due(&mut failed_sets),
+        vec![(true, channels(&["timeline:public"]))]
+    );
+
+    // or it succeeded, and nothing is
+    failed_sets.push(failed(false, &["timeline:public"]));
+    failed_sets.supersede(&channels(&["timeline:public"]));
+    assert_eq!(due(&mut failed_sets), vec![]);
+}
+
+#[test]
+fn failed_writes_wait_until_they_are_due() {
+    let mut failed_sets = FailedSets::default();
+    let later = Instant::now() + Duration::from_secs(60);
+    failed_sets.push(FailedSet {
+        retry_at: later,
+        ..failed(true, &["timeline:public"])
+    });
+    failed_sets.push(failed(true, &["timeline:public:local"]));
+
+    assert_eq!(failed_sets.next_retry().map(|at| at < later), Some(true));
+    assert_eq!(
+        due(&mut failed_sets),
+        vec![(true, channels(&["timeline:public:local"]))]
+    );
+    assert_eq!(failed_sets.next_retry(), Some(later));
+}
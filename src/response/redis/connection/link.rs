@@ -0,0 +1,106 @@
+//! One of the connections to Redis, which is replaced (after a backoff) when it fails
+use crate::jitter;
+use crate::metrics;
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// How long to wait before the first attempt to replace a failed connection
+const MIN_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub(super) enum Link<T> {
+    Up(T),
+    /// Failed; the next attempt to reconnect is due at `retry_at`
+    Down {
+        retry_at: Instant,
+        backoff: Duration,
+    },
+    /// Never connects (in `--dev` mode)
+    Detached,
+}
+
+impl<T> Link<T> {
+    /// A link that has yet to connect; the first attempt is due now.
+    pub(super) fn down() -> Self {
+        Link::Down {
+            retry_at: Instant::now(),
+            backoff: MIN_BACKOFF,
+        }
+    }
+
+    pub(super) fn get(&mut self) -> Option<&mut T> {
+        match self {
+            Link::Up(conn) => Some(conn),
+            Link::Down { .. } | Link::Detached => None,
+        }
+    }
+
+    pub(super) fn is_detached(&self) -> bool {
+        matches!(self, Link::Detached)
+    }
+
+    /// Drop a connection that has failed; the first attempt to replace it is due after
+    /// `MIN_BACKOFF` (varied, as each backoff is, by the `JITTER`).
+    pub(super) fn fail(&mut self) {
+        if let Link::Up(_) = self {
+            *self = Link::Down {
+                retry_at: Instant::now() + jitter::vary(MIN_BACKOFF),
+                backoff: MIN_BACKOFF,
+            };
+        }
+    }
+
+    /// If an attempt to reconnect is due, try to `connect`, doubling the backoff if that
+    /// fails.  Returns whether the link came back up.
+    pub(super) fn reconnect<E: fmt::Display>(
+        &mut self,
+        name: &str,
+        connect: impl FnOnce() -> Result<T, E>,
+    ) -> bool {
+        let backoff = match self {
+            Link::Down { retry_at, backoff } if *retry_at <= Instant::now() => *backoff,
+            Link::Up(_) | Link::Down { .. } | Link::Detached => return false,
+        };
+        match connect() {
+            Ok(conn) => {
+                tracing::warn!("Reconnected the {} connection to Redis", name);
+                metrics::REDIS_RECONNECTS.inc();
+                *self = Link::Up(conn);
+                true
+            }
+            Err(e) => {
+                let backoff = (backoff * 2).min(MAX_BACKOFF);
+                let delay = jitter::vary(backoff);
+                tracing::error!(
+                    "Could not reconnect the {} connection to Redis; retrying in {:?}.\n{}",
+                    name,
+                    delay,
+                    e
+                );
+                *self = Link::Down {
+                    retry_at: Instant::now() + delay,
+                    backoff,
+                };
+                false
+            }
+        }
+    }
+
+    /// When the next attempt to reconnect is due, if the link is down
+    pub(super) fn retry_at(&self) -> Option<Instant> {
+        match self {
+            Link::Down { retry_at, .. } => Some(*retry_at),
+            Link::Up(_) | Link::Detached => None,
+        }
+    }
+
+    /// How long until the next attempt to reconnect (zero, unless the link is down)
+    pub(super) fn retry_in(&self) -> Duration {
+        match self {
+            Link::Down { retry_at, .. } => retry_at.saturating_duration_since(Instant::now()),
+            Link::Up(_) | Link::Detached => Duration::from_secs(0),
+        }
+    }
+}
@@ -0,0 +1,133 @@
+//! Resolves the Redis host to the addresses Flodgatt connects to.
+//!
+//! Results are cached (see `DnsCache`) so that opening a connection (for example, to list
+//! Redis's channels) doesn't cost a DNS lookup, but they are resolved again once
+//! `REDIS_DNS_TTL` expires or a connection fails.  This lets Flodgatt follow a Redis failover
+//! that is done by updating DNS records (as many managed Redis providers do) without being
+//! restarted.
+//!
+//! With `REDIS_SENTINEL_HOSTS`, the address of the master comes from Redis Sentinel instead.
+//! Sentinel closes the connections to a master it demotes, and a replacement connection asks
+//! the Sentinels again, so Flodgatt follows a failover to whichever replica they promote.
+use super::super::msg::{self, RedisParseErr};
+use super::super::subscribed_keys::bulk_string;
+use super::dns::{DnsCache, Resolve};
+use super::socket;
+use crate::config::Redis;
+
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// How long to wait for a Sentinel to accept a connection, and then to reply
+const SENTINEL_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Looks up addresses with the operating system's resolver
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemResolver;
+
+impl Resolve for SystemResolver {
+    fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        Ok((host, port).to_socket_addrs()?.collect())
+    }
+}
+
+/// Asks each Redis Sentinel in turn for the address of the master it monitors under the name
+/// it is given as the host (the port is ignored)
+#[derive(Debug, Clone)]
+pub struct SentinelResolver {
+    sentinels: Vec<String>,
+    /// The local addresses to connect from (`REDIS_BIND_ADDRESS`)
+    bind: Vec<IpAddr>,
+}
+
+impl SentinelResolver {
+    pub fn new(sentinels: Vec<String>, bind: Vec<IpAddr>) -> Self {
+        Self { sentinels, bind }
+    }
+
+    /// The host and port of the master the `sentinel` monitors as `master_name`, if it knows it
+    fn ask(&self, sentinel: &str, master_name: &str) -> io::Result<Option<(String, u16)>> {
+        let mut conn = self.connect(sentinel)?;
+        conn.set_read_timeout(Some(SENTINEL_TIMEOUT))?;
+        let cmd = "*3\r\n$8\r\nSENTINEL\r\n$23\r\nget-master-addr-by-name\r\n";
+        conn.write_all([cmd, &bulk_string(master_name)].concat().as_bytes())?;
+
+        let (mut reply, mut buffer) = (Vec::new(), [0_u8; 256]);
+        loop {
+            let n = conn.read(&mut buffer)?;
+            reply.extend_from_slice(&buffer[..n]);
+            let reply_txt = String::from_utf8_lossy(&reply);
+            match msg::parse_master_addr(&reply_txt) {
+                Ok(master) => break Ok(master),
+                Err(RedisParseErr::Incomplete) if n > 0 => continue,
+                Err(_) => {
+                    let msg = format!("unexpected reply `{}`", reply_txt.trim_end());
+                    break Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+                }
+            }
+        }
+    }
+
+    fn connect(&self, sentinel: &str) -> io::Result<TcpStream> {
+        let addrs: Vec<SocketAddr> = sentinel.to_socket_addrs()?.collect();
+        socket::connect_tcp(&addrs, &self.bind, Some(SENTINEL_TIMEOUT))
+    }
+}
+
+impl Resolve for SentinelResolver {
+    fn resolve(&self, master_name: &str, _port: u16) -> io::Result<Vec<SocketAddr>> {
+        for sentinel in &self.sentinels {
+            match self.ask(sentinel, master_name) {
+                Ok(Some((host, port))) => return Ok((&*host, port).to_socket_addrs()?.collect()),
+                Ok(None) => tracing::warn!(
+                    "The Sentinel at {} does not monitor a master named {}",
+                    sentinel,
+                    master_name
+                ),
+                Err(e) => tracing::warn!("Could not ask the Sentinel at {}: {}", sentinel, e),
+            }
+        }
+        let msg = format!("no Sentinel could name the master {}", master_name);
+        Err(io::Error::new(io::ErrorKind::NotFound, msg))
+    }
+}
+
+/// Where the addresses of Redis come from
+#[derive(Debug, Clone)]
+pub enum Resolver {
+    System(SystemResolver),
+    Sentinel(SentinelResolver),
+}
+
+impl Resolve for Resolver {
+    fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        match self {
+            Resolver::System(resolver) => resolver.resolve(host, port),
+            Resolver::Sentinel(resolver) => resolver.resolve(host, port),
+        }
+    }
+}
+
+/// Caches the addresses of `REDIS_HOST` (or the Sentinels' master)
+pub type CachingResolver = DnsCache<Resolver>;
+
+impl CachingResolver {
+    /// Looks up `REDIS_HOST`, or asks the `REDIS_SENTINEL_HOSTS` for the master
+    pub fn from_cfg(redis_cfg: &Redis) -> Self {
+        let ttl = *redis_cfg.dns_ttl;
+        match &*redis_cfg.sentinel_master_name {
+            Some(name) => {
+                let sentinels = SentinelResolver::new(
+                    redis_cfg.sentinel_hosts.to_vec(),
+                    redis_cfg.bind_address.to_vec(),
+                );
+                Self::new(Resolver::Sentinel(sentinels), name, 0, ttl)
+            }
+            None => {
+                let (host, port) = (&redis_cfg.host, *redis_cfg.port);
+                Self::new(Resolver::System(SystemResolver), host, port, ttl)
+            }
+        }
+    }
+}
@@ -0,0 +1,334 @@
+//! What a connection to Redis runs over: TCP, or a Unix domain socket when Redis runs on the
+//! same host (with `REDIS_UNIX_SOCKET`), either of them optionally wrapped in TLS (with
+//! `REDIS_TLS`, in builds with the `tls` feature)
+use super::err::RedisConnErr;
+use crate::config::Redis;
+
+#[cfg(feature = "tls")]
+use rustls::{internal::pemfile, ClientConfig, ClientSession, StreamOwned};
+use socket2::{Domain, Protocol, Type};
+use std::fmt;
+#[cfg(feature = "tls")]
+use std::fs::File;
+#[cfg(feature = "tls")]
+use std::io::BufReader;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::os::unix::net::UnixStream;
+#[cfg(feature = "tls")]
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpStream as AsyncTcpStream, UnixStream as AsyncUnixStream};
+use tokio::reactor::Handle;
+#[cfg(feature = "tls")]
+use webpki::DNSNameRef;
+
+type Result<T> = std::result::Result<T, RedisConnErr>;
+
+/// A blocking connection
+pub(super) enum Socket {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<StreamOwned<ClientSession, Socket>>),
+}
+
+/// A connection registered with the reactor, whose reads return `WouldBlock` (and wake the
+/// current task once there is input) rather than blocking.  Over TLS, a read also returns
+/// `WouldBlock` while a record has only partly arrived.
+pub(super) enum AsyncSocket {
+    Tcp(AsyncTcpStream),
+    Unix(AsyncUnixStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<StreamOwned<ClientSession, AsyncSocket>>),
+}
+
+/// How to reach Redis, besides its address: over a Unix domain socket in place of TCP, from
+/// which local addresses, and whether over TLS
+#[derive(Debug, Clone, Default)]
+pub(super) struct Transport {
+    pub(super) unix_socket: Option<String>,
+    pub(super) bind: Vec<IpAddr>,
+    pub(super) tls: Option<Tls>,
+}
+
+/// The TLS settings for connections to Redis, and the name the server's certificate must have
+#[cfg(feature = "tls")]
+#[derive(Clone)]
+pub(super) struct Tls {
+    config: Arc<ClientConfig>,
+    host: String,
+}
+
+impl Socket {
+    pub(super) fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Socket::Tcp(conn) => conn.set_read_timeout(timeout),
+            Socket::Unix(conn) => conn.set_read_timeout(timeout),
+            #[cfg(feature = "tls")]
+            Socket::Tls(tls) => tls.sock.set_read_timeout(timeout),
+        }
+    }
+
+    /// Register the connection with the reactor `handle` is bound to.  A TLS session carries
+    /// over, so the handshake (done while the connection blocked) isn't repeated.
+    pub(super) fn into_async(self, handle: &Handle) -> io::Result<AsyncSocket> {
+        match self {
+            Socket::Tcp(conn) => AsyncTcpStream::from_std(conn, handle).map(AsyncSocket::Tcp),
+            Socket::Unix(conn) => AsyncUnixStream::from_std(conn, handle).map(AsyncSocket::Unix),
+            #[cfg(feature = "tls")]
+            Socket::Tls(tls) => {
+                let StreamOwned { sess, sock } = *tls;
+                let sock = sock.into_async(handle)?;
+                Ok(AsyncSocket::Tls(Box::new(StreamOwned::new(sess, sock))))
+            }
+        }
+    }
+}
+
+impl Transport {
+    pub(super) fn from_cfg(redis_cfg: &Redis) -> Result<Self> {
+        Ok(Self {
+            unix_socket: redis_cfg.unix_socket.clone().0,
+            bind: redis_cfg.bind_address.clone().0,
+            tls: Tls::from_cfg(redis_cfg)?,
+        })
+    }
+}
+
+/// Connect to the first of `addrs` that accepts (within `timeout`, if there is one), from the
+/// address in `bind` of the same IP family, if there is one
+pub(super) fn connect_tcp(
+    addrs: &[SocketAddr],
+    bind: &[IpAddr],
+    timeout: Option<Duration>,
+) -> io::Result<TcpStream> {
+    let mut last_err = io::Error::new(io::ErrorKind::NotFound, "no addresses");
+    for addr in addrs {
+        let local = bind.iter().find(|ip| ip.is_ipv4() == addr.is_ipv4());
+        let conn = match (local, timeout) {
+            (Some(ip), _) => connect_from(*ip, addr, timeout),
+            (None, Some(timeout)) => TcpStream::connect_timeout(addr, timeout),
+            (None, None) => TcpStream::connect(addr),
+        };
+        match conn {
+            Ok(conn) => return Ok(conn),
+            Err(e) => last_err = e,
+        }
+    }
+    Err(last_err)
+}
+
+fn connect_from(ip: IpAddr, addr: &SocketAddr, timeout: Option<Duration>) -> io::Result<TcpStream> {
+    let domain = match addr {
+        SocketAddr::V4(_) => Domain::ipv4(),
+        SocketAddr::V6(_) => Domain::ipv6(),
+    };
+    let socket = socket2::Socket::new(domain, Type::stream(), Some(Protocol::tcp()))?;
+    socket.bind(&SocketAddr::new(ip, 0).into())?;
+    match timeout {
+        Some(timeout) => socket.connect_timeout(&(*addr).into(), timeout)?,
+        None => socket.connect(&(*addr).into())?,
+    }
+    Ok(socket.into_tcp_stream())
+}
+
+#[cfg(feature = "tls")]
+impl Tls {
+    /// The settings `redis_cfg` asks for, if it asks for TLS
+    pub(super) fn from_cfg(redis_cfg: &Redis) -> Result<Option<Self>> {
+        if !*redis_cfg.tls {
+            return Ok(None);
+        }
+        let mut config = ClientConfig::new();
+        match &*redis_cfg.tls_ca_cert {
+            Some(path) => {
+                let (added, _) = config
+                    .root_store
+                    .add_pem_file(&mut pem_file(path)?)
+                    .map_err(|()| invalid(path, "certificates"))?;
+                if added == 0 {
+                    Err(invalid(path, "certificates"))?;
+                }
+            }
+            None => config
+                .root_store
+                .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS),
+        }
+        if let (Some(cert), Some(key)) = (&*redis_cfg.tls_client_cert, &*redis_cfg.tls_client_key) {
+            let chain =
+                pemfile::certs(&mut pem_file(cert)?).map_err(|()| invalid(cert, "certificates"))?;
+            let key = private_key(key)?;
+            config
+                .set_single_client_cert(chain, key)
+                .map_err(|e| RedisConnErr::Tls(e.to_string()))?;
+        }
+        Ok(Some(Self {
+            config: Arc::new(config),
+            host: redis_cfg.host.to_string(),
+        }))
+    }
+
+    /// The same settings, for the Redis server at `addr` (e.g., a master that a replica
+    /// redirected us to)
+    pub(super) fn for_addr(&self, addr: &str) -> Self {
+        let host = match addr.rfind(':') {
+            Some(i) => &addr[..i],
+            None => addr,
+        };
+        Self {
+            config: self.config.clone(),
+            host: host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_string(),
+        }
+    }
+
+    /// Start a TLS session over `conn`; the handshake happens on its first read or write.
+    pub(super) fn wrap(&self, conn: Socket) -> Result<Socket> {
+        let name = DNSNameRef::try_from_ascii_str(&self.host).map_err(|_| {
+            let msg = format!("`{}` is not a name a certificate can have", self.host);
+            RedisConnErr::Tls(msg)
+        })?;
+        let session = ClientSession::new(&self.config, name);
+        Ok(Socket::Tls(Box::new(StreamOwned::new(session, conn))))
+    }
+}
+
+/// Without the `tls` feature there are no TLS settings to have (the config refuses
+/// `REDIS_TLS`), so this can't be constructed.
+#[cfg(not(feature = "tls"))]
+#[derive(Debug, Clone)]
+pub(super) enum Tls {}
+
+#[cfg(not(feature = "tls"))]
+impl Tls {
+    pub(super) fn from_cfg(_redis_cfg: &Redis) -> Result<Option<Self>> {
+        Ok(None)
+    }
+
+    pub(super) fn for_addr(&self, _addr: &str) -> Self {
+        match *self {}
+    }
+
+    pub(super) fn wrap(&self, _conn: Socket) -> Result<Socket> {
+        match *self {}
+    }
+}
+
+#[cfg(feature = "tls")]
+fn pem_file(path: &str) -> Result<BufReader<File>> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| RedisConnErr::Tls(format!("could not read {}: {}", path, e)))
+}
+
+#[cfg(feature = "tls")]
+fn invalid(path: &str, contents: &str) -> RedisConnErr {
+    RedisConnErr::Tls(format!("{} does not hold any PEM {}", path, contents))
+}
+
+/// The PKCS #8 or RSA private key in the PEM file at `path`
+#[cfg(feature = "tls")]
+fn private_key(path: &str) -> Result<rustls::PrivateKey> {
+    let pkcs8 = pemfile::pkcs8_private_keys(&mut pem_file(path)?).unwrap_or_default();
+    let rsa = pemfile::rsa_private_keys(&mut pem_file(path)?).unwrap_or_default();
+    pkcs8
+        .into_iter()
+        .chain(rsa)
+        .next()
+        .ok_or_else(|| invalid(path, "private keys"))
+}
+
+impl Read for Socket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Socket::Tcp(conn) => conn.read(buf),
+            Socket::Unix(conn) => conn.read(buf),
+            #[cfg(feature = "tls")]
+            Socket::Tls(tls) => tls.read(buf),
+        }
+    }
+}
+
+impl Write for Socket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Socket::Tcp(conn) => conn.write(buf),
+            Socket::Unix(conn) => conn.write(buf),
+            #[cfg(feature = "tls")]
+            Socket::Tls(tls) => tls.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Socket::Tcp(conn) => conn.flush(),
+            Socket::Unix(conn) => conn.flush(),
+            #[cfg(feature = "tls")]
+            Socket::Tls(tls) => tls.flush(),
+        }
+    }
+}
+
+impl Read for AsyncSocket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            AsyncSocket::Tcp(conn) => conn.read(buf),
+            AsyncSocket::Unix(conn) => conn.read(buf),
+            #[cfg(feature = "tls")]
+            AsyncSocket::Tls(tls) => tls.read(buf),
+        }
+    }
+}
+
+impl Write for AsyncSocket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            AsyncSocket::Tcp(conn) => conn.write(buf),
+            AsyncSocket::Unix(conn) => conn.write(buf),
+            #[cfg(feature = "tls")]
+            AsyncSocket::Tls(tls) => tls.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            AsyncSocket::Tcp(conn) => conn.flush(),
+            AsyncSocket::Unix(conn) => conn.flush(),
+            #[cfg(feature = "tls")]
+            AsyncSocket::Tls(tls) => tls.flush(),
+        }
+    }
+}
+
+// A `ClientSession` isn't `Debug`
+impl fmt::Debug for Socket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Socket::Tcp(conn) => write!(f, "Tcp({:?})", conn),
+            Socket::Unix(conn) => write!(f, "Unix({:?})", conn),
+            #[cfg(feature = "tls")]
+            Socket::Tls(tls) => write!(f, "Tls({:?})", tls.sock),
+        }
+    }
+}
+
+impl fmt::Debug for AsyncSocket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AsyncSocket::Tcp(conn) => write!(f, "Tcp({:?})", conn),
+            AsyncSocket::Unix(conn) => write!(f, "Unix({:?})", conn),
+            #[cfg(feature = "tls")]
+            AsyncSocket::Tls(tls) => write!(f, "Tls({:?})", tls.sock),
+        }
+    }
+}
+
+#[cfg(feature = "tls")]
+impl fmt::Debug for Tls {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Tls({})", self.host)
+    }
+}
@@ -1,115 +1,430 @@
 //! Receives data from Redis, sorts it by `ClientAgent`, and stores it until
 //! polled by the correct `ClientAgent`.  Also manages sububscriptions and
 //! unsubscriptions to/from Redis.
+mod broadcasts;
+mod composites;
+mod decisions;
 mod err;
+mod event_modes;
+mod heartbeat;
+mod history;
+mod hot;
+mod invalid_utf8;
+mod lifecycle;
+mod limits;
+mod receipts;
+mod report;
+mod snapshot;
+mod tag_cache;
+pub(crate) use broadcasts::EventRx;
 pub use err::Error;
+pub use event_modes::PassThrough;
+pub use heartbeat::PoolHealth;
+pub use history::Retained;
+pub use hot::HotTimeline;
+pub use lifecycle::OpenConnection;
+pub use receipts::Receipt;
+pub use snapshot::Snapshot;
 
-use super::msg::{RedisParseErr, RedisParseOutput};
-use super::{Event, RedisCmd, RedisConn};
-use crate::config;
-use crate::request::{Subscription, Timeline};
+use self::broadcasts::{Broadcasts, EventChannel};
+use self::composites::CompositeClients;
+use self::decisions::Decisions;
+use self::event_modes::EventModes;
+use self::heartbeat::Heartbeat;
+use self::history::History;
+use self::hot::Fanout;
+use self::lifecycle::Departure;
+use self::limits::ChannelLimits;
+use self::receipts::Receipts;
+
+use super::msg::{self, RedisMsg, RedisParseOutput};
+use super::{Event, Hooks, IpcSink, ListQuery, RedisCmd, RedisConn};
+use crate::config::{self, ClientOverflowInner, InvalidUtf8Inner};
+use crate::jitter;
+use crate::metrics;
+use crate::request::{Subscription, TagRegistry, Timeline, GOING_AWAY, TRY_AGAIN_LATER};
+use crate::Id;
 
 pub(self) use super::EventErr;
 
+use futures::task::{self, Task};
 use futures::{Async, Poll, Stream};
 use hashbrown::{HashMap, HashSet};
-use lru::LruCache;
-use std::convert::{TryFrom, TryInto};
+use std::convert::TryFrom;
+use std::net::IpAddr;
 use std::str;
 use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
 use std::time::{Duration, Instant};
-use tokio::sync::mpsc::Sender;
 
 type Result<T> = std::result::Result<T, Error>;
-type EventChannel = Sender<Arc<Event>>;
+
+/// The most input to search for the next reply after one that can't be parsed
+const RESYNC_LIMIT: usize = 64 * 1024;
+
+/// How often to send clients a heartbeat (which is also how closed channels are noticed),
+/// give or take the `JITTER`
+pub(crate) const PING_INTERVAL: Duration = Duration::from_secs(30);
 
 /// The item that streams from Redis and is polled by the `ClientAgent`
 pub struct Manager {
     pub redis_conn: RedisConn,
-    timelines: HashMap<Timeline, HashMap<u32, EventChannel>>,
-    ping_time: Instant,
+    timelines: HashMap<Timeline, HashMap<u32, Client>>,
+    /// When each timeline last had an event or a new client, to choose which to drop when a
+    /// cap in `channel_limits` is reached
+    last_active: HashMap<Timeline, Instant>,
+    channel_limits: ChannelLimits,
+    /// When clients are next due a heartbeat
+    ping_due: Instant,
     channel_id: u32,
     pub unread_idx: (usize, usize),
-    tag_id_cache: LruCache<String, i64>,
+    /// Shared with `redis_conn` (and the request parser, see `use_tags`)
+    tags: TagRegistry,
+    confirmed: HashSet<Timeline>,
+    /// When `SUBSCRIBE` was sent for each timeline Redis hasn't yet confirmed
+    subscribes_sent: HashMap<Timeline, Instant>,
+    /// Subscription changes made since the last poll, sent together by `send_queued_cmds`
+    queued_cmds: Vec<(Timeline, RedisCmd)>,
+    ipc_sink: Option<IpcSink>,
+    dispatch_budget: usize,
+    client_buffer: usize,
+    client_overflow: ClientOverflowInner,
+    invalid_utf8: InvalidUtf8Inner,
+    event_modes: EventModes,
+    /// Whether Redis sends the messages of every timeline (`REDIS_PSUBSCRIBE`), rather than
+    /// only of those subscribed to
+    wildcard: bool,
+    /// The Redis set that the denylist is kept in, if it is kept in Redis
+    denylist_key: Option<String>,
+    /// The Redis hash that the hashtag cache is kept in, if it is kept in Redis, and how long
+    /// each entry is trusted
+    tag_cache_key: Option<String>,
+    tag_cache_ttl: Duration,
+    /// The Redis hash that this instance's heartbeat is kept in, if one is kept
+    heartbeat: Option<Heartbeat>,
+    parked: Option<Task>,
+    hooks: Hooks,
+    history: History,
+    fanout: Fanout,
+    decisions: Decisions,
+    receipts: Receipts,
+    composites: CompositeClients,
+    broadcasts: Broadcasts,
+}
+
+/// A client's channel, along with what the denylist can match the client by
+struct Client {
+    channel: EventChannel,
+    user_id: Option<Id>,
+    token: Option<String>,
+    ip: Option<IpAddr>,
+    /// The client's connection, which its receipts name
+    connection: Option<u64>,
+    /// For a client of a composite stream, its id in `composites` (which it has under each of
+    /// the stream's timelines)
+    composite: Option<u64>,
+    /// For a client of the timeline's broadcast (see `broadcasts`), what keeps it reading the
+    /// broadcast: it stops once this is dropped
+    broadcast: Option<Arc<()>>,
 }
 
 impl Stream for Manager {
     type Item = (Timeline, Arc<Event>);
     type Error = Error;
 
+    /// Parse the reply at the start of the unread input.  Each reply is measured before any of
+    /// it is checked for UTF-8 or parsed, so that a read holding many replies is parsed in one
+    /// pass (rather than the rest of the read being checked again for each one).
     fn poll(&mut self) -> Poll<Option<Self::Item>, Error> {
         let input = &self.redis_conn.input[self.unread_idx.0..self.unread_idx.1];
-        let (valid, invalid) = str::from_utf8(input)
-            .map(|v| (v, &b""[..]))
-            .unwrap_or_else(|e| {
-                // NOTE - this bounds check occurs more often than necessary; it could occur only when
-                // polling Redis.  However, benchmarking with Criterion shows it to be *very*
-                // inexpensive (<1 us) and thus not worth removing (doing so would require `unsafe`).
-                let (valid, invalid) = input.split_at(e.valid_up_to());
-                (str::from_utf8(valid).expect("split_at"), invalid)
-            });
+        if input.is_empty() {
+            self.unread_idx = (0, 0);
+            self.redis_conn.reclaim_input();
+            return Ok(Async::NotReady);
+        }
+        let len = match msg::reply_len(input) {
+            Some(len) => len,
+            None => {
+                self.copy_partial_msg();
+                return Ok(Async::NotReady);
+            }
+        };
+        let reply = match str::from_utf8(&input[..len]) {
+            Ok(reply) => reply,
+            Err(_) => return self.poll_invalid_utf8(len),
+        };
 
-        if !valid.is_empty() {
-            use RedisParseOutput::*;
-            match RedisParseOutput::try_from(valid) {
-                Ok(Msg(msg)) => {
-                    // If we get a message and it matches the redis_namespace, get the msg's
-                    // Event and send it to all channels matching the msg's Timeline
-                    if let Some(tl) = msg.timeline_matching_ns(&self.redis_conn.namespace) {
-                        self.unread_idx.0 =
-                            self.unread_idx.1 - msg.leftover_input.len() - invalid.len();
-
-                        let tl = Timeline::from_redis_text(tl, &mut self.tag_id_cache)?;
-                        let event: Arc<Event> = Arc::new(msg.event_txt.try_into()?);
+        use RedisParseOutput::*;
+        match RedisParseOutput::try_from(reply) {
+            Ok(Msg(msg)) => {
+                self.unread_idx.0 += len - msg.leftover_input.len();
+                // If we get a message and it matches the redis_namespace, get the msg's
+                // Event and send it to all channels matching the msg's Timeline
+                match self.timeline_of(&msg)? {
+                    Some(tl) => {
+                        let event = Arc::new(self.parse_event(tl, msg.event_txt)?);
                         Ok(Async::Ready(Some((tl, event))))
-                    } else {
-                        Ok(Async::Ready(None))
                     }
+                    None => Ok(Async::Ready(None)),
                 }
-                Ok(NonMsg(leftover_input)) => {
-                    self.unread_idx.0 = self.unread_idx.1 - leftover_input.len();
-                    Ok(Async::Ready(None))
-                }
-                Err(RedisParseErr::Incomplete) => {
-                    self.copy_partial_msg();
-                    Ok(Async::NotReady)
+            }
+            Ok(SubscriptionReply(reply)) => {
+                self.unread_idx.0 += len - reply.leftover_input.len();
+                if let Some(tl) = reply.timeline_matching_ns(&self.redis_conn.namespace) {
+                    match Timeline::from_redis_text(tl, &self.tags) {
+                        Ok(tl) if reply.subscribed => {
+                            if let Some(sent) = self.subscribes_sent.remove(&tl) {
+                                metrics::REDIS_SUBSCRIBE_CONFIRMATION.observe(sent.elapsed());
+                            }
+                            self.confirmed.insert(tl);
+                            self.decided("subscribe_confirmed", &[tl]);
+                        }
+                        Ok(tl) => {
+                            self.confirmed.remove(&tl);
+                            self.decided("unsubscribe_confirmed", &[tl]);
+                        }
+                        Err(e) => tracing::warn!("Unexpected subscription reply: {}", e),
+                    }
                 }
-                Err(e) => Err(Error::RedisParseErr(e, valid.to_string()))?,
+                Ok(Async::Ready(None))
+            }
+            Ok(NonMsg(leftover_input)) => {
+                self.unread_idx.0 += len - leftover_input.len();
+                Ok(Async::Ready(None))
+            }
+            // The reply is complete, so a parser that wants more of it can't make sense of it
+            Err(e) => {
+                let reply = reply.to_string();
+                self.skip_unparseable();
+                Err(Error::RedisParseErr(e, reply))?
             }
-        } else {
-            self.unread_idx = (0, 0);
-            Ok(Async::NotReady)
         }
     }
 }
 
 impl Manager {
-    // untested
+    /// Send the messages Redis has published to the clients subscribed to them.
+    ///
+    /// At most `REDIS_DISPATCH_BUDGET` messages are sent per call; any input beyond that stays
+    /// buffered for the next call so that one burst from Redis can't starve the tasks that
+    /// write to clients and send heartbeats.
+    ///
+    /// Must be called from within a task, which is woken when Redis sends input, when a full
+    /// client channel has room again, or after yielding; see `park` and `next_due` for the rest.
     pub fn send_msgs(&mut self) -> Poll<(), Error> {
-        if self.ping_time.elapsed() > Duration::from_secs(30) {
+        if Instant::now() >= self.ping_due {
             self.send_pings()?
         }
+        self.redis_conn.ping_idle_primaries();
+        if self.redis_conn.reconnect_primary() {
+            // The new connection has no subscriptions, and the input left from the old one
+            // may end partway through a message
+            self.unread_idx = (0, 0);
+            self.confirmed.clear();
+            self.subscribes_sent.clear();
+            // which subscribes to every timeline that has clients, including the queued ones
+            self.queued_cmds.retain(|(_, cmd)| !cmd.is_subscribe());
+            self.decided("reconnect", &[]);
+            self.resync()?;
+        }
+        self.send_queued_cmds()
+            .unwrap_or_else(|e| tracing::error!("Could not update the Redis subscriptions: {}", e));
+        self.redis_conn.retry_failed_sets();
+        self.save_tag_cache()
+            .unwrap_or_else(|e| tracing::error!("Could not save hashtags to Redis: {}", e));
+        if self.redis_conn.keys_refresh_due() {
+            let active = self.active_timelines();
+            self.redis_conn.refresh_subscribed_keys(&active[..])?;
+        }
 
-        while let Ok(Async::Ready(Some(msg_len))) = self.redis_conn.poll_redis(self.unread_idx.1) {
-            self.unread_idx.1 += msg_len;
-
-            while let Ok(Async::Ready(msg)) = self.poll() {
+        let mut budget = self.dispatch_budget;
+        loop {
+            // Input left over from the previous call is sent before reading more from Redis
+            loop {
+                let msg = match self.poll() {
+                    Ok(Async::Ready(msg)) => msg,
+                    Ok(Async::NotReady) => break,
+                    Err(e) => {
+                        // The input it failed on has been skipped
+                        metrics::REDIS_PARSE_ERRORS.inc();
+                        self.hooks.parse_failed(&e);
+                        continue;
+                    }
+                };
                 if let Some((tl, event)) = msg {
-                    for channel in self.timelines.entry(tl).or_default().values_mut() {
-                        if let Ok(Async::NotReady) = channel.poll_ready() {
-                            log::warn!("{:?} channel full\ncan't send:{:?}", tl, event);
-                            self.rewind_to_prev_msg();
-                            return Ok(Async::NotReady);
+                    let dispatch = tracing::info_span!(
+                        "redis_msg",
+                        timeline = ?tl,
+                        delivered = tracing::field::Empty
+                    );
+                    let _dispatch = dispatch.enter();
+                    self.last_active.insert(tl, Instant::now());
+                    let channels = self.timelines.entry(tl).or_default();
+                    // The clients of the timeline's broadcast skip what they fall behind on
+                    let full: Vec<u32> = channels
+                        .iter_mut()
+                        .filter(|(_, client)| client.broadcast.is_none())
+                        .filter_map(|(id, client)| match client.channel.poll_ready() {
+                            Ok(Async::NotReady) => Some(*id),
+                            _ => None,
+                        })
+                        .collect();
+                    let mut overflowed = 0;
+                    if !full.is_empty() {
+                        match self.client_overflow {
+                            ClientOverflowInner::Wait => {
+                                tracing::warn!("{:?} channel full\ncan't send:{:?}", tl, event);
+                                self.rewind_to_prev_msg();
+                                return Ok(Async::NotReady);
+                            }
+                            ClientOverflowInner::Drop => {
+                                metrics::CLIENT_EVENTS_DROPPED.add(full.len() as u64);
+                            }
+                            ClientOverflowInner::Disconnect => {
+                                tracing::warn!(
+                                    "Disconnecting {} slow clients of {:?}",
+                                    full.len(),
+                                    tl
+                                );
+                                metrics::SLOW_CLIENT_DISCONNECTS.add(full.len() as u64);
+                                // Dropping the sender ends the client's stream
+                                channels.retain(|id, _| !full.contains(id));
+                                overflowed = full.len();
+                            }
                         }
+                    }
 
-                        let _ = channel.try_send(event.clone()); // err just means channel will be closed
+                    let (mut delivered, mut sampled) = (0, Vec::new());
+                    let status_id = self.receipts.status_id(&event);
+                    let composite_status = self.composites.status_of(&event);
+                    let composites = &mut self.composites;
+                    for client in channels.values_mut() {
+                        if client.broadcast.is_some() {
+                            // sent through the timeline's broadcast, below
+                            if status_id.is_some() && self.receipts.sample() {
+                                sampled.push(client.connection);
+                            }
+                            continue;
+                        }
+                        if composites.already_sent(client.composite, composite_status) {
+                            continue; // on another of its composite stream's timelines
+                        }
+                        // err just means channel will be closed (or is full, and dropping)
+                        if client.channel.try_send(event.clone()).is_ok() {
+                            delivered += 1;
+                            if status_id.is_some() && self.receipts.sample() {
+                                sampled.push(client.connection);
+                            }
+                        }
+                    }
+                    delivered += self.broadcasts.send(tl, &event);
+                    dispatch.record("delivered", &delivered);
+                    if let Some(status_id) = status_id {
+                        self.receipted(tl, status_id, &sampled);
                     }
+                    self.clients_left(tl, overflowed, Departure::Overflow);
+                    metrics::REDIS_MESSAGES.inc();
+                    metrics::CLIENT_EVENTS_SENT.add(delivered as u64);
+                    self.hooks.event_delivered(tl, &event, delivered);
+                    self.history.record(tl, &event);
+                    self.fanout.record(tl, delivered);
+                    self.publish_to_sink(tl, &event);
+
+                    budget -= 1;
+                    if budget == 0 {
+                        tracing::debug!("Sent {} messages; yielding", self.dispatch_budget);
+                        metrics::DISPATCH_YIELDS.inc();
+                        task::current().notify(); // to send the rest once other tasks have run
+                        return Ok(Async::NotReady);
+                    }
+                }
+            }
+
+            match self.redis_conn.poll_redis(self.unread_idx.1) {
+                Ok(Async::Ready(Some(msg_len))) => self.unread_idx.1 += msg_len,
+                Ok(_) => break,
+                Err(e) => {
+                    // The message that didn't fit is dropped, along with the connection
+                    tracing::error!("{}", e);
+                    self.unread_idx = (0, 0);
+                    self.redis_conn.reclaim_input();
+                    break;
                 }
             }
         }
         Ok(Async::Ready(()))
     }
 
+    /// Send `event_txt` to the clients subscribed to `channel`, exactly as though it had been
+    /// published to that Redis channel.  Returns the number of clients it was sent to.
+    pub fn inject(&mut self, channel: &str, event_txt: &str) -> Result<usize> {
+        let msg = RedisMsg {
+            timeline_txt: channel,
+            event_txt,
+            leftover_input: "",
+        };
+        let tl = match msg.timeline_matching_ns(&self.redis_conn.namespace) {
+            Some(tl) => Timeline::from_redis_text(tl, &self.tags)?,
+            None => return Ok(0), // not our namespace, so Redis wouldn't have sent it to us
+        };
+        let event = Arc::new(self.parse_event(tl, msg.event_txt)?);
+
+        // Injected events are never waited for, so clients with full channels miss them
+        let (mut sent, mut sampled) = (0, Vec::new());
+        let status_id = self.receipts.status_id(&event);
+        let composite_status = self.composites.status_of(&event);
+        let composites = &mut self.composites;
+        let channels = self.timelines.get_mut(&tl).into_iter();
+        for client in channels.flat_map(HashMap::values_mut) {
+            if client.broadcast.is_some() {
+                if status_id.is_some() && self.receipts.sample() {
+                    sampled.push(client.connection);
+                }
+                continue;
+            }
+            if composites.already_sent(client.composite, composite_status) {
+                continue;
+            }
+            match client.channel.try_send(event.clone()) {
+                Ok(()) => {
+                    sent += 1;
+                    if status_id.is_some() && self.receipts.sample() {
+                        sampled.push(client.connection);
+                    }
+                }
+                Err(e) if e.is_full() => metrics::CLIENT_EVENTS_DROPPED.inc(),
+                Err(_) => (), // the channel will be closed
+            }
+        }
+        sent += self.broadcasts.send(tl, &event);
+        if let Some(status_id) = status_id {
+            self.receipted(tl, status_id, &sampled);
+        }
+        self.hooks.event_delivered(tl, &event, sent);
+        if self.timelines.contains_key(&tl) {
+            self.history.record(tl, &event);
+        }
+        self.publish_to_sink(tl, &event);
+        Ok(sent)
+    }
+
+    /// `PUBLISH` each `(channel, event_txt)` pair to Redis (in one round trip), exactly as
+    /// Mastodon would.
+    pub fn publish(&mut self, msgs: &[(String, String)]) -> Result<()> {
+        if !msgs.is_empty() {
+            self.redis_conn.publish(msgs)?;
+        }
+        Ok(())
+    }
+
+    fn publish_to_sink(&mut self, tl: Timeline, event: &Event) {
+        if let Some(sink) = &mut self.ipc_sink {
+            match self.redis_conn.channel_name(&tl) {
+                Ok(channel) => sink.publish(&channel, event),
+                Err(e) => tracing::error!("Could not publish {:?} over IPC: {}", tl, e),
+            }
+        }
+    }
+
     fn rewind_to_prev_msg(&mut self) {
         self.unread_idx.0 = loop {
             let input = &self.redis_conn.input[..self.unread_idx.0];
@@ -130,6 +445,18 @@ impl Manager {
         }
     }
 
+    /// Skip the reply at the start of the unread input, which can't be parsed, up to where the
+    /// next one starts, so that one corrupt reply doesn't stop every later message from being
+    /// read.  If no reply starts within `RESYNC_LIMIT` bytes, all the unread input is dropped.
+    fn skip_unparseable(&mut self) {
+        let input = &self.redis_conn.input[self.unread_idx.0..self.unread_idx.1];
+        let skipped = msg::next_reply(input, RESYNC_LIMIT).unwrap_or_else(|| input.len());
+        tracing::warn!("Skipped {} bytes of unparseable input from Redis", skipped);
+        metrics::REDIS_RESYNCS.inc();
+        metrics::REDIS_RESYNC_BYTES_SKIPPED.add(skipped as u64);
+        self.unread_idx.0 += skipped;
+    }
+
     fn copy_partial_msg(&mut self) {
         if self.unread_idx.0 == 0 {
             // msg already first; no copying needed
@@ -141,7 +468,7 @@ impl Manager {
             }
         } else {
             // Less efficient, but should never occur in production
-            log::warn!("Moving partial input requires heap allocation");
+            tracing::warn!("Moving partial input requires heap allocation");
             self.redis_conn.input = self.redis_conn.input[self.unread_idx.0..].into();
         }
         self.unread_idx = (0, self.unread_idx.1 - self.unread_idx.0);
@@ -149,37 +476,356 @@ impl Manager {
     }
     /// Create a new `Manager`, with its own Redis connections (but no active subscriptions).
     pub fn try_from(redis_cfg: &config::Redis) -> Result<Self> {
-        Ok(Self {
-            redis_conn: RedisConn::new(redis_cfg)?,
+        Ok(Self::with_conn(RedisConn::new(redis_cfg)?, redis_cfg))
+    }
+
+    /// Create a `Manager` that doesn't connect to Redis, so that its clients only receive the
+    /// events passed to `inject`.
+    pub fn detached(redis_cfg: &config::Redis) -> Self {
+        Self::with_conn(RedisConn::detached(redis_cfg), redis_cfg)
+    }
+
+    fn with_conn(redis_conn: RedisConn, redis_cfg: &config::Redis) -> Self {
+        let tags = redis_conn.tags.clone();
+        if *redis_cfg.hashtag_id_channels {
+            tags.use_id_channels();
+        }
+        Self {
+            redis_conn,
             timelines: HashMap::new(),
-            ping_time: Instant::now(),
+            last_active: HashMap::new(),
+            channel_limits: ChannelLimits::from_cfg(redis_cfg),
+            ping_due: Instant::now() + jitter::vary(PING_INTERVAL),
             channel_id: 0,
             unread_idx: (0, 0),
-            tag_id_cache: LruCache::new(1000),
-        })
+            tags,
+            confirmed: HashSet::new(),
+            subscribes_sent: HashMap::new(),
+            queued_cmds: Vec::new(),
+            ipc_sink: None,
+            dispatch_budget: *redis_cfg.dispatch_budget,
+            client_buffer: *redis_cfg.client_buffer,
+            client_overflow: *redis_cfg.client_overflow,
+            invalid_utf8: *redis_cfg.invalid_utf8,
+            event_modes: EventModes::from_cfg(redis_cfg),
+            wildcard: *redis_cfg.psubscribe,
+            denylist_key: redis_cfg.denylist_key.clone().0,
+            tag_cache_key: redis_cfg.tag_cache_key.clone().0,
+            tag_cache_ttl: *redis_cfg.tag_cache_ttl,
+            heartbeat: (redis_cfg.heartbeat_key.clone().0)
+                .map(|key| Heartbeat::new(key, *redis_cfg.heartbeat_ttl)),
+            parked: None,
+            hooks: Hooks::default(),
+            history: History::default(),
+            fanout: Fanout::new(*redis_cfg.hot_timelines),
+            decisions: Decisions::default(),
+            receipts: Receipts::default(),
+            composites: CompositeClients::default(),
+            broadcasts: Broadcasts::new(*redis_cfg.client_buffer),
+        }
+    }
+
+    /// Also publish every event to sidecars listening on `sink`
+    pub fn publish_to(&mut self, sink: IpcSink) {
+        self.ipc_sink = Some(sink);
+    }
+
+    /// Call `hooks` when clients connect, events are delivered, or Redis input can't be parsed
+    pub fn set_hooks(&mut self, hooks: Hooks) {
+        self.hooks = hooks;
+    }
+
+    /// Apply the settings of `redis_cfg` that can change while clients are connected (on
+    /// `SIGHUP`): the channel limits (which only refuse or evict on later subscriptions), the
+    /// `CLIENT_BUFFER` of later clients, the `CLIENT_OVERFLOW` policy, the
+    /// `REDIS_DISPATCH_BUDGET`, and the `EVENT_MODES` (which replace those set through the
+    /// admin API)
+    pub fn reconfigure(&mut self, redis_cfg: &config::Redis) {
+        self.channel_limits = ChannelLimits::from_cfg(redis_cfg);
+        self.client_buffer = *redis_cfg.client_buffer;
+        self.client_overflow = *redis_cfg.client_overflow;
+        self.dispatch_budget = *redis_cfg.dispatch_budget;
+        self.event_modes = EventModes::from_cfg(redis_cfg);
     }
 
     pub fn into_arc(self) -> Arc<Mutex<Self>> {
         Arc::new(Mutex::new(self))
     }
 
-    pub fn subscribe(&mut self, subscription: &Subscription, channel: EventChannel) {
-        let (tag, tl) = (subscription.hashtag_name.clone(), subscription.timeline);
+    /// A channel for one client's events, which holds up to `CLIENT_BUFFER` of them
+    pub fn channel(&self) -> (EventChannel, EventRx) {
+        broadcasts::channel(self.client_buffer)
+    }
+
+    /// Send the events for `subscription`'s timeline (or, for a composite stream, each of its
+    /// timelines) to `channel`, subscribing to its Redis channel if no other client has.
+    ///
+    /// If that would exceed a cap in `channel_limits` and there is no channel to drop to make
+    /// room (see `make_room`), the client is sent `Event::Disconnect(TRY_AGAIN_LATER)` instead.
+    pub fn subscribe(
+        &mut self,
+        subscription: &Subscription,
+        mut channel: EventChannel,
+    ) -> Result<()> {
+        // A status on more than one of a composite stream's timelines is sent to it once
+        let composite = subscription
+            .composite
+            .as_ref()
+            .map(|_| self.composites.register());
+        for (tl, tag) in subscription.timelines() {
+            if let Err(e) = self.subscribe_to(tl, tag, subscription, channel.clone(), composite) {
+                let _ = channel.try_send(Arc::new(Event::Disconnect(TRY_AGAIN_LATER)));
+                return Err(e);
+            }
+        }
+        self.hooks.client_connected(subscription);
+        Ok(())
+    }
+
+    /// Send the events for `tl` (whose hashtag, if it has one, is named `tag`) to `channel`, the
+    /// `composite` client's channel if it is one
+    fn subscribe_to(
+        &mut self,
+        tl: Timeline,
+        tag: Option<String>,
+        subscription: &Subscription,
+        channel: EventChannel,
+        composite: Option<u64>,
+    ) -> Result<()> {
+        let subscribed = self.timelines.get(&tl).map_or(false, |c| !c.is_empty());
+        if !subscribed {
+            self.make_room(tl)?;
+        }
         if let (Some(hashtag), Some(id)) = (tag, tl.tag()) {
-            self.tag_id_cache.put(hashtag.clone(), id);
-            self.redis_conn.tag_name_cache.put(id, hashtag);
+            self.tags.insert(hashtag, id);
         };
 
+        let broadcast = self.broadcasts.join(tl, composite, &channel);
         let channels = self.timelines.entry(tl).or_default();
-        channels.insert(self.channel_id, channel);
+        let client = Client {
+            channel,
+            user_id: subscription.user_id,
+            token: subscription.access_token.clone(),
+            ip: subscription.ip,
+            connection: subscription.connection_id,
+            composite,
+            broadcast,
+        };
+        channels.insert(self.channel_id, client);
         self.channel_id += 1;
+        self.last_active.insert(tl, Instant::now());
 
         if channels.len() == 1 {
+            self.decided("subscribe", &[tl]);
+            self.queue_cmd(tl, RedisCmd::Subscribe);
+        };
+        self.client_joined(tl);
+        if let Some(task) = self.parked.take() {
+            task.notify();
+        }
+        Ok(())
+    }
+
+    /// Make room to subscribe to `tl` under the `channel_limits`, if it would exceed one, by
+    /// dropping the least recently active channel under that cap whose clients are all
+    /// anonymous (signed-in users keep their streams).  Each time a cap is reached is counted.
+    fn make_room(&mut self, tl: Timeline) -> Result<()> {
+        let active = self.active_timelines();
+        let limit = match self.channel_limits.exceeded(&active, tl) {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        metrics::REDIS_CHANNEL_LIMIT_HITS.inc();
+        let timelines = &self.timelines;
+        let anonymous = |tl: &Timeline| {
+            let mut clients = timelines.get(tl).into_iter().flat_map(HashMap::values);
+            clients.all(|client| client.user_id.is_none())
+        };
+        let last_active = &self.last_active;
+        let idlest = active
+            .into_iter()
+            .filter(|&other| limit.covers(other) && anonymous(&other))
+            .min_by_key(|other| last_active.get(other).copied());
+        match idlest {
+            Some(idlest) => self.evict(idlest),
+            None => {
+                tracing::warn!("Refused {:?}: at the {:?} channel limit", tl, limit);
+                self.decided("refuse", &[tl]);
+                Err(Error::ChannelLimit(tl))
+            }
+        }
+    }
+
+    /// Close the connections of `tl`'s clients (with `TRY_AGAIN_LATER`) and unsubscribe from
+    /// its Redis channel
+    fn evict(&mut self, tl: Timeline) -> Result<()> {
+        let clients = self.timelines.remove(&tl).unwrap_or_default();
+        self.clients_left(tl, clients.len(), Departure::Evicted);
+        for (_, mut client) in clients {
+            let _ = client
+                .channel
+                .try_send(Arc::new(Event::Disconnect(TRY_AGAIN_LATER)));
+        }
+        self.last_active.remove(&tl);
+        metrics::REDIS_CHANNELS_EVICTED.inc();
+        tracing::warn!("Dropped {:?} to stay under the channel limits", tl);
+        self.decided("evict", &[tl]);
+        self.queue_cmd(tl, RedisCmd::Unsubscribe);
+        Ok(())
+    }
+
+    /// Queue `cmd` for `tl`, to be sent along with the other subscription changes made before
+    /// the next poll.  It replaces any command already queued for `tl`.
+    fn queue_cmd(&mut self, tl: Timeline, cmd: RedisCmd) {
+        self.queued_cmds.retain(|(queued, _)| *queued != tl);
+        self.queued_cmds.push((tl, cmd));
+    }
+
+    /// Send the queued subscription changes, with one `UNSUBSCRIBE` and one `SUBSCRIBE` for all
+    /// of them, so that many clients connecting at once (e.g., after a restart) take a single
+    /// write to Redis rather than one for each new channel.
+    fn send_queued_cmds(&mut self) -> Result<()> {
+        if self.queued_cmds.is_empty() {
+            return Ok(());
+        }
+        let (subscribes, unsubscribes): (Vec<_>, Vec<_>) = self
+            .queued_cmds
+            .drain(..)
+            .partition(|(_, cmd)| cmd.is_subscribe());
+        let unsubscribes: Vec<_> = unsubscribes.into_iter().map(|(tl, _)| tl).collect();
+        if !unsubscribes.is_empty() {
             self.redis_conn
-                .send_cmd(RedisCmd::Subscribe, &[tl])
-                .unwrap_or_else(|e| log::error!("Could not subscribe to the Redis channel: {}", e));
-            log::info!("Subscribed to {:?}", tl);
+                .send_cmd(RedisCmd::Unsubscribe, &unsubscribes[..])?;
+            tracing::info!("Unsubscribed from {:?}", unsubscribes);
+        }
+        let subscribes: Vec<_> = subscribes.into_iter().map(|(tl, _)| tl).collect();
+        if !subscribes.is_empty() {
+            self.send_subscribe(&subscribes[..])?;
+            tracing::info!("Subscribed to {:?}", subscribes);
+        }
+        Ok(())
+    }
+
+    /// Send `Event::Disconnect(code)` to every client that `matches` (by its user, token, or
+    /// address) and drop its channel, which ends its connection.  Returns the number of clients
+    /// disconnected; Redis channels left without clients are unsubscribed from at the next ping.
+    pub fn disconnect(
+        &mut self,
+        code: u16,
+        matches: impl Fn(Option<Id>, Option<&str>, Option<IpAddr>) -> bool,
+    ) -> usize {
+        let mut departures = Vec::new();
+        for (tl, channels) in self.timelines.iter_mut() {
+            let before = channels.len();
+            channels.retain(|_, client| {
+                let matched = matches(client.user_id, client.token.as_deref(), client.ip);
+                if matched {
+                    // If the channel is full, the client just sees its connection end
+                    let _ = client.channel.try_send(Arc::new(Event::Disconnect(code)));
+                }
+                !matched
+            });
+            departures.push((*tl, before - channels.len()));
+        }
+        let mut disconnected = 0;
+        for (tl, left) in departures {
+            self.clients_left(tl, left, Departure::Disconnected);
+            disconnected += left;
+        }
+        if disconnected > 0 {
+            tracing::warn!(
+                "Disconnected {} clients with close code {}",
+                disconnected,
+                code
+            );
+        }
+        disconnected
+    }
+
+    /// The members of the `DENYLIST_KEY` set, or `None` if the denylist isn't kept in Redis
+    pub fn denylist_members(&mut self) -> Result<Option<Vec<String>>> {
+        Ok(self.denylist_query().map(ListQuery::run).transpose()?)
+    }
+
+    /// A query for the members of the `DENYLIST_KEY` set (if there is one), to run without
+    /// holding the `Manager`
+    pub fn denylist_query(&mut self) -> Option<ListQuery> {
+        let key = self.denylist_key.as_ref()?;
+        Some(self.redis_conn.set_members(key))
+    }
+
+    /// Add `members` to (or remove them from) the `DENYLIST_KEY` set, if there is one
+    pub fn update_denylist(&mut self, members: &[String], add: bool) -> Result<()> {
+        match &self.denylist_key {
+            Some(key) => Ok(self.redis_conn.update_set(key, members, add)?),
+            None => Ok(()),
+        }
+    }
+
+    /// Hand off the subscribed keys before shutting down: this instance gives up its hold on
+    /// them, and the keys that other instances in the pool still hold stay set.
+    pub fn hand_off(&mut self) -> Result<()> {
+        self.send_queued_cmds()?;
+        let active = self.active_timelines();
+        tracing::info!("Handing off the keys of {:?}", active);
+        Ok(self.redis_conn.release_subscribed_keys(&active)?)
+    }
+
+    /// Shut down: send every client `Event::Disconnect(GOING_AWAY)` and drop its channel, then
+    /// unsubscribe from every Redis channel (which hands off the subscribed keys, as
+    /// `hand_off` does).  Returns the number of clients disconnected.
+    pub fn shut_down(&mut self) -> Result<usize> {
+        self.send_queued_cmds()?;
+        let active = self.active_timelines();
+        let disconnected = self.disconnect(GOING_AWAY, |_, _, _| true);
+        self.stop_beating()
+            .unwrap_or_else(|e| tracing::error!("Could not delete the heartbeat: {}", e));
+        self.timelines.clear();
+        self.last_active.clear();
+        if !active.is_empty() {
+            self.decided("unsubscribe", &active);
+            self.redis_conn.send_cmd(RedisCmd::Unsubscribe, &active)?;
+            tracing::info!("Unsubscribed from {:?}", active);
+        }
+        Ok(disconnected)
+    }
+
+    /// Whether the connections that read what Redis publishes are up, for the health endpoint
+    pub fn pubsub_health(&self) -> std::result::Result<(), String> {
+        match &self.redis_conn.primaries_down()[..] {
+            [] => Ok(()),
+            down => Err(format!(
+                "the pubsub connection to {} is down",
+                down.join(", ")
+            )),
+        }
+    }
+
+    /// Whether there is nothing to do until a client connects or Redis sends more input: no
+    /// clients, no messages left over from the last poll, and no writes waiting to be sent or
+    /// retried.
+    pub fn is_idle(&self) -> bool {
+        self.timelines.values().all(HashMap::is_empty)
+            && self.unread_idx.0 == self.unread_idx.1
+            && self.queued_cmds.is_empty()
+            && !self.redis_conn.has_failed_sets()
+            && !self.redis_conn.has_queued_cmds()
+    }
+
+    /// Wake the current task when a client connects (which may subscribe to a new channel).
+    /// Must be called from within the task that calls `send_msgs`.
+    pub fn park(&mut self) {
+        self.parked = Some(task::current());
+    }
+
+    /// When `send_msgs` next has work that nothing will wake its task for: heartbeats (while
+    /// any client is connected), and the timed work of the Redis connection.
+    pub fn next_due(&self) -> Option<Instant> {
+        let pings = match self.timelines.values().all(HashMap::is_empty) {
+            true => None,
+            false => Some(self.ping_due),
         };
+        pings.into_iter().chain(self.redis_conn.next_due()).min()
     }
 
     fn send_pings(&mut self) -> Result<()> {
@@ -188,30 +834,192 @@ impl Manager {
         // that thread fatally errors sending to the client.  On the *second* cycle, this
         // gets the error.  This isn't ideal, but is harmless.
 
-        self.ping_time = Instant::now();
+        self.ping_due = Instant::now() + jitter::vary(PING_INTERVAL);
         let mut subscriptions_to_close = HashSet::new();
+        let mut departures = Vec::new();
+        let last_active = &mut self.last_active;
         self.timelines.retain(|tl, channels| {
-            channels.retain(|_, chan| chan.try_send(Arc::new(Event::Ping)).is_ok());
+            let before = channels.len();
+            channels.retain(|_, client| client.channel.try_send(Arc::new(Event::Ping)).is_ok());
+            departures.push((*tl, before - channels.len()));
 
             if channels.is_empty() {
                 subscriptions_to_close.insert(*tl);
+                last_active.remove(tl);
                 false
             } else {
                 true
             }
         });
+        for (tl, left) in departures {
+            self.clients_left(tl, left, Departure::Closed);
+        }
+        let timelines = &self.timelines;
+        self.history.retain(|tl| timelines.contains_key(tl));
+        self.broadcasts.retain(timelines);
+        if !self.composites.is_empty() {
+            let clients = timelines.values().flat_map(HashMap::values);
+            let connected = clients.filter_map(|client| client.composite).collect();
+            self.composites.retain(&connected);
+        }
         if !subscriptions_to_close.is_empty() {
             let timelines: Vec<_> = subscriptions_to_close.into_iter().collect();
+            self.decided("unsubscribe", &timelines);
             &self
                 .redis_conn
                 .send_cmd(RedisCmd::Unsubscribe, &timelines[..])?;
-            log::info!("Unsubscribed from {:?}", timelines);
+            tracing::info!("Unsubscribed from {:?}", timelines);
         }
         Ok(())
     }
 
+    /// Re-synchronize Redis's subscriptions with the timelines that currently have clients.
+    ///
+    /// This re-issues `SUBSCRIBE` for every timeline with at least one client (which also
+    /// refreshes the keys that tell Mastodon we are subscribed) and issues `UNSUBSCRIBE` for
+    /// every channel Redis has confirmed that no client needs.
+    pub fn resync(&mut self) -> Result<()> {
+        let active = self.active_timelines();
+        let orphaned: Vec<_> = self
+            .confirmed
+            .iter()
+            .filter(|tl| !active.contains(*tl))
+            .copied()
+            .collect();
+
+        self.confirmed.clear();
+        self.decided("resync", &active);
+        if !orphaned.is_empty() {
+            self.decided("resync_unsubscribe", &orphaned);
+            self.redis_conn
+                .send_cmd(RedisCmd::Unsubscribe, &orphaned[..])?;
+            tracing::info!("Resync: unsubscribed from {:?}", orphaned);
+        }
+        if !active.is_empty() {
+            self.send_subscribe(&active[..])?;
+            tracing::info!("Resync: resubscribed to {:?}", active);
+        }
+        Ok(())
+    }
+
+    /// A query for the channels Redis has subscribers for, which `reconcile` compares with
+    /// the timelines that have clients, or `None` if there is nothing to reconcile.  It is run
+    /// without holding the `Manager`, since Redis may be slow to reply.
+    pub fn reconciliation_query(&self) -> Option<ListQuery> {
+        if self.wildcard {
+            return None; // the pattern covers every channel, so none can be missing
+        }
+        if self.confirmed.is_empty() && self.is_idle() {
+            return None; // nothing could have drifted; don't wake Redis
+        }
+        Some(self.redis_conn.pubsub_channels())
+    }
+
+    /// Compare the channels Redis reported having subscribers for (in reply to the
+    /// `reconciliation_query`) against the timelines that currently have clients, and repair
+    /// any drift between the two.  Returns the number of channels that had drifted.
+    ///
+    /// Because Redis reports subscriptions from *all* clients, a channel only counts as an
+    /// extra subscription if Redis previously confirmed that it was ours.  A timeline
+    /// subscribed to since the query was run may be subscribed to again, which is harmless.
+    pub fn reconcile(&mut self, redis_channels: Vec<String>) -> Result<usize> {
+        let redis_channels: HashSet<String> = redis_channels.into_iter().collect();
+        let active = self.active_timelines();
+
+        let conn = &self.redis_conn;
+        let in_redis = |tl: &Timeline| {
+            conn.channel_name(tl)
+                .map_or(false, |channel| redis_channels.contains(&channel))
+        };
+        self.confirmed.retain(|tl| in_redis(tl));
+        let missing: Vec<_> = active.iter().filter(|tl| !in_redis(tl)).copied().collect();
+        let extra: Vec<_> = self
+            .confirmed
+            .iter()
+            .filter(|tl| !active.contains(*tl))
+            .copied()
+            .collect();
+
+        if !extra.is_empty() {
+            tracing::warn!("Subscribed to Redis channels without clients: {:?}", extra);
+            self.decided("reconcile_extra", &extra);
+            self.redis_conn
+                .send_cmd(RedisCmd::Unsubscribe, &extra[..])?;
+        }
+        if !missing.is_empty() {
+            tracing::warn!("Missing Redis subscriptions for: {:?}", missing);
+            self.decided("reconcile_missing", &missing);
+            self.send_subscribe(&missing[..])?;
+        }
+
+        let drift = missing.len() + extra.len();
+        metrics::RECONCILIATIONS.inc();
+        metrics::SUBSCRIPTION_DRIFT.add(drift as u64);
+        Ok(drift)
+    }
+
+    /// Send `SUBSCRIBE` for `timelines`, noting when so that Redis's confirmation can be timed
+    /// (unless `wildcard`, when Redis sends no confirmation)
+    fn send_subscribe(&mut self, timelines: &[Timeline]) -> Result<()> {
+        let sent = Instant::now();
+        self.redis_conn.send_cmd(RedisCmd::Subscribe, timelines)?;
+        if !self.wildcard {
+            self.subscribes_sent
+                .extend(timelines.iter().map(|tl| (*tl, sent)));
+        }
+        Ok(())
+    }
+
+    /// The timeline a message from Redis was published on, or `None` if it is to be skipped:
+    /// when it is in another namespace, or when Redis sends every timeline's messages
+    /// (`wildcard`) and no client is subscribed to this one (or its hashtag isn't cached).
+    fn timeline_of(&self, msg: &RedisMsg) -> Result<Option<Timeline>> {
+        let tl = match msg.timeline_matching_ns(&self.redis_conn.namespace) {
+            Some(tl) => tl,
+            None => return Ok(None),
+        };
+        match Timeline::from_redis_text(tl, &self.tags) {
+            Ok(tl) if self.wildcard && self.timelines.get(&tl).map_or(true, HashMap::is_empty) => {
+                Ok(None)
+            }
+            Ok(tl) => Ok(Some(tl)),
+            Err(_) if self.wildcard => Ok(None),
+            Err(e) => Err(e)?,
+        }
+    }
+
+    /// Drop the cached hashtag names and ids that no client is subscribed to, so that they are
+    /// read again (from the connecting client's subscription or Postgres) the next time they are
+    /// needed.  Returns how many hashtag names were dropped.
+    pub fn clear_tag_cache(&mut self) -> usize {
+        let active: HashSet<i64> = self
+            .active_timelines()
+            .iter()
+            .filter_map(Timeline::tag)
+            .collect();
+        self.tags.retain(&active)
+    }
+
+    /// Look up hashtags in `tags` (which the request parser shares) rather than in a registry
+    /// of the `Manager`'s own
+    pub fn use_tags(&mut self, tags: TagRegistry) {
+        if self.tags.id_channels() {
+            tags.use_id_channels();
+        }
+        self.redis_conn.tags = tags.clone();
+        self.tags = tags;
+    }
+
+    fn active_timelines(&self) -> Vec<Timeline> {
+        self.timelines
+            .iter()
+            .filter(|(_, channels)| !channels.is_empty())
+            .map(|(tl, _)| *tl)
+            .collect()
+    }
+
     pub fn recover(poisoned: PoisonError<MutexGuard<Self>>) -> MutexGuard<Self> {
-        log::error!("{}", &poisoned);
+        tracing::error!("{}", &poisoned);
         poisoned.into_inner()
     }
 
@@ -0,0 +1,69 @@
+//! The events of public and hashtag timelines (which the readers of a viral post crowd into)
+//! are sent through a `broadcast` channel for each timeline, which is written to once however
+//! many clients read it, rather than to each of their clients' channels in turn.  Their
+//! clients keep channels of their own, for pings and disconnections.  A client that falls
+//! `CLIENT_BUFFER` events behind a broadcast skips the ones it missed, whatever the
+//! `CLIENT_OVERFLOW` policy.  The clients of composite streams, whose statuses are
+//! deduplicated (see `composites`), are sent every event themselves.
+use super::Event;
+use crate::request::Timeline;
+use crate::response::broadcast::{self, ClientRx, ClientTx, Sender};
+
+use hashbrown::HashMap;
+use std::sync::Arc;
+
+pub(super) type EventChannel = ClientTx;
+/// The client's half of a client's channel
+pub(crate) type EventRx = ClientRx;
+
+pub(super) fn channel(buffer: usize) -> (EventChannel, EventRx) {
+    broadcast::client_channel(buffer)
+}
+
+#[derive(Debug)]
+pub(super) struct Broadcasts {
+    senders: HashMap<Timeline, Sender>,
+    /// How far behind a broadcast a client may fall (`CLIENT_BUFFER`)
+    capacity: usize,
+}
+
+impl Broadcasts {
+    pub(super) fn new(capacity: usize) -> Self {
+        Self {
+            senders: HashMap::new(),
+            capacity,
+        }
+    }
+
+    /// Hand the client whose channel is `channel` (and which is of the `composite` stream, if
+    /// any) a receiver of `tl`'s broadcast, if `tl` has one.  The client reads it while the
+    /// returned `Arc` is held.
+    pub(super) fn join(
+        &mut self,
+        tl: Timeline,
+        composite: Option<u64>,
+        channel: &EventChannel,
+    ) -> Option<Arc<()>> {
+        if composite.is_some() || !(tl.is_public() || tl.tag().is_some()) {
+            return None;
+        }
+        let capacity = self.capacity;
+        let sender = self
+            .senders
+            .entry(tl)
+            .or_insert_with(|| broadcast::channel(capacity));
+        Some(channel.join(sender))
+    }
+
+    /// Send `event` to the clients of `tl`'s broadcast, returning how many there are
+    pub(super) fn send(&self, tl: Timeline, event: &Arc<Event>) -> usize {
+        self.senders
+            .get(&tl)
+            .map_or(0, |sender| sender.send(event.clone()))
+    }
+
+    /// Drop the broadcasts of the timelines without clients, which ends them
+    pub(super) fn retain<T>(&mut self, timelines: &HashMap<Timeline, T>) {
+        self.senders.retain(|tl, _| timelines.contains_key(tl));
+    }
+}
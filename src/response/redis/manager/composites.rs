@@ -0,0 +1,64 @@
+//! The statuses recently sent to each client of a composite stream (see
+//! `request::composite`), so that a status published on more than one of the timelines it is
+//! composed of (e.g., a status on the local timeline with the conference's hashtag) is sent to
+//! the client once.  Mastodon publishes a status to each of its timelines at once, so only the
+//! latest `RECENT` statuses need to be remembered.
+use super::Event;
+use crate::Id;
+
+use hashbrown::{HashMap, HashSet};
+use std::collections::VecDeque;
+
+/// How many of the latest statuses sent to a client of a composite stream to remember
+const RECENT: usize = 256;
+
+#[derive(Debug, Default)]
+pub(super) struct CompositeClients {
+    /// The latest statuses sent to each client, oldest first, by the id in its `Client`
+    sent: HashMap<u64, VecDeque<Id>>,
+    next_id: u64,
+}
+
+impl CompositeClients {
+    /// An id for a new client of a composite stream, which each of its `Client`s is given
+    pub(super) fn register(&mut self) -> u64 {
+        self.next_id += 1;
+        self.sent.insert(self.next_id, VecDeque::new());
+        self.next_id
+    }
+
+    /// The status that `event` carries, if any client of a composite stream could be sent it
+    pub(super) fn status_of(&self, event: &Event) -> Option<Id> {
+        match self.sent.is_empty() {
+            true => None,
+            false => event.status_id(),
+        }
+    }
+
+    /// Whether the client of a composite stream that a `Client` names (if it names one) has been
+    /// sent the `status` being sent (which, if not, it now has)
+    pub(super) fn already_sent(&mut self, client: Option<u64>, status: Option<Id>) -> bool {
+        let (client, id) = match (client, status) {
+            (Some(client), Some(id)) => (client, id),
+            _ => return false,
+        };
+        let sent = self.sent.entry(client).or_default();
+        if sent.contains(&id) {
+            return true;
+        }
+        if sent.len() >= RECENT {
+            sent.pop_front();
+        }
+        sent.push_back(id);
+        false
+    }
+
+    /// Forget the clients that aren't `connected`
+    pub(super) fn retain(&mut self, connected: &HashSet<u64>) {
+        self.sent.retain(|client, _| connected.contains(client));
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.sent.is_empty()
+    }
+}
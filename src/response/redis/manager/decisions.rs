@@ -0,0 +1,72 @@
+//! A record of the choices the `Manager` makes about its Redis subscriptions, for finding where
+//! two runs of a load test (with the same `SEED`) went different ways
+//!
+//! With `DECISION_LOG`, each decision is appended to the file as a line of JSON: its sequence
+//! number, the milliseconds since the log was opened, what was decided, and the channels it was
+//! about (e.g., `{"seq":3,"ms":120,"decision":"evict","channels":["timeline:public"]}`).  The
+//! times depend on the machine, but in a run that repeats another the sequence of decisions
+//! should not, so the first line that differs is where to look for a race.
+//!
+//! The decisions are to `subscribe` to a timeline's first client, `refuse` a timeline or
+//! `evict` one at a channel limit, `unsubscribe` from timelines whose clients have left, and
+//! `resync` or `reconcile_missing`/`reconcile_extra` subscriptions after a `reconnect` or
+//! drift; Redis's replies are logged as `subscribe_confirmed` and `unsubscribe_confirmed`.
+use super::Manager;
+use crate::request::Timeline;
+
+use serde_json::json;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::time::Instant;
+
+#[derive(Debug, Default)]
+pub(super) struct Decisions {
+    log: Option<BufWriter<File>>,
+    seq: u64,
+    opened: Option<Instant>,
+}
+
+impl Manager {
+    /// Append a line to the file at `path` for each decision about the subscriptions
+    pub fn log_decisions_to(&mut self, path: &str) -> io::Result<()> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        self.decisions = Decisions {
+            log: Some(BufWriter::new(file)),
+            seq: 0,
+            opened: Some(Instant::now()),
+        };
+        Ok(())
+    }
+
+    /// Record that the `Manager` decided to do `decision` about `timelines`, if it is keeping
+    /// a log.  A log that can't be written to is closed.
+    pub(super) fn decided(&mut self, decision: &str, timelines: &[Timeline]) {
+        let (decisions, conn) = (&mut self.decisions, &self.redis_conn);
+        let log = match &mut decisions.log {
+            Some(log) => log,
+            None => return,
+        };
+        let mut channels: Vec<_> = timelines
+            .iter()
+            .map(|tl| {
+                conn.channel_name(tl)
+                    .unwrap_or_else(|_| format!("{:?}", tl))
+            })
+            .collect();
+        channels.sort(); // so that the order of a `HashSet` doesn't make two runs differ
+        decisions.seq += 1;
+        let ms = decisions
+            .opened
+            .map_or(0, |opened| opened.elapsed().as_millis());
+        let line = json!({
+            "seq": decisions.seq,
+            "ms": ms as u64,
+            "decision": decision,
+            "channels": channels,
+        });
+        if let Err(e) = writeln!(log, "{}", line).and_then(|()| log.flush()) {
+            tracing::error!("Could not write to the DECISION_LOG; closing it: {}", e);
+            decisions.log = None;
+        }
+    }
+}
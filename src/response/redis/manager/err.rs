@@ -1,6 +1,6 @@
 use super::super::{RedisConnErr, RedisParseErr};
 use super::{Event, EventErr};
-use crate::request::TimelineErr;
+use crate::request::{Timeline, TimelineErr};
 
 use std::fmt;
 use std::sync::Arc;
@@ -13,6 +13,9 @@ pub enum Error {
     RedisParseErr(RedisParseErr, String),
     RedisConnErr(RedisConnErr),
     ChannelSendErr(tokio::sync::mpsc::error::TrySendError<Arc<Event>>),
+    /// Subscribing to the timeline would exceed a limit on Redis channels, and no channel
+    /// could be dropped to make room
+    ChannelLimit(Timeline),
 }
 
 impl std::error::Error for Error {}
@@ -30,6 +33,11 @@ impl fmt::Display for Error {
             RedisConnErr(inner) => write!(f, "{}", inner),
             TimelineErr(inner) => write!(f, "{}", inner),
             ChannelSendErr(inner) => write!(f, "{}", inner),
+            ChannelLimit(tl) => write!(
+                f,
+                "subscribing to {:?} would exceed a limit on Redis channels",
+                tl
+            ),
         }?;
         Ok(())
     }
@@ -0,0 +1,82 @@
+//! How the events of each category of timeline are parsed (with `EVENT_MODES`): `strict`,
+//! which type checks them so that the filters see every field they can use, or
+//! `pass_through`, which reads only what routing needs.  The CPU that pass-through saves
+//! matters most on the public timelines, while typed events matter most on the user timelines
+//! that are filtered by mentions.
+//!
+//! A mode is applied to each message as a whole when it is read from Redis, so changing one
+//! (on `SIGHUP`, or with `POST /api/v1/streaming/admin/event_modes/{strict,pass_through}`) only
+//! affects the messages read after it; clients already connected get both kinds of event
+//! alike.
+use super::{Error, Manager};
+use crate::config::{self, EventModeInner, EVENT_CATEGORIES};
+use crate::request::Timeline;
+use crate::response::Event;
+
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// The categories whose events are passed through; every other category is strict
+#[derive(Debug, Clone, Default)]
+pub(super) struct EventModes(BTreeSet<&'static str>);
+
+/// The admin API's reply: every category whose events are currently passed through
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PassThrough {
+    pub pass_through: Vec<String>,
+}
+
+impl EventModes {
+    pub(super) fn from_cfg(redis_cfg: &config::Redis) -> Self {
+        let mut modes = Self::default();
+        for (category, mode) in redis_cfg.event_modes.iter() {
+            modes.set(category, *mode);
+        }
+        modes
+    }
+
+    fn set(&mut self, category: &str, mode: EventModeInner) {
+        if let Some(category) = EVENT_CATEGORIES.iter().find(|c| **c == category) {
+            match mode {
+                EventModeInner::Strict => self.0.remove(category),
+                EventModeInner::PassThrough => self.0.insert(category),
+            };
+        }
+    }
+
+    fn mode(&self, tl: Timeline) -> EventModeInner {
+        match tl.category() {
+            Some(category) if self.0.contains(category) => EventModeInner::PassThrough,
+            _ => EventModeInner::Strict,
+        }
+    }
+}
+
+impl Manager {
+    /// Parse `event_txt`, which was published on `tl`, in the mode set for `tl`'s category
+    pub(super) fn parse_event(&self, tl: Timeline, event_txt: &str) -> Result<Event> {
+        Ok(match self.event_modes.mode(tl) {
+            EventModeInner::Strict => Event::try_from(event_txt)?,
+            EventModeInner::PassThrough => Event::dynamic(event_txt)?,
+        })
+    }
+
+    /// Parse the events of each of `categories` (which are `EVENT_CATEGORIES`) in `mode` from
+    /// the next message on
+    pub fn set_event_mode(&mut self, categories: &[String], mode: EventModeInner) -> PassThrough {
+        for category in categories {
+            self.event_modes.set(category, mode);
+        }
+        tracing::warn!("Passing through the events of {:?}", self.event_modes.0);
+        self.pass_through()
+    }
+
+    pub fn pass_through(&self) -> PassThrough {
+        PassThrough {
+            pass_through: self.event_modes.0.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+}
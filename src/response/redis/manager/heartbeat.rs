@@ -0,0 +1,147 @@
+//! The heartbeat kept in Redis (with `HEARTBEAT_KEY`), so that Mastodon or a monitor can check
+//! that the streaming servers are alive and sending what Redis publishes.
+//!
+//! Each instance sets its own field of the hash (named by `INSTANCE_ID`, or else by its
+//! process) three times per `HEARTBEAT_TTL` to a JSON object: when it beat (`ts`, in
+//! milliseconds since the Unix epoch), its `clients` and `channels`, and how long ago it last
+//! sent an event (`idle_ms`, `null` if it hasn't).  The hash expires a TTL after the last beat
+//! of any instance, so it exists while any instance is alive; an instance whose `ts` is older
+//! than the TTL has stopped.  An instance that shuts down deletes its field.
+use super::{Error, Manager};
+
+use hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+use std::process;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Clone)]
+pub(super) struct Heartbeat {
+    key: String,
+    ttl: Duration,
+    instance: String,
+}
+
+impl Heartbeat {
+    pub(super) fn new(key: String, ttl: Duration) -> Self {
+        Self {
+            key,
+            ttl,
+            instance: format!("pid-{}", process::id()),
+        }
+    }
+}
+
+/// What an instance's heartbeat says
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Beat {
+    pub ts: u64,
+    pub clients: usize,
+    pub channels: usize,
+    pub idle_ms: Option<u64>,
+}
+
+/// Every instance's heartbeat, for `GET /api/v1/streaming/admin/info`
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PoolHealth {
+    /// The field this instance beats in
+    pub instance: String,
+    /// How many instances beat within the TTL
+    pub alive: usize,
+    pub instances: Vec<InstanceHealth>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct InstanceHealth {
+    pub instance: String,
+    /// Whether it beat within the TTL
+    pub alive: bool,
+    /// `None` if its field can't be parsed
+    pub beat: Option<Beat>,
+}
+
+impl Manager {
+    /// Name this instance's field of the `HEARTBEAT_KEY` hash (by default, after its process)
+    pub fn set_instance_id(&mut self, instance: &str) {
+        if let Some(heartbeat) = &mut self.heartbeat {
+            heartbeat.instance = instance.to_string();
+        }
+    }
+
+    /// How often to `beat`, if a heartbeat is kept
+    pub fn heartbeat_interval(&self) -> Option<Duration> {
+        self.heartbeat.as_ref().map(|heartbeat| heartbeat.ttl / 3)
+    }
+
+    /// Set this instance's field of the `HEARTBEAT_KEY` hash (if there is one)
+    pub fn beat(&mut self) -> Result<()> {
+        let heartbeat = match &self.heartbeat {
+            Some(heartbeat) => heartbeat,
+            None => return Ok(()),
+        };
+        let beat = Beat {
+            ts: unix_millis(),
+            clients: self.timelines.values().map(HashMap::len).sum(),
+            channels: self.timelines.values().filter(|ch| !ch.is_empty()).count(),
+            idle_ms: self
+                .last_active
+                .values()
+                .max()
+                .map(|last| Instant::now().duration_since(*last).as_millis() as u64),
+        };
+        let beat = serde_json::to_string(&beat).expect("Guaranteed: Beat is Serialize");
+        let fields = [(heartbeat.instance.clone(), beat)];
+        Ok(self
+            .redis_conn
+            .update_hash(&heartbeat.key, &fields, &[], heartbeat.ttl)?)
+    }
+
+    /// Delete this instance's field of the `HEARTBEAT_KEY` hash (e.g., when shutting down)
+    pub(super) fn stop_beating(&mut self) -> Result<()> {
+        match &self.heartbeat {
+            Some(heartbeat) => {
+                let instance = [heartbeat.instance.clone()];
+                Ok(self
+                    .redis_conn
+                    .update_hash(&heartbeat.key, &[], &instance, heartbeat.ttl)?)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Every instance's heartbeat in the `HEARTBEAT_KEY` hash, if a heartbeat is kept
+    pub fn pool_health(&mut self) -> Result<Option<PoolHealth>> {
+        let heartbeat = match &self.heartbeat {
+            Some(heartbeat) => heartbeat.clone(),
+            None => return Ok(None),
+        };
+        let fresh_since = unix_millis().saturating_sub(heartbeat.ttl.as_millis() as u64);
+        let mut instances: Vec<InstanceHealth> = self
+            .redis_conn
+            .hash_fields(&heartbeat.key)?
+            .into_iter()
+            .map(|(instance, beat)| {
+                let beat: Option<Beat> = serde_json::from_str(&beat).ok();
+                InstanceHealth {
+                    instance,
+                    alive: beat.as_ref().map_or(false, |beat| beat.ts >= fresh_since),
+                    beat,
+                }
+            })
+            .collect();
+        instances.sort_by(|a, b| a.instance.cmp(&b.instance));
+        Ok(Some(PoolHealth {
+            instance: heartbeat.instance,
+            alive: instances.iter().filter(|i| i.alive).count(),
+            instances,
+        }))
+    }
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
@@ -0,0 +1,86 @@
+//! The latest events sent on each timeline (with `EVENT_HISTORY`), so that operators can check
+//! what Flodgatt actually delivered (e.g., during an incident) through
+//! `GET /api/v1/streaming/history`.  Only timelines with clients keep their events; a timeline's
+//! history is dropped along with its last client.
+use super::Manager;
+use crate::request::Timeline;
+use crate::response::{Event, EventFormat};
+
+use hashbrown::HashMap;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Default)]
+pub(super) struct History {
+    /// How many events to keep for each timeline (none, unless `EVENT_HISTORY` is set)
+    per_timeline: usize,
+    /// Oldest first, with when each was sent
+    events: HashMap<Timeline, VecDeque<(SystemTime, Arc<Event>)>>,
+}
+
+/// An event that was sent on a timeline, as WebSocket clients received it
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Retained {
+    /// When it was sent, in milliseconds since the Unix epoch
+    pub sent_at: u64,
+    pub message: Value,
+}
+
+impl History {
+    pub(super) fn record(&mut self, tl: Timeline, event: &Arc<Event>) {
+        if self.per_timeline == 0 {
+            return;
+        }
+        let events = self.events.entry(tl).or_default();
+        if events.len() == self.per_timeline {
+            events.pop_front();
+        }
+        events.push_back((SystemTime::now(), event.clone()));
+    }
+
+    /// Drop the history of every timeline that isn't `kept`
+    pub(super) fn retain(&mut self, kept: impl Fn(&Timeline) -> bool) {
+        self.events.retain(|tl, _| kept(tl));
+    }
+}
+
+impl Manager {
+    /// Keep the latest `len` events sent on each timeline (see `history`)
+    pub fn retain_history(&mut self, len: usize) {
+        self.history = History {
+            per_timeline: len,
+            events: HashMap::new(),
+        };
+    }
+
+    /// The latest `limit` events (or all of those kept) sent on `tl`, oldest first, or `None`
+    /// if no history is kept
+    pub fn history(
+        &self,
+        tl: Timeline,
+        limit: Option<usize>,
+        format: &EventFormat,
+    ) -> Option<Vec<Retained>> {
+        if self.history.per_timeline == 0 {
+            return None;
+        }
+        let events = match self.history.events.get(&tl) {
+            Some(events) => events,
+            None => return Some(Vec::new()),
+        };
+        let skipped = events.len().saturating_sub(limit.unwrap_or(events.len()));
+        let retained = events
+            .iter()
+            .skip(skipped)
+            .map(|(sent_at, event)| Retained {
+                sent_at: sent_at
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |since| since.as_millis() as u64),
+                message: serde_json::from_str(&event.to_json_string(format)).unwrap_or_default(),
+            });
+        Some(retained.collect())
+    }
+}
@@ -0,0 +1,121 @@
+//! How many clients each timeline's events have fanned out to, so that operators can see which
+//! streams (e.g., a trending hashtag) drive the load, through `GET /api/v1/streaming/admin/hot`
+//! and the admin metrics.  Deliveries are counted over a minute at a time, and the
+//! `HOT_TIMELINES` busiest timelines of the latest complete minute are ranked.
+use super::Manager;
+use crate::metrics;
+use crate::request::Timeline;
+
+use hashbrown::HashMap;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+pub(super) struct Fanout {
+    /// How many timelines to rank (none, with `HOT_TIMELINES=0`)
+    top: usize,
+    /// When the current window started
+    pub(super) started: Instant,
+    /// The events sent on each timeline in the current window, and how many clients they
+    /// were delivered to
+    counts: HashMap<Timeline, (u64, u64)>,
+    /// The busiest timelines of the latest complete window, busiest first
+    ranking: Vec<(Timeline, u64, u64)>,
+}
+
+/// One of the timelines whose events reached the most clients in the latest minute
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct HotTimeline {
+    pub channel: String,
+    pub events: u64,
+    pub deliveries: u64,
+}
+
+impl Fanout {
+    pub(super) fn new(top: usize) -> Self {
+        Self {
+            top,
+            started: Instant::now(),
+            counts: HashMap::new(),
+            ranking: Vec::new(),
+        }
+    }
+
+    pub(super) fn record(&mut self, tl: Timeline, delivered: usize) {
+        if self.top == 0 {
+            return;
+        }
+        self.roll();
+        let (events, deliveries) = self.counts.entry(tl).or_default();
+        *events += 1;
+        *deliveries += delivered as u64;
+    }
+
+    /// Rank the timelines of the current window, if it has ended.  Only events recorded before
+    /// it ended are counted, so if the window after it has ended as well, that later (and
+    /// latest) window had no events at all.
+    fn roll(&mut self) {
+        let elapsed = self.started.elapsed();
+        if elapsed < WINDOW {
+            return;
+        }
+        let mut ranking: Vec<_> = self
+            .counts
+            .drain()
+            .map(|(tl, (events, deliveries))| (tl, events, deliveries))
+            .filter(|_| elapsed < WINDOW * 2)
+            .collect();
+        ranking.sort_by(|a, b| b.2.cmp(&a.2).then(b.1.cmp(&a.1)));
+        ranking.truncate(self.top);
+        self.ranking = ranking;
+        self.started = Instant::now();
+    }
+}
+
+impl Manager {
+    /// The timelines whose events reached the most clients in the latest complete minute,
+    /// busiest first
+    pub fn hot_timelines(&mut self) -> Vec<HotTimeline> {
+        self.fanout.roll();
+        let conn = &self.redis_conn;
+        self.fanout
+            .ranking
+            .iter()
+            .map(|(tl, events, deliveries)| HotTimeline {
+                channel: conn
+                    .channel_name(tl)
+                    .unwrap_or_else(|_| format!("{:?}", tl)),
+                events: *events,
+                deliveries: *deliveries,
+            })
+            .collect()
+    }
+
+    /// The `hot_timelines`, as metrics labeled with their channels
+    pub fn hot_timelines_report(&mut self) -> String {
+        let hot = self.hot_timelines();
+        let families: [(&str, &str, fn(&HotTimeline) -> u64); 2] = [
+            (
+                "flodgatt_hot_timeline_events",
+                "Events published on a hot timeline in the latest minute",
+                |hot| hot.events,
+            ),
+            (
+                "flodgatt_hot_timeline_deliveries",
+                "Clients a hot timeline's events reached in the latest minute",
+                |hot| hot.deliveries,
+            ),
+        ];
+        let mut report = String::new();
+        for (name, help, value) in &families {
+            report += &metrics::header(name, help, "gauge");
+            for hot in &hot {
+                let channel = hot.channel.replace('\\', "\\\\").replace('"', "\\\"");
+                report += &format!("{}{{channel=\"{}\"}} {}\n", name, channel, value(hot));
+            }
+        }
+        report
+    }
+}
@@ -0,0 +1,64 @@
+//! Messages from Redis whose bytes aren't valid UTF-8, which are dropped, repaired, or passed
+//! through as `INVALID_UTF8` says.  Either way, the policy is applied to the whole reply
+//! before it is parsed, so that no part of Flodgatt sees a message that was silently altered.
+use super::{Error, Manager};
+use crate::config::InvalidUtf8Inner;
+use crate::metrics;
+use crate::request::Timeline;
+use crate::response::redis::msg::{self, RedisMsg};
+use crate::response::Event;
+
+use futures::{Async, Poll};
+use std::sync::Arc;
+
+impl Manager {
+    /// Apply the `INVALID_UTF8` policy to the reply at the start of the unread input, which is
+    /// `len` bytes long and isn't valid UTF-8.
+    pub(super) fn poll_invalid_utf8(
+        &mut self,
+        len: usize,
+    ) -> Poll<Option<(Timeline, Arc<Event>)>, Error> {
+        let reply = &self.redis_conn.input[self.unread_idx.0..self.unread_idx.0 + len];
+        self.unread_idx.0 += len;
+        metrics::INVALID_UTF8_MESSAGES.inc();
+
+        let fields = msg::bulk_strings(reply);
+        let (channel, event_bytes) = match fields.as_deref() {
+            Some([kind, channel, event]) if [&b"message"[..], b"smessage"].contains(kind) => {
+                (String::from_utf8_lossy(channel), *event)
+            }
+            Some([kind, _pattern, channel, event]) if kind[..] == b"pmessage"[..] => {
+                (String::from_utf8_lossy(channel), *event)
+            }
+            _ => {
+                let reply = String::from_utf8_lossy(reply);
+                tracing::warn!(
+                    "Dropped a reply from Redis that isn't valid UTF-8: {:?}",
+                    reply
+                );
+                return Ok(Async::Ready(None));
+            }
+        };
+        if self.invalid_utf8 == InvalidUtf8Inner::Reject {
+            tracing::warn!("Dropped a message on {} that isn't valid UTF-8", channel);
+            return Ok(Async::Ready(None));
+        }
+
+        let event_txt = String::from_utf8_lossy(event_bytes);
+        let msg = RedisMsg {
+            timeline_txt: &channel,
+            event_txt: &event_txt,
+            leftover_input: "",
+        };
+        let tl = match self.timeline_of(&msg)? {
+            Some(tl) => tl,
+            None => return Ok(Async::Ready(None)),
+        };
+        let event = self.parse_event(tl, msg.event_txt)?;
+        let event = match self.invalid_utf8 {
+            InvalidUtf8Inner::Raw => Event::Raw(Box::new(event), event_bytes.into()),
+            _ => event,
+        };
+        Ok(Async::Ready(Some((tl, Arc::new(event)))))
+    }
+}
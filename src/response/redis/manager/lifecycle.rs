@@ -0,0 +1,179 @@
+//! A changefeed of the connections clients open and close and the timelines they subscribe
+//! to, so that autoscalers and dashboards can react to streaming load rather than poll the
+//! metrics
+//!
+//! With `IPC_LIFECYCLE`, each change is published to the `IPC_SOCKET`'s sidecars as a frame on
+//! the `LIFECYCLE_CHANNEL`, whose event is a JSON object with the `ts` of the change (in
+//! milliseconds since the Unix epoch) and an `event` of:
+//! - `open`: a client connected, with the `connection`'s id and `protocol` (`sse` or `ws`)
+//! - `close`: the `connection` ended
+//! - `subscribe`: a `timeline` gained a client, and now has `clients` of them
+//! - `unsubscribe`: `left` clients of a `timeline` went, for a `reason` (`closed`, `overflow`,
+//!   `disconnected`, or `evicted`), and it now has `clients` of them
+//!
+//! A `connection`'s id is the `client_id` of its log lines.  Fields may be added to these
+//! events, but those listed won't change their meaning.
+use super::Manager;
+use crate::request::Timeline;
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How many clients have connected, which numbers their connections
+static CONNECTIONS: AtomicU64 = AtomicU64::new(0);
+/// How many connections are open, which shutting down waits for
+static OPEN: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub(super) enum Lifecycle {
+    Open {
+        connection: u64,
+        protocol: &'static str,
+    },
+    Close {
+        connection: u64,
+        protocol: &'static str,
+    },
+    Subscribe {
+        timeline: String,
+        clients: usize,
+    },
+    Unsubscribe {
+        timeline: String,
+        left: usize,
+        reason: Departure,
+        clients: usize,
+    },
+}
+
+/// Why clients stopped receiving a timeline's events
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum Departure {
+    /// The client unsubscribed, or its connection ended
+    Closed,
+    /// The client fell behind, with `CLIENT_OVERFLOW=disconnect`
+    Overflow,
+    /// An admin disconnected the client, or the instance shut down
+    Disconnected,
+    /// The timeline was dropped to stay under a channel limit
+    Evicted,
+}
+
+#[derive(Serialize)]
+struct Change {
+    ts: u64,
+    #[serde(flatten)]
+    event: Lifecycle,
+}
+
+/// A client's connection, which is announced on the changefeed when it's opened and again when
+/// this is dropped
+#[derive(Debug)]
+pub struct OpenConnection {
+    id: u64,
+    protocol: &'static str,
+    /// The `Manager` to announce the close through, if the changefeed was on at the open
+    manager: Option<Arc<Mutex<Manager>>>,
+}
+
+impl OpenConnection {
+    pub fn new(manager: &Arc<Mutex<Manager>>, protocol: &'static str) -> Self {
+        let id = CONNECTIONS.fetch_add(1, Ordering::Relaxed);
+        OPEN.fetch_add(1, Ordering::Relaxed);
+        let mut locked = manager.lock().unwrap_or_else(Manager::recover);
+        let manager = match locked.feeds_lifecycle() {
+            true => {
+                locked.lifecycle(Lifecycle::Open {
+                    connection: id,
+                    protocol,
+                });
+                Some(manager.clone())
+            }
+            false => None,
+        };
+        Self {
+            id,
+            protocol,
+            manager,
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// How many connections are open
+    pub fn count() -> usize {
+        OPEN.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        OPEN.fetch_sub(1, Ordering::Relaxed);
+        if let Some(manager) = &self.manager {
+            let mut manager = manager.lock().unwrap_or_else(Manager::recover);
+            manager.lifecycle(Lifecycle::Close {
+                connection: self.id,
+                protocol: self.protocol,
+            });
+        }
+    }
+}
+
+impl Manager {
+    /// Whether changes to clients' connections are published (see `IpcSink::with_lifecycle`)
+    pub(super) fn feeds_lifecycle(&self) -> bool {
+        self.ipc_sink
+            .as_ref()
+            .map_or(false, |sink| sink.publishes_lifecycle())
+    }
+
+    /// Publish `event` on the changefeed, if it's on
+    pub(super) fn lifecycle(&mut self, event: Lifecycle) {
+        let sink = match &mut self.ipc_sink {
+            Some(sink) => sink,
+            None => return,
+        };
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as u64);
+        match serde_json::to_string(&Change { ts, event }) {
+            Ok(change) => sink.publish_lifecycle(&change),
+            Err(e) => tracing::error!("Could not serialize a lifecycle event: {}", e),
+        }
+    }
+
+    /// Publish that `tl` gained a client
+    pub(super) fn client_joined(&mut self, tl: Timeline) {
+        if self.feeds_lifecycle() {
+            let clients = self.timelines.get(&tl).map_or(0, |c| c.len());
+            let timeline = self.timeline_name(tl);
+            self.lifecycle(Lifecycle::Subscribe { timeline, clients });
+        }
+    }
+
+    /// Publish that `left` clients of `tl` went, for `reason`
+    pub(super) fn clients_left(&mut self, tl: Timeline, left: usize, reason: Departure) {
+        if left > 0 && self.feeds_lifecycle() {
+            let clients = self.timelines.get(&tl).map_or(0, |c| c.len());
+            let timeline = self.timeline_name(tl);
+            self.lifecycle(Lifecycle::Unsubscribe {
+                timeline,
+                left,
+                reason,
+                clients,
+            });
+        }
+    }
+
+    fn timeline_name(&self, tl: Timeline) -> String {
+        self.redis_conn
+            .channel_name(&tl)
+            .unwrap_or_else(|_| format!("{:?}", tl))
+    }
+}
@@ -0,0 +1,86 @@
+//! Caps on how many Redis channels the `Manager` subscribes to at once (`REDIS_MAX_CHANNELS`,
+//! and for each kind of timeline that can grow without bound: `REDIS_MAX_HASHTAG_CHANNELS`,
+//! `REDIS_MAX_LIST_CHANNELS`, and `REDIS_MAX_USER_CHANNELS`).  Every hashtag that anyone
+//! follows is another channel, so without them an instance with heavy hashtag use can make
+//! Redis hold (and fan out to) any number of subscriptions.
+use crate::config;
+use crate::request::Timeline;
+
+/// The kinds of timeline that have their own cap
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Category {
+    Hashtag,
+    List,
+    /// A user's home timeline, notifications, or direct messages
+    User,
+}
+
+/// The cap that subscribing to one more channel would exceed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Limit {
+    Total,
+    Of(Category),
+}
+
+#[derive(Debug, Clone, Default)]
+pub(super) struct ChannelLimits {
+    total: Option<usize>,
+    hashtags: Option<usize>,
+    lists: Option<usize>,
+    users: Option<usize>,
+}
+
+impl ChannelLimits {
+    pub(super) fn from_cfg(redis_cfg: &config::Redis) -> Self {
+        Self {
+            total: *redis_cfg.max_channels,
+            hashtags: *redis_cfg.max_hashtag_channels,
+            lists: *redis_cfg.max_list_channels,
+            users: *redis_cfg.max_user_channels,
+        }
+    }
+
+    /// The cap that subscribing to `new` would exceed, given the channels already `subscribed`
+    /// to (a category's cap before the total, since making room under it makes room under both)
+    pub(super) fn exceeded(&self, subscribed: &[Timeline], new: Timeline) -> Option<Limit> {
+        if let Some(category) = Category::of(new) {
+            let max = match category {
+                Category::Hashtag => self.hashtags,
+                Category::List => self.lists,
+                Category::User => self.users,
+            };
+            let count = subscribed
+                .iter()
+                .filter(|&&tl| Category::of(tl) == Some(category))
+                .count();
+            if max.map_or(false, |max| count >= max) {
+                return Some(Limit::Of(category));
+            }
+        }
+        match self.total {
+            Some(max) if subscribed.len() >= max => Some(Limit::Total),
+            _ => None,
+        }
+    }
+}
+
+impl Category {
+    fn of(timeline: Timeline) -> Option<Self> {
+        match timeline.stream_type()? {
+            "hashtag" | "hashtag:local" => Some(Category::Hashtag),
+            "list" => Some(Category::List),
+            "user" | "user:notification" | "direct" => Some(Category::User),
+            _public => None,
+        }
+    }
+}
+
+impl Limit {
+    /// Whether dropping `timeline` makes room under this cap
+    pub(super) fn covers(self, timeline: Timeline) -> bool {
+        match self {
+            Limit::Total => true,
+            Limit::Of(category) => Category::of(timeline) == Some(category),
+        }
+    }
+}
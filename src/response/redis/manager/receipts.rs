@@ -0,0 +1,118 @@
+//! A sample of the deliveries of statuses to clients (with `RECEIPT_LOG`), so that a report
+//! that a client never got a status can be checked against evidence, without logging every
+//! delivery
+//!
+//! One in `RECEIPT_SAMPLE` deliveries is appended to the file as a line of JSON: when it was
+//! made (`ts`, in milliseconds since the Unix epoch), the `timeline` it was sent on, the
+//! `status_id`, and the `connection` it went to, which is the `client_id` of the client's log
+//! lines (e.g., `{"ts":1568227693541,"timeline":"timeline:public","status_id":"1038647",
+//! "connection":12}`).  A delivery is the status being queued for the client; the client's own
+//! filters (such as its blocks) are applied after that.  `GET
+//! /api/v1/streaming/admin/receipts` searches the file.
+use super::Manager;
+use crate::request::{ReceiptQuery, Timeline};
+use crate::response::Event;
+use crate::Id;
+
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The most receipts a search returns (the latest of those that match)
+const MAX_FOUND: usize = 1000;
+
+#[derive(Debug, Default)]
+pub(super) struct Receipts {
+    log: Option<BufWriter<File>>,
+    /// How many deliveries there are to each one recorded
+    every: u64,
+    deliveries: u64,
+}
+
+/// A status's delivery to a client
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Receipt {
+    pub ts: u64,
+    pub timeline: String,
+    pub status_id: Id,
+    /// `None` for clients that `inject` or a test subscribed, which have no connection
+    pub connection: Option<u64>,
+}
+
+impl Receipts {
+    /// The status `event` carries, if receipts are being logged and it is one
+    pub(super) fn status_id(&self, event: &Event) -> Option<Id> {
+        self.log.as_ref().and(event.status_id())
+    }
+
+    /// Whether to record the next delivery
+    pub(super) fn sample(&mut self) -> bool {
+        self.deliveries += 1;
+        self.deliveries % self.every == 0
+    }
+}
+
+impl Manager {
+    /// Append one in `every` deliveries of a status to the file at `path`
+    pub fn log_receipts_to(&mut self, path: &str, every: u64) -> io::Result<()> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        self.receipts = Receipts {
+            log: Some(BufWriter::new(file)),
+            every: every.max(1),
+            deliveries: 0,
+        };
+        Ok(())
+    }
+
+    /// Record that the status `status_id` on `tl` was delivered to the sampled `connections`.
+    /// A log that can't be written to is closed.
+    pub(super) fn receipted(&mut self, tl: Timeline, status_id: Id, connections: &[Option<u64>]) {
+        if connections.is_empty() {
+            return;
+        }
+        let timeline = self
+            .redis_conn
+            .channel_name(&tl)
+            .unwrap_or_else(|_| format!("{:?}", tl));
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as u64);
+        let receipts = &mut self.receipts;
+        let log = match &mut receipts.log {
+            Some(log) => log,
+            None => return,
+        };
+        let written = connections.iter().try_for_each(|&connection| {
+            let receipt = Receipt {
+                ts,
+                timeline: timeline.clone(),
+                status_id,
+                connection,
+            };
+            let line = serde_json::to_string(&receipt).map_err(io::Error::from)?;
+            writeln!(log, "{}", line)
+        });
+        if let Err(e) = written.and_then(|()| log.flush()) {
+            tracing::error!("Could not write to the RECEIPT_LOG; closing it: {}", e);
+            receipts.log = None;
+        }
+    }
+
+    /// The latest receipts in the log at `path` that match `q`, oldest first.  Lines that can't
+    /// be parsed (such as one being written) are skipped.
+    pub fn find_receipts(path: &str, q: &ReceiptQuery) -> io::Result<Vec<Receipt>> {
+        let mut found = Vec::new();
+        for line in BufReader::new(File::open(path)?).lines() {
+            match serde_json::from_str::<Receipt>(&line?) {
+                Ok(receipt) if q.matches(receipt.status_id, receipt.connection) => {
+                    found.push(receipt)
+                }
+                _ => (),
+            }
+        }
+        let skipped = found.len().saturating_sub(MAX_FOUND);
+        found.drain(..skipped);
+        Ok(found)
+    }
+}
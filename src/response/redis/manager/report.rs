@@ -0,0 +1,38 @@
+//! Everything the `Manager` can tell a Prometheus server: how many clients each kind of
+//! stream has and how many Redis channels are subscribed to, along with every counter and
+//! histogram in `metrics` and the hot timelines.
+use super::Manager;
+use crate::config::STREAM_TYPES;
+use crate::metrics;
+
+use hashbrown::HashMap;
+
+impl Manager {
+    /// The metrics, in Prometheus's text format
+    pub fn metrics_report(&mut self) -> String {
+        let mut clients: HashMap<&str, usize> = HashMap::new();
+        for (tl, channels) in &self.timelines {
+            if let Some(stream) = tl.stream_type() {
+                *clients.entry(stream).or_default() += channels.len();
+            }
+        }
+
+        let mut report = metrics::report();
+        report += &metrics::header(
+            "flodgatt_clients",
+            "Clients connected to each kind of stream (a multiplexed WebSocket once per stream)",
+            "gauge",
+        );
+        for stream in STREAM_TYPES.iter() {
+            let count = clients.get(stream).copied().unwrap_or_default();
+            report += &format!("flodgatt_clients{{stream=\"{}\"}} {}\n", stream, count);
+        }
+        report += &metrics::header(
+            "flodgatt_redis_subscriptions",
+            "Redis channels subscribed to, as Redis has confirmed",
+            "gauge",
+        );
+        report += &format!("flodgatt_redis_subscriptions {}\n", self.confirmed.len());
+        report + &self.hot_timelines_report()
+    }
+}
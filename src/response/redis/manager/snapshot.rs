@@ -0,0 +1,71 @@
+//! A point-in-time view of the `Manager`'s subscriptions, for debugging via the admin API
+use super::Manager;
+use crate::request::Timeline;
+
+use serde::Serialize;
+
+/// The full subscription state of the `Manager`
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub total_clients: usize,
+    pub timelines: Vec<TimelineSnapshot>,
+    /// Channels Redis has confirmed a subscription to that no client is using
+    pub orphaned: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TimelineSnapshot {
+    pub channel: String,
+    pub clients: usize,
+    pub redis: RedisStatus,
+}
+
+/// Whether Redis has confirmed our subscription to a channel
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RedisStatus {
+    Confirmed,
+    Pending,
+    /// Covered by the `PSUBSCRIBE` pattern (with `REDIS_PSUBSCRIBE`)
+    Pattern,
+}
+
+impl Manager {
+    pub fn snapshot(&self) -> Snapshot {
+        let channel_name = |tl: &Timeline| {
+            self.redis_conn
+                .channel_name(tl)
+                .unwrap_or_else(|_| format!("{:?}", tl))
+        };
+
+        let mut timelines: Vec<_> = self
+            .timelines
+            .iter()
+            .filter(|(_, channels)| !channels.is_empty())
+            .map(|(tl, channels)| TimelineSnapshot {
+                channel: channel_name(tl),
+                clients: channels.len(),
+                redis: match (self.wildcard, self.confirmed.contains(tl)) {
+                    (true, _) => RedisStatus::Pattern,
+                    (false, true) => RedisStatus::Confirmed,
+                    (false, false) => RedisStatus::Pending,
+                },
+            })
+            .collect();
+        timelines.sort_by(|a, b| a.channel.cmp(&b.channel));
+
+        let mut orphaned: Vec<_> = self
+            .confirmed
+            .iter()
+            .filter(|tl| self.timelines.get(*tl).map_or(true, |ch| ch.is_empty()))
+            .map(channel_name)
+            .collect();
+        orphaned.sort();
+
+        Snapshot {
+            total_clients: timelines.iter().map(|tl| tl.clients).sum(),
+            timelines,
+            orphaned,
+        }
+    }
+}
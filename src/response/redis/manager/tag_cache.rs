@@ -0,0 +1,70 @@
+//! The hashtag cache kept in Redis (with `TAG_CACHE_KEY`), so that a restarted instance
+//! doesn't look up each hashtag in Postgres again.  Each field of the hash is a hashtag's name,
+//! and its value is the hashtag's id and when the entry expires (`<id>:<unix seconds>`).
+use super::{Error, Manager};
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+type Result<T> = std::result::Result<T, Error>;
+
+impl Manager {
+    /// Cache the hashtags kept in the `TAG_CACHE_KEY` hash (if there is one), deleting the
+    /// entries that have expired, and from then on save the hashtags looked up in Postgres to
+    /// it.  Returns how many hashtags were loaded.
+    pub fn load_tag_cache(&mut self) -> Result<usize> {
+        let key = match &self.tag_cache_key {
+            Some(key) => key.clone(),
+            None => return Ok(0),
+        };
+        self.tags.save_lookups();
+        let now = unix_secs();
+        let (mut loaded, mut expired) = (0, Vec::new());
+        for (name, entry) in self.redis_conn.hash_fields(&key)? {
+            match parse_entry(&entry) {
+                Some((id, expires_at)) if expires_at > now => {
+                    self.tags.insert(name, id);
+                    loaded += 1;
+                }
+                _ => expired.push(name),
+            }
+        }
+        if !expired.is_empty() {
+            let ttl = self.tag_cache_ttl;
+            self.redis_conn.update_hash(&key, &[], &expired, ttl)?;
+        }
+        Ok(loaded)
+    }
+
+    /// Save the hashtags looked up in Postgres since the last call to the `TAG_CACHE_KEY` hash
+    pub(super) fn save_tag_cache(&mut self) -> Result<()> {
+        let key = match &self.tag_cache_key {
+            Some(key) => key,
+            None => return Ok(()),
+        };
+        let unsaved = self.tags.take_unsaved();
+        if unsaved.is_empty() {
+            return Ok(());
+        }
+        let expires_at = unix_secs() + self.tag_cache_ttl.as_secs();
+        let fields: Vec<_> = unsaved
+            .into_iter()
+            .map(|(name, id)| (name, format!("{}:{}", id, expires_at)))
+            .collect();
+        Ok(self
+            .redis_conn
+            .update_hash(key, &fields, &[], self.tag_cache_ttl)?)
+    }
+}
+
+/// The id and expiry of an entry in the hash
+fn parse_entry(entry: &str) -> Option<(i64, u64)> {
+    let mut parts = entry.splitn(2, ':');
+    Some((parts.next()?.parse().ok()?, parts.next()?.parse().ok()?))
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
@@ -1,5 +1,6 @@
 use super::*;
-use crate::config;
+use crate::config::{self, EventModeInner};
+use crate::request::{ReceiptQuery, TagRegistry};
 use crate::response::event::checked_event::{
     account::{Account, Field},
     status::attachment::{Attachment, AttachmentType::*},
@@ -8,7 +9,10 @@ use crate::response::event::checked_event::{
     visibility::Visibility::*,
     CheckedEvent::*,
 };
+use crate::response::generator::{pubsub_message, Generator};
+use crate::response::EventFormat;
 use crate::Id;
+use futures::future::{self, Future};
 use serde_json::json;
 use std::fs;
 
@@ -243,3 +247,1254 @@ fn manager_poll_handles_full_channel() -> TestResult {
 
     Ok(assert_eq!(i, 6))
 }
+
+#[test]
+fn manager_snapshot_tracks_redis_confirmations() -> TestResult {
+    use super::snapshot::RedisStatus;
+    let mut manager = Manager::try_from(&config::Redis::default())?;
+    let subscription = Subscription {
+        timeline: Timeline::from_redis_text("public:local", &TagRegistry::default())?,
+        ..Subscription::default()
+    };
+    let (event_tx, _event_rx) = manager.channel();
+    manager.subscribe(&subscription, event_tx)?;
+
+    let snapshot = manager.snapshot();
+    assert_eq!(snapshot.total_clients, 1);
+    assert_eq!(snapshot.timelines[0].channel, "timeline:public:local");
+    assert_eq!(snapshot.timelines[0].redis, RedisStatus::Pending);
+
+    manager
+        .redis_conn
+        .add(b"*3\r\n$9\r\nsubscribe\r\n$21\r\ntimeline:public:local\r\n:1\r\n");
+    while let Ok(Async::Ready(Some(len))) = manager.redis_conn.poll_redis(manager.unread_idx.1) {
+        manager.unread_idx.1 += len;
+        while let Ok(Async::Ready(Some(_))) = manager.poll() {}
+    }
+    assert_eq!(
+        manager.snapshot().timelines[0].redis,
+        RedisStatus::Confirmed
+    );
+
+    manager.resync()?;
+    assert_eq!(manager.snapshot().timelines[0].redis, RedisStatus::Pending);
+    Ok(())
+}
+
+#[test]
+fn manager_reconcile_repairs_drift() -> TestResult {
+    let mut manager = Manager::try_from(&config::Redis::default())?;
+    let subscription = Subscription {
+        timeline: Timeline::from_redis_text("public:local", &TagRegistry::default())?,
+        ..Subscription::default()
+    };
+    let (event_tx, _event_rx) = manager.channel();
+    manager.subscribe(&subscription, event_tx)?;
+    manager
+        .redis_conn
+        .add(b"*3\r\n$9\r\nsubscribe\r\n$15\r\ntimeline:public\r\n:1\r\n");
+    while let Ok(Async::Ready(Some(len))) = manager.redis_conn.poll_redis(manager.unread_idx.1) {
+        manager.unread_idx.1 += len;
+        while let Ok(Async::Ready(Some(_))) = manager.poll() {}
+    }
+
+    // Redis has our orphaned `timeline:public` subscription but lost `timeline:public:local`
+    manager.redis_conn.test_pubsub_channels = vec!["timeline:public".to_string()];
+    let query = manager
+        .reconciliation_query()
+        .expect("a timeline with a client");
+    assert_eq!(manager.reconcile(query.run()?)?, 2);
+
+    let channels = vec!["timeline:public:local".to_string()];
+    assert_eq!(manager.reconcile(channels)?, 0);
+    Ok(())
+}
+
+#[test]
+fn manager_clear_tag_cache_keeps_subscribed_tags() -> TestResult {
+    let mut manager = Manager::try_from(&config::Redis::default())?;
+    let tags = TagRegistry::new(2);
+    tags.insert("rust".to_string(), 1);
+    tags.insert("go".to_string(), 2);
+    let (event_tx, _event_rx) = manager.channel();
+    for tag in &["rust", "go"] {
+        let subscription = Subscription {
+            timeline: Timeline::from_redis_text(&format!("hashtag:{}", tag), &tags)?,
+            hashtag_name: Some(tag.to_string()),
+            ..Subscription::default()
+        };
+        manager.subscribe(&subscription, event_tx.clone())?;
+    }
+    // Nobody is still listening to `#go` after its channel fails a ping
+    manager.timelines.retain(|tl, _| tl.tag() == Some(1));
+
+    assert_eq!(manager.clear_tag_cache(), 1);
+    assert_eq!(manager.clear_tag_cache(), 0);
+    assert!(manager.tags.contains("rust"));
+    assert!(!manager.tags.contains("go"));
+    Ok(())
+}
+
+#[test]
+fn manager_tag_caches_hold_tag_cache_size_hashtags() -> TestResult {
+    let mut cfg = config::Redis::default();
+    cfg.tag_cache_size.0 = 2;
+    let mut manager = Manager::try_from(&cfg)?;
+    let tags = TagRegistry::new(3);
+    let (event_tx, _event_rx) = manager.channel();
+    let evictions = crate::metrics::TAG_CACHE_EVICTIONS.get();
+    for (id, tag) in (1..).zip(&["rust", "go", "zig"]) {
+        tags.insert(tag.to_string(), id);
+        let subscription = Subscription {
+            timeline: Timeline::from_redis_text(&format!("hashtag:{}", tag), &tags)?,
+            hashtag_name: Some(tag.to_string()),
+            ..Subscription::default()
+        };
+        manager.subscribe(&subscription, event_tx.clone())?;
+    }
+
+    assert!(!manager.tags.contains("rust"));
+    assert!(manager.tags.contains("go") && manager.tags.contains("zig"));
+    // Both directions evicted `#rust` (other tests may be evicting at the same time)
+    assert!(crate::metrics::TAG_CACHE_EVICTIONS.get() - evictions >= 2);
+    Ok(())
+}
+
+#[test]
+fn manager_inject_reaches_subscribers() -> TestResult {
+    let mut manager = Manager::try_from(&config::Redis::default())?;
+    let subscription = Subscription {
+        timeline: Timeline::from_redis_text("public", &TagRegistry::default())?,
+        ..Subscription::default()
+    };
+    let (event_tx, _event_rx) = manager.channel();
+    manager.subscribe(&subscription, event_tx)?;
+
+    let event_txt = r#"{"event":"delete","payload":"1038647"}"#;
+    assert_eq!(manager.inject("timeline:public", event_txt)?, 1);
+    assert_eq!(manager.inject("timeline:public:local", event_txt)?, 0);
+    assert!(manager.inject("timeline:public", "not an event").is_err());
+    assert!(manager
+        .inject("timeline:no:such:channel", event_txt)
+        .is_err());
+    Ok(())
+}
+
+#[test]
+fn manager_sends_a_status_once_to_a_client_of_a_composite_stream() -> TestResult {
+    let mut manager = Manager::try_from(&config::Redis::default())?;
+    let tags = TagRegistry::default();
+    let timelines = vec![
+        (Timeline::from_redis_text("public", &tags)?, None),
+        (Timeline::from_redis_text("public:local", &tags)?, None),
+    ];
+    let composite = Subscription {
+        timeline: timelines[0].0,
+        composite: Some(crate::request::Composite {
+            name: "events".to_string(),
+            timelines,
+        }),
+        ..Subscription::default()
+    };
+    let (event_tx, _event_rx) = manager.channel();
+    manager.subscribe(&composite, event_tx)?;
+    let local = Subscription {
+        timeline: Timeline::from_redis_text("public:local", &tags)?,
+        ..Subscription::default()
+    };
+    let (event_tx, _event_rx) = manager.channel();
+    manager.subscribe(&local, event_tx)?;
+
+    let account = json!({ "id": "1", "acct": "admin" });
+    let update =
+        |id: &str| json!({ "event": "update", "payload": { "id": id, "account": account } });
+    let first = update("101").to_string();
+    assert_eq!(manager.inject("timeline:public", &first)?, 1);
+    // The composite stream's client was sent it on `public`; the local client was not
+    assert_eq!(manager.inject("timeline:public:local", &first)?, 1);
+    let second = update("102").to_string();
+    assert_eq!(manager.inject("timeline:public:local", &second)?, 2);
+    // Other events are not deduplicated
+    let delete = r#"{"event":"delete","payload":"101"}"#;
+    assert_eq!(manager.inject("timeline:public", delete)?, 1);
+    assert_eq!(manager.inject("timeline:public:local", delete)?, 2);
+    Ok(())
+}
+
+#[test]
+fn manager_metrics_report_counts_clients_by_stream() -> TestResult {
+    let mut manager = Manager::try_from(&config::Redis::default())?;
+    let mut clients = Vec::new();
+    for tl in &["public", "public", "public:local"] {
+        let subscription = Subscription {
+            timeline: Timeline::from_redis_text(tl, &TagRegistry::default())?,
+            ..Subscription::default()
+        };
+        let (event_tx, event_rx) = manager.channel();
+        manager.subscribe(&subscription, event_tx)?;
+        clients.push(event_rx);
+    }
+
+    let report = manager.metrics_report();
+    for line in &[
+        "# TYPE flodgatt_redis_messages_total counter",
+        "# TYPE flodgatt_clients gauge",
+        r#"flodgatt_clients{stream="public"} 2"#,
+        r#"flodgatt_clients{stream="public:local"} 1"#,
+        r#"flodgatt_clients{stream="hashtag"} 0"#,
+        "flodgatt_redis_subscriptions 0",
+    ] {
+        assert!(
+            report.lines().any(|l| l == *line),
+            "{} in:\n{}",
+            line,
+            report
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn manager_send_msgs_yields_after_budget() -> TestResult {
+    // `send_msgs` polls the client channels, which must happen inside a task
+    future::lazy(|| -> TestResult {
+        let mut cfg = config::Redis::default();
+        cfg.dispatch_budget.0 = 4;
+        let mut manager = Manager::try_from(&cfg)?;
+        let subscription = Subscription {
+            timeline: Timeline::from_redis_text("public", &TagRegistry::default())?,
+            ..Subscription::default()
+        };
+        let (event_tx, mut event_rx) = manager.channel();
+        manager.subscribe(&subscription, event_tx)?;
+        for i in 1..=6 {
+            manager.redis_conn.add(&input(i));
+        }
+
+        let mut received = || {
+            let mut events = Vec::new();
+            while let Ok(Async::Ready(Some(event))) = event_rx.poll() {
+                events.push(event);
+            }
+            events
+        };
+        assert_eq!(manager.send_msgs()?, Async::NotReady);
+        assert_eq!(received(), (0..4).map(output).collect::<Vec<_>>());
+        assert_eq!(manager.send_msgs()?, Async::Ready(()));
+        assert_eq!(received(), vec![output(4), output(5)]);
+        Ok(())
+    })
+    .wait()
+}
+
+#[test]
+fn manager_applies_client_overflow_policy_to_full_channels() -> TestResult {
+    future::lazy(|| -> TestResult {
+        for &overflow in &[
+            ClientOverflowInner::Wait,
+            ClientOverflowInner::Drop,
+            ClientOverflowInner::Disconnect,
+        ] {
+            let mut cfg = config::Redis::default();
+            cfg.client_buffer.0 = 1;
+            cfg.client_overflow.0 = overflow;
+            let mut manager = Manager::try_from(&cfg)?;
+            // A list's events are sent through each client's own channel
+            let subscription = Subscription {
+                timeline: Timeline::from_redis_text("list:1", &TagRegistry::default())?,
+                ..Subscription::default()
+            };
+            // The channel also has a slot for its one sender, so it holds two events
+            let (event_tx, mut event_rx) = manager.channel();
+            manager.subscribe(&subscription, event_tx)?;
+            for i in 1..=6 {
+                let msg = String::from_utf8(input(i))?;
+                let msg = msg.replacen("timeline:public", "timeline:list:1", 1);
+                manager.redis_conn.add(msg.as_bytes());
+            }
+
+            let sent = manager.send_msgs()?;
+            let mut received = Vec::new();
+            let closed = loop {
+                match event_rx.poll() {
+                    Ok(Async::Ready(Some(event))) => received.push(event),
+                    Ok(Async::Ready(None)) => break true,
+                    _ => break false,
+                }
+            };
+            assert_eq!(received, vec![output(0), output(1)], "{:?}", overflow);
+            match overflow {
+                ClientOverflowInner::Wait => assert_eq!((sent, closed), (Async::NotReady, false)),
+                ClientOverflowInner::Drop => assert_eq!((sent, closed), (Async::Ready(()), false)),
+                ClientOverflowInner::Disconnect => {
+                    assert_eq!((sent, closed), (Async::Ready(()), true))
+                }
+            }
+        }
+        Ok(())
+    })
+    .wait()
+}
+
+#[test]
+fn manager_lets_broadcast_clients_skip_what_they_fall_behind_on() -> TestResult {
+    future::lazy(|| -> TestResult {
+        let mut cfg = config::Redis::default();
+        cfg.client_buffer.0 = 1;
+        cfg.client_overflow.0 = ClientOverflowInner::Wait;
+        let mut manager = Manager::try_from(&cfg)?;
+        let subscription = Subscription {
+            timeline: Timeline::from_redis_text("public", &TagRegistry::default())?,
+            ..Subscription::default()
+        };
+        let (event_tx, mut event_rx) = manager.channel();
+        manager.subscribe(&subscription, event_tx)?;
+        for i in 1..=6 {
+            manager.redis_conn.add(&input(i));
+        }
+
+        // The public timeline isn't held up for its slow client, which gets the latest event
+        let dropped = metrics::CLIENT_EVENTS_DROPPED.get();
+        assert_eq!(manager.send_msgs()?, Async::Ready(()));
+        assert_eq!(event_rx.poll()?, Async::Ready(Some(output(5))));
+        assert_eq!(event_rx.poll()?, Async::NotReady);
+        assert!(metrics::CLIENT_EVENTS_DROPPED.get() - dropped >= 5);
+        Ok(())
+    })
+    .wait()
+}
+
+#[test]
+fn manager_applies_invalid_utf8_policy_to_whole_messages() -> TestResult {
+    // Latin-1, as a misconfigured publisher might send it
+    let event_bytes = &b"{\"event\":\"delete\",\"payload\":\"1038647\",\"note\":\"caf\xe9\"}"[..];
+    let mut published = format!(
+        "*3\r\n$7\r\nmessage\r\n$15\r\ntimeline:public\r\n${}\r\n",
+        event_bytes.len()
+    )
+    .into_bytes();
+    published.extend_from_slice(event_bytes);
+    published.extend_from_slice(b"\r\n");
+    published.extend_from_slice(&input(1));
+    let replaced = Event::try_from(&*String::from_utf8_lossy(event_bytes))?;
+
+    for &policy in &[
+        InvalidUtf8Inner::Reject,
+        InvalidUtf8Inner::Replace,
+        InvalidUtf8Inner::Raw,
+    ] {
+        let mut cfg = config::Redis::default();
+        cfg.invalid_utf8.0 = policy;
+        let mut manager = Manager::try_from(&cfg)?;
+        manager.redis_conn.add(&published);
+
+        let mut events = Vec::new();
+        while let Ok(Async::Ready(Some(len))) = manager.redis_conn.poll_redis(manager.unread_idx.1)
+        {
+            manager.unread_idx.1 += len;
+            loop {
+                match manager.poll()? {
+                    Async::Ready(Some((_tl, event))) => events.push(event),
+                    Async::Ready(None) => continue,
+                    Async::NotReady => break,
+                }
+            }
+        }
+        // The message never stalls the input that follows it
+        let expected = match policy {
+            InvalidUtf8Inner::Reject => vec![output(0)],
+            InvalidUtf8Inner::Replace => vec![Arc::new(replaced.clone()), output(0)],
+            InvalidUtf8Inner::Raw => {
+                let raw = Event::Raw(Box::new(replaced.clone()), event_bytes.into());
+                vec![Arc::new(raw), output(0)]
+            }
+        };
+        assert_eq!(events, expected, "{:?}", policy);
+    }
+    Ok(())
+}
+
+#[test]
+fn wildcard_manager_skips_timelines_without_clients() -> TestResult {
+    use super::snapshot::RedisStatus;
+    let mut cfg = config::Redis::default();
+    cfg.psubscribe.0 = true;
+    let mut manager = Manager::try_from(&cfg)?;
+    let subscription = Subscription {
+        timeline: Timeline::from_redis_text("public", &TagRegistry::default())?,
+        ..Subscription::default()
+    };
+    let (event_tx, _event_rx) = manager.channel();
+    manager.subscribe(&subscription, event_tx)?;
+
+    let event_txt = r#"{"event":"delete","payload":"1038647"}"#;
+    for channel in &[
+        "timeline:public:local",
+        "timeline:hashtag:uncached",
+        "timeline:public",
+    ] {
+        let msg = pubsub_message(channel, event_txt);
+        let pattern = String::from_utf8(msg)?.replacen(
+            "*3\r\n$7\r\nmessage\r\n",
+            "*4\r\n$8\r\npmessage\r\n$10\r\ntimeline:*\r\n",
+            1,
+        );
+        manager.redis_conn.add(pattern.as_bytes());
+    }
+    let mut received = Vec::new();
+    while let Ok(Async::Ready(Some(len))) = manager.redis_conn.poll_redis(manager.unread_idx.1) {
+        manager.unread_idx.1 += len;
+        loop {
+            match manager.poll()? {
+                Async::Ready(Some((tl, _event))) => received.push(tl),
+                Async::Ready(None) => continue,
+                Async::NotReady => break,
+            }
+        }
+    }
+    assert_eq!(received, vec![subscription.timeline]);
+    assert_eq!(manager.snapshot().timelines[0].redis, RedisStatus::Pattern);
+    assert!(manager.reconciliation_query().is_none());
+    Ok(())
+}
+
+#[test]
+fn manager_ranks_the_timelines_that_reach_the_most_clients() -> TestResult {
+    future::lazy(|| -> TestResult {
+        let mut cfg = config::Redis::default();
+        cfg.hot_timelines.0 = 1;
+        let mut manager = Manager::try_from(&cfg)?;
+        let tags = TagRegistry::default();
+        let event_txt = r#"{"event":"delete","payload":"1038647"}"#;
+        let mut receivers = Vec::new();
+        for &(redis_text, clients) in &[("public", 2), ("public:local", 1)] {
+            let subscription = Subscription {
+                timeline: Timeline::from_redis_text(redis_text, &tags)?,
+                ..Subscription::default()
+            };
+            for _ in 0..clients {
+                let (event_tx, event_rx) = manager.channel();
+                manager.subscribe(&subscription, event_tx)?;
+                receivers.push(event_rx);
+            }
+            let channel = format!("timeline:{}", redis_text);
+            manager.redis_conn.add(&pubsub_message(&channel, event_txt));
+        }
+        manager.send_msgs()?;
+        assert_eq!(manager.hot_timelines(), Vec::new()); // the first minute isn't over
+
+        manager.fanout.started -= Duration::from_secs(60);
+        let hottest = HotTimeline {
+            channel: "timeline:public".to_string(),
+            events: 1,
+            deliveries: 2,
+        };
+        assert_eq!(manager.hot_timelines(), vec![hottest]);
+        assert!(manager
+            .hot_timelines_report()
+            .contains("flodgatt_hot_timeline_deliveries{channel=\"timeline:public\"} 2\n"));
+        Ok(())
+    })
+    .wait()
+}
+
+#[test]
+fn manager_disconnects_banned_clients_and_stores_the_denylist() -> TestResult {
+    let mut cfg = config::Redis::default();
+    cfg.denylist_key.0 = Some("flodgatt:denylist".to_string());
+    let mut manager = Manager::try_from(&cfg)?;
+    let timeline = Timeline::from_redis_text("public", &TagRegistry::default())?;
+    let clients = [(Some("scraper"), "192.0.2.1"), (None, "198.51.100.1")];
+    let mut receivers = Vec::new();
+    for &(token, ip) in &clients {
+        let subscription = Subscription {
+            timeline,
+            access_token: token.map(str::to_string),
+            ip: Some(ip.parse()?),
+            ..Subscription::default()
+        };
+        let (event_tx, event_rx) = manager.channel();
+        manager.subscribe(&subscription, event_tx)?;
+        receivers.push(event_rx);
+    }
+
+    let disconnected = manager.disconnect(1008, |_, token, _ip| token == Some("scraper"));
+    assert_eq!(disconnected, 1);
+    assert_eq!(manager.snapshot().total_clients, 1);
+    let close = receivers[0].poll();
+    assert!(matches!(close, Ok(Async::Ready(Some(ref e))) if **e == Event::Disconnect(1008)));
+    assert!(matches!(receivers[0].poll(), Ok(Async::Ready(None))));
+
+    let members = vec!["token:scraper".to_string()];
+    manager.update_denylist(&members, true)?;
+    assert_eq!(manager.denylist_members()?, Some(members.clone()));
+    manager.update_denylist(&members, false)?;
+    assert_eq!(manager.denylist_members()?, Some(Vec::new()));
+    Ok(())
+}
+
+#[test]
+fn manager_disconnects_a_users_clients_with_the_chosen_code() -> TestResult {
+    let mut manager = Manager::try_from(&config::Redis::default())?;
+    let mut receivers = Vec::new();
+    for &(channel, user) in &[("1", Some(1)), ("public", Some(1)), ("public", Some(2))] {
+        let subscription = Subscription {
+            timeline: Timeline::from_redis_text(channel, &TagRegistry::default())?,
+            user_id: user.map(Id),
+            ..Subscription::default()
+        };
+        let (event_tx, event_rx) = manager.channel();
+        manager.subscribe(&subscription, event_tx)?;
+        receivers.push(event_rx);
+    }
+
+    let disconnected = manager.disconnect(4001, |user_id, _, _| user_id == Some(Id(1)));
+    assert_eq!(disconnected, 2);
+    assert_eq!(manager.snapshot().total_clients, 1);
+    for receiver in &mut receivers[..2] {
+        let close = receiver.poll();
+        assert!(matches!(close, Ok(Async::Ready(Some(ref e))) if **e == Event::Disconnect(4001)));
+    }
+    Ok(())
+}
+
+#[test]
+fn manager_closes_every_client_and_unsubscribes_on_shutdown() -> TestResult {
+    let mut manager = Manager::try_from(&config::Redis::default())?;
+    let mut receivers = Vec::new();
+    for &channel in &["1", "public", "public"] {
+        let subscription = Subscription {
+            timeline: Timeline::from_redis_text(channel, &TagRegistry::default())?,
+            ..Subscription::default()
+        };
+        let (event_tx, event_rx) = manager.channel();
+        manager.subscribe(&subscription, event_tx)?;
+        receivers.push(event_rx);
+    }
+    manager.send_queued_cmds()?;
+    let key_cmds = manager.redis_conn.test_key_cmds.len();
+
+    assert_eq!(manager.shut_down()?, 3);
+    assert_eq!(manager.snapshot().total_clients, 0);
+    assert!(manager.active_timelines().is_empty());
+    for receiver in &mut receivers {
+        let close = receiver.poll();
+        assert!(matches!(close, Ok(Async::Ready(Some(ref e))) if **e == Event::Disconnect(1001)));
+        assert!(matches!(receiver.poll(), Ok(Async::Ready(None))));
+    }
+    assert_eq!(manager.redis_conn.test_key_cmds.len(), key_cmds + 1);
+    Ok(())
+}
+
+#[test]
+fn manager_drops_the_idlest_anonymous_channel_at_a_channel_limit() -> TestResult {
+    let mut cfg = config::Redis::default();
+    cfg.max_hashtag_channels.0 = Some(2);
+    let mut manager = Manager::try_from(&cfg)?;
+    let tags = TagRegistry::new(4);
+    let mut receivers = Vec::new();
+    let clients = [
+        ("rust", None),
+        ("go", None),
+        ("zig", Some(1)),
+        ("elm", None),
+    ];
+    for (id, &(tag, user)) in clients.iter().enumerate() {
+        tags.insert(tag.to_string(), id as i64 + 1);
+        let subscription = Subscription {
+            timeline: Timeline::from_redis_text(&format!("hashtag:{}", tag), &tags)?,
+            hashtag_name: Some(tag.to_string()),
+            user_id: user.map(Id),
+            ..Subscription::default()
+        };
+        if tag == "zig" {
+            // `#go` has been idle longer than `#rust`, so it makes room for `#zig`
+            let rust = Timeline::from_redis_text("hashtag:rust", &tags)?;
+            manager
+                .last_active
+                .insert(rust, Instant::now() + Duration::from_secs(1));
+        }
+        let (event_tx, event_rx) = manager.channel();
+        manager.subscribe(&subscription, event_tx)?;
+        receivers.push(event_rx);
+    }
+
+    // `#rust` made room for `#elm`, since `#zig` has a signed-in client
+    let subscribed: HashSet<_> = manager
+        .active_timelines()
+        .iter()
+        .filter_map(Timeline::tag)
+        .collect();
+    assert_eq!(subscribed, vec![3, 4].into_iter().collect());
+    for receiver in &mut receivers[..2] {
+        let close = receiver.poll();
+        assert!(matches!(close, Ok(Async::Ready(Some(ref e))) if **e == Event::Disconnect(1013)));
+    }
+    Ok(())
+}
+
+#[test]
+fn manager_refuses_channels_over_a_limit_it_cannot_make_room_under() -> TestResult {
+    let mut cfg = config::Redis::default();
+    cfg.max_user_channels.0 = Some(1);
+    let mut manager = Manager::try_from(&cfg)?;
+    let mut receivers = Vec::new();
+    for &(channel, user) in &[("1", 1), ("public", 2), ("2", 2)] {
+        let subscription = Subscription {
+            timeline: Timeline::from_redis_text(channel, &TagRegistry::default())?,
+            user_id: Some(Id(user)),
+            ..Subscription::default()
+        };
+        let (event_tx, event_rx) = manager.channel();
+        let subscribed = manager.subscribe(&subscription, event_tx);
+        assert_eq!(subscribed.is_ok(), channel != "2", "{}", channel);
+        receivers.push(event_rx);
+    }
+
+    assert_eq!(manager.snapshot().total_clients, 2);
+    let refused = receivers[2].poll();
+    assert!(matches!(refused, Ok(Async::Ready(Some(ref e))) if **e == Event::Disconnect(1013)));
+    Ok(())
+}
+
+#[test]
+fn manager_applies_reconfigured_channel_limits_to_later_subscriptions() -> TestResult {
+    let mut manager = Manager::try_from(&config::Redis::default())?;
+    let mut receivers = Vec::new();
+    for &(channel, user) in &[("1", 1), ("2", 2), ("3", 3)] {
+        if channel == "3" {
+            let mut cfg = config::Redis::default();
+            cfg.max_user_channels.0 = Some(2);
+            manager.reconfigure(&cfg);
+        }
+        let subscription = Subscription {
+            timeline: Timeline::from_redis_text(channel, &TagRegistry::default())?,
+            user_id: Some(Id(user)),
+            ..Subscription::default()
+        };
+        let (event_tx, event_rx) = manager.channel();
+        let subscribed = manager.subscribe(&subscription, event_tx);
+        assert_eq!(subscribed.is_ok(), channel != "3", "{}", channel);
+        receivers.push(event_rx);
+    }
+
+    assert_eq!(manager.snapshot().total_clients, 2);
+    Ok(())
+}
+
+#[test]
+fn manager_logs_its_decisions_about_subscriptions() -> TestResult {
+    let path = std::env::temp_dir().join(format!("flodgatt_decisions_{}", std::process::id()));
+    let mut cfg = config::Redis::default();
+    cfg.max_user_channels.0 = Some(1);
+    let mut manager = Manager::try_from(&cfg)?;
+    manager.log_decisions_to(path.to_str().expect("a UTF-8 temp dir"))?;
+    let mut receivers = Vec::new();
+    for &(channel, user) in &[("1", 1), ("public", 2), ("2", 2)] {
+        let subscription = Subscription {
+            timeline: Timeline::from_redis_text(channel, &TagRegistry::default())?,
+            user_id: Some(Id(user)),
+            ..Subscription::default()
+        };
+        let (event_tx, event_rx) = manager.channel();
+        let _ = manager.subscribe(&subscription, event_tx);
+        receivers.push(event_rx);
+    }
+
+    let log = fs::read_to_string(&path)?;
+    fs::remove_file(&path)?;
+    let mut decisions: Vec<serde_json::Value> = log
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<std::result::Result<_, _>>()?;
+    for decision in &mut decisions {
+        decision.as_object_mut().map(|d| d.remove("ms")); // the only part that may differ
+    }
+    assert_eq!(
+        decisions,
+        vec![
+            json!({"seq": 1, "decision": "subscribe", "channels": ["timeline:1"]}),
+            json!({"seq": 2, "decision": "subscribe", "channels": ["timeline:public"]}),
+            json!({"seq": 3, "decision": "refuse", "channels": ["timeline:2"]}),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn manager_samples_delivery_receipts_for_statuses() -> TestResult {
+    let path = std::env::temp_dir().join(format!("flodgatt_receipts_{}", std::process::id()));
+    let path = path.to_str().expect("a UTF-8 temp dir");
+    let mut manager = Manager::try_from(&config::Redis::default())?;
+    manager.log_receipts_to(path, 2)?;
+    let mut receivers = Vec::new();
+    for &connection_id in &[Some(7), Some(8)] {
+        let subscription = Subscription {
+            timeline: Timeline::from_redis_text("public", &TagRegistry::default())?,
+            connection_id,
+            ..Subscription::default()
+        };
+        let (event_tx, event_rx) = manager.channel();
+        manager.subscribe(&subscription, event_tx)?;
+        receivers.push(event_rx);
+    }
+    let account = json!({ "id": "1", "acct": "admin" });
+    for id in &["101", "102"] {
+        let update = json!({ "event": "update", "payload": { "id": id, "account": account } });
+        manager.inject("timeline:public", &update.to_string())?;
+    }
+    manager.inject("timeline:public", r#"{"event":"delete","payload":"101"}"#)?;
+
+    // Every second delivery of a status (of four) was recorded, to one of the two connections
+    let all = Manager::find_receipts(path, &ReceiptQuery::default())?;
+    let first = ReceiptQuery {
+        status_id: Some(Id(101)),
+        ..ReceiptQuery::default()
+    };
+    let found = Manager::find_receipts(path, &first)?;
+    fs::remove_file(path)?;
+    assert_eq!(all.len(), 2);
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].timeline, "timeline:public");
+    assert!(matches!(found[0].connection, Some(7) | Some(8)));
+    Ok(())
+}
+
+#[test]
+fn manager_parses_events_in_the_mode_of_their_category() -> TestResult {
+    future::lazy(|| -> TestResult {
+        let mut cfg = config::Redis::default();
+        cfg.event_modes.0 = vec![("public".to_string(), EventModeInner::PassThrough)]
+            .into_iter()
+            .collect();
+        let mut manager = Manager::try_from(&cfg)?;
+        let mut receivers = Vec::new();
+        for timeline in &["public", "1"] {
+            let subscription = Subscription {
+                timeline: Timeline::from_redis_text(timeline, &TagRegistry::default())?,
+                ..Subscription::default()
+            };
+            let (event_tx, event_rx) = manager.channel();
+            manager.subscribe(&subscription, event_tx)?;
+            receivers.push(event_rx);
+        }
+        let delete = r#"{"event":"delete","payload":"101"}"#;
+        let mut received = |manager: &mut Manager| -> Result<Vec<bool>> {
+            manager.inject("timeline:public", delete)?;
+            manager.inject("timeline:1", delete)?;
+            let typed = receivers.iter_mut().map(|rx| match rx.poll() {
+                Ok(Async::Ready(Some(event))) => matches!(*event, Event::TypeSafe(_)),
+                other => panic!("Expected an event, got {:?}", other),
+            });
+            Ok(typed.collect())
+        };
+
+        // The public timeline's events are passed through, and the user's are type checked
+        assert_eq!(received(&mut manager)?, vec![false, true]);
+        let strict = manager.set_event_mode(&["public".to_string()], EventModeInner::Strict);
+        assert!(strict.pass_through.is_empty());
+        assert_eq!(received(&mut manager)?, vec![true, true]);
+        let pass_through = ["user".to_string(), "public".to_string()];
+        let set = manager.set_event_mode(&pass_through, EventModeInner::PassThrough);
+        assert_eq!(set.pass_through, vec!["public", "user"]);
+        assert_eq!(received(&mut manager)?, vec![false, false]);
+
+        // Reloading the configuration replaces the modes set at runtime
+        manager.reconfigure(&cfg);
+        assert_eq!(manager.pass_through().pass_through, vec!["public"]);
+        assert_eq!(received(&mut manager)?, vec![false, true]);
+        Ok(())
+    })
+    .wait()
+}
+
+#[test]
+fn manager_keeps_a_heartbeat_until_it_shuts_down() -> TestResult {
+    let mut cfg = config::Redis::default();
+    cfg.heartbeat_key.0 = Some("flodgatt:heartbeat".to_string());
+    let mut manager = Manager::try_from(&cfg)?;
+    manager.set_instance_id("flodgatt-1");
+    assert_eq!(manager.heartbeat_interval(), Some(Duration::from_secs(10)));
+    let subscription = Subscription {
+        timeline: Timeline::from_redis_text("public", &TagRegistry::default())?,
+        ..Subscription::default()
+    };
+    let (event_tx, _event_rx) = manager.channel();
+    manager.subscribe(&subscription, event_tx)?;
+    manager.redis_conn.test_hash.insert(
+        "flodgatt-2".to_string(),
+        json!({ "ts": 1, "clients": 3, "channels": 1, "idle_ms": null }).to_string(),
+    );
+
+    manager.beat()?;
+    let pool = manager.pool_health()?.expect("a heartbeat");
+    assert_eq!((pool.instance.as_str(), pool.alive), ("flodgatt-1", 1));
+    let beats: Vec<_> = pool
+        .instances
+        .iter()
+        .map(|i| {
+            (
+                i.instance.as_str(),
+                i.alive,
+                i.beat.as_ref().map(|b| b.clients),
+            )
+        })
+        .collect();
+    assert_eq!(
+        beats,
+        vec![
+            ("flodgatt-1", true, Some(1)),
+            ("flodgatt-2", false, Some(3))
+        ]
+    );
+
+    manager.shut_down()?;
+    let pool = manager.pool_health()?.expect("a heartbeat");
+    assert_eq!(pool.instances.len(), 1);
+    Ok(())
+}
+
+#[test]
+fn manager_resubscribes_after_reconnecting() -> TestResult {
+    future::lazy(|| -> TestResult {
+        let mut manager = Manager::try_from(&config::Redis::default())?;
+        let subscription = Subscription {
+            timeline: Timeline::from_redis_text("public", &TagRegistry::default())?,
+            ..Subscription::default()
+        };
+        let (event_tx, _event_rx) = manager.channel();
+        manager.subscribe(&subscription, event_tx)?;
+        let key_cmds = manager.redis_conn.test_key_cmds.len();
+
+        // Input from the old connection that ends partway through a message
+        manager.redis_conn.add(&input(1)[..20]);
+        while let Ok(Async::Ready(Some(len))) = manager.redis_conn.poll_redis(manager.unread_idx.1)
+        {
+            manager.unread_idx.1 += len;
+        }
+        manager.redis_conn.test_reconnected = true;
+
+        assert_eq!(manager.send_msgs()?, Async::Ready(()));
+        assert_eq!(manager.redis_conn.test_key_cmds.len(), key_cmds + 1);
+        assert_eq!(manager.unread_idx, (0, 0));
+        Ok(())
+    })
+    .wait()
+}
+
+#[test]
+fn manager_reports_its_pubsub_connections_down_to_the_health_endpoint() -> TestResult {
+    let mut manager = Manager::try_from(&config::Redis::default())?;
+    assert_eq!(manager.pubsub_health(), Ok(()));
+
+    manager.redis_conn.test_down = vec!["10.0.0.1:6379".to_string(), "10.0.0.2:6379".to_string()];
+    let failure = manager.pubsub_health().unwrap_err();
+    assert!(
+        failure.contains("10.0.0.1:6379, 10.0.0.2:6379"),
+        "{}",
+        failure
+    );
+    Ok(())
+}
+
+#[test]
+fn manager_sends_the_subscriptions_made_between_polls_together() -> TestResult {
+    future::lazy(|| -> TestResult {
+        let mut manager = Manager::try_from(&config::Redis::default())?;
+        let mut clients = Vec::new();
+        for tl in &["public", "public:local", "public:media"] {
+            let subscription = Subscription {
+                timeline: Timeline::from_redis_text(tl, &TagRegistry::default())?,
+                ..Subscription::default()
+            };
+            let (event_tx, event_rx) = manager.channel();
+            manager.subscribe(&subscription, event_tx)?;
+            clients.push(event_rx);
+        }
+        assert!(manager.redis_conn.test_key_cmds.is_empty());
+
+        assert_eq!(manager.send_msgs()?, Async::Ready(()));
+        let key_cmds = &manager.redis_conn.test_key_cmds;
+        assert_eq!(key_cmds.len(), 1);
+        let keys = String::from_utf8_lossy(&key_cmds[0]);
+        for channel in &["public", "public:local", "public:media"] {
+            assert!(
+                keys.contains(&format!("timeline:{}\r\n", channel)),
+                "{}",
+                keys
+            );
+        }
+        Ok(())
+    })
+    .wait()
+}
+
+#[test]
+fn manager_subscribes_to_hashtags_by_id_with_hashtag_id_channels() -> TestResult {
+    future::lazy(|| -> TestResult {
+        let mut cfg = config::Redis::default();
+        cfg.hashtag_id_channels.0 = true;
+        let mut manager = Manager::try_from(&cfg)?;
+        manager.use_tags(TagRegistry::new(1));
+        let subscription = Subscription {
+            timeline: Timeline::from_redis_text("hashtag:42", &manager.tags)?,
+            ..Subscription::default()
+        };
+        let (event_tx, _event_rx) = manager.channel();
+        manager.subscribe(&subscription, event_tx)?;
+
+        assert_eq!(manager.send_msgs()?, Async::Ready(()));
+        let keys = String::from_utf8_lossy(&manager.redis_conn.test_key_cmds[0]).to_string();
+        assert!(keys.contains("timeline:hashtag:42\r\n"), "{}", keys);
+        let event_txt = r#"{"event":"delete","payload":"1038647"}"#;
+        assert_eq!(manager.inject("timeline:hashtag:42", event_txt)?, 1);
+        assert!(!manager.tags.contains("42"));
+        Ok(())
+    })
+    .wait()
+}
+
+#[test]
+fn manager_loads_the_unexpired_hashtags_kept_in_redis() -> TestResult {
+    let mut cfg = config::Redis::default();
+    cfg.tag_cache_key.0 = Some("flodgatt:tag_cache".to_string());
+    let mut manager = Manager::try_from(&cfg)?;
+    let hash = &mut manager.redis_conn.test_hash;
+    hash.insert("rust".to_string(), format!("1:{}", u64::max_value()));
+    hash.insert("go".to_string(), "2:1".to_string()); // expired in 1970
+    hash.insert("zig".to_string(), "3".to_string());
+
+    assert_eq!(manager.load_tag_cache()?, 1);
+    assert!(manager.tags.contains("rust"));
+    assert!(!manager.tags.contains("go") && !manager.tags.contains("zig"));
+    let kept: Vec<_> = manager.redis_conn.test_hash.keys().collect();
+    assert_eq!(kept, vec!["rust"]);
+    Ok(())
+}
+
+#[test]
+fn manager_hands_off_only_its_own_hold_on_legacy_keys() -> TestResult {
+    let mut cfg = config::Redis::default();
+    cfg.subscribed_key_style.0 = config::SubscribedKeyStyleInner::Legacy;
+    let mut manager = Manager::try_from(&cfg)?;
+    let subscription = Subscription {
+        timeline: Timeline::from_redis_text("public", &TagRegistry::default())?,
+        ..Subscription::default()
+    };
+    let (event_tx, _event_rx) = manager.channel();
+    manager.subscribe(&subscription, event_tx)?;
+
+    manager.hand_off()?;
+    let key_cmds = &manager.redis_conn.test_key_cmds;
+    let release = String::from_utf8_lossy(key_cmds.last().expect("a key command"));
+    assert!(release.contains("$26\r\nsubscribed:timeline:public\r\n$4\r\nSREM\r\n"));
+    Ok(())
+}
+
+#[test]
+fn manager_calls_hooks() -> TestResult {
+    use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+    let (connects, deliveries) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+    let (c, d) = (connects.clone(), deliveries.clone());
+
+    let mut manager = Manager::try_from(&config::Redis::default())?;
+    manager.set_hooks(
+        Hooks::new()
+            .on_client_connect(move |_| {
+                c.fetch_add(1, SeqCst);
+            })
+            .on_event_delivered(move |_, _, clients| {
+                d.fetch_add(clients, SeqCst);
+            }),
+    );
+    let subscription = Subscription {
+        timeline: Timeline::from_redis_text("public", &TagRegistry::default())?,
+        ..Subscription::default()
+    };
+    let mut clients = Vec::new();
+    for _ in 0..2 {
+        let (event_tx, event_rx) = manager.channel();
+        manager.subscribe(&subscription, event_tx)?;
+        clients.push(event_rx);
+    }
+
+    let event_txt = r#"{"event":"delete","payload":"1038647"}"#;
+    manager.inject("timeline:public", event_txt)?;
+    assert_eq!(connects.load(SeqCst), 2);
+    assert_eq!(deliveries.load(SeqCst), 2);
+    Ok(())
+}
+
+#[test]
+fn manager_keeps_the_latest_events_of_timelines_with_clients() -> TestResult {
+    let format = EventFormat::default();
+    let mut manager = Manager::try_from(&config::Redis::default())?;
+    let public = Timeline::from_redis_text("public", &TagRegistry::default())?;
+    assert_eq!(manager.history(public, None, &format), None);
+
+    manager.retain_history(2);
+    let subscription = Subscription {
+        timeline: public,
+        ..Subscription::default()
+    };
+    let (event_tx, event_rx) = manager.channel();
+    manager.subscribe(&subscription, event_tx)?;
+    for id in &["1", "2", "3"] {
+        let event_txt = json!({ "event": "delete", "payload": id }).to_string();
+        manager.inject("timeline:public", &event_txt)?;
+    }
+    let payloads = |limit| -> Vec<_> {
+        let history = manager.history(public, limit, &format).unwrap_or_default();
+        history
+            .into_iter()
+            .map(|e| e.message["payload"].clone())
+            .collect()
+    };
+    assert_eq!(payloads(None), vec![json!("2"), json!("3")]);
+    assert_eq!(payloads(Some(1)), vec![json!("3")]);
+
+    // Once the last client is gone, so is the history
+    drop(event_rx);
+    manager.send_pings()?;
+    assert_eq!(manager.history(public, None, &format), Some(Vec::new()));
+    Ok(())
+}
+
+/// How much resident memory may grow over a soak run before it counts as a leak
+const MAX_RSS_GROWTH: u64 = 8 * 1024 * 1024;
+const SOAK_BATCH: usize = 100;
+
+/// Resident memory (in bytes) and open file descriptors, on platforms with `/proc`
+fn process_usage() -> Option<(u64, usize)> {
+    let statm = fs::read_to_string("/proc/self/statm").ok()?;
+    let resident_pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    let open_fds = fs::read_dir("/proc/self/fd").ok()?.count();
+    Some((resident_pages * 4096, open_fds))
+}
+
+fn subscription_reply(command: &str, channel: &str) -> Vec<u8> {
+    format!(
+        "*3\r\n${}\r\n{}\r\n${}\r\n{}\r\n:1\r\n",
+        command.len(),
+        command,
+        channel.len(),
+        channel
+    )
+    .into_bytes()
+}
+
+/// Connect `cycles` clients in batches, delivering synthetic events and subscription replies
+/// the way Redis would, and disconnect each batch once it is full.
+fn run_soak_cycles(manager: &mut Manager, generator: &mut Generator, cycles: usize) -> TestResult {
+    let mut clients = Vec::with_capacity(SOAK_BATCH);
+    for cycle in 0..cycles {
+        let channel = match cycle % 4 {
+            0 => "public".to_string(),
+            1 => format!("{}", cycle % 5000),
+            2 => format!("list:{}", cycle % 500),
+            _ => format!("direct:{}", cycle % 50),
+        };
+        let subscription = Subscription {
+            timeline: Timeline::from_redis_text(&channel, &TagRegistry::default())?,
+            ..Subscription::default()
+        };
+        let (event_tx, event_rx) = manager.channel();
+        manager.subscribe(&subscription, event_tx)?;
+        clients.push(event_rx);
+
+        let channel = format!("timeline:{}", channel);
+        manager
+            .redis_conn
+            .add(&subscription_reply("subscribe", &channel));
+        let (_, event_txt) = generator.next_event();
+        manager
+            .redis_conn
+            .add(&pubsub_message(&channel, &event_txt));
+        manager.send_msgs()?;
+
+        if clients.len() == SOAK_BATCH || cycle + 1 == cycles {
+            clients.clear();
+            manager.send_pings()?;
+            for tl in manager.confirmed.clone() {
+                let channel = manager.redis_conn.channel_name(&tl).map_err(Error::from)?;
+                manager
+                    .redis_conn
+                    .add(&subscription_reply("unsubscribe", &channel));
+            }
+            manager.send_msgs()?;
+        }
+    }
+    Ok(())
+}
+
+/// Soak test for slow leaks; run with `cargo test manager_soak -- --ignored --nocapture`
+///
+/// Cycles through `SOAK_CYCLES` (default: 50,000) connect/subscribe/disconnect operations and
+/// checks that the subscription registry, open file descriptors, and resident memory all
+/// return to their baseline once every client has disconnected.
+#[test]
+#[ignore]
+fn manager_soak_returns_to_baseline() -> TestResult {
+    // `send_msgs` polls the client channels, which must happen inside a task
+    future::lazy(|| -> TestResult {
+        let cycles = std::env::var("SOAK_CYCLES").map_or(Ok(50_000), |n| n.parse())?;
+        let redis_cfg = config::Redis::default();
+        let mut manager = Manager::try_from(&redis_cfg)?;
+        let mut generator = Generator::from_cfg(&config::Deployment::default(), &redis_cfg);
+
+        // warm up the allocator and input buffer
+        run_soak_cycles(&mut manager, &mut generator, 1_000)?;
+        let baseline = process_usage();
+        run_soak_cycles(&mut manager, &mut generator, cycles)?;
+
+        assert!(manager.timelines.is_empty(), "{}", manager.list());
+        assert!(manager.confirmed.is_empty(), "{:?}", manager.confirmed);
+        if let (Some((base_rss, base_fds)), Some((rss, fds))) = (baseline, process_usage()) {
+            println!(
+                "After {} cycles: RSS {} -> {} KiB; open fds {} -> {}",
+                cycles,
+                base_rss / 1024,
+                rss / 1024,
+                base_fds,
+                fds
+            );
+            assert_eq!(fds, base_fds);
+            assert!(rss < base_rss + MAX_RSS_GROWTH);
+        }
+        Ok(())
+    })
+    .wait()
+}
+
+#[test]
+fn manager_skips_input_it_cannot_parse() -> TestResult {
+    future::lazy(|| -> TestResult {
+        let mut manager = Manager::try_from(&config::Redis::default())?;
+        let subscription = Subscription {
+            timeline: Timeline::from_redis_text("public", &TagRegistry::default())?,
+            ..Subscription::default()
+        };
+        let (event_tx, mut event_rx) = manager.channel();
+        manager.subscribe(&subscription, event_tx)?;
+
+        let resyncs = metrics::REDIS_RESYNCS.get();
+        manager.redis_conn.add(b"?corrupt\r\n");
+        manager.redis_conn.add(&input(1));
+        assert_eq!(manager.send_msgs()?, Async::Ready(()));
+        match event_rx.poll() {
+            Ok(Async::Ready(Some(event))) => assert_eq!(event, output(0)),
+            other => panic!("Expected the next message, got {:?}", other),
+        }
+        assert!(metrics::REDIS_RESYNCS.get() > resyncs);
+        Ok(())
+    })
+    .wait()
+}
+
+#[test]
+fn manager_drops_messages_that_outgrow_the_input_buffer() -> TestResult {
+    future::lazy(|| -> TestResult {
+        let mut cfg = config::Redis::default();
+        cfg.max_input_buffer.0 = Some(64 * 1024);
+        let mut manager = Manager::try_from(&cfg)?;
+        let subscription = Subscription {
+            timeline: Timeline::from_redis_text("public", &TagRegistry::default())?,
+            ..Subscription::default()
+        };
+        let (event_tx, mut event_rx) = manager.channel();
+        manager.subscribe(&subscription, event_tx)?;
+
+        let payload = "x".repeat(100 * 1024);
+        let huge = format!(
+            "*3\r\n$7\r\nmessage\r\n$15\r\ntimeline:public\r\n${}\r\n{}\r\n",
+            payload.len(),
+            payload
+        );
+        manager.redis_conn.add(huge.as_bytes());
+        assert_eq!(manager.send_msgs()?, Async::Ready(()));
+        assert!(matches!(event_rx.poll(), Ok(Async::NotReady)));
+        // The buffer gives back what it grew by, and the connection is replaced
+        assert_eq!(manager.redis_conn.input.len(), 4096 * 4);
+        assert!(manager.redis_conn.test_reconnected);
+
+        manager.redis_conn.add(&input(1));
+        manager.send_msgs()?;
+        match event_rx.poll() {
+            Ok(Async::Ready(Some(event))) => assert_eq!(event, output(0)),
+            other => panic!("Expected the next message, got {:?}", other),
+        }
+        Ok(())
+    })
+    .wait()
+}
+
+#[test]
+fn manager_publishes_the_changefeed_of_connections() -> TestResult {
+    use std::io::Read;
+    use std::os::unix::net::UnixStream;
+
+    fn next_change(
+        reader: &mut UnixStream,
+    ) -> std::result::Result<serde_json::Value, Box<dyn std::error::Error>> {
+        let mut fields = Vec::new();
+        for _ in 0..2 {
+            let mut len = [0; 4];
+            reader.read_exact(&mut len)?;
+            let mut field = vec![0; u32::from_be_bytes(len) as usize];
+            reader.read_exact(&mut field)?;
+            fields.push(String::from_utf8(field)?);
+        }
+        assert_eq!(fields[0], crate::response::LIFECYCLE_CHANNEL);
+        let mut change: serde_json::Value = serde_json::from_str(&fields[1])?;
+        assert!(change["ts"].is_number());
+        change.as_object_mut().map(|c| c.remove("ts"));
+        Ok(change)
+    }
+
+    let path = std::env::temp_dir().join(format!("flodgatt_lifecycle_{}", std::process::id()));
+    let path = path.to_str().expect("a UTF-8 temp dir");
+    let mut manager = Manager::try_from(&config::Redis::default())?;
+    manager.publish_to(IpcSink::bind(path)?.with_lifecycle(true));
+    let mut reader = UnixStream::connect(path)?;
+    reader.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let manager = manager.into_arc();
+
+    let connection = OpenConnection::new(&manager, "ws");
+    let subscription = Subscription {
+        timeline: Timeline::from_redis_text("public", &TagRegistry::default())?,
+        ..Subscription::default()
+    };
+    let mut receivers = Vec::new();
+    for _ in 0..2 {
+        let mut manager = manager.lock().expect("unpoisoned");
+        let (event_tx, event_rx) = manager.channel();
+        manager.subscribe(&subscription, event_tx)?;
+        receivers.push(event_rx);
+    }
+    receivers.pop();
+    manager.lock().expect("unpoisoned").send_pings()?;
+    let id = connection.id();
+    drop(connection);
+    fs::remove_file(path)?;
+
+    let changes = (0..5)
+        .map(|_| next_change(&mut reader))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    assert_eq!(
+        changes,
+        vec![
+            json!({"event": "open", "connection": id, "protocol": "ws"}),
+            json!({"event": "subscribe", "timeline": "timeline:public", "clients": 1}),
+            json!({"event": "subscribe", "timeline": "timeline:public", "clients": 2}),
+            json!({
+                "event": "unsubscribe",
+                "timeline": "timeline:public",
+                "left": 1,
+                "reason": "closed",
+                "clients": 1,
+            }),
+            json!({"event": "close", "connection": id, "protocol": "ws"}),
+        ]
+    );
+    Ok(())
+}
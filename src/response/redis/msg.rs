@@ -19,6 +19,10 @@
 //! Read that as: an array with three elements: the first element is a bulk string with
 //! three characters, the second is a bulk string with ten characters, and the third is a
 //! bulk string with 1,386 characters.
+//!
+//! Redis 6 and later speak RESP3 once a connection asks for it with `HELLO 3`.  Messages then
+//! arrive as push frames (`>3\r\n` in place of `*3\r\n`), which are read like arrays, as are
+//! sets (`~`); replies may also hold maps (`%`) and doubles (`,`).
 use self::RedisParseOutput::*;
 pub use err::RedisParseErr;
 use std::convert::{TryFrom, TryInto};
@@ -29,6 +33,7 @@ mod err;
 #[derive(Debug, Clone, PartialEq)]
 pub enum RedisParseOutput<'a> {
     Msg(RedisMsg<'a>),
+    SubscriptionReply(RedisSubscriptionReply<'a>),
     NonMsg(&'a str),
 }
 
@@ -41,13 +46,31 @@ pub struct RedisMsg<'a> {
 
 impl<'a> RedisMsg<'a> {
     pub(super) fn timeline_matching_ns(&self, namespace: &Option<String>) -> Option<&str> {
-        match namespace {
-            Some(ns) if self.timeline_txt.starts_with(ns) => {
-                Some(&self.timeline_txt[ns.len() + ":timeline:".len()..])
-            }
-            None => Some(&self.timeline_txt["timeline:".len()..]),
-            Some(_non_matching_ns) => None,
+        strip_namespace(self.timeline_txt, namespace)
+    }
+}
+
+/// Redis's reply to a `SUBSCRIBE` or `UNSUBSCRIBE` command, which confirms the (un)subscription
+#[derive(Debug, Clone, PartialEq)]
+pub struct RedisSubscriptionReply<'a> {
+    pub subscribed: bool,
+    pub timeline_txt: &'a str,
+    pub(crate) leftover_input: &'a str,
+}
+
+impl<'a> RedisSubscriptionReply<'a> {
+    pub(super) fn timeline_matching_ns(&self, namespace: &Option<String>) -> Option<&str> {
+        strip_namespace(self.timeline_txt, namespace)
+    }
+}
+
+fn strip_namespace<'a>(timeline_txt: &'a str, namespace: &Option<String>) -> Option<&'a str> {
+    match namespace {
+        Some(ns) if timeline_txt.starts_with(ns) => {
+            timeline_txt.get(ns.len() + ":timeline:".len()..)
         }
+        None => timeline_txt.get("timeline:".len()..),
+        Some(_non_matching_ns) => None,
     }
 }
 
@@ -63,6 +86,210 @@ impl<'a> TryFrom<&'a str> for RedisParseOutput<'a> {
     }
 }
 
+/// Parse Redis's reply to `HELLO 3`: the protocol version the connection now speaks, or `None`
+/// if Redis refused the command (as Redis before 6, which doesn't know it, does) and so the
+/// connection still speaks RESP2.
+pub(super) fn parse_hello(utf8: &str) -> Result<Option<usize>, RedisParseErr> {
+    if utf8.starts_with('-') {
+        return match utf8.contains("\r\n") {
+            true => Ok(None),
+            false => Err(Incomplete),
+        };
+    }
+    let fields = match utf8_to_redis_data(utf8)? {
+        (RedisData::RedisMap(fields), _leftover) => fields,
+        _ => Err(IncorrectRedisType)?,
+    };
+    fields
+        .into_iter()
+        .find_map(|field| match field {
+            (BulkString("proto"), Integer(proto)) => Some(Some(proto)),
+            _ => None,
+        })
+        .ok_or(MissingField)
+}
+
+/// Parse Redis's reply to `PUBSUB CHANNELS`, which is an array of channel names (or to
+/// `SMEMBERS`, which is a set of them with RESP3).
+pub(super) fn parse_channel_list(utf8: &str) -> Result<Vec<&str>, RedisParseErr> {
+    match utf8_to_redis_data(utf8)? {
+        (RedisData::RedisArray(channels), _leftover) => {
+            channels.into_iter().rev().map(TryInto::try_into).collect()
+        }
+        _ => Err(RedisParseErr::IncorrectRedisType),
+    }
+}
+
+/// Parse Redis's reply to `HGETALL`: the fields of a hash and their values, which Redis sends
+/// as an array that alternates them (or, with RESP3, as a map).
+pub(super) fn parse_hash(utf8: &str) -> Result<Vec<(&str, &str)>, RedisParseErr> {
+    let pairs = match utf8_to_redis_data(utf8)? {
+        (RedisData::RedisArray(fields), _leftover) => {
+            let mut fields = fields.into_iter().rev();
+            let mut pairs = Vec::new();
+            while let Some(field) = fields.next() {
+                pairs.push((field, fields.next().ok_or(MissingField)?));
+            }
+            pairs
+        }
+        (RedisData::RedisMap(pairs), _leftover) => pairs,
+        _ => Err(IncorrectRedisType)?,
+    };
+    pairs
+        .into_iter()
+        .map(|(field, value)| Ok((field.try_into()?, value.try_into()?)))
+        .collect()
+}
+
+/// The address (`host:port`) in a `-MOVED <slot> <host>:<port>` error reply, which Redis sends
+/// when a key belongs to another node.
+pub(super) fn parse_moved(utf8: &str) -> Option<&str> {
+    const MOVED: &str = "-MOVED ";
+    if !utf8.starts_with(MOVED) {
+        return None;
+    }
+    let line = utf8[MOVED.len()..].lines().next()?;
+    line.split(' ').nth(1).filter(|addr| !addr.is_empty())
+}
+
+/// Parse Redis's reply to `ROLE`: the address (`host:port`) of the master, if the server is a
+/// replica, or `None` if it is the master.
+pub(super) fn parse_role(utf8: &str) -> Result<Option<String>, RedisParseErr> {
+    let mut fields = match utf8_to_redis_data(utf8)? {
+        (RedisData::RedisArray(fields), _leftover) => fields.into_iter().rev(),
+        _ => Err(RedisParseErr::IncorrectRedisType)?,
+    };
+    let role: &str = fields.next().ok_or(MissingField)?.try_into()?;
+    match role {
+        "master" => Ok(None),
+        "slave" | "replica" => {
+            let host: &str = fields.next().ok_or(MissingField)?.try_into()?;
+            match fields.next().ok_or(MissingField)? {
+                Integer(port) => Ok(Some(format!("{}:{}", host, port))),
+                _ => Err(IncorrectRedisType),
+            }
+        }
+        _ => Err(IncorrectRedisType), // a Sentinel, which doesn't take writes at all
+    }
+}
+
+/// Parse Redis Cluster's reply to `CLUSTER SLOTS`: the first and last slot of each range, and
+/// the address (`host:port`) of the master that serves it (replicas are ignored).
+pub(super) fn parse_cluster_slots(utf8: &str) -> Result<Vec<(u16, u16, String)>, RedisParseErr> {
+    let ranges = match utf8_to_redis_data(utf8)? {
+        (RedisData::RedisArray(ranges), _leftover) => ranges,
+        _ => Err(IncorrectRedisType)?,
+    };
+    let slot = |field: Option<RedisData>| match field.ok_or(MissingField)? {
+        Integer(slot) => u16::try_from(slot).map_err(|_| IncorrectRedisType),
+        _ => Err(IncorrectRedisType),
+    };
+    ranges
+        .into_iter()
+        .rev()
+        .map(|range| {
+            let mut fields = match range {
+                RedisArray(fields) => fields.into_iter().rev(),
+                _ => Err(IncorrectRedisType)?,
+            };
+            let (first, last) = (slot(fields.next())?, slot(fields.next())?);
+            let mut master = match fields.next().ok_or(MissingField)? {
+                RedisArray(master) => master.into_iter().rev(),
+                _ => Err(IncorrectRedisType)?,
+            };
+            let host: &str = master.next().ok_or(MissingField)?.try_into()?;
+            match master.next().ok_or(MissingField)? {
+                Integer(port) => Ok((first, last, format!("{}:{}", host, port))),
+                _ => Err(IncorrectRedisType),
+            }
+        })
+        .collect()
+}
+
+/// Where the next reply plausibly starts in `input`, which starts with one that can't be
+/// parsed: the first line (after the first) that opens an array or push frame, as every message
+/// does.  Payloads can't contain such a line, since JSON escapes line breaks in strings.  Only
+/// the first `limit` bytes are searched.
+pub(super) fn next_reply(input: &[u8], limit: usize) -> Option<usize> {
+    let input = &input[..input.len().min(limit)];
+    (2..input.len()).find(|&i| {
+        &input[i - 2..i] == b"\r\n"
+            && (input[i] == b'*' || input[i] == b'>')
+            && input.get(i + 1).map_or(true, u8::is_ascii_digit)
+    })
+}
+
+/// The length of the complete reply at the start of `input`, or `None` if it is incomplete.
+/// Input read from several connections (the nodes of a cluster) is only merged at the ends of
+/// replies, so that no reply is split by another.
+pub(super) fn reply_len(input: &[u8]) -> Option<usize> {
+    let line_len = input.windows(2).position(|pair| pair == b"\r\n")?;
+    let after_line = line_len + "\r\n".len();
+    let count = || -> Option<i64> { str::from_utf8(input.get(1..line_len)?).ok()?.parse().ok() };
+    match (input[0], count()) {
+        (b'$', Some(len)) if len >= 0 => {
+            Some(after_line + len as usize + "\r\n".len()).filter(|&end| end <= input.len())
+        }
+        // A verbatim string or a bulk error, with RESP3
+        (b'=', Some(len)) | (b'!', Some(len)) => {
+            Some(after_line + len as usize + "\r\n".len()).filter(|&end| end <= input.len())
+        }
+        // An array, or a push frame or set (RESP3); a map (RESP3) holds a key and a value each
+        (b'*', Some(len)) | (b'>', Some(len)) | (b'~', Some(len)) | (b'%', Some(len)) => {
+            let elements = match input[0] {
+                b'%' => len.max(0) * 2,
+                _ => len.max(0),
+            };
+            (0..elements).try_fold(after_line, |end, _| Some(end + reply_len(&input[end..])?))
+        }
+        // A simple string, error, integer, double, boolean, or null (or a line the parser will
+        // reject)
+        _ => Some(after_line),
+    }
+}
+
+/// The elements of the complete array reply (or push frame) at the start of `input`, as the
+/// bytes Redis sent
+/// (which needn't be UTF-8), or `None` unless every element is a bulk string.  Bulk strings
+/// are measured in bytes, so a reply with invalid UTF-8 has to be split up before any of it
+/// can be replaced.
+pub(super) fn bulk_strings(input: &[u8]) -> Option<Vec<&[u8]>> {
+    let line = |input: &[u8], prefix| -> Option<(usize, usize)> {
+        let line_len = input.windows(2).position(|pair| pair == b"\r\n")?;
+        match input.first() {
+            Some(first) if *first == prefix => {
+                let n = str::from_utf8(&input[1..line_len]).ok()?.parse().ok()?;
+                Some((n, line_len + "\r\n".len()))
+            }
+            _ => None,
+        }
+    };
+    let (count, mut start) = line(input, b'*').or_else(|| line(input, b'>'))?;
+    let mut elements = Vec::with_capacity(count);
+    for _ in 0..count {
+        let (len, content_start) = line(&input[start..], b'$')?;
+        let content = input.get(start + content_start..start + content_start + len)?;
+        elements.push(content);
+        start += content_start + len + "\r\n".len();
+    }
+    Some(elements)
+}
+
+/// Parse a Sentinel's reply to `SENTINEL get-master-addr-by-name`: the host and port of the
+/// master, or `None` if the Sentinel doesn't monitor a master by that name.
+pub(super) fn parse_master_addr(utf8: &str) -> Result<Option<(String, u16)>, RedisParseErr> {
+    if utf8.starts_with("*-1\r\n") {
+        return Ok(None); // a null array
+    }
+    let mut fields = match utf8_to_redis_data(utf8)? {
+        (RedisData::RedisArray(fields), _leftover) => fields.into_iter().rev(),
+        _ => Err(RedisParseErr::IncorrectRedisType)?,
+    };
+    let host: &str = fields.next().ok_or(MissingField)?.try_into()?;
+    let port: &str = fields.next().ok_or(MissingField)?.try_into()?;
+    Ok(Some((host.to_string(), port.parse()?)))
+}
+
 #[derive(Debug, Clone, PartialEq)]
 struct RedisStructuredText<'a> {
     structured_txt: RedisData<'a>,
@@ -71,8 +298,12 @@ struct RedisStructuredText<'a> {
 #[derive(Debug, Clone, PartialEq)]
 enum RedisData<'a> {
     RedisArray(Vec<RedisData<'a>>),
+    /// The keys and values of a RESP3 map, in the order Redis sent them
+    RedisMap(Vec<(RedisData<'a>, RedisData<'a>)>),
     BulkString(&'a str),
+    SimpleString(&'a str),
     Integer(usize),
+    Double(f64),
     Uninitilized,
 }
 
@@ -86,8 +317,12 @@ fn utf8_to_redis_data<'a>(s: &'a str) -> Result<(RedisData, &'a str), RedisParse
     let (first_char, s) = s.split_at(1);
     match first_char {
         ":" => parse_redis_int(s),
+        "+" => parse_redis_simple_string(s),
         "$" => parse_redis_bulk_string(s),
-        "*" => parse_redis_array(s),
+        // RESP3 push frames and sets are read as arrays
+        "*" | ">" | "~" => parse_redis_array(s),
+        "%" => parse_redis_map(s),
+        "," => parse_redis_double(s),
         e => Err(InvalidLineStart(e.to_string())),
     }
 }
@@ -113,11 +348,39 @@ fn parse_redis_bulk_string<'a>(s: &'a str) -> RedisParser<(RedisData, &'a str)>
     Ok((BulkString(content), skip_line(rest, len)?))
 }
 
+fn parse_redis_simple_string<'a>(s: &'a str) -> RedisParser<(RedisData, &'a str)> {
+    let len = s.find("\r\n").ok_or(Incomplete)?;
+    Ok((SimpleString(&s[..len]), skip_line(s, len)?))
+}
+
 fn parse_redis_int<'a>(s: &'a str) -> RedisParser<(RedisData, &'a str)> {
     let (number, rest) = parse_number_at(s)?;
     Ok((Integer(number), rest))
 }
 
+/// Parse a RESP3 double, e.g. `,3.14\r\n` (or `,inf\r\n`, `,-inf\r\n`, or `,nan\r\n`).
+fn parse_redis_double<'a>(s: &'a str) -> RedisParser<(RedisData, &'a str)> {
+    let len = s.find("\r\n").ok_or(Incomplete)?;
+    let double = match &s[..len] {
+        "nan" => std::f64::NAN,
+        txt => txt.parse().map_err(|_| InvalidDouble(txt.to_string()))?,
+    };
+    Ok((Double(double), skip_line(s, len)?))
+}
+
+fn parse_redis_map<'a>(s: &'a str) -> RedisParser<(RedisData, &'a str)> {
+    let (number_of_pairs, mut rest) = parse_number_at(s)?;
+
+    let mut pairs = Vec::with_capacity(number_of_pairs);
+    for _ in 0..number_of_pairs {
+        let (key, after_key) = utf8_to_redis_data(rest)?;
+        let (value, after_value) = utf8_to_redis_data(after_key)?;
+        rest = after_value;
+        pairs.push((key, value));
+    }
+    Ok((RedisData::RedisMap(pairs), rest))
+}
+
 fn parse_redis_array<'a>(s: &'a str) -> RedisParser<(RedisData, &'a str)> {
     let (number_of_elements, mut rest) = parse_number_at(s)?;
 
@@ -153,17 +416,44 @@ impl<'a> TryFrom<RedisStructuredText<'a>> for RedisParseOutput<'a> {
                 // subscription statuses look like:
                 // $14\r\ntimeline:local\r\n
                 // :47\r\n
-                "subscribe" | "unsubscribe" => Ok(NonMsg(input.leftover_input)),
+                // (`ssubscribe`, `sunsubscribe`, and `smessage` are their sharded equivalents)
+                "subscribe" | "unsubscribe" | "ssubscribe" | "sunsubscribe" => {
+                    Ok(SubscriptionReply(RedisSubscriptionReply {
+                        subscribed: command == "subscribe" || command == "ssubscribe",
+                        timeline_txt: redis_strings.pop().ok_or(MissingField)?.try_into()?,
+                        leftover_input: input.leftover_input,
+                    }))
+                }
                 // Messages look like;
                 // $10\r\ntimeline:4\r\n
                 // $1386\r\n{\"event\":\"update\",\"payload\"...\"queued_at\":1569623342825}\r\n
-                "message" => Ok(Msg(RedisMsg {
+                "message" | "smessage" => Ok(Msg(RedisMsg {
                     timeline_txt: redis_strings.pop().ok_or(MissingField)?.try_into()?,
                     event_txt: redis_strings.pop().ok_or(MissingField)?.try_into()?,
                     leftover_input: input.leftover_input,
                 })),
+                // With `REDIS_PSUBSCRIBE`, messages name the pattern before the channel:
+                // $10\r\ntimeline:*\r\n
+                // $10\r\ntimeline:4\r\n
+                // $1386\r\n{\"event\":\"update\",\"payload\"...}\r\n
+                "pmessage" => {
+                    let _pattern = redis_strings.pop().ok_or(MissingField)?;
+                    Ok(Msg(RedisMsg {
+                        timeline_txt: redis_strings.pop().ok_or(MissingField)?.try_into()?,
+                        event_txt: redis_strings.pop().ok_or(MissingField)?.try_into()?,
+                        leftover_input: input.leftover_input,
+                    }))
+                }
+                // The pattern is (un)subscribed to on every (re)connection; no timeline waits
+                // for its confirmation
+                "psubscribe" | "punsubscribe" => Ok(NonMsg(input.leftover_input)),
+                // The reply to a `PING` sent to check an idle connection (`REDIS_PING_INTERVAL`)
+                "pong" => Ok(NonMsg(input.leftover_input)),
                 _cmd => Err(Incomplete),
             }
+        } else if input.structured_txt == SimpleString("PONG") {
+            // The same reply with RESP3, which isn't limited to pubsub replies while subscribed
+            Ok(NonMsg(input.leftover_input))
         } else {
             Err(IncorrectRedisType)
         }
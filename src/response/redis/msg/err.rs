@@ -4,6 +4,7 @@ use std::{error::Error, fmt};
 pub enum RedisParseErr {
     Incomplete,
     InvalidNumber(std::num::ParseIntError),
+    InvalidDouble(String),
     InvalidLineStart(String),
     InvalidLineEnd(usize, String),
     IncorrectRedisType,
@@ -22,7 +23,10 @@ impl fmt::Display for RedisParseErr {
                 "Redis indicated that an item would be a number, but it could not be parsed: {}",
                 parse_int_err
             ),
-
+            InvalidDouble(txt) => format!(
+                "Redis indicated that an item would be a double, but `{}` is not one",
+                txt
+            ),
             InvalidLineStart(line_start_char) => format!(
                 "A line from Redis started with `{}`, which is not a valid character to indicate \
                 the type of the Redis line.",
@@ -6,11 +6,29 @@ fn parse_redis_subscribe() -> Result<(), RedisParseErr> {
     let input = "*3\r\n$9\r\nsubscribe\r\n$15\r\ntimeline:public\r\n:1\r\n";
 
     let r_subscribe = match RedisParseOutput::try_from(input) {
-        Ok(NonMsg(leftover)) => leftover,
+        Ok(SubscriptionReply(reply)) => reply,
+        Ok(NonMsg(leftover)) => panic!("unexpectedly got a non-msg: {:?}", leftover),
         Ok(Msg(msg)) => panic!("unexpectedly got a msg: {:?}", msg),
         Err(e) => panic!("Error in parsing subscribe command: {}", e),
     };
-    assert!(r_subscribe.is_empty());
+    assert!(r_subscribe.leftover_input.is_empty());
+    assert!(r_subscribe.subscribed);
+    assert_eq!(r_subscribe.timeline_txt, "timeline:public");
+
+    Ok(())
+}
+
+#[test]
+fn parse_redis_unsubscribe() -> Result<(), RedisParseErr> {
+    let input = "*3\r\n$11\r\nunsubscribe\r\n$12\r\ntimeline:308\r\n:0\r\n";
+
+    match RedisParseOutput::try_from(input) {
+        Ok(SubscriptionReply(reply)) => {
+            assert!(!reply.subscribed);
+            assert_eq!(reply.timeline_txt, "timeline:308");
+        }
+        other => panic!("Expected an unsubscribe reply, got {:?}", other),
+    };
 
     Ok(())
 }
@@ -25,6 +43,10 @@ fn parse_redis_detects_non_newline() -> Result<(), RedisParseErr> {
             "Parsed an invalid msg as a non-msg.\nInput `{}` parsed to NonMsg({})",
             &input, leftover
         ),
+        Ok(SubscriptionReply(reply)) => panic!(
+            "Parsed an invalid msg as a subscription reply.\nInput `{}` parsed to {:?}",
+            &input, reply
+        ),
         Ok(Msg(msg)) => panic!(
             "Parsed an invalid msg as a msg.\nInput `{}` parsed to {:?}",
             &input, msg
@@ -45,6 +67,10 @@ fn parse_redis_msg() -> Result<(), RedisParseErr> {
             "Parsed a msg as a non-msg.\nInput `{}` parsed to NonMsg({:?})",
             &input, leftover
         ),
+        Ok(SubscriptionReply(reply)) => panic!(
+            "Parsed a msg as a subscription reply.\nInput `{}` parsed to {:?}",
+            &input, reply
+        ),
         Ok(Msg(msg)) => msg,
         Err(e) => panic!("Error in parsing subscribe command: {}", e),
     };
@@ -70,6 +96,10 @@ fn parse_long_redis_msg() -> Result<(), Box<dyn std::error::Error>> {
                 "Parsed a msg as a non-msg.\nInput `{}` parsed to NonMsg({:?})",
                 &input, leftover
             ),
+            Ok(SubscriptionReply(reply)) => panic!(
+                "Parsed a msg as a subscription reply.\nInput `{}` parsed to {:?}",
+                &input, reply
+            ),
             Ok(Msg(msg)) => msg,
             Err(e) => panic!("Error in parsing Redis input: {}", e),
         };
@@ -82,3 +112,240 @@ fn parse_long_redis_msg() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+#[test]
+fn parse_pubsub_channels_reply() -> Result<(), RedisParseErr> {
+    let input = "*2\r\n$15\r\ntimeline:public\r\n$12\r\ntimeline:308\r\n";
+    assert_eq!(
+        parse_channel_list(input)?,
+        vec!["timeline:public", "timeline:308"]
+    );
+    assert_eq!(parse_channel_list("*0\r\n")?, Vec::<&str>::new());
+    assert!(matches!(
+        parse_channel_list("*2\r\n$15\r\ntimeline:public\r\n"),
+        Err(RedisParseErr::Incomplete)
+    ));
+    Ok(())
+}
+
+#[test]
+fn parse_hash_reply() -> Result<(), RedisParseErr> {
+    let resp2 = "*4\r\n$4\r\nrust\r\n$12\r\n1:1700000000\r\n$2\r\ngo\r\n$12\r\n2:1700000000\r\n";
+    let resp3 = "%2\r\n$4\r\nrust\r\n$12\r\n1:1700000000\r\n$2\r\ngo\r\n$12\r\n2:1700000000\r\n";
+    for reply in &[resp2, resp3] {
+        assert_eq!(
+            parse_hash(reply)?,
+            vec![("rust", "1:1700000000"), ("go", "2:1700000000")]
+        );
+    }
+    assert_eq!(parse_hash("*0\r\n")?, Vec::<(&str, &str)>::new());
+    assert!(matches!(
+        parse_hash("*1\r\n$4\r\nrust\r\n"),
+        Err(RedisParseErr::MissingField)
+    ));
+    Ok(())
+}
+
+#[test]
+fn find_the_reply_after_a_corrupt_one() {
+    let input = b"?corrupt\r\n*3\r\n$7\r\nmessage\r\n";
+    assert_eq!(next_reply(input, 1024), Some(10));
+    assert_eq!(next_reply(b">3\r\n$7\r\nmessage\r\n>3\r\n", 1024), Some(17));
+    // A line that only starts with `*` isn't a reply
+    assert_eq!(next_reply(b"?\r\n*bold*\r\n", 1024), None);
+    assert_eq!(next_reply(input, 10), None);
+}
+
+#[test]
+fn parse_write_redirects() -> Result<(), RedisParseErr> {
+    assert_eq!(
+        parse_moved("-MOVED 3999 10.0.0.2:6381\r\n"),
+        Some("10.0.0.2:6381")
+    );
+    assert_eq!(
+        parse_moved("-READONLY You can't write against a read only replica.\r\n"),
+        None
+    );
+
+    let replica = "*5\r\n$5\r\nslave\r\n$8\r\n10.0.0.2\r\n:6379\r\n$9\r\nconnected\r\n:3167\r\n";
+    assert_eq!(parse_role(replica)?, Some("10.0.0.2:6379".to_string()));
+    let master = "*3\r\n$6\r\nmaster\r\n:3129659\r\n*0\r\n";
+    assert_eq!(parse_role(master)?, None);
+    assert!(matches!(
+        parse_role("*1\r\n$6\r\nma"),
+        Err(RedisParseErr::Incomplete)
+    ));
+    Ok(())
+}
+
+#[test]
+fn parse_sentinel_master_addr() -> Result<(), RedisParseErr> {
+    let master = "*2\r\n$8\r\n10.0.0.3\r\n$4\r\n6380\r\n";
+    assert_eq!(
+        parse_master_addr(master)?,
+        Some(("10.0.0.3".to_string(), 6380))
+    );
+    assert_eq!(parse_master_addr("*-1\r\n")?, None);
+    assert!(matches!(
+        parse_master_addr("*2\r\n$8\r\n10.0.0.3\r\n$4\r\n63"),
+        Err(RedisParseErr::Incomplete)
+    ));
+    Ok(())
+}
+
+#[test]
+fn parse_sharded_subscribe_and_msg() -> Result<(), RedisParseErr> {
+    let input = "*3\r\n$10\r\nssubscribe\r\n$15\r\ntimeline:public\r\n:1\r\n";
+    match RedisParseOutput::try_from(input)? {
+        SubscriptionReply(reply) => assert!(reply.subscribed),
+        other => panic!("Expected a subscribe reply, got {:?}", other),
+    };
+    let input = "*3\r\n$12\r\nsunsubscribe\r\n$15\r\ntimeline:public\r\n:0\r\n";
+    match RedisParseOutput::try_from(input)? {
+        SubscriptionReply(reply) => assert!(!reply.subscribed),
+        other => panic!("Expected an unsubscribe reply, got {:?}", other),
+    };
+    let input = "*3\r\n$8\r\nsmessage\r\n$15\r\ntimeline:public\r\n$2\r\n{}\r\n";
+    match RedisParseOutput::try_from(input)? {
+        Msg(msg) => assert_eq!((msg.timeline_txt, msg.event_txt), ("timeline:public", "{}")),
+        other => panic!("Expected a msg, got {:?}", other),
+    };
+    Ok(())
+}
+
+#[test]
+fn parse_pattern_subscribe_and_msg() -> Result<(), RedisParseErr> {
+    let input = "*3\r\n$10\r\npsubscribe\r\n$10\r\ntimeline:*\r\n:1\r\n*1\r\n";
+    match RedisParseOutput::try_from(input)? {
+        NonMsg(leftover) => assert_eq!(leftover, "*1\r\n"),
+        other => panic!("Expected a non-message, got {:?}", other),
+    };
+    let input =
+        "*4\r\n$8\r\npmessage\r\n$10\r\ntimeline:*\r\n$15\r\ntimeline:public\r\n$2\r\n{}\r\n";
+    match RedisParseOutput::try_from(input)? {
+        Msg(msg) => assert_eq!((msg.timeline_txt, msg.event_txt), ("timeline:public", "{}")),
+        other => panic!("Expected a msg, got {:?}", other),
+    };
+    Ok(())
+}
+
+#[test]
+fn parse_cluster_slots_reply() -> Result<(), RedisParseErr> {
+    let slots = "*2\r\n\
+                 *3\r\n:0\r\n:8191\r\n*3\r\n$8\r\n10.0.0.1\r\n:6379\r\n$2\r\nid\r\n\
+                 *4\r\n:8192\r\n:16383\r\n*3\r\n$8\r\n10.0.0.2\r\n:6380\r\n$2\r\nid\r\n\
+                 *3\r\n$8\r\n10.0.0.3\r\n:6379\r\n$2\r\nid\r\n";
+    assert_eq!(
+        parse_cluster_slots(slots)?,
+        vec![
+            (0, 8191, "10.0.0.1:6379".to_string()),
+            (8192, 16383, "10.0.0.2:6380".to_string()),
+        ]
+    );
+    assert!(matches!(
+        parse_cluster_slots("*1\r\n*3\r\n:0\r\n:70000\r\n*2\r\n$1\r\na\r\n:1\r\n"),
+        Err(RedisParseErr::IncorrectRedisType)
+    ));
+    Ok(())
+}
+
+#[test]
+fn measure_complete_replies() {
+    let msg = "*3\r\n$8\r\nsmessage\r\n$15\r\ntimeline:public\r\n$2\r\n{}\r\n";
+    assert_eq!(reply_len(msg.as_bytes()), Some(msg.len()));
+    assert_eq!(reply_len(&msg.as_bytes()[..msg.len() - 1]), None);
+    assert_eq!(reply_len(b"+OK\r\n:1\r\n"), Some(5));
+    assert_eq!(reply_len(b"-MOVED 3999 10.0.0.2:6380\r\n"), Some(27));
+    // A bulk string is measured by its length, not by the line breaks it holds
+    assert_eq!(reply_len(b"$4\r\na\r\nb\r\n"), Some(10));
+    assert_eq!(reply_len(b"$4\r\na\r\n"), None);
+}
+
+#[test]
+fn split_replies_that_are_not_utf8_into_bulk_strings() {
+    let msg = b"*3\r\n$7\r\nmessage\r\n$15\r\ntimeline:public\r\n$5\r\ncaf\xe9\n\r\n";
+    assert_eq!(
+        bulk_strings(msg),
+        Some(vec![&b"message"[..], b"timeline:public", b"caf\xe9\n"])
+    );
+    assert_eq!(bulk_strings(&msg[..msg.len() - 4]), None);
+    assert_eq!(bulk_strings(b"*2\r\n$1\r\na\r\n:1\r\n"), None);
+}
+
+#[test]
+fn parse_resp3_push_frames_as_messages() -> Result<(), RedisParseErr> {
+    let input = ">3\r\n$9\r\nsubscribe\r\n$15\r\ntimeline:public\r\n:1\r\n";
+    match RedisParseOutput::try_from(input)? {
+        SubscriptionReply(reply) => assert_eq!(reply.timeline_txt, "timeline:public"),
+        other => panic!("Expected a subscribe reply, got {:?}", other),
+    };
+    let input = ">3\r\n$7\r\nmessage\r\n$15\r\ntimeline:public\r\n$2\r\n{}\r\n";
+    match RedisParseOutput::try_from(input)? {
+        Msg(msg) => assert_eq!((msg.timeline_txt, msg.event_txt), ("timeline:public", "{}")),
+        other => panic!("Expected a msg, got {:?}", other),
+    };
+    assert_eq!(reply_len(input.as_bytes()), Some(input.len()));
+    assert_eq!(
+        bulk_strings(input.as_bytes()),
+        Some(vec![&b"message"[..], b"timeline:public", b"{}"])
+    );
+    // `SMEMBERS` replies with a set
+    assert_eq!(
+        parse_channel_list("~2\r\n$1\r\na\r\n$1\r\nb\r\n")?,
+        vec!["a", "b"]
+    );
+    Ok(())
+}
+
+#[test]
+fn parse_hello_reply() -> Result<(), RedisParseErr> {
+    let hello = "%7\r\n$6\r\nserver\r\n$5\r\nredis\r\n$7\r\nversion\r\n$5\r\n7.2.4\r\n\
+                 $5\r\nproto\r\n:3\r\n$2\r\nid\r\n:5\r\n$4\r\nmode\r\n$10\r\nstandalone\r\n\
+                 $4\r\nrole\r\n$6\r\nmaster\r\n$7\r\nmodules\r\n*0\r\n";
+    assert_eq!(parse_hello(hello)?, Some(3));
+    assert_eq!(reply_len(hello.as_bytes()), Some(hello.len()));
+    assert!(matches!(
+        parse_hello(&hello[..hello.len() - 3]),
+        Err(RedisParseErr::Incomplete)
+    ));
+    // Redis before 6
+    assert_eq!(parse_hello("-ERR unknown command 'HELLO'\r\n")?, None);
+    assert!(matches!(
+        parse_hello("-ERR unknown"),
+        Err(RedisParseErr::Incomplete)
+    ));
+    Ok(())
+}
+
+#[test]
+fn parse_resp3_doubles() -> Result<(), RedisParseErr> {
+    assert_eq!(
+        utf8_to_redis_data(",3.5\r\n:1\r\n")?,
+        (Double(3.5), ":1\r\n")
+    );
+    assert_eq!(
+        utf8_to_redis_data(",-inf\r\n")?,
+        (Double(std::f64::NEG_INFINITY), "")
+    );
+    assert!(matches!(utf8_to_redis_data(",3.5\r"), Err(Incomplete)));
+    assert!(matches!(
+        utf8_to_redis_data(",three\r\n"),
+        Err(InvalidDouble(_))
+    ));
+    Ok(())
+}
+
+#[test]
+fn parse_pong_replies() -> Result<(), RedisParseErr> {
+    for input in &["*2\r\n$4\r\npong\r\n$0\r\n\r\n:1\r\n", "+PONG\r\n:1\r\n"] {
+        match RedisParseOutput::try_from(*input)? {
+            NonMsg(leftover) => assert_eq!(leftover, ":1\r\n"),
+            other => panic!("Expected a non-message, got {:?}", other),
+        };
+    }
+    assert!(matches!(
+        RedisParseOutput::try_from("+PONG\r"),
+        Err(RedisParseErr::Incomplete)
+    ));
+    Ok(())
+}
@@ -0,0 +1,121 @@
+//! The keys that tell Mastodon which channels have subscribers.
+//!
+//! Mastodon stops publishing to a channel when it thinks no one is subscribed to it, so the
+//! streaming server has to set a key for each channel it subscribes to.  The format of these
+//! keys has changed across Mastodon versions (see `SubscribedKeyStyleInner`).
+//!
+//! Several instances may subscribe to the same channel.  Legacy keys are never cleared by
+//! expiring, so each instance claims the keys it needs in a shared set of holders (the
+//! `<key>:holders` set, whose size counts the instances that need the key), and a key is only
+//! cleared once no instance holds it.  Otherwise, one instance losing its last subscriber
+//! (e.g., while it drains for a rolling restart) would silence the channel for the others.
+use crate::config::{Redis, SubscribedKeyStyleInner as Style};
+
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Adds (`SADD`) or removes (`SREM`, in `ARGV[1]`) the holder `ARGV[2]` for each legacy key in
+/// `KEYS`, and then sets the key to whether any holder is left, in one atomic step
+const HOLD_SCRIPT: &str = "for _, key in ipairs(KEYS) do \
+                             local holders = key .. ':holders' \
+                             redis.call(ARGV[1], holders, ARGV[2]) \
+                             redis.call('SET', key, math.min(redis.call('SCARD', holders), 1)) \
+                           end \
+                           return redis.status_reply('OK')";
+
+#[derive(Debug, Clone)]
+pub(super) struct SubscribedKeys {
+    style: Style,
+    prefix: String,
+    ttl: Duration,
+    namespace: Option<String>,
+    /// Identifies this process in the sets of holders
+    pub(super) holder: String,
+}
+
+impl SubscribedKeys {
+    pub(super) fn from_cfg(redis_cfg: &Redis) -> Self {
+        Self {
+            style: *redis_cfg.subscribed_key_style,
+            prefix: redis_cfg.subscribed_key_prefix.clone().0,
+            ttl: *redis_cfg.subscribed_key_ttl,
+            namespace: redis_cfg.namespace.clone().0,
+            holder: holder_id(),
+        }
+    }
+
+    /// How often the keys for channels that still have subscribers need to be set again (if
+    /// they expire at all).  Like Mastodon, we refresh them three times per TTL.
+    pub(super) fn refresh_interval(&self) -> Option<Duration> {
+        match self.style {
+            Style::Legacy => None,
+            Style::Expiring => Some(self.ttl / 3),
+        }
+    }
+
+    /// The command that marks `channels` (which include the namespace, if any) as subscribed
+    /// or unsubscribed by this instance, along with the number of `+OK` replies Redis sends in
+    /// response.
+    pub(super) fn cmd(&self, subscribed: bool, channels: &[String]) -> Option<(Vec<u8>, usize)> {
+        if channels.is_empty() {
+            return None;
+        }
+        match self.style {
+            Style::Legacy => {
+                let op = if subscribed { "SADD" } else { "SREM" };
+                let mut cmd = format!("*{}\r\n$4\r\nEVAL\r\n", 5 + channels.len());
+                cmd.push_str(&bulk_string(HOLD_SCRIPT));
+                cmd.push_str(&bulk_string(&channels.len().to_string()));
+                for channel in channels {
+                    cmd.push_str(&bulk_string(&self.key(channel)));
+                }
+                cmd.push_str(&bulk_string(op));
+                cmd.push_str(&bulk_string(&self.holder));
+                Some((cmd.into_bytes(), 1))
+            }
+            // Expiring keys are left to expire once no one is subscribed
+            Style::Expiring if !subscribed => None,
+            Style::Expiring => {
+                let ttl = self.ttl.as_secs().to_string();
+                let cmd: String = channels
+                    .iter()
+                    .map(|channel| {
+                        [
+                            "*5\r\n$3\r\nSET\r\n",
+                            &bulk_string(&self.key(channel)),
+                            "$1\r\n1\r\n$2\r\nEX\r\n",
+                            &bulk_string(&ttl),
+                        ]
+                        .concat()
+                    })
+                    .collect();
+                Some((cmd.into_bytes(), channels.len()))
+            }
+        }
+    }
+
+    /// Mastodon's legacy keys put the prefix before the whole channel name; current versions
+    /// put the namespace first (`<namespace>:subscribed:timeline:…`).
+    fn key(&self, channel: &str) -> String {
+        match (self.style, &self.namespace) {
+            (Style::Expiring, Some(ns)) if channel.starts_with(&format!("{}:", ns)) => {
+                format!("{}:{}{}", ns, self.prefix, &channel[ns.len() + 1..])
+            }
+            _ => format!("{}{}", self.prefix, channel),
+        }
+    }
+}
+
+/// An ID for this process that another process is very unlikely to have, even on another host:
+/// its PID and the time it started
+fn holder_id() -> String {
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    format!("{}-{:x}", process::id(), started)
+}
+
+pub(super) fn bulk_string(s: &str) -> String {
+    format!("${}\r\n{}\r\n", s.len(), s)
+}
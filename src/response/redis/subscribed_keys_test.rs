@@ -0,0 +1,82 @@
+use super::SubscribedKeys;
+use crate::config::{self, SubscribedKeyStyleInner as Style};
+
+use std::time::Duration;
+
+fn keys(style: Style, namespace: Option<&str>) -> SubscribedKeys {
+    let mut cfg = config::Redis::default();
+    cfg.subscribed_key_style.0 = style;
+    cfg.namespace.0 = namespace.map(String::from);
+    SubscribedKeys::from_cfg(&cfg)
+}
+
+fn cmd_txt(keys: &SubscribedKeys, subscribed: bool, channels: &[&str]) -> Option<(String, usize)> {
+    let channels: Vec<String> = channels.iter().map(|c| c.to_string()).collect();
+    keys.cmd(subscribed, &channels)
+        .map(|(cmd, replies)| (String::from_utf8_lossy(&cmd).to_string(), replies))
+}
+
+#[test]
+fn legacy_keys_are_held_by_each_instance_without_expiry() {
+    let keys = keys(Style::Legacy, Some("ns"));
+    assert_eq!(keys.refresh_interval(), None);
+    let holder = format!("${}\r\n{}\r\n", keys.holder.len(), keys.holder);
+
+    let (claim, replies) =
+        cmd_txt(&keys, true, &["ns:timeline:public", "ns:timeline:1"]).expect("a command");
+    assert!(claim.starts_with("*7\r\n$4\r\nEVAL\r\n"));
+    assert!(claim.ends_with(
+        &[
+            "$1\r\n2\r\n",
+            "$29\r\nsubscribed:ns:timeline:public\r\n$24\r\nsubscribed:ns:timeline:1\r\n",
+            "$4\r\nSADD\r\n",
+            &holder,
+        ]
+        .concat()
+    ));
+    assert_eq!(replies, 1);
+
+    // Unsubscribing only removes this instance from the holders; the key is cleared if no
+    // other instance holds it
+    let (release, _) = cmd_txt(&keys, false, &["ns:timeline:1"]).expect("a command");
+    assert!(release.ends_with(
+        &[
+            "$1\r\n1\r\n$24\r\nsubscribed:ns:timeline:1\r\n$4\r\nSREM\r\n",
+            &holder,
+        ]
+        .concat()
+    ));
+}
+
+#[test]
+fn expiring_keys_are_namespaced_and_refreshed() {
+    let keys = keys(Style::Expiring, Some("ns"));
+    assert_eq!(keys.refresh_interval(), Some(Duration::from_secs(6 * 60)));
+    assert_eq!(
+        cmd_txt(&keys, true, &["ns:timeline:public", "ns:timeline:1"]),
+        Some((
+            "*5\r\n$3\r\nSET\r\n$29\r\nns:subscribed:timeline:public\r\n\
+             $1\r\n1\r\n$2\r\nEX\r\n$4\r\n1080\r\n\
+             *5\r\n$3\r\nSET\r\n$24\r\nns:subscribed:timeline:1\r\n\
+             $1\r\n1\r\n$2\r\nEX\r\n$4\r\n1080\r\n"
+                .to_string(),
+            2
+        ))
+    );
+    assert_eq!(cmd_txt(&keys, false, &["ns:timeline:1"]), None);
+}
+
+#[test]
+fn expiring_keys_without_namespace() {
+    let keys = keys(Style::Expiring, None);
+    assert_eq!(
+        cmd_txt(&keys, true, &["timeline:1"]),
+        Some((
+            "*5\r\n$3\r\nSET\r\n$21\r\nsubscribed:timeline:1\r\n\
+             $1\r\n1\r\n$2\r\nEX\r\n$4\r\n1080\r\n"
+                .to_string(),
+            1
+        ))
+    );
+    assert_eq!(cmd_txt(&keys, true, &[]), None);
+}
@@ -1,7 +1,13 @@
+pub use first_byte::FirstByte;
 pub use sse::Sse;
-pub use ws::Ws;
+pub use ws::{Multiplex, Ws};
 
-pub(self) use super::{Event, Payload};
+pub(self) use super::redis::{EventRx, PING_INTERVAL};
+pub(self) use super::{Event, EventFormat, OpenConnection, Payload, RedisManager};
 
+mod first_byte;
+#[cfg(test)]
+mod first_byte_test;
+pub(crate) mod framing;
 mod sse;
 mod ws;
@@ -0,0 +1,64 @@
+//! The bound on how long a new connection goes without being sent anything
+//!
+//! Some proxies close an upgraded connection that stays silent, and a WebSocket has nothing to
+//! send until its first event or heartbeat.  `FirstByte` notes when a connection was accepted
+//! and comes due once `FIRST_BYTE_TIMEOUT` passes with nothing sent, so that the stream can
+//! send a heartbeat early.  A first byte sent later than that (e.g., when the server is too
+//! loaded to poll the stream in time) is counted in `metrics::CLIENT_FIRST_BYTES_LATE`.
+use crate::metrics;
+
+use futures::{Async, Future};
+use std::time::{Duration, Instant};
+use tokio::timer::Delay;
+
+pub struct FirstByte {
+    accepted: Instant,
+    timeout: Option<Duration>,
+    deadline: Option<Delay>,
+    sent: bool,
+}
+
+impl FirstByte {
+    /// Start the clock for a connection accepted now (with no deadline if `timeout` is `None`)
+    pub fn new(timeout: Option<Duration>) -> Self {
+        let accepted = Instant::now();
+        Self {
+            accepted,
+            timeout,
+            deadline: timeout.map(|timeout| Delay::new(accepted + timeout)),
+            sent: false,
+        }
+    }
+
+    /// Whether the timeout has passed with nothing sent, in which case the stream should send
+    /// a heartbeat now.  Until then, the current task is woken when it does.
+    pub(crate) fn poll_due(&mut self) -> bool {
+        let due = match self.deadline.as_mut().map(Delay::poll) {
+            Some(Ok(Async::Ready(()))) => true,
+            Some(Ok(Async::NotReady)) | None => false,
+            Some(Err(e)) => {
+                tracing::warn!("The first byte timer failed: {}", e);
+                true
+            }
+        };
+        if due {
+            self.deadline = None;
+        }
+        due
+    }
+
+    /// Record that the stream sent something (only the first call has any effect)
+    pub(crate) fn sent(&mut self) {
+        if self.sent {
+            return;
+        }
+        self.sent = true;
+        self.deadline = None;
+        let elapsed = self.accepted.elapsed();
+        metrics::CLIENT_FIRST_BYTE.observe(elapsed);
+        if self.timeout.map_or(false, |timeout| elapsed > timeout) {
+            tracing::info!("Sent a connection its first byte after {:?}", elapsed);
+            metrics::CLIENT_FIRST_BYTES_LATE.inc();
+        }
+    }
+}
@@ -0,0 +1,41 @@
+use super::FirstByte;
+use crate::metrics;
+
+use futures::{future, Async};
+use std::time::{Duration, Instant};
+use tokio::runtime::current_thread::Runtime;
+
+type TestResult = std::result::Result<(), Box<dyn std::error::Error>>;
+
+#[test]
+fn a_silent_connection_is_due_a_heartbeat_after_the_timeout() -> TestResult {
+    let (timeout, start) = (Duration::from_millis(20), Instant::now());
+    let mut first_byte = FirstByte::new(Some(timeout));
+
+    Runtime::new()?
+        .block_on(future::poll_fn(|| match first_byte.poll_due() {
+            true => Ok::<_, ()>(Async::Ready(())),
+            false => Ok(Async::NotReady),
+        }))
+        .map_err(|()| "the timer failed")?;
+    assert!(start.elapsed() >= timeout);
+
+    // Sent once it came due, the heartbeat is late
+    let late = metrics::CLIENT_FIRST_BYTES_LATE.get();
+    first_byte.sent();
+    first_byte.sent();
+    assert_eq!(metrics::CLIENT_FIRST_BYTES_LATE.get(), late + 1);
+    assert!(!first_byte.poll_due());
+    Ok(())
+}
+
+#[test]
+fn a_connection_sent_something_in_time_is_not_due_a_heartbeat() {
+    let mut first_byte = FirstByte::new(Some(Duration::from_secs(60)));
+    first_byte.sent();
+    assert!(!first_byte.poll_due());
+
+    let mut unbounded = FirstByte::new(None);
+    assert!(!unbounded.poll_due());
+    unbounded.sent();
+}
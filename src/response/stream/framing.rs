@@ -0,0 +1,49 @@
+//! The exact bytes Flodgatt writes to a client, which follow Mastodon's Node streaming server
+//! down to the whitespace.  Some clients match the stream with regular expressions rather than
+//! parsing it, and break on differences that a conforming parser would ignore.
+//!
+//! A Server Sent Event stream opens with the comment `:)`, sends `:thump` as a heartbeat, and
+//! frames each event as `event: <name>`, a `data: <line>` for each line of the payload, an
+//! optional `id: <id>`, and a blank line.  A WebSocket frame is the JSON object
+//! `{"stream":[..],"event":"..","payload":".."}` with its fields in that order and `stream`
+//! only present on a multiplexed connection (see `Event::to_json_string_on`).
+use super::{Event, EventFormat};
+
+/// Sent as soon as a Server Sent Event stream opens
+pub(crate) const SSE_OPENED: &str = ":)\n";
+/// Sent in place of a `Ping`, to keep the connection (and any proxy in front of it) open
+pub(crate) const SSE_HEARTBEAT: &str = ":thump\n";
+
+/// The Server Sent Event frame for `event` (a heartbeat for a `Ping`), followed by the frame
+/// for its duplicate, if it has one (see `Event::duplicate_format`)
+pub(crate) fn sse(event: &Event, format: &EventFormat) -> String {
+    let mut frame = match event.to_sse_fields(format) {
+        Some((name, data)) => sse_event(&name, &data, None),
+        None => return SSE_HEARTBEAT.to_string(),
+    };
+    if let Some((name, data)) = event
+        .duplicate_format(format)
+        .and_then(|duplicate| event.to_sse_fields(&duplicate))
+    {
+        frame.push_str(&sse_event(&name, &data, None));
+    }
+    frame
+}
+
+/// A Server Sent Event with the given fields.  `data` is split over as many `data:` lines as
+/// it has lines, since a newline would otherwise end the field.
+pub(crate) fn sse_event(event: &str, data: &str, id: Option<&str>) -> String {
+    let mut frame = format!("event: {}\n", event);
+    for line in data.split('\n') {
+        frame.push_str("data: ");
+        frame.push_str(line.trim_end_matches('\r'));
+        frame.push('\n');
+    }
+    if let Some(id) = id {
+        frame.push_str("id: ");
+        frame.push_str(id);
+        frame.push('\n');
+    }
+    frame.push('\n');
+    frame
+}
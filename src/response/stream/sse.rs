@@ -1,37 +1,78 @@
-use super::{Event, Payload};
-use crate::request::Subscription;
+use super::{
+    framing, Event, EventFormat, EventRx, FirstByte, OpenConnection, Payload, PING_INTERVAL,
+};
+use crate::request::{Closing, Subscription};
 
-use futures::stream::Stream;
+use futures::stream::{self, Stream};
+use hyper::Body;
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::sync::mpsc::Receiver;
+use tracing::Span;
+use tracing_futures::Instrument;
+use warp::http::header::{HeaderValue, CACHE_CONTROL, CONTENT_TYPE};
+use warp::http::Response;
 use warp::reply::Reply;
-use warp::sse::Sse as WarpSse;
 
-type EventRx = Receiver<Arc<Event>>;
-
-pub struct Sse(Subscription);
+pub struct Sse(Subscription, EventFormat);
 
 impl Sse {
-    pub fn new(subscription: Subscription) -> Self {
-        Self(subscription)
+    pub fn new(subscription: Subscription, format: &EventFormat) -> Self {
+        let format = format.for_subscription(&subscription);
+        Self(subscription, format)
     }
 
-    pub fn send_events(self, sse: WarpSse, event_rx: EventRx) -> impl Reply {
-        let event_stream = event_rx.filter_map(move |event| {
-            match (event.update_payload(), event.dyn_update_payload()) {
-                (Some(update), _) if self.update_not_filtered(update) => event.to_warp_reply(),
-                (_, Some(update)) if self.update_not_filtered(update) => event.to_warp_reply(),
-                (_, _) => event.to_warp_reply(), // send all non-updates
-            }
+    /// Stream the events on `event_rx` to the client, framed as the Node streaming server
+    /// frames them (see `framing`).  The opening comment is its first byte, so `first_byte`
+    /// only records when that goes out.  What is logged while streaming is in the client's
+    /// `span`, and the `connection` is closed when the stream ends.
+    pub fn send_events(
+        self,
+        event_rx: EventRx,
+        mut first_byte: FirstByte,
+        span: Span,
+        connection: OpenConnection,
+    ) -> impl Reply {
+        let frames = self.frames(event_rx).inspect(move |_| {
+            let _open = &connection;
+            first_byte.sent()
         });
+        let frames = frames.instrument(span);
+        let mut response = Response::new(Body::wrap_stream(frames));
+        let headers = response.headers_mut();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/event-stream"));
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+        response
+    }
 
-        sse.reply(
-            warp::sse::keep_alive()
-                .interval(Duration::from_secs(30))
-                .text("thump".to_string())
-                .stream(event_stream),
-        )
+    /// The text sent to the client: the opening comment, then a frame for each event that
+    /// isn't filtered out (and a heartbeat for each `Ping`), starting with the `connected`
+    /// event if the format sends one.  When the server ends the stream (e.g., as it shuts
+    /// down), it first sends an `error` event saying why (see `Closing`).
+    pub(crate) fn frames<S>(self, events: S) -> impl Stream<Item = String, Error = S::Error>
+    where
+        S: Stream<Item = Arc<Event>>,
+    {
+        let hello = match self.1.sends_hello() {
+            true => Some(Arc::new(Event::hello(self.0.stream_name(), PING_INTERVAL))),
+            false => None,
+        };
+        let events = stream::iter_ok(hello).chain(events);
+        let frames = events.filter_map(move |event| {
+            if let Event::Disconnect(code) = *event {
+                let closing = serde_json::to_string(&Closing::from_close_code(code));
+                return Some(framing::sse_event("error", &closing.ok()?, None));
+            }
+            let sendable = match (event.update_payload(), event.dyn_update_payload()) {
+                _ if !event.is_wanted(&self.0.event_types) => false,
+                (Some(update), _) => self.update_not_filtered(update),
+                (_, Some(update)) => self.update_not_filtered(update),
+                (_, _) => true, // send all non-updates
+            };
+            match sendable {
+                true => Some(framing::sse(&event, &self.1)),
+                false => None,
+            }
+        });
+        stream::once(Ok(framing::SSE_OPENED.to_string())).chain(frames)
     }
 
     fn update_not_filtered(&self, update: &impl Payload) -> bool {
@@ -1,76 +1,316 @@
-use super::{Event, Payload};
-use crate::request::Subscription;
+use super::{
+    Event, EventFormat, EventRx, FirstByte, OpenConnection, Payload, RedisManager, PING_INTERVAL,
+};
+use crate::request::{Closing, Frame, FrameKind, Multiplexer, Refusal, Subscription};
 
 use futures::future::Future;
-use futures::stream::Stream;
-use std::sync::Arc;
-use tokio::sync::mpsc::Receiver;
+use futures::stream::{SplitStream, Stream};
+use futures::{Async, Poll};
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use tracing::Span;
+use tracing_futures::Instrument;
 use warp::ws::{Message, WebSocket};
 
-type EventRx = Receiver<Arc<Event>>;
+pub struct Ws(Subscription, EventFormat);
 
-pub struct Ws(Subscription);
+/// The streams a client added to its connection after connecting (see `Multiplexer`)
+pub struct Multiplex {
+    pub manager: Arc<Mutex<RedisManager>>,
+    pub multiplexer: Multiplexer,
+}
+
+/// A stream added with a `subscribe` message
+struct Added {
+    name: Vec<String>,
+    subscription: Subscription,
+    format: EventFormat,
+    event_rx: EventRx,
+}
 
 impl Ws {
-    pub fn new(subscription: Subscription) -> Self {
-        Self(subscription)
+    pub fn new(subscription: Subscription, format: &EventFormat) -> Self {
+        let format = format.for_subscription(&subscription);
+        Self(subscription, format)
     }
 
+    /// Stream the events on `event_rx` (and the replies to the client's messages) to `ws`, in
+    /// the client's `span`.  The `connection` is closed when the WebSocket is.
     pub fn send_to(
-        mut self,
+        self,
         ws: WebSocket,
         event_rx: EventRx,
+        multiplex: Multiplex,
+        first_byte: FirstByte,
+        span: Span,
+        connection: OpenConnection,
     ) -> impl Future<Item = (), Error = ()> {
-        let (transmit_to_ws, _receive_from_ws) = ws.split();
-        event_rx
-            .filter_map(move |event| {
-                if matches!(*event, Event::Ping) {
-                    Some(Message::text(&event.to_json_string()))
-                } else {
-                    match (event.update_payload(), event.dyn_update_payload()) {
-                        (Some(update), _) if !self.filtered(update) => {
-                            Some(Message::text(&event.to_json_string()))
-                        }
-                        (None, None) => Some(Message::text(&event.to_json_string())), // send all non-updates
-                        (_, Some(dyn_update)) if !self.filtered(dyn_update) => {
-                            Some(Message::text(&event.to_json_string()))
-                        }
-                        _ => None,
+        let (transmit_to_ws, receive_from_ws) = ws.split();
+        let next = match self.1.sends_hello() {
+            true => Some(Event::hello(self.0.stream_name(), PING_INTERVAL))
+                .filter(|hello| hello.is_wanted(&self.0.event_types))
+                .map(|hello| Message::text(hello.to_json_string(&self.1))),
+            false => None,
+        };
+        Connection {
+            ws: self,
+            event_rx,
+            client_msgs: receive_from_ws,
+            added: Vec::new(),
+            unsubscribed: false,
+            multiplex,
+            next,
+            first_byte,
+            _connection: connection,
+        }
+        .forward(transmit_to_ws)
+        .map(|_r| ())
+        // ignore errors that indicate normal disconnects.  TODO - once we upgrade our
+        // Warp version, we should stop matching on text, which is fragile.
+        .map_err(|e| match e.to_string().as_ref() {
+            "IO error: Broken pipe (os error 32)"
+            | "IO error: Connection reset by peer (os error 104)" => (),
+            e => tracing::warn!("WebSocket send error: {}", e),
+        })
+        .instrument(span)
+    }
+}
+
+/// The messages for one WebSocket: the events for the stream it connected to and any streams
+/// its client added, and the replies to the client's `subscribe` messages.
+struct Connection {
+    ws: Ws,
+    event_rx: EventRx,
+    client_msgs: SplitStream<WebSocket>,
+    added: Vec<Added>,
+    /// Whether the client unsubscribed from the stream it connected to.  Its events are still
+    /// received (for their heartbeats) but not sent.
+    unsubscribed: bool,
+    multiplex: Multiplex,
+    /// The message to send before any other: the `connected` event (see `Event::hello`), or
+    /// the copy of the last event sent (see `Event::duplicate_format`)
+    next: Option<Message>,
+    first_byte: FirstByte,
+    /// Held until the WebSocket closes, which announces that on the changefeed
+    _connection: OpenConnection,
+}
+
+impl Stream for Connection {
+    type Item = Message;
+    type Error = warp::Error;
+
+    /// The next message, or a heartbeat if the connection has gone `FIRST_BYTE_TIMEOUT`
+    /// without one
+    fn poll(&mut self) -> Poll<Option<Message>, warp::Error> {
+        let msg = match self.next_msg()? {
+            Async::NotReady if self.first_byte.poll_due() => {
+                let ping = Event::Ping.to_json_string(&self.ws.1);
+                Async::Ready(Some(Message::text(ping)))
+            }
+            msg => msg,
+        };
+        if let Async::Ready(Some(_)) = msg {
+            self.first_byte.sent();
+        }
+        Ok(msg)
+    }
+}
+
+impl Connection {
+    /// A reply to the client's message, or else the next event to send (if any)
+    fn next_msg(&mut self) -> Poll<Option<Message>, warp::Error> {
+        if let Some(next) = self.next.take() {
+            return Ok(Async::Ready(Some(next)));
+        }
+        while let Async::Ready(msg) = self.client_msgs.poll()? {
+            match msg {
+                Some(msg) => {
+                    if let Some(reply) = self.reply_to(msg) {
+                        return Ok(Async::Ready(Some(reply)));
                     }
                 }
-            })
-            .map_err(|_| -> warp::Error { unreachable!() })
-            .forward(transmit_to_ws)
-            .map(|_r| ())
-            // ignore errors that indicate normal disconnects.  TODO - once we upgrade our
-            // Warp version, we should stop matching on text, which is fragile.
-            .map_err(|e| match e.to_string().as_ref() {
-                "IO error: Broken pipe (os error 32)"
-                | "IO error: Connection reset by peer (os error 104)" => (),
-                e => log::warn!("WebSocket send error: {}", e),
-            })
+                None => return Ok(Async::Ready(None)), // the client closed the connection
+            }
+        }
+
+        // The `Manager` closes a channel that stays full when `CLIENT_OVERFLOW=disconnect`
+        while let Ok(Async::Ready(event)) = self.event_rx.poll() {
+            let event = match event {
+                Some(event) => event,
+                None => return Ok(Async::Ready(None)),
+            };
+            if matches!(*event, Event::Ping) {
+                let ping = event.to_json_string(&self.ws.1);
+                return Ok(Async::Ready(Some(Message::text(ping))));
+            }
+            if let Event::Disconnect(code) = *event {
+                let (error, close) = closing(code);
+                self.next = Some(close);
+                return Ok(Async::Ready(Some(error)));
+            }
+            let (subscription, format) = (&self.ws.0, &self.ws.1);
+            if !self.unsubscribed && !filtered(subscription, &event) {
+                if let Event::Raw(_, bytes) = &*event {
+                    return Ok(Async::Ready(Some(Message::binary(bytes.to_vec()))));
+                }
+                let name = Some(subscription.stream_name()).filter(|_| self.multiplexed());
+                let json = event.to_json_string_on(format, name.as_deref());
+                self.next = event.duplicate_format(format).map(|duplicate| {
+                    Message::text(event.to_json_string_on(&duplicate, name.as_deref()))
+                });
+                return Ok(Async::Ready(Some(Message::text(json))));
+            }
+        }
+
+        for added in &mut self.added {
+            while let Ok(Async::Ready(event)) = added.event_rx.poll() {
+                let event = match event {
+                    Some(event) => event,
+                    None => return Ok(Async::Ready(None)),
+                };
+                if let Event::Disconnect(code) = *event {
+                    let (error, close) = closing(code);
+                    self.next = Some(close);
+                    return Ok(Async::Ready(Some(error)));
+                }
+                // Heartbeats come from the stream the client connected to
+                if !matches!(*event, Event::Ping) && !filtered(&added.subscription, &event) {
+                    if let Event::Raw(_, bytes) = &*event {
+                        return Ok(Async::Ready(Some(Message::binary(bytes.to_vec()))));
+                    }
+                    let json = event.to_json_string_on(&added.format, Some(&added.name));
+                    self.next = event.duplicate_format(&added.format).map(|duplicate| {
+                        Message::text(event.to_json_string_on(&duplicate, Some(&added.name)))
+                    });
+                    return Ok(Async::Ready(Some(Message::text(json))));
+                }
+            }
+        }
+        Ok(Async::NotReady)
     }
-    fn filtered<T: std::fmt::Debug + Payload>(&mut self, update: &T) -> bool {
-        let (blocks, allowed_langs) = (&self.0.blocks, &self.0.allowed_langs);
-        let skip = |msg| {
-            // Some(log::info!("{:?} msg skipped - {}\n{:?}", self.0.timeline, msg, update)).is_some()
-            Some(log::info!("{:?} msg skipped - {}", self.0.timeline, msg)).is_some()
+
+    /// Whether events need to name their stream, because the client has added streams
+    fn multiplexed(&self) -> bool {
+        !self.added.is_empty()
+    }
+
+    /// Add or remove the stream that a `subscribe` or `unsubscribe` message asks for, returning
+    /// the frame that acknowledges (or refuses) it.  Other messages are ignored.
+    fn reply_to(&mut self, msg: Message) -> Option<Message> {
+        let msg: Value = serde_json::from_str(msg.to_str().ok()?).ok()?;
+        let kind = match msg["type"].as_str() {
+            Some(kind @ "subscribe") | Some(kind @ "unsubscribe") => kind.to_string(),
+            _ => return None,
+        };
+        let (result, name) = match serde_json::from_value::<Frame>(msg) {
+            Ok(frame) => (self.apply(&frame), frame.stream_name()),
+            Err(_) => (Err(Refusal::Invalid), Vec::new()),
         };
+        let reply = match result {
+            Ok(()) => json!({ "type": format!("{}d", kind), "stream": name }),
+            Err(refusal) => {
+                tracing::info!("Refused to {} to {:?}: {}", kind, name, refusal);
+                json!({
+                    "error": refusal.to_string(),
+                    "status": refusal.status(),
+                    "code": refusal.code(),
+                    "type": kind,
+                    "stream": name,
+                })
+            }
+        };
+        Some(Message::text(reply.to_string()))
+    }
 
-        match self.0.timeline {
-            tl if tl.is_public()
-                && !update.language_unset()
-                && !allowed_langs.is_empty()
-                && !allowed_langs.contains(&update.language()) =>
-            {
-                skip("disallowed language")
+    /// Add or remove the stream `frame` names.  Subscribing to a stream the connection already
+    /// has succeeds without adding it again.
+    fn apply(&mut self, frame: &Frame) -> Result<(), Refusal> {
+        let name = frame.stream_name();
+        let opened_with = self.ws.0.stream_name();
+        let added = self.added.iter().position(|added| added.name == name);
+        match (frame.kind, added) {
+            (FrameKind::Subscribe, _) if name == opened_with => self.unsubscribed = false,
+            (FrameKind::Subscribe, Some(_)) => (),
+            (FrameKind::Subscribe, None) => {
+                let (count, multiplexer) = (1 + self.added.len(), &self.multiplex.multiplexer);
+                if multiplexer.max_streams.map_or(false, |max| count >= max) {
+                    Err(Refusal::TooManyStreams)?;
+                }
+                let subscription = multiplexer.subscribe(frame, &self.ws.0)?;
+                let mut manager = self
+                    .multiplex
+                    .manager
+                    .lock()
+                    .unwrap_or_else(RedisManager::recover);
+                let (event_tx, event_rx) = manager.channel();
+                manager
+                    .subscribe(&subscription, event_tx)
+                    .map_err(|_| Refusal::AtChannelLimit)?;
+                tracing::info!("Added {:?} to a WebSocket", subscription.timeline);
+                self.added.push(Added {
+                    name,
+                    format: self.ws.1.for_subscription(&subscription),
+                    subscription,
+                    event_rx,
+                });
             }
-            _ if !blocks.blocked_users.is_disjoint(&update.involved_users()) => {
-                skip("involves blocked user")
+            (FrameKind::Unsubscribe, _) if name == opened_with && !self.unsubscribed => {
+                self.unsubscribed = true
             }
-            _ if blocks.blocking_users.contains(update.author()) => skip("from blocking user"),
-            _ if blocks.blocked_domains.contains(update.sent_from()) => skip("from blocked domain"),
-            _ => false,
+            // Dropping the receiver closes the channel, so the `Manager` stops sending to it
+            (FrameKind::Unsubscribe, Some(i)) => drop(self.added.remove(i)),
+            (FrameKind::Unsubscribe, None) => Err(Refusal::NotSubscribed)?,
+        }
+        Ok(())
+    }
+}
+
+/// The frames that close the connection of a client that an admin disconnected, whose stream
+/// was dropped to stay under a limit on Redis channels, or whose server is shutting down: an
+/// `error` frame that tells the client why, and then the close frame with `code`
+fn closing(code: u16) -> (Message, Message) {
+    let closing = Closing::from_close_code(code);
+    let error = json!({ "type": "error", "error": closing.error, "code": closing.code });
+    let close = Message::close_with(code, closing.error);
+    (Message::text(error.to_string()), close)
+}
+
+/// Whether `event` is of a type the subscriber didn't ask for, or an update it shouldn't see
+/// (all other events are sent)
+fn filtered(subscription: &Subscription, event: &Event) -> bool {
+    match (event.update_payload(), event.dyn_update_payload()) {
+        _ if !event.is_wanted(&subscription.event_types) => true,
+        (Some(update), _) => filtered_update(subscription, update),
+        (None, Some(dyn_update)) => filtered_update(subscription, dyn_update),
+        (None, None) => false,
+    }
+}
+
+fn filtered_update<T: std::fmt::Debug + Payload>(subscription: &Subscription, update: &T) -> bool {
+    let (blocks, allowed_langs) = (&subscription.blocks, &subscription.allowed_langs);
+    let skip = |msg| {
+        // Some(tracing::info!("{:?} msg skipped - {}\n{:?}", subscription.timeline, msg, update)).is_some()
+        Some(tracing::info!(
+            "{:?} msg skipped - {}",
+            subscription.timeline,
+            msg
+        ))
+        .is_some()
+    };
+
+    match subscription.timeline {
+        tl if tl.is_public()
+            && !update.language_unset()
+            && !allowed_langs.is_empty()
+            && !allowed_langs.contains(&update.language()) =>
+        {
+            skip("disallowed language")
+        }
+        _ if !blocks.blocked_users.is_disjoint(&update.involved_users()) => {
+            skip("involves blocked user")
         }
+        _ if blocks.blocking_users.contains(update.author()) => skip("from blocking user"),
+        _ if blocks.blocked_domains.contains(update.sent_from()) => skip("from blocked domain"),
+        _ => false,
     }
 }
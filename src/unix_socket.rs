@@ -0,0 +1,82 @@
+//! The Unix sockets Flodgatt listens on: `SOCKET`, for clients proxied by nginx (as with
+//! Mastodon's streaming server), and `IPC_SOCKET`, for sidecars.
+//!
+//! A socket file outlives a process that doesn't shut down cleanly, and would make the next
+//! `bind` fail, so a stale one is removed first.  Only a socket that nothing accepts
+//! connections on is stale: a path held by a running instance, or by a file that isn't a
+//! socket, is an error rather than something to delete.
+use std::fs::{self, DirBuilder, Permissions};
+use std::io::{self, ErrorKind};
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(test)]
+mod test;
+
+/// Remove the socket at `path` if it was left by a process that has stopped
+pub fn remove_stale(path: &str) -> io::Result<()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if !metadata.file_type().is_socket() {
+        let msg = format!("{} exists and isn't a socket", path);
+        return Err(io::Error::new(ErrorKind::AlreadyExists, msg));
+    }
+    match UnixStream::connect(path) {
+        Ok(_) => {
+            let msg = format!("{} is in use by another process", path);
+            Err(io::Error::new(ErrorKind::AddrInUse, msg))
+        }
+        Err(e) if e.kind() == ErrorKind::ConnectionRefused => {
+            tracing::info!("Removing the stale socket {}", path);
+            fs::remove_file(path)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Create the socket at `path` with `bind`, readable and writable by those that `mode` (e.g.,
+/// `0o660`) allows.  It is bound in a directory next to `path` that only this user can enter,
+/// and moved to `path` once its permissions are set, so that it never has wider permissions
+/// than `mode`, even for an instant.  (Setting the umask instead would change it for the whole
+/// process, whose other threads are already running.)  The directory's name is short, so that
+/// a `path` that fits in a socket address still does while it's in there.
+pub fn bind<T>(path: &str, mode: u32, bind: impl FnOnce(&str) -> io::Result<T>) -> io::Result<T> {
+    remove_stale(path)?;
+    let dir = match Path::new(path).parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    };
+    let n = PRIVATE_DIRS.fetch_add(1, Ordering::Relaxed);
+    let private = dir.join(format!(".{}-{}.d", process::id(), n));
+    DirBuilder::new().mode(0o700).create(&private)?;
+    let bound = bind_privately(&private, path, mode, bind);
+    // Whether or not the socket was bound, so as not to hide why it wasn't
+    if let Err(e) = fs::remove_dir_all(&private) {
+        tracing::warn!("Couldn't remove {}: {}", private.display(), e);
+    }
+    bound
+}
+
+/// How many private directories this process has made, to name the next one
+static PRIVATE_DIRS: AtomicUsize = AtomicUsize::new(0);
+
+fn bind_privately<T>(
+    private: &Path,
+    path: &str,
+    mode: u32,
+    bind: impl FnOnce(&str) -> io::Result<T>,
+) -> io::Result<T> {
+    let hidden = private.join("socket");
+    let hidden = hidden.to_str().expect("made from UTF-8 paths");
+    let listener = bind(hidden)?;
+    fs::set_permissions(hidden, Permissions::from_mode(mode))?;
+    // On the same file system, so the move is atomic and the listener keeps the socket
+    fs::rename(hidden, path)?;
+    Ok(listener)
+}
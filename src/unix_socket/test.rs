@@ -0,0 +1,64 @@
+use super::*;
+use std::os::unix::net::UnixListener;
+
+fn temp_path(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("flodgatt_{}_{}", name, std::process::id()));
+    path.to_str().expect("a UTF-8 temp dir").to_string()
+}
+
+#[test]
+fn a_stale_socket_is_replaced() -> io::Result<()> {
+    let path = temp_path("stale.sock");
+    drop(UnixListener::bind(&path)?);
+
+    let listener = bind(&path, 0o660, |hidden| UnixListener::bind(hidden))?;
+    let mode = fs::metadata(&path)?.permissions().mode() & 0o777;
+    let connected = UnixStream::connect(&path).map(drop);
+    drop(listener);
+    fs::remove_file(&path)?;
+    assert_eq!(mode, 0o660);
+    assert!(
+        connected.is_ok(),
+        "the moved socket is still the listener's"
+    );
+    // The directory it was bound in is removed
+    let prefix = format!(".{}-", std::process::id());
+    let private = fs::read_dir(std::env::temp_dir())?
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .find(|name| name.starts_with(&prefix) && name.ends_with(".d"));
+    assert_eq!(private, None);
+    Ok(())
+}
+
+#[test]
+fn a_path_that_fits_in_a_socket_address_is_bound() -> io::Result<()> {
+    // `sun_path` holds 108 bytes, including the terminating NUL
+    let dir = temp_path("long");
+    let name = "s".repeat(107 - dir.len() - 1);
+    let path = format!("{}/{}", dir, name);
+    fs::create_dir(&dir)?;
+
+    let bound = bind(&path, 0o600, |hidden| UnixListener::bind(hidden)).map(drop);
+    fs::remove_dir_all(&dir)?;
+    bound
+}
+
+#[test]
+fn a_socket_in_use_or_another_file_is_kept() -> io::Result<()> {
+    let path = temp_path("live.sock");
+    let live = UnixListener::bind(&path)?;
+    let in_use = remove_stale(&path).map_err(|e| e.kind());
+    drop(live);
+    fs::remove_file(&path)?;
+
+    let file = temp_path("not_a_socket");
+    fs::write(&file, "data")?;
+    let not_a_socket = remove_stale(&file).map_err(|e| e.kind());
+    let kept = fs::read_to_string(&file)?;
+    fs::remove_file(&file)?;
+
+    assert_eq!(in_use, Err(ErrorKind::AddrInUse));
+    assert_eq!(not_a_socket, Err(ErrorKind::AlreadyExists));
+    assert_eq!(kept, "data");
+    Ok(())
+}